@@ -0,0 +1,28 @@
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_color;
+layout(location = 2) in vec4 v_clip_position;
+
+layout(set = 0, binding = 0) uniform sampler2D atlas;
+// The scene's depth, which may be at a lower resolution than the composited image
+layout(set = 0, binding = 1) uniform sampler2D scene_depth;
+
+layout(location = 0) out vec4 f_color;
+
+// Keeps billboards standing on a surface from flickering into it
+const float DEPTH_BIAS = 0.0005;
+
+void main() {
+    vec3 ndc = v_clip_position.xyz / v_clip_position.w;
+    float depth = texture(scene_depth, ndc.xy * 0.5 + 0.5).r;
+    if (ndc.z > depth + DEPTH_BIAS) {
+        discard;
+    }
+
+    vec4 color = texture(atlas, v_uv) * v_color;
+    if (color.a <= 0.0) {
+        discard;
+    }
+    f_color = color;
+}
@@ -0,0 +1,40 @@
+#version 450
+
+// One instance per billboard, see billboard.rs
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 size;
+layout(location = 2) in vec4 uv_rect;
+layout(location = 3) in vec4 color;
+layout(location = 4) in float cylindrical;
+
+layout(push_constant) uniform PushConstants {
+    // Unjittered, the billboards are drawn after the TAA resolve
+    mat4 view;
+    mat4 projection;
+} push_constants;
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec4 v_color;
+layout(location = 2) out vec4 v_clip_position;
+
+void main() {
+    // A triangle strip of four corners, no vertex buffer needed
+    vec2 corner = vec2(float(gl_VertexIndex & 1), float((gl_VertexIndex >> 1) & 1));
+
+    mat4 view = push_constants.view;
+    vec3 right = vec3(view[0][0], view[1][0], view[2][0]);
+    vec3 up = vec3(view[0][1], view[1][1], view[2][1]);
+    if (cylindrical > 0.5) {
+        // Turns about the world's up axis only
+        right = normalize(vec3(right.x, 0.0, right.z));
+        up = vec3(0.0, 1.0, 0.0);
+    }
+
+    vec2 offset = (corner - vec2(0.5, 0.0)) * size;
+    vec3 world_position = position + right * offset.x + up * offset.y;
+
+    v_uv = mix(uv_rect.xy, uv_rect.zw, vec2(corner.x, 1.0 - corner.y));
+    v_color = color;
+    v_clip_position = push_constants.projection * view * vec4(world_position, 1.0);
+    gl_Position = v_clip_position;
+}
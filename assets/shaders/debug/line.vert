@@ -0,0 +1,17 @@
+#version 450
+
+// World space, see debug_draw.rs
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+
+layout(push_constant) uniform PushConstants {
+    // Unjittered, so lines hold still under TAA
+    mat4 view_projection;
+} push_constants;
+
+layout(location = 0) out vec4 v_color;
+
+void main() {
+    v_color = color;
+    gl_Position = push_constants.view_projection * vec4(position, 1.0);
+}
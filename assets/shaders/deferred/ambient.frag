@@ -1,18 +1,84 @@
 #version 450
 
+#include "brdf.glsl"
+
 // The `color_input` parameter of the `draw` method.
 layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput u_diffuse;
+// The `normals_input` parameter of the `draw` method.
+layout(input_attachment_index = 1, set = 0, binding = 1) uniform subpassInput u_normals;
+// The `depth_input` parameter of the `draw` method.
+layout(input_attachment_index = 3, set = 0, binding = 2) uniform subpassInput u_depth;
+// The `material_input` parameter of the `draw` method.
+layout(input_attachment_index = 2, set = 0, binding = 3) uniform subpassInput u_material;
+// The `emissive_input` parameter of the `draw` method.
+layout(input_attachment_index = 4, set = 0, binding = 7) uniform subpassInput u_emissive;
+// The maps of the `environment` parameter of the `draw` method.
+layout(set = 0, binding = 4) uniform samplerCube u_irradiance;
+layout(set = 0, binding = 5) uniform samplerCube u_prefiltered;
+layout(set = 0, binding = 6) uniform sampler2D u_brdf_lut;
+layout(set = 0, binding = 8) uniform samplerCubeArray u_probes;
 
 layout(push_constant) uniform PushConstants {
+    // The `screen_to_world` parameter of the `draw` method.
+    mat4 screen_to_world;
     // The `ambient_color` parameter of the `draw` method.
     vec4 color;
+    // The prefiltered map's last mip level, used at full roughness.
+    float max_reflection_lod;
 } push_constants;
 
+layout(location = 0) in vec2 v_screen_coords;
 layout(location = 0) out vec4 f_color;
 
 void main() {
-    // Load the value at the current pixel.
-    vec3 in_diffuse = subpassLoad(u_diffuse).rgb;
-    f_color.rgb = push_constants.color.rgb * in_diffuse;
+    float in_depth = subpassLoad(u_depth).x;
+
+    // Pixels at the far plane weren't touched by the deferred pass.
+    if (in_depth >= 1.0) {
+        discard;
+    }
+
+    vec3 albedo = subpassLoad(u_diffuse).rgb;
+    vec3 in_normal = subpassLoad(u_normals).rgb;
+    // The ambient light runs once per frame, so emitted light is added here.
+    vec3 emissive = subpassLoad(u_emissive).rgb;
     f_color.a = 1.0;
+
+    // Meshes without normals get a flat ambient term.
+    if (dot(in_normal, in_normal) < 0.25) {
+        f_color.rgb = push_constants.color.rgb * albedo + emissive;
+        return;
+    }
+    vec3 n = normalize(in_normal);
+
+    vec4 world = push_constants.screen_to_world * vec4(v_screen_coords, in_depth, 1.0);
+    world /= world.w;
+    vec4 near = push_constants.screen_to_world * vec4(v_screen_coords, 0.0, 1.0);
+    near /= near.w;
+    vec3 v = normalize(near.xyz - world.xyz);
+    vec3 r = reflect(-v, n);
+
+    vec4 material = subpassLoad(u_material);
+    float metallic = material.r;
+    float roughness = material.g;
+    // One more than the reflection probe's index, 0 to reflect the environment
+    int probe = int(round(material.b * 255.0)) - 1;
+    // 0 where the geometry pass already added a lightmap's diffuse light to the emitted light
+    float ambient_diffuse = material.a;
+    float n_dot_v = max(dot(n, v), 1e-4);
+
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    vec3 k_d = (1.0 - f) * (1.0 - metallic);
+
+    vec3 diffuse = texture(u_irradiance, n).rgb * albedo;
+    float lod = roughness * push_constants.max_reflection_lod;
+    // Probes were captured lit, the environment is lit by the ambient color
+    vec3 prefiltered = probe >= 0
+        ? textureLod(u_probes, vec4(r, float(probe)), lod).rgb
+        : push_constants.color.rgb * textureLod(u_prefiltered, r, lod).rgb;
+    vec2 brdf = texture(u_brdf_lut, vec2(n_dot_v, roughness)).rg;
+    vec3 specular = prefiltered * (f * brdf.x + brdf.y);
+
+    f_color.rgb = ambient_diffuse * push_constants.color.rgb * k_d * diffuse + specular + emissive;
 }
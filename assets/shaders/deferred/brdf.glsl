@@ -0,0 +1,50 @@
+// Cook-Torrance BRDF shared by the lighting shaders, using the GGX distribution, Smith-Schlick
+// geometry term and Schlick's Fresnel approximation.
+
+const float PI = 3.14159265359;
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float r = roughness + 1.0;
+    float k = (r * r) / 8.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Radiance reflected towards `v` from light arriving along `l`. All vectors are normalized and
+// point away from the surface.
+vec3 cook_torrance(vec3 albedo, float metallic, float roughness, vec3 n, vec3 v, vec3 l, vec3 radiance) {
+    vec3 h = normalize(v + l);
+    float n_dot_l = max(dot(n, l), 0.0);
+    float n_dot_v = max(dot(n, v), 1e-4);
+    float n_dot_h = max(dot(n, h), 0.0);
+
+    // Dielectrics reflect about 4% at normal incidence, metals tint the reflection with albedo
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+    vec3 f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    // Very low roughness turns the highlight into a sub-pixel spike
+    float d = distribution_ggx(n_dot_h, max(roughness, 0.045));
+    float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+
+    vec3 specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 1e-4);
+    vec3 k_d = (1.0 - f) * (1.0 - metallic);
+
+    return (k_d * albedo / PI + specular) * radiance * n_dot_l;
+}
+
+// Fresnel for light arriving from every direction, as with image-based lighting, where rough
+// surfaces reflect less at grazing angles.
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
@@ -0,0 +1,53 @@
+// Light clusters shared by the culling compute shader and the point lighting shader. The view
+// is split into a grid of tiles across the screen and exponentially spaced slices in depth.
+
+struct PointLightData {
+    // xyz: world position, w: radius the light reaches
+    vec4 position_radius;
+    // rgb: color times exposed intensity in candela, w: attenuation model, 0 for inverse
+    // square, 1 for linear
+    vec4 color;
+};
+
+// Upper bound of the lights a single cluster can hold. Lights past it are dropped.
+const uint MAX_LIGHTS_PER_CLUSTER = 128;
+
+layout(set = CLUSTER_SET, binding = 0) readonly buffer Lights {
+    PointLightData lights[];
+};
+
+layout(set = CLUSTER_SET, binding = 1) uniform ClusterParams {
+    mat4 view;
+    mat4 inverse_projection;
+    // xyz: cluster counts, w: number of lights
+    uvec4 grid;
+    // xy: framebuffer size, z: near distance, w: far distance
+    vec4 screen;
+} params;
+
+layout(set = CLUSTER_SET, binding = 2) CLUSTER_ACCESS buffer ClusterCounts {
+    uint cluster_counts[];
+};
+
+layout(set = CLUSTER_SET, binding = 3) CLUSTER_ACCESS buffer ClusterIndices {
+    uint cluster_indices[];
+};
+
+uint cluster_index(uvec3 cluster) {
+    return cluster.x + params.grid.x * (cluster.y + params.grid.y * cluster.z);
+}
+
+// The depth slice holding a point `depth` units in front of the camera.
+uint depth_slice(float depth) {
+    float near = params.screen.z;
+    float far = params.screen.w;
+    float slice = log(max(depth, near) / near) / log(far / near) * float(params.grid.z);
+    return uint(clamp(slice, 0.0, float(params.grid.z - 1)));
+}
+
+// The distance in front of the camera where `slice` starts.
+float slice_depth(uint slice) {
+    float near = params.screen.z;
+    float far = params.screen.w;
+    return near * pow(far / near, float(slice) / float(params.grid.z));
+}
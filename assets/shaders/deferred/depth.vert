@@ -0,0 +1,46 @@
+#version 460
+
+layout(location = 0) in vec3 position;
+
+layout(set = 0, binding = 0) uniform FrameData {
+    mat4 view;
+    // Jittered when TAA is enabled
+    mat4 proj;
+    // Without jitter, for motion vectors
+    mat4 view_projection;
+    mat4 previous_view_projection;
+    // Non-zero when vertex colors are sRGB and need converting to linear
+    uint srgb_colors;
+}
+frame_data;
+
+struct ObjectData {
+    mat4 model;
+    // The model matrix of the previous frame, for motion vectors
+    mat4 previous_model;
+    // Inverse transpose of the model matrix, for normals under non-uniform scale
+    mat4 normal_matrix;
+    // Multiplies the vertex color and base color texture
+    vec4 base_color;
+    // x: metallic, y: roughness
+    vec4 material;
+    // Linear light emitted regardless of lighting, may exceed 1 to drive bloom
+    vec4 emissive;
+    // rgb: tint color, a: how much it replaces the base color
+    vec4 tint;
+};
+
+layout(std140, set = 1, binding = 0) readonly buffer ObjectBuffer {
+    ObjectData objects[];
+}
+object_buffer;
+
+// Must match geometry.vert, which tests against the depth written here with LESS_OR_EQUAL
+invariant gl_Position;
+
+void main() {
+    mat4 model_matrix = object_buffer.objects[gl_BaseInstance].model;
+    mat4 model_view = frame_data.view * model_matrix;
+
+    gl_Position = frame_data.proj * model_view * vec4(position, 1.0);
+}
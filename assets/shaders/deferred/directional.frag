@@ -1,31 +1,131 @@
 #version 450
 
+#include "brdf.glsl"
+
 // The `color_input` parameter of the `draw` method.
 layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput u_diffuse;
 // The `normals_input` parameter of the `draw` method.
 layout(input_attachment_index = 1, set = 0, binding = 1) uniform subpassInput u_normals;
+// The `depth_input` parameter of the `draw` method.
+layout(input_attachment_index = 3, set = 0, binding = 2) uniform subpassInput u_depth;
+// The `material_input` parameter of the `draw` method.
+layout(input_attachment_index = 2, set = 0, binding = 3) uniform subpassInput u_material;
+
+// The shadow map layers of the `shadows` parameter of the `draw` method, one per cascade.
+layout(set = 1, binding = 0) uniform sampler2DArrayShadow u_shadow_map;
+
+layout(set = 1, binding = 1) uniform ShadowParams {
+    mat4 view;
+    // World to shadow map, with x and y in -1..1 and depth in 0..1
+    mat4 cascades[4];
+    // View depth at which each cascade ends
+    vec4 splits;
+    // x: cascade count, zero without shadows, y: fraction of each cascade blended into the next,
+    // z: depth bias, w: non-zero to tint by cascade
+    vec4 settings;
+} u_shadows;
 
 layout(push_constant) uniform PushConstants {
+    // The `screen_to_world` parameter of the `draw` method.
+    mat4 screen_to_world;
     // The `color` parameter of the `draw` method.
     vec4 color;
     // The `direction` parameter of the `draw` method.
     vec4 direction;
 } push_constants;
 
+layout(location = 0) in vec2 v_screen_coords;
 layout(location = 0) out vec4 f_color;
 
+const vec3 CASCADE_COLORS[4] = vec3[](
+    vec3(1.0, 0.3, 0.3),
+    vec3(0.3, 1.0, 0.3),
+    vec3(0.3, 0.3, 1.0),
+    vec3(1.0, 1.0, 0.3)
+);
+
+// How much of the light reaches `world` in `cascade`, filtered over 3x3 texels.
+float sample_cascade(int cascade, vec3 world, float bias) {
+    vec4 shadow_coords = u_shadows.cascades[cascade] * vec4(world, 1.0);
+    vec2 uv = shadow_coords.xy * 0.5 + 0.5;
+    float depth = shadow_coords.z - bias;
+    vec2 texel = 1.0 / vec2(textureSize(u_shadow_map, 0).xy);
+
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(u_shadow_map, vec4(uv + vec2(x, y) * texel, cascade, depth));
+        }
+    }
+    return lit / 9.0;
+}
+
+// How much of the light reaches `world`, and the cascade it was looked up in, -1 when it's
+// past the last one.
+float shadow(vec3 world, vec3 n, vec3 l, out int cascade) {
+    int count = int(u_shadows.settings.x);
+    float view_depth = -(u_shadows.view * vec4(world, 1.0)).z;
+    for (cascade = 0; cascade < count; cascade++) {
+        if (view_depth < u_shadows.splits[cascade]) {
+            break;
+        }
+    }
+    if (cascade == count) {
+        cascade = -1;
+        return 1.0;
+    }
+
+    // Surfaces at grazing angles to the light need more bias
+    float bias = u_shadows.settings.z * (1.0 + 4.0 * (1.0 - max(dot(n, l), 0.0)));
+    float lit = sample_cascade(cascade, world, bias);
+
+    // Fade into the next cascade towards the end of this one, past the last into no shadow
+    float start = cascade == 0 ? 0.0 : u_shadows.splits[cascade - 1];
+    float end = u_shadows.splits[cascade];
+    float blend_start = end - (end - start) * u_shadows.settings.y;
+    if (view_depth > blend_start) {
+        float next = cascade + 1 < count ? sample_cascade(cascade + 1, world, bias) : 1.0;
+        lit = mix(lit, next, (view_depth - blend_start) / max(end - blend_start, 1e-4));
+    }
+    return lit;
+}
+
 void main() {
-    vec3 in_normal = normalize(subpassLoad(u_normals).rgb);
-
-    // If the normal is perpendicular to the direction of the lighting, then 
-    // `light_percent` will be 0. If the normal is parallel to the direction of the 
-    // lightin, then `light_percent` will be 1. Any other angle will yield an 
-    // intermediate value.
-    float light_percent = -dot(push_constants.direction.xyz, in_normal);
-    // `light_percent` must not go below 0.0. There's no such thing as negative lighting.
-    light_percent = max(light_percent, 0.0);
-
-    vec3 in_diffuse = subpassLoad(u_diffuse).rgb;
-    f_color.rgb = light_percent * push_constants.color.rgb * in_diffuse;
+    float in_depth = subpassLoad(u_depth).x;
+
+    // Pixels at the far plane weren't touched by the deferred pass.
+    if (in_depth >= 1.0) {
+        discard;
+    }
+
+    vec3 in_normal = subpassLoad(u_normals).rgb;
+    // Meshes without normals are only lit by the ambient light.
+    if (dot(in_normal, in_normal) < 0.25) {
+        discard;
+    }
+    vec3 n = normalize(in_normal);
+
+    // The point on the near plane under this pixel lies on the view ray, so the view direction
+    // doesn't need the camera position.
+    vec4 world = push_constants.screen_to_world * vec4(v_screen_coords, in_depth, 1.0);
+    world /= world.w;
+    vec4 near = push_constants.screen_to_world * vec4(v_screen_coords, 0.0, 1.0);
+    near /= near.w;
+    vec3 v = normalize(near.xyz - world.xyz);
+
+    // `direction` is the way the light travels.
+    vec3 l = normalize(-push_constants.direction.xyz);
+
+    vec3 albedo = subpassLoad(u_diffuse).rgb;
+    vec2 material = subpassLoad(u_material).rg;
+
+    int cascade;
+    float lit = shadow(world.xyz, n, l, cascade);
+
+    f_color.rgb = cook_torrance(albedo, material.r, material.g, n, v, l, push_constants.color.rgb);
+    f_color.rgb *= lit;
+    if (u_shadows.settings.w != 0.0 && cascade >= 0) {
+        f_color.rgb = mix(f_color.rgb, CASCADE_COLORS[cascade], 0.5);
+    }
     f_color.a = 1.0;
-}
\ No newline at end of file
+}
@@ -1,12 +1,76 @@
 #version 450
 
-layout(location = 0) in vec3 in_color;
+layout(location = 0) in vec4 in_color;
 layout(location = 1) in vec4 in_normal;
+layout(location = 2) in vec2 in_uv;
+layout(location = 3) flat in vec4 in_material;
+layout(location = 4) flat in vec3 in_emissive;
+layout(location = 5) flat in vec4 in_tint;
+layout(location = 6) in vec4 in_current_position;
+layout(location = 7) in vec4 in_previous_position;
+layout(location = 8) in vec2 in_lightmap_uv;
+
+// Materials without textures bind a white texel
+layout(set = 2, binding = 0) uniform sampler2D base_color_texture;
+// Roughness in green and metallic in blue, as in glTF
+layout(set = 2, binding = 1) uniform sampler2D metallic_roughness_texture;
+layout(set = 2, binding = 2) uniform sampler2D emissive_texture;
+// Baked indirect light, only read when in_material.w is set
+layout(set = 2, binding = 3) uniform sampler2D lightmap_texture;
+
+// Which material inputs are read, specialized per material so the unused ones are compiled out.
+// The defaults read everything
+layout(constant_id = 0) const bool HAS_BASE_COLOR_TEXTURE = true;
+layout(constant_id = 1) const bool HAS_METALLIC_ROUGHNESS_TEXTURE = true;
+layout(constant_id = 2) const bool HAS_EMISSIVE_TEXTURE = true;
+layout(constant_id = 3) const bool HAS_LIGHTMAP = true;
+// The material's shader and blend mode, see `MaterialShader` and `BlendMode`
+layout(constant_id = 4) const bool UNLIT = false;
+layout(constant_id = 5) const bool ALPHA_MASK = false;
+
+// Coverage masked materials cut out below, glTF's default alphaCutoff
+const float ALPHA_CUTOFF = 0.5;
 
 layout(location = 0) out vec4 f_color;
 layout(location = 1) out vec4 f_normal;
+layout(location = 2) out vec4 f_material;
+layout(location = 3) out vec4 f_emissive;
+// Screen space motion since the previous frame, in UV units
+layout(location = 4) out vec2 f_velocity;
 
 void main() {
-    f_color = vec4(in_color, 1.0);
+    vec4 metallic_roughness = HAS_METALLIC_ROUGHNESS_TEXTURE ? texture(metallic_roughness_texture, in_uv) : vec4(1.0);
+
+    vec4 base_color = in_color;
+    if (HAS_BASE_COLOR_TEXTURE) {
+        base_color *= texture(base_color_texture, in_uv);
+    }
+    if (ALPHA_MASK && base_color.a < ALPHA_CUTOFF) {
+        discard;
+    }
+    vec3 albedo = mix(base_color.rgb, in_tint.rgb, in_tint.a);
+    float metallic = in_material.x * metallic_roughness.b;
+    f_color = vec4(albedo, 1.0);
     f_normal = in_normal;
+    vec3 emissive = in_emissive;
+    if (HAS_EMISSIVE_TEXTURE) {
+        emissive *= texture(emissive_texture, in_uv).rgb;
+    }
+
+    // The lightmap takes the place of the ambient light's diffuse term, so it's added with the
+    // emitted light here and alpha tells the ambient light to leave its own out
+    bool lightmapped = HAS_LIGHTMAP && in_material.w > 0.5;
+    if (lightmapped) {
+        emissive += (1.0 - metallic) * albedo * texture(lightmap_texture, in_lightmap_uv).rgb;
+    }
+    f_material = vec4(metallic, in_material.y * metallic_roughness.g, in_material.z, lightmapped ? 0.0 : 1.0);
+    f_emissive = vec4(emissive, 1.0);
+    if (UNLIT) {
+        // Without a normal the lights leave it alone, and without an albedo so does the ambient
+        // light, so only the emitted light is left
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        f_normal = vec4(0.0);
+        f_emissive = vec4(albedo + emissive, 1.0);
+    }
+    f_velocity = (in_current_position.xy / in_current_position.w - in_previous_position.xy / in_previous_position.w) * 0.5;
 }
@@ -1,20 +1,66 @@
 #version 460
 
+// Compiled once per vertex layout, the HAS_* defines select the attributes the layout provides.
+// See geometry_shaders.rs
 layout(location = 0) in vec3 position;
+#ifdef HAS_COLOR
 layout(location = 1) in vec3 color;
+#endif
+#ifdef HAS_NORMAL
 layout(location = 2) in vec3 normal;
+#endif
+#ifdef HAS_UV
+layout(location = 3) in vec2 uv;
+#endif
+#ifdef HAS_TANGENT
+layout(location = 4) in vec4 tangent;
+#endif
+#ifdef HAS_SKIN
+layout(location = 5) in uvec4 joints;
+layout(location = 6) in vec4 weights;
+#endif
+#ifdef HAS_LIGHTMAP_UV
+layout(location = 7) in vec2 lightmap_uv;
+#endif
 
-layout(location = 0) out vec3 out_color;
+// Alpha is the base color's, for masked materials
+layout(location = 0) out vec4 out_color;
 layout(location = 1) out vec4 out_normal;
+layout(location = 2) out vec2 out_uv;
+layout(location = 3) flat out vec4 out_material;
+layout(location = 4) flat out vec3 out_emissive;
+layout(location = 5) flat out vec4 out_tint;
+layout(location = 6) out vec4 out_current_position;
+layout(location = 7) out vec4 out_previous_position;
+layout(location = 8) out vec2 out_lightmap_uv;
 
 layout(set = 0, binding = 0) uniform FrameData {
     mat4 view;
+    // Jittered when TAA is enabled
     mat4 proj;
+    // Without jitter, for motion vectors
+    mat4 view_projection;
+    mat4 previous_view_projection;
+    // Non-zero when vertex colors are sRGB and need converting to linear
+    uint srgb_colors;
 }
 frame_data;
 
 struct ObjectData {
     mat4 model;
+    // The model matrix of the previous frame, for motion vectors
+    mat4 previous_model;
+    // Inverse transpose of the model matrix, for normals under non-uniform scale
+    mat4 normal_matrix;
+    // Multiplies the vertex color and base color texture
+    vec4 base_color;
+    // x: metallic, y: roughness, z: the reflection probe, see `GeometrySystem::enqueue_mesh`,
+    // w: 1 when the material has a lightmap
+    vec4 material;
+    // Linear light emitted regardless of lighting, may exceed 1 to drive bloom
+    vec4 emissive;
+    // rgb: tint color, a: how much it replaces the base color
+    vec4 tint;
 };
 
 layout(std140, set = 1, binding = 0) readonly buffer ObjectBuffer {
@@ -22,12 +68,48 @@ layout(std140, set = 1, binding = 0) readonly buffer ObjectBuffer {
 }
 object_buffer;
 
+// Must produce exactly the depth written by depth.vert for the depth pre-pass
+invariant gl_Position;
+
+vec3 srgb_to_linear(vec3 srgb) {
+    return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), greaterThan(srgb, vec3(0.04045)));
+}
+
 void main() {
-    out_color = color;
+    ObjectData object = object_buffer.objects[gl_BaseInstance];
 
-    mat4 model_matrix = object_buffer.objects[gl_BaseInstance].model;
+#ifdef HAS_COLOR
+    vec3 vertex_color = frame_data.srgb_colors != 0 ? srgb_to_linear(color) : color;
+#else
+    vec3 vertex_color = vec3(1.0);
+#endif
+    out_color = vec4(vertex_color * object.base_color.rgb, object.base_color.a);
+    out_material = object.material;
+    out_emissive = object.emissive.rgb;
+    out_tint = object.tint;
+
+#ifdef HAS_UV
+    out_uv = uv;
+#else
+    out_uv = vec2(0.0);
+#endif
+#ifdef HAS_LIGHTMAP_UV
+    out_lightmap_uv = lightmap_uv;
+#else
+    out_lightmap_uv = vec2(0.0);
+#endif
+
+    mat4 model_matrix = object.model;
     mat4 model_view = frame_data.view * model_matrix;
 
-    out_normal = normalize(model_matrix * vec4(normal, 0.0));
+#ifdef HAS_NORMAL
+    out_normal = normalize(object.normal_matrix * vec4(normal, 0.0));
+#else
+    // Without normals only the ambient light contributes
+    out_normal = vec4(0.0);
+#endif
     gl_Position = frame_data.proj * model_view * vec4(position, 1.0);
+
+    out_current_position = frame_data.view_projection * model_matrix * vec4(position, 1.0);
+    out_previous_position = frame_data.previous_view_projection * object.previous_model * vec4(position, 1.0);
 }
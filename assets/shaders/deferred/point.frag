@@ -1,24 +1,39 @@
 #version 450
 
+#include "brdf.glsl"
+
+#define CLUSTER_SET 1
+#define CLUSTER_ACCESS readonly
+#include "clusters.glsl"
+
 // The `color_input` parameter of the `draw` method.
 layout(input_attachment_index = 0, set = 0, binding = 0) uniform subpassInput u_diffuse;
 // The `normals_input` parameter of the `draw` method.
 layout(input_attachment_index = 1, set = 0, binding = 1) uniform subpassInput u_normals;
 // The `depth_input` parameter of the `draw` method.
-layout(input_attachment_index = 2, set = 0, binding = 2) uniform subpassInput u_depth;
+layout(input_attachment_index = 3, set = 0, binding = 2) uniform subpassInput u_depth;
+// The `material_input` parameter of the `draw` method.
+layout(input_attachment_index = 2, set = 0, binding = 3) uniform subpassInput u_material;
 
 layout(push_constant) uniform PushConstants {
     // The `screen_to_world` parameter of the `draw` method.
     mat4 screen_to_world;
-    // The `color` parameter of the `draw` method.
-    vec4 color;
-    // The `position` parameter of the `draw` method.
-    vec4 position;
 } push_constants;
 
 layout(location = 0) in vec2 v_screen_coords;
 layout(location = 0) out vec4 f_color;
 
+// How much of a light's intensity reaches `dist` units away.
+float attenuation(float dist, float radius, float model) {
+    if (model > 0.5) {
+        return clamp(1.0 - dist / radius, 0.0, 1.0);
+    }
+    // Inverse square falloff, windowed to reach zero at the radius. The distance is clamped to
+    // 1 cm so surfaces touching the light don't blow up
+    float window = clamp(1.0 - pow(dist / radius, 4.0), 0.0, 1.0);
+    return window * window / max(dist * dist, 0.0001);
+}
+
 void main() {
     float in_depth = subpassLoad(u_depth).x;
 
@@ -28,22 +43,42 @@ void main() {
         discard;
     }
 
+    vec3 in_normal = subpassLoad(u_normals).rgb;
+    // Meshes without normals are only lit by the ambient light.
+    if (dot(in_normal, in_normal) < 0.25) {
+        discard;
+    }
+    vec3 n = normalize(in_normal);
+
     // Find the world coordinates of the current pixel.
     vec4 world = push_constants.screen_to_world * vec4(v_screen_coords, in_depth, 1.0);
     world /= world.w;
+    // The point on the near plane under this pixel lies on the view ray.
+    vec4 near = push_constants.screen_to_world * vec4(v_screen_coords, 0.0, 1.0);
+    near /= near.w;
+    vec3 v = normalize(near.xyz - world.xyz);
+
+    float view_depth = -(params.view * vec4(world.xyz, 1.0)).z;
+    uvec2 tile = min(uvec2(gl_FragCoord.xy / params.screen.xy * vec2(params.grid.xy)), params.grid.xy - 1);
+    uint cluster = cluster_index(uvec3(tile, depth_slice(view_depth)));
+
+    vec3 albedo = subpassLoad(u_diffuse).rgb;
+    vec2 material = subpassLoad(u_material).rg;
 
-    vec3 in_normal = normalize(subpassLoad(u_normals).rgb);
-    vec3 light_direction = normalize(push_constants.position.xyz - world.xyz);
+    uint count = cluster_counts[cluster];
+    if (count == 0) {
+        discard;
+    }
 
-    // Calculate the percent of lighting that is received based on the orientation of 
-    // the normal and the direction of the light.
-    float light_percent = max(-dot(light_direction, in_normal), 0.0);
+    vec3 color = vec3(0.0);
+    for (uint i = 0; i < count; i++) {
+        PointLightData light = lights[cluster_indices[cluster * MAX_LIGHTS_PER_CLUSTER + i]];
+        vec3 to_light = light.position_radius.xyz - world.xyz;
+        float dist = length(to_light);
 
-    float light_distance = length(push_constants.position.xyz - world.xyz);
-    // Further decrease light_percent based on the distance with the light position.
-    light_percent *= 1.0 / exp(light_distance);
+        vec3 radiance = light.color.rgb * attenuation(dist, light.position_radius.w, light.color.w);
 
-    vec3 in_diffuse = subpassLoad(u_diffuse).rgb;
-    f_color.rgb = push_constants.color.rgb * light_percent * in_diffuse;
-    f_color.a = 1.0;
-}
\ No newline at end of file
+        color += cook_torrance(albedo, material.r, material.g, n, v, normalize(to_light), radiance);
+    }
+    f_color = vec4(color, 1.0);
+}
@@ -0,0 +1,30 @@
+#version 460
+
+layout(location = 0) in vec3 position;
+
+layout(push_constant) uniform PushConstants {
+    // World to shadow map of the cascade being drawn
+    mat4 light_view_projection;
+}
+push_constants;
+
+struct ObjectData {
+    mat4 model;
+    mat4 previous_model;
+    mat4 normal_matrix;
+    vec4 base_color;
+    vec4 material;
+    vec4 emissive;
+    vec4 tint;
+};
+
+layout(std140, set = 1, binding = 0) readonly buffer ObjectBuffer {
+    ObjectData objects[];
+}
+object_buffer;
+
+void main() {
+    mat4 model_matrix = object_buffer.objects[gl_BaseInstance].model;
+
+    gl_Position = push_constants.light_view_projection * model_matrix * vec4(position, 1.0);
+}
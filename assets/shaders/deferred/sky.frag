@@ -0,0 +1,33 @@
+#version 450
+
+// The `depth_input` parameter of the `draw` method.
+layout(input_attachment_index = 3, set = 0, binding = 0) uniform subpassInput u_depth;
+
+layout(push_constant) uniform PushConstants {
+    // The `screen_to_world` parameter of the `draw` method.
+    mat4 screen_to_world;
+    vec4 zenith;
+    vec4 horizon;
+    vec4 ground;
+} push_constants;
+
+layout(location = 0) in vec2 v_screen_coords;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // Only what the deferred pass left at the far plane is sky
+    if (subpassLoad(u_depth).x < 1.0) {
+        discard;
+    }
+
+    vec4 near = push_constants.screen_to_world * vec4(v_screen_coords, 0.0, 1.0);
+    vec4 far = push_constants.screen_to_world * vec4(v_screen_coords, 1.0, 1.0);
+    vec3 direction = normalize(far.xyz / far.w - near.xyz / near.w);
+
+    // The horizon's color spreads into the sky and fades quickly into the ground
+    float up = direction.y;
+    vec3 color = up >= 0.0
+        ? mix(push_constants.horizon.rgb, push_constants.zenith.rgb, pow(up, 0.5))
+        : mix(push_constants.horizon.rgb, push_constants.ground.rgb, pow(-up, 0.25));
+    f_color = vec4(color, 1.0);
+}
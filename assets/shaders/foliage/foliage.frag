@@ -0,0 +1,50 @@
+#version 450
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 1) in vec3 in_color;
+layout(location = 2) in vec3 in_normal;
+layout(location = 3) in float in_fade;
+layout(location = 4) in vec4 in_current_position;
+layout(location = 5) in vec4 in_previous_position;
+
+// The layer's texture, white when it has none
+layout(set = 1, binding = 0) uniform sampler2D foliage_texture;
+
+layout(push_constant) uniform LayerData {
+    float fade_start;
+    float fade_end;
+    float sway;
+    float roughness;
+}
+layer;
+
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec4 f_normal;
+layout(location = 2) out vec4 f_material;
+layout(location = 3) out vec4 f_emissive;
+layout(location = 4) out vec2 f_velocity;
+
+const float ALPHA_CUTOFF = 0.5;
+
+// Thresholds of a 4x4 ordered dither, fading instances out with a pattern TAA smooths over since
+// the G-buffer can't blend
+const float DITHER[16] = float[](
+    0.0 / 16.0, 8.0 / 16.0, 2.0 / 16.0, 10.0 / 16.0,
+    12.0 / 16.0, 4.0 / 16.0, 14.0 / 16.0, 6.0 / 16.0,
+    3.0 / 16.0, 11.0 / 16.0, 1.0 / 16.0, 9.0 / 16.0,
+    15.0 / 16.0, 7.0 / 16.0, 13.0 / 16.0, 5.0 / 16.0
+);
+
+void main() {
+    vec4 texel = texture(foliage_texture, in_uv);
+    ivec2 pixel = ivec2(gl_FragCoord.xy) % 4;
+    if (texel.a < ALPHA_CUTOFF || in_fade <= DITHER[pixel.y * 4 + pixel.x]) {
+        discard;
+    }
+
+    f_color = vec4(in_color * texel.rgb, 1.0);
+    f_normal = vec4(normalize(in_normal), 0.0);
+    f_material = vec4(0.0, layer.roughness, 0.0, 1.0);
+    f_emissive = vec4(0.0, 0.0, 0.0, 1.0);
+    f_velocity = (in_current_position.xy / in_current_position.w - in_previous_position.xy / in_previous_position.w) * 0.5;
+}
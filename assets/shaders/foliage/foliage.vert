@@ -0,0 +1,75 @@
+#version 450
+
+// One instance per plant, drawn as two crossed quads from gl_VertexIndex. See foliage.rs
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 size;
+layout(location = 2) in float rotation;
+layout(location = 3) in vec3 color;
+
+layout(set = 0, binding = 0) uniform FoliageData {
+    // Jittered when TAA is enabled
+    mat4 view_projection;
+    // Without jitter, for motion vectors
+    mat4 unjittered_view_projection;
+    mat4 previous_view_projection;
+    // xyz: camera position, w: seconds since the renderer started
+    vec4 camera;
+    // xy: direction along the ground, z: how far the tips bend in meters, w: gusts per second
+    vec4 wind;
+}
+foliage;
+
+layout(push_constant) uniform LayerData {
+    float fade_start;
+    float fade_end;
+    // Scales the wind's bend, lower for stiffer plants
+    float sway;
+    float roughness;
+}
+layer;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec3 out_color;
+layout(location = 2) out vec3 out_normal;
+layout(location = 3) out float out_fade;
+layout(location = 4) out vec4 out_current_position;
+layout(location = 5) out vec4 out_previous_position;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+const float TAU = 6.28318530718;
+
+void main() {
+    // The second quad is turned a quarter turn from the first
+    int quad = gl_VertexIndex / 6;
+    vec2 corner = CORNERS[gl_VertexIndex % 6];
+    float angle = rotation + float(quad) * TAU * 0.25;
+    vec3 across = vec3(cos(angle), 0.0, sin(angle));
+
+    // Roots stay put and tips bend the most. The phase comes from the position so neighbours
+    // don't sway in lockstep
+    float phase = dot(position.xz, vec2(0.37, 0.61));
+    float gust = 0.6 + 0.4 * sin(foliage.camera.w * foliage.wind.w * TAU + phase);
+    float flutter = sin(foliage.camera.w * foliage.wind.w * TAU * 2.7 + phase * 1.3);
+    float bend = corner.y * corner.y * foliage.wind.z * layer.sway;
+    vec2 sway = foliage.wind.xy * bend * gust + vec2(-foliage.wind.y, foliage.wind.x) * bend * 0.2 * flutter;
+
+    vec3 world_position = position
+        + across * (corner.x - 0.5) * size.x
+        + vec3(sway.x, corner.y * size.y, sway.y);
+
+    out_uv = vec2(corner.x, 1.0 - corner.y);
+    out_color = color;
+    // Mostly up, so the crossed quads shade like the ground they cover rather than like cards
+    vec3 facing = vec3(-across.z, 0.0, across.x);
+    out_normal = normalize(vec3(sway.x, 1.0, sway.y) + facing * 0.3);
+    float distance_to_camera = length(position - foliage.camera.xyz);
+    out_fade = 1.0 - smoothstep(layer.fade_start, layer.fade_end, distance_to_camera);
+
+    gl_Position = foliage.view_projection * vec4(world_position, 1.0);
+    // The sway since the previous frame isn't included, only the camera's motion
+    out_current_position = foliage.unjittered_view_projection * vec4(world_position, 1.0);
+    out_previous_position = foliage.previous_view_projection * vec4(world_position, 1.0);
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec4 v_color;
+// Negative for untextured shapes
+layout(location = 1) in vec2 v_uv;
+
+layout(set = 0, binding = 0) uniform sampler2D atlas;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // Sampled either way, keeping the texture read in uniform control flow
+    vec4 texel = texture(atlas, max(v_uv, vec2(0.0)));
+    f_color = v_uv.x < 0.0 ? v_color : v_color * texel;
+}
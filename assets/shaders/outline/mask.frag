@@ -0,0 +1,9 @@
+#version 450
+
+// Drawn with depth.vert for every selected object, without depth testing so hidden parts of
+// a selection still get outlined
+layout(location = 0) out float f_mask;
+
+void main() {
+    f_mask = 1.0;
+}
@@ -0,0 +1,38 @@
+#version 450
+
+// Coverage of the selected objects, at the scene's render resolution
+layout(set = 0, binding = 0) uniform sampler2D u_mask;
+
+layout(push_constant) uniform PushConstants {
+    vec4 color;
+    // In mask pixels
+    float width;
+} push_constants;
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    // Only the ring around the silhouette is drawn, the selection itself stays as it was
+    if (texture(u_mask, v_uv).r >= 0.5) {
+        discard;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(u_mask, 0));
+    float width = push_constants.width;
+    int radius = int(ceil(width));
+    float coverage = 0.0;
+    for (int y = -radius; y <= radius; y++) {
+        for (int x = -radius; x <= radius; x++) {
+            if (float(x * x + y * y) > width * width) {
+                continue;
+            }
+            coverage = max(coverage, texture(u_mask, v_uv + vec2(x, y) * texel).r);
+        }
+    }
+    if (coverage <= 0.0) {
+        discard;
+    }
+
+    f_color = vec4(push_constants.color.rgb, push_constants.color.a * coverage);
+}
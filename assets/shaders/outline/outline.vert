@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) out vec2 v_uv;
+
+// A single triangle covering the screen, no vertex buffer needed
+void main() {
+    vec2 position = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    v_uv = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
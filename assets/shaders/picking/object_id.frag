@@ -0,0 +1,15 @@
+#version 450
+
+// Drawn with the geometry vertex shaders, so objects are picked where they're drawn, skinned
+// meshes included. Zero is left wherever nothing was drawn
+layout(push_constant) uniform PickObject {
+    // The object's index plus one
+    uint object;
+}
+pick;
+
+layout(location = 0) out uint f_object;
+
+void main() {
+    f_object = pick.object;
+}
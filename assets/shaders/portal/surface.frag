@@ -0,0 +1,18 @@
+#version 450
+
+// Draws a portal's surface into a portal view's target.
+layout(set = 0, binding = 0) uniform sampler2D portal_view;
+
+layout(push_constant) uniform Surface {
+    mat4 model_view_projection;
+    vec2 viewport;
+    uint has_view;
+}
+surface;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    vec3 color = texture(portal_view, gl_FragCoord.xy / surface.viewport).rgb;
+    f_color = vec4(surface.has_view != 0 ? color : vec3(0.0), 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(push_constant) uniform Surface {
+    mat4 model_view_projection;
+    // Size of the target being drawn into, to sample the view in screen space
+    vec2 viewport;
+    // Zero when the portal's view wasn't rendered, e.g. past the recursion depth
+    uint has_view;
+}
+surface;
+
+// The unit quad in x and y, facing +z
+const vec2 CORNERS[6] = vec2[](
+    vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+    vec2(0.5, 0.5), vec2(-0.5, 0.5), vec2(-0.5, -0.5)
+);
+
+void main() {
+    gl_Position = surface.model_view_projection * vec4(CORNERS[gl_VertexIndex], 0.0, 1.0);
+}
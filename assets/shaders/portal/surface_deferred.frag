@@ -0,0 +1,28 @@
+#version 450
+
+// Draws a portal's surface into the G-buffer. The view is already lit, so it goes in as emitted
+// light on an unlit black surface.
+layout(set = 0, binding = 0) uniform sampler2D portal_view;
+
+layout(push_constant) uniform Surface {
+    mat4 model_view_projection;
+    vec2 viewport;
+    uint has_view;
+}
+surface;
+
+layout(location = 0) out vec4 f_color;
+layout(location = 1) out vec4 f_normal;
+layout(location = 2) out vec4 f_material;
+layout(location = 3) out vec4 f_emissive;
+layout(location = 4) out vec2 f_velocity;
+
+void main() {
+    vec3 color = texture(portal_view, gl_FragCoord.xy / surface.viewport).rgb;
+    f_color = vec4(0.0, 0.0, 0.0, 1.0);
+    // No normal, so only the ambient light touches it, and it adds the emitted light
+    f_normal = vec4(0.0);
+    f_material = vec4(0.0, 1.0, 0.0, 1.0);
+    f_emissive = vec4(surface.has_view != 0 ? color : vec3(0.0), 1.0);
+    f_velocity = vec2(0.0);
+}
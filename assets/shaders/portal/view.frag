@@ -0,0 +1,46 @@
+#version 450
+
+// Forward shaded version of geometry.frag for the scene seen through a portal, lit by the
+// ambient and directional lights without shadows or the environment.
+layout(location = 0) in vec3 in_color;
+layout(location = 1) in vec4 in_normal;
+layout(location = 2) in vec2 in_uv;
+layout(location = 3) flat in vec4 in_material;
+layout(location = 4) flat in vec3 in_emissive;
+layout(location = 5) flat in vec4 in_tint;
+layout(location = 8) in vec2 in_lightmap_uv;
+
+// The same material set as geometry.frag
+layout(set = 2, binding = 0) uniform sampler2D base_color_texture;
+layout(set = 2, binding = 1) uniform sampler2D metallic_roughness_texture;
+layout(set = 2, binding = 2) uniform sampler2D emissive_texture;
+layout(set = 2, binding = 3) uniform sampler2D lightmap_texture;
+
+layout(push_constant) uniform Lighting {
+    vec4 ambient;
+    // The way the directional light travels
+    vec4 sun_direction;
+    // Already scaled by the illuminance and exposure, zero without a directional light
+    vec4 sun_color;
+}
+lighting;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    vec3 base_color = in_color * texture(base_color_texture, in_uv).rgb;
+    vec3 albedo = mix(base_color, in_tint.rgb, in_tint.a);
+    float metallic = in_material.x * texture(metallic_roughness_texture, in_uv).b;
+
+    // Lightmaps stand in for the ambient light's diffuse term, as in the deferred pass
+    vec3 light = in_material.w > 0.5 ? texture(lightmap_texture, in_lightmap_uv).rgb : lighting.ambient.rgb;
+    // Meshes without normals are only lit by the ambient light, as in the deferred pass
+    if (dot(in_normal.xyz, in_normal.xyz) >= 0.25) {
+        vec3 n = normalize(in_normal.xyz);
+        light += lighting.sun_color.rgb * max(dot(n, -lighting.sun_direction.xyz), 0.0);
+    }
+
+    // Without the environment to reflect, metals reflect the ambient light in their own color
+    vec3 color = albedo * (1.0 - metallic) * light + albedo * metallic * lighting.ambient.rgb;
+    f_color = vec4(color + in_emissive * texture(emissive_texture, in_uv).rgb, 1.0);
+}
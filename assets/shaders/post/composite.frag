@@ -0,0 +1,45 @@
+#version 450
+
+// The lit scene, possibly rendered below the output resolution
+layout(set = 0, binding = 0) uniform sampler2D u_hdr;
+// The largest bloom level
+layout(set = 0, binding = 1) uniform sampler2D u_bloom;
+
+layout(push_constant) uniform PushConstants {
+    // Zero when bloom is disabled
+    float bloom_intensity;
+    // Zero for a plain bilinear upscale
+    float sharpness;
+    // Non-zero to write the scene's coverage as alpha, for a transparent window
+    uint transparent;
+} push_constants;
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 0) out vec4 f_color;
+
+// Contrast adaptive sharpening in the spirit of FSR1's RCAS: a negative lobe on the four
+// neighbours, weakened where local contrast is already high so edges don't ring
+vec3 sharpen(vec2 uv) {
+    vec2 texel = 1.0 / vec2(textureSize(u_hdr, 0));
+    vec3 c = texture(u_hdr, uv).rgb;
+    vec3 n = texture(u_hdr, uv + vec2(0.0, -texel.y)).rgb;
+    vec3 s = texture(u_hdr, uv + vec2(0.0, texel.y)).rgb;
+    vec3 e = texture(u_hdr, uv + vec2(texel.x, 0.0)).rgb;
+    vec3 w = texture(u_hdr, uv + vec2(-texel.x, 0.0)).rgb;
+
+    vec3 lo = min(c, min(min(n, s), min(e, w)));
+    vec3 hi = max(c, max(max(n, s), max(e, w)));
+    vec3 amount = sqrt(clamp(min(lo, 1.0 - min(hi, 1.0)) / max(hi, vec3(1e-5)), 0.0, 1.0));
+    vec3 weight = -amount * push_constants.sharpness * 0.2;
+
+    return max((c + (n + s + e + w) * weight) / (1.0 + 4.0 * weight), 0.0);
+}
+
+void main() {
+    vec3 scene = push_constants.sharpness > 0.0 ? sharpen(v_uv) : texture(u_hdr, v_uv).rgb;
+    vec3 color = scene + texture(u_bloom, v_uv).rgb * push_constants.bloom_intensity;
+    // Where nothing was drawn the color is black but for the bloom, so it's already
+    // premultiplied and glows over what's behind the window
+    float alpha = push_constants.transparent != 0 ? texture(u_hdr, v_uv).a : 1.0;
+    f_color = vec4(color, alpha);
+}
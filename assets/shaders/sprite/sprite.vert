@@ -0,0 +1,19 @@
+#version 450
+
+// VertexPositionNormalUv, matched by name, the normal isn't used
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 uv;
+
+layout(push_constant) uniform PushConstants {
+    // Unjittered, sprites are drawn after the TAA resolve
+    mat4 model_view_projection;
+} push_constants;
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec4 v_clip_position;
+
+void main() {
+    v_uv = uv;
+    v_clip_position = push_constants.model_view_projection * vec4(position, 1.0);
+    gl_Position = v_clip_position;
+}
@@ -0,0 +1,198 @@
+//! Renderer throughput: whole frames of a grid of cubes lit by point lights, across scene sizes
+//! and with direct and indirect draws. Needs a GPU and a display, the window stays open while the
+//! benches run. Run with `cargo bench --bench renderer`.
+//!
+//! Criterion times the CPU side of each frame, from queueing the scene to submitting it. The GPU
+//! pass times and draw counts the renderer reports are averaged and printed after each bench.
+
+use std::time::{Duration, Instant};
+
+use cgmath::{perspective, Deg, Matrix4, Point3, Quaternion, Rotation3, Vector3};
+use criterion::{BenchmarkId, Criterion};
+use triton::{
+    FrameStats, PointLight, Renderer, RendererConfig, Transform, CUBE_INDICES, CUBE_VERTICES,
+};
+use winit::{event_loop::EventLoop, platform::pump_events::EventLoopExtPumpEvents};
+
+const CUBES: [usize; 3] = [100, 1_000, 10_000];
+const LIGHTS: [usize; 3] = [0, 16, 256];
+const CUBE_SPACING: f32 = 3.0;
+const WINDOW_SIZE: [f32; 2] = [1280.0, 720.0];
+
+fn main() {
+    let mut criterion = Criterion::default()
+        .sample_size(30)
+        .measurement_time(Duration::from_secs(5))
+        .configure_from_args();
+    bench_frames(&mut criterion);
+    criterion.final_summary();
+}
+
+fn bench_frames(criterion: &mut Criterion) {
+    // winit only allows one event loop per process, so every bench shares the window
+    let mut event_loop = EventLoop::new().expect("creating event loop");
+    let mut renderer = Renderer::new(
+        &event_loop,
+        RendererConfig {
+            window_title: String::from("triton bench"),
+            window_size: WINDOW_SIZE,
+            vsync: false,
+            validation: false,
+            ..Default::default()
+        },
+    )
+    .expect("creating renderer");
+    let mesh_id = renderer
+        .create_mesh(CUBE_VERTICES.to_vec(), CUBE_INDICES.to_vec())
+        .expect("creating cube mesh");
+    renderer.set_hud_visible(false);
+
+    let mut group = criterion.benchmark_group("frame");
+    for indirect in [false, true] {
+        renderer.set_indirect_draws(indirect);
+        for cubes in CUBES {
+            for lights in LIGHTS {
+                let scene = Scene::new(cubes, lights);
+                let draws = if indirect { "indirect" } else { "direct" };
+                let parameter = format!("{} cubes, {} lights", cubes, lights);
+                let label = format!("{}/{}", draws, parameter);
+                let mut averages = Averages::default();
+                group.bench_function(BenchmarkId::new(draws, parameter), |b| {
+                    b.iter(|| {
+                        let _ = event_loop.pump_events(Some(Duration::ZERO), |_, _| {});
+                        let start = Instant::now();
+                        scene.enqueue(&mut renderer, mesh_id);
+                        renderer.render().expect("rendering");
+                        renderer.set_cpu_time(start.elapsed().as_secs_f32() * 1000.0);
+                        averages.add(&renderer.frame_stats());
+                    })
+                });
+                averages.print(&label);
+            }
+        }
+    }
+    group.finish();
+}
+
+/// The cubes and lights queued every frame, so queueing is measured along with drawing.
+struct Scene {
+    cubes: Vec<Transform>,
+    lights: Vec<PointLight>,
+    camera: (Matrix4<f32>, Matrix4<f32>),
+}
+
+impl Scene {
+    /// `cubes` in a cube of a grid, `lights` in a ring above it.
+    fn new(cubes: usize, lights: usize) -> Self {
+        let side = (cubes as f32).cbrt().ceil() as usize;
+        let extent = side as f32 * CUBE_SPACING;
+        let offset = (side as f32 - 1.0) * CUBE_SPACING * 0.5;
+        let cubes = (0..cubes)
+            .map(|index| Transform {
+                position: Vector3::new(
+                    (index % side) as f32 * CUBE_SPACING - offset,
+                    (index / side % side) as f32 * CUBE_SPACING - offset,
+                    (index / (side * side)) as f32 * CUBE_SPACING - offset,
+                ),
+                rotation: Quaternion::from_angle_y(Deg(index as f32 * 7.0)),
+                scale: Vector3::new(1.0, 1.0, 1.0),
+            })
+            .collect();
+        let lights = (0..lights)
+            .map(|index| {
+                let angle = index as f32 / lights as f32 * std::f32::consts::TAU;
+                PointLight {
+                    position: Vector3::new(angle.cos(), 0.5, angle.sin()) * extent * 0.5,
+                    radius: extent * 0.5,
+                    intensity: 800.0,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let eye = Point3::new(0.0, extent * 0.5, extent * 1.5);
+        let camera = (
+            perspective(
+                Deg(60.0),
+                WINDOW_SIZE[0] / WINDOW_SIZE[1],
+                0.1,
+                extent * 4.0,
+            ),
+            Matrix4::look_at_rh(eye, Point3::new(0.0, 0.0, 0.0), Vector3::unit_y()),
+        );
+        Scene {
+            cubes,
+            lights,
+            camera,
+        }
+    }
+
+    fn enqueue(&self, renderer: &mut Renderer, mesh_id: usize) {
+        renderer.set_camera_params(self.camera);
+        for cube in &self.cubes {
+            renderer.enqueue_mesh(mesh_id, *cube);
+        }
+        for light in &self.lights {
+            renderer.enqueue_point_light(*light);
+        }
+    }
+}
+
+/// Sums of the renderer's frame stats. GPU times lag and can be missing, so they're averaged over
+/// the frames that had them.
+#[derive(Default)]
+struct Averages {
+    frames: u32,
+    gpu_frames: u32,
+    gpu_frame_ms: f32,
+    gpu_scene_ms: f32,
+    gpu_post_process_ms: f32,
+    geometry_ms: f32,
+    lighting_ms: f32,
+    draw_calls: u64,
+    occluded_objects: u64,
+}
+
+impl Averages {
+    fn add(&mut self, stats: &FrameStats) {
+        self.frames += 1;
+        self.geometry_ms += stats.cpu_pass_ms.geometry;
+        self.lighting_ms += stats.cpu_pass_ms.lighting;
+        self.draw_calls += stats.draw_calls as u64;
+        self.occluded_objects += stats.occluded_objects as u64;
+        if let (Some(frame), Some(scene), Some(post_process)) = (
+            stats.gpu_time_ms,
+            stats.gpu_scene_ms,
+            stats.gpu_post_process_ms,
+        ) {
+            self.gpu_frames += 1;
+            self.gpu_frame_ms += frame;
+            self.gpu_scene_ms += scene;
+            self.gpu_post_process_ms += post_process;
+        }
+    }
+
+    fn print(&self, label: &str) {
+        if self.frames == 0 {
+            return;
+        }
+        let frames = self.frames as f32;
+        println!(
+            "{}: {:.0} draw calls, {:.0} occluded, recording geometry {:.3} ms, lighting {:.3} ms",
+            label,
+            self.draw_calls as f32 / frames,
+            self.occluded_objects as f32 / frames,
+            self.geometry_ms / frames,
+            self.lighting_ms / frames,
+        );
+        if self.gpu_frames > 0 {
+            let gpu_frames = self.gpu_frames as f32;
+            println!(
+                "{}: gpu frame {:.3} ms, scene {:.3} ms, post process {:.3} ms",
+                label,
+                self.gpu_frame_ms / gpu_frames,
+                self.gpu_scene_ms / gpu_frames,
+                self.gpu_post_process_ms / gpu_frames,
+            );
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use super::compression::{
+    compress, BlockFormat, CompressedTexture, CompressionQuality, TextureUsage,
+};
+
+const MAGIC: &[u8; 4] = b"TRTX";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 17;
+
+/// Imports source images into block compressed textures, keeping the results on disk so each
+/// image is only encoded once per change to its contents or import settings.
+pub struct TextureCache {
+    root: PathBuf,
+    quality: CompressionQuality,
+}
+
+impl TextureCache {
+    pub fn new(root: impl Into<PathBuf>, quality: CompressionQuality) -> Self {
+        TextureCache {
+            root: root.into(),
+            quality,
+        }
+    }
+
+    pub fn load(&self, source: &Path, usage: TextureUsage) -> anyhow::Result<CompressedTexture> {
+        profile_scope!("load texture");
+        let cache_path = self.cache_path(source, usage)?;
+
+        if let Ok(bytes) = fs::read(&cache_path) {
+            match decode(&bytes) {
+                Ok(texture) => return Ok(texture),
+                Err(e) => log::warn!("discarding cached texture {:?}: {}", cache_path, e),
+            }
+        }
+
+        let image = image::open(source)
+            .with_context(|| format!("decoding {:?}", source))?
+            .to_rgba8();
+
+        let texture = compress(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            usage,
+            self.quality,
+        );
+
+        fs::create_dir_all(&self.root).context("creating texture cache directory")?;
+        fs::write(&cache_path, encode(&texture))
+            .with_context(|| format!("writing cached texture {:?}", cache_path))?;
+
+        Ok(texture)
+    }
+
+    /// Cache entries are keyed on everything that changes the encoded output, so stale entries
+    /// are never read back. They're just left behind for a cache clear to pick up.
+    fn cache_path(&self, source: &Path, usage: TextureUsage) -> anyhow::Result<PathBuf> {
+        let modified = fs::metadata(source)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("reading metadata of {:?}", source))?;
+
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        modified.hash(&mut hasher);
+        usage.hash(&mut hasher);
+        self.quality.hash(&mut hasher);
+        VERSION.hash(&mut hasher);
+
+        Ok(self.root.join(format!("{:016x}.tex", hasher.finish())))
+    }
+}
+
+fn encode(texture: &CompressedTexture) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + texture.data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.push(texture.format.to_u8());
+    bytes.extend_from_slice(&texture.width.to_le_bytes());
+    bytes.extend_from_slice(&texture.height.to_le_bytes());
+    bytes.extend_from_slice(&texture.data);
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> anyhow::Result<CompressedTexture> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+        bail!("not a cached texture");
+    }
+
+    let read_u32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+
+    if read_u32(4) != VERSION {
+        bail!("cached texture version {} is out of date", read_u32(4));
+    }
+
+    let format =
+        BlockFormat::from_u8(bytes[8]).ok_or_else(|| anyhow!("unknown format {}", bytes[8]))?;
+
+    Ok(CompressedTexture {
+        format,
+        width: read_u32(9),
+        height: read_u32(13),
+        data: bytes[HEADER_SIZE..].to_vec(),
+    })
+}
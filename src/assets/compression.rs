@@ -0,0 +1,169 @@
+use intel_tex_2::{bc4, bc5, bc7, RSurface, RgSurface, RgbaSurface};
+use vulkano::format::Format;
+
+/// Trades import time for quality. Only BC7 has tunable encoders, BC4 and BC5 ignore this.
+#[derive(Debug, Default, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum CompressionQuality {
+    Fast,
+    #[default]
+    Default,
+    High,
+}
+
+/// What a texture's channels hold, which decides the block format it's encoded to.
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum TextureUsage {
+    /// Color data with optional alpha, encoded as BC7.
+    Color { srgb: bool },
+    /// Tangent space normals, encoded as BC5 with Z reconstructed in the shader.
+    Normal,
+    /// Single channel data (roughness, occlusion, masks) read from red, encoded as BC4.
+    Mask,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum BlockFormat {
+    Bc4,
+    Bc5,
+    Bc7,
+    Bc7Srgb,
+}
+
+impl BlockFormat {
+    pub fn vk_format(self) -> Format {
+        match self {
+            BlockFormat::Bc4 => Format::BC4_UNORM_BLOCK,
+            BlockFormat::Bc5 => Format::BC5_UNORM_BLOCK,
+            BlockFormat::Bc7 => Format::BC7_UNORM_BLOCK,
+            BlockFormat::Bc7Srgb => Format::BC7_SRGB_BLOCK,
+        }
+    }
+
+    pub(super) fn to_u8(self) -> u8 {
+        match self {
+            BlockFormat::Bc4 => 0,
+            BlockFormat::Bc5 => 1,
+            BlockFormat::Bc7 => 2,
+            BlockFormat::Bc7Srgb => 3,
+        }
+    }
+
+    pub(super) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(BlockFormat::Bc4),
+            1 => Some(BlockFormat::Bc5),
+            2 => Some(BlockFormat::Bc7),
+            3 => Some(BlockFormat::Bc7Srgb),
+            _ => None,
+        }
+    }
+}
+
+pub struct CompressedTexture {
+    pub format: BlockFormat,
+    /// Dimensions of the source image. The encoded data covers these rounded up to whole blocks.
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Block compresses tightly packed RGBA8 pixels.
+pub fn compress(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    usage: TextureUsage,
+    quality: CompressionQuality,
+) -> CompressedTexture {
+    let (padded, padded_width, padded_height) = pad_to_blocks(rgba, width, height);
+
+    let (format, data) = match usage {
+        TextureUsage::Color { srgb } => {
+            let opaque = padded.chunks_exact(4).all(|p| p[3] == u8::MAX);
+            let settings = match (quality, opaque) {
+                (CompressionQuality::Fast, true) => bc7::opaque_very_fast_settings(),
+                (CompressionQuality::Fast, false) => bc7::alpha_very_fast_settings(),
+                (CompressionQuality::Default, true) => bc7::opaque_basic_settings(),
+                (CompressionQuality::Default, false) => bc7::alpha_basic_settings(),
+                (CompressionQuality::High, true) => bc7::opaque_slow_settings(),
+                (CompressionQuality::High, false) => bc7::alpha_slow_settings(),
+            };
+            let surface = RgbaSurface {
+                data: &padded,
+                width: padded_width,
+                height: padded_height,
+                stride: padded_width * 4,
+            };
+            let format = if srgb {
+                BlockFormat::Bc7Srgb
+            } else {
+                BlockFormat::Bc7
+            };
+            (format, bc7::compress_blocks(&settings, &surface))
+        }
+        TextureUsage::Normal => {
+            let rg = pack_normal_xy(&padded);
+            let surface = RgSurface {
+                data: &rg,
+                width: padded_width,
+                height: padded_height,
+                stride: padded_width * 2,
+            };
+            (BlockFormat::Bc5, bc5::compress_blocks(&surface))
+        }
+        TextureUsage::Mask => {
+            let r: Vec<u8> = padded.chunks_exact(4).map(|p| p[0]).collect();
+            let surface = RSurface {
+                data: &r,
+                width: padded_width,
+                height: padded_height,
+                stride: padded_width,
+            };
+            (BlockFormat::Bc4, bc4::compress_blocks(&surface))
+        }
+    };
+
+    CompressedTexture {
+        format,
+        width,
+        height,
+        data,
+    }
+}
+
+/// Renormalizes each normal and keeps only X and Y. Authoring tools don't always write unit
+/// vectors, and since Z is rebuilt from X and Y any error there would skew the result.
+fn pack_normal_xy(rgba: &[u8]) -> Vec<u8> {
+    let decode = |v: u8| v as f32 / 127.5 - 1.0;
+    let encode = |v: f32| ((v * 0.5 + 0.5) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    rgba.chunks_exact(4)
+        .flat_map(|p| {
+            let (x, y, z) = (decode(p[0]), decode(p[1]), decode(p[2]).max(0.0));
+            let length = (x * x + y * y + z * z).sqrt().max(f32::EPSILON);
+            [encode(x / length), encode(y / length)]
+        })
+        .collect()
+}
+
+/// The encoders work on whole 4x4 blocks, so odd sized images are extended by repeating their
+/// last row and column.
+fn pad_to_blocks(rgba: &[u8], width: u32, height: u32) -> (Vec<u8>, u32, u32) {
+    let padded_width = width.next_multiple_of(4);
+    let padded_height = height.next_multiple_of(4);
+
+    if padded_width == width && padded_height == height {
+        return (rgba.to_vec(), width, height);
+    }
+
+    let mut padded = Vec::with_capacity((padded_width * padded_height * 4) as usize);
+    for y in 0..padded_height {
+        let src_row = y.min(height - 1) as usize * width as usize * 4;
+        for x in 0..padded_width {
+            let src = src_row + x.min(width - 1) as usize * 4;
+            padded.extend_from_slice(&rgba[src..src + 4]);
+        }
+    }
+
+    (padded, padded_width, padded_height)
+}
@@ -0,0 +1,508 @@
+use std::{f32::consts::PI, fs::File, io::BufWriter, path::Path};
+
+use anyhow::Context;
+use cgmath::{ElementWise, InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+use ddsfile::{AlphaMode, D3D10ResourceDimension, Dds, DxgiFormat, NewDxgiParams};
+use rayon::prelude::*;
+
+use crate::{random::Random, renderer::VertexPositionNormalUvLightmap};
+
+/// Rays leave surfaces this far along their normal so they don't hit the triangle they left.
+const RAY_OFFSET: f32 = 1e-3;
+/// Texels no triangle covers take the average of their covered neighbours this many times over,
+/// so filtering across the edges of UV islands doesn't pull in black.
+const DILATION_PASSES: u32 = 2;
+const BVH_LEAF_SIZE: usize = 4;
+
+/// A world position and normal on a mesh.
+type Surface = (Vector3<f32>, Vector3<f32>);
+
+/// A static mesh placed in the scene being baked. Every mesh gets its own lightmap, and blocks
+/// and bounces light for the others.
+#[derive(Debug, Clone)]
+pub struct LightmapMesh<'a> {
+    pub vertices: &'a [VertexPositionNormalUvLightmap],
+    pub indices: &'a [u32],
+    pub model: Matrix4<f32>,
+    /// Linear diffuse color, tinting the light bounced off the mesh.
+    pub albedo: [f32; 3],
+    /// Width and height of the mesh's lightmap, in texels.
+    pub resolution: u32,
+}
+
+/// The directional light, which the renderer lights with at runtime but is baked into the light
+/// it bounces around the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct LightmapSun {
+    /// The way the light travels.
+    pub direction: Vector3<f32>,
+    /// The light a white surface facing the sun reflects, in the same units as the sky.
+    pub color: [f32; 3],
+}
+
+#[derive(Debug, Clone)]
+pub struct LightmapSettings {
+    /// Rays traced per texel. Noise falls with the square root of the count.
+    pub samples: u32,
+    /// How many times light bounces between meshes. 0 bakes only how much of the sky each texel
+    /// sees.
+    pub bounces: u32,
+    /// Linear radiance of the sky, usually the ambient light's color.
+    pub sky: [f32; 3],
+    pub sun: Option<LightmapSun>,
+    /// Seeds the ray directions, so the same scene bakes the same lightmaps every time.
+    pub seed: u32,
+}
+
+impl Default for LightmapSettings {
+    fn default() -> Self {
+        LightmapSettings {
+            samples: 256,
+            bounces: 2,
+            sky: [1.0; 3],
+            sun: None,
+            seed: 1,
+        }
+    }
+}
+
+/// A baked lightmap, ready to be saved and loaded back with `Renderer::load_lightmap`.
+#[derive(Debug, Clone)]
+pub struct Lightmap {
+    pub extent: [u32; 2],
+    /// Linear RGB, row by row from the top.
+    pub texels: Vec<[f32; 3]>,
+}
+
+impl Lightmap {
+    /// Writes the lightmap as an RGBA 32 bit float DDS file.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let [width, height] = self.extent;
+        let mut dds = Dds::new_dxgi(NewDxgiParams {
+            height,
+            width,
+            depth: None,
+            format: DxgiFormat::R32G32B32A32_Float,
+            mipmap_levels: None,
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Unknown,
+        })
+        .context("creating lightmap DDS header")?;
+        dds.data = self
+            .texels
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0])
+            .flat_map(f32::to_le_bytes)
+            .collect();
+
+        let file = File::create(path).with_context(|| format!("creating {:?}", path))?;
+        dds.write(&mut BufWriter::new(file))
+            .with_context(|| format!("writing lightmap {:?}", path))
+    }
+}
+
+/// Path traces the light reaching each texel of each mesh's lightmap, returned in the same
+/// order as `meshes`.
+///
+/// Only indirect light is baked, the sky and what bounces off other surfaces, since the
+/// renderer still lights lightmapped meshes with its lights and reflections at runtime. Runs on
+/// rayon's global pool and can take a while, so it's meant for offline tools rather than the
+/// game loop.
+pub fn bake_lightmaps(
+    meshes: &[LightmapMesh],
+    settings: &LightmapSettings,
+) -> anyhow::Result<Vec<Lightmap>> {
+    profile_scope!("bake lightmaps");
+    let scene = Scene::new(meshes);
+
+    meshes
+        .iter()
+        .enumerate()
+        .map(|(index, mesh)| {
+            let texels =
+                rasterize(mesh).with_context(|| format!("rasterizing lightmap mesh {}", index))?;
+            let resolution = mesh.resolution as usize;
+
+            let mut light: Vec<Option<Vector3<f32>>> = texels
+                .par_iter()
+                .enumerate()
+                .map(|(texel, surface)| {
+                    let (position, normal) = (*surface)?;
+                    let mut random =
+                        Random::new(hash(&[settings.seed, index as u32, texel as u32]));
+                    let origin = position + normal * RAY_OFFSET;
+                    let sum = (0..settings.samples.max(1))
+                        .map(|_| {
+                            let direction = cosine_sample(normal, &mut random);
+                            scene.trace(origin, direction, settings, &mut random)
+                        })
+                        .fold(Vector3::new(0.0, 0.0, 0.0), |sum, light| sum + light);
+                    // Cosine weighted samples average straight to the irradiance over pi
+                    Some(sum / settings.samples.max(1) as f32)
+                })
+                .collect();
+
+            for _ in 0..DILATION_PASSES {
+                light = dilate(&light, resolution);
+            }
+
+            Ok(Lightmap {
+                extent: [mesh.resolution, mesh.resolution],
+                texels: light
+                    .into_iter()
+                    .map(|light| light.map_or([0.0; 3], Into::into))
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// The world position and normal at the center of each texel a triangle covers.
+fn rasterize(mesh: &LightmapMesh) -> anyhow::Result<Vec<Option<Surface>>> {
+    let model = mesh.model;
+    let normal_matrix =
+        Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate())
+            .invert()
+            .context("inverting lightmap mesh transform")?
+            .transpose();
+    let resolution = mesh.resolution as usize;
+    let mut texels = vec![None; resolution * resolution];
+
+    for triangle in mesh.indices.chunks_exact(3) {
+        let vertices = [
+            &mesh.vertices[triangle[0] as usize],
+            &mesh.vertices[triangle[1] as usize],
+            &mesh.vertices[triangle[2] as usize],
+        ];
+        let uvs = vertices.map(|vertex| {
+            [
+                vertex.lightmap_uv[0] * mesh.resolution as f32,
+                vertex.lightmap_uv[1] * mesh.resolution as f32,
+            ]
+        });
+        let area = edge(uvs[0], uvs[1], uvs[2]);
+        if area.abs() < f32::EPSILON {
+            continue;
+        }
+
+        // Negative coordinates saturate to 0 when cast
+        let texels_along = |axis: usize| {
+            let min = uvs.iter().map(|uv| uv[axis]).fold(f32::MAX, f32::min);
+            let max = uvs.iter().map(|uv| uv[axis]).fold(f32::MIN, f32::max);
+            min.floor() as usize..(max.ceil() as usize).min(resolution)
+        };
+
+        for y in texels_along(1) {
+            for x in texels_along(0) {
+                let center = [x as f32 + 0.5, y as f32 + 0.5];
+                let weights = [
+                    edge(uvs[1], uvs[2], center) / area,
+                    edge(uvs[2], uvs[0], center) / area,
+                    edge(uvs[0], uvs[1], center) / area,
+                ];
+                if weights.iter().any(|&weight| weight < -1e-4) {
+                    continue;
+                }
+
+                let (position, normal) = vertices.iter().zip(weights).fold(
+                    (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+                    |(position, normal), (vertex, weight)| {
+                        (
+                            position + Vector3::from(vertex.position) * weight,
+                            normal + Vector3::from(vertex.normal) * weight,
+                        )
+                    },
+                );
+                let position = (model * position.extend(1.0)).truncate();
+                let normal = (normal_matrix * normal).normalize();
+                if normal.x.is_finite() {
+                    texels[y * resolution + x] = Some((position, normal));
+                }
+            }
+        }
+    }
+
+    Ok(texels)
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p`.
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Fills each empty texel with the average of its filled neighbours.
+fn dilate(light: &[Option<Vector3<f32>>], resolution: usize) -> Vec<Option<Vector3<f32>>> {
+    (0..light.len())
+        .map(|texel| {
+            if light[texel].is_some() {
+                return light[texel];
+            }
+            let (x, y) = ((texel % resolution) as i64, (texel / resolution) as i64);
+            let (sum, count) = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+                .filter(|&(x, y)| {
+                    (0..resolution as i64).contains(&x) && (0..resolution as i64).contains(&y)
+                })
+                .filter_map(|(x, y)| light[y as usize * resolution + x as usize])
+                .fold((Vector3::new(0.0, 0.0, 0.0), 0), |(sum, count), light| {
+                    (sum + light, count + 1)
+                });
+            (count > 0).then(|| sum / count as f32)
+        })
+        .collect()
+}
+
+struct Triangle {
+    vertices: [Vector3<f32>; 3],
+    normal: Vector3<f32>,
+    albedo: Vector3<f32>,
+}
+
+impl Triangle {
+    /// Möller-Trumbore, returning the distance along the ray.
+    fn intersect(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> Option<f32> {
+        let edge1 = self.vertices[1] - self.vertices[0];
+        let edge2 = self.vertices[2] - self.vertices[0];
+        let p = direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < 1e-8 {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let s = origin - self.vertices[0];
+        let u = s.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge2.dot(q) * inverse;
+        (distance > 0.0).then_some(distance)
+    }
+}
+
+/// A bounding volume hierarchy node. Leaves hold `count` triangles from `first`, inner nodes
+/// have their first child right after them and their second at `first`.
+struct Node {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    first: usize,
+    count: usize,
+}
+
+/// Every mesh's triangles in world space.
+struct Scene {
+    triangles: Vec<Triangle>,
+    nodes: Vec<Node>,
+}
+
+impl Scene {
+    fn new(meshes: &[LightmapMesh]) -> Self {
+        let mut triangles = vec![];
+        for mesh in meshes {
+            let positions: Vec<_> = mesh
+                .vertices
+                .iter()
+                .map(|vertex| {
+                    let [x, y, z] = vertex.position;
+                    (mesh.model * Vector4::new(x, y, z, 1.0)).truncate()
+                })
+                .collect();
+            for triangle in mesh.indices.chunks_exact(3) {
+                let vertices = [
+                    positions[triangle[0] as usize],
+                    positions[triangle[1] as usize],
+                    positions[triangle[2] as usize],
+                ];
+                let normal = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]);
+                if normal.magnitude2() <= 0.0 {
+                    continue;
+                }
+                triangles.push(Triangle {
+                    vertices,
+                    normal: normal.normalize(),
+                    albedo: mesh.albedo.into(),
+                });
+            }
+        }
+
+        let mut scene = Scene {
+            triangles,
+            nodes: vec![],
+        };
+        if !scene.triangles.is_empty() {
+            scene.build(0, scene.triangles.len());
+        }
+        scene
+    }
+
+    /// Splits the triangles from `first` at the median of their centers along the longest axis.
+    fn build(&mut self, first: usize, count: usize) -> usize {
+        let triangles = &mut self.triangles[first..first + count];
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for vertex in triangles.iter().flat_map(|triangle| triangle.vertices) {
+            min = min_vector(min, vertex);
+            max = max_vector(max, vertex);
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            min,
+            max,
+            first,
+            count,
+        });
+        if count <= BVH_LEAF_SIZE {
+            return index;
+        }
+
+        let size = max - min;
+        let axis = if size.x > size.y && size.x > size.z {
+            0
+        } else if size.y > size.z {
+            1
+        } else {
+            2
+        };
+        let center = |triangle: &Triangle| {
+            triangle.vertices[0][axis] + triangle.vertices[1][axis] + triangle.vertices[2][axis]
+        };
+        let half = count / 2;
+        triangles.select_nth_unstable_by(half, |a, b| center(a).total_cmp(&center(b)));
+
+        self.build(first, half);
+        let second = self.build(first + half, count - half);
+        self.nodes[index].first = second;
+        self.nodes[index].count = 0;
+        index
+    }
+
+    /// The nearest triangle the ray hits and how far along it.
+    fn hit(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> Option<(f32, &Triangle)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse = Vector3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        let mut nearest: Option<(f32, &Triangle)> = None;
+        let mut stack = vec![0];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let limit = nearest.map_or(f32::MAX, |(distance, _)| distance);
+            if !hits_box(node, origin, inverse, limit) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(index + 1);
+                continue;
+            }
+            for triangle in &self.triangles[node.first..node.first + node.count] {
+                if let Some(distance) = triangle.intersect(origin, direction) {
+                    if distance < nearest.map_or(f32::MAX, |(nearest, _)| nearest) {
+                        nearest = Some((distance, triangle));
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    /// The light arriving at `origin` from `direction`, following it through `bounces`.
+    fn trace(
+        &self,
+        mut origin: Vector3<f32>,
+        mut direction: Vector3<f32>,
+        settings: &LightmapSettings,
+        random: &mut Random,
+    ) -> Vector3<f32> {
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut light = Vector3::new(0.0, 0.0, 0.0);
+
+        for bounce in 0..=settings.bounces {
+            let Some((distance, triangle)) = self.hit(origin, direction) else {
+                light += throughput.mul_element_wise(Vector3::from(settings.sky));
+                break;
+            };
+            if bounce == settings.bounces {
+                break;
+            }
+
+            // Both sides of a triangle bounce light
+            let normal = if triangle.normal.dot(direction) > 0.0 {
+                -triangle.normal
+            } else {
+                triangle.normal
+            };
+            origin += direction * distance + normal * RAY_OFFSET;
+            throughput = throughput.mul_element_wise(triangle.albedo);
+
+            if let Some(sun) = settings.sun {
+                let to_sun = -sun.direction.normalize();
+                let cos = normal.dot(to_sun);
+                if cos > 0.0 && self.hit(origin, to_sun).is_none() {
+                    light += throughput.mul_element_wise(Vector3::from(sun.color)) * cos;
+                }
+            }
+            direction = cosine_sample(normal, random);
+        }
+
+        light
+    }
+}
+
+fn hits_box(node: &Node, origin: Vector3<f32>, inverse: Vector3<f32>, limit: f32) -> bool {
+    let mut near = 0.0f32;
+    let mut far = limit;
+    for axis in 0..3 {
+        let a = (node.min[axis] - origin[axis]) * inverse[axis];
+        let b = (node.max[axis] - origin[axis]) * inverse[axis];
+        near = near.max(a.min(b));
+        far = far.min(a.max(b));
+    }
+    near <= far
+}
+
+fn min_vector(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn max_vector(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
+
+/// A direction around `normal`, more likely the closer it is to the normal.
+fn cosine_sample(normal: Vector3<f32>, random: &mut Random) -> Vector3<f32> {
+    let angle = 2.0 * PI * random.next();
+    let radius2 = random.next();
+    let radius = radius2.sqrt();
+
+    let up = if normal.y.abs() < 0.999 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent * (radius * angle.cos())
+        + bitangent * (radius * angle.sin())
+        + normal * (1.0 - radius2).max(0.0).sqrt())
+    .normalize()
+}
+
+/// FNV-1a, mixing the seed with where a texel is so each gets its own ray directions no matter
+/// which thread bakes it.
+fn hash(values: &[u32]) -> u32 {
+    values.iter().fold(0x811c_9dc5, |hash, value| {
+        value.to_le_bytes().iter().fold(hash, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+    })
+}
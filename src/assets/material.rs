@@ -0,0 +1,98 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::renderer::{BlendMode, Material, MaterialShader};
+
+/// A material as written in a RON file, loaded with `AssetServer::load_material`. Texture paths
+/// are relative to the file, and anything left out takes `Material`'s default.
+///
+/// ```ron
+/// (
+///     base_color: (0.8, 0.2, 0.2, 1.0),
+///     metallic: 1.0,
+///     roughness: 0.3,
+///     shader: Lit,
+///     blend: Mask,
+///     base_color_texture: Some("textures/rust.png"),
+/// )
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialFile {
+    /// Linear RGBA.
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub shader: MaterialShader,
+    pub blend: BlendMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_color_texture: Option<PathBuf>,
+    /// Roughness in green and metallic in blue, as in glTF.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metallic_roughness_texture: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emissive_texture: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lightmap: Option<PathBuf>,
+}
+
+impl Default for MaterialFile {
+    fn default() -> Self {
+        let material = Material::default();
+        MaterialFile {
+            base_color: material.base_color,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            emissive: material.emissive,
+            shader: material.shader,
+            blend: material.blend,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            emissive_texture: None,
+            lightmap: None,
+        }
+    }
+}
+
+impl MaterialFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        ron::from_str(&text).with_context(|| format!("parsing {:?}", path))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .context("serializing material")?;
+        fs::write(path, text).with_context(|| format!("writing {:?}", path))
+    }
+
+    /// Takes the factors of `material`, e.g. after they were tweaked in the editor. Textures
+    /// are left as they are.
+    pub fn set_factors(&mut self, material: &Material) {
+        self.base_color = material.base_color;
+        self.metallic = material.metallic;
+        self.roughness = material.roughness;
+        self.emissive = material.emissive;
+        self.shader = material.shader;
+        self.blend = material.blend;
+    }
+
+    /// The factors as a `Material` without textures, for the caller to load them into.
+    pub fn factors(&self) -> Material {
+        Material {
+            base_color: self.base_color,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            emissive: self.emissive,
+            shader: self.shader,
+            blend: self.blend,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,26 @@
+pub use cache::TextureCache;
+pub use compression::{BlockFormat, CompressedTexture, CompressionQuality, TextureUsage};
+#[cfg(feature = "lightmap-baker")]
+pub use lightmap::{bake_lightmaps, Lightmap, LightmapMesh, LightmapSettings, LightmapSun};
+pub use material::MaterialFile;
+#[cfg(feature = "obj")]
+pub use obj::{load_obj, ObjMaterial, ObjMesh, ObjModel};
+pub use server::AssetServer;
+#[cfg(feature = "tiled")]
+pub use tilemap::{
+    load_tilemap, Tilemap, TilemapChunk, TilemapData, TilesetImage, TILEMAP_CHUNK_SIZE,
+};
+pub(crate) use watcher::canonical;
+pub use watcher::AssetWatcher;
+
+mod cache;
+mod compression;
+#[cfg(feature = "lightmap-baker")]
+mod lightmap;
+mod material;
+#[cfg(feature = "obj")]
+mod obj;
+mod server;
+#[cfg(feature = "tiled")]
+mod tilemap;
+mod watcher;
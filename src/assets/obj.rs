@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::renderer::{VertexPositionColorNormal, VertexPositionNormalUv};
+
+/// The parts of an MTL material the renderer can make use of. Texture paths are resolved
+/// relative to the OBJ file, ready to be imported through a `TextureCache`.
+#[derive(Debug, Clone)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: [f32; 3],
+    pub diffuse_texture: Option<PathBuf>,
+    pub normal_texture: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjMesh {
+    pub name: String,
+    pub vertices: Vec<VertexPositionNormalUv>,
+    pub indices: Vec<u32>,
+    /// Index into `ObjModel::materials`.
+    pub material: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: Vec<ObjMaterial>,
+}
+
+impl ObjModel {
+    /// A mesh's vertices with its material's diffuse color baked in, for drawing with the
+    /// vertex colored pipeline.
+    pub fn colored_vertices(&self, mesh: &ObjMesh) -> Vec<VertexPositionColorNormal> {
+        let color = mesh
+            .material
+            .and_then(|index| self.materials.get(index))
+            .map_or([1.0; 3], |material| material.diffuse);
+
+        mesh.vertices
+            .iter()
+            .map(|vertex| VertexPositionColorNormal::new(vertex.position, color, vertex.normal))
+            .collect()
+    }
+}
+
+/// Loads an OBJ file and the MTL libraries it references. Faces are triangulated, and meshes
+/// without normals get smooth normals generated from their faces. A missing or broken material
+/// library is logged and the meshes are loaded without materials.
+pub fn load_obj(path: &Path) -> anyhow::Result<ObjModel> {
+    profile_scope!("load obj");
+    let (models, materials) = tobj::load_obj(
+        path,
+        &tobj::LoadOptions {
+            single_index: true,
+            triangulate: true,
+            ..Default::default()
+        },
+    )
+    .with_context(|| format!("loading {:?}", path))?;
+
+    let materials = materials.unwrap_or_else(|e| {
+        log::warn!("loading materials of {:?}: {}", path, e);
+        vec![]
+    });
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let materials = materials
+        .into_iter()
+        .map(|material| ObjMaterial {
+            name: material.name,
+            diffuse: material.diffuse.unwrap_or([1.0; 3]),
+            diffuse_texture: material.diffuse_texture.map(|t| base.join(t)),
+            normal_texture: material.normal_texture.map(|t| base.join(t)),
+        })
+        .collect();
+
+    let meshes = models
+        .into_iter()
+        .map(|model| {
+            let mesh = model.mesh;
+            let vertex_count = mesh.positions.len() / 3;
+
+            let normals = if mesh.normals.len() == mesh.positions.len() {
+                mesh.normals.clone()
+            } else {
+                smooth_normals(&mesh.positions, &mesh.indices)
+            };
+
+            let vertices = (0..vertex_count)
+                .map(|i| VertexPositionNormalUv {
+                    position: [
+                        mesh.positions[i * 3],
+                        mesh.positions[i * 3 + 1],
+                        mesh.positions[i * 3 + 2],
+                    ],
+                    normal: [normals[i * 3], normals[i * 3 + 1], normals[i * 3 + 2]],
+                    // OBJ puts the texture origin at the bottom left
+                    uv: match mesh.texcoords.get(i * 2..i * 2 + 2) {
+                        Some(uv) => [uv[0], 1.0 - uv[1]],
+                        None => [0.0, 0.0],
+                    },
+                })
+                .collect();
+
+            ObjMesh {
+                name: model.name,
+                vertices,
+                indices: mesh.indices,
+                material: mesh.material_id,
+            }
+        })
+        .collect();
+
+    Ok(ObjModel { meshes, materials })
+}
+
+/// Area weighted vertex normals, accumulated from every face sharing the vertex.
+fn smooth_normals(positions: &[f32], indices: &[u32]) -> Vec<f32> {
+    let mut normals = vec![0.0f32; positions.len()];
+    let position = |i: u32| {
+        let i = i as usize * 3;
+        [positions[i], positions[i + 1], positions[i + 2]]
+    };
+
+    for face in indices.chunks_exact(3) {
+        let [a, b, c] = [position(face[0]), position(face[1]), position(face[2])];
+        let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let ac = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        let normal = [
+            ab[1] * ac[2] - ab[2] * ac[1],
+            ab[2] * ac[0] - ab[0] * ac[2],
+            ab[0] * ac[1] - ab[1] * ac[0],
+        ];
+        for index in face {
+            let i = *index as usize * 3;
+            normals[i] += normal[0];
+            normals[i + 1] += normal[1];
+            normals[i + 2] += normal[2];
+        }
+    }
+
+    for normal in normals.chunks_exact_mut(3) {
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        if length > 0.0 {
+            normal.iter_mut().for_each(|n| *n /= length);
+        }
+    }
+
+    normals
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::renderer::{Material, MaterialId, Renderer, TextureHandle};
+
+use super::{
+    watcher::{canonical, AssetWatcher},
+    MaterialFile, TextureUsage,
+};
+
+type Reloader = Box<dyn FnMut(&Path) -> anyhow::Result<()>>;
+
+/// Loads assets into a renderer and reloads them when their files change on disk.
+///
+/// Textures, materials and, with the `obj` feature, meshes loaded through the server are swapped
+/// on the GPU in place, so their handles stay valid. Files other systems own, like prefabs or input
+/// bindings, can be watched with `on_change`.
+pub struct AssetServer {
+    root: PathBuf,
+    watcher: AssetWatcher,
+    textures: HashMap<PathBuf, (TextureHandle, TextureUsage)>,
+    /// Each material file's material and what was last loaded from or saved to it.
+    materials: HashMap<PathBuf, (MaterialId, MaterialFile)>,
+    /// The mesh ids of each loaded model's meshes, in file order.
+    #[cfg(feature = "obj")]
+    models: HashMap<PathBuf, Vec<usize>>,
+    reloaders: HashMap<PathBuf, Vec<Reloader>>,
+}
+
+impl AssetServer {
+    /// Watches every file under `root`.
+    pub fn new(root: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(AssetServer {
+            root: canonical(root.as_ref()),
+            watcher: AssetWatcher::new(root.as_ref())?,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            #[cfg(feature = "obj")]
+            models: HashMap::new(),
+            reloaders: HashMap::new(),
+        })
+    }
+
+    /// Loads the texture at `path`, reloading it into the same handle whenever the file changes.
+    /// The same file loaded twice shares a texture.
+    pub fn load_texture(
+        &mut self,
+        renderer: &mut Renderer,
+        path: &Path,
+        usage: TextureUsage,
+    ) -> anyhow::Result<TextureHandle> {
+        let key = canonical(path);
+        if let Some((handle, _)) = self.textures.get(&key) {
+            return Ok(*handle);
+        }
+        let handle = renderer.load_texture(path, usage)?;
+        self.textures.insert(key, (handle, usage));
+        Ok(handle)
+    }
+
+    /// Creates a material from the RON material file at `path`, loading its textures through
+    /// the server too. The material is rebuilt whenever the file changes, and the same file
+    /// loaded twice shares a material.
+    pub fn load_material(
+        &mut self,
+        renderer: &mut Renderer,
+        path: &Path,
+    ) -> anyhow::Result<MaterialId> {
+        let key = canonical(path);
+        if let Some((id, _)) = self.materials.get(&key) {
+            return Ok(*id);
+        }
+        let file = MaterialFile::load(path)?;
+        let material = self.material_from_file(renderer, path, &file)?;
+        let id = renderer.create_material(&material)?;
+        self.materials.insert(key, (id, file));
+        Ok(id)
+    }
+
+    /// The file `material` was loaded from, if it was loaded through the server.
+    pub fn material_path(&self, material: MaterialId) -> Option<&Path> {
+        self.materials
+            .iter()
+            .find(|(_, (id, _))| *id == material)
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// Writes the factors `material` has now, e.g. after they were tweaked in the editor, back
+    /// to the file it was loaded from.
+    pub fn save_material(
+        &mut self,
+        renderer: &Renderer,
+        material: MaterialId,
+    ) -> anyhow::Result<()> {
+        let current = renderer
+            .material(material)
+            .context("material doesn't exist")?;
+        let (path, (_, file)) = self
+            .materials
+            .iter_mut()
+            .find(|(_, (id, _))| *id == material)
+            .context("material wasn't loaded from a file")?;
+        file.set_factors(current);
+        file.save(path)?;
+        log::info!("saved material {:?}", path);
+        Ok(())
+    }
+
+    fn material_from_file(
+        &mut self,
+        renderer: &mut Renderer,
+        path: &Path,
+        file: &MaterialFile,
+    ) -> anyhow::Result<Material> {
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let mut texture = |texture: &Option<PathBuf>, srgb: bool| {
+            texture
+                .as_ref()
+                .map(|texture| {
+                    let texture = directory.join(texture);
+                    self.load_texture(renderer, &texture, TextureUsage::Color { srgb })
+                        .with_context(|| format!("loading {:?}", texture))
+                })
+                .transpose()
+        };
+        Ok(Material {
+            base_color_texture: texture(&file.base_color_texture, true)?,
+            metallic_roughness_texture: texture(&file.metallic_roughness_texture, false)?,
+            emissive_texture: texture(&file.emissive_texture, true)?,
+            lightmap: texture(&file.lightmap, false)?,
+            ..file.factors()
+        })
+    }
+
+    /// Uploads each mesh of the OBJ model at `path`, returning their mesh ids. The meshes are
+    /// reloaded whenever the file changes, as long as it keeps the same number of meshes.
+    #[cfg(feature = "obj")]
+    pub fn load_obj_meshes(
+        &mut self,
+        renderer: &mut Renderer,
+        path: &Path,
+    ) -> anyhow::Result<Vec<usize>> {
+        let key = canonical(path);
+        if let Some(mesh_ids) = self.models.get(&key) {
+            return Ok(mesh_ids.clone());
+        }
+        let model = super::load_obj(path)?;
+        let mesh_ids = model
+            .meshes
+            .into_iter()
+            .map(|mesh| renderer.create_mesh(mesh.vertices, mesh.indices))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        self.models.insert(key, mesh_ids.clone());
+        Ok(mesh_ids)
+    }
+
+    /// Calls `reload` with the changed file whenever `path` changes. Files outside the root
+    /// are watched too.
+    pub fn on_change<F>(&mut self, path: &Path, reload: F)
+    where
+        F: FnMut(&Path) -> anyhow::Result<()> + 'static,
+    {
+        let key = canonical(path);
+        if !key.starts_with(&self.root) {
+            if let Err(e) = self.watcher.watch_file(&key) {
+                log::warn!("{:?} won't be reloaded: {:#}", path, e);
+            }
+        }
+        self.reloaders
+            .entry(key)
+            .or_default()
+            .push(Box::new(reload));
+    }
+
+    /// Reloads the assets whose files changed since the last call. Must be called between
+    /// frames. Failed reloads are logged and keep the previous version.
+    pub fn reload_changed(&mut self, renderer: &mut Renderer) {
+        profile_scope!("reload assets");
+        for path in self.watcher.changed() {
+            if let Err(e) = self.reload(renderer, &path) {
+                log::warn!("reloading {:?}: {:#}", path, e);
+            }
+        }
+    }
+
+    fn reload(&mut self, renderer: &mut Renderer, path: &Path) -> anyhow::Result<()> {
+        if let Some((handle, usage)) = self.textures.get(path) {
+            log::info!("reloading texture {:?}", path);
+            renderer.reload_texture(*handle, path, *usage)?;
+        }
+
+        if let Some((id, loaded)) = self.materials.get(path) {
+            let id = *id;
+            let file = MaterialFile::load(path)?;
+            // Saving from the editor writes what the material already is, so there's nothing to do
+            if file != *loaded {
+                log::info!("reloading material {:?}", path);
+                let material = self.material_from_file(renderer, path, &file)?;
+                renderer.update_material(id, &material)?;
+                self.materials.insert(path.to_path_buf(), (id, file));
+            }
+        }
+
+        #[cfg(feature = "obj")]
+        if let Some(mesh_ids) = self.models.get(path) {
+            log::info!("reloading model {:?}", path);
+            let model = super::load_obj(path)?;
+            if model.meshes.len() != mesh_ids.len() {
+                anyhow::bail!(
+                    "model went from {} to {} meshes",
+                    mesh_ids.len(),
+                    model.meshes.len()
+                );
+            }
+            for (mesh_id, mesh) in mesh_ids.iter().zip(model.meshes) {
+                renderer.reload_mesh(*mesh_id, mesh.vertices, mesh.indices)?;
+            }
+        }
+
+        if let Some(reloaders) = self.reloaders.get_mut(path) {
+            log::info!("reloading {:?}", path);
+            for reload in reloaders {
+                reload(path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use crate::{
+    game::Transform,
+    renderer::{Renderer, SpriteMeshId, TextureHandle, VertexPositionNormalUv},
+};
+
+use super::TextureUsage;
+
+/// Tiles along each side of a chunk. Each chunk of a layer is one mesh, drawn with one draw call
+/// per tileset it uses.
+pub const TILEMAP_CHUNK_SIZE: u32 = 16;
+
+/// How far in front of the previous layer each layer is drawn.
+const LAYER_SPACING: f32 = 0.01;
+
+/// The part of a Tiled tileset needed to find a tile's UVs. Only tilesets made from a single
+/// image are supported.
+#[derive(Debug, Clone)]
+pub struct TilesetImage {
+    pub path: PathBuf,
+    /// In pixels.
+    pub size: [u32; 2],
+    pub tile_size: [u32; 2],
+    pub columns: u32,
+    pub margin: u32,
+    pub spacing: u32,
+}
+
+impl TilesetImage {
+    /// The UVs of the top left, top right, bottom right and bottom left corners of `tile`.
+    fn uvs(&self, tile: &TileRef) -> [[f32; 2]; 4] {
+        let columns = self.columns.max(1);
+        let x = self.margin + (tile.id % columns) * (self.tile_size[0] + self.spacing);
+        let y = self.margin + (tile.id / columns) * (self.tile_size[1] + self.spacing);
+        let [width, height] = self.size.map(|size| size.max(1) as f32);
+        let (mut u0, mut v0) = (x as f32 / width, y as f32 / height);
+        let (mut u1, mut v1) = (
+            (x + self.tile_size[0]) as f32 / width,
+            (y + self.tile_size[1]) as f32 / height,
+        );
+        if tile.flip_h {
+            std::mem::swap(&mut u0, &mut u1);
+        }
+        if tile.flip_v {
+            std::mem::swap(&mut v0, &mut v1);
+        }
+        let mut uvs = [[u0, v0], [u1, v0], [u1, v1], [u0, v1]];
+        if tile.flip_d {
+            // Mirrored along the top left to bottom right diagonal
+            uvs.swap(1, 3);
+        }
+        uvs
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TileRef {
+    /// Within its tileset.
+    id: u32,
+    flip_h: bool,
+    flip_v: bool,
+    flip_d: bool,
+}
+
+#[derive(Debug, Clone)]
+struct TileQuad {
+    /// In tiles from the map's top left corner.
+    cell: [u32; 2],
+    tile: TileRef,
+    /// The tile ids and how long they're shown for, empty for static tiles.
+    frames: Vec<(u32, Duration)>,
+}
+
+impl TileQuad {
+    /// The tile shown `elapsed` into the map's animations.
+    fn tile_at(&self, elapsed: Duration) -> TileRef {
+        let cycle: Duration = self.frames.iter().map(|(_, duration)| *duration).sum();
+        if cycle.is_zero() {
+            return self.tile;
+        }
+        let mut time = Duration::from_nanos((elapsed.as_nanos() % cycle.as_nanos()) as u64);
+        for &(id, duration) in &self.frames {
+            if time < duration {
+                return TileRef { id, ..self.tile };
+            }
+            time -= duration;
+        }
+        self.tile
+    }
+}
+
+/// The tiles of one layer and tileset within a `TILEMAP_CHUNK_SIZE` square.
+#[derive(Debug, Clone)]
+pub struct TilemapChunk {
+    pub layer: usize,
+    /// Index into `TilemapData::tilesets`.
+    pub tileset: usize,
+    quads: Vec<TileQuad>,
+}
+
+impl TilemapChunk {
+    pub fn animated(&self) -> bool {
+        self.quads.iter().any(|quad| !quad.frames.is_empty())
+    }
+
+    /// One unit square per tile, with x to the right and y up from the map's top left corner.
+    /// Later layers are in front, toward +z.
+    pub fn mesh(
+        &self,
+        tileset: &TilesetImage,
+        elapsed: Duration,
+    ) -> (Vec<VertexPositionNormalUv>, Vec<u32>) {
+        let z = self.layer as f32 * LAYER_SPACING;
+        let mut vertices = Vec::with_capacity(self.quads.len() * 4);
+        let mut indices = Vec::with_capacity(self.quads.len() * 6);
+        for quad in &self.quads {
+            let [x, y] = quad.cell.map(|cell| cell as f32);
+            let corners = [[x, -y], [x + 1.0, -y], [x + 1.0, -y - 1.0], [x, -y - 1.0]];
+            let uvs = tileset.uvs(&quad.tile_at(elapsed));
+            let base = vertices.len() as u32;
+            vertices.extend(
+                corners
+                    .iter()
+                    .zip(uvs)
+                    .map(|(corner, uv)| VertexPositionNormalUv {
+                        position: [corner[0], corner[1], z],
+                        normal: [0.0, 0.0, 1.0],
+                        uv,
+                    }),
+            );
+            indices.extend([base, base + 2, base + 1, base, base + 3, base + 2]);
+        }
+        (vertices, indices)
+    }
+
+    /// The tiles the animated quads show `elapsed` in, to tell when the mesh needs rebuilding.
+    fn frame_key(&self, elapsed: Duration) -> Vec<u32> {
+        self.quads
+            .iter()
+            .filter(|quad| !quad.frames.is_empty())
+            .map(|quad| quad.tile_at(elapsed).id)
+            .collect()
+    }
+}
+
+/// A Tiled map's visible tile layers split into chunks. Tilesets that aren't made from a single
+/// image are `None`, and their tiles are skipped.
+#[derive(Debug, Clone)]
+pub struct TilemapData {
+    /// In tiles.
+    pub size: [u32; 2],
+    pub tilesets: Vec<Option<TilesetImage>>,
+    pub chunks: Vec<TilemapChunk>,
+}
+
+/// Loads a Tiled `.tmx` map and the `.tsx` tilesets it references. Only finite maps are
+/// supported, group, object and image layers are skipped.
+pub fn load_tilemap(path: &Path) -> anyhow::Result<TilemapData> {
+    profile_scope!("load tilemap");
+    let map = tiled::Loader::new()
+        .load_tmx_map(path)
+        .with_context(|| format!("loading {:?}", path))?;
+
+    let tilesets = map
+        .tilesets()
+        .iter()
+        .map(|tileset| match &tileset.image {
+            Some(image) => Some(TilesetImage {
+                path: image.source.clone(),
+                size: [image.width as u32, image.height as u32],
+                tile_size: [tileset.tile_width, tileset.tile_height],
+                columns: tileset.columns,
+                margin: tileset.margin,
+                spacing: tileset.spacing,
+            }),
+            None => {
+                log::warn!("skipping image collection tileset {}", tileset.name);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut chunks: BTreeMap<(usize, usize, [u32; 2]), Vec<TileQuad>> = BTreeMap::new();
+    for (layer_index, layer) in map.layers().enumerate() {
+        if !layer.visible {
+            continue;
+        }
+        let tiled::LayerType::Tiles(tiles) = layer.layer_type() else {
+            continue;
+        };
+        let tiled::TileLayer::Finite(tiles) = tiles else {
+            log::warn!("skipping infinite layer {}", layer.name);
+            continue;
+        };
+
+        for y in 0..tiles.height() {
+            for x in 0..tiles.width() {
+                let Some(tile) = tiles.get_tile(x as i32, y as i32) else {
+                    continue;
+                };
+                if tilesets
+                    .get(tile.tileset_index())
+                    .map_or(true, Option::is_none)
+                {
+                    continue;
+                }
+                let frames = tile
+                    .get_tile()
+                    .and_then(|data| data.animation.clone())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|frame| (frame.tile_id, Duration::from_millis(frame.duration as u64)))
+                    .collect();
+                let chunk = [x / TILEMAP_CHUNK_SIZE, y / TILEMAP_CHUNK_SIZE];
+                chunks
+                    .entry((layer_index, tile.tileset_index(), chunk))
+                    .or_default()
+                    .push(TileQuad {
+                        cell: [x, y],
+                        tile: TileRef {
+                            id: tile.id(),
+                            flip_h: tile.flip_h,
+                            flip_v: tile.flip_v,
+                            flip_d: tile.flip_d,
+                        },
+                        frames,
+                    });
+            }
+        }
+    }
+
+    Ok(TilemapData {
+        size: [map.width, map.height],
+        tilesets,
+        chunks: chunks
+            .into_iter()
+            .map(|((layer, tileset, _), quads)| TilemapChunk {
+                layer,
+                tileset,
+                quads,
+            })
+            .collect(),
+    })
+}
+
+struct ChunkMesh {
+    mesh: SpriteMeshId,
+    texture: TextureHandle,
+    /// What the chunk's animated tiles showed when the mesh was last built.
+    frame_key: Vec<u32>,
+}
+
+/// A tilemap uploaded to a renderer, drawn as one sprite mesh per chunk. The tiles are drawn
+/// unlit over the composited image with their tileset's alpha, so they keep its colors. There's
+/// no 2D layer yet, so maps are placed in the 3D scene, one unit per tile, wherever they're
+/// enqueued, and hidden behind what's in front of them.
+pub struct Tilemap {
+    data: TilemapData,
+    chunks: Vec<ChunkMesh>,
+    elapsed: Duration,
+}
+
+impl Tilemap {
+    /// Loads the map at `path` with `load_tilemap` and uploads its tilesets and chunks.
+    pub fn load(renderer: &mut Renderer, path: &Path) -> anyhow::Result<Self> {
+        Self::new(renderer, load_tilemap(path)?)
+    }
+
+    pub fn new(renderer: &mut Renderer, data: TilemapData) -> anyhow::Result<Self> {
+        let textures = data
+            .tilesets
+            .iter()
+            .map(|tileset| {
+                tileset
+                    .as_ref()
+                    .map(|tileset| {
+                        renderer
+                            .load_texture(&tileset.path, TextureUsage::Color { srgb: true })
+                            .with_context(|| format!("loading tileset {:?}", tileset.path))
+                    })
+                    .transpose()
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let chunks = data
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let tileset = data.tilesets[chunk.tileset]
+                    .as_ref()
+                    .context("chunk of a skipped tileset")?;
+                let texture = textures[chunk.tileset].context("getting tileset texture")?;
+                let (vertices, indices) = chunk.mesh(tileset, Duration::ZERO);
+                Ok(ChunkMesh {
+                    mesh: renderer
+                        .create_sprite_mesh(vertices, indices)
+                        .context("creating tilemap chunk")?,
+                    texture,
+                    frame_key: chunk.frame_key(Duration::ZERO),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Tilemap {
+            data,
+            chunks,
+            elapsed: Duration::ZERO,
+        })
+    }
+
+    pub fn data(&self) -> &TilemapData {
+        &self.data
+    }
+
+    /// Advances the animated tiles by `delta`, rebuilding the chunks whose tiles changed.
+    pub fn update(&mut self, renderer: &mut Renderer, delta: Duration) -> anyhow::Result<()> {
+        self.elapsed += delta;
+        for (chunk, mesh) in self.data.chunks.iter().zip(&mut self.chunks) {
+            if !chunk.animated() {
+                continue;
+            }
+            let frame_key = chunk.frame_key(self.elapsed);
+            if frame_key == mesh.frame_key {
+                continue;
+            }
+            let Some(tileset) = &self.data.tilesets[chunk.tileset] else {
+                continue;
+            };
+            let (vertices, indices) = chunk.mesh(tileset, self.elapsed);
+            renderer
+                .reload_sprite_mesh(mesh.mesh, vertices, indices)
+                .context("rebuilding animated tilemap chunk")?;
+            mesh.frame_key = frame_key;
+        }
+        Ok(())
+    }
+
+    /// Queues every chunk for the next frame, placed by `transform`. Later layers are drawn over
+    /// earlier ones.
+    pub fn enqueue(&self, renderer: &mut Renderer, transform: Transform) -> anyhow::Result<()> {
+        for chunk in &self.chunks {
+            renderer.enqueue_sprite_mesh(chunk.mesh, chunk.texture, transform)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long a file has to stay untouched before its change is reported. Editors and exporters
+/// often write a file in several steps.
+const SETTLE_TIME: Duration = Duration::from_millis(150);
+
+/// Watches a directory tree for files being created or written.
+pub struct AssetWatcher {
+    // Stops watching when dropped
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    /// Changed files and when they last changed.
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl AssetWatcher {
+    pub fn new(root: &Path) -> anyhow::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // Only fails once the watcher has been dropped
+            let _ = sender.send(event);
+        })
+        .context("creating file watcher")?;
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| format!("watching {:?}", root))?;
+
+        Ok(AssetWatcher {
+            watcher,
+            events,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Also watches the directory `path` is in, for a file that lives outside the root. The
+    /// directory is watched rather than the file, since editors often replace files on save.
+    pub fn watch_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let directory = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        self.watcher
+            .watch(directory, RecursiveMode::NonRecursive)
+            .with_context(|| format!("watching {:?}", directory))
+    }
+
+    /// Files that changed and have settled since the last call, as canonical paths.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    for path in event.paths {
+                        self.pending.insert(canonical(&path), now);
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => log::warn!("file watcher error: {}", e),
+            }
+        }
+
+        let settled: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &settled {
+            self.pending.remove(path);
+        }
+        settled
+    }
+}
+
+/// `path` with symlinks and relative components resolved, or as given when that fails, e.g.
+/// because the file is gone.
+pub(crate) fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,183 @@
+use std::{fs, io::ErrorKind, path::Path, path::PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{DeviceSelector, FocusBehavior, RendererConfig};
+
+/// Prefix of the environment variables overriding config values, e.g. `TRITON_VSYNC=false`.
+const ENV_PREFIX: &str = "TRITON_";
+const FIELDS: [&str; 20] = [
+    "window_width",
+    "window_height",
+    "vsync",
+    "render_scale",
+    "msaa_samples",
+    "key_bindings",
+    "asset_root",
+    "ui_scale",
+    "pause_on_focus_loss",
+    "background_fps",
+    "net_listen",
+    "net_connect",
+    "navmesh",
+    "stress_scene",
+    "gpu",
+    "validation",
+    "hidden_window",
+    "time_of_day",
+    "day_length",
+    "retained_scene",
+];
+
+/// Engine settings read from a TOML file, each of which can be overridden with an environment
+/// variable named after it. Inserted into the ECS worlds as a resource.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// Initial logical size of the window.
+    pub window_width: u32,
+    pub window_height: u32,
+    pub vsync: bool,
+    /// Fraction of the window resolution the scene renders at, see `Renderer::set_render_scale`.
+    pub render_scale: f32,
+    /// Samples per pixel. The deferred renderer only draws with one, smooth edges with TAA
+    /// instead.
+    pub msaa_samples: u32,
+    /// Key bindings replacing the built-in ones.
+    pub key_bindings: Option<PathBuf>,
+    /// Where assets are loaded from and watched for changes.
+    pub asset_root: PathBuf,
+    /// Screen pixels per logical pixel of the HUD and other UI, following the window's scale
+    /// factor when unset.
+    pub ui_scale: Option<f32>,
+    /// Stops the simulation while the window doesn't have focus.
+    pub pause_on_focus_loss: bool,
+    /// Frames per second rendered while the window doesn't have focus, unlimited when zero.
+    pub background_fps: f32,
+    /// Address to serve the simulation on, with the `net` feature.
+    pub net_listen: Option<String>,
+    /// Server to mirror the simulation of, with the `net` feature. Ignored when `net_listen` is
+    /// set.
+    pub net_connect: Option<String>,
+    /// The navmesh paths are searched on, relative to `asset_root`. Either one saved with
+    /// `NavMesh::save`, or an `.obj` of level geometry to bake one from with the `obj` feature.
+    pub navmesh: Option<PathBuf>,
+    /// Spawns a `StressScene` this many meshes wide alongside the demo scene, e.g. to compare
+    /// frame times between changes.
+    pub stress_scene: Option<u32>,
+    /// Part of the name of the GPU to run on, see `DeviceSelector::Name`.
+    pub gpu: Option<String>,
+    /// Turns the Vulkan validation layer on or off, on in debug builds when unset.
+    pub validation: Option<bool>,
+    /// Hides the window and keeps running without focus, for automated runs. Frames are still
+    /// rendered and presented to the hidden window, so a GPU and a display are still needed.
+    pub hidden_window: bool,
+    /// Starts a day-night cycle at this hour, from 0 to 24, that moves the sun and colors the sky.
+    pub time_of_day: Option<f32>,
+    /// Seconds a whole day of the day-night cycle lasts.
+    pub day_length: f32,
+    /// Keeps the scene in the renderer between frames, sending it only the objects that
+    /// changed instead of every object each frame.
+    pub retained_scene: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            window_width: 1280,
+            window_height: 720,
+            vsync: true,
+            render_scale: 1.0,
+            msaa_samples: 1,
+            key_bindings: None,
+            asset_root: PathBuf::from("assets"),
+            ui_scale: None,
+            pause_on_focus_loss: true,
+            background_fps: 15.0,
+            net_listen: None,
+            net_connect: None,
+            navmesh: None,
+            stress_scene: None,
+            gpu: None,
+            validation: None,
+            hidden_window: false,
+            time_of_day: None,
+            day_length: 1200.0,
+            retained_scene: false,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Reads the config at `path`, falling back to the defaults when there's no such file, then
+    /// applies the environment overrides.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                log::info!("no config at {:?}, using defaults", path);
+                String::new()
+            }
+            Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+        };
+        let table: toml::Table =
+            toml::from_str(&text).with_context(|| format!("parsing {:?}", path))?;
+        toml::Value::Table(with_env_overrides(table))
+            .try_into()
+            .with_context(|| format!("reading {:?}", path))
+    }
+
+    /// What the game loop does while the window doesn't have focus.
+    pub fn focus_behavior(&self) -> FocusBehavior {
+        // A hidden window never has focus
+        if self.hidden_window {
+            return FocusBehavior {
+                pause: false,
+                background_fps: None,
+            };
+        }
+        FocusBehavior {
+            pause: self.pause_on_focus_loss,
+            background_fps: (self.background_fps > 0.0).then_some(self.background_fps),
+        }
+    }
+
+    /// The renderer's options, with the ones this config doesn't cover left at their defaults.
+    pub fn renderer_config(&self) -> RendererConfig {
+        if self.msaa_samples > 1 {
+            log::warn!(
+                "ignoring msaa_samples = {}, the deferred renderer doesn't multisample",
+                self.msaa_samples
+            );
+        }
+        RendererConfig {
+            window_size: [self.window_width as f32, self.window_height as f32],
+            vsync: self.vsync,
+            render_scale: self.render_scale,
+            preferred_device: self.gpu.clone().map(DeviceSelector::Name),
+            validation: self.validation.unwrap_or(cfg!(debug_assertions)),
+            visible: !self.hidden_window,
+            ..Default::default()
+        }
+    }
+}
+
+/// Replaces the values of `table` with the matching `TRITON_` environment variables. Variables
+/// are parsed as TOML values, and as strings when they aren't valid TOML.
+fn with_env_overrides(mut table: toml::Table) -> toml::Table {
+    for key in FIELDS {
+        let name = format!("{}{}", ENV_PREFIX, key.to_ascii_uppercase());
+        let Ok(raw) = std::env::var(&name) else {
+            continue;
+        };
+        let value = toml::from_str::<toml::Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or(toml::Value::String(raw));
+        log::info!("{} overrides {} with {}", name, key, value);
+        table.insert(key.to_string(), value);
+    }
+    table
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use specs::{Component, DenseVecStorage, Entity, Join, RunNow, World, WorldExt};
+
+/// What a behavior tree node did this tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BehaviorStatus {
+    Success,
+    Failure,
+    /// Still working, e.g. walking somewhere. Ticked again next time.
+    Running,
+}
+
+type Leaf = Arc<dyn Fn(&World, Entity) -> BehaviorStatus + Send + Sync>;
+
+/// A node of a behavior tree. Trees are ticked from the root every fixed update without
+/// remembering which node was running, so higher priority branches of a selector can take over
+/// as soon as they succeed.
+#[derive(Clone)]
+pub enum BehaviorNode {
+    /// Ticks the children in order until one doesn't succeed.
+    Sequence(Vec<BehaviorNode>),
+    /// Ticks the children in order until one doesn't fail.
+    Selector(Vec<BehaviorNode>),
+    /// Swaps success and failure.
+    Invert(Box<BehaviorNode>),
+    /// Succeeds, whatever the child did.
+    Succeed(Box<BehaviorNode>),
+    /// Runs game code against the world, e.g. checking a condition or changing the entity's
+    /// `Steering`.
+    Leaf(Leaf),
+}
+
+impl BehaviorNode {
+    pub fn sequence(children: impl IntoIterator<Item = BehaviorNode>) -> Self {
+        BehaviorNode::Sequence(children.into_iter().collect())
+    }
+
+    pub fn selector(children: impl IntoIterator<Item = BehaviorNode>) -> Self {
+        BehaviorNode::Selector(children.into_iter().collect())
+    }
+
+    pub fn invert(child: BehaviorNode) -> Self {
+        BehaviorNode::Invert(Box::new(child))
+    }
+
+    pub fn succeed(child: BehaviorNode) -> Self {
+        BehaviorNode::Succeed(Box::new(child))
+    }
+
+    pub fn action<F>(action: F) -> Self
+    where
+        F: Fn(&World, Entity) -> BehaviorStatus + Send + Sync + 'static,
+    {
+        BehaviorNode::Leaf(Arc::new(action))
+    }
+
+    /// A leaf that succeeds when `condition` holds and fails otherwise.
+    pub fn condition<F>(condition: F) -> Self
+    where
+        F: Fn(&World, Entity) -> bool + Send + Sync + 'static,
+    {
+        BehaviorNode::action(move |world, entity| {
+            if condition(world, entity) {
+                BehaviorStatus::Success
+            } else {
+                BehaviorStatus::Failure
+            }
+        })
+    }
+
+    pub fn tick(&self, world: &World, entity: Entity) -> BehaviorStatus {
+        match self {
+            BehaviorNode::Sequence(children) => children
+                .iter()
+                .map(|child| child.tick(world, entity))
+                .find(|status| *status != BehaviorStatus::Success)
+                .unwrap_or(BehaviorStatus::Success),
+            BehaviorNode::Selector(children) => children
+                .iter()
+                .map(|child| child.tick(world, entity))
+                .find(|status| *status != BehaviorStatus::Failure)
+                .unwrap_or(BehaviorStatus::Failure),
+            BehaviorNode::Invert(child) => match child.tick(world, entity) {
+                BehaviorStatus::Success => BehaviorStatus::Failure,
+                BehaviorStatus::Failure => BehaviorStatus::Success,
+                BehaviorStatus::Running => BehaviorStatus::Running,
+            },
+            BehaviorNode::Succeed(child) => {
+                child.tick(world, entity);
+                BehaviorStatus::Success
+            }
+            BehaviorNode::Leaf(leaf) => leaf(world, entity),
+        }
+    }
+}
+
+/// Gives an entity a behavior tree, ticked every fixed update by `BehaviorTreeSystem`. Trees are
+/// shared, so many agents can run the same one.
+#[derive(Component, Clone)]
+#[storage(DenseVecStorage)]
+pub struct BehaviorTree {
+    pub root: Arc<BehaviorNode>,
+    /// What the root returned on the last tick, `None` before the first.
+    pub status: Option<BehaviorStatus>,
+}
+
+impl BehaviorTree {
+    pub fn new(root: BehaviorNode) -> Self {
+        BehaviorTree {
+            root: Arc::new(root),
+            status: None,
+        }
+    }
+}
+
+/// Ticks every `BehaviorTree`. Leaves get the whole world, so the trees run one after another on
+/// the simulation thread, after the systems that run in parallel.
+pub struct BehaviorTreeSystem;
+
+impl<'a> RunNow<'a> for BehaviorTreeSystem {
+    fn run_now(&mut self, world: &'a World) {
+        profile_scope!("behavior trees");
+        let trees: Vec<(Entity, Arc<BehaviorNode>)> =
+            (&world.entities(), &world.read_storage::<BehaviorTree>())
+                .join()
+                .map(|(entity, tree)| (entity, tree.root.clone()))
+                .collect();
+
+        for (entity, root) in trees {
+            let status = root.tick(world, entity);
+            if let Some(tree) = world.write_storage::<BehaviorTree>().get_mut(entity) {
+                tree.status = Some(status);
+            }
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        world.register::<BehaviorTree>();
+    }
+}
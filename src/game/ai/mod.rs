@@ -0,0 +1,5 @@
+pub use behavior::{BehaviorNode, BehaviorStatus, BehaviorTree, BehaviorTreeSystem};
+pub use steering::{Steering, SteeringBehavior, SteeringSystem};
+
+mod behavior;
+mod steering;
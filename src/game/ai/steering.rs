@@ -0,0 +1,240 @@
+use cgmath::{InnerSpace, Quaternion, Rotation, Vector3, Zero};
+use specs::{Component, DenseVecStorage, Entities, Join, Read, System, WriteStorage};
+
+use crate::{
+    game::{
+        camera_math::Ray,
+        components::{
+            transform::{update_transform, Transform},
+            SpatialIndex,
+        },
+        simulation::FIXED_TIME_STEP,
+    },
+    random::Random,
+};
+
+/// One way of steering, pulling an agent toward a velocity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SteeringBehavior {
+    /// Heads for `target` at full speed, overshooting it.
+    Seek(Vector3<f32>),
+    /// Runs from `from` while it's within `panic_distance`.
+    Flee {
+        from: Vector3<f32>,
+        panic_distance: f32,
+    },
+    /// Heads for `target`, slowing down within `slowing_distance` to stop on it.
+    Arrive {
+        target: Vector3<f32>,
+        slowing_distance: f32,
+    },
+    /// Drifts about by chasing a point that moves randomly around a circle of `radius` kept
+    /// `distance` ahead. `jitter` is how far the point can move per second.
+    Wander {
+        radius: f32,
+        distance: f32,
+        jitter: f32,
+    },
+    /// Turns away from the entities in the spatial index within `look_ahead` of the path ahead.
+    AvoidObstacles { look_ahead: f32 },
+}
+
+/// Moves an entity by blending its weighted behaviors into a steering force each fixed update.
+/// Movement is kept to the xz plane when `planar`, e.g. for agents walking on the ground.
+#[derive(Component, Debug, Clone)]
+#[storage(DenseVecStorage)]
+pub struct Steering {
+    pub behaviors: Vec<(SteeringBehavior, f32)>,
+    pub max_speed: f32,
+    /// How quickly the velocity can change, in units per second squared.
+    pub max_force: f32,
+    pub planar: bool,
+    /// Turns the transform's +z toward where it's going.
+    pub face_movement: bool,
+    pub velocity: Vector3<f32>,
+    wander_target: Vector3<f32>,
+    /// None until the agent's first update seeds it from the entity.
+    random: Option<Random>,
+}
+
+impl Steering {
+    pub fn new(max_speed: f32, max_force: f32) -> Self {
+        Steering {
+            behaviors: vec![],
+            max_speed,
+            max_force,
+            planar: true,
+            face_movement: true,
+            velocity: Vector3::zero(),
+            wander_target: Vector3::unit_z(),
+            random: None,
+        }
+    }
+
+    pub fn with_behavior(mut self, behavior: SteeringBehavior, weight: f32) -> Self {
+        self.behaviors.push((behavior, weight));
+        self
+    }
+
+    /// Replaces the behaviors, keeping the velocity.
+    pub fn set_behaviors(&mut self, behaviors: Vec<(SteeringBehavior, f32)>) {
+        self.behaviors = behaviors;
+    }
+
+    /// A pseudo-random number from -1 to 1.
+    fn random(&mut self) -> f32 {
+        self.random.get_or_insert(Random::new(1)).range(-1.0, 1.0)
+    }
+
+    fn desired_velocity(
+        &mut self,
+        behavior: SteeringBehavior,
+        position: Vector3<f32>,
+        obstacles: &dyn Fn(Vector3<f32>, f32) -> Option<Vector3<f32>>,
+    ) -> Vector3<f32> {
+        match behavior {
+            SteeringBehavior::Seek(target) => towards(target - position, self.max_speed),
+            SteeringBehavior::Flee {
+                from,
+                panic_distance,
+            } => {
+                let away = position - from;
+                if away.magnitude2() > panic_distance * panic_distance {
+                    return self.velocity;
+                }
+                towards(away, self.max_speed)
+            }
+            SteeringBehavior::Arrive {
+                target,
+                slowing_distance,
+            } => {
+                let offset = target - position;
+                let distance = offset.magnitude();
+                let speed = self.max_speed * (distance / slowing_distance.max(f32::EPSILON));
+                towards(offset, speed.min(self.max_speed))
+            }
+            SteeringBehavior::Wander {
+                radius,
+                distance,
+                jitter,
+            } => {
+                let jitter = jitter * FIXED_TIME_STEP;
+                let nudge = Vector3::new(self.random(), self.random(), self.random()) * jitter;
+                let target = self.wander_target + nudge;
+                self.wander_target = if target.magnitude2() > 0.0 {
+                    target.normalize() * radius
+                } else {
+                    Vector3::unit_z() * radius
+                };
+                let heading = towards(self.velocity, 1.0);
+                let heading = if heading.is_zero() {
+                    Vector3::unit_z()
+                } else {
+                    heading
+                };
+                towards(heading * distance + self.wander_target, self.max_speed)
+            }
+            SteeringBehavior::AvoidObstacles { look_ahead } => {
+                let heading = towards(self.velocity, 1.0);
+                if heading.is_zero() {
+                    return self.velocity;
+                }
+                let speed_ratio = self.velocity.magnitude() / self.max_speed.max(f32::EPSILON);
+                match obstacles(heading, look_ahead * speed_ratio.max(0.25)) {
+                    Some(away) => self.velocity + away * self.max_speed,
+                    None => self.velocity,
+                }
+            }
+        }
+    }
+}
+
+fn towards(direction: Vector3<f32>, length: f32) -> Vector3<f32> {
+    if direction.magnitude2() <= f32::EPSILON {
+        return Vector3::zero();
+    }
+    direction.normalize() * length
+}
+
+fn truncate(vector: Vector3<f32>, length: f32) -> Vector3<f32> {
+    if vector.magnitude2() > length * length {
+        vector.normalize() * length
+    } else {
+        vector
+    }
+}
+
+/// Integrates every `Steering` agent's velocity and moves its transform. Obstacles are found
+/// through the `SpatialIndex`, so only entities with `Bounds` are avoided.
+pub struct SteeringSystem;
+
+impl<'a> System<'a> for SteeringSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, SpatialIndex>,
+        WriteStorage<'a, Steering>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(&mut self, (entities, index, mut steerings, mut transforms): Self::SystemData) {
+        profile_scope!("steering");
+        for (entity, steering) in (&entities, &mut steerings).join() {
+            let Some(&current) = transforms.get(entity) else {
+                continue;
+            };
+            let mut transform = current;
+            if steering.random.is_none() {
+                // Agents spawned together shouldn't wander in step
+                steering.random = Some(Random::new(entity.id().wrapping_mul(0x9e37_79b9) | 1));
+            }
+            let position = transform.position;
+            let obstacles = |heading: Vector3<f32>, distance: f32| {
+                let ray = Ray {
+                    origin: position,
+                    direction: heading,
+                };
+                let (obstacle, hit) = index
+                    .raycast(&ray, distance)
+                    .into_iter()
+                    .find(|(other, _)| *other != entity)?;
+                let center = index.bounds(obstacle)?.center();
+                // Steer sideways from the obstacle, harder the closer it is
+                let offset = position - center;
+                let sideways = offset - heading * offset.dot(heading);
+                let sideways = if sideways.magnitude2() <= f32::EPSILON {
+                    heading.cross(Vector3::unit_y())
+                } else {
+                    sideways
+                };
+                Some(towards(sideways, 1.0 - hit / distance.max(f32::EPSILON)))
+            };
+
+            let mut force = Vector3::zero();
+            for index in 0..steering.behaviors.len() {
+                let (behavior, weight) = steering.behaviors[index];
+                let desired = steering.desired_velocity(behavior, position, &obstacles);
+                force += (desired - steering.velocity) * weight;
+            }
+            if steering.planar {
+                force.y = 0.0;
+            }
+
+            let force = truncate(force, steering.max_force);
+            steering.velocity = truncate(
+                steering.velocity + force * FIXED_TIME_STEP,
+                steering.max_speed,
+            );
+            if steering.planar {
+                steering.velocity.y = 0.0;
+            }
+            transform.position += steering.velocity * FIXED_TIME_STEP;
+
+            if steering.face_movement && steering.velocity.magnitude2() > 1e-6 {
+                transform.rotation =
+                    Quaternion::look_at(steering.velocity.normalize(), Vector3::unit_y()).invert();
+            }
+            // Agents at rest aren't flagged as moved
+            update_transform(&mut transforms, entity, transform);
+        }
+    }
+}
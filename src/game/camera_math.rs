@@ -0,0 +1,177 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, SquareMatrix, Vector3, Vector4};
+
+// The projection is cgmath's, which maps depth to -1..1 like OpenGL, but y is left pointing down
+// as in Vulkan's clip space, so NDC and screen space y both run top to bottom.
+
+/// A ray in world space.
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    /// Normalized.
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// The distances along the ray and along the line through `point` in the normalized
+    /// `direction` of their closest points, `None` when they're parallel.
+    pub fn closest_to_line(
+        &self,
+        point: Vector3<f32>,
+        direction: Vector3<f32>,
+    ) -> Option<(f32, f32)> {
+        let offset = self.origin - point;
+        let b = self.direction.dot(direction);
+        let d = self.direction.dot(offset);
+        let e = direction.dot(offset);
+        let denominator = 1.0 - b * b;
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        Some(((b * e - d) / denominator, (e - b * d) / denominator))
+    }
+
+    /// Where the ray crosses the plane through `point` facing `normal`, if it does.
+    pub fn intersect_plane(
+        &self,
+        point: Vector3<f32>,
+        normal: Vector3<f32>,
+    ) -> Option<Vector3<f32>> {
+        let denominator = self.direction.dot(normal);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let distance = (point - self.origin).dot(normal) / denominator;
+        (distance > 0.0).then(|| self.at(distance))
+    }
+}
+
+/// Where `point` lands on a `viewport` of the given size, in pixels from its top left. `None`
+/// for points behind the camera.
+pub fn world_to_screen(
+    view_projection: Matrix4<f32>,
+    point: Vector3<f32>,
+    viewport: [f32; 2],
+) -> Option<[f32; 2]> {
+    let clip = view_projection * point.extend(1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    Some([
+        (clip.x / clip.w + 1.0) * 0.5 * viewport[0],
+        (clip.y / clip.w + 1.0) * 0.5 * viewport[1],
+    ])
+}
+
+/// The ray through `screen`, in pixels from the top left of a `viewport` of the given size,
+/// starting on the near plane. `None` when `view_projection` can't be inverted.
+pub fn screen_to_world_ray(
+    view_projection: Matrix4<f32>,
+    screen: [f32; 2],
+    viewport: [f32; 2],
+) -> Option<Ray> {
+    let inverse = view_projection.invert()?;
+    let x = screen[0] / viewport[0] * 2.0 - 1.0;
+    let y = screen[1] / viewport[1] * 2.0 - 1.0;
+    let near = unproject(inverse, Vector3::new(x, y, -1.0));
+    let far = unproject(inverse, Vector3::new(x, y, 1.0));
+    Some(Ray {
+        origin: near,
+        direction: (far - near).normalize(),
+    })
+}
+
+/// World space corners of the volume `view_projection` sees, the near plane's first, each plane
+/// in the order top left, top right, bottom left, bottom right.
+pub fn frustum_corners(view_projection: Matrix4<f32>) -> [Vector3<f32>; 8] {
+    let inverse = view_projection.invert().unwrap_or_else(Matrix4::identity);
+    let mut corners = [Vector3::new(0.0, 0.0, 0.0); 8];
+    for (index, corner) in corners.iter_mut().enumerate() {
+        let x = if index & 1 == 0 { -1.0 } else { 1.0 };
+        let y = if index & 2 == 0 { -1.0 } else { 1.0 };
+        let z = if index & 4 == 0 { -1.0 } else { 1.0 };
+        *corner = unproject(inverse, Vector3::new(x, y, z));
+    }
+    corners
+}
+
+fn unproject(inverse: Matrix4<f32>, ndc: Vector3<f32>) -> Vector3<f32> {
+    let point = inverse * ndc.extend(1.0);
+    point.truncate() / point.w
+}
+
+/// Points with `normal.dot(point) + distance >= 0` are on the plane's inner side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    /// Normalized.
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_coefficients(coefficients: Vector4<f32>) -> Self {
+        let length = coefficients.truncate().magnitude();
+        Plane {
+            normal: coefficients.truncate() / length,
+            distance: coefficients.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: Vector3<f32>) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+/// The six planes bounding what a camera sees, facing inward.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from the rows of `view_projection`.
+    pub fn from_view_projection(view_projection: Matrix4<f32>) -> Self {
+        let row = |index| view_projection.row(index);
+        let w = row(3);
+        Frustum {
+            planes: [
+                Plane::from_coefficients(w + row(0)),
+                Plane::from_coefficients(w - row(0)),
+                Plane::from_coefficients(w + row(1)),
+                Plane::from_coefficients(w - row(1)),
+                Plane::from_coefficients(w + row(2)),
+                Plane::from_coefficients(w - row(2)),
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, point: Vector3<f32>) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Conservative: spheres outside near a corner of the frustum can pass.
+    pub fn intersects_sphere(&self, center: Vector3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(center) >= -radius)
+    }
+
+    /// Conservative like `intersects_sphere`, for a world space box from `min` to `max`.
+    pub fn intersects_aabb(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            // The box corner furthest along the plane's normal
+            let corner = Vector3::new(
+                if plane.normal.x >= 0.0 { max.x } else { min.x },
+                if plane.normal.y >= 0.0 { max.y } else { min.y },
+                if plane.normal.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+}
@@ -0,0 +1,29 @@
+/// The system clipboard, for copy and paste in the console. Does nothing where there isn't one,
+/// such as on a headless machine.
+pub struct Clipboard {
+    clipboard: Option<arboard::Clipboard>,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        let clipboard = arboard::Clipboard::new()
+            .map_err(|e| log::warn!("no clipboard: {}", e))
+            .ok();
+        Clipboard { clipboard }
+    }
+}
+
+impl Clipboard {
+    /// The clipboard's text, `None` when it's empty or holds something else.
+    pub fn text(&mut self) -> Option<String> {
+        self.clipboard.as_mut()?.get_text().ok()
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        if let Some(clipboard) = self.clipboard.as_mut() {
+            if let Err(e) = clipboard.set_text(text) {
+                log::warn!("copying to the clipboard: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use specs::{Component, Entity, World, WorldExt};
+
+type Encode = Box<dyn Fn(&World, Entity) -> anyhow::Result<Option<Vec<u8>>> + Send + Sync>;
+type Decode = Box<dyn Fn(&[u8]) -> anyhow::Result<Decoded> + Send + Sync>;
+
+/// A component decoded from its bytes, inserted into an entity when called.
+pub type Decoded = Box<dyn FnOnce(&World, Entity) -> anyhow::Result<()> + Send>;
+
+/// Components encoded with bincode under registered names, for save games and replication.
+#[derive(Default)]
+pub struct ComponentCodecs {
+    codecs: BTreeMap<String, (Encode, Decode)>,
+}
+
+impl ComponentCodecs {
+    pub fn register<C>(&mut self, name: &str)
+    where
+        C: Component + Serialize + DeserializeOwned + Send + Sync,
+    {
+        let encode: Encode = Box::new(|world, entity| {
+            world
+                .read_storage::<C>()
+                .get(entity)
+                .map(|component| bincode::serialize(component))
+                .transpose()
+                .context("encoding component")
+        });
+        let decode: Decode = Box::new(|bytes| {
+            let component: C = bincode::deserialize(bytes).context("decoding component")?;
+            Ok(Box::new(move |world: &World, entity| {
+                world
+                    .write_storage::<C>()
+                    .insert(entity, component)
+                    .context("inserting component")?;
+                Ok(())
+            }) as Decoded)
+        });
+        self.codecs.insert(name.to_string(), (encode, decode));
+    }
+
+    /// The registered components `entity` has, by name.
+    pub fn encode(
+        &self,
+        world: &World,
+        entity: Entity,
+    ) -> anyhow::Result<BTreeMap<String, Vec<u8>>> {
+        let mut components = BTreeMap::new();
+        for (name, (encode, _)) in &self.codecs {
+            if let Some(bytes) =
+                encode(world, entity).with_context(|| format!("encoding {}", name))?
+            {
+                components.insert(name.clone(), bytes);
+            }
+        }
+        Ok(components)
+    }
+
+    /// Inserts `components` into `entity`, skipping the names that aren't registered.
+    pub fn decode<'a>(
+        &self,
+        world: &World,
+        entity: Entity,
+        components: impl IntoIterator<Item = (&'a String, &'a Vec<u8>)>,
+    ) -> anyhow::Result<()> {
+        for insert in self.decode_detached(components)? {
+            insert(world, entity)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes `components` without a world to insert them into yet, skipping the names that
+    /// aren't registered. Fails before anything is inserted if any of them doesn't decode.
+    pub fn decode_detached<'a>(
+        &self,
+        components: impl IntoIterator<Item = (&'a String, &'a Vec<u8>)>,
+    ) -> anyhow::Result<Vec<Decoded>> {
+        let mut decoded = vec![];
+        for (name, bytes) in components {
+            match self.codecs.get(name) {
+                Some((_, decode)) => {
+                    decoded.push(decode(bytes).with_context(|| format!("decoding {}", name))?)
+                }
+                None => log::warn!("skipping unregistered component {}", name),
+            }
+        }
+        Ok(decoded)
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use specs::{shrev::EventChannel, Read, System, Write};
+
+use crate::renderer::HudPanel;
+
+use super::resources::FrameStatsResource;
+
+/// Per-frame limits checked by the `BudgetSystem`. A limit of `None` is not checked.
+#[derive(Debug, Clone)]
+pub struct PerformanceBudget {
+    pub cpu_time_ms: Option<f32>,
+    pub gpu_time_ms: Option<f32>,
+    pub draw_calls: Option<u32>,
+    pub triangles: Option<u64>,
+    pub vram_bytes: Option<u64>,
+    /// How many frames in a row a metric has to be over its limit before it's reported.
+    pub consecutive_frames: u32,
+}
+
+impl Default for PerformanceBudget {
+    fn default() -> Self {
+        PerformanceBudget {
+            cpu_time_ms: Some(1000.0 / 60.0),
+            gpu_time_ms: Some(1000.0 / 60.0),
+            draw_calls: None,
+            triangles: None,
+            vram_bytes: None,
+            consecutive_frames: 30,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub enum BudgetMetric {
+    CpuTime,
+    GpuTime,
+    DrawCalls,
+    Triangles,
+    Vram,
+}
+
+/// Emitted on the `EventChannel<BudgetExceeded>` resource once a metric has been over budget for
+/// `PerformanceBudget::consecutive_frames` frames. It isn't emitted again until the metric has
+/// dropped back under its limit.
+#[derive(Debug, Clone, Copy)]
+pub struct BudgetExceeded {
+    pub metric: BudgetMetric,
+    pub value: f64,
+    pub limit: f64,
+}
+
+/// Metrics that are currently over budget, for display.
+#[derive(Default)]
+pub struct BudgetWarnings(pub Vec<BudgetExceeded>);
+
+impl BudgetWarnings {
+    /// A HUD panel listing each metric that's over budget, or `None` while everything is within.
+    pub fn panel(&self) -> Option<HudPanel> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let lines = self
+            .0
+            .iter()
+            .map(|exceeded| match exceeded.metric {
+                BudgetMetric::CpuTime => {
+                    format!("CPU {:.2} > {:.2} MS", exceeded.value, exceeded.limit)
+                }
+                BudgetMetric::GpuTime => {
+                    format!("GPU {:.2} > {:.2} MS", exceeded.value, exceeded.limit)
+                }
+                BudgetMetric::DrawCalls => {
+                    format!("DRAWS {} > {}", exceeded.value, exceeded.limit)
+                }
+                BudgetMetric::Triangles => {
+                    format!("TRIANGLES {} > {}", exceeded.value, exceeded.limit)
+                }
+                BudgetMetric::Vram => format!(
+                    "VRAM {:.1} > {:.1} MB",
+                    exceeded.value / (1024.0 * 1024.0),
+                    exceeded.limit / (1024.0 * 1024.0)
+                ),
+            })
+            .collect();
+        Some(HudPanel {
+            title: "OVER BUDGET".to_string(),
+            lines,
+            highlighted: None,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct BudgetSystem {
+    frames_over: HashMap<BudgetMetric, u32>,
+}
+
+impl<'a> System<'a> for BudgetSystem {
+    type SystemData = (
+        Read<'a, PerformanceBudget>,
+        Read<'a, FrameStatsResource>,
+        Write<'a, EventChannel<BudgetExceeded>>,
+        Write<'a, BudgetWarnings>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        profile_scope!("performance budget");
+        let (budget, stats, mut events, mut warnings) = data;
+        let stats = stats.0;
+
+        let measurements = [
+            (
+                BudgetMetric::CpuTime,
+                Some(stats.cpu_time_ms as f64),
+                budget.cpu_time_ms.map(f64::from),
+            ),
+            (
+                BudgetMetric::GpuTime,
+                stats.gpu_time_ms.map(f64::from),
+                budget.gpu_time_ms.map(f64::from),
+            ),
+            (
+                BudgetMetric::DrawCalls,
+                Some(stats.draw_calls as f64),
+                budget.draw_calls.map(f64::from),
+            ),
+            (
+                BudgetMetric::Triangles,
+                Some(stats.triangles as f64),
+                budget.triangles.map(|l| l as f64),
+            ),
+            (
+                BudgetMetric::Vram,
+                stats.vram_bytes.map(|v| v as f64),
+                budget.vram_bytes.map(|l| l as f64),
+            ),
+        ];
+
+        warnings.0.clear();
+
+        for (metric, value, limit) in measurements {
+            let (Some(value), Some(limit)) = (value, limit) else {
+                self.frames_over.remove(&metric);
+                continue;
+            };
+
+            if value <= limit {
+                self.frames_over.remove(&metric);
+                continue;
+            }
+
+            let frames = self.frames_over.entry(metric).or_insert(0);
+            *frames += 1;
+
+            if *frames < budget.consecutive_frames.max(1) {
+                continue;
+            }
+
+            let exceeded = BudgetExceeded {
+                metric,
+                value,
+                limit,
+            };
+
+            if *frames == budget.consecutive_frames.max(1) {
+                log::warn!(
+                    "{:?} over budget for {} frames: {:.2} > {:.2}",
+                    metric,
+                    frames,
+                    value,
+                    limit
+                );
+                events.single_write(exceeded);
+            }
+
+            warnings.0.push(exceeded);
+        }
+    }
+}
@@ -1,13 +1,20 @@
 use cgmath::{
     perspective, Deg, EuclideanSpace, Euler, Matrix4, Point3, Quaternion, Rad, Rotation, Vector3,
-    Zero,
+    VectorSpace, Zero,
 };
 use specs::{Component, Read, System, VecStorage, WriteStorage};
 use tracing::{event, Level};
 
-use crate::game::context::InputStateResource;
+use crate::{
+    game::{
+        camera_math::{self, Frustum, Ray},
+        context::InputStateResource,
+        marker::{self, ScreenAnchor},
+    },
+    renderer::RenderLayers,
+};
 
-use super::CurrentWindowSize;
+use super::{transform::Transform, CurrentWindowSize};
 
 #[derive(Component, Debug, Clone, Copy)]
 #[storage(VecStorage)]
@@ -21,6 +28,16 @@ pub struct Camera {
     pub rotation: Quaternion<f32>,
     pub velocity: Vector3<f32>,
     pub y_velocity: f32,
+
+    /// Draws the renderables that share a layer with it.
+    pub layers: RenderLayers,
+    /// Cameras other than the `ActiveCamera` are only drawn when they have an order, over the
+    /// active camera's image, lowest first.
+    pub order: Option<i32>,
+    /// When drawn over another camera, draws in front of everything before it, see
+    /// `OverlayCamera::clear_depth`. Otherwise its objects sort against the scene, which only
+    /// works out when it shares the active camera's near and far planes.
+    pub clear_depth: bool,
 }
 
 impl Camera {
@@ -34,6 +51,76 @@ impl Camera {
             ),
         )
     }
+
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        let (projection, view) = self.calculate_matrices();
+        projection * view
+    }
+
+    /// Where `point` lands on a `viewport` of the given size, in pixels from its top left, e.g. to
+    /// put a health bar over a character. `None` for points behind the camera.
+    pub fn world_to_screen(&self, point: Vector3<f32>, viewport: [f32; 2]) -> Option<[f32; 2]> {
+        camera_math::world_to_screen(self.view_projection(), point, viewport)
+    }
+
+    /// Like `world_to_screen`, but also placing points off screen or behind the camera, see
+    /// `screen_anchor`.
+    pub fn screen_anchor(
+        &self,
+        point: Vector3<f32>,
+        viewport: [f32; 2],
+        clamp_margin: Option<f32>,
+    ) -> ScreenAnchor {
+        marker::screen_anchor(self.view_projection(), point, viewport, clamp_margin)
+    }
+
+    /// The ray through `screen`, in pixels from the top left of a `viewport` of the given size,
+    /// e.g. to pick what's under the cursor.
+    pub fn screen_to_world_ray(&self, screen: [f32; 2], viewport: [f32; 2]) -> Option<Ray> {
+        camera_math::screen_to_world_ray(self.view_projection(), screen, viewport)
+    }
+
+    /// A camera drawn over the active one that only sees `layers`, e.g. with a narrower field of
+    /// view for a first person weapon on its own layer.
+    pub fn overlay(order: i32, layers: RenderLayers) -> Self {
+        Camera {
+            layers,
+            order: Some(order),
+            clear_depth: true,
+            ..Default::default()
+        }
+    }
+
+    /// Where a view model's `transform`, relative to the camera, is in the world.
+    pub fn view_model_transform(&self, transform: &Transform) -> Transform {
+        Transform {
+            position: self.position + self.rotation.rotate_vector(transform.position),
+            rotation: self.rotation * transform.rotation,
+            scale: transform.scale,
+        }
+    }
+
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(self.view_projection())
+    }
+
+    /// World space corners of the part of the view between `near` and `far` units in front of
+    /// the camera, ordered as in `camera_math::frustum_corners`. Shadow cascades are fit around
+    /// these slices.
+    pub fn sub_frustum_corners(&self, near: f32, far: f32) -> [Vector3<f32>; 8] {
+        let (_, view) = self.calculate_matrices();
+        camera_math::frustum_corners(perspective(self.fov, self.aspect_ratio, near, far) * view)
+    }
+
+    /// Blends the position and orientation from `self` at 0 to `other` at 1, taking the
+    /// projection from `other`.
+    pub fn interpolate(&self, other: &Camera, amount: f32) -> Camera {
+        Camera {
+            position: self.position.lerp(other.position, amount),
+            rotation: self.rotation.slerp(other.rotation, amount),
+            ..*other
+        }
+    }
 }
 
 impl Default for Camera {
@@ -47,10 +134,51 @@ impl Default for Camera {
             rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
             velocity: Vector3::zero(),
             y_velocity: 0.0,
+            layers: RenderLayers::DEFAULT,
+            order: None,
+            clear_depth: false,
+        }
+    }
+}
+
+/// Draws a renderable as a first person view model, e.g. the player's weapon. It's held by the
+/// `ActiveCamera` and drawn in its own pass after the scene, with its own field of view and depth
+/// range, so it never clips into walls and isn't stretched by a wide field of view.
+///
+/// The entity's `Transform` is relative to the camera, +z ahead and +y up. It isn't a position
+/// in the world, so view models shouldn't have `Bounds`. When there are several, they're all
+/// drawn with the first one's projection.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+#[storage(VecStorage)]
+pub struct ViewModel {
+    pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for ViewModel {
+    fn default() -> Self {
+        ViewModel {
+            fov: Deg(50.0),
+            near: 0.01,
+            far: 10.0,
         }
     }
 }
 
+impl ViewModel {
+    pub fn new(fov: Deg<f32>) -> Self {
+        ViewModel {
+            fov,
+            ..Default::default()
+        }
+    }
+
+    pub fn projection(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        perspective(self.fov, aspect_ratio, self.near, self.far)
+    }
+}
+
 pub struct CameraSystem;
 
 impl<'a> System<'a> for CameraSystem {
@@ -61,21 +189,16 @@ impl<'a> System<'a> for CameraSystem {
     );
 
     fn run(&mut self, data: Self::SystemData) {
+        profile_scope!("cameras");
         let (current_window_size, input_state, mut cameras) = data;
 
         let aspect = current_window_size
             .0
             .map(|ps| ps.width as f32 / ps.height as f32);
 
-        let delta_x = input_state
-            .0
-            .get("look_vertical_action")
-            .and_then(|dx| dx.value);
+        let delta_x = input_state.value("look_vertical_action");
 
-        let delta_y = input_state
-            .0
-            .get("look_horizontal_action")
-            .and_then(|dy| dy.value);
+        let delta_y = input_state.value("look_horizontal_action");
 
         use specs::Join;
 
@@ -106,33 +229,33 @@ impl<'a> System<'a> for CameraSystem {
 
             camera.rotation = camera.rotation * (pitch_quat * yaw_quat);
 
-            if let Some(state) = input_state.0.get("walk_forward") {
+            if let Some(state) = input_state.get("walk_forward") {
                 let direction = camera.rotation.rotate_vector(Vector3::new(0.0, 0.0, 1.0));
                 camera.velocity += direction * state.value.unwrap_or(0.5);
             }
 
-            if input_state.0.get("walk_backward").is_some() {
+            if input_state.get("walk_backward").is_some() {
                 let direction = camera.rotation.rotate_vector(Vector3::new(0.0, 0.0, -1.0));
                 camera.velocity += direction * 0.5;
             }
 
-            if let Some(state) = input_state.0.get("strafe_right") {
+            if let Some(state) = input_state.get("strafe_right") {
                 let direction = camera.rotation.rotate_vector(Vector3::new(0.0, 0.0, -1.0));
                 let right = direction.cross(Vector3::unit_y());
                 camera.velocity -= right * state.value.unwrap_or(0.5);
             }
 
-            if input_state.0.get("strafe_left").is_some() {
+            if input_state.get("strafe_left").is_some() {
                 let direction = camera.rotation.rotate_vector(Vector3::new(0.0, 0.0, -1.0));
                 let left = direction.cross(Vector3::unit_y());
                 camera.velocity += left * 0.5;
             }
 
-            if input_state.0.get("move_up").is_some() {
+            if input_state.get("move_up").is_some() {
                 camera.y_velocity -= 0.5;
             }
 
-            if input_state.0.get("move_down").is_some() {
+            if input_state.get("move_down").is_some() {
                 camera.y_velocity += 0.5;
             }
 
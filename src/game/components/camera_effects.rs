@@ -0,0 +1,235 @@
+use std::f32::consts::E;
+
+use cgmath::{Deg, Quaternion, Rotation, Rotation3, Vector3, VectorSpace, Zero};
+use serde::{Deserialize, Serialize};
+use specs::{Component, HashMapStorage, Join, System, WriteStorage};
+
+use crate::game::simulation::FIXED_TIME_STEP;
+
+use super::camera::Camera;
+
+/// Something gameplay sets off on a camera, see `CameraEffects::add` and
+/// `GameContext::add_camera_effect`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CameraEffect {
+    /// Adds to the shake's trauma, which is kept from 0 to 1. An explosion might add 0.5 and a
+    /// heavy footstep 0.05.
+    Trauma(f32),
+    /// Kicks the view up by `pitch` and right by `yaw` degrees, springing back after, e.g. for
+    /// recoil.
+    Kick { pitch: f32, yaw: f32 },
+    /// Widens the field of view by this many degrees, or narrows it when negative, springing
+    /// back after.
+    FovPunch(f32),
+}
+
+/// How a camera shakes at full trauma. Shake grows with the square of the trauma, so small
+/// amounts barely show and they add up quickly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraShake {
+    /// Largest pitch and yaw.
+    pub max_angle: Deg<f32>,
+    pub max_roll: Deg<f32>,
+    /// Largest movement along each axis, in world units.
+    pub max_offset: f32,
+    /// How quickly the shake wobbles, in changes of direction per second.
+    pub frequency: f32,
+    /// Trauma lost per second.
+    pub decay: f32,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        CameraShake {
+            max_angle: Deg(4.0),
+            max_roll: Deg(6.0),
+            max_offset: 0.1,
+            frequency: 15.0,
+            decay: 1.0,
+        }
+    }
+}
+
+/// Procedural effects layered over a camera's view: trauma based shake, recoil kicks and field
+/// of view punches. Drawn on the `ActiveCamera` only.
+///
+/// The effects only move what's drawn. The camera's own position and rotation, and whatever
+/// reads them like picking and culling, stay where gameplay put them.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct CameraEffects {
+    pub shake: CameraShake,
+    /// Angular frequency of the springs pulling kicks and punches back, in radians per second.
+    /// Higher settles faster.
+    pub spring_frequency: f32,
+    trauma: f32,
+    /// Seconds the current shake has run, reset once the trauma is gone.
+    time: f32,
+    pitch: Spring,
+    yaw: Spring,
+    fov: Spring,
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        CameraEffects {
+            shake: CameraShake::default(),
+            spring_frequency: 20.0,
+            trauma: 0.0,
+            time: 0.0,
+            pitch: Spring::default(),
+            yaw: Spring::default(),
+            fov: Spring::default(),
+        }
+    }
+}
+
+impl CameraEffects {
+    pub fn add(&mut self, effect: CameraEffect) {
+        match effect {
+            CameraEffect::Trauma(amount) => {
+                self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+            }
+            CameraEffect::Kick { pitch, yaw } => {
+                self.pitch.kick(pitch, self.spring_frequency);
+                self.yaw.kick(yaw, self.spring_frequency);
+            }
+            CameraEffect::FovPunch(degrees) => self.fov.kick(degrees, self.spring_frequency),
+        }
+    }
+
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+
+    /// Stops every effect at once, e.g. on a camera cut.
+    pub fn clear(&mut self) {
+        *self = CameraEffects {
+            shake: self.shake,
+            spring_frequency: self.spring_frequency,
+            ..Default::default()
+        };
+    }
+
+    fn update(&mut self, delta: f32) {
+        self.trauma = (self.trauma - self.shake.decay * delta).max(0.0);
+        self.time = if self.trauma > 0.0 {
+            self.time + delta
+        } else {
+            0.0
+        };
+        for spring in [&mut self.pitch, &mut self.yaw, &mut self.fov] {
+            spring.update(delta, self.spring_frequency);
+        }
+    }
+
+    /// Where the effects put the view right now, relative to the camera.
+    pub fn offset(&self) -> CameraOffset {
+        let shake = self.trauma * self.trauma;
+        let t = self.time * self.shake.frequency;
+        let pitch = self.pitch.value + self.shake.max_angle.0 * shake * noise(0, t);
+        let yaw = self.yaw.value + self.shake.max_angle.0 * shake * noise(1, t);
+        let roll = self.shake.max_roll.0 * shake * noise(2, t);
+
+        // Cameras look down +z with +y up, so +x is to their left
+        CameraOffset {
+            rotation: Quaternion::from_angle_y(Deg(-yaw))
+                * Quaternion::from_angle_x(Deg(-pitch))
+                * Quaternion::from_angle_z(Deg(roll)),
+            translation: Vector3::new(noise(3, t), noise(4, t), noise(5, t))
+                * (self.shake.max_offset * shake),
+            fov: Deg(self.fov.value),
+        }
+    }
+}
+
+/// Moves, turns and zooms a camera in its own frame, applied to its matrices when it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraOffset {
+    pub rotation: Quaternion<f32>,
+    pub translation: Vector3<f32>,
+    /// Added to the field of view.
+    pub fov: Deg<f32>,
+}
+
+impl Default for CameraOffset {
+    fn default() -> Self {
+        CameraOffset {
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            translation: Vector3::zero(),
+            fov: Deg(0.0),
+        }
+    }
+}
+
+impl CameraOffset {
+    pub fn interpolate(&self, other: &CameraOffset, amount: f32) -> CameraOffset {
+        CameraOffset {
+            rotation: self.rotation.slerp(other.rotation, amount),
+            translation: self.translation.lerp(other.translation, amount),
+            fov: Deg(self.fov.0 + (other.fov.0 - self.fov.0) * amount),
+        }
+    }
+
+    pub fn apply(&self, camera: &Camera) -> Camera {
+        Camera {
+            position: camera.position + camera.rotation.rotate_vector(self.translation),
+            rotation: camera.rotation * self.rotation,
+            fov: Deg((camera.fov.0 + self.fov.0).clamp(1.0, 170.0)),
+            ..*camera
+        }
+    }
+}
+
+/// A critically damped spring pulling `value` back to 0.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Spring {
+    value: f32,
+    velocity: f32,
+}
+
+impl Spring {
+    /// Pushes the spring so it swings out to about `peak` before settling.
+    fn kick(&mut self, peak: f32, frequency: f32) {
+        // Starting from rest, a critically damped spring peaks at velocity / (frequency * e)
+        self.velocity += peak * frequency * E;
+    }
+
+    fn update(&mut self, delta: f32, frequency: f32) {
+        let acceleration = -frequency * frequency * self.value - 2.0 * frequency * self.velocity;
+        self.velocity += acceleration * delta;
+        self.value += self.velocity * delta;
+    }
+}
+
+/// Smooth noise from -1 to 1, following a different curve for each `channel`.
+fn noise(channel: u32, t: f32) -> f32 {
+    let cell = t.floor();
+    let fraction = t - cell;
+    let a = lattice(channel, cell as i32);
+    let b = lattice(channel, cell as i32 + 1);
+    a + (b - a) * fraction * fraction * (3.0 - 2.0 * fraction)
+}
+
+fn lattice(channel: u32, cell: i32) -> f32 {
+    let mut hash = (cell as u32).wrapping_mul(0x9e37_79b9) ^ channel.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb_352d);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x846c_a68b);
+    hash ^= hash >> 16;
+    hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+/// Decays trauma and settles the springs of every camera's effects.
+pub struct CameraEffectsSystem;
+
+impl<'a> System<'a> for CameraEffectsSystem {
+    type SystemData = WriteStorage<'a, CameraEffects>;
+
+    fn run(&mut self, mut effects: Self::SystemData) {
+        for effects in (&mut effects).join() {
+            effects.update(FIXED_TIME_STEP);
+        }
+    }
+}
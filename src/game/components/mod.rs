@@ -1,10 +1,35 @@
-pub use camera::{Camera, CameraSystem};
+pub use budget::{BudgetExceeded, BudgetMetric, BudgetSystem, BudgetWarnings, PerformanceBudget};
+pub use camera::{Camera, CameraSystem, ViewModel};
+pub use camera_effects::{
+    CameraEffect, CameraEffects, CameraEffectsSystem, CameraOffset, CameraShake,
+};
+pub use particles::{ParticleEmitter, ParticleSystem};
 pub use resources::{
-    ActiveCamera, BlendFactor, CurrentWindowId, CurrentWindowSize, CursorCaptured, ResizeEvents,
+    ActiveCamera, CurrentCursorMode, CurrentWindowId, CurrentWindowSize, DebugLine, DebugLines,
+    FoliageChange, FoliageChanges, FrameAnalysisResource, FrameCaptureRequest, FrameStatsResource,
+    HudPanels, HudVisible, MaterialEdit, MaterialEdits, MaterialEntry, MaterialLibrary,
+    NavMeshDebug, PickResource, ProbeRecaptureRequest, RenderFeature, RenderFeatureChanges,
+    ResizeEvents, TextInputActive, UiAtlasRequest, UiPrimitives, UiScale, VisibilityResource,
+};
+pub use spatial::{Bounds, EntityKey, RayHit, RayQuery, SpatialIndex, SpatialIndexSystem};
+pub use time_of_day::{Sky, SkyKeyframe, TimeOfDay, TimeOfDaySystem, HOURS_PER_DAY};
+pub use timeline::{
+    CameraKey, Cue, Keyframe, Timeline, TimelineCommand, TimelineEvent, TimelineStatus,
+    TimelineSystem, Timelines, Track,
 };
+pub use tween::{Easing, Tween, TweenLoop, TweenSystem, TweenTarget};
+
+pub(crate) use timeline::apply_timeline_command;
 
 pub mod render;
 pub mod transform;
 
+mod budget;
 mod camera;
+mod camera_effects;
+mod particles;
 mod resources;
+mod spatial;
+mod time_of_day;
+mod timeline;
+mod tween;
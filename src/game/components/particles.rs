@@ -0,0 +1,231 @@
+use std::f32::consts::{PI, TAU};
+
+use cgmath::{InnerSpace, Vector3};
+use specs::{Component, DenseVecStorage, Join, ReadExpect, ReadStorage, System, WriteStorage};
+
+use crate::{
+    game::{jobs::JobSystem, simulation::FIXED_TIME_STEP},
+    random::Random,
+    renderer::Billboard,
+};
+
+use super::transform::Transform;
+
+/// Starts particles from the entity's position and simulates them on the CPU every fixed update,
+/// spread over the job pool. They're drawn as billboards, so their `uv` region comes from the
+/// billboard atlas.
+///
+/// Particles move in world space once started, so a moving emitter leaves a trail.
+#[derive(Component)]
+#[storage(DenseVecStorage)]
+pub struct ParticleEmitter {
+    /// Particles started per second.
+    pub rate: f32,
+    /// No more are started while this many are alive.
+    pub max_particles: usize,
+    /// Seconds each particle lives, picked between the two.
+    pub lifetime: [f32; 2],
+    /// Meters per second each particle starts at, picked between the two.
+    pub speed: [f32; 2],
+    /// Particles start moving this way, give or take `spread`.
+    pub direction: Vector3<f32>,
+    /// Radians from `direction`, up to PI for every way.
+    pub spread: f32,
+    /// Meters per second squared, e.g. gravity.
+    pub acceleration: Vector3<f32>,
+    /// How much of its velocity a particle loses per second.
+    pub drag: f32,
+    /// Width and height at birth, growing or shrinking to `end_size` at death.
+    pub start_size: [f32; 2],
+    pub end_size: [f32; 2],
+    /// Straight alpha, fading to `end_color`.
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub uv: [f32; 4],
+    /// Live particles carry on when this is turned off.
+    pub emitting: bool,
+    particles: Particles,
+    random: Random,
+    /// Fractions of a particle owed by previous updates.
+    pending: f32,
+}
+
+impl Default for ParticleEmitter {
+    /// A fountain of white sparks.
+    fn default() -> Self {
+        ParticleEmitter {
+            rate: 50.0,
+            max_particles: 1000,
+            lifetime: [1.0, 2.0],
+            speed: [2.0, 4.0],
+            direction: Vector3::unit_y(),
+            spread: 0.4,
+            acceleration: Vector3::new(0.0, -9.81, 0.0),
+            drag: 0.1,
+            start_size: [0.1, 0.1],
+            end_size: [0.02, 0.02],
+            start_color: [1.0; 4],
+            end_color: [1.0, 1.0, 1.0, 0.0],
+            uv: [0.0, 0.0, 1.0, 1.0],
+            emitting: true,
+            particles: Particles::default(),
+            random: Random::new(1),
+            pending: 0.0,
+        }
+    }
+}
+
+impl ParticleEmitter {
+    pub fn new(rate: f32) -> Self {
+        ParticleEmitter {
+            rate,
+            ..Default::default()
+        }
+    }
+
+    /// Seeds the random lifetimes, speeds and directions.
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.random = Random::new(seed);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.ages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Starts `count` particles at `origin` right away, e.g. for an explosion, up to
+    /// `max_particles`.
+    pub fn burst(&mut self, origin: Vector3<f32>, count: usize) {
+        for _ in 0..count.min(self.max_particles.saturating_sub(self.len())) {
+            self.spawn(origin);
+        }
+    }
+
+    /// The live particles as billboards, in world space.
+    pub fn billboards(&self) -> impl Iterator<Item = Billboard> + '_ {
+        let particles = &self.particles;
+        (0..self.len()).map(move |index| {
+            let t = (particles.ages[index] / particles.lifetimes[index]).clamp(0.0, 1.0);
+            let mix = |a: f32, b: f32| a + (b - a) * t;
+            Billboard {
+                position: particles.positions[index],
+                size: [
+                    mix(self.start_size[0], self.end_size[0]),
+                    mix(self.start_size[1], self.end_size[1]),
+                ],
+                uv: self.uv,
+                color: [0, 1, 2, 3].map(|c| mix(self.start_color[c], self.end_color[c])),
+                cylindrical: false,
+            }
+        })
+    }
+
+    fn spawn(&mut self, origin: Vector3<f32>) {
+        let random = &mut self.random;
+        let lifetime = random.range(self.lifetime[0], self.lifetime[1]);
+        let speed = random.range(self.speed[0], self.speed[1]);
+        // Uniform over the cap of the sphere within `spread` of the direction
+        let cos_theta = 1.0 - random.range(0.0, 1.0) * (1.0 - self.spread.clamp(0.0, PI).cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = random.range(0.0, TAU);
+        let forward = if self.direction.magnitude2() > 0.0 {
+            self.direction.normalize()
+        } else {
+            Vector3::unit_y()
+        };
+        let side = if forward.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_z()
+        };
+        let right = forward.cross(side).normalize();
+        let up = right.cross(forward);
+        let direction = forward * cos_theta + (right * phi.cos() + up * phi.sin()) * sin_theta;
+
+        self.particles.positions.push(origin);
+        self.particles.velocities.push(direction * speed);
+        self.particles.ages.push(0.0);
+        self.particles.lifetimes.push(lifetime.max(f32::EPSILON));
+    }
+
+    /// Starts this update's particles at `origin` and moves every particle on by `dt`.
+    fn update(&mut self, origin: Vector3<f32>, dt: f32, jobs: &JobSystem) {
+        if self.emitting {
+            self.pending += self.rate.max(0.0) * dt;
+            let count = self.pending as usize;
+            self.pending -= count as f32;
+            self.burst(origin, count);
+        }
+
+        let particles = &mut self.particles;
+        jobs.for_each_mut("particle ages", &mut particles.ages, |age| *age += dt);
+        let (acceleration, damping) = (self.acceleration, (1.0 - self.drag * dt).max(0.0));
+        jobs.for_each_mut(
+            "particle velocities",
+            &mut particles.velocities,
+            |velocity| {
+                *velocity = (*velocity + acceleration * dt) * damping;
+            },
+        );
+        jobs.for_each_zip_mut(
+            "particle positions",
+            &mut particles.positions,
+            &particles.velocities,
+            |position, velocity| *position += velocity * dt,
+        );
+
+        // Back to front, so swapping in the last particle doesn't skip one
+        for index in (0..particles.ages.len()).rev() {
+            if particles.ages[index] >= particles.lifetimes[index] {
+                particles.swap_remove(index);
+            }
+        }
+    }
+}
+
+/// The live particles, one array per attribute so each pass only touches what it updates.
+#[derive(Default)]
+struct Particles {
+    positions: Vec<Vector3<f32>>,
+    velocities: Vec<Vector3<f32>>,
+    ages: Vec<f32>,
+    lifetimes: Vec<f32>,
+}
+
+impl Particles {
+    fn swap_remove(&mut self, index: usize) {
+        self.positions.swap_remove(index);
+        self.velocities.swap_remove(index);
+        self.ages.swap_remove(index);
+        self.lifetimes.swap_remove(index);
+    }
+}
+
+/// Moves every `ParticleEmitter`'s particles on and starts new ones.
+pub struct ParticleSystem;
+
+impl<'a> System<'a> for ParticleSystem {
+    type SystemData = (
+        ReadExpect<'a, JobSystem>,
+        ReadStorage<'a, Transform>,
+        WriteStorage<'a, ParticleEmitter>,
+    );
+
+    fn run(&mut self, (jobs, transforms, mut emitters): Self::SystemData) {
+        profile_scope!("particles");
+        for (transform, emitter) in (&transforms, &mut emitters).join() {
+            emitter.update(transform.position, FIXED_TIME_STEP, &jobs);
+        }
+        profile_plot!(
+            "particles",
+            (&emitters)
+                .join()
+                .map(|emitter| emitter.len())
+                .sum::<usize>()
+        );
+    }
+}
@@ -1,55 +1,326 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{ensure, Context};
+use cgmath::{Vector3, VectorSpace};
 use log::error;
-use specs::{Component, Read, ReadStorage, System, VecStorage, Write};
+use serde::{Deserialize, Serialize};
+use specs::{Component, NullStorage, Read, System, VecStorage, Write};
 use tracing::{event, Level};
 
-use crate::Renderer;
+use crate::{
+    assets::{AssetServer, TextureUsage},
+    game::{
+        inspect::Inspect,
+        simulation::SnapshotBuffer,
+        voxel::{VoxelMeshQueue, CHUNK_SIZE},
+    },
+    renderer::{
+        Attenuation, Billboard, DirectionalLight, GlobalIllumination, MaterialId, OverlayCamera,
+        PointLight, Portal, RenderLayers, SkyGradient, Tint, VIEW_MODEL_LAYER,
+    },
+    Renderer,
+};
 
 use super::{
-    resources::{BlendFactor, ResizeEvents},
-    transform::Transform,
-    ActiveCamera, Camera, CurrentWindowId, CurrentWindowSize, CursorCaptured,
+    resources::ResizeEvents, transform::Transform, CurrentCursorMode, CurrentWindowId,
+    CurrentWindowSize, DebugLines, EntityKey, FoliageChange, FoliageChanges, FrameAnalysisResource,
+    FrameCaptureRequest, FrameStatsResource, HudPanels, HudVisible, MaterialEdit, MaterialEdits,
+    MaterialEntry, MaterialLibrary, PickResource, ProbeRecaptureRequest, RenderFeature,
+    RenderFeatureChanges, TextInputActive, UiAtlasRequest, UiPrimitives, UiScale,
+    VisibilityResource,
 };
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Serialize, Deserialize)]
 #[storage(VecStorage)]
 pub struct Renderable {
     pub mesh_id: usize,
+    /// Drawn with the default material when `None`.
+    pub material: Option<MaterialId>,
+    /// The cameras that draw it, see `Camera::layers`.
+    #[serde(default)]
+    pub layers: RenderLayers,
 }
 
+/// Blends a renderable's base color toward a flat color, e.g. to flash it on damage or highlight
+/// a selection.
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct TintComponent(pub Tint);
+
+/// Outlines a renderable, e.g. the entities picked in an editor.
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct SelectedTag;
+
+/// A point light at the entity's position.
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct PointLightComponent {
+    /// See `color_temperature` for the color of light sources.
+    pub color: [f32; 3],
+    /// Lumens.
+    pub intensity: f32,
+    pub radius: f32,
+    pub attenuation: Attenuation,
+}
+
+impl Default for PointLightComponent {
+    fn default() -> Self {
+        let light = PointLight::default();
+        PointLightComponent {
+            color: light.color,
+            intensity: light.intensity,
+            radius: light.radius,
+            attenuation: light.attenuation,
+        }
+    }
+}
+
+/// A camera facing quad drawn at the entity's position, offset by the billboard's `position`.
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct BillboardComponent(pub Billboard);
+
+/// A portal quad at the entity's position and rotation, facing its +z, showing the scene from
+/// `destination`. See `Portal`.
+#[derive(Component, Debug, Clone, Copy)]
+#[storage(VecStorage)]
+pub struct PortalComponent {
+    /// A mirror when `None`.
+    pub destination: Option<Transform>,
+    /// Width and height of the quad.
+    pub size: [f32; 2],
+}
+
+/// A reflection probe whose box is centered on the entity's position. See `ReflectionProbe`.
+#[derive(Component, Debug, Clone, Copy)]
+#[storage(VecStorage)]
+pub struct ReflectionProbeComponent {
+    /// Half the box's size along each axis.
+    pub half_extents: Vector3<f32>,
+}
+
+/// The scene's sun. Only the first entity with one is drawn.
+#[derive(Component, Debug)]
+#[storage(VecStorage)]
+pub struct DirectionalLightComponent {
+    /// The way the light travels.
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    /// Lux.
+    pub illuminance: f32,
+}
+
+impl From<&DirectionalLightComponent> for DirectionalLight {
+    fn from(light: &DirectionalLightComponent) -> Self {
+        DirectionalLight {
+            direction: light.direction,
+            color: light.color,
+            illuminance: light.illuminance,
+        }
+    }
+}
+
+/// Draws the simulation's snapshots, interpolated between the last two ticks.
 pub struct RenderSystem {
     renderer: Renderer,
+    snapshots: Arc<SnapshotBuffer>,
+    /// Reloads the assets loaded through it when their files change.
+    assets: Option<AssetServer>,
+    voxel_chunks: HashMap<[i32; 3], ChunkDraw>,
+    /// Keeps the renderer's scene across frames, see `with_retained_scene`.
+    retained: bool,
+    /// What was last sent to the renderer for each object of a retained scene.
+    queued: HashMap<u64, QueuedObject>,
+}
+
+/// A tracked object as it's sent to the renderer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct QueuedObject {
+    mesh_id: usize,
+    material: Option<MaterialId>,
+    transform: Transform,
+    tint: Tint,
+    layers: RenderLayers,
+}
+
+/// The mesh the render system keeps for a voxel chunk.
+struct ChunkDraw {
+    /// `None` until the chunk first meshes to something.
+    mesh_id: Option<usize>,
+    entity: EntityKey,
+    version: u64,
+    empty: bool,
 }
 
 impl RenderSystem {
-    pub fn new(renderer: Renderer) -> Self {
-        RenderSystem { renderer }
+    pub fn new(
+        renderer: Renderer,
+        snapshots: Arc<SnapshotBuffer>,
+        assets: Option<AssetServer>,
+    ) -> Self {
+        RenderSystem {
+            renderer,
+            snapshots,
+            assets,
+            voxel_chunks: HashMap::new(),
+            retained: false,
+            queued: HashMap::new(),
+        }
+    }
+
+    /// Keeps the scene in the renderer between frames instead of queuing every object each
+    /// frame. Only the objects that were added, moved, changed or removed since the last frame
+    /// are sent.
+    pub fn with_retained_scene(mut self, retained: bool) -> Self {
+        if retained {
+            self.renderer.begin_scene();
+            self.renderer.end_scene();
+        }
+        self.retained = retained;
+        self
+    }
+
+    /// Queues `objects` for the frame, or in a retained scene sends the renderer what changed
+    /// since the last frame.
+    fn queue_objects(&mut self, objects: Vec<(u64, QueuedObject)>) {
+        if !self.retained {
+            for (key, object) in objects {
+                self.renderer.enqueue_tracked_mesh(
+                    key,
+                    object.mesh_id,
+                    object.material,
+                    object.transform,
+                    object.tint,
+                    object.layers,
+                );
+            }
+            return;
+        }
+
+        let mut queued = HashMap::with_capacity(objects.len());
+        for (key, object) in objects {
+            if self.queued.get(&key) != Some(&object) {
+                self.renderer.update_tracked_mesh(
+                    key,
+                    object.mesh_id,
+                    object.material,
+                    object.transform,
+                    object.tint,
+                    object.layers,
+                );
+            }
+            queued.insert(key, object);
+        }
+        for key in self.queued.keys() {
+            if !queued.contains_key(key) {
+                self.renderer.remove_tracked_mesh(*key);
+            }
+        }
+        self.queued = queued;
+    }
+
+    fn apply_material_edit(&mut self, edit: MaterialEdit) -> anyhow::Result<()> {
+        match edit {
+            MaterialEdit::Field {
+                material,
+                field,
+                value,
+            } => {
+                let mut changed = self
+                    .renderer
+                    .material(material)
+                    .context("material doesn't exist")?
+                    .clone();
+                ensure!(
+                    changed.set_field(field, value),
+                    "material has no field {:?}",
+                    field
+                );
+                self.renderer.update_material(material, &changed)
+            }
+            MaterialEdit::Save(material) => self
+                .assets
+                .as_mut()
+                .context("assets aren't loaded through an asset server")?
+                .save_material(&self.renderer, material),
+        }
+    }
+
+    /// Every material, named after its file when it was loaded from one.
+    fn material_library(&self) -> Vec<MaterialEntry> {
+        self.renderer
+            .materials()
+            .map(|(id, material)| {
+                let path = self
+                    .assets
+                    .as_ref()
+                    .and_then(|assets| assets.material_path(id));
+                MaterialEntry {
+                    id,
+                    name: path
+                        .and_then(|path| path.file_stem())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| format!("material {}", id.0)),
+                    saveable: path.is_some(),
+                    fields: material.fields(),
+                }
+            })
+            .collect()
     }
 }
 
 impl<'a> System<'a> for RenderSystem {
     type SystemData = (
-        Read<'a, BlendFactor>,
-        Option<Read<'a, ActiveCamera>>,
         Write<'a, ResizeEvents>,
         Write<'a, CurrentWindowSize>,
         Write<'a, CurrentWindowId>,
-        ReadStorage<'a, Transform>,
-        ReadStorage<'a, Camera>,
-        ReadStorage<'a, Renderable>,
-        Read<'a, CursorCaptured>,
+        Read<'a, CurrentCursorMode>,
+        Read<'a, TextInputActive>,
+        Write<'a, FrameStatsResource>,
+        Write<'a, FrameAnalysisResource>,
+        Write<'a, PickResource>,
+        Read<'a, HudVisible>,
+        Read<'a, HudPanels>,
+        Write<'a, UiPrimitives>,
+        Write<'a, UiAtlasRequest>,
+        Read<'a, UiScale>,
+        Read<'a, DebugLines>,
+        Write<'a, FrameCaptureRequest>,
+        Write<'a, ProbeRecaptureRequest>,
+        Write<'a, RenderFeatureChanges>,
+        Write<'a, VisibilityResource>,
+        Write<'a, FoliageChanges>,
+        Read<'a, VoxelMeshQueue>,
+        Write<'a, MaterialEdits>,
+        Write<'a, MaterialLibrary>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
+        profile_scope!("render system");
         let (
-            _blending_factor,
-            active_camera,
             mut resize_events,
             mut current_window_size,
             mut current_window_id,
-            transforms,
-            cameras,
-            meshes,
-            cursor_captured,
+            cursor_mode,
+            text_input,
+            mut frame_stats,
+            mut frame_analysis,
+            mut pick,
+            hud_visible,
+            hud_panels,
+            mut ui_primitives,
+            mut ui_atlas,
+            ui_scale,
+            debug_lines,
+            mut capture_request,
+            mut recapture_request,
+            mut feature_changes,
+            mut visibility,
+            mut foliage_changes,
+            voxel_meshes,
+            mut material_edits,
+            mut material_library,
         ) = data;
 
         // Handle Resize Events
@@ -59,26 +330,335 @@ impl<'a> System<'a> for RenderSystem {
             resize_events.0 = false;
         }
 
+        if let Some(assets) = &mut self.assets {
+            assets.reload_changed(&mut self.renderer);
+        }
+
         current_window_size.0 = self.renderer.window_size();
         current_window_id.0 = self.renderer.window_id();
 
-        if let Some(captured) = cursor_captured.0 {
-            self.renderer.set_cursor_captured(captured);
+        self.renderer.set_cursor_mode(cursor_mode.0);
+        self.renderer.set_text_input(text_input.0);
+
+        if frame_analysis.requested {
+            self.renderer.request_analysis();
+            frame_analysis.requested = false;
+        }
+
+        if let Some(position) = pick.requested.take() {
+            self.renderer.request_pick(position);
+        }
+
+        if capture_request.0 {
+            self.renderer.capture_frame();
+            capture_request.0 = false;
+        }
+
+        if recapture_request.0 {
+            self.renderer.recapture_reflection_probes();
+            recapture_request.0 = false;
+        }
+
+        for (feature, enabled) in feature_changes.0.drain(..) {
+            match feature {
+                RenderFeature::Taa => self.renderer.set_taa(enabled),
+                RenderFeature::MotionBlur => self.renderer.set_motion_blur(enabled),
+                RenderFeature::Bloom => self.renderer.set_bloom(enabled),
+                RenderFeature::OcclusionCulling => self.renderer.set_occlusion_culling(enabled),
+                RenderFeature::DepthPrepass => self.renderer.set_depth_prepass(enabled),
+                RenderFeature::IndirectDraws => self.renderer.set_indirect_draws(enabled),
+                RenderFeature::GpuCulling => self.renderer.set_gpu_culling(enabled),
+                RenderFeature::Shadows => self.renderer.set_shadows(enabled),
+                RenderFeature::Ssr => self.renderer.set_ssr(enabled),
+                RenderFeature::Ssgi => self.renderer.set_global_illumination(if enabled {
+                    GlobalIllumination::ScreenSpace
+                } else {
+                    GlobalIllumination::Off
+                }),
+                RenderFeature::ShadowCascades => self.renderer.set_shadow_cascade_debug(enabled),
+            }
+        }
+
+        for edit in material_edits.0.drain(..) {
+            if let Err(e) = self.apply_material_edit(edit.clone()) {
+                error!("applying {:?}: {:#}", edit, e);
+            }
+            // Queued objects hold a copy of their material's factors
+            self.queued.clear();
+        }
+        if material_library.requested {
+            material_library.materials = self.material_library();
+        }
+
+        let (previous, current, blend) = self.snapshots.latest();
+
+        self.renderer.set_hud_visible(hud_visible.0);
+        self.renderer.set_hud_panels(hud_panels.0.clone());
+        if let Some(path) = ui_atlas.0.take() {
+            let texture = match &mut self.assets {
+                Some(assets) => assets.load_texture(
+                    &mut self.renderer,
+                    &path,
+                    TextureUsage::Color { srgb: true },
+                ),
+                None => self
+                    .renderer
+                    .load_texture(&path, TextureUsage::Color { srgb: true }),
+            };
+            if let Err(e) = texture.and_then(|texture| self.renderer.set_ui_atlas(texture)) {
+                error!("loading UI atlas {:?}: {:#}", path, e);
+            }
+        }
+        for change in foliage_changes.0.drain(..) {
+            match change {
+                FoliageChange::Set(id, mut layer, texture) => {
+                    let set = texture
+                        .map(|path| {
+                            let texture = match &mut self.assets {
+                                Some(assets) => assets.load_texture(
+                                    &mut self.renderer,
+                                    &path,
+                                    TextureUsage::Color { srgb: true },
+                                ),
+                                None => self
+                                    .renderer
+                                    .load_texture(&path, TextureUsage::Color { srgb: true }),
+                            };
+                            texture.with_context(|| format!("loading {:?}", path))
+                        })
+                        .transpose()
+                        .and_then(|texture| {
+                            layer.texture = texture;
+                            self.renderer.set_foliage(id, layer)
+                        });
+                    if let Err(e) = set {
+                        error!("setting foliage layer {:?}: {:#}", id, e);
+                    }
+                }
+                FoliageChange::Remove(id) => self.renderer.remove_foliage(id),
+                FoliageChange::Wind(wind) => self.renderer.set_wind(wind),
+            }
+        }
+        for mesh in voxel_meshes.take() {
+            let chunk = self.voxel_chunks.entry(mesh.coord).or_insert(ChunkDraw {
+                mesh_id: None,
+                entity: mesh.entity,
+                version: 0,
+                empty: true,
+            });
+            // A later remesh already finished
+            if mesh.version <= chunk.version {
+                continue;
+            }
+            chunk.version = mesh.version;
+            chunk.entity = mesh.entity;
+            chunk.empty = mesh.indices.is_empty();
+            if chunk.empty {
+                continue;
+            }
+            let uploaded = match chunk.mesh_id {
+                Some(mesh_id) => self
+                    .renderer
+                    .reload_mesh(mesh_id, mesh.vertices, mesh.indices),
+                None => self
+                    .renderer
+                    .create_mesh(mesh.vertices, mesh.indices)
+                    .map(|mesh_id| chunk.mesh_id = Some(mesh_id)),
+            };
+            if let Err(e) = uploaded {
+                error!("uploading voxel chunk {:?}: {:#}", mesh.coord, e);
+                chunk.empty = true;
+            }
+        }
+        for primitive in ui_primitives.0.drain(..) {
+            self.renderer.draw_ui(primitive);
         }
+        self.renderer.set_ui_scale(ui_scale.get());
+        for line in &debug_lines.0 {
+            self.renderer
+                .draw_debug_line(line.from, line.to, line.color);
+        }
+        self.renderer.set_hud_entities(Some(current.entities));
+        self.renderer.set_cpu_time(frame_stats.0.cpu_time_ms);
 
-        // Apply Active Camera's matrices
-        if let Some(active_cam) = active_camera {
-            let camera = cameras.get(active_cam.0).unwrap();
+        // Apply Active Camera's matrices, moved by its effects
+        let camera_offset = previous
+            .camera_offset
+            .interpolate(&current.camera_offset, blend);
+        let camera = current.camera.map(|camera| {
+            let camera = previous
+                .camera
+                .map(|previous| previous.interpolate(&camera, blend))
+                .unwrap_or(camera);
+            camera_offset.apply(&camera)
+        });
+        if let Some(camera) = camera {
             self.renderer.set_camera_params(camera.calculate_matrices());
+            self.renderer
+                .set_camera_layers(camera.layers.without(VIEW_MODEL_LAYER));
+        }
+
+        // View models get their own pass first, through the active camera with their own
+        // projection
+        let view_model = camera
+            .zip(current.objects.iter().find_map(|object| object.view_model))
+            .map(|(camera, view_model)| OverlayCamera {
+                projection: view_model.projection(camera.aspect_ratio),
+                view: camera.calculate_matrices().1,
+                layers: RenderLayers::layer(VIEW_MODEL_LAYER),
+                clear_depth: true,
+            });
+        let overlays = current.overlay_cameras.iter().map(|(entity, camera)| {
+            let camera = previous
+                .overlay_camera(*entity)
+                .map(|previous| previous.interpolate(camera, blend))
+                .unwrap_or(*camera);
+            let (projection, view) = camera.calculate_matrices();
+            OverlayCamera {
+                projection,
+                view,
+                layers: camera.layers,
+                clear_depth: camera.clear_depth,
+            }
+        });
+        self.renderer
+            .set_overlay_cameras(view_model.into_iter().chain(overlays));
+
+        self.renderer
+            .set_portals(current.portals.iter().map(|portal| {
+                let (source, destination) = match previous
+                    .portal(portal.entity.id())
+                    .filter(|previous| previous.entity == portal.entity)
+                {
+                    Some(previous) => (
+                        previous.source.interpolate(&portal.source, blend),
+                        previous
+                            .destination
+                            .zip(portal.destination)
+                            .map(|(from, to)| from.interpolate(&to, blend))
+                            .or(portal.destination),
+                    ),
+                    None => (portal.source, portal.destination),
+                };
+                match destination {
+                    Some(destination) => Portal::between(&source, &destination, portal.size),
+                    None => Portal::mirror(&source, portal.size),
+                }
+            }));
+        self.renderer
+            .set_spatial_index(current.spatial_index.clone());
+        // Before the objects are queued, they pick their probes from these
+        self.renderer
+            .set_reflection_probes(current.reflection_probes.iter().copied());
+
+        self.renderer.set_selected_objects(
+            current
+                .objects
+                .iter()
+                .filter(|object| object.selected)
+                .map(|object| object.entity.to_bits()),
+        );
+
+        // Entities that only exist in the current snapshot are drawn where they are, including
+        // ones that took the id of an entity deleted since the previous one
+        let mut objects = Vec::with_capacity(current.objects.len());
+        for object in &current.objects {
+            let transform = previous
+                .object(object.entity.id())
+                .filter(|previous| previous.entity == object.entity)
+                .map(|previous| previous.transform.interpolate(&object.transform, blend))
+                .unwrap_or(object.transform);
+            let (transform, layers) = match (object.view_model, camera) {
+                (None, _) => (transform, object.layers),
+                (Some(_), Some(camera)) => (
+                    camera.view_model_transform(&transform),
+                    RenderLayers::layer(VIEW_MODEL_LAYER),
+                ),
+                // Nothing to hold it
+                (Some(_), None) => continue,
+            };
+            objects.push((
+                object.entity.to_bits(),
+                QueuedObject {
+                    mesh_id: object.mesh_id,
+                    material: object.material,
+                    transform,
+                    tint: object.tint,
+                    layers,
+                },
+            ));
+        }
+
+        // Chunks don't move, they're culled by their entity like any other object
+        for (coord, chunk) in &self.voxel_chunks {
+            let Some(mesh_id) = chunk.mesh_id.filter(|_| !chunk.empty) else {
+                continue;
+            };
+            let origin = coord.map(|c| (c * CHUNK_SIZE) as f32);
+            objects.push((
+                chunk.entity.to_bits(),
+                QueuedObject {
+                    mesh_id,
+                    material: None,
+                    transform: Transform {
+                        position: origin.into(),
+                        rotation: [1.0, 0.0, 0.0, 0.0].into(),
+                        scale: [1.0, 1.0, 1.0].into(),
+                    },
+                    tint: Tint::default(),
+                    layers: RenderLayers::DEFAULT,
+                },
+            ));
         }
+        self.queue_objects(objects);
 
-        // Consider accumulating all the renderables into a list here
-        // and just passing them to renderer.draw()
-        // profile and see if that even has an impact
-        use specs::Join;
-        for (transform, mesh) in (&transforms, &meshes).join() {
-            // Apply blending_factor to Transforms before passing them to renderer
-            self.renderer.enqueue_mesh(mesh.mesh_id, *transform);
+        // Replaced rather than queued, so a retained scene's lights follow the snapshots too
+        self.renderer.set_point_lights(
+            current
+                .lights
+                .iter()
+                .map(|light| PointLight {
+                    position: previous
+                        .light(light.entity.id())
+                        .filter(|previous| previous.entity == light.entity)
+                        .map(|previous| previous.position.lerp(light.position, blend))
+                        .unwrap_or(light.position),
+                    color: light.color,
+                    intensity: light.intensity,
+                    radius: light.radius,
+                    attenuation: light.attenuation,
+                })
+                .collect(),
+        );
+        for billboard in &current.billboards {
+            let position = previous
+                .billboard(billboard.entity.id())
+                .filter(|previous| previous.entity == billboard.entity)
+                .map(|previous| {
+                    previous
+                        .billboard
+                        .position
+                        .lerp(billboard.billboard.position, blend)
+                })
+                .unwrap_or(billboard.billboard.position);
+            self.renderer.enqueue_billboard(&Billboard {
+                position,
+                ..billboard.billboard
+            });
+        }
+        for particle in &current.particles {
+            self.renderer.enqueue_billboard(particle);
+        }
+        self.renderer.set_directional_light(current.sun);
+        if let Some(sky) = current.sky {
+            self.renderer.set_ambient_light(sky.ambient);
+            self.renderer.set_exposure(sky.ev100);
+            // The ground under the horizon only shows where the scene has none, lit like it
+            self.renderer.set_sky(Some(SkyGradient {
+                zenith: sky.zenith,
+                horizon: sky.horizon,
+                ground: sky.ambient,
+            }));
         }
         let result: anyhow::Result<()> = self.renderer.render();
         match result {
@@ -87,5 +667,17 @@ impl<'a> System<'a> for RenderSystem {
                 error!("Error drawing: {:#?}", e);
             }
         }
+
+        // CPU time is measured around the whole frame by the game loop, so this is the last
+        // frame's
+        frame_stats.0 = self.renderer.frame_stats();
+        visibility.update(self.renderer.visible_objects());
+
+        if let Some(report) = self.renderer.take_analysis_report() {
+            frame_analysis.report = Some(report);
+        }
+        if let Some(result) = self.renderer.take_pick() {
+            pick.result = Some(result);
+        }
     }
 }
@@ -1,11 +1,22 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
 use specs::Entity;
 use winit::{dpi::PhysicalSize, window::WindowId};
 
-#[derive(Default)]
-pub struct ResizeEvents(pub bool);
+use crate::{
+    game::{input::CursorMode, inspect::Field},
+    renderer::{
+        AnalysisReport, FoliageId, FoliageLayer, FrameStats, HudPanel, MaterialId, PickResult,
+        UiPrimitive, Wind,
+    },
+};
+
+use super::EntityKey;
 
 #[derive(Default)]
-pub struct BlendFactor(pub f32);
+pub struct ResizeEvents(pub bool);
 
 pub struct ActiveCamera(pub Entity);
 
@@ -16,4 +27,251 @@ pub struct CurrentWindowId(pub Option<WindowId>);
 pub struct CurrentWindowSize(pub Option<PhysicalSize<u32>>);
 
 #[derive(Default)]
-pub struct CursorCaptured(pub Option<bool>);
+pub struct CurrentCursorMode(pub CursorMode);
+
+/// Whether something is taking typed text, letting the window's input method compose it.
+#[derive(Default)]
+pub struct TextInputActive(pub bool);
+
+#[derive(Default)]
+pub struct FrameStatsResource(pub FrameStats);
+
+/// Set to capture the next frame with RenderDoc.
+#[derive(Default)]
+pub struct FrameCaptureRequest(pub bool);
+
+/// Set to capture every reflection probe again.
+#[derive(Default)]
+pub struct ProbeRecaptureRequest(pub bool);
+
+/// Whether the performance HUD is drawn.
+#[derive(Default)]
+pub struct HudVisible(pub bool);
+
+/// Screen pixels per logical pixel for everything the UI passes draw. Follows the window's scale
+/// factor, updated from `ScaleFactorChanged` events, unless `forced` is set.
+#[derive(Debug, Clone, Copy)]
+pub struct UiScale {
+    pub scale_factor: f32,
+    pub forced: Option<f32>,
+}
+
+impl Default for UiScale {
+    fn default() -> Self {
+        UiScale {
+            scale_factor: 1.0,
+            forced: None,
+        }
+    }
+}
+
+impl UiScale {
+    pub fn get(&self) -> f32 {
+        self.forced.unwrap_or(self.scale_factor)
+    }
+}
+
+/// Panels the HUD draws whether or not its stats are visible.
+#[derive(Default)]
+pub struct HudPanels(pub Vec<HudPanel>);
+
+/// The game's UI, drawn under the HUD over the next frame.
+#[derive(Default)]
+pub struct UiPrimitives(pub Vec<UiPrimitive>);
+
+/// Set to load a texture as the UI atlas before the next frame.
+#[derive(Default)]
+pub struct UiAtlasRequest(pub Option<PathBuf>);
+
+/// A world space line for the debug draw pass.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugLine {
+    pub from: Vector3<f32>,
+    pub to: Vector3<f32>,
+    pub color: [f32; 4],
+}
+
+/// Lines drawn over the next frame, e.g. the editor's gizmo.
+#[derive(Default)]
+pub struct DebugLines(pub Vec<DebugLine>);
+
+/// The loaded navmesh's edges, drawn over every frame while `visible`.
+#[derive(Default)]
+pub struct NavMeshDebug {
+    pub visible: bool,
+    pub lines: Vec<DebugLine>,
+}
+
+/// Renderer features that can be switched while running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderFeature {
+    Taa,
+    MotionBlur,
+    Bloom,
+    OcclusionCulling,
+    DepthPrepass,
+    IndirectDraws,
+    /// Frustum culling of the indirect draws on the GPU.
+    GpuCulling,
+    Shadows,
+    /// Screen-space reflections.
+    Ssr,
+    /// Screen-space global illumination.
+    Ssgi,
+    /// Tints the scene by shadow cascade.
+    ShadowCascades,
+}
+
+impl RenderFeature {
+    pub const ALL: [RenderFeature; 11] = [
+        RenderFeature::Taa,
+        RenderFeature::MotionBlur,
+        RenderFeature::Bloom,
+        RenderFeature::OcclusionCulling,
+        RenderFeature::DepthPrepass,
+        RenderFeature::IndirectDraws,
+        RenderFeature::GpuCulling,
+        RenderFeature::Shadows,
+        RenderFeature::Ssr,
+        RenderFeature::Ssgi,
+        RenderFeature::ShadowCascades,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderFeature::Taa => "taa",
+            RenderFeature::MotionBlur => "motion_blur",
+            RenderFeature::Bloom => "bloom",
+            RenderFeature::OcclusionCulling => "occlusion",
+            RenderFeature::DepthPrepass => "prepass",
+            RenderFeature::IndirectDraws => "indirect",
+            RenderFeature::GpuCulling => "gpu_culling",
+            RenderFeature::Shadows => "shadows",
+            RenderFeature::Ssr => "ssr",
+            RenderFeature::Ssgi => "ssgi",
+            RenderFeature::ShadowCascades => "cascades",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|feature| feature.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// A change to the foliage, applied by the render system on its next run.
+#[derive(Debug, Clone)]
+pub enum FoliageChange {
+    /// Replaces a layer, drawn with the texture at the path when there is one.
+    Set(FoliageId, FoliageLayer, Option<PathBuf>),
+    Remove(FoliageId),
+    Wind(Wind),
+}
+
+#[derive(Default)]
+pub struct FoliageChanges(pub Vec<FoliageChange>);
+
+/// A material as the editor lists it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialEntry {
+    pub id: MaterialId,
+    /// The file's name for materials loaded from one.
+    pub name: String,
+    /// Whether it was loaded from a file it can be saved back to.
+    pub saveable: bool,
+    pub fields: Vec<Field>,
+}
+
+/// The renderer's materials, listed by the render system on every run it's `requested`.
+#[derive(Debug, Default)]
+pub struct MaterialLibrary {
+    pub requested: bool,
+    pub materials: Vec<MaterialEntry>,
+}
+
+/// A change to a material, applied by the render system on its next run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaterialEdit {
+    Field {
+        material: MaterialId,
+        field: &'static str,
+        value: f32,
+    },
+    /// Writes the material's factors back to its file.
+    Save(MaterialId),
+}
+
+#[derive(Default)]
+pub struct MaterialEdits(pub Vec<MaterialEdit>);
+
+/// Feature switches the render system applies on its next run.
+#[derive(Default)]
+pub struct RenderFeatureChanges(pub Vec<(RenderFeature, bool)>);
+
+/// The entities whose renderables passed frustum and occlusion culling in the last rendered
+/// frame, for gameplay that only matters on screen, e.g. waking AI or playing ambient sounds.
+/// Both tests are conservative, so an entity that just went out of sight can count as visible for
+/// a frame or two. The simulation world's copy lags the render world's by a frame.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VisibilityResource {
+    /// Counts the rendered frames, zero until the first one.
+    pub frame: u64,
+    entities: HashSet<u32>,
+}
+
+impl VisibilityResource {
+    pub fn is_visible(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity.id())
+    }
+
+    /// The ids of the visible entities.
+    pub fn visible(&self) -> impl Iterator<Item = u32> + '_ {
+        self.entities.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// The same set with every id passed through `map`, e.g. for entities a replay loaded under
+    /// new ids.
+    pub(crate) fn remapped(&self, map: impl Fn(u32) -> u32) -> Self {
+        VisibilityResource {
+            frame: self.frame,
+            entities: self.entities.iter().map(|id| map(*id)).collect(),
+        }
+    }
+
+    /// Replaces the set with the renderer's tracked object keys, which are `EntityKey`s.
+    pub(crate) fn update<'a>(&mut self, keys: impl IntoIterator<Item = &'a u64>) {
+        self.frame += 1;
+        self.entities = keys
+            .into_iter()
+            .map(|key| EntityKey::from_bits(*key).id())
+            .collect();
+    }
+}
+
+/// Set `requested` to a position in window pixels to pick the entity drawn there on the next
+/// frame, `result` is replaced once the pixel has been read back from the GPU. Its key is the
+/// entity's `EntityKey`.
+#[derive(Default)]
+pub struct PickResource {
+    pub requested: Option<[f32; 2]>,
+    pub result: Option<PickResult>,
+}
+
+/// Set `requested` to run the frame analysis tools, the report replaces `report` once it has been
+/// read back from the GPU.
+#[derive(Default)]
+pub struct FrameAnalysisResource {
+    pub requested: bool,
+    pub report: Option<AnalysisReport>,
+    /// Draws `report` as a HUD panel.
+    pub overlay: bool,
+}
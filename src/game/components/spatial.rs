@@ -0,0 +1,668 @@
+use std::{cell::Cell, collections::HashMap};
+
+use cgmath::{ElementWise, InnerSpace, Matrix4, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+use specs::{
+    hibitset::BitSet, storage::ComponentEvent, Component, Entities, Entity, FlaggedStorage, Join,
+    ReaderId, System, VecStorage, Write, WriteStorage,
+};
+
+use crate::game::{
+    camera_math::{Frustum, Ray},
+    jobs::JobSystem,
+};
+
+use super::transform::Transform;
+
+/// An entity's id and generation packed together, so an entity that reuses a deleted one's id
+/// isn't taken for it. Snapshots and the renderer's tracked objects refer to entities by these,
+/// which sort by id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntityKey(u64);
+
+impl EntityKey {
+    pub fn id(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+
+    /// The key as the renderer's object key.
+    pub fn to_bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        EntityKey(bits)
+    }
+}
+
+impl From<Entity> for EntityKey {
+    fn from(entity: Entity) -> Self {
+        EntityKey(((entity.id() as u64) << 32) | entity.gen().id() as u32 as u64)
+    }
+}
+
+/// How far each side of a leaf's box is grown past its entity's bounds, so small moves don't
+/// change the tree.
+const FAT_MARGIN: f32 = 0.2;
+
+/// An axis aligned box. As a component it's the entity's extent around its transform's origin,
+/// in local space. The spatial index hands out world space boxes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Component for Bounds {
+    type Storage = FlaggedStorage<Self, VecStorage<Self>>;
+}
+
+impl Bounds {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        Bounds { min, max }
+    }
+
+    /// A box reaching `half_extent` from the origin along each axis.
+    pub fn cube(half_extent: f32) -> Self {
+        let half = Vector3::new(half_extent, half_extent, half_extent);
+        Bounds::new(-half, half)
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: Vector3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Vector3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn grow(&self, amount: f32) -> Bounds {
+        let amount = Vector3::new(amount, amount, amount);
+        Bounds::new(self.min - amount, self.max + amount)
+    }
+
+    pub fn contains(&self, other: &Bounds) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && self.min.z <= other.min.z
+            && self.max.x >= other.max.x
+            && self.max.y >= other.max.y
+            && self.max.z >= other.max.z
+    }
+
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        self.min.x <= other.max.x
+            && self.min.y <= other.max.y
+            && self.min.z <= other.max.z
+            && self.max.x >= other.min.x
+            && self.max.y >= other.min.y
+            && self.max.z >= other.min.z
+    }
+
+    /// Zero for points inside.
+    pub fn distance_squared(&self, point: Vector3<f32>) -> f32 {
+        let clamped = Vector3::new(
+            point.x.clamp(self.min.x, self.max.x),
+            point.y.clamp(self.min.y, self.max.y),
+            point.z.clamp(self.min.z, self.max.z),
+        );
+        (point - clamped).magnitude2()
+    }
+
+    /// The distance along `ray` to where it enters the box, zero when it starts inside.
+    pub fn ray_distance(&self, ray: &Ray) -> Option<f32> {
+        self.ray_hit(ray).map(|(distance, _)| distance)
+    }
+
+    /// The distance along `ray` to where it enters the box and the normal of the side it enters
+    /// through. Rays starting inside hit at zero, facing back along the ray.
+    pub fn ray_hit(&self, ray: &Ray) -> Option<(f32, Vector3<f32>)> {
+        let inverse = Vector3::new(1.0, 1.0, 1.0).div_element_wise(ray.direction);
+        let near = (self.min - ray.origin).mul_element_wise(inverse);
+        let far = (self.max - ray.origin).mul_element_wise(inverse);
+        let entries = [near.x.min(far.x), near.y.min(far.y), near.z.min(far.z)];
+        let exit = near
+            .x
+            .max(far.x)
+            .min(near.y.max(far.y))
+            .min(near.z.max(far.z));
+        let (axis, entry) =
+            entries
+                .into_iter()
+                .enumerate()
+                .fold((0, f32::MIN), |best, (axis, entry)| {
+                    if entry > best.1 {
+                        (axis, entry)
+                    } else {
+                        best
+                    }
+                });
+        if exit < entry.max(0.0) {
+            return None;
+        }
+        if entry < 0.0 {
+            return Some((0.0, -ray.direction));
+        }
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        normal[axis] = -ray.direction[axis].signum();
+        Some((entry, normal))
+    }
+
+    /// The box around these bounds once moved by `model`.
+    pub fn transformed(&self, model: &Matrix4<f32>) -> Bounds {
+        let corners = (0..8).map(|index| {
+            let pick = |bit: usize, min: f32, max: f32| if index & bit == 0 { min } else { max };
+            let corner = Vector4::new(
+                pick(1, self.min.x, self.max.x),
+                pick(2, self.min.y, self.max.y),
+                pick(4, self.min.z, self.max.z),
+                1.0,
+            );
+            (model * corner).truncate()
+        });
+        corners.fold(
+            Bounds::new(
+                Vector3::new(f32::MAX, f32::MAX, f32::MAX),
+                Vector3::new(f32::MIN, f32::MIN, f32::MIN),
+            ),
+            |bounds, corner| bounds.union(&Bounds::new(corner, corner)),
+        )
+    }
+
+    fn surface_area(&self) -> f32 {
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+}
+
+/// Where a ray first entered an entity's bounds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub entity: Entity,
+    pub distance: f32,
+    pub point: Vector3<f32>,
+    /// Of the side of the bounds the ray entered through.
+    pub normal: Vector3<f32>,
+}
+
+/// One ray of a batch, see `SpatialIndex::raycast_batch`.
+#[derive(Debug, Clone, Copy)]
+pub struct RayQuery {
+    pub ray: Ray,
+    pub max_distance: f32,
+    /// Entities the ray passes through, e.g. the one firing and, for line of sight, the one
+    /// looked at.
+    pub ignore: [Option<Entity>; 2],
+}
+
+impl RayQuery {
+    pub fn new(ray: Ray, max_distance: f32) -> Self {
+        RayQuery {
+            ray,
+            max_distance,
+            ignore: [None; 2],
+        }
+    }
+
+    /// From `from` to `to`, stopping there.
+    pub fn between(from: Vector3<f32>, to: Vector3<f32>) -> Self {
+        let offset = to - from;
+        let distance = offset.magnitude();
+        let direction = if distance > 0.0 {
+            offset / distance
+        } else {
+            Vector3::unit_z()
+        };
+        RayQuery::new(
+            Ray {
+                origin: from,
+                direction,
+            },
+            distance,
+        )
+    }
+
+    /// Passes through `entity`, up to two of them.
+    pub fn ignoring(mut self, entity: Entity) -> Self {
+        if let Some(slot) = self.ignore.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(entity);
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NodeKind {
+    Leaf(Entity),
+    Branch([usize; 2]),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    /// Grown by `FAT_MARGIN` for leaves.
+    bounds: Bounds,
+    parent: Option<usize>,
+    kind: NodeKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Leaf {
+    node: usize,
+    entity: Entity,
+    bounds: Bounds,
+}
+
+/// A bounding volume hierarchy over the world space bounds of the simulation's entities with a
+/// `Transform` and `Bounds`, kept up to date by `SpatialIndexSystem`. Culling, picking and
+/// proximity queries all go through it rather than testing every entity.
+///
+/// Leaves are grown a little past their entity so most moves only update the entity's own box,
+/// and the tree is only changed for the entities that leave their leaf.
+#[derive(Debug, Clone, Default)]
+pub struct SpatialIndex {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    /// By entity id.
+    leaves: HashMap<u32, Leaf>,
+}
+
+impl SpatialIndex {
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Whether the entity `key` refers to is indexed.
+    pub fn contains_key(&self, key: EntityKey) -> bool {
+        self.leaves
+            .get(&key.id())
+            .is_some_and(|leaf| EntityKey::from(leaf.entity) == key)
+    }
+
+    /// The world space bounds `entity` was indexed with.
+    pub fn bounds(&self, entity: Entity) -> Option<Bounds> {
+        self.leaves
+            .get(&entity.id())
+            .filter(|leaf| leaf.entity == entity)
+            .map(|leaf| leaf.bounds)
+    }
+
+    /// Adds `entity` at the world space `bounds`, or moves it there.
+    pub fn update(&mut self, entity: Entity, bounds: Bounds) {
+        if let Some(leaf) = self.leaves.get_mut(&entity.id()) {
+            leaf.entity = entity;
+            leaf.bounds = bounds;
+            let node = leaf.node;
+            self.nodes[node].kind = NodeKind::Leaf(entity);
+            if self.nodes[node].bounds.contains(&bounds) {
+                return;
+            }
+            self.remove_leaf(node);
+            self.nodes[node].bounds = bounds.grow(FAT_MARGIN);
+            self.insert_leaf(node);
+            return;
+        }
+
+        let node = self.allocate(Node {
+            bounds: bounds.grow(FAT_MARGIN),
+            parent: None,
+            kind: NodeKind::Leaf(entity),
+        });
+        self.insert_leaf(node);
+        self.leaves.insert(
+            entity.id(),
+            Leaf {
+                node,
+                entity,
+                bounds,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if self
+            .leaves
+            .get(&entity.id())
+            .is_some_and(|leaf| leaf.entity == entity)
+        {
+            self.remove_id(entity.id());
+        }
+    }
+
+    /// Removes whichever entity is indexed under `id`, for entities that are already deleted.
+    fn remove_id(&mut self, id: u32) {
+        if let Some(leaf) = self.leaves.remove(&id) {
+            self.remove_leaf(leaf.node);
+            self.free.push(leaf.node);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = SpatialIndex::default();
+    }
+
+    /// The entities whose bounds overlap `bounds`.
+    pub fn query_bounds(&self, bounds: &Bounds) -> Vec<Entity> {
+        let mut found = vec![];
+        self.visit(
+            |node| node.intersects(bounds),
+            |leaf| {
+                if leaf.bounds.intersects(bounds) {
+                    found.push(leaf.entity);
+                }
+            },
+        );
+        found
+    }
+
+    /// The entities whose bounds could be inside `frustum`. Conservative like
+    /// `Frustum::intersects_aabb`.
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<Entity> {
+        let inside = |bounds: &Bounds| frustum.intersects_aabb(bounds.min, bounds.max);
+        let mut found = vec![];
+        self.visit(inside, |leaf| {
+            if inside(&leaf.bounds) {
+                found.push(leaf.entity);
+            }
+        });
+        found
+    }
+
+    /// The entities whose bounds come within `radius` of `center`, nearest first, with their
+    /// distances.
+    pub fn query_sphere(&self, center: Vector3<f32>, radius: f32) -> Vec<(Entity, f32)> {
+        let radius_squared = radius * radius;
+        let mut found = vec![];
+        self.visit(
+            |node| node.distance_squared(center) <= radius_squared,
+            |leaf| {
+                let distance_squared = leaf.bounds.distance_squared(center);
+                if distance_squared <= radius_squared {
+                    found.push((leaf.entity, distance_squared.sqrt()));
+                }
+            },
+        );
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+
+    /// The entity whose bounds are nearest `point` within `max_distance`.
+    pub fn nearest(&self, point: Vector3<f32>, max_distance: f32) -> Option<(Entity, f32)> {
+        self.query_sphere(point, max_distance).into_iter().next()
+    }
+
+    /// The entities whose bounds `ray` passes through within `max_distance`, nearest first, with
+    /// the distance to where it enters each.
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Vec<(Entity, f32)> {
+        let hits = |bounds: &Bounds| {
+            bounds
+                .ray_distance(ray)
+                .filter(|distance| *distance <= max_distance)
+        };
+        let mut found = vec![];
+        self.visit(
+            |node| hits(node).is_some(),
+            |leaf| {
+                if let Some(distance) = hits(&leaf.bounds) {
+                    found.push((leaf.entity, distance));
+                }
+            },
+        );
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+
+    /// The nearest entity `query` hits. Entities are hit where their bounds are, not their
+    /// meshes.
+    pub fn raycast_first(&self, query: &RayQuery) -> Option<RayHit> {
+        // Shrinks as hits are found, so branches behind the nearest one aren't walked
+        let nearest = Cell::new(query.max_distance);
+        let mut found = None;
+        self.visit(
+            |node| {
+                node.ray_distance(&query.ray)
+                    .is_some_and(|distance| distance <= nearest.get())
+            },
+            |leaf| {
+                if query.ignore.contains(&Some(leaf.entity)) {
+                    return;
+                }
+                if let Some((distance, normal)) = leaf.bounds.ray_hit(&query.ray) {
+                    if distance <= nearest.get() {
+                        nearest.set(distance);
+                        found = Some(RayHit {
+                            entity: leaf.entity,
+                            distance,
+                            point: query.ray.at(distance),
+                            normal,
+                        });
+                    }
+                }
+            },
+        );
+        found
+    }
+
+    /// Casts every query in parallel on the job pool, e.g. a tick's worth of hitscan shots,
+    /// returning each one's nearest hit in the same order.
+    pub fn raycast_batch(&self, jobs: &JobSystem, queries: &[RayQuery]) -> Vec<Option<RayHit>> {
+        jobs.map("raycast batch", queries, |query| self.raycast_first(query))
+    }
+
+    /// Whether each query reaches its end without hitting anything it doesn't ignore, in
+    /// parallel. Build them with `RayQuery::between`, ignoring the entities at either end.
+    pub fn line_of_sight_batch(&self, jobs: &JobSystem, queries: &[RayQuery]) -> Vec<bool> {
+        jobs.map("line of sight batch", queries, |query| {
+            self.raycast_first(query).is_none()
+        })
+    }
+
+    /// Walks the nodes whose grown bounds pass `descend`, calling `leaf` for each leaf reached.
+    fn visit(&self, descend: impl Fn(&Bounds) -> bool, mut leaf: impl FnMut(&Leaf)) {
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !descend(&node.bounds) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf(entity) => {
+                    if let Some(found) = self.leaves.get(&entity.id()) {
+                        leaf(found);
+                    }
+                }
+                NodeKind::Branch(children) => stack.extend(children),
+            }
+        }
+    }
+
+    fn allocate(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    /// Hangs `leaf` next to the node that grows the tree's surface area the least.
+    fn insert_leaf(&mut self, leaf: usize) {
+        let Some(root) = self.root else {
+            self.nodes[leaf].parent = None;
+            self.root = Some(leaf);
+            return;
+        };
+
+        let bounds = self.nodes[leaf].bounds;
+        let mut sibling = root;
+        while let NodeKind::Branch(children) = self.nodes[sibling].kind {
+            let area = self.nodes[sibling].bounds.surface_area();
+            let combined = self.nodes[sibling].bounds.union(&bounds).surface_area();
+            // Pairing with this node, or the growth its ancestors take on for descending further
+            let cost = 2.0 * combined;
+            let inherited = 2.0 * (combined - area);
+            let costs = children.map(|child| {
+                let node = &self.nodes[child];
+                let merged = node.bounds.union(&bounds).surface_area();
+                match node.kind {
+                    NodeKind::Leaf(_) => merged + inherited,
+                    NodeKind::Branch(_) => merged - node.bounds.surface_area() + inherited,
+                }
+            });
+            if cost < costs[0] && cost < costs[1] {
+                break;
+            }
+            sibling = if costs[0] <= costs[1] {
+                children[0]
+            } else {
+                children[1]
+            };
+        }
+
+        let old_parent = self.nodes[sibling].parent;
+        let parent = self.allocate(Node {
+            bounds: self.nodes[sibling].bounds.union(&bounds),
+            parent: old_parent,
+            kind: NodeKind::Branch([sibling, leaf]),
+        });
+        self.nodes[sibling].parent = Some(parent);
+        self.nodes[leaf].parent = Some(parent);
+        match old_parent {
+            Some(old_parent) => {
+                self.replace_child(old_parent, sibling, parent);
+                self.refit(Some(old_parent));
+            }
+            None => self.root = Some(parent),
+        }
+    }
+
+    /// Unhooks `leaf` from the tree, freeing its parent but not the leaf itself.
+    fn remove_leaf(&mut self, leaf: usize) {
+        let Some(parent) = self.nodes[leaf].parent else {
+            self.root = None;
+            return;
+        };
+        let NodeKind::Branch(children) = self.nodes[parent].kind else {
+            return;
+        };
+        let sibling = if children[0] == leaf {
+            children[1]
+        } else {
+            children[0]
+        };
+
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        match grandparent {
+            Some(grandparent) => {
+                self.replace_child(grandparent, parent, sibling);
+                self.refit(Some(grandparent));
+            }
+            None => self.root = Some(sibling),
+        }
+        self.nodes[leaf].parent = None;
+        self.free.push(parent);
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if let NodeKind::Branch(children) = &mut self.nodes[parent].kind {
+            for child in children.iter_mut().filter(|child| **child == old) {
+                *child = new;
+            }
+        }
+    }
+
+    /// Recomputes the bounds of `index` and its ancestors.
+    fn refit(&mut self, mut index: Option<usize>) {
+        while let Some(current) = index {
+            if let NodeKind::Branch([a, b]) = self.nodes[current].kind {
+                self.nodes[current].bounds = self.nodes[a].bounds.union(&self.nodes[b].bounds);
+            }
+            index = self.nodes[current].parent;
+        }
+    }
+}
+
+/// Updates the `SpatialIndex` for the entities whose `Transform` or `Bounds` changed since the
+/// last run, building it in full the first time. Changes made after it runs in a tick, e.g. by
+/// scripts, are picked up the next tick.
+#[derive(Default)]
+pub struct SpatialIndexSystem {
+    transform_events: Option<ReaderId<ComponentEvent>>,
+    bounds_events: Option<ReaderId<ComponentEvent>>,
+    dirty: BitSet,
+}
+
+impl<'a> System<'a> for SpatialIndexSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Bounds>,
+        Write<'a, SpatialIndex>,
+    );
+
+    fn run(&mut self, (entities, mut transforms, mut bounds, mut index): Self::SystemData) {
+        profile_scope!("spatial index");
+        self.dirty.clear();
+
+        if self.transform_events.is_none() || self.bounds_events.is_none() {
+            self.transform_events = Some(transforms.register_reader());
+            self.bounds_events = Some(bounds.register_reader());
+            index.clear();
+            for (entity, _, _) in (&entities, &transforms, &bounds).join() {
+                self.dirty.add(entity.id());
+            }
+        }
+
+        if let Some(reader) = &mut self.transform_events {
+            mark_dirty(&mut self.dirty, transforms.channel().read(reader));
+        }
+        if let Some(reader) = &mut self.bounds_events {
+            mark_dirty(&mut self.dirty, bounds.channel().read(reader));
+        }
+
+        for id in (&self.dirty).join() {
+            let entity = entities.entity(id);
+            let world_bounds = entities
+                .is_alive(entity)
+                .then(|| Some((transforms.get(entity)?, bounds.get(entity)?)))
+                .flatten()
+                .map(|(transform, bounds)| bounds.transformed(&transform.model()));
+            match world_bounds {
+                Some(world_bounds) => index.update(entity, world_bounds),
+                None => index.remove_id(id),
+            }
+        }
+    }
+}
+
+fn mark_dirty<'a>(dirty: &mut BitSet, events: impl Iterator<Item = &'a ComponentEvent>) {
+    for event in events {
+        match event {
+            ComponentEvent::Inserted(id)
+            | ComponentEvent::Modified(id)
+            | ComponentEvent::Removed(id) => {
+                dirty.add(*id);
+            }
+        }
+    }
+}
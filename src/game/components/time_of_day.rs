@@ -0,0 +1,247 @@
+use cgmath::{Deg, InnerSpace, Rad, Vector3};
+use specs::{Join, System, Write, WriteStorage};
+
+use crate::{game::simulation::FIXED_TIME_STEP, renderer::color_temperature};
+
+use super::render::DirectionalLightComponent;
+
+pub const HOURS_PER_DAY: f32 = 24.0;
+
+/// The sun and sky at one hour of a `TimeOfDay`'s curve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyKeyframe {
+    pub hour: f32,
+    /// The sun's color, or the moon's while the sun is down.
+    pub sun_color: [f32; 3],
+    /// Lux.
+    pub illuminance: f32,
+    /// The ambient light's color, which scales the environment.
+    pub ambient: [f32; 3],
+    /// The sky's color straight up and at the horizon, see `SkyGradient`.
+    pub zenith: [f32; 3],
+    pub horizon: [f32; 3],
+    /// See `Renderer::set_exposure`.
+    pub ev100: f32,
+}
+
+impl SkyKeyframe {
+    fn lerp(&self, other: &SkyKeyframe, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        let mix3 = |a: [f32; 3], b: [f32; 3]| [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])];
+        SkyKeyframe {
+            hour: mix(self.hour, other.hour),
+            sun_color: mix3(self.sun_color, other.sun_color),
+            illuminance: mix(self.illuminance, other.illuminance),
+            ambient: mix3(self.ambient, other.ambient),
+            zenith: mix3(self.zenith, other.zenith),
+            horizon: mix3(self.horizon, other.horizon),
+            ev100: mix(self.ev100, other.ev100),
+        }
+    }
+}
+
+/// What the renderer draws of the time of day besides the sun.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    pub hour: f32,
+    pub ambient: [f32; 3],
+    pub zenith: [f32; 3],
+    pub horizon: [f32; 3],
+    pub ev100: f32,
+}
+
+/// A day-night cycle. While it's a resource of the simulation world, `TimeOfDaySystem` moves it
+/// on every fixed update and points every `DirectionalLightComponent` from the sun, colored and
+/// brightened along `keyframes`. Game code can change it like any other resource, e.g. from a
+/// behavior tree leaf, or through `GameLoop::set_time_of_day`.
+///
+/// The sun rises in the east, +x, at 6 and sets in the west at 18. Between, the directional light
+/// is the moon, opposite the sun.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeOfDay {
+    /// From 0 up to 24, noon is 12.
+    pub hour: f32,
+    /// Seconds a whole day lasts.
+    pub day_length: f32,
+    pub paused: bool,
+    /// How far the sun's path leans south, towards -z, from passing straight overhead. About the
+    /// latitude.
+    pub tilt: Deg<f32>,
+    /// Sorted by hour, wrapping around from the last to the first at midnight.
+    pub keyframes: Vec<SkyKeyframe>,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        TimeOfDay {
+            hour: 12.0,
+            day_length: 1200.0,
+            paused: false,
+            tilt: Deg(30.0),
+            keyframes: default_keyframes(),
+        }
+    }
+}
+
+impl TimeOfDay {
+    pub fn new(hour: f32) -> Self {
+        let mut time = TimeOfDay::default();
+        time.set_hour(hour);
+        time
+    }
+
+    pub fn with_day_length(mut self, seconds: f32) -> Self {
+        self.day_length = seconds;
+        self
+    }
+
+    /// Replaces the curve, which is sorted by hour.
+    pub fn with_keyframes(mut self, mut keyframes: Vec<SkyKeyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.hour.total_cmp(&b.hour));
+        self.keyframes = keyframes;
+        self
+    }
+
+    /// Wraps `hour` into the day.
+    pub fn set_hour(&mut self, hour: f32) {
+        self.hour = hour.rem_euclid(HOURS_PER_DAY);
+    }
+
+    /// Moves time on by `seconds` of real time, unless paused.
+    pub fn advance(&mut self, seconds: f32) {
+        if self.paused || self.day_length <= 0.0 {
+            return;
+        }
+        self.set_hour(self.hour + seconds / self.day_length * HOURS_PER_DAY);
+    }
+
+    /// A unit vector from the ground towards the sun.
+    pub fn sun_position(&self) -> Vector3<f32> {
+        let angle = Rad::from(Deg((self.hour - 6.0) / HOURS_PER_DAY * 360.0));
+        let (sin, cos) = (angle.0.sin(), angle.0.cos());
+        let tilt = Rad::from(self.tilt).0;
+        Vector3::new(cos, sin * tilt.cos(), -sin * tilt.sin()).normalize()
+    }
+
+    pub fn is_day(&self) -> bool {
+        self.sun_position().y > 0.0
+    }
+
+    /// The way the directional light travels: from the sun by day and the moon by night.
+    pub fn light_direction(&self) -> Vector3<f32> {
+        let sun = self.sun_position();
+        if sun.y > 0.0 {
+            -sun
+        } else {
+            sun
+        }
+    }
+
+    /// The curve at the current hour, `None` when it has no keyframes.
+    pub fn sample(&self) -> Option<SkyKeyframe> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        let (from, to) = match self.keyframes.iter().position(|key| key.hour > self.hour) {
+            Some(next) if next > 0 => (&self.keyframes[next - 1], &self.keyframes[next]),
+            _ => (last, first),
+        };
+        let span = (to.hour - from.hour).rem_euclid(HOURS_PER_DAY);
+        let elapsed = (self.hour - from.hour).rem_euclid(HOURS_PER_DAY);
+        let t = if span > 0.0 { elapsed / span } else { 0.0 };
+        Some(SkyKeyframe {
+            hour: self.hour,
+            ..from.lerp(to, t)
+        })
+    }
+
+    pub fn sky(&self) -> Option<Sky> {
+        self.sample().map(|key| Sky {
+            hour: key.hour,
+            ambient: key.ambient,
+            zenith: key.zenith,
+            horizon: key.horizon,
+            ev100: key.ev100,
+        })
+    }
+}
+
+/// Moonlit nights, orange sunrises and sunsets and a white noon under a blue sky, with the exposure following the
+/// light. The sun's illuminance is zero as it crosses the horizon, where the light switches
+/// between the sun and the moon.
+fn default_keyframes() -> Vec<SkyKeyframe> {
+    let night = SkyKeyframe {
+        hour: 0.0,
+        sun_color: [0.6, 0.7, 1.0],
+        illuminance: 0.25,
+        ambient: [0.02, 0.025, 0.05],
+        zenith: [0.002, 0.003, 0.008],
+        horizon: [0.01, 0.012, 0.025],
+        ev100: 0.0,
+    };
+    let horizon = |hour| SkyKeyframe {
+        hour,
+        sun_color: color_temperature(2000.0),
+        illuminance: 0.0,
+        ambient: [0.12, 0.08, 0.07],
+        zenith: [0.08, 0.1, 0.2],
+        horizon: [0.9, 0.45, 0.2],
+        ev100: 6.0,
+    };
+    let morning = |hour| SkyKeyframe {
+        hour,
+        sun_color: color_temperature(3500.0),
+        illuminance: 10_000.0,
+        ambient: [0.12, 0.13, 0.16],
+        zenith: [0.2, 0.35, 0.7],
+        horizon: [0.6, 0.65, 0.75],
+        ev100: 13.0,
+    };
+    vec![
+        night,
+        SkyKeyframe { hour: 5.5, ..night },
+        horizon(6.0),
+        morning(8.0),
+        SkyKeyframe {
+            hour: 12.0,
+            sun_color: color_temperature(5500.0),
+            illuminance: 60_000.0,
+            ambient: [0.15, 0.17, 0.2],
+            zenith: [0.15, 0.3, 0.75],
+            horizon: [0.6, 0.7, 0.85],
+            ev100: 15.0,
+        },
+        morning(16.0),
+        horizon(18.0),
+        SkyKeyframe {
+            hour: 18.5,
+            ..night
+        },
+    ]
+}
+
+/// Moves the `TimeOfDay` resource on and lights the scene with it. Does nothing without one.
+pub struct TimeOfDaySystem;
+
+impl<'a> System<'a> for TimeOfDaySystem {
+    type SystemData = (
+        Option<Write<'a, TimeOfDay>>,
+        WriteStorage<'a, DirectionalLightComponent>,
+    );
+
+    fn run(&mut self, (time, mut lights): Self::SystemData) {
+        profile_scope!("time of day");
+        let Some(mut time) = time else {
+            return;
+        };
+        time.advance(FIXED_TIME_STEP);
+        let Some(key) = time.sample() else {
+            return;
+        };
+        let direction = time.light_direction();
+        for light in (&mut lights).join() {
+            light.direction = direction;
+            light.color = key.sun_color;
+            light.illuminance = key.illuminance;
+        }
+    }
+}
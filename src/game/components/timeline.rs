@@ -0,0 +1,412 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::Context;
+use cgmath::{Deg, Quaternion, Vector3, VectorSpace, Zero};
+use serde::{Deserialize, Serialize};
+use specs::{
+    world::EntitiesRes, Entities, Entity, ReadExpect, System, World, WorldExt, Write, WriteStorage,
+};
+
+use crate::game::simulation::FIXED_TIME_STEP;
+
+use super::{
+    camera::Camera,
+    transform::{update_transform, Transform},
+    tween::Easing,
+    ActiveCamera,
+};
+
+/// A value a track passes through. The track eases into each key from the one before it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Keyframe<T> {
+    /// Seconds from the start of the timeline.
+    pub time: f32,
+    pub value: T,
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+impl<T> Keyframe<T> {
+    pub fn new(time: f32, value: T) -> Self {
+        Keyframe {
+            time,
+            value,
+            easing: Easing::default(),
+        }
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+}
+
+/// Where a camera track puts the active camera.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CameraKey {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    /// Field of view in degrees, left as it is when `None`.
+    #[serde(default)]
+    pub fov: Option<f32>,
+}
+
+/// Keys animating one thing over a timeline, sorted by time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Track {
+    /// Moves the `ActiveCamera`, stopping it from drifting on its own velocity.
+    Camera(Vec<Keyframe<CameraKey>>),
+    /// Moves the `Transform` of the entity with this id.
+    Transform {
+        entity: u32,
+        keys: Vec<Keyframe<Transform>>,
+    },
+}
+
+/// A named moment on a timeline, sent out as a `TimelineEvent` when playback passes it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cue {
+    pub time: f32,
+    pub name: String,
+}
+
+/// Tracks and cues sequenced over time, e.g. for a cutscene. Built in code or loaded from a
+/// TOML file, and played by name through the `Timelines` resource.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Timeline {
+    #[serde(default)]
+    pub tracks: Vec<Track>,
+    #[serde(default)]
+    pub cues: Vec<Cue>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        let mut timeline: Timeline =
+            toml::from_str(&text).with_context(|| format!("parsing {:?}", path))?;
+        timeline.sort();
+        Ok(timeline)
+    }
+
+    pub fn with_camera(mut self, keys: Vec<Keyframe<CameraKey>>) -> Self {
+        self.tracks.push(Track::Camera(keys));
+        self.sort();
+        self
+    }
+
+    pub fn with_transform(mut self, entity: u32, keys: Vec<Keyframe<Transform>>) -> Self {
+        self.tracks.push(Track::Transform { entity, keys });
+        self.sort();
+        self
+    }
+
+    pub fn with_cue(mut self, time: f32, name: &str) -> Self {
+        self.cues.push(Cue {
+            time,
+            name: name.to_string(),
+        });
+        self.sort();
+        self
+    }
+
+    /// Seconds until the last key or cue.
+    pub fn duration(&self) -> f32 {
+        let keys = self.tracks.iter().filter_map(|track| match track {
+            Track::Camera(keys) => keys.last().map(|key| key.time),
+            Track::Transform { keys, .. } => keys.last().map(|key| key.time),
+        });
+        let cues = self.cues.iter().map(|cue| cue.time);
+        keys.chain(cues).fold(0.0, f32::max)
+    }
+
+    fn sort(&mut self) {
+        for track in &mut self.tracks {
+            match track {
+                Track::Camera(keys) => keys.sort_by(|a, b| a.time.total_cmp(&b.time)),
+                Track::Transform { keys, .. } => keys.sort_by(|a, b| a.time.total_cmp(&b.time)),
+            }
+        }
+        self.cues.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    /// Puts everything the tracks move where they are at `time`.
+    fn pose(
+        &self,
+        time: f32,
+        active_camera: Option<Entity>,
+        entities: &EntitiesRes,
+        cameras: &mut WriteStorage<Camera>,
+        transforms: &mut WriteStorage<Transform>,
+    ) {
+        for track in &self.tracks {
+            match track {
+                Track::Camera(keys) => {
+                    let camera = active_camera.and_then(|entity| cameras.get_mut(entity));
+                    if let (Some(camera), Some(key)) = (camera, sample(keys, time)) {
+                        camera.position = key.position;
+                        camera.rotation = key.rotation;
+                        if let Some(fov) = key.fov {
+                            camera.fov = Deg(fov);
+                        }
+                        camera.velocity = Vector3::zero();
+                        camera.y_velocity = 0.0;
+                    }
+                }
+                Track::Transform { entity, keys } => {
+                    if let Some(pose) = sample(keys, time) {
+                        update_transform(transforms, entities.entity(*entity), pose);
+                    }
+                }
+            }
+        }
+    }
+}
+
+trait Blend {
+    fn blend(&self, other: &Self, amount: f32) -> Self;
+}
+
+impl Blend for CameraKey {
+    fn blend(&self, other: &CameraKey, amount: f32) -> CameraKey {
+        CameraKey {
+            position: self.position.lerp(other.position, amount),
+            rotation: self.rotation.slerp(other.rotation, amount),
+            fov: match (self.fov, other.fov) {
+                (Some(from), Some(to)) => Some(from + (to - from) * amount),
+                (from, to) => to.or(from),
+            },
+        }
+    }
+}
+
+impl Blend for Transform {
+    fn blend(&self, other: &Transform, amount: f32) -> Transform {
+        self.interpolate(other, amount)
+    }
+}
+
+/// The value of sorted `keys` at `time`, holding the first and last keys before and after them.
+fn sample<T: Blend + Copy>(keys: &[Keyframe<T>], time: f32) -> Option<T> {
+    let next = keys.partition_point(|key| key.time <= time);
+    match (
+        next.checked_sub(1).map(|index| &keys[index]),
+        keys.get(next),
+    ) {
+        (Some(previous), Some(next)) => {
+            let amount = next
+                .easing
+                .apply((time - previous.time) / (next.time - previous.time));
+            Some(previous.value.blend(&next.value, amount))
+        }
+        (Some(key), None) | (None, Some(key)) => Some(key.value),
+        (None, None) => None,
+    }
+}
+
+/// Sent once playback passes a timeline's cue.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEvent {
+    pub timeline: String,
+    pub cue: String,
+}
+
+/// Where a playing timeline is, e.g. for the editor to show.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineStatus {
+    pub name: String,
+    pub time: f32,
+    pub duration: f32,
+    pub looping: bool,
+    pub paused: bool,
+}
+
+/// A change to the timelines playing in the simulation world.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TimelineCommand {
+    /// Plays `timeline` from its start, replacing whatever was playing under `name`.
+    Play {
+        name: String,
+        timeline: Timeline,
+        looping: bool,
+    },
+    /// Stops where it is, leaving everything it moved there.
+    Stop(String),
+    Pause {
+        name: String,
+        paused: bool,
+    },
+    /// Jumps to `time` and poses the tracks there at once, so it can be scrubbed while the
+    /// simulation is paused. Cues jumped over aren't sent.
+    Seek {
+        name: String,
+        time: f32,
+    },
+}
+
+struct Playback {
+    timeline: Timeline,
+    time: f32,
+    looping: bool,
+    paused: bool,
+}
+
+impl Playback {
+    /// Moves on `seconds`, calling `fire` with every cue passed on the way.
+    fn advance(&mut self, seconds: f32, mut fire: impl FnMut(&Cue)) {
+        // Its cues at the end were sent when it got there, e.g. before it was paused
+        if self.finished() {
+            return;
+        }
+        let duration = self.timeline.duration();
+        let from = self.time;
+        let to = from + seconds;
+        let ended = to >= duration;
+        for cue in &self.timeline.cues {
+            if cue.time >= from && (cue.time < to || ended) {
+                fire(cue);
+            }
+        }
+
+        self.time = if ended && self.loops() {
+            let time = to % duration;
+            for cue in self.timeline.cues.iter().filter(|cue| cue.time < time) {
+                fire(cue);
+            }
+            time
+        } else {
+            to.min(duration)
+        };
+    }
+
+    fn finished(&self) -> bool {
+        !self.loops() && self.time >= self.timeline.duration()
+    }
+
+    /// A timeline with no length plays once even when looping, since it would otherwise send
+    /// all its cues every tick.
+    fn loops(&self) -> bool {
+        self.looping && self.timeline.duration() > 0.0
+    }
+}
+
+/// The timelines playing in the simulation world, by name, stepped every fixed update by
+/// `TimelineSystem`. Change them with `TimelineCommand`s.
+#[derive(Default)]
+pub struct Timelines {
+    playing: BTreeMap<String, Playback>,
+    /// Cues passed since the simulation last took them.
+    fired: Vec<TimelineEvent>,
+}
+
+impl Timelines {
+    pub fn status(&self) -> Vec<TimelineStatus> {
+        self.playing
+            .iter()
+            .map(|(name, playback)| TimelineStatus {
+                name: name.clone(),
+                time: playback.time,
+                duration: playback.timeline.duration(),
+                looping: playback.looping,
+                paused: playback.paused,
+            })
+            .collect()
+    }
+
+    pub fn is_playing(&self, name: &str) -> bool {
+        self.playing.contains_key(name)
+    }
+
+    pub(crate) fn take_events(&mut self) -> Vec<TimelineEvent> {
+        std::mem::take(&mut self.fired)
+    }
+}
+
+/// Applies `command` to the world's `Timelines`, logging commands for timelines that aren't
+/// playing.
+pub(crate) fn apply_timeline_command(world: &World, command: TimelineCommand) {
+    let mut timelines = world.write_resource::<Timelines>();
+    match command {
+        TimelineCommand::Play {
+            name,
+            timeline,
+            looping,
+        } => {
+            let playback = Playback {
+                timeline,
+                time: 0.0,
+                looping,
+                paused: false,
+            };
+            timelines.playing.insert(name, playback);
+        }
+        TimelineCommand::Stop(name) => {
+            if timelines.playing.remove(&name).is_none() {
+                log::warn!("can't stop timeline {:?}, it isn't playing", name);
+            }
+        }
+        TimelineCommand::Pause { name, paused } => match timelines.playing.get_mut(&name) {
+            Some(playback) => playback.paused = paused,
+            None => log::warn!("can't pause timeline {:?}, it isn't playing", name),
+        },
+        TimelineCommand::Seek { name, time } => match timelines.playing.get_mut(&name) {
+            Some(playback) => {
+                playback.time = time.clamp(0.0, playback.timeline.duration());
+                let active_camera = world.try_fetch::<ActiveCamera>().map(|active| active.0);
+                playback.timeline.pose(
+                    playback.time,
+                    active_camera,
+                    &world.entities(),
+                    &mut world.write_storage::<Camera>(),
+                    &mut world.write_storage::<Transform>(),
+                );
+            }
+            None => log::warn!("can't seek timeline {:?}, it isn't playing", name),
+        },
+    }
+}
+
+/// Steps the playing timelines, posing what their tracks move and sending their cues. Finished
+/// timelines are removed, leaving everything where they ended, unless they're paused there.
+pub struct TimelineSystem;
+
+impl<'a> System<'a> for TimelineSystem {
+    type SystemData = (
+        Entities<'a>,
+        Option<ReadExpect<'a, ActiveCamera>>,
+        Write<'a, Timelines>,
+        WriteStorage<'a, Camera>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, active_camera, mut timelines, mut cameras, mut transforms): Self::SystemData,
+    ) {
+        profile_scope!("timelines");
+        let active_camera = active_camera.map(|active| active.0);
+        let Timelines { playing, fired } = &mut *timelines;
+        playing.retain(|name, playback| {
+            if !playback.paused {
+                playback.advance(FIXED_TIME_STEP, |cue| {
+                    fired.push(TimelineEvent {
+                        timeline: name.clone(),
+                        cue: cue.name.clone(),
+                    })
+                });
+            }
+            playback.timeline.pose(
+                playback.time,
+                active_camera,
+                &entities,
+                &mut cameras,
+                &mut transforms,
+            );
+            playback.paused || !playback.finished()
+        });
+    }
+}
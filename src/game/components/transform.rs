@@ -1,15 +1,20 @@
-use cgmath::{Deg, Matrix4, Quaternion, Rotation3, Vector3};
-use specs::{Component, System, VecStorage, WriteStorage};
+use cgmath::{Deg, Matrix4, Quaternion, Rotation3, Vector3, VectorSpace};
+use serde::{Deserialize, Serialize};
+use specs::{Component, Entity, FlaggedStorage, ReadStorage, System, VecStorage, WriteStorage};
 
 #[repr(C)]
-#[derive(Component, Debug, Clone, Copy)]
-#[storage(VecStorage)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Transform {
     pub position: Vector3<f32>,
     pub rotation: Quaternion<f32>,
     pub scale: Vector3<f32>,
 }
 
+// Flagged so the spatial index only updates the entities that moved
+impl Component for Transform {
+    type Storage = FlaggedStorage<Self, VecStorage<Self>>;
+}
+
 impl Transform {
     pub fn model(&self) -> Matrix4<f32> {
         let scale_matrix = Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
@@ -17,25 +22,60 @@ impl Transform {
         let translation_matrix = Matrix4::from_translation(self.position);
         translation_matrix * rotation_matrix * scale_matrix
     }
+
+    /// Blends from `self` at 0 to `other` at 1.
+    pub fn interpolate(&self, other: &Transform, amount: f32) -> Transform {
+        Transform {
+            position: self.position.lerp(other.position, amount),
+            rotation: self.rotation.slerp(other.rotation, amount),
+            scale: self.scale.lerp(other.scale, amount),
+        }
+    }
+
+    /// The inverse transpose of the model matrix without its translation, which keeps normals
+    /// perpendicular to their surfaces under non-uniform scale. Axes scaled to zero flatten
+    /// normals rather than dividing by zero.
+    pub fn normal_matrix(&self) -> Matrix4<f32> {
+        let inverse_scale = self.scale.map(|s| if s == 0.0 { 0.0 } else { 1.0 / s });
+        let inverse_scale_matrix =
+            Matrix4::from_nonuniform_scale(inverse_scale.x, inverse_scale.y, inverse_scale.z);
+        Matrix4::from(self.rotation) * inverse_scale_matrix
+    }
 }
 
+/// Stores `transform` for `entity` unless it's already there. Writing through `get_mut` flags
+/// the entity as moved even when nothing changed, and the spatial index updates every flagged
+/// entity.
+pub fn update_transform(
+    transforms: &mut WriteStorage<Transform>,
+    entity: Entity,
+    transform: Transform,
+) {
+    if transforms.get(entity) == Some(&transform) {
+        return;
+    }
+    if let Some(current) = transforms.get_mut(entity) {
+        *current = transform;
+    }
+}
+
+/// Turns the entity about the y axis by this many degrees every fixed update.
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Spin(pub f32);
+
+/// Turns the entities with a `Spin`. Only they're flagged as moved.
 pub struct TransformSystem;
 
 impl<'a> System<'a> for TransformSystem {
-    type SystemData = WriteStorage<'a, Transform>;
+    type SystemData = (ReadStorage<'a, Spin>, WriteStorage<'a, Transform>);
 
-    fn run(&mut self, mut transforms: Self::SystemData) {
+    fn run(&mut self, (spins, mut transforms): Self::SystemData) {
+        profile_scope!("transforms");
         use specs::Join;
-        for transform in (&mut transforms).join() {
-            // TODO: this is hardcoded for now.
-            // Eventually have some controller component or system
-            let axis = Vector3::new(0.0, 1.0, 0.0);
-            let angle = Deg(0.5);
-            let new_rotation = Quaternion::from_axis_angle(axis, angle);
-
-            let rot = transform.rotation * new_rotation;
-
-            transform.rotation = rot;
+        for (spin, transform) in (&spins, &mut transforms).join() {
+            let turn = Quaternion::from_axis_angle(Vector3::unit_y(), Deg(spin.0));
+            transform.rotation = transform.rotation * turn;
         }
     }
 }
@@ -0,0 +1,231 @@
+use std::f32::consts::PI;
+
+use cgmath::{Quaternion, Vector3, VectorSpace};
+use serde::{Deserialize, Serialize};
+use specs::{Component, DenseVecStorage, Entities, Join, System, WriteStorage};
+
+use crate::{game::simulation::FIXED_TIME_STEP, renderer::Tint};
+
+use super::{
+    render::TintComponent,
+    transform::{update_transform, Transform},
+};
+
+/// How a tween's progress maps onto its value, from 0 at the start to 1 at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineInOut,
+    /// Overshoots the end a little before settling.
+    BackOut,
+    ElasticOut,
+    BounceOut,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::QuadInOut if t < 0.5 => 2.0 * t * t,
+            Easing::QuadInOut => 1.0 - (-2.0 * t + 2.0).powi(2) / 2.0,
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Easing::CubicInOut if t < 0.5 => 4.0 * t * t * t,
+            Easing::CubicInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+            Easing::SineInOut => -((PI * t).cos() - 1.0) / 2.0,
+            Easing::BackOut => {
+                const OVERSHOOT: f32 = 1.70158;
+                1.0 + (OVERSHOOT + 1.0) * (t - 1.0).powi(3) + OVERSHOOT * (t - 1.0).powi(2)
+            }
+            Easing::ElasticOut if t == 0.0 || t == 1.0 => t,
+            Easing::ElasticOut => {
+                2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+            }
+            Easing::BounceOut => bounce_out(t),
+        }
+    }
+}
+
+fn bounce_out(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+
+/// The property a tween animates, and the values it runs between.
+#[derive(Debug, Clone, Copy)]
+pub enum TweenTarget {
+    Position {
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+    },
+    Rotation {
+        from: Quaternion<f32>,
+        to: Quaternion<f32>,
+    },
+    Scale {
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+    },
+    /// Written to the entity's `TintComponent`, which is added when missing.
+    Color { from: Tint, to: Tint },
+}
+
+/// What a tween does once it reaches its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TweenLoop {
+    /// Stops at the end and removes itself.
+    #[default]
+    Once,
+    /// Starts over from the beginning.
+    Repeat,
+    /// Runs back to the beginning, then forward again.
+    PingPong,
+}
+
+/// Animates one property of an entity over `duration` seconds, stepped every fixed update by
+/// `TweenSystem`.
+#[derive(Component, Debug, Clone)]
+#[storage(DenseVecStorage)]
+pub struct Tween {
+    pub target: TweenTarget,
+    pub easing: Easing,
+    pub duration: f32,
+    pub looping: TweenLoop,
+    elapsed: f32,
+}
+
+impl Tween {
+    pub fn new(target: TweenTarget, duration: f32) -> Self {
+        Tween {
+            target,
+            easing: Easing::default(),
+            duration,
+            looping: TweenLoop::default(),
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+
+    pub fn with_looping(mut self, looping: TweenLoop) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// How far through the tween is, from 0 to 1 and, while ping-ponging, back again.
+    pub fn progress(&self) -> f32 {
+        if self.duration <= 0.0 {
+            return 1.0;
+        }
+        let cycles = self.elapsed / self.duration;
+        match self.looping {
+            TweenLoop::Once => cycles.min(1.0),
+            TweenLoop::Repeat => cycles.fract(),
+            TweenLoop::PingPong => 1.0 - (cycles % 2.0 - 1.0).abs(),
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.looping == TweenLoop::Once && self.elapsed >= self.duration
+    }
+
+    fn advance(&mut self, seconds: f32) {
+        self.elapsed += seconds;
+        // Keeps looping tweens from losing precision as they run
+        if self.looping != TweenLoop::Once && self.duration > 0.0 {
+            self.elapsed %= 2.0 * self.duration;
+        }
+    }
+}
+
+pub struct TweenSystem;
+
+impl<'a> System<'a> for TweenSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Tween>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, TintComponent>,
+    );
+
+    fn run(&mut self, (entities, mut tweens, mut transforms, mut tints): Self::SystemData) {
+        profile_scope!("tweens");
+        let mut finished = vec![];
+        for (entity, tween) in (&entities, &mut tweens).join() {
+            tween.advance(FIXED_TIME_STEP);
+            let amount = tween.easing.apply(tween.progress());
+
+            let transform = transforms.get(entity).copied();
+            match tween.target {
+                TweenTarget::Position { from, to } => {
+                    if let Some(transform) = transform {
+                        let position = from.lerp(to, amount);
+                        let moved = Transform {
+                            position,
+                            ..transform
+                        };
+                        update_transform(&mut transforms, entity, moved);
+                    }
+                }
+                TweenTarget::Rotation { from, to } => {
+                    if let Some(transform) = transform {
+                        let rotation = from.slerp(to, amount);
+                        let moved = Transform {
+                            rotation,
+                            ..transform
+                        };
+                        update_transform(&mut transforms, entity, moved);
+                    }
+                }
+                TweenTarget::Scale { from, to } => {
+                    if let Some(transform) = transform {
+                        let scale = from.lerp(to, amount);
+                        update_transform(&mut transforms, entity, Transform { scale, ..transform });
+                    }
+                }
+                TweenTarget::Color { from, to } => {
+                    let tint = Tint {
+                        color: Vector3::from(from.color)
+                            .lerp(Vector3::from(to.color), amount)
+                            .into(),
+                        amount: from.amount + (to.amount - from.amount) * amount,
+                    };
+                    let _ = tints.insert(entity, TintComponent(tint));
+                }
+            }
+
+            if tween.finished() {
+                finished.push(entity);
+            }
+        }
+
+        for entity in finished {
+            tweens.remove(entity);
+        }
+    }
+}
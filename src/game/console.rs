@@ -0,0 +1,415 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, bail, Context};
+use cgmath::{Rotation, Vector3};
+use specs::{World, WorldExt};
+use winit::keyboard::KeyCode;
+
+use crate::renderer::HudPanel;
+
+use super::{
+    clipboard::Clipboard,
+    components::{
+        transform::Transform, Camera, FrameAnalysisResource, FrameStatsResource, NavMeshDebug,
+        RenderFeature, RenderFeatureChanges, UiScale,
+    },
+    inspect::Edit,
+    simulation::Simulation,
+};
+
+/// Output lines kept on screen.
+const OUTPUT_LINES: usize = 16;
+const HISTORY_LINES: usize = 64;
+/// How far in front of the camera `spawn` puts prefabs.
+const SPAWN_DISTANCE: f32 = 5.0;
+
+/// What a console command can reach while it runs.
+pub struct CommandContext<'a> {
+    world: &'a mut World,
+    simulation: &'a Simulation,
+    output: Vec<String>,
+}
+
+impl<'a> CommandContext<'a> {
+    /// The render world, holding the resources the render system reads.
+    pub fn world(&mut self) -> &mut World {
+        self.world
+    }
+
+    /// Adds a line to the console's output.
+    pub fn print(&mut self, line: impl Into<String>) {
+        self.output.push(line.into());
+    }
+
+    /// Spawns the prefab called `prefab` at `transform` on the simulation's next tick.
+    pub fn spawn(&mut self, prefab: &str, transform: Transform) -> anyhow::Result<()> {
+        if !self
+            .simulation
+            .prefab_names()
+            .iter()
+            .any(|name| name == prefab)
+        {
+            bail!("no prefab called {}", prefab);
+        }
+        self.simulation.edit(Edit::Spawn {
+            prefab: prefab.to_string(),
+            transform,
+        });
+        Ok(())
+    }
+
+    fn camera(&self) -> Option<Camera> {
+        self.simulation.snapshots().latest().1.camera
+    }
+}
+
+type Command = Box<dyn FnMut(&mut CommandContext, &[&str]) -> anyhow::Result<()>>;
+
+/// A line of text input over the game, running registered commands by name. Opened with the
+/// backtick key and drawn as a HUD panel.
+pub struct Console {
+    open: bool,
+    input: String,
+    output: VecDeque<String>,
+    history: Vec<String>,
+    /// The history entry being edited, `None` for a new line.
+    history_index: Option<usize>,
+    commands: BTreeMap<String, Command>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Console {
+            open: false,
+            input: String::new(),
+            output: VecDeque::new(),
+            history: vec![],
+            history_index: None,
+            commands: BTreeMap::new(),
+        }
+        .with_builtins()
+    }
+}
+
+impl Console {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    /// Makes `command` run for lines starting with `name`, given the rest of the line's words.
+    /// Replaces any command already called `name`. Errors are printed to the console.
+    pub fn register_command<F>(&mut self, name: &str, command: F)
+    where
+        F: FnMut(&mut CommandContext, &[&str]) -> anyhow::Result<()> + 'static,
+    {
+        self.commands
+            .insert(name.to_ascii_lowercase(), Box::new(command));
+    }
+
+    /// Edits the input line with a pressed `key` and the `text` it typed, returning a submitted
+    /// line when `key` was Enter.
+    pub fn key(&mut self, key: KeyCode, text: Option<&str>) -> Option<String> {
+        match key {
+            KeyCode::Enter | KeyCode::NumpadEnter => {
+                self.history_index = None;
+                return Some(std::mem::take(&mut self.input));
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Escape => self.open = false,
+            KeyCode::ArrowUp | KeyCode::ArrowDown if !self.history.is_empty() => {
+                let last = self.history.len() - 1;
+                self.history_index = match (key, self.history_index) {
+                    (KeyCode::ArrowUp, None) => Some(last),
+                    (KeyCode::ArrowUp, Some(index)) => Some(index.saturating_sub(1)),
+                    (_, Some(index)) if index < last => Some(index + 1),
+                    _ => None,
+                };
+                self.input = self
+                    .history_index
+                    .map(|index| self.history[index].clone())
+                    .unwrap_or_default();
+            }
+            _ => {
+                let typed = text.unwrap_or_default().chars().filter(|c| !c.is_control());
+                self.input.extend(typed);
+            }
+        }
+        None
+    }
+
+    /// Adds `text` composed by the input method to the input line.
+    pub fn text(&mut self, text: &str) {
+        self.input.extend(text.chars().filter(|c| !c.is_control()));
+    }
+
+    /// Handles Ctrl+C, Ctrl+X and Ctrl+V on the input line, returning whether `key` was one of
+    /// them. Only the first line of pasted text is kept.
+    pub fn clipboard_key(&mut self, key: KeyCode, clipboard: &mut Clipboard) -> bool {
+        match key {
+            KeyCode::KeyC => clipboard.set_text(&self.input),
+            KeyCode::KeyX => {
+                clipboard.set_text(&self.input);
+                self.input.clear();
+                self.history_index = None;
+            }
+            KeyCode::KeyV => {
+                if let Some(text) = clipboard.text() {
+                    let line = text.lines().next().unwrap_or_default();
+                    self.input.extend(line.chars().filter(|c| !c.is_control()));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Runs `line` against the render `world` and `simulation`, echoing it and the command's
+    /// output.
+    pub fn execute(&mut self, line: &str, world: &mut World, simulation: &Simulation) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        self.print(format!("> {}", line));
+        if self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+            if self.history.len() > HISTORY_LINES {
+                self.history.remove(0);
+            }
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let name = words[0].to_ascii_lowercase();
+        if name == "help" {
+            let names: Vec<&str> = self.commands.keys().map(String::as_str).collect();
+            self.print(format!("commands: help {}", names.join(" ")));
+            return;
+        }
+        let Some(command) = self.commands.get_mut(&name) else {
+            self.print(format!("unknown command {}", name));
+            return;
+        };
+
+        let mut context = CommandContext {
+            world,
+            simulation,
+            output: vec![],
+        };
+        let result = command(&mut context, &words[1..]);
+        for line in std::mem::take(&mut context.output) {
+            self.print(line);
+        }
+        if let Err(e) = result {
+            self.print(format!("error: {}", e));
+        }
+    }
+
+    /// The console's panel, `None` while closed.
+    pub fn panel(&self) -> Option<HudPanel> {
+        self.open.then(|| {
+            let mut lines: Vec<String> = self.output.iter().cloned().collect();
+            lines.push(format!("{}_", self.input));
+            HudPanel {
+                title: "CONSOLE".to_string(),
+                highlighted: Some(lines.len() - 1),
+                lines,
+            }
+        })
+    }
+
+    fn print(&mut self, line: String) {
+        log::info!("console: {}", line);
+        self.output.push_back(line);
+        while self.output.len() > OUTPUT_LINES {
+            self.output.pop_front();
+        }
+    }
+
+    fn with_builtins(mut self) -> Self {
+        self.register_command("render", |context, args| {
+            let names: Vec<&str> = RenderFeature::ALL.iter().map(|f| f.name()).collect();
+            let usage = || anyhow!("usage: render <{}> <on/off>", names.join("/"));
+            let (Some(feature), Some(state)) = (args.first(), args.get(1)) else {
+                return Err(usage());
+            };
+            let feature = RenderFeature::from_name(feature).ok_or_else(usage)?;
+            let enabled = match *state {
+                "on" | "1" | "true" => true,
+                "off" | "0" | "false" => false,
+                _ => return Err(usage()),
+            };
+            context
+                .world()
+                .write_resource::<RenderFeatureChanges>()
+                .0
+                .push((feature, enabled));
+            context.print(format!("{} {}", feature.name(), state));
+            Ok(())
+        });
+
+        self.register_command("ui_scale", |context, args| {
+            let usage = || anyhow!("usage: ui_scale <scale/auto>");
+            let forced = match args.first() {
+                Some(&"auto") => None,
+                Some(scale) => Some(scale.parse::<f32>().map_err(|_| usage())?.max(0.1)),
+                None => return Err(usage()),
+            };
+            let scale = {
+                let mut ui_scale = context.world().write_resource::<UiScale>();
+                ui_scale.forced = forced;
+                ui_scale.get()
+            };
+            context.print(format!("ui scale {}", scale));
+            Ok(())
+        });
+
+        self.register_command("navmesh", |context, args| {
+            let visible = match args.first().copied() {
+                Some("on" | "1" | "true") => true,
+                Some("off" | "0" | "false") => false,
+                _ => bail!("usage: navmesh <on/off>"),
+            };
+            if visible
+                && context
+                    .world()
+                    .read_resource::<NavMeshDebug>()
+                    .lines
+                    .is_empty()
+            {
+                bail!("no navmesh loaded");
+            }
+            context.world().write_resource::<NavMeshDebug>().visible = visible;
+            context.print(format!("navmesh {}", args[0]));
+            Ok(())
+        });
+
+        self.register_command("save", |context, args| {
+            let path = args.first().ok_or_else(|| anyhow!("usage: save <path>"))?;
+            context.simulation.save(*path);
+            context.print(format!("saving {}", path));
+            Ok(())
+        });
+
+        self.register_command("load", |context, args| {
+            let path = args.first().ok_or_else(|| anyhow!("usage: load <path>"))?;
+            context.simulation.load(*path);
+            context.print(format!("loading {}", path));
+            Ok(())
+        });
+
+        self.register_command("replay", |context, args| {
+            match args {
+                ["record"] => context.simulation.record_replay(),
+                ["stop"] => context.simulation.stop_replay(None),
+                ["stop", path] => context.simulation.stop_replay(Some(PathBuf::from(path))),
+                ["play", path] => context.simulation.play_replay(*path),
+                ["status"] => {
+                    let status = context.simulation.replay_status();
+                    context.print(format!("{:?}", status));
+                    return Ok(());
+                }
+                _ => bail!("usage: replay <record/stop [path]/play <path>/status>"),
+            }
+            context.print(format!("replay {}", args.join(" ")));
+            Ok(())
+        });
+
+        self.register_command("time", |context, args| {
+            let paused = match args {
+                [] => {
+                    let sky = context.simulation.snapshots().latest().1.sky;
+                    let sky = sky.context("no day-night cycle, see time_of_day in the config")?;
+                    context.print(format!("{:.2}", sky.hour));
+                    return Ok(());
+                }
+                ["pause"] => Some(true),
+                ["resume"] => Some(false),
+                _ => None,
+            };
+            let hour = match paused {
+                Some(_) => None,
+                None => Some(
+                    args[0]
+                        .parse::<f32>()
+                        .map_err(|_| anyhow!("usage: time [hour/pause/resume]"))?,
+                ),
+            };
+            context.simulation.edit(Edit::TimeOfDay { hour, paused });
+            context.print(format!("time {}", args.join(" ")));
+            Ok(())
+        });
+
+        self.register_command("spawn", |context, args| {
+            let Some(prefab) = args.first() else {
+                let names = context.simulation.prefab_names().join(" ");
+                bail!("usage: spawn <prefab>, one of: {}", names);
+            };
+            let camera = context.camera().context("no active camera")?;
+            let forward = camera.rotation.rotate_vector(Vector3::unit_z());
+            context.spawn(
+                prefab,
+                Transform {
+                    position: camera.position + forward * SPAWN_DISTANCE,
+                    rotation: [1.0, 0.0, 0.0, 0.0].into(),
+                    scale: [1.0, 1.0, 1.0].into(),
+                },
+            )?;
+            context.print(format!("spawned {}", prefab));
+            Ok(())
+        });
+
+        self.register_command("analyze", |context, args| {
+            let overlay = match args.first().copied() {
+                None => true,
+                Some("off" | "0" | "false") => false,
+                _ => bail!("usage: analyze [off]"),
+            };
+            {
+                let mut analysis = context.world().write_resource::<FrameAnalysisResource>();
+                analysis.requested |= overlay;
+                analysis.overlay = overlay;
+            }
+            context.print(if overlay {
+                "analyzing next frame"
+            } else {
+                "analysis overlay off"
+            });
+            Ok(())
+        });
+
+        self.register_command("stats", |context, _| {
+            let stats = context.world().read_resource::<FrameStatsResource>().0;
+            let entities = context.simulation.snapshots().latest().1.entities;
+            let ms = |time: Option<f32>| {
+                time.map(|time| format!("{:.2}", time))
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            context.print(format!(
+                "cpu {:.2} ms, gpu {} ms, scene {} ms, post {} ms",
+                stats.cpu_time_ms,
+                ms(stats.gpu_time_ms),
+                ms(stats.gpu_scene_ms),
+                ms(stats.gpu_post_process_ms)
+            ));
+            context.print(format!(
+                "draws {}, triangles {}, occluded {}, entities {}",
+                stats.draw_calls, stats.triangles, stats.occluded_objects, entities
+            ));
+            if let Some(vram) = stats.vram_bytes {
+                context.print(format!("vram {:.1} mb", vram as f64 / (1024.0 * 1024.0)));
+            }
+            Ok(())
+        });
+
+        self
+    }
+}
@@ -1,100 +1,535 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, MutexGuard,
+    },
+    time::Duration,
+};
 
 use anyhow::Context;
-use gilrs::Axis;
-use specs::{Builder, Dispatcher, DispatcherBuilder, World, WorldExt};
-use tracing::{span, Level};
+use cgmath::Vector3;
+use gilrs::{Axis, GamepadId};
+use rayon::ThreadPool;
+use specs::{
+    shrev::{EventChannel, ReaderId},
+    Builder, Dispatcher, DispatcherBuilder, World, WorldExt,
+};
 use winit::{
-    dpi::PhysicalSize, event::Event, event_loop::EventLoop, keyboard::KeyCode, window::WindowId,
+    dpi::PhysicalSize,
+    event::{Event, WindowEvent},
+    event_loop::EventLoop,
+    keyboard::{KeyCode, ModifiersState},
+    window::WindowId,
 };
 
 use crate::{
-    renderer::{CUBE_INDICES, CUBE_VERTICES},
-    Renderer,
+    assets::AssetServer,
+    renderer::{color_temperature, Billboard, Material, UiPrimitive, CUBE_INDICES, CUBE_VERTICES},
+    renderer::{AnalysisReport, FoliageId, FoliageLayer, FrameStats, RenderLayers, Wind},
+    EngineConfig, Renderer,
 };
 
 use super::{
+    ai::{BehaviorTreeSystem, SteeringSystem},
+    clipboard::Clipboard,
     components::{
-        render::{RenderSystem, Renderable},
-        transform::{Transform, TransformSystem},
-        ActiveCamera, BlendFactor, Camera, CameraSystem, CurrentWindowId, CurrentWindowSize,
-        CursorCaptured, ResizeEvents,
+        render::{
+            BillboardComponent, DirectionalLightComponent, PointLightComponent, PortalComponent,
+            ReflectionProbeComponent, RenderSystem, Renderable, SelectedTag, TintComponent,
+        },
+        transform::{Spin, Transform, TransformSystem},
+        ActiveCamera, Bounds, BudgetExceeded, BudgetSystem, BudgetWarnings, Camera, CameraEffect,
+        CameraEffectsSystem, CameraSystem, CurrentCursorMode, CurrentWindowId, CurrentWindowSize,
+        DebugLine, DebugLines, EntityKey, FoliageChange, FoliageChanges, FrameAnalysisResource,
+        FrameCaptureRequest, FrameStatsResource, HudPanels, HudVisible, MaterialEdits,
+        MaterialLibrary, NavMeshDebug, ParticleEmitter, ParticleSystem, PerformanceBudget,
+        PickResource, ProbeRecaptureRequest, ResizeEvents, Sky, SpatialIndex, SpatialIndexSystem,
+        TextInputActive, TimeOfDay, TimeOfDaySystem, Timeline, TimelineCommand, TimelineEvent,
+        TimelineStatus, TimelineSystem, TweenSystem, UiAtlasRequest, UiPrimitives, UiScale,
+        ViewModel, VisibilityResource,
     },
+    console::{CommandContext, Console},
+    editor::Editor,
+    gizmo::{Gizmo, GizmoMode},
     input::{
-        ActionDescriptor, ActionKind, ActionMap, ActionState, GamepadSource, InputSystem,
-        MouseAxis, MouseSource, Source,
+        ActionDescriptor, ActionKind, ActionMap, ActionState, CursorBinding, CursorMode,
+        GamepadEvent, GamepadInfo, GamepadSource, InputSystem, MouseAxis, MouseSource, PlayerIndex,
+        Rumble, Source, SystemMouseButton,
     },
+    inspect::{Edit, Inspector},
+    jobs::JobSystem,
+    marker::{MarkerId, Markers, ScreenAnchor, WorldMarker},
+    nav::{NavMesh, NavMeshResource, PathRequest, PathResult, PathfindingSystem},
+    prefab::{PrefabFile, Prefabs},
+    replay::ReplayStatus,
+    save::{SaveRegistry, Saved},
+    simulation::{Simulation, SimulationInput},
+    stress::StressScene,
+    voxel::{Voxel, VoxelMeshQueue, VoxelSystem},
 };
 
-#[derive(Default)]
+#[cfg(feature = "net")]
+use super::{
+    codec::ComponentCodecs,
+    net::{engine_replication, NetClient, NetServer, NetSession, Replicated},
+};
+#[cfg(feature = "net")]
+use std::sync::Mutex;
+
+#[cfg(feature = "lua")]
+use super::script::{ScriptComponents, ScriptRunner};
+
+/// Ticks between network snapshots, 30 a second.
+#[cfg(feature = "net")]
+const NET_SEND_INTERVAL_TICKS: u64 = 8;
+
+/// Bright enough to light the demo scene at the default exposure.
+const DEMO_LIGHT_LUMENS: f32 = 15000.0;
+/// Degrees the demo cubes turn each fixed update.
+const DEMO_SPIN: f32 = 0.5;
+
+/// The first player's action states this fixed update, including the actions released since
+/// the last one.
+#[derive(Debug, Clone, Default)]
 pub struct InputStateResource(pub HashMap<String, ActionState>);
 
+impl InputStateResource {
+    /// The state of `action` while it's active.
+    pub fn get(&self, action: &str) -> Option<&ActionState> {
+        self.0.get(action).filter(|state| state.active)
+    }
+
+    pub fn active(&self, action: &str) -> bool {
+        self.get(action).is_some()
+    }
+
+    pub fn value(&self, action: &str) -> Option<f32> {
+        self.get(action).and_then(|state| state.value)
+    }
+
+    pub fn just_pressed(&self, action: &str) -> bool {
+        self.0.get(action).is_some_and(|state| state.just_pressed)
+    }
+
+    pub fn just_released(&self, action: &str) -> bool {
+        self.0.get(action).is_some_and(|state| state.just_released)
+    }
+
+    /// Whether `action` was pressed or repeated while held this fixed update, for menus that
+    /// step through items.
+    pub fn repeated(&self, action: &str) -> bool {
+        self.0.get(action).is_some_and(|state| state.repeated)
+    }
+
+    /// How long `action` has been held, zero while it isn't.
+    pub fn held_for(&self, action: &str) -> Duration {
+        self.get(action)
+            .map_or(Duration::ZERO, |state| state.held_for)
+    }
+
+    pub fn repeat_count(&self, action: &str) -> u32 {
+        self.get(action).map_or(0, |state| state.repeat_count)
+    }
+}
+
+/// Raw mouse motion since the last fixed update, zero on the ticks after the first of a frame.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MouseDeltaResource(pub (f32, f32));
+
+/// Every local player's action states, the first player's being those in `InputStateResource`.
+#[derive(Default)]
+pub struct PlayerInputStateResource(pub HashMap<PlayerIndex, InputStateResource>);
+
 pub struct GameContext {
     input_system: InputSystem,
     world: World,
-    fixed_update_dispatcher: Dispatcher<'static, 'static>, //TODO: this is probably wrong
-    render_dispatcher: Dispatcher<'static, 'static>,       // TODO: this is probably wrong
+    simulation: Simulation,
+    render_dispatcher: Dispatcher<'static, 'static>, // TODO: this is probably wrong
+    editor: Editor,
+    gizmo: Gizmo,
+    markers: Markers,
+    console: Console,
+    clipboard: Clipboard,
+    modifiers: ModifiersState,
+    /// Set while the window is out of focus and the focus behavior pauses the simulation.
+    focus_paused: bool,
+    /// Set by the asset server when the config's `key_bindings` file changed on disk.
+    bindings_changed: Arc<AtomicBool>,
+    /// Components sent to network clients, shared with the simulation's session.
+    #[cfg(feature = "net")]
+    replication: Arc<Mutex<ComponentCodecs>>,
+}
+
+/// The systems run every fixed update, on the simulation thread. Lua scripts are run from
+/// `scripts` when it's given.
+#[cfg_attr(not(feature = "lua"), allow(unused_variables))]
+fn fixed_update_dispatcher(
+    pool: Arc<ThreadPool>,
+    scripts: Option<&Path>,
+) -> Dispatcher<'static, 'static> {
+    let builder = DispatcherBuilder::new()
+        .with_pool(pool)
+        .with(TransformSystem, "transform_system", &[])
+        .with(CameraSystem, "camera_system", &[])
+        .with(CameraEffectsSystem, "camera_effects_system", &[])
+        .with(TweenSystem, "tween_system", &["transform_system"])
+        .with(
+            TimelineSystem,
+            "timeline_system",
+            &["transform_system", "camera_system", "tween_system"],
+        )
+        .with(TimeOfDaySystem, "time_of_day_system", &[])
+        .with(VoxelSystem::default(), "voxel_system", &[])
+        .with(
+            ParticleSystem,
+            "particle_system",
+            &["transform_system", "tween_system"],
+        )
+        .with(
+            SteeringSystem,
+            "steering_system",
+            &["transform_system", "tween_system"],
+        )
+        .with(
+            SpatialIndexSystem::default(),
+            "spatial_index_system",
+            &[
+                "transform_system",
+                "tween_system",
+                "steering_system",
+                "timeline_system",
+                "voxel_system",
+            ],
+        )
+        .with(
+            PathfindingSystem::default(),
+            "pathfinding_system",
+            &["transform_system", "tween_system"],
+        )
+        .with_thread_local(BehaviorTreeSystem);
+    #[cfg(feature = "lua")]
+    let builder = match scripts {
+        Some(scripts) => builder.with_thread_local(ScriptRunner::new(
+            scripts,
+            ScriptComponents::default().with_engine_components(),
+        )),
+        None => builder,
+    };
+    builder.build()
 }
 
 impl GameContext {
-    pub fn new(event_loop: &EventLoop<()>) -> anyhow::Result<Self> {
-        let mut renderer = Renderer::new(event_loop)?;
+    pub fn new(event_loop: &EventLoop<()>, config: EngineConfig) -> anyhow::Result<Self> {
+        let mut renderer = Renderer::new(event_loop, config.renderer_config())?;
         let extent_physical_size = renderer.window_size().context("getting window size")?;
         let extent: [f32; 2] = extent_physical_size.into();
 
         let window_id = renderer.window_id();
 
+        let jobs = JobSystem::new(None).context("creating job system")?;
+
         let mut world = World::new();
 
+        world.insert(jobs.clone());
+        world.insert(config.clone());
         world.insert(ResizeEvents(false));
         world.insert(CurrentWindowSize(Some(extent_physical_size)));
         world.insert(CurrentWindowId(window_id));
-        world.insert(InputStateResource(HashMap::new()));
-        world.insert(CursorCaptured(Some(false)));
+        world.insert(CurrentCursorMode(CursorMode::Free));
+        world.insert(TextInputActive(false));
+        world.insert(EventChannel::<GamepadEvent>::new());
+        world.insert(EventChannel::<TimelineEvent>::new());
+        world.insert(UiScale {
+            scale_factor: renderer.scale_factor().unwrap_or(1.0) as f32,
+            forced: config.ui_scale,
+        });
+
+        let mesh_id = renderer.create_mesh(CUBE_VERTICES.to_vec(), CUBE_INDICES.to_vec())?;
+        let mut assets = AssetServer::new(&config.asset_root)
+            .map_err(|e| log::warn!("assets won't be reloaded: {:#}", e))
+            .ok();
+        let metal_path = config.asset_root.join("materials/metal.ron");
+        let metal = match &mut assets {
+            Some(assets) if metal_path.exists() => {
+                assets.load_material(&mut renderer, &metal_path)?
+            }
+            _ => renderer.create_material(&Material {
+                metallic: 1.0,
+                roughness: 0.3,
+                ..Default::default()
+            })?,
+        };
 
-        let mesh_id = renderer.create_mesh(CUBE_VERTICES.into(), CUBE_INDICES.into())?;
+        // Meshed on the simulation's job pool, uploaded by the render system
+        let voxel_meshes = VoxelMeshQueue::default();
+        world.insert(voxel_meshes.clone());
 
-        let mut fixed_update_dispatcher = DispatcherBuilder::new()
-            .with(TransformSystem, "transform_system", &[])
-            .with(CameraSystem, "camera_system", &[])
-            .build();
+        // Simulated entities live in their own world, which moves onto the simulation thread
+        let mut sim_world = World::new();
+        sim_world.insert(voxel_meshes);
+        sim_world.insert(CurrentWindowSize(Some(extent_physical_size)));
+        sim_world.insert(InputStateResource(HashMap::new()));
+        sim_world.insert(PlayerInputStateResource(HashMap::new()));
+        sim_world.insert(MouseDeltaResource::default());
+        sim_world.insert(jobs.clone());
+        sim_world.insert(config.clone());
+        fixed_update_dispatcher(jobs.pool().clone(), None).setup(&mut sim_world);
+        sim_world.register::<Renderable>();
+        sim_world.register::<TintComponent>();
+        sim_world.register::<SelectedTag>();
+        sim_world.register::<PointLightComponent>();
+        sim_world.register::<DirectionalLightComponent>();
+        sim_world.register::<BillboardComponent>();
+        sim_world.register::<PortalComponent>();
+        sim_world.register::<ReflectionProbeComponent>();
+        sim_world.register::<Bounds>();
+        sim_world.register::<ViewModel>();
+        sim_world.insert(SpatialIndex::default());
+        sim_world.register::<PathRequest>();
+        sim_world.register::<PathResult>();
+        sim_world.register::<Saved>();
+        #[cfg(feature = "net")]
+        sim_world.register::<Replicated>();
 
-        let mut render_dispatcher = DispatcherBuilder::new()
-            .with_thread_local(RenderSystem::new(renderer))
-            .build();
+        let navmesh = config.navmesh.as_ref().and_then(|path| {
+            NavMesh::load(&config.asset_root.join(path))
+                .map_err(|e| log::error!("{:#}, pathfinding is off", e))
+                .ok()
+                .map(Arc::new)
+        });
+        world.insert(NavMeshDebug {
+            visible: false,
+            lines: navmesh
+                .as_ref()
+                .map(|navmesh| navmesh.debug_lines())
+                .unwrap_or_default(),
+        });
+        sim_world.insert(NavMeshResource(navmesh));
+        if let Some(hour) = config.time_of_day {
+            sim_world.insert(TimeOfDay::new(hour).with_day_length(config.day_length));
+        }
 
-        fixed_update_dispatcher.setup(&mut world);
-        render_dispatcher.setup(&mut world);
+        #[cfg(feature = "net")]
+        let replication = engine_replication();
+        #[cfg(feature = "net")]
+        let net = match (&config.net_listen, &config.net_connect) {
+            (Some(address), _) => Some(NetSession::Server(
+                NetServer::bind(
+                    address.as_str(),
+                    replication.clone(),
+                    NET_SEND_INTERVAL_TICKS,
+                )
+                .context("starting server")?,
+            )),
+            (None, Some(address)) => Some(NetSession::Client(
+                NetClient::connect(
+                    address.as_str(),
+                    replication.clone(),
+                    NET_SEND_INTERVAL_TICKS,
+                )
+                .context("starting client")?,
+            )),
+            (None, None) => None,
+        };
+        let scripts = config.asset_root.join("scripts");
+        #[cfg(not(feature = "lua"))]
+        if scripts.is_dir() {
+            log::warn!("ignoring {:?}, scripting needs the lua feature", scripts);
+        }
+
+        #[cfg(not(feature = "net"))]
+        if config.net_listen.is_some() || config.net_connect.is_some() {
+            log::warn!("ignoring net_listen and net_connect, networking needs the net feature");
+        }
 
-        world
+        sim_world
             .create_entity()
             .with(Transform {
                 position: [0.0, 0.0, 0.0].into(),
                 rotation: [1.0, 0.0, 0.0, 0.0].into(),
                 scale: [1.0, 1.0, 1.0].into(),
             })
-            .with(Renderable { mesh_id })
+            .with(Renderable {
+                mesh_id,
+                material: None,
+                layers: RenderLayers::DEFAULT,
+            })
+            .with(Bounds::cube(1.0))
+            .with(Spin(DEMO_SPIN))
             .build();
 
-        world
+        sim_world
             .create_entity()
             .with(Transform {
                 position: [5.0, 0.0, 0.0].into(),
                 rotation: [1.0, 0.0, 0.0, 0.0].into(),
                 scale: [1.0, 1.0, 1.0].into(),
             })
-            .with(Renderable { mesh_id })
+            .with(Renderable {
+                mesh_id,
+                material: Some(metal),
+                layers: RenderLayers::DEFAULT,
+            })
+            .with(Bounds::cube(1.0))
+            .with(Spin(DEMO_SPIN))
             .build();
 
-        let cam = world
+        for (position, color) in [
+            ([0.5, -0.5, -0.1], [1.0, 0.0, 0.0]),
+            ([-0.9, 0.2, -0.15], [0.0, 1.0, 0.0]),
+            ([0.0, 0.5, -0.05], [0.0, 0.0, 1.0]),
+        ] {
+            sim_world
+                .create_entity()
+                .with(Transform {
+                    position: position.into(),
+                    rotation: [1.0, 0.0, 0.0, 0.0].into(),
+                    scale: [1.0, 1.0, 1.0].into(),
+                })
+                .with(PointLightComponent {
+                    color,
+                    intensity: DEMO_LIGHT_LUMENS,
+                    radius: 5.0,
+                    ..Default::default()
+                })
+                .build();
+        }
+
+        sim_world
             .create_entity()
-            .with(Camera {
-                aspect_ratio: extent[0] / extent[1],
-                ..Default::default()
+            .with(DirectionalLightComponent {
+                direction: Vector3::new(0.2, -0.1, -0.7),
+                color: [1.0, 0.0, 0.0],
+                illuminance: 750.0,
+            })
+            .build();
+
+        let mut camera = Camera {
+            aspect_ratio: extent[0] / extent[1],
+            ..Default::default()
+        };
+        if let Some(size) = config.stress_scene {
+            let stress = StressScene::new(size);
+            let entities = stress.spawn(&mut sim_world, mesh_id, None);
+            log::info!("spawned a stress scene of {} entities", entities.len());
+            // Back the camera out of the grid
+            let half_extent = stress.half_extent();
+            camera.position = Vector3::new(0.0, 0.0, -(half_extent * 2.0 + 10.0));
+            camera.far = camera.far.max(half_extent * 4.0 + 20.0);
+        }
+        let cam = sim_world.create_entity().with(camera).build();
+
+        sim_world.insert(ActiveCamera(cam));
+
+        let mut prefabs = Prefabs::default()
+            .with_engine_components()
+            .register("cube", move |entity| {
+                entity
+                    .with(Renderable {
+                        mesh_id,
+                        material: None,
+                        layers: RenderLayers::DEFAULT,
+                    })
+                    .with(Bounds::cube(1.0))
+                    .with(Spin(DEMO_SPIN))
+            })
+            .register("metal_cube", move |entity| {
+                entity
+                    .with(Renderable {
+                        mesh_id,
+                        material: Some(metal),
+                        layers: RenderLayers::DEFAULT,
+                    })
+                    .with(Bounds::cube(1.0))
+                    .with(Spin(DEMO_SPIN))
+            })
+            .register("billboard", |entity| {
+                entity.with(BillboardComponent(Billboard::default()))
+            })
+            .register("mirror", |entity| {
+                entity.with(PortalComponent {
+                    destination: None,
+                    size: [2.0, 2.0],
+                })
+            })
+            .register("reflection_probe", |entity| {
+                entity.with(ReflectionProbeComponent {
+                    half_extents: Vector3::new(4.0, 3.0, 4.0),
+                })
+            })
+            .register("particles", |entity| {
+                entity.with(ParticleEmitter::default())
             })
+            .register("light", |entity| {
+                entity.with(PointLightComponent {
+                    color: color_temperature(2700.0),
+                    intensity: DEMO_LIGHT_LUMENS,
+                    radius: 5.0,
+                    ..Default::default()
+                })
+            });
+        // Prefab files are read from the asset root's prefabs directory
+        let prefab_paths: Vec<PathBuf> = fs::read_dir(config.asset_root.join("prefabs"))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        for path in &prefab_paths {
+            match PrefabFile::load(path) {
+                Ok(file) => prefabs.set_file(path, file),
+                Err(e) => log::warn!("{:#}", e),
+            }
+        }
+
+        let simulation = Simulation::spawn(
+            sim_world,
+            {
+                let pool = jobs.pool().clone();
+                move || fixed_update_dispatcher(pool, Some(&scripts))
+            },
+            Inspector::default().with_engine_components(),
+            prefabs,
+            SaveRegistry::default().with_engine_components(),
+            #[cfg(feature = "net")]
+            net,
+        )
+        .context("starting simulation")?;
+
+        let bindings_changed = Arc::new(AtomicBool::new(false));
+        if let Some(assets) = &mut assets {
+            for path in prefab_paths {
+                let prefab_files = simulation.prefab_files();
+                assets.on_change(&path, move |path| {
+                    prefab_files.send(path, PrefabFile::load(path)?);
+                    Ok(())
+                });
+            }
+            // The input system lives on this thread, so it loads them before the next update
+            if let Some(path) = &config.key_bindings {
+                let bindings_changed = bindings_changed.clone();
+                assets.on_change(path, move |_| {
+                    bindings_changed.store(true, Ordering::Release);
+                    Ok(())
+                });
+            }
+        }
+
+        let mut render_dispatcher = DispatcherBuilder::new()
+            .with_pool(jobs.pool().clone())
+            .with_thread_local(
+                RenderSystem::new(renderer, simulation.snapshots().clone(), assets)
+                    .with_retained_scene(config.retained_scene),
+            )
+            .with_thread_local(BudgetSystem::default())
             .build();
 
-        world.insert(ActiveCamera(cam));
+        render_dispatcher.setup(&mut world);
 
         world.write_resource::<ResizeEvents>().0 = true;
 
@@ -106,8 +541,11 @@ impl GameContext {
         let move_down_action = "move_down";
         let look_vertical_action = "look_vertical_action";
         let look_horizontal_action = "look_horizontal_action";
+        let capture_cursor_action = "capture_cursor";
+        let release_cursor_action = "release_cursor";
+        let toggle_cursor_action = "toggle_cursor";
 
-        let input_system = InputSystem::new()
+        let mut input_system = InputSystem::new()
             .add_action(
                 walk_forward_action,
                 ActionDescriptor {
@@ -156,6 +594,24 @@ impl GameContext {
                     kind: ActionKind::Button,
                 },
             )
+            .add_action(
+                capture_cursor_action,
+                ActionDescriptor {
+                    kind: ActionKind::Button,
+                },
+            )
+            .add_action(
+                release_cursor_action,
+                ActionDescriptor {
+                    kind: ActionKind::Button,
+                },
+            )
+            .add_action(
+                toggle_cursor_action,
+                ActionDescriptor {
+                    kind: ActionKind::Button,
+                },
+            )
             .add_action_map(
                 "main",
                 ActionMap::new()
@@ -192,42 +648,239 @@ impl GameContext {
                     .bind(
                         Source::Mouse(MouseSource::Move(MouseAxis::MouseX)),
                         look_horizontal_action,
-                    ),
-            );
+                    )
+                    .bind(
+                        Source::Mouse(MouseSource::Button(SystemMouseButton::Left)),
+                        capture_cursor_action,
+                    )
+                    .bind(
+                        Source::Mouse(MouseSource::Button(SystemMouseButton::Right)),
+                        release_cursor_action,
+                    )
+                    .bind(Source::Keyboard(KeyCode::Escape), release_cursor_action)
+                    .bind(Source::Keyboard(KeyCode::Tab), toggle_cursor_action),
+            )
+            .bind_cursor_action(capture_cursor_action, CursorBinding::Capture)
+            .bind_cursor_action(release_cursor_action, CursorBinding::Release)
+            .bind_cursor_action(toggle_cursor_action, CursorBinding::Toggle);
+        if let Some(path) = config.key_bindings.as_ref().filter(|path| path.exists()) {
+            if let Err(e) = input_system.load_bindings(path) {
+                log::warn!("{:#}, keeping the built-in key bindings", e);
+            }
+        }
 
         Ok(GameContext {
             world,
-            fixed_update_dispatcher,
+            simulation,
             render_dispatcher,
             input_system,
+            editor: Editor::default(),
+            gizmo: Gizmo::default(),
+            markers: Markers::default(),
+            console: Console::default(),
+            clipboard: Clipboard::default(),
+            modifiers: ModifiersState::empty(),
+            focus_paused: false,
+            bindings_changed,
+            #[cfg(feature = "net")]
+            replication,
         })
     }
 
-    pub fn process_winit_event(&mut self, event: &Event<()>, mouse_captured: bool) -> bool {
-        self.input_system.process_winit_event(event, mouse_captured)
+    pub fn process_winit_event(&mut self, event: &Event<()>) -> bool {
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::ScaleFactorChanged { scale_factor, .. },
+                ..
+            } => {
+                self.world.write_resource::<UiScale>().scale_factor = *scale_factor as f32;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(modifiers),
+                ..
+            } => {
+                self.modifiers = modifiers.state();
+            }
+            _ => (),
+        }
+        self.input_system.process_winit_event(event)
     }
 
+    /// Hands this frame's input to the simulation thread.
     pub fn pre_update(&mut self) {
+        if self.bindings_changed.swap(false, Ordering::Acquire) {
+            let path = self
+                .world
+                .read_resource::<EngineConfig>()
+                .key_bindings
+                .clone();
+            if let Some(path) = path {
+                log::info!("reloading key bindings {:?}", path);
+                if let Err(e) = self.input_system.load_bindings(&path) {
+                    log::warn!("{:#}, keeping the previous key bindings", e);
+                }
+            }
+        }
         self.input_system.update_gamepads();
-        self.world.insert(InputStateResource(
-            self.input_system.get_action_state_map().clone(),
-        ));
+        for (player, rumble) in self.simulation.take_rumbles() {
+            if let Err(e) = self.input_system.rumble(player, rumble) {
+                log::warn!("{:#}", e);
+            }
+        }
+        let gamepad_events = self.input_system.take_gamepad_events();
+        if !gamepad_events.is_empty() {
+            self.world
+                .write_resource::<EventChannel<GamepadEvent>>()
+                .iter_write(gamepad_events);
+        }
+        let timeline_events = self.simulation.take_timeline_events();
+        if !timeline_events.is_empty() {
+            self.world
+                .write_resource::<EventChannel<TimelineEvent>>()
+                .iter_write(timeline_events);
+        }
+        if self.editor.is_open() {
+            // Clicks belong to the gizmo and picking while the editor is open
+            self.input_system.set_cursor_mode(CursorMode::Free);
+            self.update_gizmo();
+            self.update_picking();
+        }
+        self.world.write_resource::<CurrentCursorMode>().0 = self.input_system.cursor_mode();
+        self.simulation.set_input(SimulationInput {
+            // Typing into the console doesn't move the camera
+            actions: if self.console.is_open() {
+                HashMap::new()
+            } else {
+                self.input_system.get_action_state_map().clone()
+            },
+            // Only the first player shares the keyboard with the console
+            players: self
+                .input_system
+                .all_player_action_states()
+                .into_iter()
+                .filter(|(player, _)| !self.console.is_open() || *player != PlayerIndex::ONE)
+                .collect(),
+            mouse_delta: if self.console.is_open() {
+                (0.0, 0.0)
+            } else {
+                self.input_system.frame_mouse_delta()
+            },
+            window_size: self.world.read_resource::<CurrentWindowSize>().0,
+            visibility: self.world.read_resource::<VisibilityResource>().clone(),
+            paused: self.editor.paused() || self.focus_paused,
+            editor: self.editor.input(),
+        });
         // I think we should clear out the action states after we've cloned them into the ECS Resource
         self.input_system.update();
     }
 
-    pub fn update(&mut self) {
-        let _span = span!(Level::INFO, "fixed_update").entered();
-        self.fixed_update_dispatcher.dispatch(&self.world);
-    }
-
-    pub fn render(&mut self, blending_factor: f32) -> anyhow::Result<()> {
-        let _span = span!(Level::INFO, "render").entered();
-        self.world.insert(BlendFactor(blending_factor));
+    pub fn render(&mut self) -> anyhow::Result<()> {
+        profile_scope!("render");
+        let (_, snapshot, _) = self.simulation.snapshots().latest();
+        self.world.write_resource::<TextInputActive>().0 = self.console.is_open();
+        let editor_panels = {
+            let mut library = self.world.write_resource::<MaterialLibrary>();
+            library.requested = self.editor.materials_open();
+            self.editor.panels(
+                snapshot.editor.as_ref(),
+                &snapshot.timelines,
+                &library.materials,
+            )
+        };
+        let analysis_panel = {
+            let analysis = self.world.read_resource::<FrameAnalysisResource>();
+            analysis
+                .report
+                .as_ref()
+                .filter(|_| analysis.overlay)
+                .map(AnalysisReport::panel)
+        };
+        let budget_panel = self.world.read_resource::<BudgetWarnings>().panel();
+        self.world.write_resource::<HudPanels>().0 = self
+            .console
+            .panel()
+            .into_iter()
+            .chain(budget_panel)
+            .chain(analysis_panel)
+            .chain(editor_panels)
+            .collect();
+        self.world.write_resource::<DebugLines>().0 = match (&snapshot.editor, snapshot.camera) {
+            (Some(view), Some(camera)) if self.editor.is_open() => view
+                .transform
+                .map(|transform| self.gizmo.lines(&transform, camera.position))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(from, to, color)| DebugLine { from, to, color })
+                .chain(snapshot.debug_lines.iter().copied())
+                .collect(),
+            _ => snapshot.debug_lines.clone(),
+        };
+        {
+            let navmesh = self.world.read_resource::<NavMeshDebug>();
+            if navmesh.visible {
+                self.world
+                    .write_resource::<DebugLines>()
+                    .0
+                    .extend_from_slice(&navmesh.lines);
+            }
+        }
         self.render_dispatcher.dispatch(&self.world);
         Ok(())
     }
 
+    /// Hovers and drags the inspected entity's gizmo with the cursor, queuing the dragged
+    /// transform as an edit.
+    fn update_gizmo(&mut self) {
+        let (_, snapshot, _) = self.simulation.snapshots().latest();
+        let (Some(view), Some(camera)) = (snapshot.editor.as_ref(), snapshot.camera) else {
+            return;
+        };
+        let (Some((entity, _)), Some(transform)) = (view.inspected.as_ref(), view.transform) else {
+            return;
+        };
+
+        let ray = self
+            .input_system
+            .cursor_position()
+            .zip(self.window_size())
+            .and_then(|(cursor, size)| {
+                camera.screen_to_world_ray(
+                    [cursor.0, cursor.1],
+                    [size.width as f32, size.height as f32],
+                )
+            });
+        let dragged = self.gizmo.update(
+            *entity,
+            &transform,
+            ray,
+            camera.position,
+            self.input_system.mouse_pressed(SystemMouseButton::Left),
+            self.input_system.mouse_held(SystemMouseButton::Left),
+        );
+        if let Some(transform) = dragged {
+            self.simulation.edit(Edit::Transform {
+                entity: *entity,
+                transform,
+            });
+        }
+    }
+
+    /// Picks the entity under the cursor when the scene is clicked anywhere but the gizmo, and
+    /// selects it once the pick has been read back. Clicking nothing clears the selection.
+    fn update_picking(&mut self) {
+        let mut pick = self.world.write_resource::<PickResource>();
+        if let Some(result) = pick.result.take() {
+            self.editor
+                .select(result.key.map(|key| EntityKey::from_bits(key).id()));
+        }
+        if self.input_system.mouse_pressed(SystemMouseButton::Left) && !self.gizmo.is_active() {
+            pick.requested = self
+                .input_system
+                .cursor_position()
+                .map(|cursor| [cursor.0, cursor.1]);
+        }
+    }
+
     pub fn resize(&mut self) -> anyhow::Result<()> {
         self.world.write_resource::<ResizeEvents>().0 = true;
         Ok(())
@@ -241,11 +894,470 @@ impl GameContext {
         self.world.read_resource::<CurrentWindowId>().0
     }
 
-    pub fn set_cursor_captured(&self) {
-        self.world.write_resource::<CursorCaptured>().0 = Some(true);
+    pub fn set_cursor_mode(&mut self, mode: CursorMode) {
+        self.input_system.set_cursor_mode(mode);
+        self.world.write_resource::<CurrentCursorMode>().0 = mode;
+    }
+
+    pub fn set_performance_budget(&mut self, budget: PerformanceBudget) {
+        self.world.insert(budget);
+    }
+
+    /// Records the CPU time of the frame that just finished, checked against the budget on the
+    /// next frame.
+    pub fn set_cpu_frame_time(&mut self, cpu_time_ms: f32) {
+        self.world
+            .write_resource::<FrameStatsResource>()
+            .0
+            .cpu_time_ms = cpu_time_ms;
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        self.world.read_resource::<FrameStatsResource>().0
+    }
+
+    pub fn visibility(&self) -> VisibilityResource {
+        self.world.read_resource::<VisibilityResource>().clone()
+    }
+
+    pub fn budget_warnings(&self) -> Vec<BudgetExceeded> {
+        self.world.read_resource::<BudgetWarnings>().0.clone()
+    }
+
+    pub fn subscribe_budget_events(&mut self) -> ReaderId<BudgetExceeded> {
+        self.world
+            .write_resource::<EventChannel<BudgetExceeded>>()
+            .register_reader()
+    }
+
+    pub fn read_budget_events(&self, reader: &mut ReaderId<BudgetExceeded>) -> Vec<BudgetExceeded> {
+        self.world
+            .read_resource::<EventChannel<BudgetExceeded>>()
+            .read(reader)
+            .copied()
+            .collect()
+    }
+
+    pub fn gamepads(&self) -> Vec<GamepadInfo> {
+        self.input_system.gamepads()
+    }
+
+    pub fn assign_gamepad(&mut self, id: GamepadId, player: Option<PlayerIndex>) {
+        self.input_system.assign_gamepad(id, player);
+    }
+
+    pub fn set_player_action_map(&mut self, player: PlayerIndex, name: &str) -> anyhow::Result<()> {
+        self.input_system.set_player_action_map(player, name)
+    }
+
+    pub fn rumble(&mut self, player: PlayerIndex, rumble: Rumble) -> anyhow::Result<()> {
+        self.input_system.rumble(player, rumble)
+    }
+
+    pub fn stop_rumble(&mut self, player: PlayerIndex) {
+        self.input_system.stop_rumble(player);
+    }
+
+    /// Pauses the simulation for the window losing focus, on top of the editor's pause.
+    pub fn set_focus_paused(&mut self, paused: bool) {
+        self.focus_paused = paused;
+    }
+
+    pub fn subscribe_gamepad_events(&mut self) -> ReaderId<GamepadEvent> {
+        self.world
+            .write_resource::<EventChannel<GamepadEvent>>()
+            .register_reader()
+    }
+
+    pub fn read_gamepad_events(&self, reader: &mut ReaderId<GamepadEvent>) -> Vec<GamepadEvent> {
+        self.world
+            .read_resource::<EventChannel<GamepadEvent>>()
+            .read(reader)
+            .cloned()
+            .collect()
+    }
+
+    pub fn request_frame_analysis(&mut self) {
+        self.world
+            .write_resource::<FrameAnalysisResource>()
+            .requested = true;
+    }
+
+    pub fn capture_frame(&mut self) {
+        self.world.write_resource::<FrameCaptureRequest>().0 = true;
+    }
+
+    /// Captures every reflection probe again over the next frames, e.g. after building or
+    /// tearing down something near them.
+    pub fn recapture_reflection_probes(&mut self) {
+        self.world.write_resource::<ProbeRecaptureRequest>().0 = true;
+    }
+
+    /// Sets off `effect` on the active camera on the next tick. Systems in the simulation can
+    /// add effects to a camera's `CameraEffects` themselves.
+    pub fn add_camera_effect(&self, effect: CameraEffect) {
+        self.simulation.edit(Edit::CameraEffect(effect));
+    }
+
+    /// Plays `timeline` from its start on the next tick, replacing whatever was playing under
+    /// `name`. Its cues come back as `TimelineEvent`s.
+    pub fn play_timeline(&self, name: &str, timeline: Timeline, looping: bool) {
+        self.simulation.edit(Edit::Timeline(TimelineCommand::Play {
+            name: name.to_string(),
+            timeline,
+            looping,
+        }));
+    }
+
+    pub fn stop_timeline(&self, name: &str) {
+        self.simulation
+            .edit(Edit::Timeline(TimelineCommand::Stop(name.to_string())));
+    }
+
+    pub fn pause_timeline(&self, name: &str, paused: bool) {
+        self.simulation.edit(Edit::Timeline(TimelineCommand::Pause {
+            name: name.to_string(),
+            paused,
+        }));
+    }
+
+    pub fn seek_timeline(&self, name: &str, time: f32) {
+        self.simulation.edit(Edit::Timeline(TimelineCommand::Seek {
+            name: name.to_string(),
+            time,
+        }));
+    }
+
+    /// The timelines playing as of the latest tick.
+    pub fn timelines(&self) -> Vec<TimelineStatus> {
+        self.simulation.snapshots().latest().1.timelines.clone()
+    }
+
+    pub fn subscribe_timeline_events(&mut self) -> ReaderId<TimelineEvent> {
+        self.world
+            .write_resource::<EventChannel<TimelineEvent>>()
+            .register_reader()
+    }
+
+    pub fn read_timeline_events(&self, reader: &mut ReaderId<TimelineEvent>) -> Vec<TimelineEvent> {
+        self.world
+            .read_resource::<EventChannel<TimelineEvent>>()
+            .read(reader)
+            .cloned()
+            .collect()
+    }
+
+    pub fn draw_ui(&mut self, primitive: UiPrimitive) {
+        self.world
+            .write_resource::<UiPrimitives>()
+            .0
+            .push(primitive);
+    }
+
+    pub fn set_ui_atlas(&mut self, path: &Path) {
+        self.world.write_resource::<UiAtlasRequest>().0 = Some(path.to_path_buf());
+    }
+
+    pub fn set_foliage(&mut self, id: FoliageId, layer: FoliageLayer, texture: Option<&Path>) {
+        self.foliage_change(FoliageChange::Set(
+            id,
+            layer,
+            texture.map(Path::to_path_buf),
+        ));
+    }
+
+    pub fn remove_foliage(&mut self, id: FoliageId) {
+        self.foliage_change(FoliageChange::Remove(id));
+    }
+
+    pub fn set_wind(&mut self, wind: Wind) {
+        self.foliage_change(FoliageChange::Wind(wind));
+    }
+
+    fn foliage_change(&mut self, change: FoliageChange) {
+        self.world.write_resource::<FoliageChanges>().0.push(change);
+    }
+
+    pub fn add_marker(&mut self, marker: WorldMarker) -> MarkerId {
+        self.markers.add(marker)
+    }
+
+    pub fn remove_marker(&mut self, id: MarkerId) {
+        self.markers.remove(id);
+    }
+
+    /// Projects the markers as the latest snapshots would be drawn now, in logical pixels.
+    pub fn marker_anchors(&mut self) -> Vec<(MarkerId, ScreenAnchor)> {
+        let Some(size) = self.window_size() else {
+            return vec![];
+        };
+        let scale = self.ui_scale();
+        let (previous, current, blend) = self.simulation.snapshots().latest();
+        self.markers.update(
+            &previous,
+            &current,
+            blend,
+            [size.width as f32 / scale, size.height as f32 / scale],
+        );
+        self.markers.anchors().to_vec()
+    }
+
+    /// Screen pixels per logical pixel of the UI passes.
+    pub fn ui_scale(&self) -> f32 {
+        self.world.read_resource::<UiScale>().get()
+    }
+
+    /// Forces the UI scale, or follows the window's scale factor again when `None`.
+    pub fn set_ui_scale(&mut self, scale: Option<f32>) {
+        self.world.write_resource::<UiScale>().forced = scale.map(|scale| scale.max(0.1));
+    }
+
+    pub fn toggle_hud(&mut self) {
+        let mut hud_visible = self.world.write_resource::<HudVisible>();
+        hud_visible.0 = !hud_visible.0;
+    }
+
+    /// Handles the console's keys, returning whether `key` was one of them. The backtick key
+    /// opens and closes the console, which takes every other key while it's open.
+    pub fn console_key(&mut self, key: KeyCode, text: Option<&str>, repeat: bool) -> bool {
+        if key == KeyCode::Backquote {
+            if !repeat {
+                self.console.toggle();
+            }
+            return true;
+        }
+        if !self.console.is_open() {
+            return false;
+        }
+        // Cmd on macOS
+        if (self.modifiers.control_key() || self.modifiers.super_key())
+            && self.console.clipboard_key(key, &mut self.clipboard)
+        {
+            return true;
+        }
+        if let Some(line) = self.console.key(key, text) {
+            self.console
+                .execute(&line, &mut self.world, &self.simulation);
+        }
+        true
+    }
+
+    /// Types `text` composed by the input method into the console, returning whether it's open.
+    pub fn console_text(&mut self, text: &str) -> bool {
+        if !self.console.is_open() {
+            return false;
+        }
+        self.console.text(text);
+        true
+    }
+
+    /// The keys and buttons bound to `action_name`, named for the current keyboard layout.
+    pub fn binding_names(&self, action_name: &str) -> Vec<String> {
+        self.input_system.binding_names(action_name)
+    }
+
+    pub fn listen_for_input(&mut self) {
+        self.input_system.listen_for_input();
+    }
+
+    pub fn cancel_listening(&mut self) {
+        self.input_system.cancel_listening();
+    }
+
+    pub fn take_captured_input(&mut self) -> Option<Source> {
+        self.input_system.take_captured_input()
+    }
+
+    /// Binds `source` to `action_name` in place of `previous`, saving every binding to the
+    /// config's `key_bindings` file when it has one.
+    pub fn rebind(
+        &mut self,
+        action_name: &str,
+        previous: Option<&Source>,
+        source: Source,
+    ) -> anyhow::Result<()> {
+        self.input_system.rebind(action_name, previous, source)?;
+        let path = self
+            .world
+            .read_resource::<EngineConfig>()
+            .key_bindings
+            .clone();
+        if let Some(path) = path {
+            self.input_system.save_bindings(&path)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_game(&self, path: &Path) {
+        self.simulation.save(path);
+    }
+
+    pub fn load_game(&self, path: &Path) {
+        self.simulation.load(path);
+    }
+
+    pub fn record_replay(&self) {
+        self.simulation.record_replay();
+    }
+
+    pub fn play_replay(&self, path: &Path) {
+        self.simulation.play_replay(path);
+    }
+
+    pub fn stop_replay(&self, path: Option<&Path>) {
+        self.simulation.stop_replay(path.map(Path::to_path_buf));
+    }
+
+    pub fn replay_status(&self) -> ReplayStatus {
+        self.simulation.replay_status()
+    }
+
+    pub fn set_time_of_day(&self, hour: f32) {
+        self.simulation.edit(Edit::TimeOfDay {
+            hour: Some(hour),
+            paused: None,
+        });
+    }
+
+    pub fn set_time_of_day_paused(&self, paused: bool) {
+        self.simulation.edit(Edit::TimeOfDay {
+            hour: None,
+            paused: Some(paused),
+        });
+    }
+
+    pub fn sky(&self) -> Option<Sky> {
+        self.simulation.snapshots().latest().1.sky
+    }
+
+    pub fn fill_voxels(&self, min: [i32; 3], max: [i32; 3], voxel: Voxel) {
+        self.simulation.edit(Edit::Voxels { min, max, voxel });
+    }
+
+    pub fn sculpt_voxels(&self, center: Vector3<f32>, radius: f32, amount: f32, material: Voxel) {
+        self.simulation.edit(Edit::Sculpt {
+            center: center.into(),
+            radius,
+            amount,
+            material,
+        });
+    }
+
+    pub fn save_registry(&self) -> MutexGuard<'_, SaveRegistry> {
+        self.simulation.save_registry()
+    }
+
+    #[cfg(feature = "net")]
+    pub fn replication_registry(&self) -> MutexGuard<'_, ComponentCodecs> {
+        self.replication
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn register_command<F>(&mut self, name: &str, command: F)
+    where
+        F: FnMut(&mut CommandContext, &[&str]) -> anyhow::Result<()> + 'static,
+    {
+        self.console.register_command(name, command);
+    }
+
+    /// Handles the editor's keys, returning whether `key` was one of them. F1 opens and closes
+    /// the editor, the rest only work while it's open.
+    pub fn editor_key(&mut self, key: KeyCode, repeat: bool) -> bool {
+        if key == KeyCode::F1 {
+            if !repeat {
+                self.editor.toggle();
+            }
+            return true;
+        }
+        if !self.editor.is_open() {
+            return false;
+        }
+        if key == KeyCode::F6 {
+            if !repeat {
+                self.editor.toggle_materials();
+            }
+            return true;
+        }
+        if self.editor.materials_open() {
+            return self.material_key(key, repeat);
+        }
+
+        let (_, snapshot, _) = self.simulation.snapshots().latest();
+        let Some(view) = snapshot.editor.as_ref() else {
+            // The simulation hasn't captured a view since the editor opened
+            return false;
+        };
+        match key {
+            KeyCode::F5 if !repeat => self.editor.toggle_paused(),
+            KeyCode::BracketLeft => self.editor.select_entity(view, -1),
+            KeyCode::BracketRight => self.editor.select_entity(view, 1),
+            KeyCode::PageUp => self.editor.select_field(view, -1),
+            KeyCode::PageDown => self.editor.select_field(view, 1),
+            KeyCode::Minus | KeyCode::Equal => {
+                let steps = if key == KeyCode::Minus { -1.0 } else { 1.0 };
+                if let Some(edit) = self.editor.nudge(view, steps) {
+                    self.simulation.edit(Edit::Field(edit));
+                }
+            }
+            KeyCode::Digit1 => self.gizmo.set_mode(GizmoMode::Translate),
+            KeyCode::Digit2 => self.gizmo.set_mode(GizmoMode::Rotate),
+            KeyCode::Digit3 => self.gizmo.set_mode(GizmoMode::Scale),
+            KeyCode::Semicolon => self.editor.select_timeline(&snapshot.timelines, 1),
+            KeyCode::Comma | KeyCode::Period | KeyCode::Slash => {
+                let command = match key {
+                    KeyCode::Comma => self.editor.scrub(&snapshot.timelines, -1.0),
+                    KeyCode::Period => self.editor.scrub(&snapshot.timelines, 1.0),
+                    _ => self.editor.toggle_timeline(&snapshot.timelines),
+                };
+                if let Some(command) = command {
+                    self.simulation.edit(Edit::Timeline(command));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// The editor's keys in the materials mode.
+    fn material_key(&mut self, key: KeyCode, repeat: bool) -> bool {
+        let materials = self
+            .world
+            .read_resource::<MaterialLibrary>()
+            .materials
+            .clone();
+        let edit = match key {
+            KeyCode::BracketLeft => {
+                self.editor.select_material(&materials, -1);
+                None
+            }
+            KeyCode::BracketRight => {
+                self.editor.select_material(&materials, 1);
+                None
+            }
+            KeyCode::PageUp => {
+                self.editor.select_material_field(&materials, -1);
+                None
+            }
+            KeyCode::PageDown => {
+                self.editor.select_material_field(&materials, 1);
+                None
+            }
+            KeyCode::Minus => self.editor.nudge_material(&materials, -1.0),
+            KeyCode::Equal => self.editor.nudge_material(&materials, 1.0),
+            KeyCode::F7 if !repeat => self.editor.save_material(&materials),
+            _ => return false,
+        };
+        if let Some(edit) = edit {
+            self.world.write_resource::<MaterialEdits>().0.push(edit);
+        }
+        true
     }
 
-    pub fn set_cursor_released(&self) {
-        self.world.write_resource::<CursorCaptured>().0 = Some(false);
+    pub fn take_frame_analysis_report(&mut self) -> Option<AnalysisReport> {
+        self.world
+            .write_resource::<FrameAnalysisResource>()
+            .report
+            .take()
     }
 }
@@ -0,0 +1,319 @@
+use crate::renderer::HudPanel;
+
+use super::{
+    components::{MaterialEdit, MaterialEntry, TimelineCommand, TimelineStatus},
+    inspect::{EditorView, Field, FieldEdit},
+    simulation::EditorInput,
+};
+
+/// Hierarchy rows shown at once, scrolled to keep the selection in view.
+const HIERARCHY_ROWS: usize = 30;
+/// Seconds one scrub moves a timeline.
+const TIMELINE_STEP: f32 = 0.1;
+
+/// A keyboard driven editor over the simulation world: a hierarchy of its entities, an inspector
+/// for the selected entity's components and play/pause of the fixed update. Drawn as HUD panels,
+/// and fed by the editor view the simulation captures while the editor is open. Playing timelines
+/// are listed too, and the selected one can be paused and scrubbed to preview it. The materials
+/// mode lists the renderer's materials instead, for tweaking them live and saving them back to
+/// their files.
+#[derive(Debug, Default)]
+pub struct Editor {
+    open: bool,
+    paused: bool,
+    selected: Option<u32>,
+    /// Index of the highlighted field, counted across all of the inspected components.
+    field: usize,
+    /// Index of the selected timeline, by name.
+    timeline: usize,
+    /// Whether the materials replace the hierarchy and inspector.
+    materials: bool,
+    material: usize,
+    material_field: usize,
+}
+
+impl Editor {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    /// Only pauses the simulation while the editor is open.
+    pub fn toggle_paused(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.open && self.paused
+    }
+
+    pub fn toggle_materials(&mut self) {
+        self.materials = !self.materials;
+    }
+
+    pub fn materials_open(&self) -> bool {
+        self.open && self.materials
+    }
+
+    /// What the simulation has to capture and outline, `None` while closed.
+    pub fn input(&self) -> Option<EditorInput> {
+        self.open.then_some(EditorInput {
+            selected: self.selected,
+        })
+    }
+
+    /// Moves the selection `delta` rows down the hierarchy, wrapping around.
+    pub fn select_entity(&mut self, view: &EditorView, delta: i32) {
+        if view.entities.is_empty() {
+            self.selected = None;
+            return;
+        }
+        let count = view.entities.len() as i32;
+        let index = match self.selected_row(view) {
+            Some(index) => (index as i32 + delta).rem_euclid(count),
+            None if delta < 0 => count - 1,
+            None => 0,
+        };
+        self.selected = Some(view.entities[index as usize].0);
+        self.field = 0;
+    }
+
+    /// Selects `entity` directly, e.g. when it was clicked in the scene, or clears the selection.
+    pub fn select(&mut self, entity: Option<u32>) {
+        if self.selected != entity {
+            self.selected = entity;
+            self.field = 0;
+        }
+    }
+
+    /// Moves the highlight `delta` fields down the inspector, wrapping around.
+    pub fn select_field(&mut self, view: &EditorView, delta: i32) {
+        let count = inspected_fields(view).count() as i32;
+        if count > 0 {
+            self.field = (self.field as i32 + delta).rem_euclid(count) as usize;
+        }
+    }
+
+    /// An edit moving the highlighted field `steps` of its step size, if an entity is inspected.
+    pub fn nudge(&self, view: &EditorView, steps: f32) -> Option<FieldEdit> {
+        let (entity, _) = view.inspected.as_ref()?;
+        let (component, field) = inspected_fields(view).nth(self.field)?;
+        Some(FieldEdit {
+            entity: *entity,
+            component,
+            field: field.name,
+            value: field.value + field.step * steps,
+        })
+    }
+
+    /// Moves the material selection `delta` rows down, wrapping around.
+    pub fn select_material(&mut self, materials: &[MaterialEntry], delta: i32) {
+        if !materials.is_empty() {
+            let count = materials.len() as i32;
+            self.material = (self.material as i32 + delta).rem_euclid(count) as usize;
+            self.material_field = 0;
+        }
+    }
+
+    /// Moves the highlight `delta` fields down the selected material, wrapping around.
+    pub fn select_material_field(&mut self, materials: &[MaterialEntry], delta: i32) {
+        let count = self
+            .selected_material(materials)
+            .map_or(0, |m| m.fields.len()) as i32;
+        if count > 0 {
+            self.material_field = (self.material_field as i32 + delta).rem_euclid(count) as usize;
+        }
+    }
+
+    /// An edit moving the highlighted material field `steps` of its step size.
+    pub fn nudge_material(&self, materials: &[MaterialEntry], steps: f32) -> Option<MaterialEdit> {
+        let material = self.selected_material(materials)?;
+        let field = material.fields.get(self.material_field)?;
+        Some(MaterialEdit::Field {
+            material: material.id,
+            field: field.name,
+            value: field.value + field.step * steps,
+        })
+    }
+
+    /// Saves the selected material, if it was loaded from a file.
+    pub fn save_material(&self, materials: &[MaterialEntry]) -> Option<MaterialEdit> {
+        let material = self.selected_material(materials)?;
+        material.saveable.then_some(MaterialEdit::Save(material.id))
+    }
+
+    /// Moves the timeline selection `delta` rows down, wrapping around.
+    pub fn select_timeline(&mut self, timelines: &[TimelineStatus], delta: i32) {
+        if !timelines.is_empty() {
+            let count = timelines.len() as i32;
+            self.timeline = (self.timeline as i32 + delta).rem_euclid(count) as usize;
+        }
+    }
+
+    /// A seek moving the selected timeline `steps` of `TIMELINE_STEP`.
+    pub fn scrub(&self, timelines: &[TimelineStatus], steps: f32) -> Option<TimelineCommand> {
+        let timeline = self.selected_timeline(timelines)?;
+        Some(TimelineCommand::Seek {
+            name: timeline.name.clone(),
+            time: timeline.time + TIMELINE_STEP * steps,
+        })
+    }
+
+    /// Pauses or resumes the selected timeline.
+    pub fn toggle_timeline(&self, timelines: &[TimelineStatus]) -> Option<TimelineCommand> {
+        let timeline = self.selected_timeline(timelines)?;
+        Some(TimelineCommand::Pause {
+            name: timeline.name.clone(),
+            paused: !timeline.paused,
+        })
+    }
+
+    /// The hierarchy, inspector and timeline panels, or the material panels in the materials
+    /// mode. None while closed.
+    pub fn panels(
+        &self,
+        view: Option<&EditorView>,
+        timelines: &[TimelineStatus],
+        materials: &[MaterialEntry],
+    ) -> Vec<HudPanel> {
+        if self.materials_open() {
+            return self.material_panels(materials);
+        }
+        let Some(view) = view.filter(|_| self.open) else {
+            return vec![];
+        };
+
+        let selected_row = self.selected_row(view);
+        let first_row = selected_row
+            .map(|row| row.saturating_sub(HIERARCHY_ROWS / 2))
+            .unwrap_or(0)
+            .min(view.entities.len().saturating_sub(HIERARCHY_ROWS));
+        let hierarchy = HudPanel {
+            title: format!(
+                "HIERARCHY ({}){}",
+                view.entities.len(),
+                if self.paused { " PAUSED" } else { "" }
+            ),
+            lines: view.entities[first_row..]
+                .iter()
+                .take(HIERARCHY_ROWS)
+                .map(|(entity, components)| format!("{:5} {}", entity, components.join(" ")))
+                .collect(),
+            highlighted: selected_row.map(|row| row - first_row),
+        };
+
+        let mut inspector = HudPanel {
+            title: "INSPECTOR".to_string(),
+            ..Default::default()
+        };
+        if let Some((entity, components)) = &view.inspected {
+            inspector.title = format!("ENTITY {}", entity);
+            let mut field_index = 0;
+            for component in components {
+                inspector.lines.push(component.component.to_string());
+                for field in &component.fields {
+                    if field_index == self.field {
+                        inspector.highlighted = Some(inspector.lines.len());
+                    }
+                    inspector
+                        .lines
+                        .push(format!("  {:12} {:9.3}", field.name, field.value));
+                    field_index += 1;
+                }
+            }
+        } else {
+            inspector
+                .lines
+                .push("[ ] SELECT  PGUP/PGDN FIELD  -/= EDIT".to_string());
+            inspector
+                .lines
+                .push("1/2/3 MOVE/ROTATE/SCALE GIZMO".to_string());
+        }
+
+        let mut panels = vec![inspector, hierarchy];
+        if !timelines.is_empty() {
+            panels.push(HudPanel {
+                title: "TIMELINES  ; SELECT  ,/. SCRUB  / PAUSE".to_string(),
+                lines: timelines
+                    .iter()
+                    .map(|timeline| {
+                        format!(
+                            "{:16} {:7.2}/{:7.2}{}{}",
+                            timeline.name,
+                            timeline.time,
+                            timeline.duration,
+                            if timeline.looping { " LOOP" } else { "" },
+                            if timeline.paused { " PAUSED" } else { "" }
+                        )
+                    })
+                    .collect(),
+                highlighted: Some(self.timeline.min(timelines.len() - 1)),
+            });
+        }
+        panels
+    }
+
+    fn material_panels(&self, materials: &[MaterialEntry]) -> Vec<HudPanel> {
+        let list = HudPanel {
+            title: format!("MATERIALS ({})", materials.len()),
+            lines: materials
+                .iter()
+                .map(|material| {
+                    let saveable = if material.saveable { "" } else { " (CODE)" };
+                    format!("{}{}", material.name, saveable)
+                })
+                .collect(),
+            highlighted: self
+                .selected_material(materials)
+                .map(|_| self.material.min(materials.len() - 1)),
+        };
+
+        let mut fields = HudPanel {
+            title: "MATERIAL".to_string(),
+            lines: vec!["[ ] SELECT  PGUP/PGDN FIELD  -/= EDIT  F7 SAVE".to_string()],
+            ..Default::default()
+        };
+        if let Some(material) = self.selected_material(materials) {
+            fields.title = format!("MATERIAL {}", material.name.to_uppercase());
+            fields.lines.extend(
+                material
+                    .fields
+                    .iter()
+                    .map(|field| format!("  {:12} {:9.3}", field.name, field.value)),
+            );
+            fields.highlighted = Some(1 + self.material_field);
+        }
+        vec![fields, list]
+    }
+
+    fn selected_material<'a>(&self, materials: &'a [MaterialEntry]) -> Option<&'a MaterialEntry> {
+        materials.get(self.material).or(materials.last())
+    }
+
+    fn selected_timeline<'a>(&self, timelines: &'a [TimelineStatus]) -> Option<&'a TimelineStatus> {
+        timelines.get(self.timeline).or(timelines.last())
+    }
+
+    fn selected_row(&self, view: &EditorView) -> Option<usize> {
+        let selected = self.selected?;
+        view.entities
+            .iter()
+            .position(|(entity, _)| *entity == selected)
+    }
+}
+
+/// The inspected entity's fields in order, with their component's name.
+fn inspected_fields(view: &EditorView) -> impl Iterator<Item = (&'static str, &Field)> {
+    view.inspected
+        .iter()
+        .flat_map(|(_, components)| components)
+        .flat_map(|component| {
+            component
+                .fields
+                .iter()
+                .map(move |field| (component.component, field))
+        })
+}
@@ -0,0 +1,25 @@
+/// What the game loop does while the window doesn't have focus, see `GameLoop::set_focus_behavior`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FocusBehavior {
+    /// Stops the fixed update until the window regains focus.
+    pub pause: bool,
+    /// Frames per second rendered in the background, unlimited when `None`.
+    pub background_fps: Option<f32>,
+}
+
+impl Default for FocusBehavior {
+    fn default() -> Self {
+        FocusBehavior {
+            pause: true,
+            background_fps: Some(15.0),
+        }
+    }
+}
+
+/// Sent to the game loop's focus event readers as the window gains and loses focus, after the
+/// focus behavior has been applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusEvent {
+    Gained,
+    Lost,
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use anyhow::Context;
+use cgmath::Vector3;
+
+use crate::{random::Random, renderer::FoliageInstance};
+
+/// How thickly foliage grows across an area, from 0 for bare ground to 1 for a scatter's full
+/// density. Sampled with bilinear filtering, stretched over whatever area it's scattered on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DensityMap {
+    width: u32,
+    height: u32,
+    values: Vec<f32>,
+}
+
+impl DensityMap {
+    /// `values` are row by row, the first row along the area's minimum z.
+    pub fn new(width: u32, height: u32, values: Vec<f32>) -> anyhow::Result<Self> {
+        if width == 0 || height == 0 || values.len() != (width * height) as usize {
+            anyhow::bail!(
+                "a {}x{} density map needs {} values, got {}",
+                width,
+                height,
+                width * height,
+                values.len()
+            );
+        }
+        Ok(DensityMap {
+            width,
+            height,
+            values,
+        })
+    }
+
+    /// The same density everywhere.
+    pub fn uniform(density: f32) -> Self {
+        DensityMap {
+            width: 1,
+            height: 1,
+            values: vec![density],
+        }
+    }
+
+    /// Reads the brightness of an image, e.g. one painted over a top down view of the level.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("loading density map {:?}", path))?
+            .to_luma8();
+        let (width, height) = image.dimensions();
+        let values = image
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / 255.0)
+            .collect();
+        DensityMap::new(width, height, values)
+    }
+
+    /// The density at `u` and `v`, each from 0 to 1 across the map.
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = (u.clamp(0.0, 1.0) * self.width as f32 - 0.5).max(0.0);
+        let y = (v.clamp(0.0, 1.0) * self.height as f32 - 0.5).max(0.0);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (x.fract(), y.fract());
+        let value = |x: u32, y: u32| self.values[(y * self.width + x) as usize];
+        let top = value(x0, y0) + (value(x1, y0) - value(x0, y0)) * tx;
+        let bottom = value(x0, y1) + (value(x1, y1) - value(x0, y1)) * tx;
+        top + (bottom - top) * ty
+    }
+}
+
+/// Places foliage instances over an area, thinned out by a `DensityMap`. The same settings and
+/// map always give the same instances.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoliageScatter {
+    /// Instances per square meter where the density map is 1.
+    pub density: f32,
+    /// Width and height of the smallest instances, and of the largest.
+    pub min_size: [f32; 2],
+    pub max_size: [f32; 2],
+    /// Each instance gets a color between these two.
+    pub colors: [[f32; 3]; 2],
+    pub seed: u32,
+}
+
+impl Default for FoliageScatter {
+    fn default() -> Self {
+        FoliageScatter {
+            density: 8.0,
+            min_size: [0.4, 0.3],
+            max_size: [0.7, 0.6],
+            colors: [[0.45, 0.6, 0.25], [0.6, 0.75, 0.3]],
+            seed: 1,
+        }
+    }
+}
+
+impl FoliageScatter {
+    /// Scatters instances between `min` and `max`, as x and z, standing on the ground `height`
+    /// gives at each x and z, e.g. sampled from a heightmap or found with
+    /// `SpatialIndex::raycast`.
+    pub fn scatter(
+        &self,
+        map: &DensityMap,
+        min: [f32; 2],
+        max: [f32; 2],
+        height: impl Fn(f32, f32) -> f32,
+    ) -> Vec<FoliageInstance> {
+        if self.density <= 0.0 {
+            return vec![];
+        }
+        let mut random = Random::new(self.seed);
+        let extent = [max[0] - min[0], max[1] - min[1]];
+        // One candidate per grid square, jittered so the grid doesn't show
+        let spacing = 1.0 / self.density.sqrt();
+        let columns = (extent[0] / spacing).ceil().max(0.0) as u32;
+        let rows = (extent[1] / spacing).ceil().max(0.0) as u32;
+
+        let mut instances = vec![];
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = min[0] + (column as f32 + random.range(0.0, 1.0)) * spacing;
+                let z = min[1] + (row as f32 + random.range(0.0, 1.0)) * spacing;
+                let keep = random.range(0.0, 1.0);
+                let scale = random.range(0.0, 1.0);
+                let tint = random.range(0.0, 1.0);
+                let rotation = random.range(0.0, std::f32::consts::PI);
+                if x > max[0] || z > max[1] {
+                    continue;
+                }
+                if keep >= map.sample((x - min[0]) / extent[0], (z - min[1]) / extent[1]) {
+                    continue;
+                }
+                let mix = |a: f32, b: f32, t: f32| a + (b - a) * t;
+                let [from, to] = self.colors;
+                instances.push(FoliageInstance {
+                    position: Vector3::new(x, height(x, z), z),
+                    size: [
+                        mix(self.min_size[0], self.max_size[0], scale),
+                        mix(self.min_size[1], self.max_size[1], scale),
+                    ],
+                    rotation,
+                    color: [
+                        mix(from[0], to[0], tint),
+                        mix(from[1], to[1], tint),
+                        mix(from[2], to[2], tint),
+                    ],
+                });
+            }
+        }
+        instances
+    }
+}
@@ -1,42 +1,394 @@
 use anyhow::Context;
-use std::time::Instant;
-use tracing::{span, Level};
-use winit::{dpi::PhysicalSize, event::Event, event_loop::EventLoop, window::WindowId};
+use cgmath::Vector3;
+use gilrs::GamepadId;
+use std::{
+    path::Path,
+    sync::MutexGuard,
+    thread,
+    time::{Duration, Instant},
+};
+use winit::{
+    dpi::PhysicalSize,
+    event::{Event, WindowEvent},
+    event_loop::EventLoop,
+    keyboard::KeyCode,
+    window::WindowId,
+};
 
-#[cfg(feature = "tracing")]
-use tracing_tracy::client::frame_mark;
+use crate::{
+    renderer::{AnalysisReport, FoliageId, FoliageLayer, FrameStats, UiPrimitive, Wind},
+    EngineConfig,
+};
 
-use super::context::GameContext;
+use specs::shrev::{EventChannel, ReaderId};
 
+#[cfg(feature = "net")]
+use super::codec::ComponentCodecs;
+
+use super::{
+    components::{BudgetExceeded, PerformanceBudget, Sky, VisibilityResource},
+    console::CommandContext,
+    context::GameContext,
+    focus::{FocusBehavior, FocusEvent},
+    input::{CursorMode, GamepadEvent, GamepadInfo, PlayerIndex, Rumble, Source},
+    marker::{MarkerId, ScreenAnchor, WorldMarker},
+    replay::ReplayStatus,
+    save::SaveRegistry,
+    voxel::Voxel,
+};
+
+/// Renders on the calling thread while the fixed update runs on a simulation thread owned by the
+/// game context.
 pub struct GameLoop {
-    previous_instant: Instant,
-    accumulated_time: f32,
     context: GameContext,
+    focused: bool,
+    focus_behavior: FocusBehavior,
+    focus_events: EventChannel<FocusEvent>,
+    /// When the last frame started, to throttle rendering in the background.
+    last_frame: Instant,
 }
 
-const FPS: f32 = 60.0;
-// Note to self: Updates per second is number of times update is called per second
-// at 60 frames, this works out to a 4 updates each frame, time permitting
-const UPS: f32 = 240.0;
-const MAX_FRAME_TIME: f32 = 1.0 / FPS;
-const FIXED_TIME_STEP: f32 = 1.0 / UPS;
-
 impl GameLoop {
-    pub fn new(event_loop: &EventLoop<()>) -> anyhow::Result<Self> {
-        let context = GameContext::new(event_loop).context("creating game context")?;
+    pub fn new(event_loop: &EventLoop<()>, config: EngineConfig) -> anyhow::Result<Self> {
+        let focus_behavior = config.focus_behavior();
+        let context = GameContext::new(event_loop, config).context("creating game context")?;
         Ok(GameLoop {
-            previous_instant: Instant::now(),
-            accumulated_time: 0.0,
             context,
+            focused: true,
+            focus_behavior,
+            focus_events: EventChannel::new(),
+            last_frame: Instant::now(),
         })
     }
 
-    pub fn set_cursor_captured(&mut self) {
-        self.context.set_cursor_captured();
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    pub fn focus_behavior(&self) -> FocusBehavior {
+        self.focus_behavior
+    }
+
+    /// Replaces what happens while the window doesn't have focus, taking effect right away.
+    /// Games wanting their own handling, like opening a pause menu instead, can turn pausing off
+    /// and react to focus events.
+    pub fn set_focus_behavior(&mut self, behavior: FocusBehavior) {
+        self.focus_behavior = behavior;
+        self.context
+            .set_focus_paused(!self.focused && behavior.pause);
+    }
+
+    pub fn subscribe_focus_events(&mut self) -> ReaderId<FocusEvent> {
+        self.focus_events.register_reader()
+    }
+
+    pub fn read_focus_events(&self, reader: &mut ReaderId<FocusEvent>) -> Vec<FocusEvent> {
+        self.focus_events.read(reader).copied().collect()
+    }
+
+    fn set_focused(&mut self, focused: bool) {
+        if self.focused == focused {
+            return;
+        }
+        self.focused = focused;
+        self.context
+            .set_focus_paused(!focused && self.focus_behavior.pause);
+        log::info!("window {}", if focused { "focused" } else { "lost focus" });
+        self.focus_events.single_write(if focused {
+            FocusEvent::Gained
+        } else {
+            FocusEvent::Lost
+        });
+    }
+
+    pub fn set_cursor_mode(&mut self, mode: CursorMode) {
+        self.context.set_cursor_mode(mode);
+    }
+
+    pub fn set_performance_budget(&mut self, budget: PerformanceBudget) {
+        self.context.set_performance_budget(budget);
+    }
+
+    /// Counters for the last rendered frame.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.context.frame_stats()
+    }
+
+    /// What passed culling in the last rendered frame. Fixed update systems read the same from
+    /// the simulation world's `VisibilityResource`.
+    pub fn visibility(&self) -> VisibilityResource {
+        self.context.visibility()
+    }
+
+    /// Metrics currently over budget.
+    pub fn budget_warnings(&self) -> Vec<BudgetExceeded> {
+        self.context.budget_warnings()
+    }
+
+    pub fn subscribe_budget_events(&mut self) -> ReaderId<BudgetExceeded> {
+        self.context.subscribe_budget_events()
+    }
+
+    pub fn read_budget_events(&self, reader: &mut ReaderId<BudgetExceeded>) -> Vec<BudgetExceeded> {
+        self.context.read_budget_events(reader)
+    }
+
+    /// Every connected gamepad and the player it's assigned to. Gamepads are assigned to the
+    /// first free player as they're connected.
+    pub fn gamepads(&self) -> Vec<GamepadInfo> {
+        self.context.gamepads()
+    }
+
+    /// Moves gamepad `id` to `player`, or leaves it unassigned when `None`.
+    pub fn assign_gamepad(&mut self, id: GamepadId, player: Option<PlayerIndex>) {
+        self.context.assign_gamepad(id, player);
+    }
+
+    /// Reads `player`'s gamepad through the named action map rather than the current one.
+    pub fn set_player_action_map(&mut self, player: PlayerIndex, name: &str) -> anyhow::Result<()> {
+        self.context.set_player_action_map(player, name)
+    }
+
+    /// Vibrates `player`'s gamepad right away. Fixed update systems queue rumbles through the
+    /// `RumbleQueue` resource instead.
+    pub fn rumble(&mut self, player: PlayerIndex, rumble: Rumble) -> anyhow::Result<()> {
+        self.context.rumble(player, rumble)
+    }
+
+    pub fn stop_rumble(&mut self, player: PlayerIndex) {
+        self.context.stop_rumble(player);
+    }
+
+    pub fn subscribe_gamepad_events(&mut self) -> ReaderId<GamepadEvent> {
+        self.context.subscribe_gamepad_events()
+    }
+
+    pub fn read_gamepad_events(&self, reader: &mut ReaderId<GamepadEvent>) -> Vec<GamepadEvent> {
+        self.context.read_gamepad_events(reader)
+    }
+
+    /// Runs the luminance histogram, NaN/Inf detector and depth range readout on the next frame.
+    pub fn request_frame_analysis(&mut self) {
+        self.context.request_frame_analysis();
+    }
+
+    /// The report of the last requested analysis, once it has been read back from the GPU.
+    pub fn take_frame_analysis_report(&mut self) -> Option<AnalysisReport> {
+        self.context.take_frame_analysis_report()
+    }
+
+    /// Captures the next frame with RenderDoc, see `Renderer::capture_frame`.
+    pub fn capture_frame(&mut self) {
+        self.context.capture_frame();
+    }
+
+    /// Queues a UI primitive for the next frame, drawn under the HUD.
+    pub fn draw_ui(&mut self, primitive: UiPrimitive) {
+        self.context.draw_ui(primitive);
+    }
+
+    /// Loads the texture at `path` as the atlas UI primitives sample, before the next frame. A
+    /// texture that fails to load is logged and the previous atlas kept.
+    pub fn set_ui_atlas(&mut self, path: &Path) {
+        self.context.set_ui_atlas(path);
+    }
+
+    /// Draws `layer` as the foliage layer `id` from the next frame on, replacing the layer `id`
+    /// had. `texture` is loaded as its texture, see `FoliageLayer::texture`. Scatter the instances
+    /// with `FoliageScatter`.
+    pub fn set_foliage(&mut self, id: FoliageId, layer: FoliageLayer, texture: Option<&Path>) {
+        self.context.set_foliage(id, layer, texture);
+    }
+
+    pub fn remove_foliage(&mut self, id: FoliageId) {
+        self.context.remove_foliage(id);
+    }
+
+    /// Replaces the wind swaying the foliage.
+    pub fn set_wind(&mut self, wind: Wind) {
+        self.context.set_wind(wind);
+    }
+
+    /// Tracks a point on an entity on screen, see `marker_anchors`.
+    pub fn add_marker(&mut self, marker: WorldMarker) -> MarkerId {
+        self.context.add_marker(marker)
+    }
+
+    pub fn remove_marker(&mut self, id: MarkerId) {
+        self.context.remove_marker(id);
+    }
+
+    /// Where each marker is on screen for the next frame, in the logical pixels `draw_ui` lays
+    /// out in, so health bars and waypoints drawn there follow their entities. Markers of
+    /// entities that aren't drawn are left out.
+    pub fn marker_anchors(&mut self) -> Vec<(MarkerId, ScreenAnchor)> {
+        self.context.marker_anchors()
+    }
+
+    /// The current UI scale, in screen pixels per logical pixel.
+    pub fn ui_scale(&self) -> f32 {
+        self.context.ui_scale()
+    }
+
+    /// Forces the scale of the HUD and other UI, or follows the window's scale factor again when
+    /// `None`.
+    pub fn set_ui_scale(&mut self, scale: Option<f32>) {
+        self.context.set_ui_scale(scale);
+    }
+
+    /// Shows or hides the performance HUD: FPS, frame times, per-pass timings and counts.
+    pub fn toggle_hud(&mut self) {
+        self.context.toggle_hud();
+    }
+
+    /// Passes a pressed key, and the `text` it typed, to the console, returning whether it was
+    /// handled. The backtick key opens and closes the console, which takes every key while it's
+    /// open. Enter runs the typed line, Up and Down browse the history and `help` lists the
+    /// commands.
+    pub fn console_key(&mut self, key: KeyCode, text: Option<&str>, repeat: bool) -> bool {
+        self.context.console_key(key, text, repeat)
+    }
+
+    /// Types text composed by the window's input method into the console. Returns whether the
+    /// console was open to take it.
+    pub fn console_text(&mut self, text: &str) -> bool {
+        self.context.console_text(text)
+    }
+
+    /// The keys and buttons bound to `action_name`, named for the current keyboard layout, for
+    /// showing in UI.
+    pub fn binding_names(&self, action_name: &str) -> Vec<String> {
+        self.context.binding_names(action_name)
+    }
+
+    /// Starts listening for the next key, mouse button, mouse movement or gamepad input, for a
+    /// controls menu. Actions don't trigger until it's taken with `take_captured_input`.
+    pub fn listen_for_input(&mut self) {
+        self.context.listen_for_input();
+    }
+
+    pub fn cancel_listening(&mut self) {
+        self.context.cancel_listening();
+    }
+
+    /// The input captured while listening, `None` while still waiting for one.
+    pub fn take_captured_input(&mut self) -> Option<Source> {
+        self.context.take_captured_input()
+    }
+
+    /// Binds `source` to `action_name` in place of `previous`, persisted to the bindings file
+    /// when the config names one.
+    pub fn rebind(
+        &mut self,
+        action_name: &str,
+        previous: Option<&Source>,
+        source: Source,
+    ) -> anyhow::Result<()> {
+        self.context.rebind(action_name, previous, source)
+    }
+
+    /// Saves the `Saved` entities of the simulation to `path`, between ticks and without waiting
+    /// for the file to be written.
+    pub fn save_game(&self, path: &Path) {
+        self.context.save_game(path);
+    }
+
+    /// Replaces the `Saved` entities of the simulation with those saved at `path`.
+    pub fn load_game(&self, path: &Path) {
+        self.context.load_game(path);
+    }
+
+    /// Starts recording every tick's input, from a save of the `Saved` entities.
+    pub fn record_replay(&self) {
+        self.context.record_replay();
+    }
+
+    /// Loads the replay at `path` and runs the simulation with its input, checking it ends each
+    /// tick as it did when recorded. See `replay_status` for where it diverged, if it did.
+    pub fn play_replay(&self, path: &Path) {
+        self.context.play_replay(path);
+    }
+
+    /// Stops recording, writing the replay to `path` when given, or stops playing back.
+    pub fn stop_replay(&self, path: Option<&Path>) {
+        self.context.stop_replay(path);
     }
 
-    pub fn set_cursor_released(&mut self) {
-        self.context.set_cursor_released();
+    pub fn replay_status(&self) -> ReplayStatus {
+        self.context.replay_status()
+    }
+
+    /// Moves the `TimeOfDay` to `hour` before the next tick. Only works with a day-night cycle,
+    /// started by the `time_of_day` config value or by inserting a `TimeOfDay` resource.
+    pub fn set_time_of_day(&self, hour: f32) {
+        self.context.set_time_of_day(hour);
+    }
+
+    /// Stops or restarts the day-night cycle.
+    pub fn set_time_of_day_paused(&self, paused: bool) {
+        self.context.set_time_of_day_paused(paused);
+    }
+
+    /// The time of day and the sky the last tick ended with, `None` without a day-night cycle.
+    pub fn sky(&self) -> Option<Sky> {
+        self.context.sky()
+    }
+
+    /// Sets one voxel of the simulation's `VoxelWorld` before the next tick.
+    pub fn set_voxel(&self, position: [i32; 3], voxel: Voxel) {
+        self.context.fill_voxels(position, position, voxel);
+    }
+
+    /// Sets every voxel from `min` to `max`, both included, before the next tick. Air clears
+    /// them.
+    pub fn fill_voxels(&self, min: [i32; 3], max: [i32; 3], voxel: Voxel) {
+        self.context.fill_voxels(min, max, voxel);
+    }
+
+    /// Builds up `material` within `radius` of `center` before the next tick, smoothly with the
+    /// `MarchingCubes` mesher. See `VoxelWorld::sculpt`.
+    pub fn sculpt_voxels(&self, center: Vector3<f32>, radius: f32, amount: f32, material: Voxel) {
+        self.context.sculpt_voxels(center, radius, amount, material);
+    }
+
+    /// Digs a hole of `radius` around `center` before the next tick, deeper with `amount`.
+    pub fn dig(&self, center: Vector3<f32>, radius: f32, amount: f32) {
+        self.context
+            .sculpt_voxels(center, radius, -amount.abs(), Voxel::AIR);
+    }
+
+    /// Registers the components saves hold, their version and migrations from older versions.
+    pub fn save_registry(&self) -> MutexGuard<'_, SaveRegistry> {
+        self.context.save_registry()
+    }
+
+    /// The components of `Replicated` entities a server sends, under names both ends share.
+    #[cfg(feature = "net")]
+    pub fn replication_registry(&self) -> MutexGuard<'_, ComponentCodecs> {
+        self.context.replication_registry()
+    }
+
+    /// Adds a console command called `name`, run with the words typed after it. Built in are
+    /// `render <feature> <on/off>`, `spawn <prefab>` and `stats`.
+    pub fn register_command<F>(&mut self, name: &str, command: F)
+    where
+        F: FnMut(&mut CommandContext, &[&str]) -> anyhow::Result<()> + 'static,
+    {
+        self.context.register_command(name, command);
+    }
+
+    /// Passes a pressed key to the editor, returning whether it was handled:
+    ///
+    /// - F1 opens and closes the editor
+    /// - F5 pauses and resumes the simulation
+    /// - `[` and `]` select the previous and next entity in the hierarchy
+    /// - Page Up and Page Down select a field of the selected entity
+    /// - `-` and `=` nudge the field down and up
+    /// - Clicking an entity in the scene selects it, clicking nothing clears the selection
+    /// - F6 switches the hierarchy and inspector for the materials, where the keys above pick
+    ///   and nudge a material's fields instead, and F7 saves it back to its file
+    pub fn editor_key(&mut self, key: KeyCode, repeat: bool) -> bool {
+        self.context.editor_key(key, repeat)
     }
 
     pub fn window_size(&self) -> Option<PhysicalSize<u32>> {
@@ -51,45 +403,41 @@ impl GameLoop {
         self.context.resize()
     }
 
-    pub fn process_winit_event(&mut self, event: &Event<()>, mouse_captured: bool) -> bool {
-        self.context.process_winit_event(event, mouse_captured)
+    pub fn process_winit_event(&mut self, event: &Event<()>) -> bool {
+        if let Event::WindowEvent {
+            event: WindowEvent::Focused(focused),
+            ..
+        } = event
+        {
+            self.set_focused(*focused);
+        }
+        self.context.process_winit_event(event)
     }
 
-    /// Implements fixed timestep game loop https://gafferongames.com/post/fix_your_timestep/
+    /// Renders a frame from the simulation's latest snapshots. The simulation thread implements
+    /// the fixed timestep https://gafferongames.com/post/fix_your_timestep/
     pub fn update(&mut self) -> anyhow::Result<()> {
-        let _update = span!(Level::INFO, "game update", self.accumulated_time).entered();
-
-        let current_instant = Instant::now();
+        profile_scope!("game update");
 
-        let mut elapsed = current_instant
-            .duration_since(self.previous_instant)
-            .as_secs_f32();
-
-        if elapsed > MAX_FRAME_TIME {
-            elapsed = MAX_FRAME_TIME;
+        if let Some(fps) = self.focus_behavior.background_fps.filter(|_| !self.focused) {
+            let frame_time = Duration::from_secs_f32(1.0 / fps.max(1.0));
+            if let Some(remaining) = frame_time.checked_sub(self.last_frame.elapsed()) {
+                thread::sleep(remaining);
+            }
         }
 
-        self.accumulated_time += elapsed;
-
-        let update_loop = span!(Level::INFO, "update loop").entered();
+        let current_instant = Instant::now();
+        self.last_frame = current_instant;
 
         self.context.pre_update();
 
-        while self.accumulated_time >= FIXED_TIME_STEP {
-            self.context.update();
-            self.accumulated_time -= FIXED_TIME_STEP;
-        }
-
-        update_loop.exit();
-
-        let blending_factor = self.accumulated_time / FIXED_TIME_STEP;
-
-        self.context.render(blending_factor)?;
+        self.context.render()?;
 
-        #[cfg(feature = "tracing")]
-        frame_mark();
+        let cpu_frame_time = current_instant.elapsed().as_secs_f32() * 1000.0;
+        self.context.set_cpu_frame_time(cpu_frame_time);
+        profile_plot!("cpu frame time (ms)", cpu_frame_time);
 
-        self.previous_instant = current_instant;
+        crate::profiling::frame_mark();
 
         Ok(())
     }
@@ -0,0 +1,227 @@
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector3};
+
+use super::{camera_math::Ray, components::transform::Transform};
+
+/// Gizmo size as a fraction of its distance to the camera, so it keeps its size on screen.
+const SCREEN_SIZE: f32 = 0.15;
+/// How close the cursor has to come to a handle to grab it, as a fraction of the gizmo's size.
+const GRAB_DISTANCE: f32 = 0.08;
+const CIRCLE_SEGMENTS: usize = 32;
+
+const AXIS_COLORS: [[f32; 4]; 3] = [
+    [1.0, 0.2, 0.2, 1.0],
+    [0.2, 1.0, 0.2, 1.0],
+    [0.3, 0.5, 1.0, 1.0],
+];
+const ACTIVE_COLOR: [f32; 4] = [1.0, 1.0, 0.2, 1.0];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// A handle grabbed by the cursor.
+#[derive(Debug, Clone, Copy)]
+struct Drag {
+    entity: u32,
+    axis: usize,
+    start: Transform,
+    /// Where the handle was grabbed, relative to the gizmo's center.
+    anchor: Vector3<f32>,
+}
+
+/// Translate, rotate and scale handles for an entity's transform, one per axis, dragged with the
+/// cursor. Translation and rotation work along the world axes, scale along the entity's own.
+#[derive(Debug, Default)]
+pub struct Gizmo {
+    mode: GizmoMode,
+    hovered: Option<usize>,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    pub fn set_mode(&mut self, mode: GizmoMode) {
+        self.mode = mode;
+        self.drag = None;
+    }
+
+    /// Whether the cursor is over a handle or dragging one, so clicks belong to the gizmo.
+    pub fn is_active(&self) -> bool {
+        self.hovered.is_some() || self.drag.is_some()
+    }
+
+    /// Follows the cursor's `ray` over `entity`'s gizmo. A handle is grabbed when the button is
+    /// `pressed` over it and dragged while it's `held`. Returns the dragged transform.
+    pub fn update(
+        &mut self,
+        entity: u32,
+        transform: &Transform,
+        ray: Option<Ray>,
+        camera_position: Vector3<f32>,
+        pressed: bool,
+        held: bool,
+    ) -> Option<Transform> {
+        if !held || self.drag.is_some_and(|drag| drag.entity != entity) {
+            self.drag = None;
+        }
+        let ray = ray?;
+        let size = gizmo_size(transform, camera_position);
+
+        let Some(drag) = self.drag else {
+            self.hovered = self.hit_test(transform, &ray, size);
+            if pressed {
+                self.drag = self.hovered.and_then(|axis| {
+                    let anchor = self.handle_point(transform, &ray, axis)?;
+                    Some(Drag {
+                        entity,
+                        axis,
+                        start: *transform,
+                        anchor,
+                    })
+                });
+            }
+            return None;
+        };
+
+        let start = drag.start;
+        let axis = self.axis(&start, drag.axis);
+        let point = self.handle_point(&start, &ray, drag.axis)?;
+        let mut dragged = start;
+        match self.mode {
+            GizmoMode::Translate => {
+                dragged.position += axis * (point - drag.anchor).dot(axis);
+            }
+            GizmoMode::Rotate => {
+                let angle = drag
+                    .anchor
+                    .cross(point)
+                    .dot(axis)
+                    .atan2(drag.anchor.dot(point));
+                dragged.rotation = Quaternion::from_axis_angle(axis, Rad(angle)) * start.rotation;
+            }
+            GizmoMode::Scale => {
+                let from = drag.anchor.dot(axis);
+                if from.abs() > 1e-6 {
+                    dragged.scale[drag.axis] = start.scale[drag.axis] * point.dot(axis) / from;
+                }
+            }
+        }
+        Some(dragged)
+    }
+
+    /// The gizmo's lines for `transform`, with the hovered or dragged handle highlighted.
+    pub fn lines(
+        &self,
+        transform: &Transform,
+        camera_position: Vector3<f32>,
+    ) -> Vec<(Vector3<f32>, Vector3<f32>, [f32; 4])> {
+        let size = gizmo_size(transform, camera_position);
+        let center = transform.position;
+        let active = self.drag.map(|drag| drag.axis).or(self.hovered);
+        let mut lines = vec![];
+
+        for index in 0..3 {
+            let axis = self.axis(transform, index);
+            let color = if active == Some(index) {
+                ACTIVE_COLOR
+            } else {
+                AXIS_COLORS[index]
+            };
+            match self.mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    let tip = center + axis * size;
+                    lines.push((center, tip, color));
+                    // Scale handles end in a cross, translate handles in an arrow head
+                    let tick = size * 0.06;
+                    for other in (0..3).filter(|other| *other != index) {
+                        let side = self.axis(transform, other) * tick;
+                        match self.mode {
+                            GizmoMode::Scale => lines.push((tip - side, tip + side, color)),
+                            _ => lines.push((tip, tip - axis * tick * 2.0 + side, color)),
+                        }
+                    }
+                }
+                GizmoMode::Rotate => {
+                    let (u, v) = perpendiculars(axis);
+                    let point = |segment: usize| {
+                        let angle = segment as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                        center + (u * angle.cos() + v * angle.sin()) * size
+                    };
+                    for segment in 0..CIRCLE_SEGMENTS {
+                        lines.push((point(segment), point(segment + 1), color));
+                    }
+                }
+            }
+        }
+
+        lines
+    }
+
+    /// The handle the ray passes closest to, within grabbing distance.
+    fn hit_test(&self, transform: &Transform, ray: &Ray, size: f32) -> Option<usize> {
+        let center = transform.position;
+        (0..3)
+            .filter_map(|index| {
+                let axis = self.axis(transform, index);
+                let distance = match self.mode {
+                    GizmoMode::Translate | GizmoMode::Scale => {
+                        let (along_ray, along_axis) = ray.closest_to_line(center, axis)?;
+                        if along_ray < 0.0 || !(0.0..=size).contains(&along_axis) {
+                            return None;
+                        }
+                        (ray.at(along_ray) - (center + axis * along_axis)).magnitude()
+                    }
+                    GizmoMode::Rotate => {
+                        let point = ray.intersect_plane(center, axis)?;
+                        ((point - center).magnitude() - size).abs()
+                    }
+                };
+                (distance < size * GRAB_DISTANCE).then_some((index, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(index, _)| index)
+    }
+
+    /// Where the ray meets handle `index`, relative to the gizmo's center: the closest point on
+    /// the axis, or for rotation the point on the handle's plane.
+    fn handle_point(&self, transform: &Transform, ray: &Ray, index: usize) -> Option<Vector3<f32>> {
+        let center = transform.position;
+        let axis = self.axis(transform, index);
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                let (_, along_axis) = ray.closest_to_line(center, axis)?;
+                Some(axis * along_axis)
+            }
+            GizmoMode::Rotate => ray
+                .intersect_plane(center, axis)
+                .map(|point| point - center),
+        }
+    }
+
+    fn axis(&self, transform: &Transform, index: usize) -> Vector3<f32> {
+        let mut axis = Vector3::new(0.0, 0.0, 0.0);
+        axis[index] = 1.0;
+        match self.mode {
+            GizmoMode::Scale => transform.rotation.rotate_vector(axis),
+            _ => axis,
+        }
+    }
+}
+
+fn gizmo_size(transform: &Transform, camera_position: Vector3<f32>) -> f32 {
+    ((transform.position - camera_position).magnitude() * SCREEN_SIZE).max(0.01)
+}
+
+/// Two unit vectors perpendicular to `axis` and each other.
+fn perpendiculars(axis: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let other = if axis.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let u = axis.cross(other).normalize();
+    (u, axis.cross(u))
+}
@@ -0,0 +1,18 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::sources::Source;
+
+/// The file `EngineConfig::key_bindings` points at, holding the bindings of every action map
+/// by name. Replaces the built-in bindings of the maps it names.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct BindingsFile {
+    pub maps: BTreeMap<String, Vec<Binding>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Binding {
+    pub action: String,
+    pub source: Source,
+}
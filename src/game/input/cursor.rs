@@ -0,0 +1,23 @@
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum CursorMode {
+    #[default]
+    Free,
+    Captured,
+}
+
+impl CursorMode {
+    pub fn toggled(self) -> Self {
+        match self {
+            CursorMode::Free => CursorMode::Captured,
+            CursorMode::Captured => CursorMode::Free,
+        }
+    }
+}
+
+/// What triggering an action bound with `InputSystem::bind_cursor_action` does to the cursor.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum CursorBinding {
+    Capture,
+    Release,
+    Toggle,
+}
@@ -0,0 +1,34 @@
+use gilrs::GamepadId;
+use serde::{Deserialize, Serialize};
+
+/// Identifies a local player. The first player also gets the keyboard and mouse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PlayerIndex(pub usize);
+
+impl PlayerIndex {
+    pub const ONE: PlayerIndex = PlayerIndex(0);
+}
+
+/// A connected gamepad and the player it drives, if any.
+#[derive(Debug, Clone)]
+pub struct GamepadInfo {
+    pub id: GamepadId,
+    pub name: String,
+    pub player: Option<PlayerIndex>,
+}
+
+/// Emitted on the `EventChannel<GamepadEvent>` resource when a gamepad is plugged in or removed.
+#[derive(Debug, Clone)]
+pub enum GamepadEvent {
+    /// `player` is the free player slot the gamepad was given, `None` when every slot is taken.
+    Connected {
+        id: GamepadId,
+        name: String,
+        player: Option<PlayerIndex>,
+    },
+    /// `player` is the slot the gamepad leaves free.
+    Disconnected {
+        id: GamepadId,
+        player: Option<PlayerIndex>,
+    },
+}
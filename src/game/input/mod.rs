@@ -1,10 +1,19 @@
 pub use system::{InputSystem, MouseButton as SystemMouseButton};
 
+pub use cursor::{CursorBinding, CursorMode};
+pub use gamepad::{GamepadEvent, GamepadInfo, PlayerIndex};
 pub use map::ActionMap;
+pub use rumble::{Rumble, RumbleEnvelope, RumbleQueue};
 pub use sources::{
     ActionDescriptor, ActionKind, ActionState, GamepadSource, MouseAxis, MouseSource, Source,
 };
+pub use tracker::ActionTracker;
 
+mod bindings;
+mod cursor;
+mod gamepad;
 mod map;
+mod rumble;
 mod sources;
 mod system;
+mod tracker;
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use gilrs::ff::{BaseEffect, BaseEffectType, EffectBuilder, Envelope, Replay, Ticks};
+
+use super::PlayerIndex;
+
+/// A gamepad vibration. Strengths are in `0.0..=1.0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rumble {
+    /// The low frequency, heavy motor.
+    pub strong: f32,
+    /// The high frequency, light motor.
+    pub weak: f32,
+    pub duration: Duration,
+    pub envelope: RumbleEnvelope,
+}
+
+impl Rumble {
+    /// Both motors at `strength` for `duration`, without an envelope.
+    pub fn new(strength: f32, duration: Duration) -> Self {
+        Rumble {
+            strong: strength,
+            weak: strength,
+            duration,
+            envelope: RumbleEnvelope::default(),
+        }
+    }
+
+    pub(super) fn effect_builder(&self) -> EffectBuilder {
+        let scheduling = Replay {
+            play_for: ticks(self.duration),
+            ..Default::default()
+        };
+        let envelope = Envelope {
+            attack_length: ticks(self.envelope.attack),
+            attack_level: self.envelope.attack_level.clamp(0.0, 1.0),
+            fade_length: ticks(self.envelope.fade),
+            fade_level: self.envelope.fade_level.clamp(0.0, 1.0),
+        };
+        let magnitude = |strength: f32| (strength.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+
+        let mut builder = EffectBuilder::new();
+        builder
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong {
+                    magnitude: magnitude(self.strong),
+                },
+                scheduling,
+                envelope,
+            })
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Weak {
+                    magnitude: magnitude(self.weak),
+                },
+                scheduling,
+                envelope,
+            });
+        builder
+    }
+}
+
+/// Ramps a rumble in from `attack_level` over `attack` and out to `fade_level` over `fade`, as
+/// fractions of its strength.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RumbleEnvelope {
+    pub attack: Duration,
+    pub attack_level: f32,
+    pub fade: Duration,
+    pub fade_level: f32,
+}
+
+/// Rumbles requested by the fixed update systems, played on the players' gamepads after the
+/// tick.
+#[derive(Debug, Default)]
+pub struct RumbleQueue(pub Vec<(PlayerIndex, Rumble)>);
+
+fn ticks(duration: Duration) -> Ticks {
+    Ticks::from_ms(duration.as_millis().min(u32::MAX as u128) as u32)
+}
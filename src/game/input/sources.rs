@@ -1,42 +1,97 @@
+use std::time::Duration;
+
 use gilrs::{Axis, Button};
-use winit::keyboard::KeyCode;
+use serde::{Deserialize, Serialize};
+use winit::keyboard::{Key, KeyCode, NamedKey};
 
 use super::SystemMouseButton;
 
-#[derive(Eq, Hash, PartialEq, Debug)]
+#[derive(Eq, Hash, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum Source {
+    /// A key by where it is on the keyboard, so WASD keeps its shape on AZERTY or Dvorak.
     Keyboard(KeyCode),
+    /// A key by what it types on the current layout, for bindings named after their letter.
+    LogicalKey(Key),
     Mouse(MouseSource),
     Gamepad(GamepadSource),
 }
 
-#[derive(Eq, Hash, PartialEq, Copy, Clone, Debug)]
+#[derive(Eq, Hash, PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum GamepadSource {
     Axis(Axis),
-    #[allow(dead_code)]
     Button(Button),
 }
 
-#[derive(Eq, Hash, PartialEq, Debug)]
+#[derive(Eq, Hash, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum MouseSource {
-    #[allow(dead_code)]
     Button(SystemMouseButton),
     Move(MouseAxis),
     #[allow(dead_code)]
     Scroll(MouseAxis),
 }
-#[derive(Eq, Hash, PartialEq, Copy, Clone, Debug)]
+#[derive(Eq, Hash, PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum MouseAxis {
     MouseX,
     MouseY,
 }
 
-#[derive(Debug, Clone)]
+/// How a key is shown in UI, `None` for keys the layout doesn't name.
+pub(super) fn key_name(key: &Key) -> Option<String> {
+    match key {
+        Key::Character(text) => Some(text.to_uppercase()),
+        Key::Named(NamedKey::Space) => Some("Space".to_string()),
+        Key::Named(named) => Some(format!("{:?}", named)),
+        Key::Dead(Some(c)) => Some(c.to_uppercase().to_string()),
+        _ => None,
+    }
+}
+
+/// How a physical key is shown before it's been pressed, named after its US layout position.
+pub(super) fn key_code_name(code: KeyCode) -> String {
+    let name = format!("{:?}", code);
+    name.strip_prefix("Key")
+        .or_else(|| name.strip_prefix("Digit"))
+        .unwrap_or(&name)
+        .to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionState {
     pub name: String,
     pub active: bool,
     pub active_state_changed_this_frame: bool,
     pub value: Option<f32>,
+    /// Whether `value` is how far something moved since the last frame, like the mouse, rather
+    /// than where it is, like a stick. Only the first fixed update after a frame sees it.
+    pub delta: bool,
+    /// Whether the action went active this fixed update.
+    pub just_pressed: bool,
+    /// Whether the action stopped being active this fixed update, when `active` is false.
+    pub just_released: bool,
+    /// How long the action has been active, or was before it was released.
+    pub held_for: Duration,
+    /// How many times the action has repeated while held, like a held key typing.
+    pub repeat_count: u32,
+    /// Whether the action was pressed or repeated this fixed update.
+    pub repeated: bool,
+}
+
+impl ActionState {
+    /// An active state, as input sources report it before the fixed update tracks its edges.
+    pub fn new(name: &str, value: Option<f32>) -> Self {
+        ActionState {
+            name: name.to_string(),
+            active: true,
+            active_state_changed_this_frame: false,
+            value,
+            delta: false,
+            just_pressed: false,
+            just_released: false,
+            held_for: Duration::ZERO,
+            repeat_count: 0,
+            repeated: false,
+        }
+    }
 }
 
 pub enum ActionKind {
@@ -13,18 +13,31 @@
     - state gets put into a Resource in the ECS
 */
 
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    time::Instant,
+};
 
-use anyhow::anyhow;
-use gilrs::{Axis, GamepadId, Gilrs};
-use winit::event::Event;
+use anyhow::{anyhow, bail, Context};
+use gilrs::{ff::Effect, Axis, EventType, Gamepad, GamepadId, Gilrs};
+use serde::{Deserialize, Serialize};
+use winit::{
+    event::{DeviceEvent, ElementState, Event, KeyEvent, WindowEvent},
+    keyboard::{Key, KeyCode, PhysicalKey},
+};
 use winit_input_helper::WinitInputHelper;
 
 use crate::game::input::{sources::ActionState, MouseAxis};
 
 use super::{
+    bindings::{Binding, BindingsFile},
+    cursor::{CursorBinding, CursorMode},
+    gamepad::{GamepadEvent, GamepadInfo, PlayerIndex},
     map::ActionMap,
-    sources::{ActionDescriptor, Source},
+    rumble::Rumble,
+    sources::{key_code_name, key_name, ActionDescriptor, Source},
     GamepadSource, MouseSource,
 };
 
@@ -35,7 +48,26 @@ pub struct InputSystem {
     action_state_map: HashMap<String, ActionState>,
     input_helper: WinitInputHelper,
     gilrs: Gilrs,
-    current_gamepad: Option<GamepadId>,
+    /// The gamepad driving each player.
+    players: BTreeMap<PlayerIndex, GamepadId>,
+    /// Action maps of players that don't use the current one.
+    player_action_maps: HashMap<PlayerIndex, String>,
+    /// Action states of every player after the first, whose are in `action_state_map`.
+    player_states: HashMap<PlayerIndex, HashMap<String, ActionState>>,
+    gamepad_events: Vec<GamepadEvent>,
+    /// Playing effects, which stop when dropped, with their gamepad and when they end.
+    rumbles: Vec<(GamepadId, Effect, Instant)>,
+    cursor_mode: CursorMode,
+    cursor_bindings: HashMap<String, CursorBinding>,
+    /// What each physical key last typed, naming it in the current keyboard layout.
+    key_names: HashMap<KeyCode, Key>,
+    /// Raw mouse motion since the end of the last batch of events.
+    mouse_motion: (f32, f32),
+    /// Raw mouse motion over the last batch of events.
+    frame_mouse_delta: (f32, f32),
+    /// Set by `listen_for_input`, while no action triggers.
+    listening: bool,
+    captured_input: Option<Source>,
 }
 
 impl Default for InputSystem {
@@ -47,18 +79,43 @@ impl Default for InputSystem {
 // TODO: pull this out into preferences
 const RIGHT_STICK_MULTIPLIER: f32 = 6.0;
 const INVERT_RIGHT_STICK_Y: f32 = 1.0;
+/// Gamepads connected beyond this many are left unassigned.
+const MAX_PLAYERS: usize = 4;
+/// How far the mouse moves in one batch of events, in pixels, to be captured as an axis.
+const MOUSE_CAPTURE_DISTANCE: f32 = 40.0;
+/// How far a stick or trigger moves to be captured as an axis.
+const GAMEPAD_CAPTURE_VALUE: f32 = 0.6;
 
 impl InputSystem {
     pub fn new() -> Self {
-        InputSystem {
+        let mut input_system = InputSystem {
             action_map_map: HashMap::new(),
             action_descriptor_map: HashMap::new(),
             current_action_map: "".to_string(),
             action_state_map: HashMap::new(),
             input_helper: WinitInputHelper::new(),
             gilrs: Gilrs::new().unwrap(),
-            current_gamepad: None,
+            players: BTreeMap::new(),
+            player_action_maps: HashMap::new(),
+            player_states: HashMap::new(),
+            gamepad_events: vec![],
+            rumbles: vec![],
+            cursor_mode: CursorMode::Free,
+            cursor_bindings: HashMap::new(),
+            key_names: HashMap::new(),
+            mouse_motion: (0.0, 0.0),
+            frame_mouse_delta: (0.0, 0.0),
+            listening: false,
+            captured_input: None,
+        };
+
+        // Gamepads plugged in before startup don't get a connected event on every platform
+        let connected: Vec<GamepadId> = input_system.gilrs.gamepads().map(|(id, _)| id).collect();
+        for id in connected {
+            input_system.connect_gamepad(id);
         }
+
+        input_system
     }
 
     pub fn add_action(mut self, name: &str, action_descriptor: ActionDescriptor) -> Self {
@@ -73,106 +130,472 @@ impl InputSystem {
         self
     }
 
+    /// Makes the named action change the cursor mode when one of its sources is pressed.
+    pub fn bind_cursor_action(mut self, action_name: &str, binding: CursorBinding) -> Self {
+        self.cursor_bindings
+            .insert(action_name.to_string(), binding);
+        self
+    }
+
+    pub fn cursor_mode(&self) -> CursorMode {
+        self.cursor_mode
+    }
+
+    pub fn set_cursor_mode(&mut self, mode: CursorMode) {
+        self.cursor_mode = mode;
+    }
+
     pub fn update(&mut self) {
         self.action_state_map.clear();
+        for states in self.player_states.values_mut() {
+            states.clear();
+        }
+    }
+
+    /// In physical pixels from the window's top left, `None` while outside the window.
+    pub fn cursor_position(&self) -> Option<(f32, f32)> {
+        self.input_helper.cursor()
+    }
+
+    /// Raw mouse motion over the last batch of events, unaffected by the cursor's acceleration
+    /// or the window's edges.
+    pub fn frame_mouse_delta(&self) -> (f32, f32) {
+        self.frame_mouse_delta
     }
 
+    /// Whether `button` went down since the last event batch.
+    pub fn mouse_pressed(&self, button: MouseButton) -> bool {
+        self.input_helper.mouse_pressed(button.into())
+    }
+
+    pub fn mouse_held(&self, button: MouseButton) -> bool {
+        self.input_helper.mouse_held(button.into())
+    }
+
+    /// Handles gamepads being plugged in and removed, then reads each player's gamepad through
+    /// their action map.
     pub fn update_gamepads(&mut self) {
         while let Some(event) = self.gilrs.next_event() {
-            if self.current_gamepad.is_none() {
-                self.current_gamepad = Some(event.id);
+            match event.event {
+                EventType::Connected => self.connect_gamepad(event.id),
+                EventType::Disconnected => self.disconnect_gamepad(event.id),
+                EventType::ButtonPressed(button, _) if self.listening => {
+                    self.capture(Source::Gamepad(GamepadSource::Button(button)))
+                }
+                EventType::AxisChanged(axis, value, _)
+                    if self.listening && value.abs() > GAMEPAD_CAPTURE_VALUE =>
+                {
+                    self.capture(Source::Gamepad(GamepadSource::Axis(axis)))
+                }
+                _ => {}
             }
         }
 
-        if let Some(gamepad_id) = self.current_gamepad {
-            let gamepad = self.gilrs.gamepad(gamepad_id);
-            if let Some(action_map) = self.action_map_map.get(&self.current_action_map) {
-                for (source, name) in action_map.map.iter() {
-                    match source {
-                        Source::Gamepad(GamepadSource::Axis(
-                            axis @ Axis::LeftStickY | axis @ Axis::LeftStickX,
-                        )) => {
-                            if let Some(axis_data) =
-                                gamepad.axis_data(*axis).filter(|v| v.value() != 0.0)
-                            {
-                                let name_clone = name.to_string();
-                                self.action_state_map.insert(
-                                    name_clone.clone(),
-                                    ActionState {
-                                        name: name_clone,
-                                        active: true,
-                                        active_state_changed_this_frame: false,
-                                        value: Some(axis_data.value()),
-                                    },
-                                );
-                            }
-                        }
+        let now = Instant::now();
+        self.rumbles.retain(|(_, _, end)| *end > now);
 
-                        Source::Gamepad(GamepadSource::Axis(
-                            axis @ Axis::RightStickY | axis @ Axis::RightStickX,
-                        )) => {
-                            if let Some(axis_data) =
-                                gamepad.axis_data(*axis).filter(|v| v.value() != 0.0)
-                            {
-                                let name_clone = name.to_string();
-                                self.action_state_map.insert(
-                                    name_clone.clone(),
-                                    ActionState {
-                                        name: name_clone,
-                                        active: true,
-                                        active_state_changed_this_frame: false,
-                                        value: Some(
-                                            axis_data.value()
-                                                * RIGHT_STICK_MULTIPLIER
-                                                * if *axis == Axis::RightStickY {
-                                                    INVERT_RIGHT_STICK_Y
-                                                } else {
-                                                    1.0
-                                                },
-                                        ),
-                                    },
-                                );
-                            }
-                        }
+        if self.listening {
+            return;
+        }
 
-                        _ => {}
-                    }
-                }
+        for (&player, &gamepad_id) in &self.players {
+            let map_name = self
+                .player_action_maps
+                .get(&player)
+                .unwrap_or(&self.current_action_map);
+            let Some(action_map) = self.action_map_map.get(map_name) else {
+                continue;
+            };
+            let states = if player == PlayerIndex::ONE {
+                &mut self.action_state_map
+            } else {
+                self.player_states.entry(player).or_default()
+            };
+            gamepad_action_states(&self.gilrs.gamepad(gamepad_id), action_map, states);
+        }
+    }
+
+    /// Gives a newly connected gamepad the first free player slot.
+    fn connect_gamepad(&mut self, id: GamepadId) {
+        if self.players.values().any(|&assigned| assigned == id) {
+            return;
+        }
+        let player = (0..MAX_PLAYERS)
+            .map(PlayerIndex)
+            .find(|player| !self.players.contains_key(player));
+        if let Some(player) = player {
+            self.players.insert(player, id);
+        }
+        let name = self.gilrs.gamepad(id).name().to_string();
+        log::info!("gamepad {} connected as player {:?}", name, player);
+        self.gamepad_events
+            .push(GamepadEvent::Connected { id, name, player });
+    }
+
+    fn disconnect_gamepad(&mut self, id: GamepadId) {
+        let player = self.player_of(id);
+        if let Some(player) = player {
+            self.players.remove(&player);
+            self.player_states.remove(&player);
+        }
+        self.rumbles.retain(|(gamepad, _, _)| *gamepad != id);
+        log::info!("gamepad {:?} disconnected from player {:?}", id, player);
+        self.gamepad_events
+            .push(GamepadEvent::Disconnected { id, player });
+    }
+
+    fn player_of(&self, id: GamepadId) -> Option<PlayerIndex> {
+        self.players
+            .iter()
+            .find_map(|(&player, &assigned)| (assigned == id).then_some(player))
+    }
+
+    /// Every connected gamepad.
+    pub fn gamepads(&self) -> Vec<GamepadInfo> {
+        self.gilrs
+            .gamepads()
+            .map(|(id, gamepad)| GamepadInfo {
+                id,
+                name: gamepad.name().to_string(),
+                player: self.player_of(id),
+            })
+            .collect()
+    }
+
+    /// Moves gamepad `id` to `player`, or leaves it unassigned when `None`. A gamepad that was
+    /// driving `player` is left unassigned.
+    pub fn assign_gamepad(&mut self, id: GamepadId, player: Option<PlayerIndex>) {
+        if let Some(previous) = self.player_of(id) {
+            self.players.remove(&previous);
+            self.player_states.remove(&previous);
+        }
+        if let Some(player) = player {
+            self.players.insert(player, id);
+        }
+    }
+
+    /// Reads `player`'s gamepad through the named action map rather than the current one.
+    pub fn set_player_action_map(&mut self, player: PlayerIndex, name: &str) -> anyhow::Result<()> {
+        if !self.action_map_map.contains_key(name) {
+            bail!("no action map named {}", name);
+        }
+        self.player_action_maps.insert(player, name.to_string());
+        Ok(())
+    }
+
+    /// The action states of `player` since the last update. The first player's include the
+    /// keyboard and mouse.
+    pub fn player_action_states(
+        &self,
+        player: PlayerIndex,
+    ) -> Option<&HashMap<String, ActionState>> {
+        if player == PlayerIndex::ONE {
+            Some(&self.action_state_map)
+        } else {
+            self.player_states.get(&player)
+        }
+    }
+
+    /// Every player's action states, see `player_action_states`.
+    pub fn all_player_action_states(&self) -> HashMap<PlayerIndex, HashMap<String, ActionState>> {
+        self.player_states
+            .iter()
+            .map(|(&player, states)| (player, states.clone()))
+            .chain([(PlayerIndex::ONE, self.action_state_map.clone())])
+            .collect()
+    }
+
+    /// Vibrates `player`'s gamepad. Does nothing when the player has no gamepad.
+    pub fn rumble(&mut self, player: PlayerIndex, rumble: Rumble) -> anyhow::Result<()> {
+        match self.players.get(&player) {
+            Some(&id) => self.rumble_gamepad(id, rumble),
+            None => Ok(()),
+        }
+    }
+
+    /// Vibrates gamepad `id`, alongside any rumble already playing on it. Gamepads without force
+    /// feedback are skipped.
+    pub fn rumble_gamepad(&mut self, id: GamepadId, rumble: Rumble) -> anyhow::Result<()> {
+        if !self
+            .gilrs
+            .connected_gamepad(id)
+            .is_some_and(|gamepad| gamepad.is_ff_supported())
+        {
+            return Ok(());
+        }
+
+        let effect = rumble
+            .effect_builder()
+            .gamepads(&[id])
+            .finish(&mut self.gilrs)
+            .context("creating rumble effect")?;
+        effect.play().context("playing rumble effect")?;
+        self.rumbles
+            .push((id, effect, Instant::now() + rumble.duration));
+        Ok(())
+    }
+
+    /// Stops every rumble playing on `player`'s gamepad.
+    pub fn stop_rumble(&mut self, player: PlayerIndex) {
+        let Some(&id) = self.players.get(&player) else {
+            return;
+        };
+        self.rumbles.retain(|(gamepad, _, _)| *gamepad != id);
+    }
+
+    /// Gamepad connections and disconnections since the last call.
+    pub fn take_gamepad_events(&mut self) -> Vec<GamepadEvent> {
+        std::mem::take(&mut self.gamepad_events)
+    }
+
+    /// How `source` is shown in UI, using the current keyboard layout's names for keys.
+    pub fn source_name(&self, source: &Source) -> String {
+        match source {
+            Source::Keyboard(code) => self
+                .key_names
+                .get(code)
+                .and_then(key_name)
+                .unwrap_or_else(|| key_code_name(*code)),
+            Source::LogicalKey(key) => key_name(key).unwrap_or_else(|| format!("{:?}", key)),
+            Source::Mouse(MouseSource::Button(button)) => format!("{:?} Mouse", button),
+            Source::Mouse(MouseSource::Move(axis)) | Source::Mouse(MouseSource::Scroll(axis)) => {
+                format!("{:?}", axis)
+            }
+            Source::Gamepad(GamepadSource::Axis(axis)) => format!("{:?}", axis),
+            Source::Gamepad(GamepadSource::Button(button)) => format!("{:?}", button),
+        }
+    }
+
+    /// The names of the sources bound to `action_name` in the current action map, sorted.
+    pub fn binding_names(&self, action_name: &str) -> Vec<String> {
+        let Some(action_map) = self.action_map_map.get(&self.current_action_map) else {
+            return vec![];
+        };
+        let mut names: Vec<String> = action_map
+            .map
+            .iter()
+            .filter(|(_, name)| *name == action_name)
+            .map(|(source, _)| self.source_name(source))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Starts listening for the next key, mouse button, mouse movement or gamepad input, for a
+    /// controls menu to bind. No action triggers until it's taken or listening is cancelled.
+    pub fn listen_for_input(&mut self) {
+        self.listening = true;
+        self.captured_input = None;
+    }
+
+    pub fn cancel_listening(&mut self) {
+        self.listening = false;
+        self.captured_input = None;
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.listening
+    }
+
+    /// The input captured since `listen_for_input`, which stops listening. `None` while still
+    /// waiting for one.
+    pub fn take_captured_input(&mut self) -> Option<Source> {
+        let captured = self.captured_input.take();
+        if captured.is_some() {
+            self.listening = false;
+        }
+        captured
+    }
+
+    fn capture(&mut self, source: Source) {
+        if self.captured_input.is_none() {
+            self.captured_input = Some(source);
+        }
+    }
+
+    /// Captures the first key or mouse button pressed while listening.
+    fn capture_winit_input(&mut self, event: &Event<()>) {
+        let Event::WindowEvent { event, .. } = event else {
+            return;
+        };
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(code),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => self.capture(Source::Keyboard(*code)),
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button,
+                ..
+            } => {
+                let button = match button {
+                    winit::event::MouseButton::Left => MouseButton::Left,
+                    winit::event::MouseButton::Right => MouseButton::Right,
+                    _ => return,
+                };
+                self.capture(Source::Mouse(MouseSource::Button(button)));
+            }
+            _ => {}
+        }
+    }
+
+    /// Captures the mouse axis it moved furthest along, once it's moved far enough.
+    fn capture_mouse_motion(&mut self) {
+        let (x, y) = self.frame_mouse_delta;
+        if x.abs().max(y.abs()) < MOUSE_CAPTURE_DISTANCE {
+            return;
+        }
+        let axis = if x.abs() >= y.abs() {
+            MouseAxis::MouseX
+        } else {
+            MouseAxis::MouseY
+        };
+        self.capture(Source::Mouse(MouseSource::Move(axis)));
+    }
+
+    /// Binds `source` to `action_name` in the current action map, in place of `previous` when
+    /// given. `source` stops triggering whatever action it was bound to.
+    pub fn rebind(
+        &mut self,
+        action_name: &str,
+        previous: Option<&Source>,
+        source: Source,
+    ) -> anyhow::Result<()> {
+        if !self.action_descriptor_map.contains_key(action_name) {
+            bail!("no action named {}", action_name);
+        }
+        let action_map = self
+            .action_map_map
+            .get_mut(&self.current_action_map)
+            .ok_or_else(|| anyhow!("no action map is current"))?;
+        if let Some(previous) = previous {
+            if action_map.map.get(previous).map(String::as_str) == Some(action_name) {
+                action_map.map.remove(previous);
+            }
+        }
+        action_map.map.insert(source, action_name.to_string());
+        Ok(())
+    }
+
+    /// Replaces the bindings of the action maps named in the bindings file at `path`.
+    pub fn load_bindings(&mut self, path: &Path) -> anyhow::Result<()> {
+        let text = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        let file: BindingsFile =
+            toml::from_str(&text).with_context(|| format!("parsing {:?}", path))?;
+        for (name, bindings) in file.maps {
+            let Some(action_map) = self.action_map_map.get_mut(&name) else {
+                log::warn!("{:?} binds an unknown action map {}", path, name);
+                continue;
+            };
+            action_map.map = bindings
+                .into_iter()
+                .map(|binding| (binding.source, binding.action))
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Writes every action map's bindings to `path`, to be read back by `load_bindings`.
+    pub fn save_bindings(&self, path: &Path) -> anyhow::Result<()> {
+        let maps = self
+            .action_map_map
+            .iter()
+            .map(|(name, action_map)| {
+                let mut bindings: Vec<Binding> = action_map
+                    .map
+                    .iter()
+                    .map(|(source, action)| Binding {
+                        action: action.clone(),
+                        source: source.clone(),
+                    })
+                    .collect();
+                bindings.sort_by(|a, b| a.action.cmp(&b.action));
+                (name.clone(), bindings)
+            })
+            .collect();
+        let text =
+            toml::to_string_pretty(&BindingsFile { maps }).context("serializing key bindings")?;
+        fs::write(path, text).with_context(|| format!("writing {:?}", path))
+    }
+
+    fn key_held(&self, source: &Source) -> bool {
+        match source {
+            Source::Keyboard(keycode) => self.input_helper.key_held(*keycode),
+            Source::LogicalKey(key) => self.input_helper.key_held_logical(key.as_ref()),
+            _ => false,
+        }
+    }
+
+    /// Learns the keyboard layout from the keys pressed, forgetting it when the window regains
+    /// focus in case the layout was switched meanwhile.
+    fn update_key_names(&mut self, event: &Event<()>) {
+        let Event::WindowEvent { event, .. } = event else {
+            return;
+        };
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(code),
+                        logical_key,
+                        ..
+                    },
+                ..
+            } => {
+                self.key_names.insert(*code, logical_key.clone());
             }
+            WindowEvent::Focused(true) => self.key_names.clear(),
+            _ => {}
         }
     }
 
-    pub fn process_winit_event(&mut self, event: &Event<()>, mouse_captured: bool) -> bool {
+    pub fn process_winit_event(&mut self, event: &Event<()>) -> bool {
+        self.update_key_names(event);
+        if let Event::DeviceEvent {
+            event: DeviceEvent::MouseMotion { delta },
+            ..
+        } = event
+        {
+            self.mouse_motion.0 += delta.0 as f32;
+            self.mouse_motion.1 += delta.1 as f32;
+        }
+        if self.listening {
+            self.capture_winit_input(event);
+        }
         if self.input_helper.update(event) {
+            self.frame_mouse_delta = std::mem::take(&mut self.mouse_motion);
+            if self.listening {
+                self.capture_mouse_motion();
+                return true;
+            }
+            self.update_cursor_mode();
+            let mouse_captured = self.cursor_mode == CursorMode::Captured;
             if let Some(action_map) = self.action_map_map.get(&self.current_action_map) {
                 for (source, name) in action_map.map.iter() {
                     match source {
-                        Source::Keyboard(keycode) => {
-                            if self.input_helper.key_held(*keycode) {
-                                self.action_state_map.insert(
-                                    name.to_string(),
-                                    ActionState {
-                                        name: name.to_string(),
-                                        active: true,
-                                        active_state_changed_this_frame: false,
-                                        value: None,
-                                    },
-                                );
+                        Source::Keyboard(_) | Source::LogicalKey(_) => {
+                            if self.key_held(source) {
+                                self.action_state_map
+                                    .insert(name.to_string(), ActionState::new(name, None));
                             }
                         }
 
                         Source::Mouse(MouseSource::Move(axis)) => {
                             if mouse_captured {
-                                let mouse_diff = self.input_helper.mouse_diff();
+                                let mouse_diff = self.frame_mouse_delta;
                                 match axis {
                                     MouseAxis::MouseX => {
                                         self.action_state_map.insert(
                                             name.to_string(),
                                             ActionState {
-                                                name: name.to_string(),
-                                                active: true,
-                                                active_state_changed_this_frame: false,
-                                                value: Some(mouse_diff.0),
+                                                delta: true,
+                                                ..ActionState::new(name, Some(mouse_diff.0))
                                             },
                                         );
                                     }
@@ -180,10 +603,8 @@ impl InputSystem {
                                         self.action_state_map.insert(
                                             name.to_string(),
                                             ActionState {
-                                                name: name.to_string(),
-                                                active: true,
-                                                active_state_changed_this_frame: false,
-                                                value: Some(mouse_diff.1),
+                                                delta: true,
+                                                ..ActionState::new(name, Some(mouse_diff.1))
                                             },
                                         );
                                     }
@@ -198,6 +619,36 @@ impl InputSystem {
         true
     }
 
+    /// Applies cursor bindings whose sources were pressed since the last update.
+    fn update_cursor_mode(&mut self) {
+        let Some(action_map) = self.action_map_map.get(&self.current_action_map) else {
+            return;
+        };
+
+        for (source, name) in action_map.map.iter() {
+            let Some(binding) = self.cursor_bindings.get(name) else {
+                continue;
+            };
+
+            let triggered = match source {
+                Source::Keyboard(keycode) => self.input_helper.key_pressed(*keycode),
+                Source::LogicalKey(key) => self.input_helper.key_pressed_logical(key.as_ref()),
+                Source::Mouse(MouseSource::Button(button)) => {
+                    self.input_helper.mouse_released((*button).into())
+                }
+                _ => false,
+            };
+
+            if triggered {
+                self.cursor_mode = match binding {
+                    CursorBinding::Capture => CursorMode::Captured,
+                    CursorBinding::Release => CursorMode::Free,
+                    CursorBinding::Toggle => self.cursor_mode.toggled(),
+                };
+            }
+        }
+    }
+
     #[allow(dead_code)]
     pub fn get_action_state(&self, action_name: &str) -> Option<&ActionState> {
         self.action_state_map.get(action_name)
@@ -218,9 +669,56 @@ impl InputSystem {
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Eq, Hash, PartialEq)]
+/// Fills `states` from the sources of `action_map` that `gamepad` has active.
+fn gamepad_action_states(
+    gamepad: &Gamepad,
+    action_map: &ActionMap,
+    states: &mut HashMap<String, ActionState>,
+) {
+    for (source, name) in action_map.map.iter() {
+        let value = match source {
+            Source::Gamepad(GamepadSource::Axis(
+                axis @ Axis::LeftStickY | axis @ Axis::LeftStickX,
+            )) => match gamepad.axis_data(*axis).filter(|v| v.value() != 0.0) {
+                Some(axis_data) => Some(axis_data.value()),
+                None => continue,
+            },
+
+            Source::Gamepad(GamepadSource::Axis(
+                axis @ Axis::RightStickY | axis @ Axis::RightStickX,
+            )) => match gamepad.axis_data(*axis).filter(|v| v.value() != 0.0) {
+                Some(axis_data) => Some(
+                    axis_data.value()
+                        * RIGHT_STICK_MULTIPLIER
+                        * if *axis == Axis::RightStickY {
+                            INVERT_RIGHT_STICK_Y
+                        } else {
+                            1.0
+                        },
+                ),
+                None => continue,
+            },
+
+            Source::Gamepad(GamepadSource::Button(button)) if gamepad.is_pressed(*button) => None,
+
+            _ => continue,
+        };
+
+        states.insert(name.to_string(), ActionState::new(name, value));
+    }
+}
+
+#[derive(Debug, Eq, Hash, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum MouseButton {
     Left,
     Right,
 }
+
+impl From<MouseButton> for winit::event::MouseButton {
+    fn from(value: MouseButton) -> Self {
+        match value {
+            MouseButton::Left => winit::event::MouseButton::Left,
+            MouseButton::Right => winit::event::MouseButton::Right,
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::{collections::HashMap, time::Duration};
+
+use super::sources::ActionState;
+
+/// How long an action is held before it starts repeating.
+const REPEAT_DELAY: Duration = Duration::from_millis(400);
+const REPEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Follows actions across fixed updates, filling in when they were pressed and released and
+/// how long they've been held.
+#[derive(Debug, Default)]
+pub struct ActionTracker {
+    /// How long each held action has been held.
+    held: HashMap<String, Duration>,
+}
+
+impl ActionTracker {
+    /// The states of the `active` actions after `elapsed`, plus an inactive state for each action
+    /// released since the last update.
+    pub fn update(
+        &mut self,
+        active: &HashMap<String, ActionState>,
+        elapsed: Duration,
+    ) -> HashMap<String, ActionState> {
+        let mut states: HashMap<String, ActionState> = self
+            .held
+            .iter()
+            .filter(|(name, _)| !active.contains_key(*name))
+            .map(|(name, &held_for)| {
+                let mut state = ActionState::new(name, None);
+                state.active = false;
+                state.active_state_changed_this_frame = true;
+                state.just_released = true;
+                state.held_for = held_for;
+                (name.clone(), state)
+            })
+            .collect();
+        self.held.retain(|name, _| active.contains_key(name));
+
+        for (name, state) in active {
+            let previous = self.held.get(name).copied();
+            let held_for = previous.map_or(Duration::ZERO, |held_for| held_for + elapsed);
+            self.held.insert(name.clone(), held_for);
+
+            let repeat_count = repeats(held_for);
+            let mut state = state.clone();
+            state.just_pressed = previous.is_none();
+            state.active_state_changed_this_frame = previous.is_none();
+            state.held_for = held_for;
+            state.repeat_count = repeat_count;
+            state.repeated = previous.map_or(true, |previous| repeats(previous) != repeat_count);
+            states.insert(name.clone(), state);
+        }
+        states
+    }
+}
+
+/// How many times an action held for `held_for` has repeated.
+fn repeats(held_for: Duration) -> u32 {
+    held_for.checked_sub(REPEAT_DELAY).map_or(0, |repeating| {
+        1 + (repeating.as_nanos() / REPEAT_INTERVAL.as_nanos()) as u32
+    })
+}
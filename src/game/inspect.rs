@@ -0,0 +1,354 @@
+use cgmath::{Deg, Euler, Quaternion};
+use specs::{Component, Entity, Join, World, WorldExt};
+
+use crate::renderer::Material;
+
+use super::components::{
+    render::{DirectionalLightComponent, PointLightComponent},
+    transform::Transform,
+    Camera, CameraEffect, TimelineCommand,
+};
+use super::voxel::Voxel;
+
+/// A component whose fields can be read and edited by name, as scalars.
+pub trait Inspect {
+    const NAME: &'static str;
+
+    fn fields(&self) -> Vec<Field>;
+
+    /// Sets the field called `name`, returning false when there's no such field.
+    fn set_field(&mut self, name: &str, value: f32) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub value: f32,
+    /// How far one nudge in the inspector moves the value.
+    pub step: f32,
+}
+
+impl Field {
+    fn new(name: &'static str, value: f32, step: f32) -> Self {
+        Field { name, value, step }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentFields {
+    pub component: &'static str,
+    pub fields: Vec<Field>,
+}
+
+/// A change to one field of an entity's component.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldEdit {
+    pub entity: u32,
+    pub component: &'static str,
+    pub field: &'static str,
+    pub value: f32,
+}
+
+/// A change the editor or console makes to the simulation world.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    Field(FieldEdit),
+    /// Replaces an entity's whole transform, e.g. while dragging a gizmo.
+    Transform {
+        entity: u32,
+        transform: Transform,
+    },
+    /// Creates a registered prefab.
+    Spawn {
+        prefab: String,
+        transform: Transform,
+    },
+    /// Changes the `TimeOfDay`, leaving what isn't given as it is.
+    TimeOfDay {
+        hour: Option<f32>,
+        paused: Option<bool>,
+    },
+    /// Sets every voxel of the `VoxelWorld` from `min` to `max`, both included.
+    Voxels {
+        min: [i32; 3],
+        max: [i32; 3],
+        voxel: Voxel,
+    },
+    /// Adds to the `VoxelWorld`'s densities around a point, see `VoxelWorld::sculpt`.
+    Sculpt {
+        center: [f32; 3],
+        radius: f32,
+        amount: f32,
+        material: Voxel,
+    },
+    /// Sets off an effect on the `ActiveCamera`, giving it `CameraEffects` when it has none.
+    CameraEffect(CameraEffect),
+    Timeline(TimelineCommand),
+}
+
+/// What the editor shows of the simulation world.
+#[derive(Debug, Clone, Default)]
+pub struct EditorView {
+    /// Every live entity by id, with the names of its inspectable components.
+    pub entities: Vec<(u32, Vec<&'static str>)>,
+    /// The inspected entity and its components' fields.
+    pub inspected: Option<(u32, Vec<ComponentFields>)>,
+    /// The inspected entity's transform, if it has one.
+    pub transform: Option<Transform>,
+}
+
+struct InspectedComponent {
+    name: &'static str,
+    read: fn(&World, Entity) -> Option<Vec<Field>>,
+    write: fn(&World, Entity, &str, f32) -> bool,
+}
+
+/// The components the editor can see and edit, looked up by name.
+#[derive(Default)]
+pub struct Inspector {
+    components: Vec<InspectedComponent>,
+}
+
+impl Inspector {
+    pub fn register<T: Inspect + Component>(mut self) -> Self {
+        self.components.push(InspectedComponent {
+            name: T::NAME,
+            read: |world, entity| world.read_storage::<T>().get(entity).map(T::fields),
+            write: |world, entity, field, value| {
+                world
+                    .write_storage::<T>()
+                    .get_mut(entity)
+                    .is_some_and(|component| component.set_field(field, value))
+            },
+        });
+        self
+    }
+
+    /// The engine's own inspectable components.
+    pub fn with_engine_components(self) -> Self {
+        self.register::<Transform>()
+            .register::<Camera>()
+            .register::<PointLightComponent>()
+            .register::<DirectionalLightComponent>()
+    }
+
+    /// Captures the entity list, and the fields of `inspected` if it's alive.
+    pub fn view(&self, world: &World, inspected: Option<u32>) -> EditorView {
+        let entities = world.entities();
+        let names = |entity: Entity| {
+            self.components
+                .iter()
+                .filter(|component| (component.read)(world, entity).is_some())
+                .map(|component| component.name)
+                .collect()
+        };
+
+        let inspected = inspected
+            .map(|id| entities.entity(id))
+            .filter(|entity| entities.is_alive(*entity));
+
+        EditorView {
+            entities: entities
+                .join()
+                .map(|entity| (entity.id(), names(entity)))
+                .collect(),
+            inspected: inspected.map(|entity| {
+                let components = self
+                    .components
+                    .iter()
+                    .filter_map(|component| {
+                        (component.read)(world, entity).map(|fields| ComponentFields {
+                            component: component.name,
+                            fields,
+                        })
+                    })
+                    .collect();
+                (entity.id(), components)
+            }),
+            transform: inspected
+                .and_then(|entity| world.read_storage::<Transform>().get(entity).copied()),
+        }
+    }
+
+    /// Applies `edit`, logging edits of dead entities or unknown fields.
+    pub fn apply(&self, world: &World, edit: &FieldEdit) {
+        if !self.write(world, edit.entity, edit.component, edit.field, edit.value) {
+            log::warn!("ignoring edit of {:?}", edit);
+        }
+    }
+
+    /// Sets a field by name, returning false for dead entities and unknown fields.
+    pub fn write(
+        &self,
+        world: &World,
+        entity: u32,
+        component: &str,
+        field: &str,
+        value: f32,
+    ) -> bool {
+        let entity = world.entities().entity(entity);
+        world.entities().is_alive(entity)
+            && self
+                .components
+                .iter()
+                .find(|registered| registered.name == component)
+                .is_some_and(|registered| (registered.write)(world, entity, field, value))
+    }
+}
+
+impl Inspect for Transform {
+    const NAME: &'static str = "Transform";
+
+    fn fields(&self) -> Vec<Field> {
+        let euler = Euler::from(self.rotation);
+        vec![
+            Field::new("position.x", self.position.x, 0.1),
+            Field::new("position.y", self.position.y, 0.1),
+            Field::new("position.z", self.position.z, 0.1),
+            Field::new("rotation.x", Deg::from(euler.x).0, 5.0),
+            Field::new("rotation.y", Deg::from(euler.y).0, 5.0),
+            Field::new("rotation.z", Deg::from(euler.z).0, 5.0),
+            Field::new("scale.x", self.scale.x, 0.1),
+            Field::new("scale.y", self.scale.y, 0.1),
+            Field::new("scale.z", self.scale.z, 0.1),
+        ]
+    }
+
+    fn set_field(&mut self, name: &str, value: f32) -> bool {
+        let mut euler = Euler::from(self.rotation);
+        match name {
+            "position.x" => self.position.x = value,
+            "position.y" => self.position.y = value,
+            "position.z" => self.position.z = value,
+            "rotation.x" => euler.x = Deg(value).into(),
+            "rotation.y" => euler.y = Deg(value).into(),
+            "rotation.z" => euler.z = Deg(value).into(),
+            "scale.x" => self.scale.x = value,
+            "scale.y" => self.scale.y = value,
+            "scale.z" => self.scale.z = value,
+            _ => return false,
+        }
+        if name.starts_with("rotation") {
+            self.rotation = Quaternion::from(euler);
+        }
+        true
+    }
+}
+
+impl Inspect for Camera {
+    const NAME: &'static str = "Camera";
+
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::new("fov", self.fov.0, 5.0),
+            Field::new("near", self.near, 0.05),
+            Field::new("far", self.far, 10.0),
+            Field::new("position.x", self.position.x, 0.1),
+            Field::new("position.y", self.position.y, 0.1),
+            Field::new("position.z", self.position.z, 0.1),
+        ]
+    }
+
+    fn set_field(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "fov" => self.fov = Deg(value.clamp(1.0, 179.0)),
+            "near" => self.near = value.max(0.001),
+            "far" => self.far = value.max(self.near + 0.001),
+            "position.x" => self.position.x = value,
+            "position.y" => self.position.y = value,
+            "position.z" => self.position.z = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Inspect for PointLightComponent {
+    const NAME: &'static str = "PointLight";
+
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::new("color.r", self.color[0], 0.1),
+            Field::new("color.g", self.color[1], 0.1),
+            Field::new("color.b", self.color[2], 0.1),
+            Field::new("intensity", self.intensity, 100.0),
+            Field::new("radius", self.radius, 0.5),
+        ]
+    }
+
+    fn set_field(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "color.r" => self.color[0] = value.max(0.0),
+            "color.g" => self.color[1] = value.max(0.0),
+            "color.b" => self.color[2] = value.max(0.0),
+            "intensity" => self.intensity = value.max(0.0),
+            "radius" => self.radius = value.max(0.0),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Inspect for Material {
+    const NAME: &'static str = "Material";
+
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::new("base_color.r", self.base_color[0], 0.05),
+            Field::new("base_color.g", self.base_color[1], 0.05),
+            Field::new("base_color.b", self.base_color[2], 0.05),
+            Field::new("base_color.a", self.base_color[3], 0.05),
+            Field::new("metallic", self.metallic, 0.05),
+            Field::new("roughness", self.roughness, 0.05),
+            Field::new("emissive.r", self.emissive[0], 0.5),
+            Field::new("emissive.g", self.emissive[1], 0.5),
+            Field::new("emissive.b", self.emissive[2], 0.5),
+        ]
+    }
+
+    fn set_field(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "base_color.r" => self.base_color[0] = value.clamp(0.0, 1.0),
+            "base_color.g" => self.base_color[1] = value.clamp(0.0, 1.0),
+            "base_color.b" => self.base_color[2] = value.clamp(0.0, 1.0),
+            "base_color.a" => self.base_color[3] = value.clamp(0.0, 1.0),
+            "metallic" => self.metallic = value.clamp(0.0, 1.0),
+            "roughness" => self.roughness = value.clamp(0.0, 1.0),
+            "emissive.r" => self.emissive[0] = value.max(0.0),
+            "emissive.g" => self.emissive[1] = value.max(0.0),
+            "emissive.b" => self.emissive[2] = value.max(0.0),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Inspect for DirectionalLightComponent {
+    const NAME: &'static str = "DirectionalLight";
+
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::new("direction.x", self.direction.x, 0.05),
+            Field::new("direction.y", self.direction.y, 0.05),
+            Field::new("direction.z", self.direction.z, 0.05),
+            Field::new("color.r", self.color[0], 0.1),
+            Field::new("color.g", self.color[1], 0.1),
+            Field::new("color.b", self.color[2], 0.1),
+            Field::new("illuminance", self.illuminance, 100.0),
+        ]
+    }
+
+    fn set_field(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "direction.x" => self.direction.x = value,
+            "direction.y" => self.direction.y = value,
+            "direction.z" => self.direction.z = value,
+            "color.r" => self.color[0] = value.max(0.0),
+            "color.g" => self.color[1] = value.max(0.0),
+            "color.b" => self.color[2] = value.max(0.0),
+            "illuminance" => self.illuminance = value.max(0.0),
+            _ => return false,
+        }
+        true
+    }
+}
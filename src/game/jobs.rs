@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+
+/// Chunks handed to the pool per worker thread, so uneven work still balances.
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// The engine's worker threads, available to systems as a `ReadExpect<JobSystem>` resource.
+///
+/// The simulation's dispatcher runs on the same pool, so jobs started from a system share threads
+/// with the systems running alongside it instead of oversubscribing the CPU. Every chunk of work
+/// is wrapped in a span named after its job.
+#[derive(Clone)]
+pub struct JobSystem {
+    pool: Arc<ThreadPool>,
+}
+
+impl JobSystem {
+    /// Creates a pool with `threads` workers, or one per logical CPU when `None`.
+    pub fn new(threads: Option<usize>) -> anyhow::Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads.unwrap_or(0))
+            .thread_name(|index| format!("job worker {}", index))
+            .build()
+            .context("creating job thread pool")?;
+
+        Ok(JobSystem {
+            pool: Arc::new(pool),
+        })
+    }
+
+    pub fn pool(&self) -> &Arc<ThreadPool> {
+        &self.pool
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Calls `job` on every item in parallel.
+    pub fn for_each<T, F>(&self, name: &'static str, items: &[T], job: F)
+    where
+        T: Sync,
+        F: Fn(&T) + Sync + Send,
+    {
+        let chunk_size = self.chunk_size(items.len());
+        self.pool.install(|| {
+            items.par_chunks(chunk_size).for_each(|chunk| {
+                profile_scope!("job", name);
+                chunk.iter().for_each(&job);
+            })
+        });
+    }
+
+    /// Calls `job` on every item in parallel, with mutable access.
+    pub fn for_each_mut<T, F>(&self, name: &'static str, items: &mut [T], job: F)
+    where
+        T: Send,
+        F: Fn(&mut T) + Sync + Send,
+    {
+        let chunk_size = self.chunk_size(items.len());
+        self.pool.install(|| {
+            items.par_chunks_mut(chunk_size).for_each(|chunk| {
+                profile_scope!("job", name);
+                chunk.iter_mut().for_each(&job);
+            })
+        });
+    }
+
+    /// Calls `job` on every item in parallel along with the item at the same index of `with`, e.g.
+    /// to integrate one array of a struct of arrays from another.
+    pub fn for_each_zip_mut<T, U, F>(&self, name: &'static str, items: &mut [T], with: &[U], job: F)
+    where
+        T: Send,
+        U: Sync,
+        F: Fn(&mut T, &U) + Sync + Send,
+    {
+        let chunk_size = self.chunk_size(items.len());
+        self.pool.install(|| {
+            items
+                .par_chunks_mut(chunk_size)
+                .zip(with.par_chunks(chunk_size))
+                .for_each(|(chunk, with)| {
+                    profile_scope!("job", name);
+                    chunk
+                        .iter_mut()
+                        .zip(with)
+                        .for_each(|(item, with)| job(item, with));
+                })
+        });
+    }
+
+    /// Maps every item in parallel, keeping the results in order.
+    pub fn map<T, R, F>(&self, name: &'static str, items: &[T], job: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync + Send,
+    {
+        let chunk_size = self.chunk_size(items.len());
+        self.pool.install(|| {
+            items
+                .par_chunks(chunk_size)
+                .flat_map_iter(|chunk| {
+                    profile_scope!("job", name);
+                    chunk.iter().map(&job).collect::<Vec<_>>()
+                })
+                .collect()
+        })
+    }
+
+    /// Runs two jobs in parallel and returns both results.
+    pub fn join<A, B, RA, RB>(&self, name: &'static str, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        self.pool.install(|| {
+            rayon::join(
+                || {
+                    profile_scope!("job", name);
+                    a()
+                },
+                || {
+                    profile_scope!("job", name);
+                    b()
+                },
+            )
+        })
+    }
+
+    /// Runs `job` in the background without waiting for it, for work that can finish on a later
+    /// tick. Results have to be sent back, e.g. over a channel.
+    pub fn spawn<F>(&self, name: &'static str, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.pool.spawn(move || {
+            profile_scope!("job", name);
+            job()
+        });
+    }
+
+    fn chunk_size(&self, len: usize) -> usize {
+        len.div_ceil(self.thread_count() * CHUNKS_PER_THREAD).max(1)
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+
+use cgmath::{Matrix4, Vector3, VectorSpace};
+
+use super::simulation::Snapshot;
+
+/// Whether an anchored point could be seen this frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnchorVisibility {
+    OnScreen,
+    /// In front of the camera but outside the viewport.
+    OffScreen,
+    BehindCamera,
+}
+
+/// Where a world position lands on screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenAnchor {
+    /// In pixels from the viewport's top left. For points behind the camera, mirrored so it
+    /// points the way the camera would have to turn.
+    pub position: [f32; 2],
+    /// Clip space w, the distance along the camera's view direction. Negative behind it.
+    pub depth: f32,
+    pub visibility: AnchorVisibility,
+    /// Whether `position` was moved onto the viewport's edge.
+    pub clamped: bool,
+}
+
+/// Where `point` lands on a `viewport` of the given size, in pixels from its top left. With a
+/// `clamp_margin`, points off screen or behind the camera are pulled along the line from the
+/// viewport's center to stay that far inside its edges, e.g. for waypoint arrows.
+pub fn screen_anchor(
+    view_projection: Matrix4<f32>,
+    point: Vector3<f32>,
+    viewport: [f32; 2],
+    clamp_margin: Option<f32>,
+) -> ScreenAnchor {
+    let clip = view_projection * point.extend(1.0);
+    let behind = clip.w <= 0.0;
+    // Dividing by |w| keeps points behind the camera on the side they'd be turned toward,
+    // which the flip then mirrors to where they are
+    let w = clip.w.abs().max(f32::EPSILON);
+    let (mut x, mut y) = (clip.x / w, clip.y / w);
+    if behind {
+        (x, y) = (-x, -y);
+    }
+    let inside = !behind && x.abs() <= 1.0 && y.abs() <= 1.0;
+    let visibility = match (behind, inside) {
+        (true, _) => AnchorVisibility::BehindCamera,
+        (false, true) => AnchorVisibility::OnScreen,
+        (false, false) => AnchorVisibility::OffScreen,
+    };
+
+    let half = [viewport[0] * 0.5, viewport[1] * 0.5];
+    let mut position = [(x + 1.0) * half[0], (y + 1.0) * half[1]];
+    let mut clamped = false;
+    if let (Some(margin), false) = (clamp_margin, inside) {
+        let extent = [(half[0] - margin).max(0.0), (half[1] - margin).max(0.0)];
+        let offset = [position[0] - half[0], position[1] - half[1]];
+        // A point straight behind the camera has no direction, send it to the bottom edge
+        let offset = if offset[0] == 0.0 && offset[1] == 0.0 {
+            [0.0, 1.0]
+        } else {
+            offset
+        };
+        let scale = (extent[0] / offset[0].abs()).min(extent[1] / offset[1].abs());
+        if behind || scale < 1.0 {
+            position = [half[0] + offset[0] * scale, half[1] + offset[1] * scale];
+            clamped = true;
+        }
+    }
+
+    ScreenAnchor {
+        position,
+        depth: clip.w,
+        visibility,
+        clamped,
+    }
+}
+
+/// Names a marker added with `Markers::add`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MarkerId(u64);
+
+/// A point that follows an entity, offset from its position, e.g. the top of its health bar.
+#[derive(Debug, Clone, Copy)]
+pub struct WorldMarker {
+    pub entity: u32,
+    pub offset: Vector3<f32>,
+    /// See `screen_anchor`. `None` lets markers leave the screen, e.g. for health bars.
+    pub clamp_margin: Option<f32>,
+}
+
+/// Projects markers to screen space every frame, following the entities as they're drawn.
+#[derive(Default)]
+pub struct Markers {
+    next_id: u64,
+    markers: BTreeMap<MarkerId, WorldMarker>,
+    anchors: Vec<(MarkerId, ScreenAnchor)>,
+}
+
+impl Markers {
+    pub fn add(&mut self, marker: WorldMarker) -> MarkerId {
+        let id = MarkerId(self.next_id);
+        self.next_id += 1;
+        self.markers.insert(id, marker);
+        id
+    }
+
+    pub fn remove(&mut self, id: MarkerId) {
+        self.markers.remove(&id);
+        self.anchors.retain(|(anchor, _)| *anchor != id);
+    }
+
+    /// This frame's anchors, in the order the markers were added. Markers whose entity isn't
+    /// drawn have none.
+    pub fn anchors(&self) -> &[(MarkerId, ScreenAnchor)] {
+        &self.anchors
+    }
+
+    /// Projects the markers through the camera and entity positions blended between `previous`
+    /// and `current`, as the render system draws them, onto a `viewport` of the given size.
+    pub fn update(
+        &mut self,
+        previous: &Snapshot,
+        current: &Snapshot,
+        blend: f32,
+        viewport: [f32; 2],
+    ) {
+        self.anchors.clear();
+        let Some(camera) = current.camera else {
+            return;
+        };
+        let camera = previous
+            .camera
+            .map(|previous| previous.interpolate(&camera, blend))
+            .unwrap_or(camera);
+        let view_projection = camera.view_projection();
+
+        for (id, marker) in &self.markers {
+            let Some(position) = entity_position(previous, current, blend, marker.entity) else {
+                continue;
+            };
+            self.anchors.push((
+                *id,
+                screen_anchor(
+                    view_projection,
+                    position + marker.offset,
+                    viewport,
+                    marker.clamp_margin,
+                ),
+            ));
+        }
+    }
+}
+
+/// Where the renderer draws `entity`, if it's an object, light or billboard.
+fn entity_position(
+    previous: &Snapshot,
+    current: &Snapshot,
+    blend: f32,
+    entity: u32,
+) -> Option<Vector3<f32>> {
+    let position = |snapshot: &Snapshot| {
+        snapshot
+            .object(entity)
+            .map(|object| object.transform.position)
+            .or_else(|| snapshot.light(entity).map(|light| light.position))
+            .or_else(|| {
+                snapshot
+                    .billboard(entity)
+                    .map(|billboard| billboard.billboard.position)
+            })
+    };
+    let current = position(current)?;
+    Some(
+        position(previous)
+            .map(|previous| previous.lerp(current, blend))
+            .unwrap_or(current),
+    )
+}
@@ -1,7 +1,63 @@
-pub use components::transform::Transform;
+pub use ai::{BehaviorNode, BehaviorStatus, BehaviorTree, Steering, SteeringBehavior};
+pub use camera_math::{frustum_corners, screen_to_world_ray, Frustum, Plane, Ray};
+pub use codec::ComponentCodecs;
+pub use components::transform::{Spin, Transform};
+pub use components::ParticleEmitter;
+pub use components::VisibilityResource;
+pub use components::{Bounds, EntityKey, RayHit, RayQuery, SpatialIndex};
+pub use components::{BudgetExceeded, BudgetMetric, PerformanceBudget};
+pub use components::{Camera, ViewModel};
+pub use components::{CameraEffect, CameraEffects, CameraOffset, CameraShake};
+pub use components::{CameraKey, Cue, Keyframe, Timeline, Track};
+pub use components::{Easing, Tween, TweenLoop, TweenTarget};
+pub use components::{Sky, SkyKeyframe, TimeOfDay, HOURS_PER_DAY};
+pub use components::{TimelineCommand, TimelineEvent, TimelineStatus, Timelines};
+pub use console::CommandContext;
+pub use focus::{FocusBehavior, FocusEvent};
+pub use foliage::{DensityMap, FoliageScatter};
 pub use game_loop::GameLoop;
+pub use input::CursorMode;
+pub use input::{GamepadEvent, GamepadInfo, PlayerIndex};
+pub use input::{GamepadSource, MouseAxis, MouseSource, Source, SystemMouseButton};
+pub use input::{Rumble, RumbleEnvelope, RumbleQueue};
+pub use jobs::JobSystem;
+pub use marker::{AnchorVisibility, MarkerId, ScreenAnchor, WorldMarker};
+pub use nav::{find_path, NavMesh, NavMeshResource, NavMeshSettings, NavPolygon};
+pub use nav::{PathRequest, PathResult};
+#[cfg(feature = "net")]
+pub use net::{NetClient, NetServer, NetSession, RemoteInputs, Replicated};
+pub use replay::{Divergence, Replay, ReplayStatus};
+pub use save::{SaveData, SaveRegistry, Saved};
+#[cfg(feature = "lua")]
+pub use script::{ScriptComponents, ScriptRunner};
+pub use stress::StressScene;
+pub use voxel::{greedy_mesh, Chunk, PaddedChunk, Voxel, VoxelPalette, CHUNK_SIZE};
+pub use voxel::{marching_cubes, ChunkMesh, VoxelChunk, VoxelMesher, VoxelWorld};
 
+mod ai;
+mod camera_math;
+mod clipboard;
+mod codec;
 mod components;
+mod console;
 mod context;
+mod editor;
+mod focus;
+mod foliage;
 mod game_loop;
+mod gizmo;
 mod input;
+mod inspect;
+mod jobs;
+mod marker;
+mod nav;
+#[cfg(feature = "net")]
+mod net;
+mod prefab;
+mod replay;
+mod save;
+#[cfg(feature = "lua")]
+mod script;
+mod simulation;
+mod stress;
+mod voxel;
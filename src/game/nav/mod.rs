@@ -0,0 +1,7 @@
+pub use navmesh::{NavMesh, NavMeshSettings, NavPolygon};
+pub use path::find_path;
+pub use system::{NavMeshResource, PathRequest, PathResult, PathResults, PathfindingSystem};
+
+mod navmesh;
+mod path;
+mod system;
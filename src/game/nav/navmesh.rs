@@ -0,0 +1,236 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::Context;
+use cgmath::{Angle, Deg, InnerSpace, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::game::components::DebugLine;
+
+/// How far above the navmesh its debug lines are drawn, to keep them out of the floor.
+const DEBUG_LIFT: f32 = 0.05;
+const EDGE_COLOR: [f32; 4] = [0.2, 0.6, 1.0, 0.4];
+const BORDER_COLOR: [f32; 4] = [0.2, 0.8, 1.0, 1.0];
+
+/// Where agents can walk, used when baking a navmesh from level geometry.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NavMeshSettings {
+    /// The steepest slope that's walkable.
+    pub max_slope: Deg<f32>,
+    /// Vertices closer than this are merged, joining up separately modelled pieces of floor.
+    pub weld_distance: f32,
+}
+
+impl Default for NavMeshSettings {
+    fn default() -> Self {
+        NavMeshSettings {
+            max_slope: Deg(45.0),
+            weld_distance: 0.01,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NavPolygon {
+    /// Indices into `NavMesh::vertices`.
+    pub vertices: [u32; 3],
+    /// The polygon across each edge, edge `i` running from vertex `i` to the next.
+    pub neighbours: [Option<u32>; 3],
+}
+
+/// The walkable surface of a level as connected triangles, with y up. Agents are treated as
+/// points, so baked meshes run right up to walls.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NavMesh {
+    pub vertices: Vec<Vector3<f32>>,
+    pub polygons: Vec<NavPolygon>,
+}
+
+impl NavMesh {
+    /// Keeps the triangles of `indices` facing up no steeper than the settings allow, joined
+    /// where they share an edge after welding. Triangles are front facing when counter-clockwise.
+    pub fn bake(positions: &[Vector3<f32>], indices: &[u32], settings: &NavMeshSettings) -> Self {
+        let weld = settings.weld_distance.max(f32::EPSILON);
+        let mut welded: HashMap<[i64; 3], u32> = HashMap::new();
+        let mut vertices = vec![];
+        let remap: Vec<u32> = positions
+            .iter()
+            .map(|position| {
+                let key = [position.x, position.y, position.z].map(|c| (c / weld).round() as i64);
+                *welded.entry(key).or_insert_with(|| {
+                    vertices.push(*position);
+                    vertices.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        let min_up = settings.max_slope.cos();
+        let mut polygons: Vec<NavPolygon> = indices
+            .chunks_exact(3)
+            .filter_map(|triangle| {
+                let corners = [0, 1, 2].map(|i| remap.get(triangle[i] as usize).copied());
+                let [Some(a), Some(b), Some(c)] = corners else {
+                    return None;
+                };
+                if a == b || b == c || c == a {
+                    return None;
+                }
+                let [pa, pb, pc] = [a, b, c].map(|index| vertices[index as usize]);
+                let normal = (pb - pa).cross(pc - pa);
+                if normal.magnitude2() <= f32::EPSILON || normal.normalize().y < min_up {
+                    return None;
+                }
+                Some(NavPolygon {
+                    vertices: [a, b, c],
+                    neighbours: [None; 3],
+                })
+            })
+            .collect();
+
+        let mut edges = HashMap::new();
+        for (index, polygon) in polygons.iter().enumerate() {
+            for edge in 0..3 {
+                let (from, to) = edge_vertices(polygon, edge);
+                edges.insert((from, to), index as u32);
+            }
+        }
+        for polygon in &mut polygons {
+            for edge in 0..3 {
+                let (from, to) = edge_vertices(polygon, edge);
+                // Neighbours wind the same way, so they run along a shared edge backwards
+                polygon.neighbours[edge] = edges.get(&(to, from)).copied();
+            }
+        }
+
+        NavMesh { vertices, polygons }
+    }
+
+    /// Bakes the navmesh of every mesh in an OBJ file.
+    #[cfg(feature = "obj")]
+    pub fn bake_obj(path: &Path, settings: &NavMeshSettings) -> anyhow::Result<Self> {
+        let model = crate::assets::load_obj(path)?;
+        let mut positions = vec![];
+        let mut indices = vec![];
+        for mesh in &model.meshes {
+            let base = positions.len() as u32;
+            positions.extend(
+                mesh.vertices
+                    .iter()
+                    .map(|vertex| Vector3::from(vertex.position)),
+            );
+            indices.extend(mesh.indices.iter().map(|index| base + index));
+        }
+        Ok(NavMesh::bake(&positions, &indices, settings))
+    }
+
+    /// Reads a navmesh written by `save`, or bakes one with the default settings from an `.obj`
+    /// file with the `obj` feature.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if path.extension().is_some_and(|extension| extension == "obj") {
+            #[cfg(feature = "obj")]
+            return NavMesh::bake_obj(path, &NavMeshSettings::default())
+                .with_context(|| format!("baking navmesh from {:?}", path));
+            #[cfg(not(feature = "obj"))]
+            anyhow::bail!("baking a navmesh from {:?} needs the obj feature", path);
+        }
+        let bytes = fs::read(path).with_context(|| format!("reading {:?}", path))?;
+        bincode::deserialize(&bytes).with_context(|| format!("decoding navmesh {:?}", path))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let bytes = bincode::serialize(self).context("encoding navmesh")?;
+        fs::write(path, bytes).with_context(|| format!("writing {:?}", path))
+    }
+
+    pub fn polygon_corners(&self, polygon: u32) -> [Vector3<f32>; 3] {
+        self.polygons[polygon as usize]
+            .vertices
+            .map(|index| self.vertices[index as usize])
+    }
+
+    pub fn polygon_center(&self, polygon: u32) -> Vector3<f32> {
+        let [a, b, c] = self.polygon_corners(polygon);
+        (a + b + c) / 3.0
+    }
+
+    /// The polygon nearest `point` and the point on it nearest `point`. Tests every polygon.
+    pub fn closest_point(&self, point: Vector3<f32>) -> Option<(u32, Vector3<f32>)> {
+        (0..self.polygons.len() as u32)
+            .map(|polygon| {
+                let closest = closest_point_on_triangle(self.polygon_corners(polygon), point);
+                (polygon, closest, (closest - point).magnitude2())
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .map(|(polygon, closest, _)| (polygon, closest))
+    }
+
+    /// The two corners of the edge `from` shares with `to`, `None` when they aren't neighbours.
+    pub fn shared_edge(&self, from: u32, to: u32) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let polygon = &self.polygons[from as usize];
+        let edge = polygon.neighbours.iter().position(|n| *n == Some(to))?;
+        let (a, b) = edge_vertices(polygon, edge);
+        Some((self.vertices[a as usize], self.vertices[b as usize]))
+    }
+
+    /// Every edge once, the ones on the border brighter, for the debug draw pass.
+    pub fn debug_lines(&self) -> Vec<DebugLine> {
+        let lift = Vector3::new(0.0, DEBUG_LIFT, 0.0);
+        let mut lines = vec![];
+        for (index, polygon) in self.polygons.iter().enumerate() {
+            for edge in 0..3 {
+                let neighbour = polygon.neighbours[edge];
+                // Shared edges are drawn by the lower numbered polygon
+                if neighbour.is_some_and(|neighbour| (neighbour as usize) < index) {
+                    continue;
+                }
+                let (from, to) = edge_vertices(polygon, edge);
+                lines.push(DebugLine {
+                    from: self.vertices[from as usize] + lift,
+                    to: self.vertices[to as usize] + lift,
+                    color: if neighbour.is_some() {
+                        EDGE_COLOR
+                    } else {
+                        BORDER_COLOR
+                    },
+                });
+            }
+        }
+        lines
+    }
+}
+
+fn edge_vertices(polygon: &NavPolygon, edge: usize) -> (u32, u32) {
+    (polygon.vertices[edge], polygon.vertices[(edge + 1) % 3])
+}
+
+/// From Real-Time Collision Detection, 5.1.5.
+fn closest_point_on_triangle([a, b, c]: [Vector3<f32>; 3], p: Vector3<f32>) -> Vector3<f32> {
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
@@ -0,0 +1,174 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use cgmath::{InnerSpace, MetricSpace, Vector3};
+
+use super::navmesh::NavMesh;
+
+/// Points closer than this are the same point to the funnel.
+const SAME_POINT_DISTANCE: f32 = 1e-4;
+
+/// Waypoints from `start` to `goal` along `navmesh`, both first moved onto the navmesh. `None`
+/// when the navmesh is empty or they're on parts of it that aren't connected.
+///
+/// Searches the polygons with A* between their centers, then pulls the path through the edges
+/// crossed taut, so waypoints are only placed at the corners it turns around.
+pub fn find_path(
+    navmesh: &NavMesh,
+    start: Vector3<f32>,
+    goal: Vector3<f32>,
+) -> Option<Vec<Vector3<f32>>> {
+    profile_scope!("find path");
+    let (start_polygon, start) = navmesh.closest_point(start)?;
+    let (goal_polygon, goal) = navmesh.closest_point(goal)?;
+    let corridor = search(navmesh, start_polygon, goal_polygon, goal)?;
+
+    let mut portals = vec![(start, start)];
+    for pair in corridor.windows(2) {
+        let (a, b) = navmesh.shared_edge(pair[0], pair[1])?;
+        let center = navmesh.polygon_center(pair[0]);
+        portals.push(if triangle_area(center, a, b) > 0.0 {
+            (a, b)
+        } else {
+            (b, a)
+        });
+    }
+    portals.push((goal, goal));
+
+    Some(pull_string(&portals))
+}
+
+/// An open polygon, ordered so the heap pops the lowest estimated cost first.
+struct Open {
+    estimate: f32,
+    polygon: u32,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+/// The polygons from `start` to `goal`, both included.
+fn search(navmesh: &NavMesh, start: u32, goal: u32, goal_point: Vector3<f32>) -> Option<Vec<u32>> {
+    let mut open = BinaryHeap::from([Open {
+        estimate: 0.0,
+        polygon: start,
+    }]);
+    let mut costs = HashMap::from([(start, 0.0)]);
+    let mut came_from: HashMap<u32, u32> = HashMap::new();
+
+    while let Some(Open { polygon, .. }) = open.pop() {
+        if polygon == goal {
+            let mut corridor = vec![goal];
+            while let Some(previous) = came_from.get(corridor.last()?) {
+                corridor.push(*previous);
+            }
+            corridor.reverse();
+            return Some(corridor);
+        }
+
+        let center = navmesh.polygon_center(polygon);
+        let cost = costs[&polygon];
+        for neighbour in navmesh.polygons[polygon as usize]
+            .neighbours
+            .into_iter()
+            .flatten()
+        {
+            let neighbour_center = navmesh.polygon_center(neighbour);
+            let neighbour_cost = cost + center.distance(neighbour_center);
+            if costs
+                .get(&neighbour)
+                .is_some_and(|known| *known <= neighbour_cost)
+            {
+                continue;
+            }
+            costs.insert(neighbour, neighbour_cost);
+            came_from.insert(neighbour, polygon);
+            open.push(Open {
+                estimate: neighbour_cost + neighbour_center.distance(goal_point),
+                polygon: neighbour,
+            });
+        }
+    }
+    None
+}
+
+/// Twice the signed area of the triangle seen from above, positive when `c` is to the right of
+/// `a` to `b`.
+fn triangle_area(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> f32 {
+    (c.x - a.x) * (b.z - a.z) - (b.x - a.x) * (c.z - a.z)
+}
+
+fn same_point(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+    (a - b).magnitude2() < SAME_POINT_DISTANCE * SAME_POINT_DISTANCE
+}
+
+/// The simple stupid funnel algorithm, narrowing a funnel from the last corner through each
+/// portal's left and right ends until one side crosses the other, which makes a new corner.
+fn pull_string(portals: &[(Vector3<f32>, Vector3<f32>)]) -> Vec<Vector3<f32>> {
+    let (start, _) = portals[0];
+    let mut points = vec![start];
+    let (mut apex, mut left, mut right) = (start, start, start);
+    let (mut apex_index, mut left_index, mut right_index) = (0, 0, 0);
+
+    let mut index = 1;
+    while index < portals.len() {
+        let (next_left, next_right) = portals[index];
+
+        if triangle_area(apex, right, next_right) <= 0.0 {
+            if same_point(apex, right) || triangle_area(apex, left, next_right) > 0.0 {
+                right = next_right;
+                right_index = index;
+            } else {
+                points.push(left);
+                apex = left;
+                apex_index = left_index;
+                (left, right) = (apex, apex);
+                (left_index, right_index) = (apex_index, apex_index);
+                index = apex_index + 1;
+                continue;
+            }
+        }
+
+        if triangle_area(apex, left, next_left) >= 0.0 {
+            if same_point(apex, left) || triangle_area(apex, right, next_left) < 0.0 {
+                left = next_left;
+                left_index = index;
+            } else {
+                points.push(right);
+                apex = right;
+                apex_index = right_index;
+                (left, right) = (apex, apex);
+                (left_index, right_index) = (apex_index, apex_index);
+                index = apex_index + 1;
+                continue;
+            }
+        }
+
+        index += 1;
+    }
+
+    let (goal, _) = portals[portals.len() - 1];
+    if !points.last().is_some_and(|last| same_point(*last, goal)) {
+        points.push(goal);
+    }
+    points
+}
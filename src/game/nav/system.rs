@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+};
+
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+use specs::{
+    Component, DenseVecStorage, Entities, Entity, Join, Read, ReadExpect, ReadStorage, System,
+    Write, WriteStorage,
+};
+
+use crate::game::{components::transform::Transform, jobs::JobSystem};
+
+use super::{navmesh::NavMesh, path::find_path};
+
+/// The navmesh paths are searched on, `None` until one is loaded.
+#[derive(Default)]
+pub struct NavMeshResource(pub Option<Arc<NavMesh>>);
+
+/// Asks for a path from the entity's transform to `target`. Taken by `PathfindingSystem`, which
+/// answers with a `PathResult` a tick or more later.
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+#[storage(DenseVecStorage)]
+pub struct PathRequest {
+    pub target: Vector3<f32>,
+}
+
+/// The answer to an entity's latest `PathRequest`, kept until the next one is answered.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[storage(DenseVecStorage)]
+pub struct PathResult {
+    pub target: Vector3<f32>,
+    /// From the entity's position to the target, both moved onto the navmesh. `None` when
+    /// there's no path.
+    pub waypoints: Option<Vec<Vector3<f32>>>,
+}
+
+/// The answers `PathfindingSystem` handed out on the last tick. Which tick a search finishes on
+/// depends on the job system's threads, so replays record these and play them back through
+/// `replayed`.
+#[derive(Debug, Default)]
+pub struct PathResults {
+    pub arrived: Vec<(Entity, PathResult)>,
+    /// Handed out in place of the searches that finish, while set. Taken every tick.
+    pub replayed: Option<Vec<(Entity, PathResult)>>,
+}
+
+/// Searches the navmesh for the entities' `PathRequest`s on the job system's threads, so long
+/// searches don't hold up the tick. Requests wait until a navmesh is loaded, and an answer to an
+/// entity's request is dropped if the entity asks again before it arrives.
+pub struct PathfindingSystem {
+    sender: Sender<(Entity, u64, PathResult)>,
+    receiver: Receiver<(Entity, u64, PathResult)>,
+    /// The latest request still being searched for, by entity.
+    pending: HashMap<Entity, u64>,
+    next_request: u64,
+}
+
+impl Default for PathfindingSystem {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        PathfindingSystem {
+            sender,
+            receiver,
+            pending: HashMap::new(),
+            next_request: 0,
+        }
+    }
+}
+
+impl<'a> System<'a> for PathfindingSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, JobSystem>,
+        Read<'a, NavMeshResource>,
+        ReadStorage<'a, Transform>,
+        WriteStorage<'a, PathRequest>,
+        WriteStorage<'a, PathResult>,
+        Write<'a, PathResults>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, jobs, navmesh, transforms, mut requests, mut results, mut path_results): Self::SystemData,
+    ) {
+        profile_scope!("pathfinding");
+        let arrived: Vec<_> = match path_results.replayed.take() {
+            Some(replayed) => {
+                // The recording decides what arrives, the live searches are dropped
+                self.receiver.try_iter().for_each(drop);
+                replayed
+            }
+            None => self
+                .receiver
+                .try_iter()
+                .filter(|(entity, request, _)| self.pending.get(entity) == Some(request))
+                .map(|(entity, _, result)| (entity, result))
+                .collect(),
+        };
+        path_results.arrived.clear();
+        for (entity, result) in arrived {
+            self.pending.remove(&entity);
+            if entities.is_alive(entity) {
+                let _ = results.insert(entity, result.clone());
+                path_results.arrived.push((entity, result));
+            }
+        }
+        self.pending.retain(|entity, _| entities.is_alive(*entity));
+
+        let Some(navmesh) = &navmesh.0 else {
+            return;
+        };
+        let requested: Vec<_> = (&entities, &transforms, &requests)
+            .join()
+            .map(|(entity, transform, request)| (entity, transform.position, request.target))
+            .collect();
+        for (entity, start, target) in requested {
+            requests.remove(entity);
+            let request = self.next_request;
+            self.next_request += 1;
+            self.pending.insert(entity, request);
+
+            let navmesh = navmesh.clone();
+            let sender = self.sender.clone();
+            jobs.spawn("pathfinding", move || {
+                let result = PathResult {
+                    target,
+                    waypoints: find_path(&navmesh, start, target),
+                };
+                let _ = sender.send((entity, request, result));
+            });
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use anyhow::{anyhow, Context};
+use specs::{Builder, Entity, World, WorldExt};
+
+use crate::game::{
+    codec::ComponentCodecs, components::transform::Transform, context::InputStateResource,
+};
+
+use super::{
+    packet::{receive, send, EntityState, Packet},
+    NetId, TRANSFORM,
+};
+
+/// Ticks between connection attempts.
+const CONNECT_RETRY_TICKS: u64 = 60;
+/// How far behind the newest snapshot entities are shown, in ticks, so there's usually a later
+/// snapshot to interpolate towards.
+const INTERPOLATION_DELAY_TICKS: f64 = 24.0;
+/// Playback further behind than this jumps ahead rather than catching up slowly.
+const MAX_LAG_TICKS: f64 = 120.0;
+const SNAPSHOT_BUFFER: usize = 32;
+
+struct Snapshot {
+    tick: u64,
+    entities: HashMap<NetId, BTreeMap<String, Vec<u8>>>,
+}
+
+/// Mirrors the server's replicated entities into the simulation world, interpolating their
+/// transforms between snapshots, and sends the local player's actions back.
+pub struct NetClient {
+    socket: UdpSocket,
+    server: SocketAddr,
+    client_id: Option<u32>,
+    codecs: Arc<Mutex<ComponentCodecs>>,
+    send_interval: u64,
+    snapshots: VecDeque<Snapshot>,
+    /// The parts received of snapshots that haven't all arrived yet, by tick.
+    partial: BTreeMap<u64, Vec<Option<Vec<EntityState>>>>,
+    /// The server tick being shown, fractional between snapshots.
+    playback: f64,
+    entities: HashMap<NetId, Entity>,
+}
+
+impl NetClient {
+    pub fn connect(
+        address: impl ToSocketAddrs,
+        codecs: Arc<Mutex<ComponentCodecs>>,
+        send_interval: u64,
+    ) -> anyhow::Result<Self> {
+        let server = address
+            .to_socket_addrs()
+            .context("resolving server address")?
+            .next()
+            .ok_or_else(|| anyhow!("server address resolves to nothing"))?;
+        let local = if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).context("binding client socket")?;
+        socket
+            .set_nonblocking(true)
+            .context("making client socket non-blocking")?;
+        log::info!("connecting to {}", server);
+        Ok(NetClient {
+            socket,
+            server,
+            client_id: None,
+            codecs,
+            send_interval: send_interval.max(1),
+            snapshots: VecDeque::new(),
+            partial: BTreeMap::new(),
+            playback: 0.0,
+            entities: HashMap::new(),
+        })
+    }
+
+    /// The id the server gave this client, `None` until connected.
+    pub fn client_id(&self) -> Option<u32> {
+        self.client_id
+    }
+
+    pub(super) fn tick(&mut self, world: &mut World, tick: u64) -> anyhow::Result<()> {
+        for (from, packet) in receive(&self.socket)? {
+            if from != self.server {
+                continue;
+            }
+            match packet {
+                Packet::Accepted { client } if self.client_id.is_none() => {
+                    log::info!("connected to {} as client {}", self.server, client);
+                    self.client_id = Some(client);
+                }
+                Packet::Snapshot {
+                    tick,
+                    part,
+                    parts,
+                    entities,
+                } => self.receive_part(tick, part, parts, entities),
+                Packet::Disconnect => {
+                    log::info!("disconnected by {}", self.server);
+                    self.client_id = None;
+                }
+                _ => {}
+            }
+        }
+
+        if self.client_id.is_none() {
+            if tick % CONNECT_RETRY_TICKS == 0 {
+                send(&self.socket, self.server, &Packet::Connect)?;
+            }
+        } else if tick % self.send_interval == 0 {
+            let actions = world
+                .read_resource::<InputStateResource>()
+                .0
+                .values()
+                .filter(|state| state.active)
+                .map(|state| (state.name.clone(), state.value))
+                .collect();
+            send(&self.socket, self.server, &Packet::Input { tick, actions })?;
+        }
+
+        self.interpolate(world)
+    }
+
+    /// Holds on to a part of the snapshot after `tick` until the rest of it arrives, then
+    /// buffers the whole snapshot.
+    fn receive_part(&mut self, tick: u64, part: u32, parts: u32, entities: Vec<EntityState>) {
+        if part >= parts {
+            log::debug!("ignoring snapshot part {} of {}", part, parts);
+            return;
+        }
+        let received = self
+            .partial
+            .entry(tick)
+            .or_insert_with(|| (0..parts).map(|_| None).collect());
+        if received.len() != parts as usize {
+            log::debug!(
+                "ignoring snapshot part with a different part count for tick {}",
+                tick
+            );
+            return;
+        }
+        received[part as usize] = Some(entities);
+        if received.iter().all(Option::is_some) {
+            let received = self.partial.remove(&tick).unwrap_or_default();
+            self.buffer(Snapshot {
+                tick,
+                entities: received.into_iter().flatten().flatten().collect(),
+            });
+        }
+
+        // Snapshots older than everything buffered would be ignored once whole, so their parts
+        // are dropped, along with those of snapshots that lost a part
+        if let Some(oldest) = self.snapshots.front().map(|snapshot| snapshot.tick) {
+            self.partial.retain(|&tick, _| tick > oldest);
+        }
+        while self.partial.len() > SNAPSHOT_BUFFER {
+            self.partial.pop_first();
+        }
+    }
+
+    /// Keeps `snapshot` in tick order, ignoring ones older than everything buffered.
+    fn buffer(&mut self, snapshot: Snapshot) {
+        if self.snapshots.is_empty() {
+            self.playback = snapshot.tick as f64 - INTERPOLATION_DELAY_TICKS;
+        }
+        if self
+            .snapshots
+            .front()
+            .is_some_and(|oldest| snapshot.tick <= oldest.tick)
+        {
+            return;
+        }
+        let index = self
+            .snapshots
+            .partition_point(|buffered| buffered.tick < snapshot.tick);
+        if self.snapshots.get(index).map(|s| s.tick) != Some(snapshot.tick) {
+            self.snapshots.insert(index, snapshot);
+        }
+        while self.snapshots.len() > SNAPSHOT_BUFFER {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Advances playback a tick and shows the entities as they were at that point between the
+    /// snapshots around it.
+    fn interpolate(&mut self, world: &mut World) -> anyhow::Result<()> {
+        let Some(newest) = self.snapshots.back().map(|snapshot| snapshot.tick as f64) else {
+            return Ok(());
+        };
+        let target = newest - INTERPOLATION_DELAY_TICKS;
+        self.playback = (self.playback + 1.0).clamp(target - MAX_LAG_TICKS, target);
+
+        while self.snapshots.len() > 1 && self.snapshots[1].tick as f64 <= self.playback {
+            self.snapshots.pop_front();
+        }
+        let from = &self.snapshots[0];
+        let (to, amount) = match self.snapshots.get(1) {
+            Some(to) if self.playback > from.tick as f64 => (
+                to,
+                ((self.playback - from.tick as f64) / (to.tick - from.tick) as f64) as f32,
+            ),
+            _ => (from, 0.0),
+        };
+
+        let gone: Vec<NetId> = self
+            .entities
+            .keys()
+            .filter(|id| !to.entities.contains_key(id))
+            .copied()
+            .collect();
+        for id in gone {
+            if let Some(entity) = self.entities.remove(&id) {
+                let _ = world.delete_entity(entity);
+            }
+        }
+
+        let codecs = self.codecs.lock().unwrap_or_else(PoisonError::into_inner);
+        for (id, components) in &to.entities {
+            let entity = *self
+                .entities
+                .entry(*id)
+                .or_insert_with(|| world.create_entity().build());
+            codecs.decode(
+                world,
+                entity,
+                components.iter().filter(|(name, _)| *name != TRANSFORM),
+            )?;
+
+            let transform = |snapshot: &Snapshot| -> Option<Transform> {
+                let bytes = snapshot.entities.get(id)?.get(TRANSFORM)?;
+                bincode::deserialize(bytes).ok()
+            };
+            let transform = match (transform(from), transform(to)) {
+                (Some(from), Some(to)) => Some(from.interpolate(&to, amount)),
+                (_, to) => to,
+            };
+            if let Some(transform) = transform {
+                world
+                    .write_storage::<Transform>()
+                    .insert(entity, transform)
+                    .context("inserting replicated transform")?;
+            }
+        }
+        world.maintain();
+        Ok(())
+    }
+}
+
+impl Drop for NetClient {
+    fn drop(&mut self) {
+        if self.client_id.is_some() {
+            let _ = send(&self.socket, self.server, &Packet::Disconnect);
+        }
+    }
+}
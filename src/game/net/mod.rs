@@ -0,0 +1,74 @@
+pub use client::NetClient;
+pub use server::{NetServer, RemoteInputs};
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use specs::{Component, Entity, NullStorage, World};
+
+use super::{
+    codec::ComponentCodecs,
+    components::{render::Renderable, spatial::EntityKey, transform::Transform},
+};
+
+mod client;
+mod packet;
+mod server;
+
+/// The name transforms are replicated under, interpolated on clients rather than snapped.
+const TRANSFORM: &str = "transform";
+
+/// Marks the server entities whose replicated components are sent to clients.
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Replicated;
+
+/// Identifies a replicated entity across the network, the bits of its `EntityKey` on the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct NetId(u64);
+
+impl From<EntityKey> for NetId {
+    fn from(key: EntityKey) -> Self {
+        NetId(key.to_bits())
+    }
+}
+
+impl From<NetId> for EntityKey {
+    fn from(id: NetId) -> Self {
+        EntityKey::from_bits(id.0)
+    }
+}
+
+impl From<Entity> for NetId {
+    fn from(entity: Entity) -> Self {
+        EntityKey::from(entity).into()
+    }
+}
+
+/// The components replicated by the engine itself. Games add theirs through the registry shared
+/// with the session.
+pub fn engine_replication() -> Arc<Mutex<ComponentCodecs>> {
+    let mut codecs = ComponentCodecs::default();
+    codecs.register::<Transform>(TRANSFORM);
+    codecs.register::<Renderable>("renderable");
+    Arc::new(Mutex::new(codecs))
+}
+
+/// Either end of a connection, stepped by the simulation after every tick so both ends count
+/// the same ticks.
+pub enum NetSession {
+    Server(NetServer),
+    Client(NetClient),
+}
+
+impl NetSession {
+    pub fn tick(&mut self, world: &mut World, tick: u64) {
+        let result = match self {
+            NetSession::Server(server) => server.tick(world, tick),
+            NetSession::Client(client) => client.tick(world, tick),
+        };
+        if let Err(e) = result {
+            log::warn!("networking: {:#}", e);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use super::NetId;
+
+/// Largest datagram sent or received.
+const MAX_PACKET_SIZE: usize = 65_000;
+/// Roughly how many bytes of entities go in each part of a snapshot, small enough that the
+/// parts aren't fragmented on a typical network and losing one doesn't cost much.
+const SNAPSHOT_PART_SIZE: usize = 1_200;
+/// Room left in a snapshot packet for everything but its entities.
+const SNAPSHOT_HEADER_SIZE: usize = 64;
+
+/// A replicated entity, with its components encoded under their registered names.
+pub(super) type EntityState = (NetId, BTreeMap<String, Vec<u8>>);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum Packet {
+    /// Sent by a client until it's accepted, which makes the handshake reliable over UDP.
+    Connect,
+    Accepted {
+        client: u32,
+    },
+    /// Part `part` of `parts` of the replicated entities after `tick`. Unreliable, since a newer
+    /// one is always on its way, so a snapshot missing any of its parts is never shown.
+    Snapshot {
+        tick: u64,
+        part: u32,
+        parts: u32,
+        entities: Vec<EntityState>,
+    },
+    /// The client's active actions and their values.
+    Input {
+        tick: u64,
+        actions: Vec<(String, Option<f32>)>,
+    },
+    Disconnect,
+}
+
+pub(super) fn send(socket: &UdpSocket, to: SocketAddr, packet: &Packet) -> anyhow::Result<()> {
+    let bytes = bincode::serialize(packet).context("encoding packet")?;
+    if bytes.len() > MAX_PACKET_SIZE {
+        bail!("dropping a {} byte packet", bytes.len());
+    }
+    socket
+        .send_to(&bytes, to)
+        .with_context(|| format!("sending to {}", to))?;
+    Ok(())
+}
+
+/// Splits the replicated entities after `tick` into snapshot packets of around
+/// `SNAPSHOT_PART_SIZE` bytes each. Entities too big for a packet of their own are left out.
+pub(super) fn snapshot_packets(
+    tick: u64,
+    entities: Vec<EntityState>,
+) -> anyhow::Result<Vec<Packet>> {
+    let mut chunks = vec![];
+    let mut chunk: Vec<EntityState> = vec![];
+    let mut chunk_size = 0;
+    for entity in entities {
+        let size = bincode::serialized_size(&entity).context("measuring entity")? as usize;
+        if size > MAX_PACKET_SIZE - SNAPSHOT_HEADER_SIZE {
+            log::warn!(
+                "not replicating {:?}, its {} bytes don't fit in a packet",
+                entity.0,
+                size
+            );
+            continue;
+        }
+        if !chunk.is_empty() && chunk_size + size > SNAPSHOT_PART_SIZE {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_size = 0;
+        }
+        chunk_size += size;
+        chunk.push(entity);
+    }
+    // An empty snapshot still tells clients every entity is gone
+    if !chunk.is_empty() || chunks.is_empty() {
+        chunks.push(chunk);
+    }
+
+    let parts = chunks.len() as u32;
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(part, entities)| Packet::Snapshot {
+            tick,
+            part: part as u32,
+            parts,
+            entities,
+        })
+        .collect())
+}
+
+/// Every packet waiting on the non-blocking `socket`. Packets that don't decode are skipped.
+pub(super) fn receive(socket: &UdpSocket) -> anyhow::Result<Vec<(SocketAddr, Packet)>> {
+    let mut buffer = vec![0; MAX_PACKET_SIZE];
+    let mut packets = vec![];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((len, from)) => match bincode::deserialize(&buffer[..len]) {
+                Ok(packet) => packets.push((from, packet)),
+                Err(e) => log::debug!("ignoring packet from {}: {}", from, e),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(packets),
+            // Windows reports an unreachable peer on the next receive
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e).context("receiving packets"),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use anyhow::Context;
+use specs::{Entity, Join, World, WorldExt};
+
+use crate::game::codec::ComponentCodecs;
+
+use super::{
+    packet::{receive, send, snapshot_packets, Packet},
+    NetId, Replicated,
+};
+
+/// Clients not heard from in this many ticks are dropped.
+const CLIENT_TIMEOUT_TICKS: u64 = 240 * 5;
+
+/// The actions each connected client last sent, by client id. Inserted into the simulation world
+/// every tick while serving.
+#[derive(Debug, Default)]
+pub struct RemoteInputs(pub HashMap<u32, HashMap<String, Option<f32>>>);
+
+struct Client {
+    id: u32,
+    last_heard: u64,
+    actions: HashMap<String, Option<f32>>,
+}
+
+/// Sends the `Replicated` entities to every connected client each `send_interval` ticks.
+pub struct NetServer {
+    socket: UdpSocket,
+    clients: HashMap<SocketAddr, Client>,
+    next_client: u32,
+    codecs: Arc<Mutex<ComponentCodecs>>,
+    send_interval: u64,
+}
+
+impl NetServer {
+    pub fn bind(
+        address: impl ToSocketAddrs,
+        codecs: Arc<Mutex<ComponentCodecs>>,
+        send_interval: u64,
+    ) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(address).context("binding server socket")?;
+        socket
+            .set_nonblocking(true)
+            .context("making server socket non-blocking")?;
+        log::info!("serving on {:?}", socket.local_addr());
+        Ok(NetServer {
+            socket,
+            clients: HashMap::new(),
+            next_client: 0,
+            codecs,
+            send_interval: send_interval.max(1),
+        })
+    }
+
+    pub(super) fn tick(&mut self, world: &mut World, tick: u64) -> anyhow::Result<()> {
+        for (from, packet) in receive(&self.socket)? {
+            match packet {
+                Packet::Connect => {
+                    let next_client = &mut self.next_client;
+                    let client = self.clients.entry(from).or_insert_with(|| {
+                        *next_client += 1;
+                        log::info!("client {} connected from {}", next_client, from);
+                        Client {
+                            id: *next_client,
+                            last_heard: tick,
+                            actions: HashMap::new(),
+                        }
+                    });
+                    client.last_heard = tick;
+                    send(&self.socket, from, &Packet::Accepted { client: client.id })?;
+                }
+                Packet::Input { actions, .. } => {
+                    if let Some(client) = self.clients.get_mut(&from) {
+                        client.last_heard = tick;
+                        client.actions = actions.into_iter().collect();
+                    }
+                }
+                Packet::Disconnect => {
+                    if let Some(client) = self.clients.remove(&from) {
+                        log::info!("client {} disconnected", client.id);
+                    }
+                }
+                Packet::Accepted { .. } | Packet::Snapshot { .. } => {}
+            }
+        }
+
+        self.clients.retain(|_, client| {
+            let alive = tick.saturating_sub(client.last_heard) < CLIENT_TIMEOUT_TICKS;
+            if !alive {
+                log::info!("client {} timed out", client.id);
+            }
+            alive
+        });
+        world.insert(RemoteInputs(
+            self.clients
+                .values()
+                .map(|client| (client.id, client.actions.clone()))
+                .collect(),
+        ));
+
+        if tick % self.send_interval != 0 || self.clients.is_empty() {
+            return Ok(());
+        }
+
+        let entities: Vec<Entity> = (&world.entities(), &world.read_storage::<Replicated>())
+            .join()
+            .map(|(entity, _)| entity)
+            .collect();
+        let codecs = self.codecs.lock().unwrap_or_else(PoisonError::into_inner);
+        let entities = entities
+            .into_iter()
+            .map(|entity| Ok((NetId::from(entity), codecs.encode(world, entity)?)))
+            .collect::<anyhow::Result<_>>()?;
+        let packets = snapshot_packets(tick, entities)?;
+        // One unreachable client shouldn't keep the snapshot from the others
+        for address in self.clients.keys() {
+            for packet in &packets {
+                if let Err(e) = send(&self.socket, *address, packet) {
+                    log::warn!("{:#}, skipping the client this tick", e);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for NetServer {
+    fn drop(&mut self) {
+        for address in self.clients.keys() {
+            let _ = send(&self.socket, *address, &Packet::Disconnect);
+        }
+    }
+}
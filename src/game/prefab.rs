@@ -0,0 +1,143 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize};
+use specs::{Builder, Component, Entity, EntityBuilder, World, WorldExt};
+
+use super::components::transform::{Spin, Transform};
+
+type Prefab = Box<dyn Fn(EntityBuilder<'_>) -> EntityBuilder<'_> + Send>;
+type DecodeComponent = Box<dyn Fn(&World, Entity, ron::Value) -> anyhow::Result<()> + Send>;
+
+/// Named recipes for entities, spawned into the simulation world at a transform.
+#[derive(Default)]
+pub struct Prefabs {
+    prefabs: BTreeMap<String, Prefab>,
+    /// Prefabs read from files, by name, with the file each came from.
+    defined: BTreeMap<String, (PathBuf, PrefabDefinition)>,
+    /// The components prefab files can add, by the name they're written under.
+    components: BTreeMap<String, DecodeComponent>,
+}
+
+/// Prefabs written in a RON file, by name. Each can start from a prefab registered in code and
+/// adds the components `Prefabs` knows by name, overriding those the base already has.
+///
+/// ```ron
+/// {
+///     "fast_cube": (
+///         base: Some("cube"),
+///         components: {
+///             "spin": 3.0,
+///         },
+///     ),
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PrefabFile(pub BTreeMap<String, PrefabDefinition>);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PrefabDefinition {
+    pub base: Option<String>,
+    pub components: BTreeMap<String, ron::Value>,
+}
+
+impl PrefabFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+        ron::from_str(&text).with_context(|| format!("parsing {:?}", path))
+    }
+}
+
+impl Prefabs {
+    /// Lets prefab files add the engine's serializable components.
+    pub fn with_engine_components(self) -> Self {
+        self.register_component::<Transform>("transform")
+            .register_component::<Spin>("spin")
+    }
+
+    /// Registers `prefab`, which adds its components to an entity that already has a transform.
+    pub fn register<F>(mut self, name: &str, prefab: F) -> Self
+    where
+        F: Fn(EntityBuilder<'_>) -> EntityBuilder<'_> + Send + 'static,
+    {
+        self.prefabs.insert(name.to_string(), Box::new(prefab));
+        self
+    }
+
+    /// Lets prefab files add a `C` written under `name`.
+    pub fn register_component<C>(mut self, name: &str) -> Self
+    where
+        C: Component + DeserializeOwned + Send + Sync,
+    {
+        let decode: DecodeComponent = Box::new(|world, entity, value| {
+            let component: C = value.into_rust().context("decoding component")?;
+            world
+                .write_storage::<C>()
+                .insert(entity, component)
+                .context("inserting component")?;
+            Ok(())
+        });
+        self.components.insert(name.to_string(), decode);
+        self
+    }
+
+    /// Replaces the prefabs last read from `path` with those in `file`.
+    pub fn set_file(&mut self, path: &Path, file: PrefabFile) {
+        self.defined.retain(|_, (defined_in, _)| defined_in != path);
+        for (name, definition) in file.0 {
+            if let Some(base) = definition.base.as_ref() {
+                if !self.prefabs.contains_key(base) {
+                    log::warn!("{:?}: prefab {} is based on unknown {}", path, name, base);
+                }
+            }
+            self.defined.insert(name, (path.to_path_buf(), definition));
+        }
+    }
+
+    /// Sorted by name.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .prefabs
+            .keys()
+            .chain(self.defined.keys())
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Creates the prefab called `name` at `transform`, `None` when there's no such prefab.
+    /// Prefabs from files take precedence over those registered in code.
+    pub fn spawn(&self, world: &World, name: &str, transform: Transform) -> Option<Entity> {
+        let Some((path, definition)) = self.defined.get(name) else {
+            let prefab = self.prefabs.get(name)?;
+            return Some(prefab(world.create_entity_unchecked().with(transform)).build());
+        };
+
+        let builder = world.create_entity_unchecked().with(transform);
+        let entity = match definition
+            .base
+            .as_ref()
+            .and_then(|base| self.prefabs.get(base))
+        {
+            Some(base) => base(builder).build(),
+            None => builder.build(),
+        };
+        for (component, value) in &definition.components {
+            let Some(decode) = self.components.get(component) else {
+                log::warn!("{:?}: skipping unregistered component {}", path, component);
+                continue;
+            };
+            if let Err(e) = decode(world, entity, value.clone()) {
+                log::warn!("{:?}: {} of {}: {:#}", path, component, name, e);
+            }
+        }
+        Some(entity)
+    }
+}
@@ -0,0 +1,399 @@
+use std::{collections::HashMap, fs, hash::Hasher, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use specs::{Entity, World};
+use winit::dpi::PhysicalSize;
+
+use super::{
+    components::{transform::Transform, CameraEffect, TimelineCommand, VisibilityResource},
+    input::{ActionState, PlayerIndex},
+    inspect::Edit,
+    nav::PathResult,
+    save::SaveRegistry,
+    voxel::Voxel,
+};
+
+const MAGIC: [u8; 4] = *b"TRRP";
+
+/// Everything the fixed update read from outside the simulation on one tick.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TickInput {
+    /// The first player's action states, as `InputStateResource` held them.
+    pub actions: HashMap<String, ActionState>,
+    /// Every player's, gamepads included, as `PlayerInputStateResource` held them.
+    pub players: HashMap<PlayerIndex, HashMap<String, ActionState>>,
+    pub mouse_delta: (f32, f32),
+    pub window_size: Option<PhysicalSize<u32>>,
+    /// What the last rendered frame saw, which depends on how fast the renderer runs.
+    pub visibility: VisibilityResource,
+    /// The paths `PathfindingSystem` handed out, by entity id.
+    pub paths: Vec<(u32, PathResult)>,
+    /// The actions of the clients connected to a server, as `RemoteInputs` held them.
+    pub remote_inputs: HashMap<u32, HashMap<String, Option<f32>>>,
+    /// Made by the editor and console since the previous tick.
+    pub edits: Vec<ReplayEdit>,
+}
+
+/// An `Edit`, with the component and field names it refers to owned so it can be read back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplayEdit {
+    Field {
+        entity: u32,
+        component: String,
+        field: String,
+        value: f32,
+    },
+    Transform {
+        entity: u32,
+        transform: Transform,
+    },
+    Spawn {
+        prefab: String,
+        transform: Transform,
+    },
+    TimeOfDay {
+        hour: Option<f32>,
+        paused: Option<bool>,
+    },
+    Voxels {
+        min: [i32; 3],
+        max: [i32; 3],
+        voxel: Voxel,
+    },
+    Sculpt {
+        center: [f32; 3],
+        radius: f32,
+        amount: f32,
+        material: Voxel,
+    },
+    CameraEffect(CameraEffect),
+    Timeline(TimelineCommand),
+}
+
+impl From<&Edit> for ReplayEdit {
+    fn from(edit: &Edit) -> Self {
+        match edit {
+            Edit::Field(edit) => ReplayEdit::Field {
+                entity: edit.entity,
+                component: edit.component.to_string(),
+                field: edit.field.to_string(),
+                value: edit.value,
+            },
+            Edit::Transform { entity, transform } => ReplayEdit::Transform {
+                entity: *entity,
+                transform: *transform,
+            },
+            Edit::Spawn { prefab, transform } => ReplayEdit::Spawn {
+                prefab: prefab.clone(),
+                transform: *transform,
+            },
+            Edit::TimeOfDay { hour, paused } => ReplayEdit::TimeOfDay {
+                hour: *hour,
+                paused: *paused,
+            },
+            Edit::Voxels { min, max, voxel } => ReplayEdit::Voxels {
+                min: *min,
+                max: *max,
+                voxel: *voxel,
+            },
+            Edit::Sculpt {
+                center,
+                radius,
+                amount,
+                material,
+            } => ReplayEdit::Sculpt {
+                center: *center,
+                radius: *radius,
+                amount: *amount,
+                material: *material,
+            },
+            Edit::CameraEffect(effect) => ReplayEdit::CameraEffect(*effect),
+            Edit::Timeline(command) => ReplayEdit::Timeline(command.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedTick {
+    pub input: TickInput,
+    /// `world_checksum` after the tick.
+    pub checksum: u64,
+}
+
+/// A recorded session: the `Saved` entities when recording started, and every tick's input
+/// after. Playing it back loads the save and feeds the inputs to the fixed update in place of
+/// the live ones, checking each tick ends the way it did when recorded.
+///
+/// Entities that aren't `Saved` aren't restored, so they should be in the same state as when
+/// recording started, e.g. by recording from startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub start: Vec<u8>,
+    /// The ids of the entities in `start`, in the order they were saved. Loading the save
+    /// creates them under new ids, which the ids the ticks refer to are mapped to.
+    pub start_ids: Vec<u32>,
+    pub ticks: Vec<RecordedTick>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("reading {:?}", path))?;
+        if bytes.get(..MAGIC.len()) != Some(&MAGIC[..]) {
+            anyhow::bail!("{:?} isn't a replay", path);
+        }
+        bincode::deserialize(&bytes[MAGIC.len()..])
+            .with_context(|| format!("decoding replay {:?}", path))
+    }
+
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serialize(self).context("encoding replay")?);
+        Ok(bytes)
+    }
+}
+
+/// The first tick a played back replay ended differently than when it was recorded, which
+/// means something the fixed update depends on isn't captured by the replay or isn't
+/// deterministic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    /// Counted from the start of the replay.
+    pub tick: usize,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// What the simulation is doing with replays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplayStatus {
+    #[default]
+    Idle,
+    Recording {
+        ticks: usize,
+    },
+    Playing {
+        tick: usize,
+        ticks: usize,
+        divergence: Option<Divergence>,
+    },
+    /// The last replay played to the end.
+    Finished {
+        divergence: Option<Divergence>,
+    },
+}
+
+/// Hashes the state replays check: the registered components of every `Saved` entity, encoded
+/// as they're saved. Entities are hashed in the order of the ids they were recorded under, from
+/// `recorded_id`, so the entities a replay loads under new ids hash the same.
+pub fn world_checksum(
+    world: &World,
+    registry: &SaveRegistry,
+    recorded_id: impl Fn(u32) -> u32,
+) -> anyhow::Result<u64> {
+    let mut entities = registry.saved_entities(world);
+    entities.sort_by_key(|entity| recorded_id(entity.id()));
+    let mut hasher = Fnv1a::default();
+    for entity in entities {
+        let components = registry.encode_entity(world, entity)?;
+        hasher.write(&(components.len() as u32).to_le_bytes());
+        for (name, bytes) in components {
+            hasher.write(name.as_bytes());
+            hasher.write(&(bytes.len() as u32).to_le_bytes());
+            hasher.write(&bytes);
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// 64 bit FNV-1a. Unlike `DefaultHasher` it's the same across runs, builds and platforms, so
+/// checksums recorded on one machine can be checked on another.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The simulation thread's side of recording and playing back.
+#[derive(Default)]
+pub(crate) enum ReplaySession {
+    #[default]
+    Idle,
+    Recording {
+        replay: Replay,
+        edits: Vec<ReplayEdit>,
+    },
+    Playing {
+        replay: Replay,
+        next: usize,
+        divergence: Option<Divergence>,
+        /// The live ids of the entities loaded from the start, by the ids they were recorded
+        /// under, and the other way around.
+        live_ids: HashMap<u32, u32>,
+        recorded_ids: HashMap<u32, u32>,
+    },
+    Finished(Option<Divergence>),
+}
+
+impl ReplaySession {
+    /// Starts recording from `start`, a save of the `start_entities`.
+    pub fn record(start: Vec<u8>, start_entities: &[Entity]) -> Self {
+        ReplaySession::Recording {
+            replay: Replay {
+                start,
+                start_ids: start_entities.iter().map(|entity| entity.id()).collect(),
+                ticks: vec![],
+            },
+            edits: vec![],
+        }
+    }
+
+    /// Plays `replay` back, its start having been loaded as the `loaded` entities.
+    pub fn play(replay: Replay, loaded: &[Entity]) -> Self {
+        let live_ids: HashMap<u32, u32> = replay
+            .start_ids
+            .iter()
+            .zip(loaded)
+            .map(|(recorded, entity)| (*recorded, entity.id()))
+            .collect();
+        let recorded_ids = live_ids
+            .iter()
+            .map(|(recorded, live)| (*live, *recorded))
+            .collect();
+        ReplaySession::Playing {
+            replay,
+            next: 0,
+            divergence: None,
+            live_ids,
+            recorded_ids,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self, ReplaySession::Playing { .. })
+    }
+
+    /// The live id of the entity recorded as `id`. Entities that weren't loaded from the start,
+    /// like those spawned while recording, keep their id.
+    pub fn live_id(&self, id: u32) -> u32 {
+        match self {
+            ReplaySession::Playing { live_ids, .. } => live_ids.get(&id).copied().unwrap_or(id),
+            _ => id,
+        }
+    }
+
+    fn recorded_id(&self, id: u32) -> u32 {
+        match self {
+            ReplaySession::Playing { recorded_ids, .. } => {
+                recorded_ids.get(&id).copied().unwrap_or(id)
+            }
+            _ => id,
+        }
+    }
+
+    /// Keeps `edit` for the next recorded tick.
+    pub fn record_edit(&mut self, edit: &Edit) {
+        if let ReplaySession::Recording { edits, .. } = self {
+            edits.push(edit.into());
+        }
+    }
+
+    /// The input to run the next tick with while playing back.
+    pub fn next_input(&self) -> Option<&TickInput> {
+        match self {
+            ReplaySession::Playing { replay, next, .. } => {
+                replay.ticks.get(*next).map(|tick| &tick.input)
+            }
+            _ => None,
+        }
+    }
+
+    /// Records the tick that just ran with `input`, or checks it against the recording while
+    /// playing back.
+    pub fn end_tick(
+        &mut self,
+        world: &World,
+        registry: &SaveRegistry,
+        input: impl FnOnce() -> TickInput,
+    ) {
+        if matches!(self, ReplaySession::Idle | ReplaySession::Finished(_)) {
+            return;
+        }
+        let checksum = match world_checksum(world, registry, |id| self.recorded_id(id)) {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                log::error!("stopping replay, the world can't be checked: {:#}", e);
+                *self = ReplaySession::Idle;
+                return;
+            }
+        };
+        match self {
+            ReplaySession::Idle | ReplaySession::Finished(_) => {}
+            ReplaySession::Recording { replay, edits } => {
+                let mut input = input();
+                input.edits = std::mem::take(edits);
+                replay.ticks.push(RecordedTick { input, checksum });
+            }
+            ReplaySession::Playing {
+                replay,
+                next,
+                divergence,
+                ..
+            } => {
+                let expected = replay.ticks[*next].checksum;
+                let actual = checksum;
+                if divergence.is_none() && expected != actual {
+                    log::warn!("replay diverged on tick {}", next);
+                    *divergence = Some(Divergence {
+                        tick: *next,
+                        expected,
+                        actual,
+                    });
+                }
+                *next += 1;
+                if *next >= replay.ticks.len() {
+                    log::info!("replay finished after {} ticks", next);
+                    let divergence = *divergence;
+                    *self = ReplaySession::Finished(divergence);
+                }
+            }
+        }
+    }
+
+    pub fn status(&self) -> ReplayStatus {
+        match self {
+            ReplaySession::Idle => ReplayStatus::Idle,
+            ReplaySession::Finished(divergence) => ReplayStatus::Finished {
+                divergence: *divergence,
+            },
+            ReplaySession::Recording { replay, .. } => ReplayStatus::Recording {
+                ticks: replay.ticks.len(),
+            },
+            ReplaySession::Playing {
+                replay,
+                next,
+                divergence,
+                ..
+            } => ReplayStatus::Playing {
+                tick: *next,
+                ticks: replay.ticks.len(),
+                divergence: *divergence,
+            },
+        }
+    }
+}
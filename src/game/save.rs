@@ -0,0 +1,192 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Cursor, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use specs::{Builder, Component, Entity, Join, NullStorage, World, WorldExt};
+
+use super::{
+    codec::ComponentCodecs,
+    components::transform::{Spin, Transform},
+};
+
+/// Starts every save file, followed by its version.
+const MAGIC: [u8; 4] = *b"TRSV";
+
+/// Marks the entities that go into save games, with those of their components registered in the
+/// `SaveRegistry`.
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Saved;
+
+/// The saved entities of a save file, each holding its components encoded with bincode under
+/// their registered names. What migrations rewrite.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SaveData {
+    pub entities: Vec<BTreeMap<String, Vec<u8>>>,
+}
+
+type Migration = Box<dyn Fn(SaveData) -> anyhow::Result<SaveData> + Send>;
+
+/// Which components go into save games, and how to upgrade saves written by older versions of
+/// the game.
+pub struct SaveRegistry {
+    version: u32,
+    components: ComponentCodecs,
+    /// Each upgrading a save from the version it's keyed by to the next.
+    migrations: BTreeMap<u32, Migration>,
+}
+
+impl Default for SaveRegistry {
+    fn default() -> Self {
+        SaveRegistry {
+            version: 1,
+            components: ComponentCodecs::default(),
+            migrations: BTreeMap::new(),
+        }
+    }
+}
+
+impl SaveRegistry {
+    pub fn with_engine_components(mut self) -> Self {
+        self.register::<Transform>("transform");
+        self.register::<Spin>("spin");
+        self
+    }
+
+    /// The version new saves are written with and older ones are migrated to.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+
+    /// Saves the `C` of `Saved` entities under `name`, which must stay the same across versions
+    /// unless a migration renames it.
+    pub fn register<C>(&mut self, name: &str)
+    where
+        C: Component + Serialize + DeserializeOwned + Send + Sync,
+    {
+        self.components.register::<C>(name);
+    }
+
+    /// Makes saves of version `from` loadable by rewriting them as `from + 1`.
+    pub fn add_migration<F>(&mut self, from: u32, migration: F)
+    where
+        F: Fn(SaveData) -> anyhow::Result<SaveData> + Send + 'static,
+    {
+        self.migrations.insert(from, Box::new(migration));
+    }
+
+    /// The `Saved` entities of `world`, in the order `save` writes them.
+    pub fn saved_entities(&self, world: &World) -> Vec<Entity> {
+        (&world.entities(), &world.read_storage::<Saved>())
+            .join()
+            .map(|(entity, _)| entity)
+            .collect()
+    }
+
+    /// The registered components `entity` has, encoded as they're saved.
+    pub fn encode_entity(
+        &self,
+        world: &World,
+        entity: Entity,
+    ) -> anyhow::Result<BTreeMap<String, Vec<u8>>> {
+        self.components.encode(world, entity)
+    }
+
+    /// Encodes the registered components of every `Saved` entity in `world`.
+    pub fn save(&self, world: &World) -> anyhow::Result<Vec<u8>> {
+        let mut data = SaveData::default();
+        for entity in self.saved_entities(world) {
+            data.entities.push(self.components.encode(world, entity)?);
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(self.version.to_le_bytes());
+        bincode::serialize_into(&mut bytes, &data).context("encoding save")?;
+        Ok(bytes)
+    }
+
+    /// Replaces the `Saved` entities of `world` with those in `bytes`, migrating them from the
+    /// version they were saved with first. Components no longer registered are skipped.
+    ///
+    /// Returns the entities created, in the order they were saved. They get new ids, so
+    /// anything that refers to the saved entities by id has to be mapped through them.
+    pub fn load(&self, world: &mut World, bytes: &[u8]) -> anyhow::Result<Vec<Entity>> {
+        let mut reader = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        let mut version = [0; 4];
+        reader
+            .read_exact(&mut magic)
+            .and_then(|_| reader.read_exact(&mut version))
+            .context("reading save header")?;
+        if magic != MAGIC {
+            bail!("not a save file");
+        }
+        let mut version = u32::from_le_bytes(version);
+        if version > self.version {
+            bail!(
+                "save version {} is newer than the game's {}",
+                version,
+                self.version
+            );
+        }
+
+        let mut data: SaveData = bincode::deserialize_from(reader).context("decoding save")?;
+        while version < self.version {
+            let migration = self
+                .migrations
+                .get(&version)
+                .with_context(|| format!("no migration from save version {}", version))?;
+            data =
+                migration(data).with_context(|| format!("migrating save version {}", version))?;
+            version += 1;
+        }
+
+        // Everything is decoded before the world is touched, so a save that doesn't decode
+        // leaves the current one in place
+        let decoded = data
+            .entities
+            .iter()
+            .enumerate()
+            .map(|(index, components)| {
+                self.components
+                    .decode_detached(components)
+                    .with_context(|| format!("decoding saved entity {}", index))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let previous = self.saved_entities(world);
+        world
+            .delete_entities(&previous)
+            .context("removing saved entities")?;
+        world.maintain();
+
+        let mut loaded = Vec::with_capacity(decoded.len());
+        for components in decoded {
+            let entity = world.create_entity().with(Saved).build();
+            for insert in components {
+                insert(world, entity)?;
+            }
+            loaded.push(entity);
+        }
+        Ok(loaded)
+    }
+}
+
+/// Writes a save next to `path` before moving it into place, so a crash mid-write leaves the
+/// previous save intact.
+pub fn write(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let partial = path.with_extension("partial");
+    fs::File::create(&partial)
+        .and_then(|mut file| file.write_all(bytes).and_then(|_| file.sync_all()))
+        .with_context(|| format!("writing {:?}", partial))?;
+    fs::rename(&partial, path).with_context(|| format!("moving save to {:?}", path))
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use cgmath::Vector3;
+use mlua::{Function, Lua, LuaSerdeExt, RegistryKey, Table, Value};
+use serde::{de::DeserializeOwned, Serialize};
+use specs::{Component, Entity, RunNow, World, WorldExt};
+
+use crate::assets::{canonical, AssetWatcher};
+
+use super::{
+    components::{render::Renderable, transform::Transform, DebugLine, DebugLines},
+    context::InputStateResource,
+    simulation::FIXED_TIME_STEP,
+};
+
+type Get = Box<dyn for<'lua> Fn(&'lua Lua, &World, Entity) -> mlua::Result<Value<'lua>> + Send>;
+type Set = Box<dyn for<'lua> Fn(&'lua Lua, &World, Entity, Value<'lua>) -> mlua::Result<()> + Send>;
+
+/// Components scripts can read and write, by name. They cross into Lua as tables shaped like
+/// their serde representation.
+#[derive(Default)]
+pub struct ScriptComponents {
+    components: BTreeMap<String, (Get, Set)>,
+}
+
+impl ScriptComponents {
+    pub fn with_engine_components(mut self) -> Self {
+        self.register::<Transform>("transform");
+        self.register::<Renderable>("renderable");
+        self
+    }
+
+    pub fn register<C>(&mut self, name: &str)
+    where
+        C: Component + Serialize + DeserializeOwned + Send + Sync,
+    {
+        let get: Get = Box::new(
+            |lua, world, entity| match world.read_storage::<C>().get(entity) {
+                Some(component) => lua.to_value(component),
+                None => Ok(Value::Nil),
+            },
+        );
+        let set: Set = Box::new(|lua, world, entity, value| {
+            let mut storage = world.write_storage::<C>();
+            if value.is_nil() {
+                storage.remove(entity);
+            } else {
+                storage
+                    .insert(entity, lua.from_value(value)?)
+                    .map_err(mlua::Error::external)?;
+            }
+            Ok(())
+        });
+        self.components.insert(name.to_string(), (get, set));
+    }
+}
+
+/// Runs the `update(dt)` function of every Lua script under a directory once per fixed update,
+/// reloading scripts as they change. Each script gets its own globals, and the engine is reached
+/// through the shared `triton` table:
+///
+/// - `triton.spawn()` and `triton.despawn(id)` create and delete entities, named by id
+/// - `triton.get(id, name)` and `triton.set(id, name, value)` read and write components; setting
+///   `nil` removes one
+/// - `triton.active(action)`, `triton.value(action)`, `triton.just_pressed(action)` and
+///   `triton.just_released(action)` query the first player's input
+/// - `triton.line(from, to, color)` draws a line over the next frame, from `{x, y, z}` tables and
+///   an optional `{r, g, b, a}` array
+///
+/// A script that fails is logged and skipped until it's changed.
+pub struct ScriptRunner {
+    lua: Lua,
+    dir: PathBuf,
+    components: ScriptComponents,
+    /// Each loaded script's environment, by canonical path.
+    scripts: BTreeMap<PathBuf, RegistryKey>,
+    watcher: Option<AssetWatcher>,
+}
+
+impl ScriptRunner {
+    /// Loads the scripts in `dir`, a missing directory holding none.
+    pub fn new(dir: &Path, components: ScriptComponents) -> Self {
+        let watcher = dir
+            .is_dir()
+            .then(|| AssetWatcher::new(dir))
+            .transpose()
+            .map_err(|e| log::warn!("scripts won't be reloaded: {:#}", e))
+            .ok()
+            .flatten();
+        let mut runner = ScriptRunner {
+            lua: Lua::new(),
+            dir: dir.to_path_buf(),
+            components,
+            scripts: BTreeMap::new(),
+            watcher,
+        };
+
+        let paths = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_script(path));
+        for path in paths {
+            runner.load(&path);
+        }
+        runner
+    }
+
+    fn load(&mut self, path: &Path) {
+        let path = canonical(path);
+        if let Some(key) = self.scripts.remove(&path) {
+            let _ = self.lua.remove_registry_value(key);
+        }
+        match self.load_environment(&path) {
+            Ok(key) => {
+                log::info!("loaded script {:?}", path);
+                self.scripts.insert(path, key);
+            }
+            Err(e) => log::error!("failed to load script {:?}: {:#}", path, e),
+        }
+    }
+
+    fn load_environment(&self, path: &Path) -> anyhow::Result<RegistryKey> {
+        let source = fs::read_to_string(path).context("reading script")?;
+        let lua = &self.lua;
+        let environment = lua.create_table()?;
+        let fallback = lua.create_table()?;
+        fallback.set("__index", lua.globals())?;
+        environment.set_metatable(Some(fallback));
+        lua.load(&source)
+            .set_name(path.to_string_lossy())
+            .set_environment(environment.clone())
+            .exec()
+            .context("running script")?;
+        Ok(lua.create_registry_value(environment)?)
+    }
+
+    fn reload_changed(&mut self) {
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        for path in watcher.changed() {
+            if is_script(&path) && path.exists() {
+                self.load(&path);
+            }
+        }
+    }
+
+    fn update(&mut self, world: &World) {
+        let lua = &self.lua;
+        let components = &self.components;
+        let mut failed = vec![];
+
+        let result = lua.scope(|scope| {
+            let triton = lua.create_table()?;
+            triton.set(
+                "spawn",
+                scope.create_function(|_, ()| Ok(world.entities().create().id()))?,
+            )?;
+            triton.set(
+                "despawn",
+                scope.create_function(|_, id: u32| {
+                    world
+                        .entities()
+                        .delete(entity(world, id)?)
+                        .map_err(mlua::Error::external)
+                })?,
+            )?;
+            triton.set(
+                "get",
+                scope.create_function(|lua, (id, name): (u32, String)| {
+                    let (get, _) = component(components, &name)?;
+                    get(lua, world, entity(world, id)?)
+                })?,
+            )?;
+            triton.set(
+                "set",
+                scope.create_function(|lua, (id, name, value): (u32, String, Value)| {
+                    let (_, set) = component(components, &name)?;
+                    set(lua, world, entity(world, id)?, value)
+                })?,
+            )?;
+            triton.set(
+                "active",
+                scope.create_function(|_, action: String| {
+                    Ok(world.read_resource::<InputStateResource>().active(&action))
+                })?,
+            )?;
+            triton.set(
+                "value",
+                scope.create_function(|_, action: String| {
+                    Ok(world.read_resource::<InputStateResource>().value(&action))
+                })?,
+            )?;
+            triton.set(
+                "just_pressed",
+                scope.create_function(|_, action: String| {
+                    Ok(world
+                        .read_resource::<InputStateResource>()
+                        .just_pressed(&action))
+                })?,
+            )?;
+            triton.set(
+                "just_released",
+                scope.create_function(|_, action: String| {
+                    Ok(world
+                        .read_resource::<InputStateResource>()
+                        .just_released(&action))
+                })?,
+            )?;
+            triton.set(
+                "line",
+                scope.create_function(
+                    |lua, (from, to, color): (Value, Value, Option<Value>)| {
+                        let from: Vector3<f32> = lua.from_value(from)?;
+                        let to: Vector3<f32> = lua.from_value(to)?;
+                        let color = match color {
+                            Some(color) => lua.from_value(color)?,
+                            None => [1.0; 4],
+                        };
+                        world
+                            .write_resource::<DebugLines>()
+                            .0
+                            .push(DebugLine { from, to, color });
+                        Ok(())
+                    },
+                )?,
+            )?;
+            lua.globals().set("triton", triton)?;
+
+            for (path, key) in &self.scripts {
+                let environment: Table = lua.registry_value(key)?;
+                let Ok(Some(update)) = environment.get::<_, Option<Function>>("update") else {
+                    continue;
+                };
+                if let Err(e) = update.call::<_, ()>(FIXED_TIME_STEP) {
+                    log::error!("script {:?} failed: {}", path, e);
+                    failed.push(path.clone());
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            log::error!("failed to run scripts: {}", e);
+        }
+
+        for path in failed {
+            if let Some(key) = self.scripts.remove(&path) {
+                let _ = self.lua.remove_registry_value(key);
+            }
+        }
+    }
+}
+
+impl<'a> RunNow<'a> for ScriptRunner {
+    fn run_now(&mut self, world: &'a World) {
+        profile_scope!("scripts");
+        self.reload_changed();
+        self.update(world);
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        world
+            .entry::<DebugLines>()
+            .or_insert_with(DebugLines::default);
+        world
+            .entry::<InputStateResource>()
+            .or_insert_with(InputStateResource::default);
+        if self.scripts.is_empty() {
+            log::debug!("no scripts in {:?}", self.dir);
+        }
+    }
+}
+
+fn is_script(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "lua")
+}
+
+fn entity(world: &World, id: u32) -> mlua::Result<Entity> {
+    let entity = world.entities().entity(id);
+    if world.entities().is_alive(entity) {
+        Ok(entity)
+    } else {
+        Err(mlua::Error::RuntimeError(format!("no entity {}", id)))
+    }
+}
+
+fn component<'a>(components: &'a ScriptComponents, name: &str) -> mlua::Result<&'a (Get, Set)> {
+    components
+        .components
+        .get(name)
+        .ok_or_else(|| mlua::Error::RuntimeError(format!("unknown component {}", name)))
+}
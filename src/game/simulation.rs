@@ -0,0 +1,1075 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use cgmath::Vector3;
+use specs::{Dispatcher, Entity, Join, World, WorldExt, WriteStorage};
+use winit::dpi::PhysicalSize;
+
+use crate::renderer::{
+    Attenuation, Billboard, DirectionalLight, MaterialId, ReflectionProbe, RenderLayers, Tint,
+};
+
+use super::{
+    components::{
+        apply_timeline_command,
+        render::{
+            BillboardComponent, DirectionalLightComponent, PointLightComponent, PortalComponent,
+            ReflectionProbeComponent, Renderable, SelectedTag, TintComponent,
+        },
+        transform::Transform,
+        ActiveCamera, Camera, CameraEffects, CameraOffset, CurrentWindowSize, DebugLine,
+        DebugLines, EntityKey, ParticleEmitter, Sky, SpatialIndex, TimeOfDay, TimelineEvent,
+        TimelineStatus, Timelines, ViewModel, VisibilityResource,
+    },
+    context::{InputStateResource, MouseDeltaResource, PlayerInputStateResource},
+    input::{ActionState, ActionTracker, PlayerIndex, Rumble, RumbleQueue},
+    inspect::{Edit, EditorView, Inspector},
+    jobs::JobSystem,
+    nav::PathResults,
+    prefab::{PrefabFile, Prefabs},
+    replay::{Replay, ReplayEdit, ReplaySession, ReplayStatus, TickInput},
+    save::{self, SaveRegistry},
+    voxel::VoxelWorld,
+};
+
+#[cfg(feature = "net")]
+use super::net::{NetSession, RemoteInputs};
+
+// Note to self: Updates per second is number of times update is called per second
+// at 60 frames, this works out to a 4 updates each frame, time permitting
+const UPS: f32 = 240.0;
+pub(crate) const FIXED_TIME_STEP: f32 = 1.0 / UPS;
+/// Longest stretch of time simulated at once, so a stall doesn't turn into a burst of updates.
+const MAX_FRAME_TIME: f32 = 1.0 / 60.0;
+
+/// A renderable entity as it was at the end of a tick.
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectSnapshot {
+    pub entity: EntityKey,
+    pub transform: Transform,
+    pub mesh_id: usize,
+    pub material: Option<MaterialId>,
+    pub tint: Tint,
+    pub selected: bool,
+    pub layers: RenderLayers,
+    /// Set for view models, whose transform is relative to the camera.
+    pub view_model: Option<ViewModel>,
+}
+
+/// A point light entity as it was at the end of a tick.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSnapshot {
+    pub entity: EntityKey,
+    pub position: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub radius: f32,
+    pub attenuation: Attenuation,
+}
+
+/// A billboard entity as it was at the end of a tick, at its world space position.
+#[derive(Debug, Clone, Copy)]
+pub struct BillboardSnapshot {
+    pub entity: EntityKey,
+    pub billboard: Billboard,
+}
+
+/// A portal entity as it was at the end of a tick.
+#[derive(Debug, Clone, Copy)]
+pub struct PortalSnapshot {
+    pub entity: EntityKey,
+    pub source: Transform,
+    /// A mirror when `None`.
+    pub destination: Option<Transform>,
+    pub size: [f32; 2],
+}
+
+/// The state the renderer needs from the simulation world, copied out after a tick. Objects,
+/// lights, billboards and portals are ordered by entity id.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub tick: u64,
+    /// Every live entity, renderable or not.
+    pub entities: usize,
+    pub camera: Option<Camera>,
+    /// The active camera's `CameraEffects`, applied when it's drawn.
+    pub camera_offset: CameraOffset,
+    /// The other cameras with an `order`, by entity, in the order they're drawn.
+    pub overlay_cameras: Vec<(u32, Camera)>,
+    pub objects: Vec<ObjectSnapshot>,
+    pub lights: Vec<LightSnapshot>,
+    pub billboards: Vec<BillboardSnapshot>,
+    pub portals: Vec<PortalSnapshot>,
+    /// Not interpolated, a probe that moves is captured again where it ends up.
+    pub reflection_probes: Vec<ReflectionProbe>,
+    /// Every emitter's live particles, which aren't interpolated.
+    pub particles: Vec<Billboard>,
+    pub sun: Option<DirectionalLight>,
+    /// From the `TimeOfDay`, when there is one.
+    pub sky: Option<Sky>,
+    /// Drawn by the fixed update, e.g. from scripts.
+    pub debug_lines: Vec<DebugLine>,
+    pub timelines: Vec<TimelineStatus>,
+    /// Only captured while the editor is open.
+    pub editor: Option<EditorView>,
+    /// The entities' bounds as of this tick, for culling and picking.
+    pub spatial_index: Arc<SpatialIndex>,
+}
+
+impl Snapshot {
+    fn capture(world: &World, tick: u64, editor: Option<EditorView>) -> Self {
+        let entities = world.entities();
+        let transforms = world.read_storage::<Transform>();
+        let renderables = world.read_storage::<Renderable>();
+        let tints = world.read_storage::<TintComponent>();
+        let selected = world.read_storage::<SelectedTag>();
+        let lights = world.read_storage::<PointLightComponent>();
+        let suns = world.read_storage::<DirectionalLightComponent>();
+        let billboards = world.read_storage::<BillboardComponent>();
+        let emitters = world.read_storage::<ParticleEmitter>();
+        let portals = world.read_storage::<PortalComponent>();
+        let probes = world.read_storage::<ReflectionProbeComponent>();
+        let cameras = world.read_storage::<Camera>();
+        let view_models = world.read_storage::<ViewModel>();
+
+        let active = world.try_fetch::<ActiveCamera>().map(|active| active.0);
+        let camera = active.and_then(|active| cameras.get(active).copied());
+        let camera_offset = active
+            .and_then(|active| world.read_storage::<CameraEffects>().get(active).copied())
+            .map(|effects| effects.offset())
+            .unwrap_or_default();
+
+        let mut overlay_cameras: Vec<(u32, Camera)> = (&entities, &cameras)
+            .join()
+            .filter(|(entity, camera)| Some(*entity) != active && camera.order.is_some())
+            .map(|(entity, camera)| (entity.id(), *camera))
+            .collect();
+        // Stable, so cameras with the same order are drawn by entity
+        overlay_cameras.sort_by_key(|(_, camera)| camera.order);
+
+        let entity_count = entities.join().count();
+
+        let objects = (
+            &entities,
+            &transforms,
+            &renderables,
+            tints.maybe(),
+            selected.maybe(),
+            view_models.maybe(),
+        )
+            .join()
+            .map(
+                |(entity, transform, renderable, tint, selected, view_model)| ObjectSnapshot {
+                    entity: EntityKey::from(entity),
+                    transform: *transform,
+                    mesh_id: renderable.mesh_id,
+                    material: renderable.material,
+                    tint: tint.map(|tint| tint.0).unwrap_or_default(),
+                    selected: selected.is_some(),
+                    layers: renderable.layers,
+                    view_model: view_model.copied(),
+                },
+            )
+            .collect();
+
+        let lights = (&entities, &transforms, &lights)
+            .join()
+            .map(|(entity, transform, light)| LightSnapshot {
+                entity: EntityKey::from(entity),
+                position: transform.position,
+                color: light.color,
+                intensity: light.intensity,
+                radius: light.radius,
+                attenuation: light.attenuation,
+            })
+            .collect();
+
+        let billboards = (&entities, &transforms, &billboards)
+            .join()
+            .map(|(entity, transform, billboard)| BillboardSnapshot {
+                entity: EntityKey::from(entity),
+                billboard: Billboard {
+                    position: transform.position + billboard.0.position,
+                    ..billboard.0
+                },
+            })
+            .collect();
+
+        let portals = (&entities, &transforms, &portals)
+            .join()
+            .map(|(entity, transform, portal)| PortalSnapshot {
+                entity: EntityKey::from(entity),
+                source: *transform,
+                destination: portal.destination,
+                size: portal.size,
+            })
+            .collect();
+
+        let reflection_probes = (&transforms, &probes)
+            .join()
+            .map(|(transform, probe)| ReflectionProbe::new(transform.position, probe.half_extents))
+            .collect();
+
+        let particles = emitters
+            .join()
+            .flat_map(|emitter| emitter.billboards())
+            .collect();
+
+        let sun = suns.join().next().map(DirectionalLight::from);
+
+        Snapshot {
+            tick,
+            entities: entity_count,
+            camera,
+            camera_offset,
+            overlay_cameras,
+            objects,
+            lights,
+            billboards,
+            portals,
+            reflection_probes,
+            particles,
+            sun,
+            sky: world.try_fetch::<TimeOfDay>().and_then(|time| time.sky()),
+            debug_lines: world
+                .try_fetch::<DebugLines>()
+                .map(|lines| lines.0.clone())
+                .unwrap_or_default(),
+            timelines: world
+                .try_fetch::<Timelines>()
+                .map(|timelines| timelines.status())
+                .unwrap_or_default(),
+            editor,
+            spatial_index: Arc::new(
+                world
+                    .try_fetch::<SpatialIndex>()
+                    .map(|index| index.clone())
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+
+    /// The object of the live entity with the id `entity`, whichever generation that is. Compare
+    /// `ObjectSnapshot::entity` to tell whether it's the same entity as in another snapshot.
+    pub fn object(&self, entity: u32) -> Option<&ObjectSnapshot> {
+        self.objects
+            .binary_search_by_key(&entity, |object| object.entity.id())
+            .ok()
+            .map(|index| &self.objects[index])
+    }
+
+    pub fn overlay_camera(&self, entity: u32) -> Option<&Camera> {
+        self.overlay_cameras
+            .iter()
+            .find(|(overlay, _)| *overlay == entity)
+            .map(|(_, camera)| camera)
+    }
+
+    pub fn light(&self, entity: u32) -> Option<&LightSnapshot> {
+        self.lights
+            .binary_search_by_key(&entity, |light| light.entity.id())
+            .ok()
+            .map(|index| &self.lights[index])
+    }
+
+    pub fn portal(&self, entity: u32) -> Option<&PortalSnapshot> {
+        self.portals
+            .binary_search_by_key(&entity, |portal| portal.entity.id())
+            .ok()
+            .map(|index| &self.portals[index])
+    }
+
+    pub fn billboard(&self, entity: u32) -> Option<&BillboardSnapshot> {
+        self.billboards
+            .binary_search_by_key(&entity, |billboard| billboard.entity.id())
+            .ok()
+            .map(|index| &self.billboards[index])
+    }
+}
+
+struct Published {
+    previous: Arc<Snapshot>,
+    current: Arc<Snapshot>,
+    at: Instant,
+}
+
+/// The two most recent snapshots. The render thread draws between them, one tick behind the
+/// simulation, so motion stays smooth whatever the ratio of frames to ticks.
+pub struct SnapshotBuffer {
+    published: Mutex<Published>,
+}
+
+impl SnapshotBuffer {
+    fn new(initial: Snapshot) -> Self {
+        let initial = Arc::new(initial);
+        SnapshotBuffer {
+            published: Mutex::new(Published {
+                previous: initial.clone(),
+                current: initial,
+                at: Instant::now(),
+            }),
+        }
+    }
+
+    fn publish(&self, snapshot: Snapshot) {
+        if let Ok(mut published) = self.published.lock() {
+            published.previous = std::mem::replace(&mut published.current, Arc::new(snapshot));
+            published.at = Instant::now();
+        }
+    }
+
+    /// The previous and current snapshots, with how far to blend from one to the other.
+    pub fn latest(&self) -> (Arc<Snapshot>, Arc<Snapshot>, f32) {
+        let published = self
+            .published
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let blend = published.at.elapsed().as_secs_f32() / FIXED_TIME_STEP;
+        (
+            published.previous.clone(),
+            published.current.clone(),
+            blend.clamp(0.0, 1.0),
+        )
+    }
+}
+
+/// What the render thread hands the simulation each frame.
+#[derive(Default)]
+pub struct SimulationInput {
+    pub actions: HashMap<String, ActionState>,
+    /// Every player's actions, including the first player's `actions`.
+    pub players: HashMap<PlayerIndex, HashMap<String, ActionState>>,
+    /// Raw mouse motion over the frame.
+    pub mouse_delta: (f32, f32),
+    pub window_size: Option<PhysicalSize<u32>>,
+    /// What passed culling in the last rendered frame.
+    pub visibility: VisibilityResource,
+    /// Stops the fixed update. Snapshots keep being published so edits still show.
+    pub paused: bool,
+    /// Present while the editor is open.
+    pub editor: Option<EditorInput>,
+}
+
+/// What the editor wants from the simulation world.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EditorInput {
+    /// Inspected, and outlined through its `SelectedTag`.
+    pub selected: Option<u32>,
+}
+
+/// Runs the fixed update dispatcher on its own thread, publishing a snapshot after every tick.
+/// The thread is stopped and joined on drop.
+pub struct Simulation {
+    running: Arc<AtomicBool>,
+    input: Arc<Mutex<SimulationInput>>,
+    edits: Arc<Mutex<Vec<Edit>>>,
+    saves: Arc<Mutex<Vec<SaveRequest>>>,
+    save_registry: Arc<Mutex<SaveRegistry>>,
+    replays: Arc<Mutex<Vec<ReplayRequest>>>,
+    replay_status: Arc<Mutex<ReplayStatus>>,
+    /// Queued by the fixed update through `RumbleQueue`, played by the render thread.
+    rumbles: Arc<Mutex<Vec<(PlayerIndex, Rumble)>>>,
+    /// Cues the fixed update's timelines passed, sent out by the render thread.
+    timeline_events: Arc<Mutex<Vec<TimelineEvent>>>,
+    snapshots: Arc<SnapshotBuffer>,
+    prefab_files: PrefabSender,
+    prefab_names: Arc<Mutex<Vec<String>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Hands prefab files to the simulation thread, which replaces the prefabs last read from the
+/// same path with them before the next tick.
+#[derive(Clone, Default)]
+pub struct PrefabSender(Arc<Mutex<Vec<(PathBuf, PrefabFile)>>>);
+
+impl PrefabSender {
+    pub fn send(&self, path: &Path, file: PrefabFile) {
+        if let Ok(mut files) = self.0.lock() {
+            files.push((path.to_path_buf(), file));
+        }
+    }
+
+    fn take(&self) -> Vec<(PathBuf, PrefabFile)> {
+        self.0
+            .lock()
+            .map(|mut files| std::mem::take(&mut *files))
+            .unwrap_or_default()
+    }
+}
+
+impl Simulation {
+    /// Moves `world` onto a new thread. The dispatcher is built there, since dispatchers can't
+    /// be sent between threads. `inspector` lists the components the editor can see, `prefabs`
+    /// what the console can spawn and `save_registry` what save games hold. `net` is stepped
+    /// after every tick.
+    pub fn spawn<F>(
+        mut world: World,
+        build_dispatcher: F,
+        inspector: Inspector,
+        mut prefabs: Prefabs,
+        save_registry: SaveRegistry,
+        #[cfg(feature = "net")] mut net: Option<NetSession>,
+    ) -> anyhow::Result<Self>
+    where
+        F: FnOnce() -> Dispatcher<'static, 'static> + Send + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let input = Arc::new(Mutex::new(SimulationInput::default()));
+        let edits = Arc::new(Mutex::new(vec![]));
+        let saves = Arc::new(Mutex::new(vec![]));
+        let save_registry = Arc::new(Mutex::new(save_registry));
+        let replays = Arc::new(Mutex::new(vec![]));
+        let replay_status = Arc::new(Mutex::new(ReplayStatus::default()));
+        let rumbles = Arc::new(Mutex::new(vec![]));
+        let timeline_events = Arc::new(Mutex::new(vec![]));
+        world.insert(RumbleQueue::default());
+        world.insert(DebugLines::default());
+        world.insert(VisibilityResource::default());
+        let snapshots = Arc::new(SnapshotBuffer::new(Snapshot::capture(&world, 0, None)));
+        let prefab_files = PrefabSender::default();
+        let prefab_names = Arc::new(Mutex::new(prefabs.names()));
+
+        let handle = {
+            let running = running.clone();
+            let input = input.clone();
+            let edits = edits.clone();
+            let saves = saves.clone();
+            let save_registry = save_registry.clone();
+            let replays = replays.clone();
+            let replay_status = replay_status.clone();
+            let rumbles = rumbles.clone();
+            let timeline_events = timeline_events.clone();
+            let snapshots = snapshots.clone();
+            let prefab_files = prefab_files.clone();
+            let prefab_names = prefab_names.clone();
+
+            thread::Builder::new()
+                .name("simulation".into())
+                .spawn(move || {
+                    let mut dispatcher = build_dispatcher();
+                    let mut previous_instant = Instant::now();
+                    let mut accumulated_time = 0.0;
+                    let mut tick = 0;
+                    let mut selection: EditorSelection = None;
+                    let mut action_tracker = ActionTracker::default();
+                    let mut player_trackers: HashMap<PlayerIndex, ActionTracker> = HashMap::new();
+                    let mut replay = ReplaySession::default();
+
+                    while running.load(Ordering::Acquire) {
+                        let current_instant = Instant::now();
+                        accumulated_time += current_instant
+                            .duration_since(previous_instant)
+                            .as_secs_f32()
+                            .min(MAX_FRAME_TIME);
+                        previous_instant = current_instant;
+
+                        let (paused, editor) = input
+                            .lock()
+                            .map(|input| (input.paused, input.editor))
+                            .unwrap_or_default();
+                        if let Ok(mut edits) = edits.lock() {
+                            for edit in edits.drain(..) {
+                                if replay.is_playing() {
+                                    log::warn!("ignoring {:?} while playing a replay", edit);
+                                    continue;
+                                }
+                                replay.record_edit(&edit);
+                                apply(&world, &inspector, &prefabs, edit);
+                            }
+                        }
+                        let files = prefab_files.take();
+                        if !files.is_empty() {
+                            for (path, file) in files {
+                                log::info!("loading prefabs from {:?}", path);
+                                prefabs.set_file(&path, file);
+                            }
+                            if let Ok(mut names) = prefab_names.lock() {
+                                *names = prefabs.names();
+                            }
+                        }
+                        let requests = saves.lock().map(|mut saves| std::mem::take(&mut *saves));
+                        for request in requests.unwrap_or_default() {
+                            let registry =
+                                save_registry.lock().unwrap_or_else(PoisonError::into_inner);
+                            handle_save(&mut world, &registry, request);
+                        }
+                        let requests = replays
+                            .lock()
+                            .map(|mut replays| std::mem::take(&mut *replays));
+                        for request in requests.unwrap_or_default() {
+                            let registry =
+                                save_registry.lock().unwrap_or_else(PoisonError::into_inner);
+                            replay = handle_replay(&mut world, &registry, replay, request);
+                        }
+                        if let Ok(mut status) = replay_status.lock() {
+                            *status = replay.status();
+                        }
+                        selection =
+                            select(&world, selection, editor.and_then(|editor| editor.selected));
+                        let view = |world: &World| {
+                            editor.map(|editor| inspector.view(world, editor.selected))
+                        };
+
+                        if paused {
+                            accumulated_time = 0.0;
+                            snapshots.publish(Snapshot::capture(&world, tick, view(&world)));
+                            thread::sleep(Duration::from_secs_f32(FIXED_TIME_STEP));
+                            continue;
+                        }
+
+                        if accumulated_time < FIXED_TIME_STEP {
+                            thread::sleep(Duration::from_secs_f32(
+                                FIXED_TIME_STEP - accumulated_time,
+                            ));
+                            continue;
+                        }
+
+                        let (mut actions, mut players, mut mouse_delta) = match input.lock() {
+                            Ok(mut input) => {
+                                world.write_resource::<CurrentWindowSize>().0 = input.window_size;
+                                *world.write_resource::<VisibilityResource>() =
+                                    input.visibility.clone();
+                                let taken = (
+                                    input.actions.clone(),
+                                    input.players.clone(),
+                                    std::mem::take(&mut input.mouse_delta),
+                                );
+                                // Motion is applied once, however many ticks read this input
+                                remove_deltas(&mut input.actions);
+                                input.players.values_mut().for_each(remove_deltas);
+                                taken
+                            }
+                            Err(_) => Default::default(),
+                        };
+                        player_trackers.retain(|player, _| players.contains_key(player));
+
+                        while accumulated_time >= FIXED_TIME_STEP {
+                            profile_scope!("fixed_update");
+                            // The same frame's input can last several ticks, only the first of
+                            // which sees its presses
+                            let step = Duration::from_secs_f32(FIXED_TIME_STEP);
+                            if let Some(input) = replay.next_input() {
+                                let live_id = |id| replay.live_id(id);
+                                for edit in &input.edits {
+                                    apply_replayed(&world, &inspector, &prefabs, edit, live_id);
+                                }
+                                insert_tick_input(&mut world, input, live_id);
+                            } else {
+                                world.insert(InputStateResource(
+                                    action_tracker.update(&actions, step),
+                                ));
+                                world.insert(PlayerInputStateResource(
+                                    players
+                                        .iter()
+                                        .map(|(&player, actions)| {
+                                            let tracker =
+                                                player_trackers.entry(player).or_default();
+                                            let states = tracker.update(actions, step);
+                                            (player, InputStateResource(states))
+                                        })
+                                        .collect(),
+                                ));
+                                world.insert(MouseDeltaResource(mouse_delta));
+                            }
+                            remove_deltas(&mut actions);
+                            players.values_mut().for_each(remove_deltas);
+                            mouse_delta = (0.0, 0.0);
+                            world.write_resource::<DebugLines>().0.clear();
+                            dispatcher.dispatch(&world);
+                            world.maintain();
+                            {
+                                let registry =
+                                    save_registry.lock().unwrap_or_else(PoisonError::into_inner);
+                                replay.end_tick(&world, &registry, || tick_input(&world));
+                            }
+                            #[cfg(feature = "net")]
+                            if let Some(net) = &mut net {
+                                net.tick(&mut world, tick);
+                            }
+                            let queued =
+                                std::mem::take(&mut world.write_resource::<RumbleQueue>().0);
+                            if !queued.is_empty() {
+                                if let Ok(mut rumbles) = rumbles.lock() {
+                                    rumbles.extend(queued);
+                                }
+                            }
+                            let fired = world
+                                .try_fetch_mut::<Timelines>()
+                                .map(|mut timelines| timelines.take_events())
+                                .unwrap_or_default();
+                            if !fired.is_empty() {
+                                if let Ok(mut events) = timeline_events.lock() {
+                                    events.extend(fired);
+                                }
+                            }
+                            accumulated_time -= FIXED_TIME_STEP;
+                            tick += 1;
+                            snapshots.publish(Snapshot::capture(&world, tick, view(&world)));
+                        }
+                    }
+                })
+                .context("spawning simulation thread")?
+        };
+
+        Ok(Simulation {
+            running,
+            input,
+            edits,
+            saves,
+            save_registry,
+            replays,
+            replay_status,
+            rumbles,
+            timeline_events,
+            snapshots,
+            prefab_files,
+            prefab_names,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn snapshots(&self) -> &Arc<SnapshotBuffer> {
+        &self.snapshots
+    }
+
+    /// Replaces the input the simulation reads on its next ticks. Motion no tick has read yet is
+    /// added to `input`'s.
+    pub fn set_input(&self, mut input: SimulationInput) {
+        if let Ok(mut current) = self.input.lock() {
+            add_deltas(&current.actions, &mut input.actions);
+            for (player, actions) in &current.players {
+                if let Some(next) = input.players.get_mut(player) {
+                    add_deltas(actions, next);
+                }
+            }
+            input.mouse_delta.0 += current.mouse_delta.0;
+            input.mouse_delta.1 += current.mouse_delta.1;
+            *current = input;
+        }
+    }
+
+    /// Rumbles the fixed update queued since the last call.
+    pub fn take_rumbles(&self) -> Vec<(PlayerIndex, Rumble)> {
+        self.rumbles
+            .lock()
+            .map(|mut rumbles| std::mem::take(&mut *rumbles))
+            .unwrap_or_default()
+    }
+
+    /// Cues the fixed update's timelines passed since the last call.
+    pub fn take_timeline_events(&self) -> Vec<TimelineEvent> {
+        self.timeline_events
+            .lock()
+            .map(|mut events| std::mem::take(&mut *events))
+            .unwrap_or_default()
+    }
+
+    /// The prefabs `Edit::Spawn` can create, sorted by name.
+    pub fn prefab_names(&self) -> Vec<String> {
+        self.prefab_names
+            .lock()
+            .map(|names| names.clone())
+            .unwrap_or_default()
+    }
+
+    /// Where prefab files are sent to be loaded, e.g. again after they changed on disk.
+    pub fn prefab_files(&self) -> PrefabSender {
+        self.prefab_files.clone()
+    }
+
+    /// Saves the `Saved` entities to `path` between ticks. The file is written in the background.
+    pub fn save(&self, path: impl Into<PathBuf>) {
+        if let Ok(mut saves) = self.saves.lock() {
+            saves.push(SaveRequest::Save(path.into()));
+        }
+    }
+
+    /// Replaces the `Saved` entities with those saved at `path` before the next tick.
+    pub fn load(&self, path: impl Into<PathBuf>) {
+        if let Ok(mut saves) = self.saves.lock() {
+            saves.push(SaveRequest::Load(path.into()));
+        }
+    }
+
+    /// Starts recording the input of every tick, from a save of the `Saved` entities.
+    pub fn record_replay(&self) {
+        self.request_replay(ReplayRequest::Record);
+    }
+
+    /// Replaces the `Saved` entities with those the replay at `path` started from, then runs the
+    /// following ticks with its input in place of the live input.
+    pub fn play_replay(&self, path: impl Into<PathBuf>) {
+        self.request_replay(ReplayRequest::Play(path.into()));
+    }
+
+    /// Stops recording, writing the replay to `path` in the background when given, or stops
+    /// playing back.
+    pub fn stop_replay(&self, path: Option<PathBuf>) {
+        self.request_replay(ReplayRequest::Stop(path));
+    }
+
+    pub fn replay_status(&self) -> ReplayStatus {
+        self.replay_status
+            .lock()
+            .map(|status| *status)
+            .unwrap_or_default()
+    }
+
+    fn request_replay(&self, request: ReplayRequest) {
+        if let Ok(mut replays) = self.replays.lock() {
+            replays.push(request);
+        }
+    }
+
+    /// Where components are registered for saving, and migrations of older saves added.
+    pub fn save_registry(&self) -> MutexGuard<'_, SaveRegistry> {
+        self.save_registry
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues `edit`, applied before the next tick or, while paused, the next snapshot.
+    pub fn edit(&self, edit: Edit) {
+        if let Ok(mut edits) = self.edits.lock() {
+            edits.push(edit);
+        }
+    }
+}
+
+enum SaveRequest {
+    Save(PathBuf),
+    Load(PathBuf),
+}
+
+/// Encodes a save right away, so it matches the tick it was asked for, and leaves writing it to
+/// the job pool. Failures are logged.
+fn handle_save(world: &mut World, registry: &SaveRegistry, request: SaveRequest) {
+    match request {
+        SaveRequest::Save(path) => match registry.save(world) {
+            Ok(bytes) => {
+                world.read_resource::<JobSystem>().pool().spawn(move || {
+                    match save::write(&path, &bytes) {
+                        Ok(()) => log::info!("saved {:?}", path),
+                        Err(e) => log::error!("{:#}", e),
+                    }
+                });
+            }
+            Err(e) => log::error!("saving {:?}: {:#}", path, e),
+        },
+        SaveRequest::Load(path) => {
+            let loaded = std::fs::read(&path)
+                .with_context(|| format!("reading {:?}", path))
+                .and_then(|bytes| registry.load(world, &bytes));
+            match loaded {
+                Ok(_) => log::info!("loaded {:?}", path),
+                Err(e) => log::error!("loading {:?}: {:#}", path, e),
+            }
+        }
+    }
+}
+
+enum ReplayRequest {
+    Record,
+    Play(PathBuf),
+    Stop(Option<PathBuf>),
+}
+
+/// Starts and stops replays, logging failures and leaving `session` as it was on failure.
+fn handle_replay(
+    world: &mut World,
+    registry: &SaveRegistry,
+    session: ReplaySession,
+    request: ReplayRequest,
+) -> ReplaySession {
+    match (request, session) {
+        (ReplayRequest::Record, session) if session.is_playing() => {
+            log::warn!("can't record while playing a replay");
+            session
+        }
+        (ReplayRequest::Record, session) => match registry.save(world) {
+            Ok(start) => {
+                log::info!("recording replay");
+                ReplaySession::record(start, &registry.saved_entities(world))
+            }
+            Err(e) => {
+                log::error!("starting replay: {:#}", e);
+                session
+            }
+        },
+        (ReplayRequest::Play(path), session) => {
+            let started = Replay::load(&path).and_then(|replay| {
+                let loaded = registry.load(world, &replay.start)?;
+                Ok((replay, loaded))
+            });
+            match started {
+                Ok((replay, loaded)) => {
+                    log::info!("playing {:?}, {} ticks", path, replay.ticks.len());
+                    ReplaySession::play(replay, &loaded)
+                }
+                Err(e) => {
+                    log::error!("playing {:?}: {:#}", path, e);
+                    session
+                }
+            }
+        }
+        (ReplayRequest::Stop(path), ReplaySession::Recording { replay, .. }) => {
+            log::info!("recorded {} ticks", replay.ticks.len());
+            if let Some(path) = path {
+                world.read_resource::<JobSystem>().pool().spawn(move || {
+                    match replay.encode().and_then(|bytes| save::write(&path, &bytes)) {
+                        Ok(()) => log::info!("saved replay {:?}", path),
+                        Err(e) => log::error!("saving replay {:?}: {:#}", path, e),
+                    }
+                });
+            }
+            ReplaySession::Idle
+        }
+        (ReplayRequest::Stop(_), _) => ReplaySession::Idle,
+    }
+}
+
+/// Inserts the input resources a replayed tick ran with, mapping the entity ids it recorded
+/// through `live_id`.
+fn insert_tick_input(world: &mut World, input: &TickInput, live_id: impl Fn(u32) -> u32) {
+    world.insert(InputStateResource(input.actions.clone()));
+    world.insert(PlayerInputStateResource(
+        input
+            .players
+            .iter()
+            .map(|(player, actions)| (*player, InputStateResource(actions.clone())))
+            .collect(),
+    ));
+    world.insert(MouseDeltaResource(input.mouse_delta));
+    world.write_resource::<CurrentWindowSize>().0 = input.window_size;
+    *world.write_resource::<VisibilityResource>() = input.visibility.remapped(&live_id);
+    let paths = input
+        .paths
+        .iter()
+        .map(|(id, path)| (world.entities().entity(live_id(*id)), path.clone()))
+        .collect();
+    world.write_resource::<PathResults>().replayed = Some(paths);
+    #[cfg(feature = "net")]
+    world.insert(RemoteInputs(input.remote_inputs.clone()));
+}
+
+/// The input resources the tick that just ran read, to record.
+fn tick_input(world: &World) -> TickInput {
+    TickInput {
+        actions: world.read_resource::<InputStateResource>().0.clone(),
+        players: world
+            .read_resource::<PlayerInputStateResource>()
+            .0
+            .iter()
+            .map(|(player, actions)| (*player, actions.0.clone()))
+            .collect(),
+        mouse_delta: world.read_resource::<MouseDeltaResource>().0,
+        window_size: world.read_resource::<CurrentWindowSize>().0,
+        visibility: world.read_resource::<VisibilityResource>().clone(),
+        paths: world
+            .try_fetch::<PathResults>()
+            .map(|paths| {
+                paths
+                    .arrived
+                    .iter()
+                    .map(|(entity, path)| (entity.id(), path.clone()))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        #[cfg(feature = "net")]
+        remote_inputs: world
+            .try_fetch::<RemoteInputs>()
+            .map(|inputs| inputs.0.clone())
+            .unwrap_or_default(),
+        #[cfg(not(feature = "net"))]
+        remote_inputs: HashMap::new(),
+        edits: vec![],
+    }
+}
+
+/// Applies an edit read from a replay, mapping the entity ids it recorded through `live_id`.
+fn apply_replayed(
+    world: &World,
+    inspector: &Inspector,
+    prefabs: &Prefabs,
+    edit: &ReplayEdit,
+    live_id: impl Fn(u32) -> u32,
+) {
+    match edit {
+        ReplayEdit::Field {
+            entity,
+            component,
+            field,
+            value,
+        } => {
+            if !inspector.write(world, live_id(*entity), component, field, *value) {
+                log::warn!("ignoring replayed edit of {}.{}", component, field);
+            }
+        }
+        ReplayEdit::Transform { entity, transform } => apply(
+            world,
+            inspector,
+            prefabs,
+            Edit::Transform {
+                entity: live_id(*entity),
+                transform: *transform,
+            },
+        ),
+        ReplayEdit::Spawn { prefab, transform } => apply(
+            world,
+            inspector,
+            prefabs,
+            Edit::Spawn {
+                prefab: prefab.clone(),
+                transform: *transform,
+            },
+        ),
+        ReplayEdit::TimeOfDay { hour, paused } => apply(
+            world,
+            inspector,
+            prefabs,
+            Edit::TimeOfDay {
+                hour: *hour,
+                paused: *paused,
+            },
+        ),
+        ReplayEdit::Voxels { min, max, voxel } => apply(
+            world,
+            inspector,
+            prefabs,
+            Edit::Voxels {
+                min: *min,
+                max: *max,
+                voxel: *voxel,
+            },
+        ),
+        ReplayEdit::Sculpt {
+            center,
+            radius,
+            amount,
+            material,
+        } => apply(
+            world,
+            inspector,
+            prefabs,
+            Edit::Sculpt {
+                center: *center,
+                radius: *radius,
+                amount: *amount,
+                material: *material,
+            },
+        ),
+        ReplayEdit::CameraEffect(effect) => {
+            apply(world, inspector, prefabs, Edit::CameraEffect(*effect))
+        }
+        ReplayEdit::Timeline(command) => {
+            apply(world, inspector, prefabs, Edit::Timeline(command.clone()))
+        }
+    }
+}
+
+/// Applies `edit`, logging the ones that can't be, like edits of dead entities.
+fn apply(world: &World, inspector: &Inspector, prefabs: &Prefabs, edit: Edit) {
+    match edit {
+        Edit::Field(edit) => inspector.apply(world, &edit),
+        Edit::Transform { entity, transform } => {
+            let entity = world.entities().entity(entity);
+            let mut transforms = world.write_storage::<Transform>();
+            match transforms.get_mut(entity) {
+                Some(current) if world.entities().is_alive(entity) => *current = transform,
+                _ => log::warn!("ignoring transform edit of entity {}", entity.id()),
+            }
+        }
+        Edit::Spawn { prefab, transform } => {
+            if prefabs.spawn(world, &prefab, transform).is_none() {
+                log::warn!("no prefab called {}", prefab);
+            }
+        }
+        Edit::TimeOfDay { hour, paused } => match world.try_fetch_mut::<TimeOfDay>() {
+            Some(mut time) => {
+                if let Some(hour) = hour {
+                    time.set_hour(hour);
+                }
+                time.paused = paused.unwrap_or(time.paused);
+            }
+            None => log::warn!("ignoring time of day edit, there's no day-night cycle"),
+        },
+        Edit::Voxels { min, max, voxel } => {
+            world.write_resource::<VoxelWorld>().fill(min, max, voxel);
+        }
+        Edit::Sculpt {
+            center,
+            radius,
+            amount,
+            material,
+        } => {
+            world
+                .write_resource::<VoxelWorld>()
+                .sculpt(center.into(), radius, amount, material);
+        }
+        Edit::CameraEffect(effect) => match world.try_fetch::<ActiveCamera>() {
+            Some(active) => match world.write_storage::<CameraEffects>().entry(active.0) {
+                Ok(entry) => entry.or_insert_with(CameraEffects::default).add(effect),
+                Err(_) => log::warn!("ignoring camera effect, the active camera is dead"),
+            },
+            None => log::warn!("ignoring camera effect, there's no active camera"),
+        },
+        Edit::Timeline(command) => apply_timeline_command(world, command),
+    }
+}
+
+/// Drops the motion in `actions`, once a tick has applied it.
+fn remove_deltas(actions: &mut HashMap<String, ActionState>) {
+    actions.retain(|_, state| !state.delta);
+}
+
+/// Adds the motion in `unread` to that of the same actions in `actions`.
+fn add_deltas(unread: &HashMap<String, ActionState>, actions: &mut HashMap<String, ActionState>) {
+    for (name, state) in unread.iter().filter(|(_, state)| state.delta) {
+        let next = actions.entry(name.clone()).or_insert_with(|| ActionState {
+            value: Some(0.0),
+            ..state.clone()
+        });
+        next.value = Some(next.value.unwrap_or(0.0) + state.value.unwrap_or(0.0));
+    }
+}
+
+/// The entity selected in the editor, and whether the editor added its `SelectedTag` rather than
+/// finding one the game set.
+type EditorSelection = Option<(Entity, bool)>;
+
+/// Moves the editor's `SelectedTag` from the `previous` selection to `entity`, or only removes it
+/// when `None`. Tags the game set are left alone.
+fn select(world: &World, previous: EditorSelection, entity: Option<u32>) -> EditorSelection {
+    let entity = entity.map(|id| world.entities().entity(id));
+    if previous.map(|(previous, _)| previous) == entity {
+        return previous;
+    }
+    let mut tags: WriteStorage<SelectedTag> = world.write_storage();
+    if let Some((previous, true)) = previous {
+        tags.remove(previous);
+    }
+    // Fails for dead entities, which have nothing to outline
+    let entity = entity?;
+    let existing = tags.insert(entity, SelectedTag).ok()?;
+    Some((entity, existing.is_none()))
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::error!("simulation thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use cgmath::{Deg, InnerSpace, Quaternion, Rotation3, Vector3};
+use specs::{Builder, Entity, World, WorldExt};
+
+use crate::{
+    random::Random,
+    renderer::{color_temperature, MaterialId, RenderLayers, Tint},
+};
+
+use super::components::{
+    render::{PointLightComponent, Renderable, TintComponent},
+    transform::Transform,
+    Bounds, Easing, Tween, TweenLoop, TweenTarget,
+};
+
+const LIGHT_LUMENS: f32 = 4000.0;
+
+/// A grid of `size` × `size` × `size` copies of one mesh with point lights scattered through it,
+/// for stress testing and for showing off instancing and culling. Every cube shares the mesh, so
+/// they're drawn together with indirect draws.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StressScene {
+    pub size: u32,
+    pub lights: u32,
+    /// Distance between the centers of neighbouring meshes.
+    pub spacing: f32,
+    /// Spins the meshes and moves the lights with looping tweens.
+    pub animated: bool,
+    /// Seeds the tints, light placement and animation, so the same scene comes out every run.
+    pub seed: u32,
+}
+
+impl Default for StressScene {
+    fn default() -> Self {
+        StressScene {
+            size: 16,
+            lights: 64,
+            spacing: 3.0,
+            animated: true,
+            seed: 1,
+        }
+    }
+}
+
+impl StressScene {
+    pub fn new(size: u32) -> Self {
+        StressScene {
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_lights(mut self, lights: u32) -> Self {
+        self.lights = lights;
+        self
+    }
+
+    pub fn with_animation(mut self, animated: bool) -> Self {
+        self.animated = animated;
+        self
+    }
+
+    /// Half the width of the grid, measured to the outer meshes' centers.
+    pub fn half_extent(&self) -> f32 {
+        self.size.saturating_sub(1) as f32 * self.spacing * 0.5
+    }
+
+    /// Creates the meshes and lights in `world`, centered on the origin.
+    pub fn spawn(
+        &self,
+        world: &mut World,
+        mesh_id: usize,
+        material: Option<MaterialId>,
+    ) -> Vec<Entity> {
+        let mut random = Random::new(self.seed);
+        let half_extent = self.half_extent();
+        let mut entities = vec![];
+
+        for x in 0..self.size {
+            for y in 0..self.size {
+                for z in 0..self.size {
+                    let position = Vector3::new(x as f32, y as f32, z as f32) * self.spacing
+                        - Vector3::new(half_extent, half_extent, half_extent);
+                    let rotation = Quaternion::from_angle_y(Deg(random.range(0.0, 360.0)));
+                    let mut entity = world
+                        .create_entity()
+                        .with(Transform {
+                            position,
+                            rotation,
+                            scale: [1.0, 1.0, 1.0].into(),
+                        })
+                        .with(Renderable {
+                            mesh_id,
+                            material,
+                            layers: RenderLayers::DEFAULT,
+                        })
+                        .with(Bounds::cube(1.0))
+                        .with(TintComponent(Tint {
+                            color: [
+                                random.range(0.2, 1.0),
+                                random.range(0.2, 1.0),
+                                random.range(0.2, 1.0),
+                            ],
+                            amount: 0.6,
+                        }));
+                    if self.animated {
+                        let axis =
+                            Vector3::new(random.range(-1.0, 1.0), 1.0, random.range(-1.0, 1.0));
+                        let to =
+                            rotation * Quaternion::from_axis_angle(axis.normalize(), Deg(180.0));
+                        entity = entity.with(
+                            Tween::new(
+                                TweenTarget::Rotation { from: rotation, to },
+                                random.range(2.0, 6.0),
+                            )
+                            .with_easing(Easing::SineInOut)
+                            .with_looping(TweenLoop::PingPong),
+                        );
+                    }
+                    entities.push(entity.build());
+                }
+            }
+        }
+
+        let light_extent = half_extent + self.spacing;
+        for _ in 0..self.lights {
+            let position = Vector3::new(
+                random.range(-light_extent, light_extent),
+                random.range(-light_extent, light_extent),
+                random.range(-light_extent, light_extent),
+            );
+            let mut entity = world
+                .create_entity()
+                .with(Transform {
+                    position,
+                    rotation: [1.0, 0.0, 0.0, 0.0].into(),
+                    scale: [1.0, 1.0, 1.0].into(),
+                })
+                .with(PointLightComponent {
+                    color: color_temperature(random.range(2000.0, 8000.0)),
+                    intensity: LIGHT_LUMENS,
+                    radius: self.spacing * 3.0,
+                    ..Default::default()
+                });
+            if self.animated {
+                let offset = Vector3::new(
+                    random.range(-1.0, 1.0),
+                    random.range(-1.0, 1.0),
+                    random.range(-1.0, 1.0),
+                ) * (self.spacing * 2.0);
+                entity = entity.with(
+                    Tween::new(
+                        TweenTarget::Position {
+                            from: position,
+                            to: position + offset,
+                        },
+                        random.range(1.0, 4.0),
+                    )
+                    .with_easing(Easing::SineInOut)
+                    .with_looping(TweenLoop::PingPong),
+                );
+            }
+            entities.push(entity.build());
+        }
+
+        entities
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+/// Voxels along each side of a chunk.
+pub const CHUNK_SIZE: i32 = 32;
+
+const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// One cell of a voxel grid, an index into the `VoxelPalette`. Zero is empty air, anything else is
+/// solid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Voxel(pub u8);
+
+impl Voxel {
+    pub const AIR: Voxel = Voxel(0);
+
+    pub fn is_solid(&self) -> bool {
+        *self != Voxel::AIR
+    }
+}
+
+/// The colors voxels are meshed with, indexed by their value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelPalette {
+    /// Linear colors. Voxels past the end are drawn magenta.
+    pub colors: Vec<[f32; 3]>,
+}
+
+impl Default for VoxelPalette {
+    /// Air, grass, dirt, stone, sand, wood, leaves and snow.
+    fn default() -> Self {
+        VoxelPalette {
+            colors: vec![
+                [0.0, 0.0, 0.0],
+                [0.2, 0.45, 0.1],
+                [0.35, 0.22, 0.12],
+                [0.4, 0.4, 0.42],
+                [0.76, 0.7, 0.5],
+                [0.4, 0.28, 0.15],
+                [0.15, 0.35, 0.1],
+                [0.9, 0.92, 0.95],
+            ],
+        }
+    }
+}
+
+impl VoxelPalette {
+    pub fn color(&self, voxel: Voxel) -> [f32; 3] {
+        self.colors
+            .get(voxel.0 as usize)
+            .copied()
+            .unwrap_or([1.0, 0.0, 1.0])
+    }
+}
+
+/// A `CHUNK_SIZE` cube of voxels, indexed from its minimum corner.
+///
+/// Every voxel also has a density from -1 to 1 for smooth meshing, with the surface where it
+/// crosses zero. Voxels are solid exactly where their density is above zero, so the blocky and
+/// smooth meshers agree on what's solid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    voxels: Vec<Voxel>,
+    densities: Vec<f32>,
+    solid: usize,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Chunk {
+            voxels: vec![Voxel::AIR; CHUNK_VOLUME],
+            densities: vec![-1.0; CHUNK_VOLUME],
+            solid: 0,
+        }
+    }
+}
+
+impl Chunk {
+    /// `None` outside the chunk.
+    pub fn get(&self, x: i32, y: i32, z: i32) -> Option<Voxel> {
+        index(x, y, z).map(|index| self.voxels[index])
+    }
+
+    /// `None` outside the chunk.
+    pub fn density(&self, x: i32, y: i32, z: i32) -> Option<f32> {
+        index(x, y, z).map(|index| self.densities[index])
+    }
+
+    /// Sets the voxel at full density, or none for air. Returns the voxel that was there, or
+    /// `None` when the position is outside the chunk.
+    pub fn set(&mut self, x: i32, y: i32, z: i32, voxel: Voxel) -> Option<Voxel> {
+        let density = if voxel.is_solid() { 1.0 } else { -1.0 };
+        let index = index(x, y, z)?;
+        let old = self.voxels[index];
+        self.replace(index, voxel, density);
+        Some(old)
+    }
+
+    /// Sets the density, clamped from -1 to 1, making the voxel `material` where it's above zero
+    /// and air elsewhere. Returns the density that was there, or `None` when the position is
+    /// outside the chunk.
+    pub fn set_density(
+        &mut self,
+        x: i32,
+        y: i32,
+        z: i32,
+        density: f32,
+        material: Voxel,
+    ) -> Option<f32> {
+        let density = density.clamp(-1.0, 1.0);
+        let voxel = if density > 0.0 { material } else { Voxel::AIR };
+        let index = index(x, y, z)?;
+        let old = self.densities[index];
+        self.replace(index, voxel, density);
+        Some(old)
+    }
+
+    fn replace(&mut self, index: usize, voxel: Voxel, density: f32) {
+        let old = std::mem::replace(&mut self.voxels[index], voxel);
+        self.densities[index] = density;
+        match (old.is_solid(), voxel.is_solid()) {
+            (false, true) => self.solid += 1,
+            (true, false) => self.solid -= 1,
+            _ => {}
+        }
+    }
+
+    /// Whether every voxel is air.
+    pub fn is_empty(&self) -> bool {
+        self.solid == 0
+    }
+}
+
+fn index(x: i32, y: i32, z: i32) -> Option<usize> {
+    let range = 0..CHUNK_SIZE;
+    if !range.contains(&x) || !range.contains(&y) || !range.contains(&z) {
+        return None;
+    }
+    Some(((z * CHUNK_SIZE + y) * CHUNK_SIZE + x) as usize)
+}
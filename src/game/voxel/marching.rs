@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3, VectorSpace};
+
+use crate::renderer::VertexPositionColorNormal;
+
+use super::{
+    chunk::{VoxelPalette, CHUNK_SIZE},
+    mesher::PaddedChunk,
+};
+
+/// A cube's corners, with x in the first bit of their index, y in the second and z in the third.
+const CORNERS: [[i32; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [1, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [0, 1, 1],
+    [1, 1, 1],
+];
+
+/// Six tetrahedra around the diagonal from corner 0 to corner 7. Neighbouring cubes split the
+/// faces they share along the same diagonal, so the surface has no cracks.
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 7, 1, 3],
+    [0, 7, 3, 2],
+    [0, 7, 2, 6],
+    [0, 7, 6, 4],
+    [0, 7, 4, 5],
+    [0, 7, 5, 1],
+];
+
+/// Meshes the surface where the densities of `chunk` cross zero, in the same space as
+/// `greedy_mesh`, with normals from the density's gradient. Vertices are shared along the edges
+/// they're on, so the surface is smooth shaded, and take the color of the solid voxel at their
+/// edge's end.
+///
+/// Each cube between eight voxels is split into tetrahedra, which leaves none of the ambiguous
+/// cases the classic marching cubes tables have to resolve, for a few more triangles.
+pub fn marching_cubes(
+    chunk: &PaddedChunk,
+    palette: &VoxelPalette,
+) -> (Vec<VertexPositionColorNormal>, Vec<u32>) {
+    let mut marcher = Marcher {
+        chunk,
+        palette,
+        vertices: vec![],
+        positions: vec![],
+        indices: vec![],
+        edges: HashMap::new(),
+    };
+    for z in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let corners = CORNERS.map(|[dx, dy, dz]| [x + dx, y + dy, z + dz]);
+                let inside = corners
+                    .iter()
+                    .filter(|&&corner| marcher.density(corner) > 0.0)
+                    .count();
+                if inside == 0 || inside == corners.len() {
+                    continue;
+                }
+                for tetrahedron in TETRAHEDRA {
+                    marcher.tetrahedron(tetrahedron.map(|corner| corners[corner]));
+                }
+            }
+        }
+    }
+    (marcher.vertices, marcher.indices)
+}
+
+struct Marcher<'a> {
+    chunk: &'a PaddedChunk,
+    palette: &'a VoxelPalette,
+    vertices: Vec<VertexPositionColorNormal>,
+    positions: Vec<Vector3<f32>>,
+    indices: Vec<u32>,
+    /// The vertex on each edge the surface crosses, by its inside and outside end.
+    edges: HashMap<([i32; 3], [i32; 3]), u32>,
+}
+
+impl Marcher<'_> {
+    fn density(&self, [x, y, z]: [i32; 3]) -> f32 {
+        self.chunk.density(x, y, z)
+    }
+
+    fn tetrahedron(&mut self, corners: [[i32; 3]; 4]) {
+        let (inside, outside): (Vec<_>, Vec<_>) = corners
+            .into_iter()
+            .partition(|&corner| self.density(corner) > 0.0);
+        match (inside.as_slice(), outside.as_slice()) {
+            (&[a], &[b, c, d]) | (&[b, c, d], &[a]) => {
+                self.triangle([(a, b), (a, c), (a, d)]);
+            }
+            (&[a, b], &[c, d]) => {
+                self.triangle([(a, c), (a, d), (b, d)]);
+                self.triangle([(b, d), (b, c), (a, c)]);
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds a triangle between the vertices on three edges, wound to face out of the solid.
+    fn triangle(&mut self, edges: [([i32; 3], [i32; 3]); 3]) {
+        let mut triangle = edges.map(|(a, b)| self.edge_vertex(a, b));
+        let [p0, p1, p2] = triangle.map(|index| self.positions[index as usize]);
+        let normal = Vector3::from(self.vertices[triangle[0] as usize].normal());
+        if (p1 - p0).cross(p2 - p0).dot(normal) < 0.0 {
+            triangle.swap(1, 2);
+        }
+        self.indices.extend(triangle);
+    }
+
+    /// The vertex where the surface crosses the edge between `a` and `b`, one inside the solid and
+    /// one outside.
+    fn edge_vertex(&mut self, a: [i32; 3], b: [i32; 3]) -> u32 {
+        let (inside, outside) = if self.density(a) > 0.0 {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        if let Some(&index) = self.edges.get(&(inside, outside)) {
+            return index;
+        }
+
+        let (inside_density, outside_density) = (self.density(inside), self.density(outside));
+        let t = inside_density / (inside_density - outside_density);
+        let from = Vector3::from(inside.map(|c| c as f32));
+        let to = Vector3::from(outside.map(|c| c as f32));
+        let position = from.lerp(to, t);
+        // Density rises into the solid, so the surface faces down its gradient
+        let gradient = self.gradient(inside).lerp(self.gradient(outside), t);
+        let normal = if gradient.magnitude2() > f32::EPSILON {
+            -gradient.normalize()
+        } else {
+            (to - from).normalize()
+        };
+        let color = self
+            .palette
+            .color(self.chunk.get(inside[0], inside[1], inside[2]));
+
+        let index = self.vertices.len() as u32;
+        self.vertices.push(VertexPositionColorNormal::new(
+            position.into(),
+            color,
+            normal.into(),
+        ));
+        self.positions.push(position);
+        self.edges.insert((inside, outside), index);
+        index
+    }
+
+    fn gradient(&self, [x, y, z]: [i32; 3]) -> Vector3<f32> {
+        let density = |x, y, z| self.chunk.density(x, y, z);
+        Vector3::new(
+            density(x + 1, y, z) - density(x - 1, y, z),
+            density(x, y + 1, z) - density(x, y - 1, z),
+            density(x, y, z + 1) - density(x, y, z - 1),
+        ) * 0.5
+    }
+}
@@ -0,0 +1,160 @@
+use crate::renderer::VertexPositionColorNormal;
+
+use super::chunk::{Voxel, VoxelPalette, CHUNK_SIZE};
+
+/// From -1 up to `CHUNK_SIZE + 1`. Marching cubes reaches one further than the blocky mesher, for
+/// the density gradient at the far side of the chunk.
+const PADDED_SIZE: i32 = CHUNK_SIZE + 3;
+
+/// A copy of a chunk's voxels and densities with a border from its neighbours, so the faces and
+/// surface it shares with them can be meshed without the rest of the world. Meshing jobs own one
+/// of these.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaddedChunk {
+    voxels: Vec<Voxel>,
+    densities: Vec<f32>,
+}
+
+impl Default for PaddedChunk {
+    fn default() -> Self {
+        let volume = (PADDED_SIZE * PADDED_SIZE * PADDED_SIZE) as usize;
+        PaddedChunk {
+            voxels: vec![Voxel::AIR; volume],
+            densities: vec![-1.0; volume],
+        }
+    }
+}
+
+impl PaddedChunk {
+    /// Each of `x`, `y` and `z` from -1 up to `CHUNK_SIZE + 1`, air outside that.
+    pub fn get(&self, x: i32, y: i32, z: i32) -> Voxel {
+        padded_index(x, y, z)
+            .map(|index| self.voxels[index])
+            .unwrap_or(Voxel::AIR)
+    }
+
+    /// -1 outside the padded chunk.
+    pub fn density(&self, x: i32, y: i32, z: i32) -> f32 {
+        padded_index(x, y, z)
+            .map(|index| self.densities[index])
+            .unwrap_or(-1.0)
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, z: i32, voxel: Voxel, density: f32) {
+        if let Some(index) = padded_index(x, y, z) {
+            self.voxels[index] = voxel;
+            self.densities[index] = density;
+        }
+    }
+}
+
+fn padded_index(x: i32, y: i32, z: i32) -> Option<usize> {
+    let range = -1..=CHUNK_SIZE + 1;
+    if !range.contains(&x) || !range.contains(&y) || !range.contains(&z) {
+        return None;
+    }
+    Some((((z + 1) * PADDED_SIZE + y + 1) * PADDED_SIZE + x + 1) as usize)
+}
+
+/// Meshes the inner `CHUNK_SIZE` cube of `chunk` in the geometry pass's vertex format, one unit
+/// per voxel with the chunk's minimum corner at the origin. Only faces between solid voxels and
+/// air are kept, and neighbouring faces of the same voxel are merged into as few quads as a
+/// greedy sweep finds, so flat ground is a handful of triangles however big it is.
+pub fn greedy_mesh(
+    chunk: &PaddedChunk,
+    palette: &VoxelPalette,
+) -> (Vec<VertexPositionColorNormal>, Vec<u32>) {
+    let size = CHUNK_SIZE as usize;
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut mask = vec![Voxel::AIR; size * size];
+
+    for axis in 0..3 {
+        // The slice's own axes, ordered so that u × v points along +axis
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for facing in [-1, 1] {
+            let mut normal = [0.0; 3];
+            normal[axis] = facing as f32;
+
+            for slice in 0..CHUNK_SIZE {
+                for j in 0..CHUNK_SIZE {
+                    for i in 0..CHUNK_SIZE {
+                        let mut position = [0; 3];
+                        position[axis] = slice;
+                        position[u] = i;
+                        position[v] = j;
+                        let voxel = chunk.get(position[0], position[1], position[2]);
+                        position[axis] += facing;
+                        let neighbour = chunk.get(position[0], position[1], position[2]);
+                        mask[j as usize * size + i as usize] =
+                            if voxel.is_solid() && !neighbour.is_solid() {
+                                voxel
+                            } else {
+                                Voxel::AIR
+                            };
+                    }
+                }
+
+                for j in 0..size {
+                    let mut i = 0;
+                    while i < size {
+                        let voxel = mask[j * size + i];
+                        if !voxel.is_solid() {
+                            i += 1;
+                            continue;
+                        }
+                        let mut width = 1;
+                        while i + width < size && mask[j * size + i + width] == voxel {
+                            width += 1;
+                        }
+                        let mut height = 1;
+                        'grow: while j + height < size {
+                            for k in 0..width {
+                                if mask[(j + height) * size + i + k] != voxel {
+                                    break 'grow;
+                                }
+                            }
+                            height += 1;
+                        }
+                        for row in j..j + height {
+                            mask[row * size + i..row * size + i + width].fill(Voxel::AIR);
+                        }
+
+                        let mut corner = [0.0; 3];
+                        corner[axis] = (slice + facing.max(0)) as f32;
+                        corner[u] = i as f32;
+                        corner[v] = j as f32;
+                        let mut along_u = [0.0; 3];
+                        along_u[u] = width as f32;
+                        let mut along_v = [0.0; 3];
+                        along_v[v] = height as f32;
+                        let add =
+                            |a: [f32; 3], b: [f32; 3]| [a[0] + b[0], a[1] + b[1], a[2] + b[2]];
+                        let mut corners = [
+                            corner,
+                            add(corner, along_u),
+                            add(add(corner, along_u), along_v),
+                            add(corner, along_v),
+                        ];
+                        // Counter-clockwise seen from the side the face looks out of
+                        if facing < 0 {
+                            corners.reverse();
+                        }
+
+                        let color = palette.color(voxel);
+                        let first = vertices.len() as u32;
+                        vertices.extend(
+                            corners.map(|corner| {
+                                VertexPositionColorNormal::new(corner, color, normal)
+                            }),
+                        );
+                        indices.extend([0, 1, 2, 2, 3, 0].map(|index| first + index));
+                        i += width;
+                    }
+                }
+            }
+        }
+    }
+
+    (vertices, indices)
+}
@@ -0,0 +1,9 @@
+pub use chunk::{Chunk, Voxel, VoxelPalette, CHUNK_SIZE};
+pub use marching::marching_cubes;
+pub use mesher::{greedy_mesh, PaddedChunk};
+pub use system::{ChunkMesh, VoxelChunk, VoxelMeshQueue, VoxelMesher, VoxelSystem, VoxelWorld};
+
+mod chunk;
+mod marching;
+mod mesher;
+mod system;
@@ -0,0 +1,345 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use cgmath::{InnerSpace, Vector3};
+use specs::{
+    Component, Entities, Entity, Read, ReadExpect, System, VecStorage, Write, WriteStorage,
+};
+
+use crate::{
+    game::{
+        components::{transform::Transform, Bounds, EntityKey},
+        jobs::JobSystem,
+    },
+    renderer::VertexPositionColorNormal,
+};
+
+use super::{
+    chunk::{Chunk, Voxel, VoxelPalette, CHUNK_SIZE},
+    marching::marching_cubes,
+    mesher::{greedy_mesh, PaddedChunk},
+};
+
+/// How `VoxelSystem` turns chunks into meshes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoxelMesher {
+    /// Blocks, see `greedy_mesh`.
+    #[default]
+    Greedy,
+    /// A smooth surface through the densities, see `marching_cubes`.
+    MarchingCubes,
+}
+
+impl VoxelMesher {
+    fn mesh(
+        &self,
+        chunk: &PaddedChunk,
+        palette: &VoxelPalette,
+    ) -> (Vec<VertexPositionColorNormal>, Vec<u32>) {
+        match self {
+            VoxelMesher::Greedy => greedy_mesh(chunk, palette),
+            VoxelMesher::MarchingCubes => marching_cubes(chunk, palette),
+        }
+    }
+}
+
+/// A voxel grid split into chunks, as a resource of the simulation world. Systems and edits change
+/// it with `set` and `fill`, and `VoxelSystem` remeshes the chunks they touched in the background.
+/// Voxels are a meter wide, with the grid's origin at the world's.
+#[derive(Debug, Default)]
+pub struct VoxelWorld {
+    chunks: BTreeMap<[i32; 3], Chunk>,
+    /// Ordered, so chunk entities are created in the same order on every run, as replays need.
+    dirty: BTreeSet<[i32; 3]>,
+    palette: Arc<VoxelPalette>,
+    mesher: VoxelMesher,
+}
+
+impl VoxelWorld {
+    /// The chunk `position` is in, and where in that chunk.
+    pub fn chunk_coord(position: [i32; 3]) -> ([i32; 3], [i32; 3]) {
+        (
+            position.map(|p| p.div_euclid(CHUNK_SIZE)),
+            position.map(|p| p.rem_euclid(CHUNK_SIZE)),
+        )
+    }
+
+    pub fn get(&self, position: [i32; 3]) -> Voxel {
+        let (coord, [x, y, z]) = VoxelWorld::chunk_coord(position);
+        self.chunks
+            .get(&coord)
+            .and_then(|chunk| chunk.get(x, y, z))
+            .unwrap_or(Voxel::AIR)
+    }
+
+    /// -1 where there's no chunk.
+    pub fn density(&self, position: [i32; 3]) -> f32 {
+        let (coord, [x, y, z]) = VoxelWorld::chunk_coord(position);
+        self.chunks
+            .get(&coord)
+            .and_then(|chunk| chunk.density(x, y, z))
+            .unwrap_or(-1.0)
+    }
+
+    /// Remeshes the chunk on the next fixed update, along with the neighbours that mesh the
+    /// changed voxel too.
+    pub fn set(&mut self, position: [i32; 3], voxel: Voxel) {
+        let (coord, [x, y, z]) = VoxelWorld::chunk_coord(position);
+        if !voxel.is_solid() && !self.chunks.contains_key(&coord) {
+            return;
+        }
+        let chunk = self.chunks.entry(coord).or_default();
+        let old = (chunk.get(x, y, z), chunk.density(x, y, z));
+        chunk.set(x, y, z, voxel);
+        if old != (chunk.get(x, y, z), chunk.density(x, y, z)) {
+            self.mark_dirty(coord, [x, y, z]);
+        }
+    }
+
+    /// Sets the density at `position` for smooth meshing, see `Chunk::set_density`.
+    pub fn set_density(&mut self, position: [i32; 3], density: f32, material: Voxel) {
+        let (coord, [x, y, z]) = VoxelWorld::chunk_coord(position);
+        if density <= -1.0 && !self.chunks.contains_key(&coord) {
+            return;
+        }
+        let chunk = self.chunks.entry(coord).or_default();
+        let old = (chunk.get(x, y, z), chunk.density(x, y, z));
+        chunk.set_density(x, y, z, density, material);
+        if old != (chunk.get(x, y, z), chunk.density(x, y, z)) {
+            self.mark_dirty(coord, [x, y, z]);
+        }
+    }
+
+    /// Sets the densities from `min` to `max`, both included, from a field such as a noise
+    /// function, making the solid ones `material`.
+    pub fn fill_density(
+        &mut self,
+        min: [i32; 3],
+        max: [i32; 3],
+        material: Voxel,
+        field: impl Fn([i32; 3]) -> f32,
+    ) {
+        for z in min[2]..=max[2] {
+            for y in min[1]..=max[1] {
+                for x in min[0]..=max[0] {
+                    self.set_density([x, y, z], field([x, y, z]), material);
+                }
+            }
+        }
+    }
+
+    /// Adds `amount` to the densities within `radius` of `center`, fading to nothing at the edge.
+    /// A positive amount builds up `material`, a negative one digs. Voxels that were already
+    /// solid keep their material.
+    pub fn sculpt(&mut self, center: Vector3<f32>, radius: f32, amount: f32, material: Voxel) {
+        let min = [center.x, center.y, center.z].map(|c| (c - radius).floor() as i32);
+        let max = [center.x, center.y, center.z].map(|c| (c + radius).ceil() as i32);
+        for z in min[2]..=max[2] {
+            for y in min[1]..=max[1] {
+                for x in min[0]..=max[0] {
+                    let distance =
+                        (Vector3::new(x as f32, y as f32, z as f32) - center).magnitude();
+                    let falloff = 1.0 - distance / radius;
+                    if falloff <= 0.0 {
+                        continue;
+                    }
+                    let existing = self.get([x, y, z]);
+                    let material = if existing.is_solid() {
+                        existing
+                    } else {
+                        material
+                    };
+                    let density = self.density([x, y, z]) + amount * falloff;
+                    self.set_density([x, y, z], density, material);
+                }
+            }
+        }
+    }
+
+    /// Sets every voxel from `min` to `max`, both included.
+    pub fn fill(&mut self, min: [i32; 3], max: [i32; 3], voxel: Voxel) {
+        for z in min[2]..=max[2] {
+            for y in min[1]..=max[1] {
+                for x in min[0]..=max[0] {
+                    self.set([x, y, z], voxel);
+                }
+            }
+        }
+    }
+
+    pub fn chunk(&self, coord: [i32; 3]) -> Option<&Chunk> {
+        self.chunks.get(&coord)
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn palette(&self) -> &VoxelPalette {
+        &self.palette
+    }
+
+    /// Remeshes every chunk in the new colors.
+    pub fn set_palette(&mut self, palette: VoxelPalette) {
+        self.palette = Arc::new(palette);
+        self.dirty.extend(self.chunks.keys().copied());
+    }
+
+    pub fn mesher(&self) -> VoxelMesher {
+        self.mesher
+    }
+
+    /// Remeshes every chunk with `mesher`.
+    pub fn set_mesher(&mut self, mesher: VoxelMesher) {
+        self.mesher = mesher;
+        self.dirty.extend(self.chunks.keys().copied());
+    }
+
+    /// Marks the chunk at `coord` and the neighbours whose border holds `local`. Meshing reads
+    /// one voxel below a chunk and two above, for marching cubes' gradients.
+    fn mark_dirty(&mut self, coord: [i32; 3], local: [i32; 3]) {
+        let steps = local.map(|l| match l {
+            0 | 1 => [-1, 0],
+            l if l == CHUNK_SIZE - 1 => [0, 1],
+            _ => [0, 0],
+        });
+        for dz in steps[2] {
+            for dy in steps[1] {
+                for dx in steps[0] {
+                    let neighbour = [coord[0] + dx, coord[1] + dy, coord[2] + dz];
+                    if neighbour == coord || self.chunks.contains_key(&neighbour) {
+                        self.dirty.insert(neighbour);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A copy of the chunk at `coord` and the voxels around it, to mesh off the simulation thread.
+    fn padded(&self, coord: [i32; 3]) -> PaddedChunk {
+        let mut padded = PaddedChunk::default();
+        let origin = coord.map(|c| c * CHUNK_SIZE);
+        for z in -1..=CHUNK_SIZE + 1 {
+            for y in -1..=CHUNK_SIZE + 1 {
+                for x in -1..=CHUNK_SIZE + 1 {
+                    let position = [origin[0] + x, origin[1] + y, origin[2] + z];
+                    let density = self.density(position);
+                    if density > -1.0 {
+                        padded.set(x, y, z, self.get(position), density);
+                    }
+                }
+            }
+        }
+        padded
+    }
+}
+
+/// The entity standing in for a chunk in the simulation world, at the chunk's minimum corner with
+/// `Bounds` around it, so the spatial index and the renderer's culling see it like any other.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+#[storage(VecStorage)]
+pub struct VoxelChunk {
+    pub coord: [i32; 3],
+}
+
+/// A remeshed chunk on its way to the render thread.
+pub struct ChunkMesh {
+    pub coord: [i32; 3],
+    /// The chunk's `VoxelChunk` entity, which the renderer reports the visibility of.
+    pub entity: EntityKey,
+    /// Counts up with each remesh of the chunk, since meshing jobs can finish out of order.
+    pub version: u64,
+    pub vertices: Vec<VertexPositionColorNormal>,
+    pub indices: Vec<u32>,
+}
+
+/// Hands finished chunk meshes from the meshing jobs to the render thread. The same queue is a
+/// resource of both worlds.
+#[derive(Clone, Default)]
+pub struct VoxelMeshQueue(Arc<Mutex<Vec<ChunkMesh>>>);
+
+impl VoxelMeshQueue {
+    pub fn push(&self, mesh: ChunkMesh) {
+        if let Ok(mut meshes) = self.0.lock() {
+            meshes.push(mesh);
+        }
+    }
+
+    pub fn take(&self) -> Vec<ChunkMesh> {
+        self.0
+            .lock()
+            .map(|mut meshes| std::mem::take(&mut *meshes))
+            .unwrap_or_default()
+    }
+}
+
+/// Creates an entity for every new chunk of the `VoxelWorld` and remeshes the changed ones on the
+/// job pool, queueing the meshes for the render thread.
+#[derive(Default)]
+pub struct VoxelSystem {
+    chunks: HashMap<[i32; 3], (Entity, u64)>,
+}
+
+impl<'a> System<'a> for VoxelSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, VoxelWorld>,
+        ReadExpect<'a, JobSystem>,
+        Read<'a, VoxelMeshQueue>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Bounds>,
+        WriteStorage<'a, VoxelChunk>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, mut voxels, jobs, queue, mut transforms, mut bounds, mut chunks): Self::SystemData,
+    ) {
+        profile_scope!("voxels");
+        let dirty = std::mem::take(&mut voxels.dirty);
+        for coord in dirty {
+            let (entity, version) = self.chunks.entry(coord).or_insert_with(|| {
+                let origin = coord.map(|c| (c * CHUNK_SIZE) as f32);
+                let entity = entities
+                    .build_entity()
+                    .with(
+                        Transform {
+                            position: origin.into(),
+                            rotation: [1.0, 0.0, 0.0, 0.0].into(),
+                            scale: [1.0, 1.0, 1.0].into(),
+                        },
+                        &mut transforms,
+                    )
+                    .with(
+                        Bounds::new(
+                            Vector3::new(0.0, 0.0, 0.0),
+                            Vector3::new(CHUNK_SIZE as f32, CHUNK_SIZE as f32, CHUNK_SIZE as f32),
+                        ),
+                        &mut bounds,
+                    )
+                    .with(VoxelChunk { coord }, &mut chunks)
+                    .build();
+                (entity, 0)
+            });
+            *version += 1;
+
+            let padded = voxels.padded(coord);
+            let palette = voxels.palette.clone();
+            let mesher = voxels.mesher;
+            let queue = queue.clone();
+            let (entity, version) = (EntityKey::from(*entity), *version);
+            jobs.spawn("voxel meshing", move || {
+                let (vertices, indices) = mesher.mesh(&padded, &palette);
+                queue.push(ChunkMesh {
+                    coord,
+                    entity,
+                    version,
+                    vertices,
+                    indices,
+                });
+            });
+        }
+    }
+}
@@ -1,9 +1,97 @@
+#[cfg(feature = "lightmap-baker")]
+pub use assets::{bake_lightmaps, Lightmap, LightmapMesh, LightmapSettings, LightmapSun};
+#[cfg(feature = "obj")]
+pub use assets::{load_obj, ObjMaterial, ObjMesh, ObjModel};
+#[cfg(feature = "tiled")]
+pub use assets::{load_tilemap, Tilemap, TilemapData, TILEMAP_CHUNK_SIZE};
+pub use assets::{AssetServer, AssetWatcher, MaterialFile};
+pub use assets::{CompressionQuality, TextureCache, TextureUsage};
+pub use config::EngineConfig;
+pub use game::CommandContext;
+pub use game::ComponentCodecs;
+pub use game::CursorMode;
 pub use game::GameLoop;
+pub use game::JobSystem;
+pub use game::ParticleEmitter;
+pub use game::StressScene;
+pub use game::VisibilityResource;
+pub use game::{find_path, NavMesh, NavMeshResource, NavMeshSettings, NavPolygon};
+pub use game::{greedy_mesh, Chunk, PaddedChunk, Voxel, VoxelPalette, CHUNK_SIZE};
+pub use game::{marching_cubes, ChunkMesh, VoxelChunk, VoxelMesher, VoxelWorld};
+pub use game::{AnchorVisibility, MarkerId, ScreenAnchor, WorldMarker};
+pub use game::{BehaviorNode, BehaviorStatus, BehaviorTree, Steering, SteeringBehavior};
+pub use game::{Bounds, EntityKey, RayHit, RayQuery, SpatialIndex};
+pub use game::{BudgetExceeded, BudgetMetric, PerformanceBudget};
+pub use game::{Camera, Frustum, Plane, Ray, ViewModel};
+pub use game::{CameraEffect, CameraEffects, CameraOffset, CameraShake};
+pub use game::{CameraKey, Cue, Keyframe, Timeline, Track};
+pub use game::{DensityMap, FoliageScatter};
+pub use game::{Divergence, Replay, ReplayStatus};
+pub use game::{Easing, Tween, TweenLoop, TweenTarget};
+pub use game::{FocusBehavior, FocusEvent};
+pub use game::{GamepadEvent, GamepadInfo, PlayerIndex};
+pub use game::{GamepadSource, MouseAxis, MouseSource, Source, SystemMouseButton};
+#[cfg(feature = "net")]
+pub use game::{NetClient, NetServer, NetSession, RemoteInputs, Replicated};
+pub use game::{PathRequest, PathResult};
+pub use game::{Rumble, RumbleEnvelope, RumbleQueue};
+pub use game::{SaveData, SaveRegistry, Saved};
+#[cfg(feature = "lua")]
+pub use game::{ScriptComponents, ScriptRunner};
+pub use game::{Sky, SkyKeyframe, TimeOfDay, HOURS_PER_DAY};
+pub use game::{Spin, Transform};
+pub use game::{TimelineCommand, TimelineEvent, TimelineStatus, Timelines};
+pub use logging::{init_logging, LoggingOptions};
+pub use renderer::Billboard;
+pub use renderer::ColorWorkflow;
+pub use renderer::EnvironmentMaps;
 pub use renderer::FrameSystem;
 pub use renderer::GeometrySystem;
+pub use renderer::Indices;
 pub use renderer::LightingPass;
+pub use renderer::MotionBlurSettings;
+pub use renderer::OutlineStyle;
 pub use renderer::Pass;
+pub use renderer::PickResult;
 pub use renderer::Renderer;
+pub use renderer::RendererConfig;
+pub use renderer::ShadowSettings;
+pub use renderer::SpriteMeshId;
+pub use renderer::StaticBatch;
+pub use renderer::TextureHandle;
+pub use renderer::UpscaleFilter;
+pub use renderer::{
+    color_temperature, exposure_from_ev100, Attenuation, DirectionalLight, PointLight, SkyGradient,
+};
+pub use renderer::{AdapterInfo, DeviceSelector};
+pub use renderer::{AnalysisReport, HISTOGRAM_BINS};
+pub use renderer::{BlendMode, Material, MaterialId, MaterialShader, Tint};
+pub use renderer::{ConstantValue, ShaderVariants, VariantKey};
+pub use renderer::{FoliageId, FoliageInstance, FoliageLayer, Wind};
+pub use renderer::{FrameStats, PassTimes, SceneStats};
+pub use renderer::{GlobalIllumination, SsgiSettings};
+pub use renderer::{HudPanel, UiPrimitive};
+pub use renderer::{MemoryCategory, MemoryStats};
+pub use renderer::{MeshUploadHandle, UploadStatus};
+pub use renderer::{
+    MeshVertex, VertexLayout, VertexPosition, VertexPositionColorNormal, VertexPositionNormalUv,
+    VertexPositionNormalUvLightmap, VertexPositionNormalUvTangent, VertexSkinned,
+};
+pub use renderer::{OverlayCamera, RenderLayers, MAX_OVERLAY_CAMERAS, VIEW_MODEL_LAYER};
+pub use renderer::{PassContext, PassPoint, UserPass, UserPassId};
+pub use renderer::{Portability, VulkanContext};
+pub use renderer::{Portal, MAX_PORTAL_VIEWS};
+pub use renderer::{ReadbackData, ReadbackFuture};
+pub use renderer::{ReflectionProbe, MAX_REFLECTION_PROBES};
+pub use renderer::{SsrQuality, SsrSettings};
+pub use renderer::{CUBE_INDICES, CUBE_VERTICES};
 
+#[macro_use]
+pub mod profiling;
+
+mod assets;
+mod config;
 mod game;
+mod logging;
+mod random;
 mod renderer;
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+/// How `init_logging` sets up logging.
+#[derive(Debug, Clone)]
+pub struct LoggingOptions {
+    /// Messages below this level are dropped, unless `RUST_LOG` says otherwise.
+    pub level: LevelFilter,
+    /// A log4rs config used in place of the built-in console logger when the file exists, e.g.
+    /// to log to files.
+    pub config_file: Option<PathBuf>,
+    /// Sends spans to the Tracy profiler, with the `tracing` feature.
+    pub tracy: bool,
+}
+
+impl Default for LoggingOptions {
+    fn default() -> Self {
+        LoggingOptions {
+            level: LevelFilter::INFO,
+            config_file: Some(PathBuf::from("log4rs.yml")),
+            tracy: cfg!(feature = "tracing"),
+        }
+    }
+}
+
+/// Sets up logging for both `log` and `tracing`: to the console, filtered by `RUST_LOG` when
+/// it's set, or as `options.config_file` describes when that file exists. Must be called once,
+/// before anything is logged.
+pub fn init_logging(options: LoggingOptions) -> anyhow::Result<()> {
+    #[cfg(feature = "tracing")]
+    let tracy = options.tracy.then(tracing_tracy::TracyLayer::new);
+    #[cfg(not(feature = "tracing"))]
+    let tracy = options.tracy.then(tracing_subscriber::layer::Identity::new);
+
+    if let Some(path) = options.config_file.filter(|path| path.exists()) {
+        log4rs::init_file(&path, Default::default())
+            .with_context(|| format!("configuring logging from {:?}", path))?;
+        // log4rs owns `log` records, so only spans go to the subscriber
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(tracy))
+            .context("setting tracing subscriber")?;
+        log::info!("logging configured from {:?}", path);
+        return Ok(());
+    }
+
+    let filter = EnvFilter::builder()
+        .with_default_directive(options.level.into())
+        .from_env_lossy();
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_filter(filter))
+        .with(tracy)
+        .try_init()
+        .map_err(|e| anyhow!("setting tracing subscriber: {}", e))?;
+
+    Ok(())
+}
@@ -1,7 +1,14 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
 use anyhow::Context;
-use triton::GameLoop;
+use clap::Parser;
+use serde::Serialize;
+use triton::{init_logging, EngineConfig, FrameStats, GameLoop, LoggingOptions};
 use winit::{
-    event::{ElementState, Event, MouseButton, WindowEvent},
+    event::{ElementState, Event, Ime, KeyEvent, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     keyboard::{KeyCode, PhysicalKey},
 };
@@ -9,7 +16,6 @@ use winit::{
 /*
     TODO:
 
-    - Add support for multiple frames in flight
     - Add additional (temporary) render passes
         - egui
         - light indicators
@@ -17,13 +23,70 @@ use winit::{
         - renderer module is getting large
 */
 
-#[cfg(feature = "tracing")]
-use tracing::{span, Level};
-#[cfg(feature = "tracing")]
-use tracing_subscriber::layer::SubscriberExt;
+/// Runs the engine's demo scene. Options given here override the config file and its
+/// environment variables.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Engine config to read at startup, every value of which can be overridden with a TRITON_
+    /// environment variable
+    #[arg(long, value_name = "PATH", default_value = "triton.toml")]
+    config: PathBuf,
+    /// Save file to load once the simulation has started
+    #[arg(long, value_name = "PATH")]
+    scene: Option<PathBuf>,
+    /// Hides the window and keeps running in the background. Frames are still rendered and
+    /// presented to it, so this needs a GPU and a display
+    #[arg(long, visible_alias = "headless")]
+    hidden_window: bool,
+    /// Exits after rendering this many frames, to the hidden window too with --hidden-window
+    #[arg(long, value_name = "N")]
+    frames: Option<u64>,
+    #[arg(long)]
+    width: Option<u32>,
+    #[arg(long)]
+    height: Option<u32>,
+    /// Part of the name of the GPU to run on
+    #[arg(long, value_name = "NAME")]
+    gpu: Option<String>,
+    /// Turns on the Vulkan validation layer
+    #[arg(long)]
+    validation: bool,
+    /// Spawns a stress test grid this many cubes wide
+    #[arg(long, value_name = "SIZE")]
+    stress_scene: Option<u32>,
+    /// Captures the last frame with RenderDoc, with the renderdoc feature
+    #[arg(long, requires = "frames")]
+    capture: bool,
+    /// Writes frame time statistics to this file on exit, as TOML
+    #[arg(long, value_name = "PATH")]
+    stats: Option<PathBuf>,
+}
+
+impl Args {
+    fn apply(&self, config: &mut EngineConfig) {
+        if let Some(width) = self.width {
+            config.window_width = width;
+        }
+        if let Some(height) = self.height {
+            config.window_height = height;
+        }
+        if let Some(gpu) = &self.gpu {
+            config.gpu = Some(gpu.clone());
+        }
+        if self.validation {
+            config.validation = Some(true);
+        }
+        if let Some(size) = self.stress_scene {
+            config.stress_scene = Some(size);
+        }
+        config.hidden_window |= self.hidden_window;
+    }
+}
 
 pub fn main() -> anyhow::Result<()> {
-    log4rs::init_file("log4rs.yml", Default::default()).context("Could not configure logger")?;
+    let args = Args::parse();
+    init_logging(LoggingOptions::default()).context("configuring logging")?;
 
     #[cfg(feature = "tracing")]
     log::info!("Tracing enabled");
@@ -33,27 +96,26 @@ pub fn main() -> anyhow::Result<()> {
     static GLOBAL: tracy_client::ProfiledAllocator<std::alloc::System> =
         tracy_client::ProfiledAllocator::new(std::alloc::System, 100);
 
-    #[cfg(feature = "tracing")]
-    tracing::subscriber::set_global_default(
-        tracing_subscriber::registry().with(tracing_tracy::TracyLayer::new()),
-    )
-    .expect("setting up tracing");
-
-    #[cfg(feature = "tracing")]
-    let _root = span!(Level::INFO, "root").entered();
+    triton::profile_scope!("root");
 
     let event_loop = EventLoop::new()?;
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut game_loop = GameLoop::new(&event_loop).context("creating game loop")?;
+    let mut config = EngineConfig::load(&args.config).context("loading engine config")?;
+    args.apply(&mut config);
+    let mut game_loop = GameLoop::new(&event_loop, config).context("creating game loop")?;
 
     log::info!("Constructed Game Loop");
 
-    let mut mouse_captured = false;
+    if let Some(scene) = &args.scene {
+        game_loop.load_game(scene);
+    }
+    let mut frames = 0;
+    let mut frame_times = FrameTimes::default();
 
     event_loop
         .run(move |event, elwt| {
-            game_loop.process_winit_event(&event, mouse_captured);
+            game_loop.process_winit_event(&event);
 
             match event {
                 Event::WindowEvent { event, window_id }
@@ -75,51 +137,158 @@ pub fn main() -> anyhow::Result<()> {
                                 log::warn!("{}", e);
                             }
                         }
-                        WindowEvent::CloseRequested => {
-                            elwt.exit();
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(KeyCode::F3),
+                                    state: ElementState::Pressed,
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            game_loop.toggle_hud();
+                        }
+                        // RenderDoc's own capture key is F12, so this stays clear of it
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(KeyCode::F10),
+                                    state: ElementState::Pressed,
+                                    repeat: false,
+                                    ..
+                                },
+                            ..
+                        } => {
+                            game_loop.capture_frame();
                         }
-                        WindowEvent::MouseInput { state, button, .. } => match (state, button) {
-                            (ElementState::Released, MouseButton::Left) => {
-                                log::info!("Capturing Mouse");
-                                game_loop.set_cursor_captured();
-                                mouse_captured = true;
-                            }
-                            (ElementState::Released, MouseButton::Right) => {
-                                game_loop.set_cursor_released();
-                                mouse_captured = false;
-                            }
-                            _ => (),
-                        },
                         WindowEvent::KeyboardInput {
-                            device_id: _,
-                            event,
-                            is_synthetic: _,
+                            event:
+                                KeyEvent {
+                                    physical_key: PhysicalKey::Code(key),
+                                    state: ElementState::Pressed,
+                                    repeat,
+                                    text,
+                                    ..
+                                },
+                            ..
                         } => {
-                            if event.physical_key == PhysicalKey::Code(KeyCode::Escape) {
-                                game_loop.set_cursor_released();
-                                mouse_captured = false;
+                            if !game_loop.console_key(key, text.as_deref(), repeat) {
+                                game_loop.editor_key(key, repeat);
                             }
                         }
+                        WindowEvent::Ime(Ime::Commit(text)) => {
+                            game_loop.console_text(&text);
+                        }
+                        WindowEvent::CloseRequested => {
+                            elwt.exit();
+                        }
                         _ => (),
                     }
                 }
 
                 Event::AboutToWait => {
-                    #[cfg(feature = "tracing")]
-                    let _span = span!(Level::INFO, "Event::AboutToWait").entered();
+                    triton::profile_scope!("Event::AboutToWait");
                     game_loop.window_size().map(|image_extent| {
                         let image_extent_arr: [u32; 2] = image_extent.into();
                         if image_extent_arr.contains(&0) {
                             return;
                         }
+                        if args.capture && args.frames == Some(frames + 1) {
+                            game_loop.capture_frame();
+                        }
+                        // Frames that failed to render don't count towards --frames
                         if let Err(e) = game_loop.update().context("rendering") {
                             log::error!("{}", e);
+                            return;
+                        }
+                        frames += 1;
+                        frame_times.record(&game_loop.frame_stats());
+                        if args.frames.is_some_and(|limit| frames >= limit) {
+                            log::info!("exiting after {} frames", frames);
+                            elwt.exit();
                         }
                     });
                 }
 
+                Event::LoopExiting => {
+                    if let Some(path) = &args.stats {
+                        if let Err(e) = frame_times.write(path) {
+                            log::error!("{:#}", e);
+                        }
+                    }
+                }
+
                 _ => (),
             }
         })
         .context("event loop")
 }
+
+/// Frame times of the whole run, for `--stats`.
+#[derive(Default)]
+struct FrameTimes {
+    cpu_ms: Vec<f32>,
+    gpu_ms: Vec<f32>,
+    last: FrameStats,
+}
+
+impl FrameTimes {
+    fn record(&mut self, stats: &FrameStats) {
+        self.cpu_ms.push(stats.cpu_time_ms);
+        self.gpu_ms.extend(stats.gpu_time_ms);
+        self.last = *stats;
+    }
+
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let report = StatsReport {
+            frames: self.cpu_ms.len(),
+            cpu_ms: TimeSummary::new(&self.cpu_ms),
+            gpu_ms: TimeSummary::new(&self.gpu_ms),
+            draw_calls: self.last.draw_calls,
+            triangles: self.last.triangles,
+        };
+        let text = toml::to_string_pretty(&report).context("encoding frame stats")?;
+        fs::write(path, text).with_context(|| format!("writing frame stats to {:?}", path))?;
+        log::info!("wrote frame stats to {:?}", path);
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct StatsReport {
+    frames: usize,
+    cpu_ms: Option<TimeSummary>,
+    /// Missing when the GPU couldn't be timed.
+    gpu_ms: Option<TimeSummary>,
+    /// Of the last frame.
+    draw_calls: u32,
+    triangles: u64,
+}
+
+#[derive(Serialize)]
+struct TimeSummary {
+    mean: f32,
+    min: f32,
+    median: f32,
+    p99: f32,
+    max: f32,
+}
+
+impl TimeSummary {
+    fn new(times: &[f32]) -> Option<Self> {
+        if times.is_empty() {
+            return None;
+        }
+        let mut sorted = times.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let percentile = |p: f32| sorted[((sorted.len() - 1) as f32 * p).round() as usize];
+        Some(TimeSummary {
+            mean: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            min: sorted[0],
+            median: percentile(0.5),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
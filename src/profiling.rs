@@ -0,0 +1,64 @@
+//! Profiling hooks for Tracy. Everything here compiles to nothing without the `tracing` feature,
+//! so scopes can be left in hot code.
+
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+pub use tracing as __tracing;
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+pub use tracy_client as __tracy_client;
+
+/// Times the rest of the enclosing block as a span named `$name`, which must be a string
+/// literal. Extra arguments are recorded as span fields, as with `tracing::span!`.
+#[cfg(feature = "tracing")]
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        let _profile_scope = $crate::profiling::__tracing::span!(
+            $crate::profiling::__tracing::Level::INFO,
+            $name
+            $(, $($fields)*)?
+        )
+        .entered();
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+#[macro_export]
+macro_rules! profile_scope {
+    // Plain fields are still touched so they don't warn as unused
+    ($name:literal $(, $field:ident)* $(,)?) => {
+        $(let _ = &$field;)*
+    };
+    ($name:literal, $($fields:tt)*) => {};
+}
+
+/// Adds `$value` to the Tracy plot named `$name`, which must be a string literal.
+#[cfg(feature = "tracing")]
+#[macro_export]
+macro_rules! profile_plot {
+    ($name:literal, $value:expr) => {
+        if let Some(client) = $crate::profiling::__tracy_client::Client::running() {
+            client.plot(
+                $crate::profiling::__tracy_client::plot_name!($name),
+                $value as f64,
+            );
+        }
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+#[macro_export]
+macro_rules! profile_plot {
+    ($name:literal, $value:expr) => {
+        let _ = || $value;
+    };
+}
+
+/// Marks the end of a frame, once per `GameLoop::update`.
+pub fn frame_mark() {
+    #[cfg(feature = "tracing")]
+    if let Some(client) = tracy_client::Client::running() {
+        client.frame_mark();
+    }
+}
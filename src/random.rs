@@ -0,0 +1,24 @@
+/// Seeded xorshift32, for scattering things the same way on every run. Not for anything that
+/// needs good randomness.
+#[derive(Debug, Clone)]
+pub(crate) struct Random(u32);
+
+impl Random {
+    /// Zero would only ever give zero, so it's taken as one.
+    pub(crate) fn new(seed: u32) -> Self {
+        Random(seed.max(1))
+    }
+
+    /// From 0 to 1.
+    pub(crate) fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / u32::MAX as f32
+    }
+
+    /// A pseudo-random number from `min` to `max`.
+    pub(crate) fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + self.next() * (max - min)
+    }
+}
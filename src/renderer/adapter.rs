@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        Features,
+    },
+    instance::Instance,
+    memory::MemoryHeapFlags,
+    VulkanLibrary,
+};
+
+use super::vulkan_context::base_instance_create_info;
+
+/// A GPU the renderer can run on.
+#[derive(Debug, Clone)]
+pub struct AdapterInfo {
+    /// Position in the instance's physical device enumeration, usable with
+    /// `DeviceSelector::Index`.
+    pub index: usize,
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    /// Only reported by Vulkan 1.1+ drivers.
+    pub uuid: Option<[u8; 16]>,
+    pub heap_sizes: Vec<u64>,
+    pub device_local_bytes: u64,
+    pub supported_features: Features,
+    /// Implements only the portability subset of Vulkan, like MoltenVK on macOS.
+    pub portability_subset: bool,
+}
+
+impl AdapterInfo {
+    /// Lists the physical devices visible to a throwaway Vulkan instance.
+    pub fn enumerate() -> anyhow::Result<Vec<AdapterInfo>> {
+        let library = VulkanLibrary::new().context("loading Vulkan library")?;
+        let instance = Instance::new(library.clone(), base_instance_create_info(&library))
+            .context("creating instance to enumerate adapters")?;
+
+        Ok(instance
+            .enumerate_physical_devices()
+            .context("enumerating physical devices")?
+            .enumerate()
+            .map(|(index, physical_device)| AdapterInfo::new(index, &physical_device))
+            .collect())
+    }
+
+    fn new(index: usize, physical_device: &Arc<PhysicalDevice>) -> Self {
+        let properties = physical_device.properties();
+        let heaps = &physical_device.memory_properties().memory_heaps;
+
+        AdapterInfo {
+            index,
+            name: properties.device_name.clone(),
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            uuid: properties.device_uuid,
+            heap_sizes: heaps.iter().map(|heap| heap.size).collect(),
+            device_local_bytes: heaps
+                .iter()
+                .filter(|heap| heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL))
+                .map(|heap| heap.size)
+                .sum(),
+            supported_features: *physical_device.supported_features(),
+            portability_subset: physical_device
+                .supported_extensions()
+                .khr_portability_subset,
+        }
+    }
+
+    /// Whether `physical_device` is this adapter, possibly seen through a different instance.
+    pub(crate) fn is(&self, physical_device: &PhysicalDevice) -> bool {
+        let properties = physical_device.properties();
+        match (self.uuid, properties.device_uuid) {
+            (Some(a), Some(b)) => a == b,
+            _ => {
+                self.vendor_id == properties.vendor_id
+                    && self.device_id == properties.device_id
+                    && self.name == properties.device_name
+            }
+        }
+    }
+}
+
+/// Picks an adapter for `RendererConfig::preferred_device`.
+#[derive(Debug, Clone)]
+pub enum DeviceSelector {
+    Index(usize),
+    /// Case-insensitive substring of the device name, e.g. `"nvidia"`.
+    Name(String),
+    Uuid([u8; 16]),
+}
+
+impl DeviceSelector {
+    pub fn matches(&self, adapter: &AdapterInfo) -> bool {
+        match self {
+            DeviceSelector::Index(index) => adapter.index == *index,
+            DeviceSelector::Name(name) => {
+                adapter.name.to_lowercase().contains(&name.to_lowercase())
+            }
+            DeviceSelector::Uuid(uuid) => adapter.uuid == Some(*uuid),
+        }
+    }
+}
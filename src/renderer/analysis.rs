@@ -0,0 +1,435 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        BlitImageInfo, CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel,
+        CommandBufferUsage, CopyImageToBufferInfo, RecordingCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, layout::DescriptorType, DescriptorSet,
+        WriteDescriptorSet,
+    },
+    device::Queue,
+    format::Format,
+    image::{sampler::Filter, view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    shader::EntryPoint,
+};
+
+use super::{
+    frames_in_flight::InFlightFrame,
+    hud::HudPanel,
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::VulkanContext,
+};
+
+pub const HISTOGRAM_BINS: usize = 64;
+
+/// Range of the luminance histogram in log2 units, wide enough for HDR values.
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+const MAX_LOG_LUMINANCE: f32 = 6.0;
+
+const IMAGE_BINDING: DescriptorBinding = DescriptorBinding::new(0, 0, DescriptorType::StorageImage);
+const IMAGE_STATS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::StorageBuffer);
+const DEPTH_TEXELS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::StorageBuffer);
+const DEPTH_RANGE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::StorageBuffer);
+
+/// Rows in the overlay's histogram, each summing `HISTOGRAM_BINS / OVERLAY_ROWS` bins.
+const OVERLAY_ROWS: usize = 16;
+const OVERLAY_BAR_WIDTH: usize = 32;
+
+/// Results of analyzing one frame's HDR scene, before tonemapping.
+#[derive(Debug, Clone)]
+pub struct AnalysisReport {
+    /// Pixel counts per log2 luminance bin, spanning `2^-10..2^6`.
+    pub luminance_histogram: [u32; HISTOGRAM_BINS],
+    pub min_luminance: f32,
+    pub max_luminance: f32,
+    /// Pixels with a NaN or infinite component. These are left out of the other statistics.
+    pub non_finite_pixels: u32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+impl AnalysisReport {
+    /// An overlay showing the report's ranges and a coarse luminance histogram.
+    pub fn panel(&self) -> HudPanel {
+        let bins_per_row = HISTOGRAM_BINS / OVERLAY_ROWS;
+        let rows: Vec<u32> = self
+            .luminance_histogram
+            .chunks(bins_per_row)
+            .map(|bins| bins.iter().sum())
+            .collect();
+        let largest = rows.iter().copied().max().unwrap_or(0).max(1);
+        let log_step = (MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE) / OVERLAY_ROWS as f32;
+
+        let mut lines = vec![
+            format!(
+                "LUMINANCE {:.4} - {:.4}",
+                self.min_luminance, self.max_luminance
+            ),
+            format!("NON-FINITE {}", self.non_finite_pixels),
+            format!("DEPTH {:.4} - {:.4}", self.min_depth, self.max_depth),
+            "LOG2 LUMINANCE:".to_string(),
+        ];
+        lines.extend(rows.iter().enumerate().map(|(row, &count)| {
+            let width = (count as usize * OVERLAY_BAR_WIDTH).div_ceil(largest as usize);
+            format!(
+                "{:6.1} {}",
+                MIN_LOG_LUMINANCE + row as f32 * log_step,
+                "-".repeat(width)
+            )
+        }));
+
+        HudPanel {
+            title: "FRAME ANALYSIS".to_string(),
+            lines,
+            highlighted: (self.non_finite_pixels > 0).then_some(1),
+        }
+    }
+}
+
+struct PendingAnalysis {
+    frame_index: usize,
+    image_stats: Subbuffer<image_cs::ImageStats>,
+    depth_range: Subbuffer<depth_cs::DepthRange>,
+}
+
+/// Compute passes that inspect a finished frame: a luminance histogram with a NaN/Inf count over
+/// the HDR target, and the min/max of the depth attachment.
+///
+/// Results are written to host visible buffers and read back once the frame's slot comes around
+/// again, so requesting an analysis never stalls the GPU.
+pub struct ImageAnalysis {
+    gfx_queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    image_stats_pipeline: Arc<ComputePipeline>,
+    depth_range_pipeline: Arc<ComputePipeline>,
+    color_copy: Option<Arc<ImageView>>,
+    pending: Option<PendingAnalysis>,
+}
+
+impl ImageAnalysis {
+    pub fn new(context: &VulkanContext) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let image_cs = image_cs::load(device.clone())
+            .context("loading image stats shader")?
+            .entry_point("main")
+            .context("image stats shader entry point not found")?;
+        validate_descriptor_bindings(
+            "ImageAnalysis image stats",
+            &[&image_cs],
+            &[IMAGE_BINDING, IMAGE_STATS_BINDING],
+        )?;
+
+        let depth_cs = depth_cs::load(device.clone())
+            .context("loading depth range shader")?
+            .entry_point("main")
+            .context("depth range shader entry point not found")?;
+        validate_descriptor_bindings(
+            "ImageAnalysis depth range",
+            &[&depth_cs],
+            &[DEPTH_TEXELS_BINDING, DEPTH_RANGE_BINDING],
+        )?;
+
+        let image_stats_pipeline =
+            Self::create_pipeline(context, image_cs).context("creating image stats pipeline")?;
+        let depth_range_pipeline =
+            Self::create_pipeline(context, depth_cs).context("creating depth range pipeline")?;
+
+        let debug_namer = context.debug_namer();
+        debug_namer.name(image_stats_pipeline.as_ref(), "image stats pipeline");
+        debug_namer.name(depth_range_pipeline.as_ref(), "depth range pipeline");
+
+        Ok(ImageAnalysis {
+            gfx_queue: context.graphics_queue().clone(),
+            memory_allocator: context.memory_allocator().clone(),
+            descriptor_set_allocator: context.descriptor_set_allocator().clone(),
+            image_stats_pipeline,
+            depth_range_pipeline,
+            color_copy: None,
+            pending: None,
+        })
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Records the analysis of `color` and `depth` into a command buffer to be executed after the
+    /// frame that rendered them. `color` is the HDR target and needs `TRANSFER_SRC` usage, so
+    /// the statistics see values before tonemapping. `depth` must be a D16 image
+    /// with `TRANSFER_SRC` usage.
+    pub fn record(
+        &mut self,
+        frame: &InFlightFrame,
+        color: Arc<Image>,
+        depth: Arc<Image>,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        let extent = color.extent();
+        let color_copy = self.color_copy(extent)?;
+
+        let image_stats = Buffer::from_data(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            image_cs::ImageStats {
+                histogram: [0; HISTOGRAM_BINS],
+                non_finite: 0,
+                min_luminance: u32::MAX,
+                max_luminance: 0,
+            },
+        )
+        .context("creating image stats buffer")?;
+
+        let depth_extent = depth.extent();
+        let texel_count = depth_extent[0] * depth_extent[1];
+
+        let depth_texels = Buffer::new_slice::<u32>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            texel_count.div_ceil(2) as u64,
+        )
+        .context("creating depth texel buffer")?;
+
+        let depth_range = Buffer::from_data(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            depth_cs::DepthRange {
+                min_depth: u32::MAX,
+                max_depth: 0,
+            },
+        )
+        .context("creating depth range buffer")?;
+
+        let image_stats_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.image_stats_pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view(IMAGE_BINDING.binding, color_copy.clone()),
+                WriteDescriptorSet::buffer(IMAGE_STATS_BINDING.binding, image_stats.clone()),
+            ],
+            [],
+        )
+        .context("creating image stats descriptor set")?;
+
+        let depth_range_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.depth_range_pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::buffer(DEPTH_TEXELS_BINDING.binding, depth_texels.clone()),
+                WriteDescriptorSet::buffer(DEPTH_RANGE_BINDING.binding, depth_range.clone()),
+            ],
+            [],
+        )
+        .context("creating depth range descriptor set")?;
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating analysis command buffer")?;
+
+        builder
+            .blit_image(BlitImageInfo {
+                filter: Filter::Nearest,
+                ..BlitImageInfo::images(color, color_copy.image().clone())
+            })
+            .context("copying color target")?
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                depth,
+                depth_texels.into_bytes(),
+            ))
+            .context("copying depth attachment")?
+            .bind_pipeline_compute(self.image_stats_pipeline.clone())
+            .context("binding image stats pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.image_stats_pipeline.layout().clone(),
+                0,
+                image_stats_set,
+            )
+            .context("binding image stats descriptor set")?
+            .push_constants(
+                self.image_stats_pipeline.layout().clone(),
+                0,
+                image_cs::PushConstants {
+                    min_log_luminance: MIN_LOG_LUMINANCE,
+                    max_log_luminance: MAX_LOG_LUMINANCE,
+                },
+            )
+            .context("pushing image stats constants")?;
+
+        unsafe { builder.dispatch([extent[0].div_ceil(16), extent[1].div_ceil(16), 1]) }
+            .context("dispatching image stats")?;
+
+        builder
+            .bind_pipeline_compute(self.depth_range_pipeline.clone())
+            .context("binding depth range pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.depth_range_pipeline.layout().clone(),
+                0,
+                depth_range_set,
+            )
+            .context("binding depth range descriptor set")?
+            .push_constants(
+                self.depth_range_pipeline.layout().clone(),
+                0,
+                depth_cs::PushConstants { texel_count },
+            )
+            .context("pushing depth range constants")?;
+
+        unsafe { builder.dispatch([texel_count.div_ceil(2).div_ceil(256), 1, 1]) }
+            .context("dispatching depth range")?;
+
+        self.pending = Some(PendingAnalysis {
+            frame_index: frame.index,
+            image_stats,
+            depth_range,
+        });
+
+        builder.end().context("ending analysis command buffer")
+    }
+
+    /// Reads back a pending analysis recorded with `frame`'s slot. Call after the slot's fence
+    /// has been waited on.
+    pub fn collect(&mut self, frame: &InFlightFrame) -> anyhow::Result<Option<AnalysisReport>> {
+        if !matches!(&self.pending, Some(pending) if pending.frame_index == frame.index) {
+            return Ok(None);
+        }
+        let pending = self.pending.take().context("taking pending analysis")?;
+
+        let stats = pending.image_stats.read().context("reading image stats")?;
+        let range = pending.depth_range.read().context("reading depth range")?;
+
+        // The minimums are still at their initial value if nothing was written
+        let (min_luminance, max_luminance) = if stats.min_luminance == u32::MAX {
+            (0.0, 0.0)
+        } else {
+            (
+                f32::from_bits(stats.min_luminance),
+                f32::from_bits(stats.max_luminance),
+            )
+        };
+        let (min_depth, max_depth) = if range.min_depth == u32::MAX {
+            (0.0, 0.0)
+        } else {
+            (
+                range.min_depth as f32 / u16::MAX as f32,
+                range.max_depth as f32 / u16::MAX as f32,
+            )
+        };
+
+        Ok(Some(AnalysisReport {
+            luminance_histogram: stats.histogram,
+            min_luminance,
+            max_luminance,
+            non_finite_pixels: stats.non_finite,
+            min_depth,
+            max_depth,
+        }))
+    }
+
+    fn color_copy(&mut self, extent: [u32; 3]) -> anyhow::Result<Arc<ImageView>> {
+        if let Some(copy) = &self.color_copy {
+            if copy.image().extent() == extent {
+                return Ok(copy.clone());
+            }
+        }
+
+        let copy = ImageView::new_default(
+            Image::new(
+                self.memory_allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format: Format::R16G16B16A16_SFLOAT,
+                    extent,
+                    usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_DST,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .context("creating color copy image")?,
+        )
+        .context("creating color copy image view")?;
+
+        self.color_copy = Some(copy.clone());
+        Ok(copy)
+    }
+
+    fn create_pipeline(
+        context: &VulkanContext,
+        entry_point: EntryPoint,
+    ) -> anyhow::Result<Arc<ComputePipeline>> {
+        let device = context.device();
+        let stage = PipelineShaderStageCreateInfo::new(entry_point);
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating pipeline layout create info")?,
+        )
+        .context("creating pipeline layout")?;
+
+        ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .context("creating compute pipeline")
+    }
+}
+
+mod image_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/analysis/image_stats.comp"
+    }
+}
+
+mod depth_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/analysis/depth_range.comp"
+    }
+}
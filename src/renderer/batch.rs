@@ -0,0 +1,62 @@
+use anyhow::Context;
+use cgmath::{InnerSpace, Matrix, Matrix3, SquareMatrix, Vector3, Vector4};
+
+use crate::game::Transform;
+
+use super::{geometry_shaders::VertexPositionColorNormal, mesh::Indices, vertex::MeshVertex};
+
+/// Static meshes merged into a single vertex and index buffer, with each mesh's transform baked
+/// into its vertices.
+///
+/// Every mesh is drawn by the same geometry pipeline, so any set of static meshes can share a
+/// batch. Create the merged mesh with `Renderer::create_static_batch` and enqueue it with an
+/// identity transform to draw everything in one call.
+#[derive(Default)]
+pub struct StaticBatch {
+    vertices: Vec<VertexPositionColorNormal>,
+    indices: Vec<u32>,
+}
+
+impl StaticBatch {
+    /// Appends a mesh placed at `transform`.
+    pub fn with_mesh(
+        mut self,
+        vertices: &[VertexPositionColorNormal],
+        indices: &Indices,
+        transform: &Transform,
+    ) -> anyhow::Result<Self> {
+        let base = self.vertices.len() as u32;
+
+        let model = transform.model();
+        let normal_matrix =
+            Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate())
+                .invert()
+                .context("inverting static mesh transform")?
+                .transpose();
+
+        self.vertices.extend(vertices.iter().map(|vertex| {
+            let [x, y, z] = vertex.position();
+            let position = (model * Vector4::new(x, y, z, 1.0)).truncate();
+            let normal = (normal_matrix * Vector3::from(vertex.normal())).normalize();
+            VertexPositionColorNormal::new(position.into(), vertex.color(), normal.into())
+        }));
+        match indices {
+            Indices::U16(indices) => self
+                .indices
+                .extend(indices.iter().map(|index| base + u32::from(*index))),
+            Indices::U32(indices) => self
+                .indices
+                .extend(indices.iter().map(|index| base + index)),
+        }
+
+        Ok(self)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub(crate) fn into_parts(self) -> (Vec<VertexPositionColorNormal>, Vec<u32>) {
+        (self.vertices, self.indices)
+    }
+}
@@ -0,0 +1,333 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use vulkano::{
+    buffer::{BufferContents, BufferUsage, Subbuffer},
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::layout::DescriptorType,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+    },
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+    DeviceSize,
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    ring_buffer::RingBuffer,
+    vulkan_context::VulkanContext,
+};
+
+const ATLAS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::CombinedImageSampler);
+
+const INITIAL_INSTANCE_CAPACITY: usize = 1024;
+
+/// A textured quad that turns to face the camera, e.g. a marker or a distant tree's impostor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Billboard {
+    /// World space position of the bottom middle of the quad.
+    pub position: Vector3<f32>,
+    /// World space width and height.
+    pub size: [f32; 2],
+    /// The region of the billboard atlas drawn, as minimum and maximum UVs.
+    pub uv: [f32; 4],
+    /// Multiplies the atlas' color. Straight alpha.
+    pub color: [f32; 4],
+    /// Turns only about the world's up axis, so the quad stays upright when seen from above.
+    pub cylindrical: bool,
+}
+
+impl Default for Billboard {
+    fn default() -> Self {
+        Billboard {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            size: [1.0, 1.0],
+            uv: [0.0, 0.0, 1.0, 1.0],
+            color: [1.0; 4],
+            cylindrical: false,
+        }
+    }
+}
+
+#[derive(BufferContents, Vertex, Clone, Copy)]
+#[repr(C)]
+struct BillboardInstance {
+    #[format(R32G32B32_SFLOAT)]
+    position: [f32; 3],
+    #[format(R32G32_SFLOAT)]
+    size: [f32; 2],
+    #[format(R32G32B32A32_SFLOAT)]
+    uv_rect: [f32; 4],
+    #[format(R32G32B32A32_SFLOAT)]
+    color: [f32; 4],
+    #[format(R32_SFLOAT)]
+    cylindrical: f32,
+}
+
+impl From<&Billboard> for BillboardInstance {
+    fn from(billboard: &Billboard) -> Self {
+        BillboardInstance {
+            position: billboard.position.into(),
+            size: billboard.size,
+            uv_rect: billboard.uv,
+            color: billboard.color,
+            cylindrical: if billboard.cylindrical { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+/// Billboards drawn unlit over the composited image in a single instanced draw, all sampling one
+/// atlas texture. They're hidden behind the scene by testing against its depth buffer in the
+/// fragment shader, since the composite pass has no depth attachment. Billboards are queued
+/// every frame and dropped once drawn.
+pub struct Billboards {
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    frames_in_flight: usize,
+    instance_ring: RingBuffer,
+    queued: Vec<BillboardInstance>,
+    instances: Option<Subbuffer<[BillboardInstance]>>,
+    atlas: Arc<ImageView>,
+    atlas_sampler: Arc<Sampler>,
+    depth_sampler: Arc<Sampler>,
+    view: Matrix4<f32>,
+    projection: Matrix4<f32>,
+}
+
+impl Billboards {
+    /// `atlas` is sampled with `atlas_sampler` until replaced with `set_atlas`.
+    pub fn new(
+        context: &VulkanContext,
+        subpass: Subpass,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+        frames_in_flight: usize,
+        atlas: Arc<ImageView>,
+        atlas_sampler: Arc<Sampler>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .context("loading billboard vertex shader")?
+                .entry_point("main")
+                .context("billboard vertex shader entry point not found")?;
+            let fs = fs::load(device.clone())
+                .context("loading billboard fragment shader")?
+                .entry_point("main")
+                .context("billboard fragment shader entry point not found")?;
+            validate_descriptor_bindings("Billboards", &[&fs], &[ATLAS_BINDING, DEPTH_BINDING])?;
+
+            let vertex_input_state = BillboardInstance::per_instance()
+                .definition(&vs.info().input_interface)
+                .context("vertex input state")?;
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("creating pipeline layout create info")?,
+            )
+            .context("creating pipeline layout")?;
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(vertex_input_state),
+                    input_assembly_state: Some(InputAssemblyState {
+                        topology: PrimitiveTopology::TriangleStrip,
+                        ..Default::default()
+                    }),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        subpass.num_color_attachments(),
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend::alpha()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(subpass.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .context("creating billboard pipeline")?
+        };
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "billboard pipeline");
+
+        let depth_sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .context("creating billboard depth sampler")?;
+
+        let memory_allocator = context.memory_allocator().clone();
+        let instance_ring = RingBuffer::new(
+            memory_allocator.clone(),
+            BufferUsage::VERTEX_BUFFER,
+            (INITIAL_INSTANCE_CAPACITY * std::mem::size_of::<BillboardInstance>()) as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating billboard instance ring buffer")?;
+
+        Ok(Billboards {
+            pipeline,
+            memory_allocator,
+            descriptor_set_cache,
+            frames_in_flight,
+            instance_ring,
+            queued: vec![],
+            instances: None,
+            atlas,
+            atlas_sampler,
+            depth_sampler,
+            view: Matrix4::identity(),
+            projection: Matrix4::identity(),
+        })
+    }
+
+    /// Replaces the texture every billboard samples its `uv` region from.
+    pub fn set_atlas(&mut self, atlas: Arc<ImageView>) {
+        self.atlas = atlas;
+    }
+
+    /// Queues a billboard for the next frame.
+    pub fn push(&mut self, billboard: &Billboard) {
+        self.queued.push(billboard.into());
+    }
+
+    /// Uploads the queued billboards, seen through the unjittered `camera` projection and view
+    /// matrices, and clears the queue. Must be called once per frame, before `record`.
+    pub fn update(
+        &mut self,
+        frame_index: usize,
+        camera: (Matrix4<f32>, Matrix4<f32>),
+    ) -> anyhow::Result<()> {
+        self.instances = None;
+        (self.projection, self.view) = camera;
+        let instances = std::mem::take(&mut self.queued);
+        if instances.is_empty() {
+            return Ok(());
+        }
+
+        let size = std::mem::size_of_val(instances.as_slice()) as DeviceSize;
+        if size > self.instance_ring.region_size() {
+            let capacity = instances.len().next_power_of_two();
+            log::debug!("growing billboard ring buffer to {} instances", capacity);
+            self.instance_ring = RingBuffer::new(
+                self.memory_allocator.clone(),
+                BufferUsage::VERTEX_BUFFER,
+                (capacity * std::mem::size_of::<BillboardInstance>()) as DeviceSize,
+                self.frames_in_flight,
+            )
+            .context("growing billboard ring buffer")?;
+        }
+        self.instance_ring.begin_frame(frame_index);
+        self.instances = Some(self.instance_ring.push_slice(&instances)?);
+
+        Ok(())
+    }
+
+    /// Draws the billboards uploaded by `update`, hidden where they're behind `depth_buffer`.
+    /// Must be recorded inside the composite render pass, whose viewport is already set.
+    pub fn record(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        depth_buffer: &Arc<ImageView>,
+    ) -> anyhow::Result<()> {
+        let Some(instances) = self.instances.clone() else {
+            return Ok(());
+        };
+
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &self.pipeline.layout().set_layouts()[0],
+            &[
+                CachedWrite::ImageViewSampler(
+                    ATLAS_BINDING.binding,
+                    self.atlas.clone(),
+                    self.atlas_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    DEPTH_BINDING.binding,
+                    depth_buffer.clone(),
+                    self.depth_sampler.clone(),
+                ),
+            ],
+        )?;
+
+        let instance_count = instances.len() as u32;
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .context("binding billboard pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding billboard descriptor set")?
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::PushConstants {
+                    view: self.view.into(),
+                    projection: self.projection.into(),
+                },
+            )
+            .context("pushing billboard constants")?
+            .bind_vertex_buffers(0, instances)
+            .context("binding billboard instances")?;
+        unsafe { builder.draw(4, instance_count, 0, 0) }.context("drawing billboards")?;
+
+        Ok(())
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/billboard/billboard.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/billboard/billboard.frag"
+    }
+}
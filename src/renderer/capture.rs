@@ -0,0 +1,91 @@
+#[cfg(feature = "renderdoc")]
+use renderdoc::{RenderDoc, V110};
+
+/// Single frame captures through RenderDoc's in-application API.
+///
+/// Only available with the `renderdoc` feature, and only when RenderDoc is attached to the
+/// process: launch it from the RenderDoc UI or inject it before the Vulkan instance is created.
+/// Otherwise requests are logged and ignored.
+pub struct FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc<V110>>,
+    requested: bool,
+    capturing: bool,
+}
+
+impl FrameCapture {
+    pub fn load() -> Self {
+        #[cfg(feature = "renderdoc")]
+        let renderdoc = match RenderDoc::<V110>::new() {
+            Ok(renderdoc) => {
+                log::info!("RenderDoc attached, frame captures available");
+                Some(renderdoc)
+            }
+            Err(e) => {
+                log::info!("RenderDoc not attached, frame captures unavailable: {}", e);
+                None
+            }
+        };
+
+        FrameCapture {
+            #[cfg(feature = "renderdoc")]
+            renderdoc,
+            requested: false,
+            capturing: false,
+        }
+    }
+
+    #[cfg(feature = "renderdoc")]
+    pub fn available(&self) -> bool {
+        self.renderdoc.is_some()
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    pub fn available(&self) -> bool {
+        false
+    }
+
+    /// Captures the next frame.
+    pub fn request(&mut self) {
+        if self.available() {
+            self.requested = true;
+        } else if cfg!(feature = "renderdoc") {
+            log::warn!("frame capture requested, but RenderDoc isn't attached");
+        } else {
+            log::warn!("frame capture requested, but triton was built without `renderdoc`");
+        }
+    }
+
+    /// Starts capturing if a capture was requested. Called before anything of the frame is
+    /// recorded.
+    pub fn begin_frame(&mut self) {
+        if !self.requested || self.capturing {
+            return;
+        }
+        self.requested = false;
+
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = &mut self.renderdoc {
+            // Null handles capture whichever device and window are presenting
+            renderdoc.start_frame_capture(std::ptr::null(), std::ptr::null());
+            self.capturing = true;
+        }
+    }
+
+    /// Ends a capture started by `begin_frame`, once the frame has been presented.
+    pub fn end_frame(&mut self) {
+        if !self.capturing {
+            return;
+        }
+        self.capturing = false;
+
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = &mut self.renderdoc {
+            renderdoc.end_frame_capture(std::ptr::null(), std::ptr::null());
+            log::info!(
+                "captured frame {} with RenderDoc",
+                renderdoc.get_num_captures()
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use vulkano::instance::debug::{DebugUtilsMessageSeverity, DebugUtilsMessageType};
+
+use super::{adapter::DeviceSelector, ssgi::GlobalIllumination};
+
+/// How colors given to the renderer are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorWorkflow {
+    /// Vertex and light colors are sRGB values, as picked in most tools, and are converted to
+    /// linear for shading. The swapchain is sRGB where the surface supports it, so the output is
+    /// encoded by the hardware.
+    #[default]
+    Srgb,
+    /// Colors are used as given and written to a UNORM swapchain without encoding. Matches the
+    /// renderer's behavior before color space management.
+    Linear,
+}
+
+/// Options that are fixed for the lifetime of a `Renderer`.
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// How many frames the CPU may record ahead of the GPU. Clamped to `1..=3`. Fewer frames cut
+    /// latency, more keep the GPU busy when the CPU's frame times vary.
+    pub frames_in_flight: usize,
+    /// The least images the swapchain is created with. The surface's own minimum wins when it's
+    /// higher. 3 lets the GPU render a frame while one waits for vertical blank, at a frame of
+    /// latency.
+    pub swapchain_images: u32,
+    /// Waits for the GPU to finish each frame after queueing its present, before the next one is
+    /// started, so input and the simulation's snapshots are read as late as possible. The wait
+    /// ends before the image reaches the display, which still takes up to a vertical blank with
+    /// vsync. Trades throughput for latency, best with one frame in flight and two swapchain
+    /// images.
+    pub low_latency: bool,
+    /// Enables the Khronos validation layer and the debug messenger. Skipped with a warning when
+    /// the layer isn't installed.
+    pub validation: bool,
+    /// Messages the debug messenger reports. They're logged at the level matching their severity.
+    pub debug_message_severity: DebugUtilsMessageSeverity,
+    pub debug_message_type: DebugUtilsMessageType,
+    /// Names Vulkan objects through `VK_EXT_debug_utils`.
+    pub debug_names: bool,
+    /// The GPU to run on, see `AdapterInfo::enumerate`. Falls back to the best suitable device,
+    /// preferring discrete GPUs, when unset or when the selected adapter can't be used.
+    pub preferred_device: Option<DeviceSelector>,
+    /// Skips drawing objects hidden behind the depth of previous frames. Objects can pop in for
+    /// a frame or two after fast camera moves, since the depth lags by the frames in flight.
+    pub occlusion_culling: bool,
+    /// Submits geometry with one `draw_indexed_indirect` per run of objects sharing a mesh
+    /// instead of one draw per object. Requires the `multiDrawIndirect` and
+    /// `drawIndirectFirstInstance` device features.
+    pub indirect_draws: bool,
+    /// Culls the indirect draws against the camera's frustum in a compute pass that writes the
+    /// draws itself, instead of deciding what to draw on the CPU. Requires `indirect_draws` and
+    /// the `drawIndirectCount` device feature. Occlusion culling is skipped while it's on.
+    pub gpu_culling: bool,
+    pub color_workflow: ColorWorkflow,
+    /// Writes each pixel's screen space motion to a velocity target in the geometry pass, for
+    /// TAA and other temporal effects. Costs an extra RG16F render target when enabled.
+    pub motion_vectors: bool,
+    /// Initial `Renderer::set_global_illumination`.
+    pub global_illumination: GlobalIllumination,
+    /// Initial logical size of the window.
+    pub window_size: [f32; 2],
+    pub window_title: String,
+    /// Waits for vertical blank to present, capping the frame rate at the display's refresh rate.
+    /// Presents immediately when disabled, which can tear.
+    pub vsync: bool,
+    /// Initial `Renderer::set_render_scale`.
+    pub render_scale: f32,
+    /// Bytes of mesh data `Renderer::upload_mesh` copies to the GPU each frame. Staging memory
+    /// of this size is kept for every frame in flight.
+    pub upload_budget: u64,
+    /// Size of the shared buffers meshes' vertices and indices are suballocated from. Each
+    /// vertex layout and index type gets its own, and meshes bigger than this a buffer of
+    /// their own size.
+    pub mesh_pool_block_size: u64,
+    /// Shows the window. Hidden windows are still rendered to, e.g. for automated runs.
+    pub visible: bool,
+    /// Composites the window with what's behind it, showing through wherever the scene covers
+    /// nothing. Needs a surface supporting premultiplied, postmultiplied or inherited alpha,
+    /// the window stays opaque with a warning otherwise.
+    pub transparent: bool,
+    /// Initial `Renderer::set_always_on_top`.
+    pub always_on_top: bool,
+    /// Initial `Renderer::set_click_through`.
+    pub click_through: bool,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        RendererConfig {
+            frames_in_flight: 2,
+            swapchain_images: 2,
+            low_latency: false,
+            validation: cfg!(debug_assertions),
+            debug_message_severity: DebugUtilsMessageSeverity::ERROR
+                | DebugUtilsMessageSeverity::WARNING,
+            debug_message_type: DebugUtilsMessageType::GENERAL
+                | DebugUtilsMessageType::VALIDATION
+                | DebugUtilsMessageType::PERFORMANCE,
+            debug_names: cfg!(debug_assertions),
+            preferred_device: None,
+            occlusion_culling: true,
+            indirect_draws: false,
+            gpu_culling: false,
+            color_workflow: ColorWorkflow::default(),
+            motion_vectors: true,
+            global_illumination: GlobalIllumination::default(),
+            window_size: [1280.0, 720.0],
+            window_title: "triton".to_string(),
+            vsync: true,
+            render_scale: 1.0,
+            upload_budget: 4 * 1024 * 1024,
+            mesh_pool_block_size: 64 * 1024 * 1024,
+            visible: true,
+            transparent: false,
+            always_on_top: false,
+            click_through: false,
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use vulkano::{
+    buffer::{BufferContents, BufferUsage, Subbuffer},
+    command_buffer::RecordingCommandBuffer,
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+    DeviceSize,
+};
+
+use super::{ring_buffer::RingBuffer, vulkan_context::VulkanContext};
+
+const INITIAL_VERTEX_CAPACITY: usize = 4 * 1024;
+
+#[derive(BufferContents, Vertex, Clone, Copy)]
+#[repr(C)]
+struct LineVertex {
+    #[format(R32G32B32_SFLOAT)]
+    position: [f32; 3],
+    #[format(R32G32B32A32_SFLOAT)]
+    color: [f32; 4],
+}
+
+/// World space lines drawn over the composited image without depth testing, for gizmos and
+/// other debug visuals. Lines are queued every frame and dropped once drawn.
+pub struct DebugDraw {
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    frames_in_flight: usize,
+    vertex_ring: RingBuffer,
+    queued: Vec<LineVertex>,
+    vertices: Option<Subbuffer<[LineVertex]>>,
+    view_projection: Matrix4<f32>,
+}
+
+impl DebugDraw {
+    pub fn new(
+        context: &VulkanContext,
+        subpass: Subpass,
+        frames_in_flight: usize,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .context("loading debug line vertex shader")?
+                .entry_point("main")
+                .context("debug line vertex shader entry point not found")?;
+            let fs = fs::load(device.clone())
+                .context("loading debug line fragment shader")?
+                .entry_point("main")
+                .context("debug line fragment shader entry point not found")?;
+
+            let vertex_input_state = LineVertex::per_vertex()
+                .definition(&vs.info().input_interface)
+                .context("vertex input state")?;
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("creating pipeline layout create info")?,
+            )
+            .context("creating pipeline layout")?;
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(vertex_input_state),
+                    input_assembly_state: Some(InputAssemblyState {
+                        topology: PrimitiveTopology::LineList,
+                        ..Default::default()
+                    }),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        subpass.num_color_attachments(),
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend::alpha()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(subpass.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .context("creating debug line pipeline")?
+        };
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "debug line pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let vertex_ring = RingBuffer::new(
+            memory_allocator.clone(),
+            BufferUsage::VERTEX_BUFFER,
+            (INITIAL_VERTEX_CAPACITY * std::mem::size_of::<LineVertex>()) as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating debug line vertex ring buffer")?;
+
+        Ok(DebugDraw {
+            pipeline,
+            memory_allocator,
+            frames_in_flight,
+            vertex_ring,
+            queued: vec![],
+            vertices: None,
+            view_projection: Matrix4::identity(),
+        })
+    }
+
+    /// Queues a line for the next frame.
+    pub fn line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: [f32; 4]) {
+        self.queued.extend([
+            LineVertex {
+                position: from.into(),
+                color,
+            },
+            LineVertex {
+                position: to.into(),
+                color,
+            },
+        ]);
+    }
+
+    /// Uploads the queued lines, seen through `view_projection`, and clears the queue. Must be
+    /// called once per frame, before `record`.
+    pub fn update(
+        &mut self,
+        frame_index: usize,
+        view_projection: Matrix4<f32>,
+    ) -> anyhow::Result<()> {
+        self.vertices = None;
+        self.view_projection = view_projection;
+        let vertices = std::mem::take(&mut self.queued);
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        let size = std::mem::size_of_val(vertices.as_slice()) as DeviceSize;
+        if size > self.vertex_ring.region_size() {
+            let capacity = vertices.len().next_power_of_two();
+            log::debug!("growing debug line ring buffer to {} vertices", capacity);
+            self.vertex_ring = RingBuffer::new(
+                self.memory_allocator.clone(),
+                BufferUsage::VERTEX_BUFFER,
+                (capacity * std::mem::size_of::<LineVertex>()) as DeviceSize,
+                self.frames_in_flight,
+            )
+            .context("growing debug line ring buffer")?;
+        }
+        self.vertex_ring.begin_frame(frame_index);
+        self.vertices = Some(self.vertex_ring.push_slice(&vertices)?);
+
+        Ok(())
+    }
+
+    /// Draws the lines uploaded by `update`. Must be recorded inside the composite render pass,
+    /// whose viewport is already set.
+    pub fn record(&self, builder: &mut RecordingCommandBuffer) -> anyhow::Result<()> {
+        let Some(vertices) = self.vertices.clone() else {
+            return Ok(());
+        };
+
+        let vertex_count = vertices.len() as u32;
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .context("binding debug line pipeline")?
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                vs::PushConstants {
+                    view_projection: self.view_projection.into(),
+                },
+            )
+            .context("pushing debug line constants")?
+            .bind_vertex_buffers(0, vertices)
+            .context("binding debug line vertices")?;
+        unsafe { builder.draw(vertex_count, 1, 0, 0) }.context("drawing debug lines")?;
+
+        Ok(())
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/debug/line.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/debug/line.frag"
+    }
+}
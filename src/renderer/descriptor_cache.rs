@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context};
+use vulkano::{
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, layout::DescriptorSetLayout, DescriptorSet,
+        WriteDescriptorSet,
+    },
+    image::{sampler::Sampler, view::ImageView},
+};
+
+/// Sets that haven't been requested for this many frames are dropped, releasing the resources
+/// they reference (e.g. G-buffer images replaced by a resize).
+const EVICT_AFTER_FRAMES: u64 = 8;
+
+/// A descriptor write that can be used as part of a cache key.
+#[derive(Clone)]
+pub enum CachedWrite {
+    ImageView(u32, Arc<ImageView>),
+    ImageViewSampler(u32, Arc<ImageView>, Arc<Sampler>),
+}
+
+impl CachedWrite {
+    /// Resources are identified by pointer. Cached sets keep their resources alive, so an address
+    /// can't be reused by a different resource while an entry refers to it.
+    fn key(&self) -> ResourceKey {
+        match self {
+            CachedWrite::ImageView(binding, view) => (*binding, Arc::as_ptr(view) as usize, 0),
+            CachedWrite::ImageViewSampler(binding, view, sampler) => (
+                *binding,
+                Arc::as_ptr(view) as usize,
+                Arc::as_ptr(sampler) as usize,
+            ),
+        }
+    }
+
+    fn to_write(&self) -> WriteDescriptorSet {
+        match self {
+            CachedWrite::ImageView(binding, view) => {
+                WriteDescriptorSet::image_view(*binding, view.clone())
+            }
+            CachedWrite::ImageViewSampler(binding, view, sampler) => {
+                WriteDescriptorSet::image_view_sampler(*binding, view.clone(), sampler.clone())
+            }
+        }
+    }
+}
+
+type ResourceKey = (u32, usize, usize);
+
+#[derive(PartialEq, Eq, Hash)]
+struct CacheKey {
+    layout: usize,
+    resources: Vec<ResourceKey>,
+}
+
+struct Entry {
+    set: Arc<DescriptorSet>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    frame: u64,
+    entries: HashMap<CacheKey, Entry>,
+}
+
+/// Descriptor sets shared by every render system, created the first time a combination of
+/// layout and resources is requested and reused until it goes unused for a few frames.
+pub struct DescriptorSetCache {
+    allocator: Arc<StandardDescriptorSetAllocator>,
+    state: Mutex<CacheState>,
+}
+
+impl DescriptorSetCache {
+    pub fn new(allocator: Arc<StandardDescriptorSetAllocator>) -> Self {
+        DescriptorSetCache {
+            allocator,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// The allocator the cache creates sets from, for systems that manage their own long-lived
+    /// sets.
+    pub fn allocator(&self) -> &Arc<StandardDescriptorSetAllocator> {
+        &self.allocator
+    }
+
+    pub fn get_or_create(
+        &self,
+        layout: &Arc<DescriptorSetLayout>,
+        writes: &[CachedWrite],
+    ) -> anyhow::Result<Arc<DescriptorSet>> {
+        let key = CacheKey {
+            layout: Arc::as_ptr(layout) as usize,
+            resources: writes.iter().map(CachedWrite::key).collect(),
+        };
+
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("descriptor set cache lock poisoned"))?;
+        let frame = state.frame;
+
+        if let Some(entry) = state.entries.get_mut(&key) {
+            entry.last_used = frame;
+            return Ok(entry.set.clone());
+        }
+
+        let set = DescriptorSet::new(
+            self.allocator.clone(),
+            layout.clone(),
+            writes.iter().map(CachedWrite::to_write),
+            [],
+        )
+        .context("creating cached descriptor set")?;
+
+        state.entries.insert(
+            key,
+            Entry {
+                set: set.clone(),
+                last_used: frame,
+            },
+        );
+
+        Ok(set)
+    }
+
+    /// Advances the cache's frame counter and evicts stale sets. Call once per frame.
+    pub fn next_frame(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.frame += 1;
+            let frame = state.frame;
+            state
+                .entries
+                .retain(|_, entry| frame - entry.last_used <= EVICT_AFTER_FRAMES);
+        }
+    }
+}
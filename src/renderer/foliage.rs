@@ -0,0 +1,585 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use anyhow::Context;
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferLevel,
+        CommandBufferUsage, RecordingCommandBuffer,
+    },
+    descriptor_set::{
+        layout::DescriptorType, DescriptorBufferInfo, DescriptorSet, DescriptorSetWithOffsets,
+        WriteDescriptorSet,
+    },
+    device::Queue,
+    image::{sampler::Sampler, view::ImageView},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::{CullMode, RasterizationState},
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+    DeviceSize,
+};
+
+use crate::game::Frustum;
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    frames_in_flight::InFlightFrame,
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    ring_buffer::RingBuffer,
+    texture::TextureHandle,
+    vulkan_context::VulkanContext,
+};
+
+const FOLIAGE_DATA_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::UniformBufferDynamic);
+const TEXTURE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(1, 0, DescriptorType::CombinedImageSampler);
+
+/// Width of the square cells instances are grouped into, each culled and drawn as one instanced
+/// draw.
+const CELL_SIZE: f32 = 16.0;
+/// Two crossed quads of two triangles each.
+const VERTICES_PER_INSTANCE: u32 = 12;
+
+/// One plant of a `FoliageLayer`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FoliageInstance {
+    /// Where its roots are, in world space.
+    pub position: Vector3<f32>,
+    /// Width and height in meters.
+    pub size: [f32; 2],
+    /// Radians about the world's up axis.
+    pub rotation: f32,
+    /// Multiplies the layer's texture.
+    pub color: [f32; 3],
+}
+
+/// Identifies a foliage layer. Chosen by the caller, see `Renderer::set_foliage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FoliageId(pub u32);
+
+/// Plants sharing a texture, e.g. a field of grass or the shrubs scattered through it. They're
+/// drawn into the G-buffer as alpha tested crossed quads, so they're lit like the rest of the
+/// scene, but don't cast shadows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoliageLayer {
+    pub instances: Vec<FoliageInstance>,
+    /// Tested against half alpha, drawn with a white texture when `None`.
+    pub texture: Option<TextureHandle>,
+    /// Instances further than this from the camera start dithering out.
+    pub fade_start: f32,
+    /// Instances further than this are gone and their cells aren't drawn at all.
+    pub fade_end: f32,
+    /// Scales how far the wind bends the tips, 1 for grass.
+    pub sway: f32,
+    pub roughness: f32,
+}
+
+impl Default for FoliageLayer {
+    fn default() -> Self {
+        FoliageLayer {
+            instances: vec![],
+            texture: None,
+            fade_start: 40.0,
+            fade_end: 60.0,
+            sway: 1.0,
+            roughness: 0.8,
+        }
+    }
+}
+
+/// Sways every foliage layer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wind {
+    /// The way it blows along the ground, as x and z. Normalized when set.
+    pub direction: [f32; 2],
+    /// How far the tips of plants with a `sway` of 1 bend, in meters.
+    pub strength: f32,
+    /// Gusts per second.
+    pub frequency: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Wind {
+            direction: [1.0, 0.0],
+            strength: 0.15,
+            frequency: 0.4,
+        }
+    }
+}
+
+#[derive(BufferContents, Vertex, Clone, Copy)]
+#[repr(C)]
+struct InstanceData {
+    #[format(R32G32B32_SFLOAT)]
+    position: [f32; 3],
+    #[format(R32G32_SFLOAT)]
+    size: [f32; 2],
+    #[format(R32_SFLOAT)]
+    rotation: f32,
+    #[format(R32G32B32_SFLOAT)]
+    color: [f32; 3],
+}
+
+/// A run of a layer's instances in the same cell, and the box around them.
+struct Cell {
+    first_instance: u32,
+    instance_count: u32,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+struct UploadedLayer {
+    layer: FoliageLayer,
+    instances: Subbuffer<[InstanceData]>,
+    cells: Vec<Cell>,
+    texture: Arc<ImageView>,
+}
+
+/// Draws the foliage layers into the G-buffer after the scene's meshes, in the same subpass.
+/// Layers are uploaded once when they're set and culled by cell every frame, against the
+/// frustum and their fade distance.
+pub struct Foliage {
+    gfx_queue: Arc<Queue>,
+    subpass: Subpass,
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    foliage_data_ring: RingBuffer,
+    foliage_data_set: Arc<DescriptorSet>,
+    sampler: Arc<Sampler>,
+    layers: HashMap<FoliageId, UploadedLayer>,
+    wind: Wind,
+    started: Instant,
+    previous_view_projection: Option<Matrix4<f32>>,
+    draw_calls: u32,
+}
+
+impl Foliage {
+    pub fn new(
+        context: &VulkanContext,
+        subpass: Subpass,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+        frames_in_flight: usize,
+        sampler: Arc<Sampler>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let vs = vs::load(device.clone())
+            .context("loading foliage vertex shader")?
+            .entry_point("main")
+            .context("foliage vertex shader entry point not found")?;
+        let fs = fs::load(device.clone())
+            .context("loading foliage fragment shader")?
+            .entry_point("main")
+            .context("foliage fragment shader entry point not found")?;
+        validate_descriptor_bindings(
+            "Foliage",
+            &[&vs, &fs],
+            &[FOLIAGE_DATA_BINDING, TEXTURE_BINDING],
+        )?;
+
+        let vertex_input_state = InstanceData::per_instance()
+            .definition(&vs.info().input_interface)
+            .context("vertex input state")?;
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        // The per-frame data is bound with a dynamic offset into a ring buffer, which reflection
+        // doesn't produce
+        let mut layout_create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages);
+        layout_create_info.set_layouts[FOLIAGE_DATA_BINDING.set as usize]
+            .bindings
+            .get_mut(&FOLIAGE_DATA_BINDING.binding)
+            .context("getting foliage data binding")?
+            .descriptor_type = FOLIAGE_DATA_BINDING.ty;
+        let layout = PipelineLayout::new(
+            device.clone(),
+            layout_create_info
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating pipeline layout create info")?,
+        )
+        .context("creating pipeline layout")?;
+
+        let pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                // Both sides of the quads are seen
+                rasterization_state: Some(RasterizationState {
+                    cull_mode: CullMode::None,
+                    ..Default::default()
+                }),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .context("creating foliage pipeline")?;
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "foliage pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let foliage_data_ring = RingBuffer::new(
+            memory_allocator.clone(),
+            BufferUsage::UNIFORM_BUFFER,
+            std::mem::size_of::<vs::FoliageData>() as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating foliage data ring buffer")?;
+        let foliage_data_set = DescriptorSet::new(
+            descriptor_set_cache.allocator().clone(),
+            pipeline.layout().set_layouts()[FOLIAGE_DATA_BINDING.set as usize].clone(),
+            [WriteDescriptorSet::buffer_with_range(
+                FOLIAGE_DATA_BINDING.binding,
+                DescriptorBufferInfo {
+                    buffer: foliage_data_ring.buffer().clone(),
+                    range: 0..foliage_data_ring.region_size(),
+                },
+            )],
+            [],
+        )
+        .context("creating foliage data descriptor set")?;
+
+        Ok(Foliage {
+            gfx_queue: context.graphics_queue().clone(),
+            subpass,
+            pipeline,
+            memory_allocator,
+            descriptor_set_cache,
+            foliage_data_ring,
+            foliage_data_set,
+            sampler,
+            layers: HashMap::new(),
+            wind: Wind::default(),
+            started: Instant::now(),
+            previous_view_projection: None,
+            draw_calls: 0,
+        })
+    }
+
+    /// Uploads `layer` in place of the layer `id` had, if any. `texture` is the view of the
+    /// layer's texture, and the instance colors must already be linear.
+    pub fn set_layer(
+        &mut self,
+        id: FoliageId,
+        layer: FoliageLayer,
+        texture: Arc<ImageView>,
+    ) -> anyhow::Result<()> {
+        if layer.instances.is_empty() {
+            self.layers.remove(&id);
+            return Ok(());
+        }
+
+        // Sorting by cell keeps each cell's instances together, to draw with one call
+        let cell_of = |instance: &FoliageInstance| {
+            (
+                (instance.position.x / CELL_SIZE).floor() as i32,
+                (instance.position.z / CELL_SIZE).floor() as i32,
+            )
+        };
+        let mut sorted = layer.instances.clone();
+        sorted.sort_by_key(cell_of);
+
+        let mut cells: Vec<Cell> = vec![];
+        let mut current = None;
+        for (index, instance) in sorted.iter().enumerate() {
+            let reach = instance.size[0].max(instance.size[1]);
+            let min = instance.position - Vector3::new(reach, 0.0, reach);
+            let max = instance.position + Vector3::new(reach, reach, reach);
+            let key = cell_of(instance);
+            match cells.last_mut() {
+                Some(cell) if current == Some(key) => {
+                    cell.instance_count += 1;
+                    cell.min = Vector3::new(
+                        cell.min.x.min(min.x),
+                        cell.min.y.min(min.y),
+                        cell.min.z.min(min.z),
+                    );
+                    cell.max = Vector3::new(
+                        cell.max.x.max(max.x),
+                        cell.max.y.max(max.y),
+                        cell.max.z.max(max.z),
+                    );
+                }
+                _ => {
+                    current = Some(key);
+                    cells.push(Cell {
+                        first_instance: index as u32,
+                        instance_count: 1,
+                        min,
+                        max,
+                    });
+                }
+            }
+        }
+
+        let instances = Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            sorted.iter().map(|instance| InstanceData {
+                position: instance.position.into(),
+                size: instance.size,
+                rotation: instance.rotation,
+                color: instance.color,
+            }),
+        )
+        .context("creating foliage instance buffer")?;
+
+        log::debug!(
+            "foliage layer {:?}: {} instances in {} cells",
+            id,
+            sorted.len(),
+            cells.len()
+        );
+        self.layers.insert(
+            id,
+            UploadedLayer {
+                layer,
+                instances,
+                cells,
+                texture,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove_layer(&mut self, id: FoliageId) {
+        self.layers.remove(&id);
+    }
+
+    pub fn layer(&self, id: FoliageId) -> Option<&FoliageLayer> {
+        self.layers.get(&id).map(|uploaded| &uploaded.layer)
+    }
+
+    pub fn set_wind(&mut self, mut wind: Wind) {
+        let [x, z] = wind.direction;
+        let length = (x * x + z * z).sqrt();
+        wind.direction = if length > 0.0 {
+            [x / length, z / length]
+        } else {
+            [1.0, 0.0]
+        };
+        self.wind = wind;
+    }
+
+    pub fn wind(&self) -> Wind {
+        self.wind
+    }
+
+    /// Every instance of every layer, whether it's drawn or not.
+    pub fn instance_count(&self) -> usize {
+        self.layers
+            .values()
+            .map(|uploaded| uploaded.layer.instances.len())
+            .sum()
+    }
+
+    /// Instanced draws of the last frame, one per visible cell.
+    pub fn draw_calls(&self) -> u32 {
+        self.draw_calls
+    }
+
+    /// Builds a secondary command buffer drawing the layers' visible cells for the deferred
+    /// subpass, seen through `camera`, the jittered projection and view matrices, and
+    /// `unjittered`. `None` when there's nothing to draw.
+    pub fn draw(
+        &mut self,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+        camera: (Matrix4<f32>, Matrix4<f32>),
+        unjittered: (Matrix4<f32>, Matrix4<f32>),
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        profile_scope!("foliage");
+        self.draw_calls = 0;
+        let unjittered_view_projection = unjittered.0 * unjittered.1;
+        let previous_view_projection = self
+            .previous_view_projection
+            .replace(unjittered_view_projection)
+            .unwrap_or(unjittered_view_projection);
+        if self.layers.is_empty() {
+            return Ok(None);
+        }
+
+        let camera_position = camera
+            .1
+            .invert()
+            .context("inverting view matrix")?
+            .w
+            .truncate();
+        let frustum = Frustum::from_view_projection(unjittered_view_projection);
+
+        self.foliage_data_ring.begin_frame(frame.index);
+        let offset = self.foliage_data_ring.push(&[vs::FoliageData {
+            view_projection: (camera.0 * camera.1).into(),
+            unjittered_view_projection: unjittered_view_projection.into(),
+            previous_view_projection: previous_view_projection.into(),
+            camera: camera_position
+                .extend(self.started.elapsed().as_secs_f32())
+                .into(),
+            wind: [
+                self.wind.direction[0],
+                self.wind.direction[1],
+                self.wind.strength,
+                self.wind.frequency,
+            ],
+        }])?;
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Secondary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::MultipleSubmit,
+                inheritance_info: Some(CommandBufferInheritanceInfo {
+                    render_pass: Some(self.subpass.clone().into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+        builder
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .context("setting viewport")?
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .context("binding foliage pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                DescriptorSetWithOffsets::new(self.foliage_data_set.clone(), [offset]),
+            )
+            .context("binding foliage data")?;
+
+        for uploaded in self.layers.values() {
+            let layer = &uploaded.layer;
+            let visible = uploaded.cells.iter().filter(|cell| {
+                distance_to_box(camera_position, cell.min, cell.max) < layer.fade_end
+                    && frustum.intersects_aabb(cell.min, cell.max)
+            });
+            let mut bound = false;
+            for cell in visible {
+                if !bound {
+                    let texture_set = self.descriptor_set_cache.get_or_create(
+                        &self.pipeline.layout().set_layouts()[TEXTURE_BINDING.set as usize],
+                        &[CachedWrite::ImageViewSampler(
+                            TEXTURE_BINDING.binding,
+                            uploaded.texture.clone(),
+                            self.sampler.clone(),
+                        )],
+                    )?;
+                    builder
+                        .bind_descriptor_sets(
+                            PipelineBindPoint::Graphics,
+                            self.pipeline.layout().clone(),
+                            TEXTURE_BINDING.set,
+                            texture_set,
+                        )
+                        .context("binding foliage texture")?
+                        .push_constants(
+                            self.pipeline.layout().clone(),
+                            0,
+                            vs::LayerData {
+                                fade_start: layer.fade_start,
+                                fade_end: layer.fade_end,
+                                sway: layer.sway,
+                                roughness: layer.roughness,
+                            },
+                        )
+                        .context("pushing foliage layer constants")?
+                        .bind_vertex_buffers(0, uploaded.instances.clone())
+                        .context("binding foliage instances")?;
+                    bound = true;
+                }
+                unsafe {
+                    builder.draw(
+                        VERTICES_PER_INSTANCE,
+                        cell.instance_count,
+                        0,
+                        cell.first_instance,
+                    )
+                }
+                .context("drawing foliage")?;
+                self.draw_calls += 1;
+            }
+        }
+
+        if self.draw_calls == 0 {
+            return Ok(None);
+        }
+        Ok(Some(
+            builder.end().context("building foliage command buffer")?,
+        ))
+    }
+}
+
+/// How far `point` is from the box between `min` and `max`, zero inside it.
+fn distance_to_box(point: Vector3<f32>, min: Vector3<f32>, max: Vector3<f32>) -> f32 {
+    let outside = |value: f32, min: f32, max: f32| (min - value).max(value - max).max(0.0);
+    let x = outside(point.x, min.x, max.x);
+    let y = outside(point.y, min.y, max.y);
+    let z = outside(point.z, min.z, max.z);
+    (x * x + y * y + z * z).sqrt()
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/foliage/foliage.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/foliage/foliage.frag"
+    }
+}
@@ -5,37 +5,47 @@ use cgmath::Matrix4;
 use vulkano::{
     command_buffer::{RecordingCommandBuffer, SubpassBeginInfo, SubpassContents},
     render_pass::Framebuffer,
-    sync::GpuFuture,
+    sync::{GpuFuture, PipelineStage},
 };
 
 use crate::FrameSystem;
 
-use super::pass::{DrawPass, LightingPass, Pass};
+use super::{
+    frames_in_flight::{InFlightFrame, FRAME_END_TIMESTAMP, SCENE_END_TIMESTAMP},
+    pass::{DrawPass, LightingPass, Pass},
+    user_pass::{PassContext, PassPoint},
+};
 
 pub struct Frame<'a> {
     pub system: &'a mut FrameSystem,
     num_pass: u8,
     pub framebuffer: Arc<Framebuffer>,
+    composite_framebuffer: Arc<Framebuffer>,
     before_main_cb_future: Option<Box<dyn GpuFuture>>,
     pub command_buffer_builder: Option<RecordingCommandBuffer>,
     pub world_to_framebuffer: Matrix4<f32>,
+    pub in_flight: InFlightFrame,
 }
 
 impl<'a> Frame<'a> {
     pub fn new(
         system: &'a mut FrameSystem,
         framebuffer: Arc<Framebuffer>,
+        composite_framebuffer: Arc<Framebuffer>,
         before_main_cb_future: Option<Box<dyn GpuFuture>>,
         command_buffer_builder: Option<RecordingCommandBuffer>,
         world_to_framebuffer: Matrix4<f32>,
+        in_flight: InFlightFrame,
     ) -> Self {
         Frame {
             system,
             num_pass: 0,
             framebuffer,
+            composite_framebuffer,
             before_main_cb_future,
             command_buffer_builder,
             world_to_framebuffer,
+            in_flight,
         }
     }
 
@@ -45,29 +55,143 @@ impl<'a> Frame<'a> {
             self.num_pass += 1;
             current_pass
         } {
-            0 => Some(Pass::Deferred(DrawPass { frame: self })),
+            0 if self.system.depth_prepass() => Some(Pass::DepthPrepass(DrawPass { frame: self })),
+
+            0 => {
+                // The pre-pass subpass is still part of the render pass, step over it
+                self.advance_subpass()?;
+                self.num_pass = 2;
+                Some(Pass::Deferred(DrawPass { frame: self }))
+            }
 
             1 => {
-                self.command_buffer_builder
-                    .as_mut()
-                    .context("command buffer builder")?
-                    .next_subpass(
-                        Default::default(),
-                        SubpassBeginInfo {
-                            contents: SubpassContents::SecondaryCommandBuffers,
-                            ..Default::default()
-                        },
-                    )
-                    .context("advancing to next subpass")?;
-                Some(Pass::Lighting(LightingPass { frame: self }))
+                self.advance_subpass()?;
+                Some(Pass::Deferred(DrawPass { frame: self }))
             }
 
             2 => {
-                self.command_buffer_builder
+                self.advance_subpass()?;
+                Some(Pass::Lighting(LightingPass { frame: self }))
+            }
+
+            3 => {
+                let builder = self
+                    .command_buffer_builder
                     .as_mut()
-                    .context("getting command buffer builder")?
+                    .context("getting command buffer builder")?;
+                builder
                     .end_render_pass(Default::default())
                     .context("ending render pass")?;
+                if let Some(pool) = &self.in_flight.timestamp_pool {
+                    unsafe {
+                        builder
+                            .write_timestamp(
+                                pool.clone(),
+                                SCENE_END_TIMESTAMP,
+                                PipelineStage::BottomOfPipe,
+                            )
+                            .context("writing scene end timestamp")?;
+                    }
+                }
+                let user_passes = self.system.user_passes.record(PassContext {
+                    point: PassPoint::AfterScene,
+                    queue: &self.system.gfx_queue,
+                    command_buffer_allocator: &self.in_flight.command_buffer_allocator,
+                    frame_index: self.in_flight.index,
+                    subpass: None,
+                    extent: self.framebuffer.extent(),
+                    view_projection: self.world_to_framebuffer,
+                    depth: &self.system.depth_buffer,
+                    target: Some(&self.system.hdr_buffer),
+                })?;
+                for cb in user_passes {
+                    builder
+                        .execute_commands(cb)
+                        .context("executing user pass")?;
+                }
+                let scene = if self.system.ssgi.enabled() {
+                    self.system
+                        .ssgi
+                        .record(
+                            builder,
+                            &self.system.hdr_buffer,
+                            &self.system.depth_buffer,
+                            &self.system.normals_buffer,
+                            &self.system.material_buffer,
+                            &self.system.diffuse_buffer,
+                            &self.system.environment,
+                        )
+                        .context("recording SSGI")?
+                } else {
+                    self.system.hdr_buffer.clone()
+                };
+                let scene = if self.system.ssr.enabled() {
+                    self.system
+                        .ssr
+                        .record(
+                            builder,
+                            &scene,
+                            &self.system.depth_buffer,
+                            &self.system.normals_buffer,
+                            &self.system.material_buffer,
+                            &self.system.diffuse_buffer,
+                            &self.system.environment,
+                        )
+                        .context("recording SSR")?
+                } else {
+                    scene
+                };
+                let scene = match &self.system.velocity_buffer {
+                    Some(velocity_buffer) if self.system.taa.enabled() => self
+                        .system
+                        .taa
+                        .record(builder, &scene, velocity_buffer, &self.system.depth_buffer)
+                        .context("recording TAA resolve")?,
+                    _ => scene,
+                };
+                let scene = match &self.system.velocity_buffer {
+                    Some(velocity_buffer) if self.system.motion_blur.enabled() => self
+                        .system
+                        .motion_blur
+                        .record(builder, &scene, velocity_buffer)
+                        .context("recording motion blur")?,
+                    _ => scene,
+                };
+                let sprites = &self.system.sprites;
+                let outline = &self.system.outline;
+                let billboards = &self.system.billboards;
+                let depth_buffer = &self.system.depth_buffer;
+                let debug_draw = &self.system.debug_draw;
+                let hud = &self.system.hud;
+                self.system
+                    .post_process
+                    .record(
+                        builder,
+                        &scene,
+                        self.composite_framebuffer.clone(),
+                        |builder| {
+                            sprites.record(builder, depth_buffer)?;
+                            outline.record(builder)?;
+                            billboards.record(builder, depth_buffer)?;
+                            debug_draw.record(builder)?;
+                            hud.record(builder)
+                        },
+                    )
+                    .context("recording post process")?;
+
+                if let Some(pool) = &self.in_flight.timestamp_pool {
+                    unsafe {
+                        self.command_buffer_builder
+                            .as_mut()
+                            .context("getting command buffer builder")?
+                            .write_timestamp(
+                                pool.clone(),
+                                FRAME_END_TIMESTAMP,
+                                PipelineStage::BottomOfPipe,
+                            )
+                            .context("writing frame end timestamp")?;
+                    }
+                }
 
                 let command_buffer = self
                     .command_buffer_builder
@@ -91,4 +215,19 @@ impl<'a> Frame<'a> {
 
         Ok(ret)
     }
+
+    fn advance_subpass(&mut self) -> anyhow::Result<()> {
+        self.command_buffer_builder
+            .as_mut()
+            .context("command buffer builder")?
+            .next_subpass(
+                Default::default(),
+                SubpassBeginInfo {
+                    contents: SubpassContents::SecondaryCommandBuffers,
+                    ..Default::default()
+                },
+            )
+            .context("advancing to next subpass")?;
+        Ok(())
+    }
 }
@@ -5,90 +5,140 @@ use cgmath::Matrix4;
 
 use vulkano::{
     command_buffer::{
-        allocator::StandardCommandBufferAllocator, CommandBufferBeginInfo, CommandBufferLevel,
-        CommandBufferUsage, RecordingCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo,
-        SubpassContents,
+        CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage, RecordingCommandBuffer,
+        RenderPassBeginInfo, SubpassBeginInfo, SubpassContents,
     },
-    descriptor_set::allocator::StandardDescriptorSetAllocator,
     device::Queue,
-    format::Format,
-    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
-    memory::allocator::{
-        AllocationCreateInfo, FreeListAllocator, GenericMemoryAllocator, StandardMemoryAllocator,
-    },
+    format::{Format, NumericFormat},
+    image::{sampler::Sampler, view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
     render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
-    sync::GpuFuture,
+    swapchain::ColorSpace,
+    sync::{GpuFuture, PipelineStage},
+};
+
+use super::{
+    billboard::Billboards,
+    debug_draw::DebugDraw,
+    descriptor_cache::DescriptorSetCache,
+    frame::Frame,
+    frames_in_flight::{InFlightFrame, FRAME_END_TIMESTAMP, FRAME_START_TIMESTAMP},
+    hud::Hud,
+    ibl::EnvironmentMaps,
+    lighting::{self, PointLight},
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    motion_blur::MotionBlur,
+    outline::Outline,
+    post_process::PostProcess,
+    shadows::CascadedShadows,
+    sprite::Sprites,
+    ssgi::Ssgi,
+    ssr::Ssr,
+    taa::Taa,
+    user_pass::{PassPoint, UserPass, UserPassId, UserPasses},
+    vulkan_context::{DebugNamer, VulkanContext},
 };
 
-use super::{frame::Frame, lighting};
+/// Exposure of a dim overcast day, suiting lights of a few hundred lux or tens of thousands of
+/// lumens.
+const DEFAULT_EV100: f32 = 10.0;
 
 pub struct FrameSystem {
     pub gfx_queue: Arc<Queue>,
     memory_allocator: Arc<StandardMemoryAllocator>,
-    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    debug_namer: DebugNamer,
+    depth_prepass: bool,
+    srgb_colors: bool,
+    render_scale: f32,
+    /// Multiplies the lights' photometric intensities, see `exposure_from_ev100`.
+    exposure: f32,
 
     render_pass: Arc<RenderPass>,
+    memory_tracker: MemoryTracker,
+    render_target_memory: Vec<TrackedMemory>,
 
+    /// The lit scene, composited into the output image by `post_process`.
+    pub hdr_buffer: Arc<ImageView>,
     pub diffuse_buffer: Arc<ImageView>,
     pub normals_buffer: Arc<ImageView>,
+    /// Metallic in red, roughness in green.
+    pub material_buffer: Arc<ImageView>,
+    /// Linear emitted light, added by the ambient light.
+    pub emissive_buffer: Arc<ImageView>,
+    /// Screen space motion of each pixel since the previous frame, in UV units. `None` when the
+    /// renderer was created without motion vectors.
+    pub velocity_buffer: Option<Arc<ImageView>>,
     pub depth_buffer: Arc<ImageView>,
 
+    /// Lights the scene through the ambient light.
+    pub environment: EnvironmentMaps,
+
+    /// Drawn before the frame, sampled by the directional light.
+    pub shadows: CascadedShadows,
     pub ambient_lighting_system: lighting::Ambient,
     pub directional_lighting_system: lighting::Directional,
     pub point_lighting_system: lighting::Point,
+    /// Fills what the scene doesn't cover.
+    pub sky_system: lighting::Sky,
+    /// Adds screen-space GI to the HDR target when enabled, before the reflections.
+    pub ssgi: Ssgi,
+    /// Adds screen-space reflections to the HDR target when enabled.
+    pub ssr: Ssr,
+    /// Resolves the HDR target before the post process when enabled.
+    pub taa: Taa,
+    /// Blurs the resolved scene along the velocity buffer when enabled.
+    pub motion_blur: MotionBlur,
+    pub post_process: PostProcess,
+    /// Draws queued sprite meshes, e.g. tilemaps, over the composited image.
+    pub sprites: Sprites,
+    /// Outlines the selected objects over the sprites.
+    pub outline: Outline,
+    /// Draws queued billboards over the outlines.
+    pub billboards: Billboards,
+    /// Draws queued lines over the billboards.
+    pub debug_draw: DebugDraw,
+    /// Drawn over the composited image and outlines, with the game's UI underneath.
+    pub hud: Hud,
+    /// Recorded at their points of the frame, see `add_user_pass`.
+    pub user_passes: UserPasses,
 }
 
 impl FrameSystem {
+    /// `default_atlas` is sampled by billboards and UI primitives until they're given atlases.
     pub fn new(
-        gfx_queue: Arc<Queue>,
+        context: &VulkanContext,
         image_format: Format,
-        memory_allocator: Arc<GenericMemoryAllocator<FreeListAllocator>>,
-        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        color_space: ColorSpace,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+        environment: EnvironmentMaps,
+        default_atlas: (Arc<ImageView>, Arc<Sampler>),
+        frames_in_flight: usize,
+        motion_vectors: bool,
     ) -> anyhow::Result<Self> {
-        let render_pass = vulkano::ordered_passes_renderpass!(
-            gfx_queue.device().clone(),
-            attachments: {
-                final_color: {
-                    format: image_format,
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: Store,
-                },
-                diffuse: {
-                    format: Format::A2B10G10R10_UNORM_PACK32,
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: DontCare,
-                },
-                normals: {
+        let gfx_queue = context.graphics_queue().clone();
+        let memory_allocator = context.memory_allocator().clone();
+
+        let render_pass = create_render_pass(&gfx_queue, motion_vectors)?;
+
+        // create temp images that will be recreated when frame() is called
+        let hdr_buffer = ImageView::new_default(
+            Image::new(
+                memory_allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
                     format: Format::R16G16B16A16_SFLOAT,
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: DontCare,
-                },
-                depth_stencil: {
-                    format: Format::D16_UNORM,
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: DontCare,
-                },
-            },
-            passes: [
-                {
-                    color: [diffuse, normals],
-                    depth_stencil: {depth_stencil},
-                    input: [],
-                },
-                {
-                    color: [final_color],
-                    depth_stencil: {},
-                    input: [diffuse, normals, depth_stencil],
+                    extent: [1, 1, 1],
+                    usage: ImageUsage::COLOR_ATTACHMENT
+                        | ImageUsage::SAMPLED
+                        | ImageUsage::TRANSFER_SRC,
+                    ..Default::default()
                 },
-            ],
+                AllocationCreateInfo::default(),
+            )
+            .context("creating initial hdr buffer image")?,
         )
-        .context("creating RenderPass")?;
+        .context("creating initial hdr buffer image view")?;
 
-        // create temp images that will be recreated when frame() is called
         let diffuse_buffer = ImageView::new_default(
             Image::new(
                 memory_allocator.clone(),
@@ -97,8 +147,8 @@ impl FrameSystem {
                     format: Format::A2B10G10R10_UNORM_PACK32,
                     extent: [1, 1, 1],
                     usage: ImageUsage::COLOR_ATTACHMENT
-                        | ImageUsage::TRANSIENT_ATTACHMENT
-                        | ImageUsage::INPUT_ATTACHMENT,
+                        | ImageUsage::INPUT_ATTACHMENT
+                        | ImageUsage::SAMPLED,
                     ..Default::default()
                 },
                 AllocationCreateInfo::default(),
@@ -114,7 +164,9 @@ impl FrameSystem {
                     image_type: ImageType::Dim2d,
                     format: Format::R16G16B16A16_SFLOAT,
                     extent: [1, 1, 1],
-                    usage: ImageUsage::TRANSIENT_ATTACHMENT | ImageUsage::INPUT_ATTACHMENT,
+                    usage: ImageUsage::COLOR_ATTACHMENT
+                        | ImageUsage::INPUT_ATTACHMENT
+                        | ImageUsage::SAMPLED,
                     ..Default::default()
                 },
                 AllocationCreateInfo::default(),
@@ -123,6 +175,46 @@ impl FrameSystem {
         )
         .context("creating initial normals buffer image view")?;
 
+        let material_buffer = ImageView::new_default(
+            Image::new(
+                memory_allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format: Format::R8G8B8A8_UNORM,
+                    extent: [1, 1, 1],
+                    usage: ImageUsage::COLOR_ATTACHMENT
+                        | ImageUsage::INPUT_ATTACHMENT
+                        | ImageUsage::SAMPLED,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .context("creating initial material buffer image")?,
+        )
+        .context("creating initial material buffer image view")?;
+
+        let emissive_buffer = ImageView::new_default(
+            Image::new(
+                memory_allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format: Format::R16G16B16A16_SFLOAT,
+                    extent: [1, 1, 1],
+                    usage: ImageUsage::COLOR_ATTACHMENT
+                        | ImageUsage::TRANSIENT_ATTACHMENT
+                        | ImageUsage::INPUT_ATTACHMENT,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .context("creating initial emissive buffer image")?,
+        )
+        .context("creating initial emissive buffer image view")?;
+
+        let velocity_buffer = motion_vectors
+            .then(|| create_velocity_buffer(&memory_allocator, [1, 1, 1]))
+            .transpose()?;
+
         let depth_buffer = ImageView::new_default(
             Image::new(
                 memory_allocator.clone(),
@@ -130,7 +222,9 @@ impl FrameSystem {
                     image_type: ImageType::Dim2d,
                     format: Format::D16_UNORM,
                     extent: [1, 1, 1],
-                    usage: ImageUsage::TRANSIENT_ATTACHMENT | ImageUsage::INPUT_ATTACHMENT,
+                    usage: ImageUsage::INPUT_ATTACHMENT
+                        | ImageUsage::SAMPLED
+                        | ImageUsage::TRANSFER_SRC,
                     ..Default::default()
                 },
                 AllocationCreateInfo::default(),
@@ -139,66 +233,171 @@ impl FrameSystem {
         )
         .context("creating initial depth buffer image view")?;
 
-        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
-            gfx_queue.device().clone(),
-            Default::default(),
-        ));
+        let lighting_subpass = Subpass::from(render_pass.clone(), 2).unwrap();
 
-        let lighting_subpass = Subpass::from(render_pass.clone(), 1).unwrap();
+        let shadows =
+            CascadedShadows::new(context, frames_in_flight).context("creating shadows")?;
 
         let ambient_lighting_system = lighting::Ambient::new(
-            gfx_queue.clone(),
+            context,
             lighting_subpass.clone(),
-            memory_allocator.clone(),
-            command_buffer_allocator.clone(),
-            descriptor_set_allocator.clone(),
+            descriptor_set_cache.clone(),
         )
         .context("creating ambient lighting system")?;
 
         let directional_lighting_system = lighting::Directional::new(
-            gfx_queue.clone(),
+            context,
             lighting_subpass.clone(),
-            memory_allocator.clone(),
-            command_buffer_allocator.clone(),
-            descriptor_set_allocator.clone(),
+            descriptor_set_cache.clone(),
         )
         .context("creating directional lighting system")?;
 
+        let sky_system = lighting::Sky::new(
+            context,
+            lighting_subpass.clone(),
+            descriptor_set_cache.clone(),
+        )
+        .context("creating sky")?;
+
         let point_lighting_system = lighting::Point::new(
-            gfx_queue.clone(),
+            context,
             lighting_subpass,
-            memory_allocator.clone(),
-            command_buffer_allocator.clone(),
-            descriptor_set_allocator,
+            descriptor_set_cache.clone(),
+            frames_in_flight,
         )
         .context("creating point lighting system")?;
 
-        Ok(FrameSystem {
+        let mut ssgi = Ssgi::new(context, descriptor_set_cache.clone()).context("creating SSGI")?;
+        ssgi.resize([1, 1, 1])?;
+        let mut ssr = Ssr::new(context, descriptor_set_cache.clone()).context("creating SSR")?;
+        ssr.resize([1, 1, 1])?;
+        let mut taa = Taa::new(context, descriptor_set_cache.clone()).context("creating TAA")?;
+        taa.resize([1, 1, 1])?;
+        let mut motion_blur = MotionBlur::new(context, descriptor_set_cache.clone())
+            .context("creating motion blur")?;
+        motion_blur.resize([1, 1, 1])?;
+        let post_process = PostProcess::new(context, image_format, descriptor_set_cache.clone())
+            .context("creating post process")?;
+        let sprites = Sprites::new(
+            context,
+            post_process.subpass(),
+            descriptor_set_cache.clone(),
+        )
+        .context("creating sprites")?;
+        let outline = Outline::new(
+            context,
+            post_process.subpass(),
+            descriptor_set_cache.clone(),
+        )
+        .context("creating outline")?;
+        let (atlas, atlas_sampler) = default_atlas;
+        let billboards = Billboards::new(
+            context,
+            post_process.subpass(),
+            descriptor_set_cache.clone(),
+            frames_in_flight,
+            atlas.clone(),
+            atlas_sampler.clone(),
+        )
+        .context("creating billboards")?;
+        let debug_draw = DebugDraw::new(context, post_process.subpass(), frames_in_flight)
+            .context("creating debug draw")?;
+        let hud = Hud::new(
+            context,
+            post_process.subpass(),
+            descriptor_set_cache,
+            frames_in_flight,
+            atlas,
+            atlas_sampler,
+        )
+        .context("creating HUD")?;
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(render_pass.as_ref(), "deferred render pass");
+
+        let mut frame_system = FrameSystem {
             gfx_queue,
             memory_allocator,
-            command_buffer_allocator,
+            debug_namer,
+            depth_prepass: false,
+            render_scale: 1.0,
+            exposure: lighting::exposure_from_ev100(DEFAULT_EV100),
+            // An extended linear swapchain is encoded by the compositor, like an sRGB one by the
+            // hardware
+            srgb_colors: image_format.numeric_format_color() == Some(NumericFormat::SRGB)
+                || color_space == ColorSpace::ExtendedSrgbLinear,
             render_pass,
+            memory_tracker: context.memory_tracker().clone(),
+            render_target_memory: vec![],
+            hdr_buffer,
             diffuse_buffer,
             normals_buffer,
+            material_buffer,
+            emissive_buffer,
+            velocity_buffer,
             depth_buffer,
+            environment,
+            shadows,
             ambient_lighting_system,
+            sky_system,
             directional_lighting_system,
             point_lighting_system,
-        })
+            ssgi,
+            ssr,
+            taa,
+            motion_blur,
+            post_process,
+            sprites,
+            outline,
+            billboards,
+            debug_draw,
+            hud,
+            user_passes: UserPasses::default(),
+        };
+        frame_system.track_render_targets();
+
+        Ok(frame_system)
     }
 
+    /// Starts a frame seen through `camera`, the projection and view matrices. `point_lights` are
+    /// culled before the render pass begins and drawn by `LightingPass::point_lights`.
     pub fn frame<F>(
         &mut self,
         before_future: F,
         final_image_view: Arc<ImageView>,
-        world_to_framebuffer: Matrix4<f32>,
+        camera: (Matrix4<f32>, Matrix4<f32>),
+        point_lights: &[PointLight],
+        in_flight: InFlightFrame,
     ) -> anyhow::Result<Frame>
     where
         F: GpuFuture + 'static,
     {
-        let extent = final_image_view.image().extent();
+        profile_scope!("begin frame");
+        let extent = self.render_extent(final_image_view.image().extent());
+        let (projection, view) = camera;
+        let world_to_framebuffer = projection * view;
+        self.ssgi.begin_frame(projection, view);
+        self.ssr.begin_frame(projection, view);
 
         if self.diffuse_buffer.image().extent() != extent {
+            self.hdr_buffer = ImageView::new_default(
+                Image::new(
+                    self.memory_allocator.clone(),
+                    ImageCreateInfo {
+                        extent,
+                        format: Format::R16G16B16A16_SFLOAT,
+                        // Read by the frame analysis tools before tonemapping
+                        usage: ImageUsage::COLOR_ATTACHMENT
+                            | ImageUsage::SAMPLED
+                            | ImageUsage::TRANSFER_SRC,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo::default(),
+                )
+                .context("creating new hdr buffer")?,
+            )
+            .context("creating new hdr buffer image view")?;
+
             self.diffuse_buffer = ImageView::new_default(
                 Image::new(
                     self.memory_allocator.clone(),
@@ -206,8 +405,8 @@ impl FrameSystem {
                         extent,
                         format: Format::A2B10G10R10_UNORM_PACK32,
                         usage: ImageUsage::COLOR_ATTACHMENT
-                            | ImageUsage::TRANSIENT_ATTACHMENT
-                            | ImageUsage::INPUT_ATTACHMENT,
+                            | ImageUsage::INPUT_ATTACHMENT
+                            | ImageUsage::SAMPLED,
                         ..Default::default()
                     },
                     AllocationCreateInfo::default(),
@@ -223,8 +422,8 @@ impl FrameSystem {
                         extent,
                         format: Format::R16G16B16A16_SFLOAT,
                         usage: ImageUsage::COLOR_ATTACHMENT
-                            | ImageUsage::TRANSIENT_ATTACHMENT
-                            | ImageUsage::INPUT_ATTACHMENT,
+                            | ImageUsage::INPUT_ATTACHMENT
+                            | ImageUsage::SAMPLED,
                         ..Default::default()
                     },
                     AllocationCreateInfo::default(),
@@ -233,6 +432,45 @@ impl FrameSystem {
             )
             .context("creating new normals buffer image view")?;
 
+            self.material_buffer = ImageView::new_default(
+                Image::new(
+                    self.memory_allocator.clone(),
+                    ImageCreateInfo {
+                        extent,
+                        format: Format::R8G8B8A8_UNORM,
+                        usage: ImageUsage::COLOR_ATTACHMENT
+                            | ImageUsage::INPUT_ATTACHMENT
+                            | ImageUsage::SAMPLED,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo::default(),
+                )
+                .context("creating new material buffer")?,
+            )
+            .context("creating new material buffer image view")?;
+
+            self.emissive_buffer = ImageView::new_default(
+                Image::new(
+                    self.memory_allocator.clone(),
+                    ImageCreateInfo {
+                        extent,
+                        format: Format::R16G16B16A16_SFLOAT,
+                        usage: ImageUsage::COLOR_ATTACHMENT
+                            | ImageUsage::TRANSIENT_ATTACHMENT
+                            | ImageUsage::INPUT_ATTACHMENT,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo::default(),
+                )
+                .context("creating new emissive buffer")?,
+            )
+            .context("creating new emissive buffer image view")?;
+
+            if self.velocity_buffer.is_some() {
+                self.velocity_buffer =
+                    Some(create_velocity_buffer(&self.memory_allocator, extent)?);
+            }
+
             self.depth_buffer = ImageView::new_default(
                 Image::new(
                     self.memory_allocator.clone(),
@@ -240,8 +478,9 @@ impl FrameSystem {
                         extent,
                         format: Format::D16_UNORM,
                         usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT
-                            | ImageUsage::TRANSIENT_ATTACHMENT
-                            | ImageUsage::INPUT_ATTACHMENT,
+                            | ImageUsage::INPUT_ATTACHMENT
+                            | ImageUsage::SAMPLED
+                            | ImageUsage::TRANSFER_SRC,
                         ..Default::default()
                     },
                     AllocationCreateInfo::default(),
@@ -249,24 +488,60 @@ impl FrameSystem {
                 .context("creating new depth buffer")?,
             )
             .context("creating new depth buffer image view")?;
+
+            self.ssgi.resize(extent)?;
+            self.ssr.resize(extent)?;
+            self.taa.resize(extent)?;
+            self.motion_blur.resize(extent)?;
+            self.post_process.resize(extent)?;
+            self.name_gbuffer();
+            self.track_render_targets();
         }
 
+        let attachments = [
+            Some(self.hdr_buffer.clone()),
+            Some(self.diffuse_buffer.clone()),
+            Some(self.normals_buffer.clone()),
+            Some(self.material_buffer.clone()),
+            Some(self.emissive_buffer.clone()),
+            self.velocity_buffer.clone(),
+            Some(self.depth_buffer.clone()),
+        ];
         let framebuffer = Framebuffer::new(
             self.render_pass.clone(),
             FramebufferCreateInfo {
-                attachments: vec![
-                    final_image_view,
-                    self.diffuse_buffer.clone(),
-                    self.normals_buffer.clone(),
-                    self.depth_buffer.clone(),
-                ],
+                attachments: attachments.into_iter().flatten().collect(),
                 ..Default::default()
             },
         )
         .context("creating framebuffer")?;
+        let composite_framebuffer = self.post_process.framebuffer(final_image_view)?;
+
+        let point_lights: Vec<PointLight> = point_lights
+            .iter()
+            .map(|light| PointLight {
+                color: self.linear_color(light.color),
+                ..*light
+            })
+            .collect();
+        let before_future: Box<dyn GpuFuture> = match self.point_lighting_system.cull(
+            &in_flight,
+            [extent[0], extent[1]],
+            &point_lights,
+            view,
+            projection,
+            self.exposure,
+        )? {
+            Some(cb) => Box::new(
+                before_future
+                    .then_execute(self.gfx_queue.clone(), cb)
+                    .context("executing point light culling")?,
+            ),
+            None => Box::new(before_future),
+        };
 
         let mut command_buffer_builder = RecordingCommandBuffer::new(
-            self.command_buffer_allocator.clone(),
+            in_flight.command_buffer_allocator.clone(),
             self.gfx_queue.queue_family_index(),
             CommandBufferLevel::Primary,
             CommandBufferBeginInfo {
@@ -276,15 +551,28 @@ impl FrameSystem {
         )
         .context("creating primary command buffer")?;
 
+        if let Some(pool) = &in_flight.timestamp_pool {
+            unsafe {
+                command_buffer_builder
+                    .reset_query_pool(pool.clone(), FRAME_START_TIMESTAMP..FRAME_END_TIMESTAMP + 1)
+                    .context("resetting frame timestamps")?
+                    .write_timestamp(
+                        pool.clone(),
+                        FRAME_START_TIMESTAMP,
+                        PipelineStage::TopOfPipe,
+                    )
+                    .context("writing frame start timestamp")?;
+            }
+        }
+
         command_buffer_builder
             .begin_render_pass(
                 RenderPassBeginInfo {
-                    clear_values: vec![
-                        Some([0.0, 0.0, 0.0, 0.0].into()),
-                        Some([0.0, 0.0, 0.0, 0.0].into()),
-                        Some([0.0, 0.0, 0.0, 0.0].into()),
-                        Some(1.0f32.into()),
-                    ],
+                    // Every color attachment clears to zero, depth to the far plane
+                    clear_values: (0..framebuffer.attachments().len() - 1)
+                        .map(|_| Some([0.0, 0.0, 0.0, 0.0].into()))
+                        .chain([Some(1.0f32.into())])
+                        .collect(),
                     ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
                 },
                 SubpassBeginInfo {
@@ -297,14 +585,295 @@ impl FrameSystem {
         Ok(Frame::new(
             self,
             framebuffer,
-            Some(Box::new(before_future)),
+            composite_framebuffer,
+            Some(before_future),
             Some(command_buffer_builder),
             world_to_framebuffer,
+            in_flight,
         ))
     }
 
+    /// Replaces the tracked memory of the previous G-buffer images with the current ones.
+    fn track_render_targets(&mut self) {
+        self.render_target_memory = [
+            &self.hdr_buffer,
+            &self.diffuse_buffer,
+            &self.normals_buffer,
+            &self.material_buffer,
+            &self.emissive_buffer,
+            &self.depth_buffer,
+        ]
+        .into_iter()
+        .chain(&self.velocity_buffer)
+        .map(|view| {
+            self.memory_tracker
+                .track_image(MemoryCategory::RenderTargets, view.image())
+        })
+        .collect();
+    }
+
+    fn name_gbuffer(&self) {
+        self.debug_namer
+            .name(self.hdr_buffer.image().as_ref(), "hdr target");
+        self.debug_namer
+            .name(self.diffuse_buffer.image().as_ref(), "g-buffer diffuse");
+        self.debug_namer
+            .name(self.normals_buffer.image().as_ref(), "g-buffer normals");
+        self.debug_namer
+            .name(self.material_buffer.image().as_ref(), "g-buffer material");
+        self.debug_namer
+            .name(self.emissive_buffer.image().as_ref(), "g-buffer emissive");
+        if let Some(velocity_buffer) = &self.velocity_buffer {
+            self.debug_namer
+                .name(velocity_buffer.image().as_ref(), "g-buffer velocity");
+        }
+        self.debug_namer
+            .name(self.depth_buffer.image().as_ref(), "g-buffer depth");
+    }
+
+    /// Whether frames run the depth pre-pass before filling the G-buffer.
+    pub fn depth_prepass(&self) -> bool {
+        self.depth_prepass
+    }
+
+    /// Toggles the depth pre-pass, which lays down depth with a position-only pipeline so the
+    /// G-buffer pass only shades visible fragments. Worth it when overdraw is high.
+    pub fn set_depth_prepass(&mut self, enabled: bool) {
+        self.depth_prepass = enabled;
+    }
+
+    /// Fraction of the output resolution the scene is rendered at.
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Renders the scene into targets `scale` times the size of the output image, clamped to
+    /// `0.25..=1.0`, which the post process upscales. The targets are recreated on the next
+    /// frame.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.render_scale = scale.clamp(0.25, 1.0);
+    }
+
+    /// The size of the scene's render targets for an output image of `extent`.
+    pub fn render_extent(&self, extent: [u32; 3]) -> [u32; 3] {
+        let scale = |size: u32| ((size as f32 * self.render_scale).round() as u32).max(1);
+        [scale(extent[0]), scale(extent[1]), extent[2]]
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Exposes the scene for `ev100`, the exposure value at ISO 100. Higher values darken it,
+    /// for brighter lights.
+    pub fn set_exposure_ev100(&mut self, ev100: f32) {
+        self.exposure = lighting::exposure_from_ev100(ev100);
+    }
+
+    /// Whether the output image is sRGB encoded. When it is, colors passed to the renderer are
+    /// taken to be sRGB and converted to linear before shading.
+    pub fn srgb_colors(&self) -> bool {
+        self.srgb_colors
+    }
+
+    /// Converts a color passed to the renderer into the linear space lighting happens in.
+    pub fn linear_color(&self, color: [f32; 3]) -> [f32; 3] {
+        if self.srgb_colors {
+            color.map(srgb_to_linear)
+        } else {
+            color
+        }
+    }
+
+    /// Records `pass` at `point` of every frame from the next one on, after the passes added at
+    /// that point before it.
+    pub fn add_user_pass(&mut self, point: PassPoint, pass: impl UserPass + 'static) -> UserPassId {
+        self.user_passes.add(point, Box::new(pass))
+    }
+
+    pub fn remove_user_pass(&mut self, id: UserPassId) -> Option<Box<dyn UserPass>> {
+        self.user_passes.remove(id)
+    }
+
     #[inline]
-    pub fn deferred_subpass(&self) -> Subpass {
+    pub fn depth_prepass_subpass(&self) -> Subpass {
         Subpass::from(self.render_pass.clone(), 0).unwrap()
     }
+
+    #[inline]
+    pub fn deferred_subpass(&self) -> Subpass {
+        Subpass::from(self.render_pass.clone(), 1).unwrap()
+    }
+}
+
+/// The deferred render pass, with a velocity attachment after emissive when `motion_vectors` is
+/// set. The macro can't leave an attachment out conditionally, hence the two copies.
+fn create_render_pass(
+    gfx_queue: &Arc<Queue>,
+    motion_vectors: bool,
+) -> anyhow::Result<Arc<RenderPass>> {
+    let render_pass = if motion_vectors {
+        vulkano::ordered_passes_renderpass!(
+            gfx_queue.device().clone(),
+            attachments: {
+                // Stored for the post process, which runs after the render pass
+                hdr: {
+                    format: Format::R16G16B16A16_SFLOAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                // Diffuse, normals and material are stored for SSR
+                diffuse: {
+                    format: Format::A2B10G10R10_UNORM_PACK32,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                normals: {
+                    format: Format::R16G16B16A16_SFLOAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                material: {
+                    format: Format::R8G8B8A8_UNORM,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                emissive: {
+                    format: Format::R16G16B16A16_SFLOAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: DontCare,
+                },
+                // Stored for TAA, cleared to no motion where nothing is drawn
+                velocity: {
+                    format: Format::R16G16_SFLOAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                // Stored so the frame analysis tools can read it back after the render pass
+                depth_stencil: {
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+            },
+            passes: [
+                // Depth pre-pass, left empty when it's disabled
+                {
+                    color: [],
+                    depth_stencil: {depth_stencil},
+                    input: [],
+                },
+                {
+                    color: [diffuse, normals, material, emissive, velocity],
+                    depth_stencil: {depth_stencil},
+                    input: [],
+                },
+                {
+                    color: [hdr],
+                    depth_stencil: {},
+                    input: [diffuse, normals, material, depth_stencil, emissive],
+                },
+            ],
+        )
+    } else {
+        vulkano::ordered_passes_renderpass!(
+            gfx_queue.device().clone(),
+            attachments: {
+                // Stored for the post process, which runs after the render pass
+                hdr: {
+                    format: Format::R16G16B16A16_SFLOAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                // Diffuse, normals and material are stored for SSR
+                diffuse: {
+                    format: Format::A2B10G10R10_UNORM_PACK32,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                normals: {
+                    format: Format::R16G16B16A16_SFLOAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                material: {
+                    format: Format::R8G8B8A8_UNORM,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                emissive: {
+                    format: Format::R16G16B16A16_SFLOAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: DontCare,
+                },
+                // Stored so the frame analysis tools can read it back after the render pass
+                depth_stencil: {
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+            },
+            passes: [
+                // Depth pre-pass, left empty when it's disabled
+                {
+                    color: [],
+                    depth_stencil: {depth_stencil},
+                    input: [],
+                },
+                {
+                    color: [diffuse, normals, material, emissive],
+                    depth_stencil: {depth_stencil},
+                    input: [],
+                },
+                {
+                    color: [hdr],
+                    depth_stencil: {},
+                    input: [diffuse, normals, material, depth_stencil, emissive],
+                },
+            ],
+        )
+    };
+    render_pass.context("creating RenderPass")
+}
+
+fn create_velocity_buffer(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    extent: [u32; 3],
+) -> anyhow::Result<Arc<ImageView>> {
+    ImageView::new_default(
+        Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16_SFLOAT,
+                extent,
+                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating velocity buffer")?,
+    )
+    .context("creating velocity buffer image view")
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
@@ -0,0 +1,184 @@
+use std::{any::Any, sync::Arc};
+
+use anyhow::Context;
+use vulkano::{
+    command_buffer::allocator::{
+        StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo,
+    },
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::{future::FenceSignalFuture, GpuFuture},
+};
+
+use super::vulkan_context::VulkanContext;
+
+pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+/// Queries written at the start and end of a frame's primary command buffer.
+pub const FRAME_START_TIMESTAMP: u32 = 0;
+/// Written after the deferred render pass ends, before the post process.
+pub const SCENE_END_TIMESTAMP: u32 = 1;
+pub const FRAME_END_TIMESTAMP: u32 = 2;
+
+/// GPU times of the most recently completed frame, in milliseconds.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GpuTimes {
+    pub frame_ms: f32,
+    /// The deferred render pass: depth pre-pass, geometry and lighting.
+    pub scene_ms: f32,
+    pub post_process_ms: f32,
+}
+
+/// The resources of the frame slot currently being recorded.
+#[derive(Clone)]
+pub struct InFlightFrame {
+    pub index: usize,
+    pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    /// `None` when the graphics queue doesn't support timestamps.
+    pub timestamp_pool: Option<Arc<QueryPool>>,
+}
+
+struct Slot {
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    timestamp_pool: Option<Arc<QueryPool>>,
+    fence: Option<Arc<FenceSignalFuture<Box<dyn GpuFuture>>>>,
+    /// Replaced resources the slot's frame may still use, dropped once its fence has signaled.
+    retired: Vec<Box<dyn Any>>,
+}
+
+/// Tracks the frames the GPU may still be working on.
+///
+/// Each slot owns a command buffer allocator and the fence of the last submission that used it.
+/// `begin_frame` waits on that fence before handing the slot out again, so anything a render
+/// system indexes by `InFlightFrame::index` can be overwritten without stalling the other frames.
+pub struct FramesInFlight {
+    slots: Vec<Slot>,
+    current: usize,
+    timestamp_period: f32,
+    last_gpu_times: Option<GpuTimes>,
+}
+
+impl FramesInFlight {
+    pub fn new(context: &VulkanContext, count: usize) -> anyhow::Result<Self> {
+        let queue = context.graphics_queue();
+        let device = context.device();
+        let physical_device = device.physical_device();
+        let supports_timestamps = physical_device.queue_family_properties()
+            [queue.queue_family_index() as usize]
+            .timestamp_valid_bits
+            .is_some();
+
+        let slots = (0..count.clamp(1, MAX_FRAMES_IN_FLIGHT))
+            .map(|_| {
+                let timestamp_pool = if supports_timestamps {
+                    Some(
+                        QueryPool::new(
+                            device.clone(),
+                            QueryPoolCreateInfo {
+                                query_count: FRAME_END_TIMESTAMP + 1,
+                                ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                            },
+                        )
+                        .context("creating timestamp query pool")?,
+                    )
+                } else {
+                    None
+                };
+
+                Ok(Slot {
+                    command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                        device.clone(),
+                        StandardCommandBufferAllocatorCreateInfo {
+                            secondary_buffer_count: 32,
+                            ..Default::default()
+                        },
+                    )),
+                    timestamp_pool,
+                    fence: None,
+                    retired: vec![],
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(FramesInFlight {
+            slots,
+            current: 0,
+            timestamp_period: physical_device.properties().timestamp_period,
+            last_gpu_times: None,
+        })
+    }
+
+    pub fn count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// GPU times of the most recently completed frame, read back from the timestamps of the slot
+    /// handed out by the last `begin_frame`.
+    pub fn last_gpu_times(&self) -> Option<GpuTimes> {
+        self.last_gpu_times
+    }
+
+    /// Waits until the GPU is done with the current slot and returns its resources.
+    pub fn begin_frame(&mut self) -> anyhow::Result<InFlightFrame> {
+        let slot = &mut self.slots[self.current];
+
+        if let Some(fence) = slot.fence.take() {
+            fence.wait(None).context("waiting on frame in flight")?;
+
+            // The queries are only reset once a frame has been recorded with this slot, so
+            // they're only read back after its fence
+            if let Some(pool) = &slot.timestamp_pool {
+                let mut ticks = [0u64; FRAME_END_TIMESTAMP as usize + 1];
+                if pool
+                    .get_results(
+                        FRAME_START_TIMESTAMP..FRAME_END_TIMESTAMP + 1,
+                        &mut ticks,
+                        QueryResultFlags::empty(),
+                    )
+                    .unwrap_or(false)
+                {
+                    let period = self.timestamp_period as f64;
+                    let ms = |from: u32, to: u32| {
+                        let elapsed = ticks[to as usize].saturating_sub(ticks[from as usize]);
+                        (elapsed as f64 * period / 1_000_000.0) as f32
+                    };
+                    self.last_gpu_times = Some(GpuTimes {
+                        frame_ms: ms(FRAME_START_TIMESTAMP, FRAME_END_TIMESTAMP),
+                        scene_ms: ms(FRAME_START_TIMESTAMP, SCENE_END_TIMESTAMP),
+                        post_process_ms: ms(SCENE_END_TIMESTAMP, FRAME_END_TIMESTAMP),
+                    });
+                }
+            }
+        }
+        slot.retired.clear();
+
+        Ok(InFlightFrame {
+            index: self.current,
+            command_buffer_allocator: slot.command_buffer_allocator.clone(),
+            timestamp_pool: slot.timestamp_pool.clone(),
+        })
+    }
+
+    /// Keeps `resource` alive until the GPU has finished every frame submitted so far, for
+    /// resources replaced between frames that descriptor sets or caches may still point at. Must
+    /// not be called while a frame is being recorded.
+    pub fn retire(&mut self, resource: impl Any) {
+        // The last submitted frame finishes after all the others
+        let last = (self.current + self.slots.len() - 1) % self.slots.len();
+        self.slots[last].retired.push(Box::new(resource));
+    }
+
+    /// Fences the current slot on `future` and advances to the next slot. The returned future
+    /// should be presented in place of `future`.
+    pub fn end_frame(&mut self, future: Box<dyn GpuFuture>) -> anyhow::Result<Box<dyn GpuFuture>> {
+        let fence = Arc::new(
+            future
+                .then_signal_fence_and_flush()
+                .context("signaling frame fence")?,
+        );
+
+        self.slots[self.current].fence = Some(fence.clone());
+        self.current = (self.current + 1) % self.slots.len();
+
+        Ok(fence.boxed())
+    }
+}
@@ -1,63 +1,251 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::Arc,
+};
 
-use anyhow::Context;
-use cgmath::Matrix4;
-use tracing::{span, Level};
+use anyhow::{bail, Context};
+use cgmath::{Matrix4, Vector3, Vector4};
 use vulkano::{
-    buffer::{
-        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
-        BufferUsage, Subbuffer,
-    },
+    buffer::{BufferUsage, Subbuffer},
     command_buffer::{
-        allocator::StandardCommandBufferAllocator, CommandBuffer, CommandBufferBeginInfo,
-        CommandBufferInheritanceInfo, CommandBufferLevel, CommandBufferUsage,
-        RecordingCommandBuffer,
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferLevel,
+        CommandBufferUsage, DrawIndexedIndirectCommand, RecordingCommandBuffer,
     },
     descriptor_set::{
-        allocator::StandardDescriptorSetAllocator, DescriptorSet, DescriptorSetsCollection,
-        WriteDescriptorSet,
+        allocator::StandardDescriptorSetAllocator, layout::DescriptorType, DescriptorBufferInfo,
+        DescriptorSet, DescriptorSetWithOffsets, WriteDescriptorSet,
     },
     device::Queue,
-    memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator},
+    image::{sampler::Sampler, view::ImageView},
+    memory::allocator::StandardMemoryAllocator,
     pipeline::{
         graphics::{
             color_blend::{ColorBlendAttachmentState, ColorBlendState},
-            depth_stencil::{DepthState, DepthStencilState},
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
             input_assembly::InputAssemblyState,
             multisample::MultisampleState,
             rasterization::RasterizationState,
-            vertex_input::{Vertex, VertexDefinition},
+            vertex_input::{VertexDefinition, VertexInputState},
             viewport::{Viewport, ViewportState},
             GraphicsPipelineCreateInfo,
         },
         layout::PipelineDescriptorSetLayoutCreateInfo,
-        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+        DynamicState, GraphicsPipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
     },
     render_pass::Subpass,
+    shader::EntryPoint,
+    DeviceSize,
 };
 
-use crate::game::Transform;
+use crate::game::{screen_to_world_ray, EntityKey, Frustum, SpatialIndex, Transform};
 
 use super::{
+    descriptor_cache::DescriptorSetCache,
+    frames_in_flight::InFlightFrame,
     geometry_shaders::{
-        fs,
+        depth_vs, fs, load_vertex_shader, mask_fs, object_id_fs, portal_fs, shadow_vs,
         vs::{self, FrameData, ObjectData},
         VertexPositionColorNormal,
     },
-    mesh::MeshBuilder,
+    gpu_culling::{CulledDraws, GpuCuller},
+    layers::{OverlayCamera, RenderLayers, MAX_OVERLAY_CAMERAS},
+    material::{BlendMode, Material, MaterialId, MaterialShader, Tint},
+    mesh::{Aabb, BasicMesh, Indices, MeshBuilder},
+    mesh_pool::MeshPool,
+    occlusion::DepthPyramid,
+    portal::{PortalLighting, MAX_PORTAL_VIEWS},
+    probe::{select_probe, ReflectionProbe, CUBE_FACES},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
     render_data::RenderData,
+    ring_buffer::RingBuffer,
+    stats::SceneStats,
+    variants::{ShaderVariants, VariantKey},
+    vertex::{MeshVertex, VertexLayout},
+    vulkan_context::{DebugNamer, VulkanContext},
 };
 
+const FRAME_DATA_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::UniformBufferDynamic);
+const OBJECT_DATA_BINDING: DescriptorBinding =
+    DescriptorBinding::new(1, 0, DescriptorType::StorageBufferDynamic);
+const BASE_COLOR_TEXTURE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(2, 0, DescriptorType::CombinedImageSampler);
+const METALLIC_ROUGHNESS_TEXTURE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(2, 1, DescriptorType::CombinedImageSampler);
+const EMISSIVE_TEXTURE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(2, 2, DescriptorType::CombinedImageSampler);
+const LIGHTMAP_TEXTURE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(2, 3, DescriptorType::CombinedImageSampler);
+
+/// Number of objects the object data ring buffer holds per frame before it has to grow.
+const INITIAL_OBJECT_CAPACITY: usize = 1024;
+
+/// The slice of the depth range overlay cameras that clear depth draw into, in front of
+/// everything but what's right against the main camera's near plane.
+const OVERLAY_DEPTH: f32 = 0.001;
+
+/// The largest `minUniformBufferOffsetAlignment` Vulkan allows, so every camera's frame data fits
+/// in a frame's region whatever the device's alignment is.
+const MAX_UNIFORM_ALIGNMENT: usize = 256;
+
 pub struct GeometrySystem {
     gfx_queue: Arc<Queue>,
     subpass: Subpass,
-    pipeline: Arc<GraphicsPipeline>,
-    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    depth_subpass: Subpass,
+    mask_subpass: Subpass,
+    object_id_subpass: Subpass,
+    shadow_subpass: Subpass,
+    portal_subpass: Subpass,
+    pipeline_layout: Arc<PipelineLayout>,
+    /// Only has the object data set, with the cascade's matrix pushed as a constant.
+    shadow_layout: Arc<PipelineLayout>,
+    /// The pipeline layout's sets, with the portal view's lighting pushed as constants.
+    portal_layout: Arc<PipelineLayout>,
+    /// The frame and object sets, with the object being drawn pushed as a constant.
+    object_id_layout: Arc<PipelineLayout>,
+    /// The G-buffer fragment shader, specialized per `MaterialFeatures`.
+    fs: ShaderVariants,
+    depth_vs: EntryPoint,
+    mask_fs: EntryPoint,
+    object_id_fs: EntryPoint,
+    shadow_vs: EntryPoint,
+    portal_fs: EntryPoint,
+    /// Created the first time a mesh with the vertex layout is created.
+    pipelines: HashMap<VertexLayout, LayoutPipelines>,
+    debug_namer: DebugNamer,
     memory_allocator: Arc<StandardMemoryAllocator>,
+    /// Holds every mesh's vertices and indices, and tracks their memory.
+    mesh_pool: MeshPool,
     render_data: RenderData,
-    storage_buffer_allocator: SubbufferAllocator,
-    uniform_buffer_allocator: SubbufferAllocator,
+    frames_in_flight: usize,
+    frame_data_ring: RingBuffer,
+    object_data_ring: RingBuffer,
+    frame_data_set: Arc<DescriptorSet>,
+    object_data_set: Arc<DescriptorSet>,
     descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    prepared_sets: Option<Vec<DescriptorSetWithOffsets>>,
+    indirect_supported: bool,
+    indirect_draws: bool,
+    srgb_colors: bool,
+    indirect_ring: RingBuffer,
+    prepared_indirect: Option<IndirectDraws>,
+    /// `None` when the device can't cull on the GPU, see `set_gpu_culling`.
+    gpu_culler: Option<GpuCuller>,
+    gpu_culling: bool,
+    prepared_culled: Option<CulledDraws>,
+    /// Where this frame's object data starts in its ring buffer.
+    object_data_offset: u32,
+    draw_calls: u32,
+    triangles: u64,
+    /// Per queued object, whether it survived occlusion culling. Empty when culling wasn't run.
+    visible: Vec<bool>,
+    occluded_objects: u32,
+    materials: Vec<GpuMaterial>,
+    /// Subpixel offset of this frame's projection in NDC units, zero unless TAA is enabled.
+    jitter: [f32; 2],
+    /// The unjittered view projection of the last frame, for motion vectors.
+    previous_view_projection: Option<Matrix4<f32>>,
+    /// Model matrices of the tracked objects drawn last frame, and of those queued this frame.
+    previous_models: HashMap<u64, Matrix4<f32>>,
+    current_models: HashMap<u64, Matrix4<f32>>,
+    /// Keys of the tracked objects drawn into the selection mask.
+    selected: HashSet<u64>,
+    /// Keys of the tracked objects that passed culling in the last drawn frame.
+    visible_keys: HashSet<u64>,
+    /// The simulation's index of entity bounds, whose entities are tracked under their ids.
+    /// Culling and picking query it for the objects it holds.
+    spatial_index: Option<Arc<SpatialIndex>>,
+    /// The layers the main camera draws.
+    camera_layers: RenderLayers,
+    overlay_cameras: Vec<OverlayCamera>,
+    /// The unjittered view projection each overlay camera had last frame, by position.
+    previous_overlay_view_projections: Vec<Matrix4<f32>>,
+    /// Each overlay camera's descriptor sets for this frame, written with the main camera's.
+    overlay_sets: Vec<Vec<DescriptorSetWithOffsets>>,
+    /// The projection and view of each of this frame's views through portals.
+    portal_views: Vec<(Matrix4<f32>, Matrix4<f32>)>,
+    portal_sets: Vec<Vec<DescriptorSetWithOffsets>>,
+    /// The projection and view of each face of the reflection probe captured this frame.
+    capture_views: Vec<(Matrix4<f32>, Matrix4<f32>)>,
+    capture_sets: Vec<Vec<DescriptorSetWithOffsets>>,
+    /// Picked from for each queued object, see `set_reflection_probes`.
+    reflection_probes: Vec<ReflectionProbe>,
+}
+
+struct LayoutPipelines {
+    /// The layout's vertex shader and input state, kept to build G-buffer pipelines for
+    /// material features seen later.
+    vs: EntryPoint,
+    vertex_input_state: VertexInputState,
+    /// A G-buffer pipeline per material feature set in use.
+    pipelines: HashMap<MaterialFeatures, Arc<GraphicsPipeline>>,
+    depth_pipeline: Arc<GraphicsPipeline>,
+    mask_pipeline: Arc<GraphicsPipeline>,
+    object_id_pipeline: Arc<GraphicsPipeline>,
+    shadow_pipeline: Arc<GraphicsPipeline>,
+    portal_pipeline: Arc<GraphicsPipeline>,
+}
+
+/// Which of a material's optional inputs the G-buffer shader reads, and its shader and blend
+/// mode. Each combination is drawn with its own variant of the shader, with the inputs it
+/// doesn't read compiled out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct MaterialFeatures {
+    base_color_texture: bool,
+    metallic_roughness_texture: bool,
+    emissive_texture: bool,
+    lightmap: bool,
+    shader: MaterialShader,
+    blend: BlendMode,
+}
+
+impl MaterialFeatures {
+    fn of(material: &Material) -> Self {
+        MaterialFeatures {
+            base_color_texture: material.base_color_texture.is_some(),
+            metallic_roughness_texture: material.metallic_roughness_texture.is_some(),
+            emissive_texture: material.emissive_texture.is_some(),
+            lightmap: material.lightmap.is_some(),
+            shader: material.shader,
+            blend: material.blend,
+        }
+    }
+
+    /// The constants matching the `constant_id`s in geometry.frag.
+    fn variant_key(&self) -> VariantKey {
+        VariantKey::new()
+            .with_bool(0, self.base_color_texture)
+            .with_bool(1, self.metallic_roughness_texture)
+            .with_bool(2, self.emissive_texture)
+            .with_bool(3, self.lightmap)
+            .with_bool(4, self.shader == MaterialShader::Unlit)
+            .with_bool(5, self.blend == BlendMode::Mask)
+    }
+
+    /// Masked materials cut holes the depth pre-pass would fill in.
+    fn writes_depth_prepass(&self) -> bool {
+        self.blend == BlendMode::Opaque
+    }
+}
+
+/// A material's factors, copied into the object data of every object drawn with it, and its
+/// texture descriptor set.
+struct GpuMaterial {
+    features: MaterialFeatures,
+    base_color: [f32; 4],
+    factors: [f32; 4],
+    emissive: [f32; 4],
+    descriptor_set: Arc<DescriptorSet>,
+}
+
+/// This frame's indirect draw commands, one per queued object, and the runs of commands that
+/// share a mesh and material.
+#[derive(Clone)]
+struct IndirectDraws {
+    commands: Subbuffer<[DrawIndexedIndirectCommand]>,
+    runs: Vec<(usize, usize, Range<u32>)>,
 }
 
 /*
@@ -78,105 +266,370 @@ pub struct GeometrySystem {
 
 impl GeometrySystem {
     /// Initializes a triangle drawing system.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        gfx_queue: Arc<Queue>,
+        context: &VulkanContext,
         subpass: Subpass,
-        memory_allocator: Arc<StandardMemoryAllocator>,
-        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        depth_subpass: Subpass,
+        mask_subpass: Subpass,
+        object_id_subpass: Subpass,
+        shadow_subpass: Subpass,
+        portal_subpass: Subpass,
+        descriptor_set_cache: &DescriptorSetCache,
+        frames_in_flight: usize,
+        mesh_pool: MeshPool,
     ) -> anyhow::Result<Self> {
-        let pipeline = {
-            let device = gfx_queue.device();
-            let vs = vs::load(device.clone())
-                .expect("failed to create shader module")
-                .entry_point("main")
-                .expect("shader entry point not found");
-            let fs = fs::load(device.clone())
-                .expect("failed to create shader module")
-                .entry_point("main")
-                .expect("shader entry point not found");
-            let vertex_input_state = VertexPositionColorNormal::per_vertex()
-                .definition(&vs.info().input_interface)
-                .unwrap();
-            let stages = [
-                PipelineShaderStageCreateInfo::new(vs),
-                PipelineShaderStageCreateInfo::new(fs),
-            ];
-            let layout = PipelineLayout::new(
-                device.clone(),
-                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
-                    .into_pipeline_layout_create_info(device.clone())
-                    .unwrap(),
-            )
-            .context("creating pipeline layout")?;
-
-            GraphicsPipeline::new(
-                device.clone(),
-                None,
-                GraphicsPipelineCreateInfo {
-                    stages: stages.into_iter().collect(),
-                    vertex_input_state: Some(vertex_input_state),
-                    input_assembly_state: Some(InputAssemblyState::default()),
-                    viewport_state: Some(ViewportState::default()),
-                    rasterization_state: Some(RasterizationState::default()),
-                    depth_stencil_state: Some(DepthStencilState {
-                        depth: Some(DepthState::simple()),
-                        ..Default::default()
-                    }),
-                    multisample_state: Some(MultisampleState::default()),
-                    color_blend_state: Some(ColorBlendState::with_attachment_states(
-                        subpass.num_color_attachments(),
-                        ColorBlendAttachmentState::default(),
-                    )),
-                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
-                    subpass: Some(subpass.clone().into()),
-                    ..GraphicsPipelineCreateInfo::layout(layout)
-                },
-            )
-            .context("creating graphics pipeline")?
-        };
+        let gfx_queue = context.graphics_queue().clone();
+        let memory_allocator = context.memory_allocator().clone();
 
-        let storage_buffer_allocator = SubbufferAllocator::new(
-            memory_allocator.clone(),
-            SubbufferAllocatorCreateInfo {
-                buffer_usage: BufferUsage::STORAGE_BUFFER,
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
+        let device = gfx_queue.device();
+        let vs = vs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .expect("shader entry point not found");
+        let mut fs = ShaderVariants::new(
+            "G-buffer fragment",
+            fs::load(device.clone()).context("creating G-buffer fragment shader module")?,
         );
+        // The shader as written reads every input, so it has every binding to validate
+        let fs_all_features = fs.get(&VariantKey::new())?;
+        let depth_vs = depth_vs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .expect("shader entry point not found");
+        let mask_fs = mask_fs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .expect("shader entry point not found");
+        let object_id_fs = object_id_fs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .expect("shader entry point not found");
+        let shadow_vs = shadow_vs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .expect("shader entry point not found");
+        let portal_fs = portal_fs::load(device.clone())
+            .expect("failed to create shader module")
+            .entry_point("main")
+            .expect("shader entry point not found");
+        validate_descriptor_bindings(
+            "GeometrySystem",
+            &[&vs, &fs_all_features],
+            &[
+                FRAME_DATA_BINDING,
+                OBJECT_DATA_BINDING,
+                BASE_COLOR_TEXTURE_BINDING,
+                METALLIC_ROUGHNESS_TEXTURE_BINDING,
+                EMISSIVE_TEXTURE_BINDING,
+                LIGHTMAP_TEXTURE_BINDING,
+            ],
+        )?;
+        validate_descriptor_bindings(
+            "GeometrySystem depth pre-pass",
+            &[&depth_vs],
+            &[FRAME_DATA_BINDING, OBJECT_DATA_BINDING],
+        )?;
+        validate_descriptor_bindings(
+            "GeometrySystem object IDs",
+            &[&vs, &object_id_fs],
+            &[FRAME_DATA_BINDING, OBJECT_DATA_BINDING],
+        )?;
+        validate_descriptor_bindings(
+            "GeometrySystem shadows",
+            &[&shadow_vs],
+            &[OBJECT_DATA_BINDING],
+        )?;
+        validate_descriptor_bindings(
+            "GeometrySystem portal views",
+            &[&vs, &portal_fs],
+            &[
+                FRAME_DATA_BINDING,
+                OBJECT_DATA_BINDING,
+                BASE_COLOR_TEXTURE_BINDING,
+                METALLIC_ROUGHNESS_TEXTURE_BINDING,
+                EMISSIVE_TEXTURE_BINDING,
+                LIGHTMAP_TEXTURE_BINDING,
+            ],
+        )?;
+
+        // The portal fragment shader reads the same sets as the G-buffer one, so the sets are
+        // bound the same way with either layout
+        let mut portal_layout_create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(&[
+            PipelineShaderStageCreateInfo::new(vs.clone()),
+            PipelineShaderStageCreateInfo::new(portal_fs.clone()),
+        ]);
+        for binding in [FRAME_DATA_BINDING, OBJECT_DATA_BINDING] {
+            portal_layout_create_info.set_layouts[binding.set as usize]
+                .bindings
+                .get_mut(&binding.binding)
+                .context("getting portal per-frame buffer binding")?
+                .descriptor_type = binding.ty;
+        }
+        let portal_layout = PipelineLayout::new(
+            device.clone(),
+            portal_layout_create_info
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating portal pipeline layout create info")?,
+        )
+        .context("creating portal pipeline layout")?;
+
+        // Only the frame and object sets, which are bound the same way as the G-buffer's
+        let mut object_id_layout_create_info =
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&[
+                PipelineShaderStageCreateInfo::new(vs.clone()),
+                PipelineShaderStageCreateInfo::new(object_id_fs.clone()),
+            ]);
+        for binding in [FRAME_DATA_BINDING, OBJECT_DATA_BINDING] {
+            object_id_layout_create_info.set_layouts[binding.set as usize]
+                .bindings
+                .get_mut(&binding.binding)
+                .context("getting object ID per-frame buffer binding")?
+                .descriptor_type = binding.ty;
+        }
+        let object_id_layout = PipelineLayout::new(
+            device.clone(),
+            object_id_layout_create_info
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating object ID pipeline layout create info")?,
+        )
+        .context("creating object ID pipeline layout")?;
+
+        // Every vertex layout's shaders share the same descriptor sets, so a single layout is
+        // used by all of the pipelines.
+        // Reflection only produces the non-dynamic buffer types, the per-frame buffers are bound
+        // with dynamic offsets into ring buffers instead
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs_all_features),
+        ];
+        let mut layout_create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages);
+        for binding in [FRAME_DATA_BINDING, OBJECT_DATA_BINDING] {
+            layout_create_info.set_layouts[binding.set as usize]
+                .bindings
+                .get_mut(&binding.binding)
+                .context("getting per-frame buffer binding")?
+                .descriptor_type = binding.ty;
+        }
+
+        let pipeline_layout = PipelineLayout::new(
+            device.clone(),
+            layout_create_info
+                .into_pipeline_layout_create_info(device.clone())
+                .unwrap(),
+        )
+        .context("creating pipeline layout")?;
+
+        let mut shadow_layout_create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(&[
+            PipelineShaderStageCreateInfo::new(shadow_vs.clone()),
+        ]);
+        shadow_layout_create_info.set_layouts[OBJECT_DATA_BINDING.set as usize]
+            .bindings
+            .get_mut(&OBJECT_DATA_BINDING.binding)
+            .context("getting shadow object buffer binding")?
+            .descriptor_type = OBJECT_DATA_BINDING.ty;
+        let shadow_layout = PipelineLayout::new(
+            device.clone(),
+            shadow_layout_create_info
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating shadow pipeline layout create info")?,
+        )
+        .context("creating shadow pipeline layout")?;
 
-        let uniform_buffer_allocator = SubbufferAllocator::new(
+        // The main camera's frame data, every overlay camera's, every portal view's and every
+        // probe face's
+        let frame_data_ring = RingBuffer::persistent(
             memory_allocator.clone(),
-            SubbufferAllocatorCreateInfo {
-                buffer_usage: BufferUsage::UNIFORM_BUFFER,
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-        );
+            BufferUsage::UNIFORM_BUFFER,
+            ((MAX_OVERLAY_CAMERAS + MAX_PORTAL_VIEWS + CUBE_FACES + 1)
+                * std::mem::size_of::<FrameData>().next_multiple_of(MAX_UNIFORM_ALIGNMENT))
+                as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating frame data ring buffer")?;
+
+        let object_data_ring = RingBuffer::persistent(
+            memory_allocator.clone(),
+            BufferUsage::STORAGE_BUFFER,
+            (INITIAL_OBJECT_CAPACITY * std::mem::size_of::<ObjectData>()) as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating object data ring buffer")?;
 
-        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
-            gfx_queue.device().clone(),
-            Default::default(),
-        ));
+        let indirect_ring = RingBuffer::new(
+            memory_allocator.clone(),
+            BufferUsage::INDIRECT_BUFFER,
+            (INITIAL_OBJECT_CAPACITY * std::mem::size_of::<DrawIndexedIndirectCommand>())
+                as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating indirect draw ring buffer")?;
+
+        let features = context.device().enabled_features();
+        let indirect_supported =
+            features.multi_draw_indirect && features.draw_indirect_first_instance;
+        // The GPU counts the culled draws, so drawing them needs `drawIndirectCount` too
+        let gpu_culler = if indirect_supported && features.draw_indirect_count {
+            GpuCuller::new(context, frames_in_flight)?
+        } else {
+            None
+        };
+
+        // The ring buffer sets live as long as their buffers, so they're allocated directly
+        // rather than through the cache
+        let descriptor_set_allocator = descriptor_set_cache.allocator().clone();
+
+        let frame_data_set = Self::create_ring_descriptor_set(
+            &descriptor_set_allocator,
+            &pipeline_layout,
+            FRAME_DATA_BINDING,
+            &frame_data_ring,
+            std::mem::size_of::<FrameData>() as DeviceSize,
+        )
+        .context("creating frame data descriptor set")?;
+
+        let object_data_set = Self::create_ring_descriptor_set(
+            &descriptor_set_allocator,
+            &pipeline_layout,
+            OBJECT_DATA_BINDING,
+            &object_data_ring,
+            object_data_ring.region_size(),
+        )
+        .context("creating object data descriptor set")?;
 
-        Ok(GeometrySystem {
+        let mut geometry_system = GeometrySystem {
             gfx_queue,
             subpass,
-            pipeline,
-            command_buffer_allocator,
+            depth_subpass,
+            mask_subpass,
+            object_id_subpass,
+            shadow_subpass,
+            portal_subpass,
+            pipeline_layout,
+            shadow_layout,
+            portal_layout,
+            object_id_layout,
+            fs,
+            depth_vs,
+            mask_fs,
+            object_id_fs,
+            shadow_vs,
+            portal_fs,
+            pipelines: HashMap::new(),
+            debug_namer: context.debug_namer().clone(),
             memory_allocator,
+            mesh_pool,
             render_data: { Default::default() },
-            storage_buffer_allocator,
-            uniform_buffer_allocator,
+            frames_in_flight,
+            frame_data_ring,
+            object_data_ring,
+            frame_data_set,
+            object_data_set,
             descriptor_set_allocator,
-        })
+            prepared_sets: None,
+            indirect_supported,
+            indirect_draws: false,
+            srgb_colors: false,
+            indirect_ring,
+            prepared_indirect: None,
+            gpu_culler,
+            gpu_culling: false,
+            prepared_culled: None,
+            object_data_offset: 0,
+            draw_calls: 0,
+            triangles: 0,
+            visible: vec![],
+            occluded_objects: 0,
+            materials: vec![],
+            jitter: [0.0, 0.0],
+            previous_view_projection: None,
+            previous_models: HashMap::new(),
+            current_models: HashMap::new(),
+            selected: HashSet::new(),
+            visible_keys: HashSet::new(),
+            spatial_index: None,
+            camera_layers: RenderLayers::ALL,
+            overlay_cameras: vec![],
+            previous_overlay_view_projections: vec![],
+            overlay_sets: vec![],
+            portal_views: vec![],
+            portal_sets: vec![],
+            capture_views: vec![],
+            capture_sets: vec![],
+            reflection_probes: vec![],
+        };
+
+        geometry_system
+            .create_layout_pipelines::<VertexPositionColorNormal>()
+            .context("creating default vertex layout pipelines")?;
+
+        Ok(geometry_system)
+    }
+
+    /// Builds a secondary command buffer that draws the queued meshes into the G-buffer.
+    ///
+    /// This is the last draw of a frame. The queued meshes are kept until `clear_objects`.
+    pub fn draw(
+        &mut self,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        profile_scope!("geometry");
+        let descriptor_sets = self.frame_descriptor_sets(frame.index)?;
+
+        let command_buffer = self.record_draws(
+            false,
+            self.subpass.clone(),
+            viewport_dimensions,
+            frame,
+            descriptor_sets,
+        )?;
+
+        self.prepared_sets = None;
+        self.prepared_indirect = None;
+        self.prepared_culled = None;
+        self.visible_keys = self.collect_visible_keys();
+        self.visible.clear();
+
+        Ok(command_buffer)
+    }
+
+    /// Builds a secondary command buffer that only writes the depth of the queued meshes, for the
+    /// depth pre-pass subpass. Must be called before `draw` in the same frame.
+    pub fn draw_depth(
+        &mut self,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        profile_scope!("depth prepass");
+        let descriptor_sets = self.frame_descriptor_sets(frame.index)?;
+
+        self.record_draws(
+            true,
+            self.depth_subpass.clone(),
+            viewport_dimensions,
+            frame,
+            descriptor_sets,
+        )
     }
 
-    /// Builds a secondary command buffer that draws the triangle on the current subpass.
-    pub fn draw(&mut self, viewport_dimensions: [u32; 2]) -> anyhow::Result<Arc<CommandBuffer>> {
+    /// Builds a secondary command buffer that draws the objects on each overlay camera's layers
+    /// into the G-buffer, after the main camera's, or `None` when there are no overlay cameras.
+    /// Occlusion culling is the main camera's, so it's ignored. Must be called before `draw` in
+    /// the same frame.
+    pub fn draw_overlays(
+        &mut self,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        if self.overlay_cameras.is_empty() {
+            return Ok(None);
+        }
+        profile_scope!("overlay cameras");
+        self.frame_descriptor_sets(frame.index)?;
+
         let mut builder = RecordingCommandBuffer::new(
-            self.command_buffer_allocator.clone(),
+            frame.command_buffer_allocator.clone(),
             self.gfx_queue.queue_family_index(),
             CommandBufferLevel::Secondary,
             CommandBufferBeginInfo {
@@ -189,7 +642,248 @@ impl GeometrySystem {
             },
         )?;
 
-        let descriptor_sets = self.create_descriptor_sets(&self.render_data)?;
+        for (camera, descriptor_sets) in self.overlay_cameras.iter().zip(self.overlay_sets.clone())
+        {
+            // Squeezing the camera into the front of the depth range puts it in front of the
+            // cameras before, while its objects still sort against each other
+            let depth_range = if camera.clear_depth {
+                0.0..=OVERLAY_DEPTH
+            } else {
+                0.0..=1.0
+            };
+            builder
+                .set_viewport(
+                    0,
+                    [Viewport {
+                        offset: [0.0, 0.0],
+                        extent: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+                        depth_range,
+                    }]
+                    .into_iter()
+                    .collect(),
+                )
+                .context("setting overlay viewport")?
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.pipeline_layout.clone(),
+                    0,
+                    descriptor_sets,
+                )
+                .context("binding overlay descriptor sets")?;
+
+            let mut bound_layout = None;
+            let mut bound_buffers = None;
+            let mut bound_material = None;
+            for ((index, mesh, material_index), layers) in self
+                .render_data
+                .render_iter()
+                .zip(self.render_data.object_layers())
+            {
+                if !layers.intersects(camera.layers) {
+                    continue;
+                }
+                Self::bind_layout_pipeline(
+                    &mut builder,
+                    &self.pipelines,
+                    mesh.layout,
+                    Some(self.materials[material_index].features),
+                    &mut bound_layout,
+                )?;
+                self.bind_material(&mut builder, material_index, &mut bound_material)?;
+                self.draw_calls += 1;
+                self.triangles += mesh.index_buffer.len() / 3;
+                Self::bind_mesh_buffers(&mut builder, mesh, &mut bound_buffers)?;
+                unsafe {
+                    builder.draw_indexed(
+                        mesh.index_count(),
+                        1,
+                        mesh.first_index(),
+                        mesh.vertex_offset(),
+                        index,
+                    )
+                }?;
+            }
+        }
+
+        Ok(Some(builder.end().context("building command buffer")?))
+    }
+
+    /// Builds a secondary command buffer that draws the objects on the main camera's layers into
+    /// the portal view at `index`, forward shaded with `lighting`, for `Portals::view_subpass`.
+    /// Occlusion culling is the main camera's, so it's ignored. Must be called before `draw` in
+    /// the same frame.
+    pub(crate) fn draw_portal_view(
+        &mut self,
+        index: usize,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+        lighting: PortalLighting,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        profile_scope!("portal view");
+        self.frame_descriptor_sets(frame.index)?;
+        let descriptor_sets = self
+            .portal_sets
+            .get(index)
+            .cloned()
+            .with_context(|| format!("no portal view {}", index))?;
+        self.draw_forward(descriptor_sets, viewport_dimensions, frame, lighting)
+    }
+
+    /// Like `draw_portal_view`, for the face at `face` of the reflection probe being captured.
+    pub(crate) fn draw_capture_view(
+        &mut self,
+        face: usize,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+        lighting: PortalLighting,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        profile_scope!("probe face");
+        self.frame_descriptor_sets(frame.index)?;
+        let descriptor_sets = self
+            .capture_sets
+            .get(face)
+            .cloned()
+            .with_context(|| format!("no probe face {}", face))?;
+        self.draw_forward(descriptor_sets, viewport_dimensions, frame, lighting)
+    }
+
+    /// Forward shades the objects on the main camera's layers, seen through the frame data in
+    /// `descriptor_sets`, for `Portals::view_subpass`.
+    fn draw_forward(
+        &mut self,
+        descriptor_sets: Vec<DescriptorSetWithOffsets>,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+        lighting: PortalLighting,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Secondary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::MultipleSubmit,
+                inheritance_info: Some(CommandBufferInheritanceInfo {
+                    render_pass: Some(self.portal_subpass.clone().into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+
+        let [x, y, z] = lighting.ambient;
+        let [dx, dy, dz] = lighting.sun_direction;
+        let [r, g, b] = lighting.sun_color;
+        builder
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .context("setting portal view viewport")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.portal_layout.clone(),
+                0,
+                descriptor_sets,
+            )
+            .context("binding portal view descriptor sets")?
+            .push_constants(
+                self.portal_layout.clone(),
+                0,
+                portal_fs::Lighting {
+                    ambient: [x, y, z, 0.0],
+                    sun_direction: [dx, dy, dz, 0.0],
+                    sun_color: [r, g, b, 0.0],
+                },
+            )
+            .context("pushing portal view lighting")?;
+
+        // The sets are bound through the portal layout, since the push constants make it
+        // incompatible with the G-buffer's
+        let mut bound_layout = None;
+        let mut bound_buffers = None;
+        let mut bound_material = None;
+        for (index, mesh, material_index) in self.render_data.render_iter() {
+            if !self.on_camera_layers(index as usize) {
+                continue;
+            }
+            if bound_layout != Some(mesh.layout) {
+                let pipelines = self
+                    .pipelines
+                    .get(&mesh.layout)
+                    .with_context(|| format!("no pipelines for {:?} vertex layout", mesh.layout))?;
+                builder
+                    .bind_pipeline_graphics(pipelines.portal_pipeline.clone())
+                    .context("binding portal view pipeline")?;
+                bound_layout = Some(mesh.layout);
+            }
+            if bound_material != Some(material_index) {
+                builder
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        self.portal_layout.clone(),
+                        BASE_COLOR_TEXTURE_BINDING.set,
+                        self.materials[material_index].descriptor_set.clone(),
+                    )
+                    .context("binding material descriptor set")?;
+                bound_material = Some(material_index);
+            }
+            self.draw_calls += 1;
+            self.triangles += mesh.index_buffer.len() / 3;
+            Self::bind_mesh_buffers(&mut builder, mesh, &mut bound_buffers)?;
+            unsafe {
+                builder.draw_indexed(
+                    mesh.index_count(),
+                    1,
+                    mesh.first_index(),
+                    mesh.vertex_offset(),
+                    index,
+                )
+            }?;
+        }
+
+        builder.end().context("building command buffer")
+    }
+
+    /// Builds a secondary command buffer that draws the silhouettes of the selected objects for
+    /// the selection mask subpass, or `None` when none of the queued objects are selected. Must
+    /// be called before `draw` in the same frame.
+    pub fn draw_selection_mask(
+        &mut self,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        profile_scope!("selection mask");
+        if self.selected.is_empty()
+            || self
+                .render_data
+                .keyed_objects(&self.selected)
+                .next()
+                .is_none()
+        {
+            return Ok(None);
+        }
+
+        let descriptor_sets = self.frame_descriptor_sets(frame.index)?;
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Secondary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::MultipleSubmit,
+                inheritance_info: Some(CommandBufferInheritanceInfo {
+                    render_pass: Some(self.mask_subpass.clone().into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
 
         builder
             .set_viewport(
@@ -203,104 +897,1485 @@ impl GeometrySystem {
                 .collect(),
             )
             .context("setting viewport")?
-            .bind_pipeline_graphics(self.pipeline.clone())
-            .context("binding pipeline graphics")?
             .bind_descriptor_sets(
                 vulkano::pipeline::PipelineBindPoint::Graphics,
-                self.pipeline.layout().clone(),
+                self.pipeline_layout.clone(),
                 0,
                 descriptor_sets,
             )
             .context("binding descriptor sets")?;
 
-        for data in self.render_data.render_iter() {
-            let (index, mesh) = data;
+        // Occlusion culling is ignored, hidden parts of a selection are outlined too
+        let mut bound_layout = None;
+        let mut bound_buffers = None;
+        for (index, mesh) in self.render_data.keyed_objects(&self.selected) {
+            if bound_layout != Some(mesh.layout) {
+                let pipelines = self
+                    .pipelines
+                    .get(&mesh.layout)
+                    .with_context(|| format!("no pipelines for {:?} vertex layout", mesh.layout))?;
+                builder
+                    .bind_pipeline_graphics(pipelines.mask_pipeline.clone())
+                    .context("binding selection mask pipeline")?;
+                bound_layout = Some(mesh.layout);
+            }
+            Self::bind_mesh_buffers(&mut builder, mesh, &mut bound_buffers)?;
             unsafe {
+                builder.draw_indexed(
+                    mesh.index_count(),
+                    1,
+                    mesh.first_index(),
+                    mesh.vertex_offset(),
+                    index,
+                )
+            }?;
+        }
+
+        Ok(Some(builder.end().context("building command buffer")?))
+    }
+
+    /// Builds a secondary command buffer that draws the objects on the main camera's layers into
+    /// the one pixel object ID target of `ObjectPicker::subpass`, with the viewport shifted so the
+    /// pixel at `pixel` of a `viewport_dimensions` view lands on it. Each object writes its index
+    /// plus one. Returns the tracked objects' keys by index, to look up what the pixel was. Must be
+    /// called before `draw` in the same frame.
+    pub fn draw_object_ids(
+        &mut self,
+        pixel: [u32; 2],
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+    ) -> anyhow::Result<(Arc<CommandBuffer>, Vec<Option<u64>>)> {
+        profile_scope!("object ids");
+        let descriptor_sets = self.frame_descriptor_sets(frame.index)?;
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Secondary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::MultipleSubmit,
+                inheritance_info: Some(CommandBufferInheritanceInfo {
+                    render_pass: Some(self.object_id_subpass.clone().into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+
+        builder
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [-(pixel[0] as f32), -(pixel[1] as f32)],
+                    extent: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .context("setting object ID viewport")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.object_id_layout.clone(),
+                0,
+                descriptor_sets,
+            )
+            .context("binding object ID descriptor sets")?;
+
+        // Indexed objects the ray under the pixel misses can't be picked, so they're skipped
+        let hits: Option<HashSet<u64>> = self
+            .spatial_index
+            .as_ref()
+            .zip(screen_to_world_ray(
+                self.view_projection(),
+                [pixel[0] as f32 + 0.5, pixel[1] as f32 + 0.5],
+                [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+            ))
+            .map(|(index, ray)| {
+                index
+                    .raycast(&ray, f32::MAX)
+                    .into_iter()
+                    .map(|(entity, _)| EntityKey::from(entity).to_bits())
+                    .collect()
+            });
+        let keys = self.render_data.object_keys();
+
+        // Occlusion culling is ignored, the depth test settles what's in front
+        let mut bound_layout = None;
+        let mut bound_buffers = None;
+        for (index, mesh, _) in self.render_data.render_iter() {
+            if !self.on_camera_layers(index as usize) {
+                continue;
+            }
+            let missed = |hits: &HashSet<u64>| {
+                keys[index as usize].is_some_and(|key| self.is_indexed(key) && !hits.contains(&key))
+            };
+            if hits.as_ref().is_some_and(missed) {
+                continue;
+            }
+            if bound_layout != Some(mesh.layout) {
+                let pipelines = self
+                    .pipelines
+                    .get(&mesh.layout)
+                    .with_context(|| format!("no pipelines for {:?} vertex layout", mesh.layout))?;
                 builder
-                    .bind_vertex_buffers(0, mesh.vertex_buffer.clone())?
-                    .bind_index_buffer(mesh.index_buffer.clone())?
-                    .draw_indexed(mesh.index_buffer.len() as u32, 1, 0, 0, index)
+                    .bind_pipeline_graphics(pipelines.object_id_pipeline.clone())
+                    .context("binding object ID pipeline")?;
+                bound_layout = Some(mesh.layout);
+            }
+            builder
+                .push_constants(
+                    self.object_id_layout.clone(),
+                    0,
+                    object_id_fs::PickObject { object: index + 1 },
+                )
+                .context("pushing object ID")?;
+            Self::bind_mesh_buffers(&mut builder, mesh, &mut bound_buffers)?;
+            unsafe {
+                builder.draw_indexed(
+                    mesh.index_count(),
+                    1,
+                    mesh.first_index(),
+                    mesh.vertex_offset(),
+                    index,
+                )
             }?;
         }
 
-        self.render_data.reset_object_data();
+        Ok((
+            builder.end().context("building command buffer")?,
+            keys.to_vec(),
+        ))
+    }
+
+    /// Builds a secondary command buffer that draws the depth of every queued mesh, seen through
+    /// `light_view_projection`, into a shadow map cascade of `viewport_dimensions`. Occlusion
+    /// culling is ignored, since objects hidden from the camera can still cast visible shadows.
+    /// Must be called before `draw` in the same frame.
+    pub fn draw_shadow_casters(
+        &mut self,
+        light_view_projection: Matrix4<f32>,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        profile_scope!("shadow casters");
+        let descriptor_sets = self.frame_descriptor_sets(frame.index)?;
+        let object_set = descriptor_sets[OBJECT_DATA_BINDING.set as usize].clone();
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Secondary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::MultipleSubmit,
+                inheritance_info: Some(CommandBufferInheritanceInfo {
+                    render_pass: Some(self.shadow_subpass.clone().into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+
+        builder
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .context("setting viewport")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.shadow_layout.clone(),
+                OBJECT_DATA_BINDING.set,
+                object_set,
+            )
+            .context("binding object descriptor set")?
+            .push_constants(
+                self.shadow_layout.clone(),
+                0,
+                shadow_vs::PushConstants {
+                    light_view_projection: light_view_projection.into(),
+                },
+            )
+            .context("pushing cascade matrix")?;
+
+        // Only what the main camera draws casts shadows, not e.g. an overlay's weapon
+        let mut bound_layout = None;
+        let mut bound_buffers = None;
+        for (index, mesh, _) in self.render_data.render_iter() {
+            if !self.on_camera_layers(index as usize) {
+                continue;
+            }
+            if bound_layout != Some(mesh.layout) {
+                let pipelines = self
+                    .pipelines
+                    .get(&mesh.layout)
+                    .with_context(|| format!("no pipelines for {:?} vertex layout", mesh.layout))?;
+                builder
+                    .bind_pipeline_graphics(pipelines.shadow_pipeline.clone())
+                    .context("binding shadow pipeline")?;
+                bound_layout = Some(mesh.layout);
+            }
+            self.draw_calls += 1;
+            self.triangles += mesh.index_buffer.len() / 3;
+            Self::bind_mesh_buffers(&mut builder, mesh, &mut bound_buffers)?;
+            unsafe {
+                builder.draw_indexed(
+                    mesh.index_count(),
+                    1,
+                    mesh.first_index(),
+                    mesh.vertex_offset(),
+                    index,
+                )
+            }?;
+        }
 
         builder.end().context("building command buffer")
     }
 
-    pub fn create_mesh(
+    /// Replaces the selection with the tracked objects queued under `keys`.
+    pub fn set_selected(&mut self, keys: impl IntoIterator<Item = u64>) {
+        self.selected = keys.into_iter().collect();
+    }
+
+    /// Switches between one draw per object and indirect draws. Stays on per-object draws when
+    /// the device wasn't created with `RendererConfig::indirect_draws`.
+    pub fn set_indirect_draws(&mut self, enabled: bool) {
+        if enabled && !self.indirect_supported {
+            log::warn!("indirect draws need the multi draw indirect features, ignoring");
+            return;
+        }
+        self.indirect_draws = enabled;
+    }
+
+    /// Culls the main camera's indirect draws against its frustum on the GPU, see `GpuCuller`.
+    /// Only takes effect while indirect draws are on, and stays off when the device wasn't
+    /// created with `RendererConfig::gpu_culling`. Occlusion culling tests objects on the CPU,
+    /// so it's skipped while the GPU culls.
+    pub fn set_gpu_culling(&mut self, enabled: bool) {
+        if enabled && self.gpu_culler.is_none() {
+            log::warn!(
+                "GPU culling needs indirect draws and the draw indirect count feature, ignoring"
+            );
+            return;
+        }
+        self.gpu_culling = enabled;
+    }
+
+    /// Whether this frame's main camera draws are culled on the GPU.
+    pub fn culls_on_gpu(&self) -> bool {
+        self.gpu_culling && self.indirect_draws && self.gpu_culler.is_some()
+    }
+
+    /// Records the GPU culling of this frame's main camera draws, to be executed before the
+    /// frame. Returns `None` when the GPU isn't culling or nothing is queued. Must be called
+    /// after the frame's views are set, like the draws.
+    pub fn record_culling(
+        &mut self,
+        frame: &InFlightFrame,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        if !self.culls_on_gpu() {
+            return Ok(None);
+        }
+        self.frame_descriptor_sets(frame.index)?;
+        if self.prepared_culled.is_none() {
+            return Ok(None);
+        }
+        let culler = self.gpu_culler.as_mut().context("getting GPU culler")?;
+        culler.record(frame, &self.object_data_ring, self.object_data_offset)
+    }
+
+    /// Treats vertex colors as sRGB, converting them to linear in the vertex shader.
+    pub fn set_srgb_colors(&mut self, enabled: bool) {
+        self.srgb_colors = enabled;
+    }
+
+    /// Tests the queued objects against `pyramid`, skipping the hidden ones in this frame's
+    /// draws. Call before the frame's first draw.
+    pub fn cull_occluded(&mut self, pyramid: &DepthPyramid) {
+        profile_scope!("occlusion culling");
+
+        self.visible = self
+            .render_data
+            .objects()
+            .map(|(mesh, object)| pyramid.is_visible(&Matrix4::from(object.model), &mesh.bounds))
+            .collect();
+        self.occluded_objects = self.visible.iter().filter(|visible| !**visible).count() as u32;
+    }
+
+    /// Keys of the tracked objects whose bounds were inside the camera's frustum and not
+    /// occlusion culled in the last drawn frame.
+    pub fn visible_keys(&self) -> &HashSet<u64> {
+        &self.visible_keys
+    }
+
+    /// Replaces the index culling and picking query for the objects tracked under an indexed
+    /// entity's `EntityKey`.
+    pub fn set_spatial_index(&mut self, index: Arc<SpatialIndex>) {
+        self.spatial_index = Some(index);
+    }
+
+    /// Whether the object tracked under `key` is in the spatial index.
+    fn is_indexed(&self, key: u64) -> bool {
+        self.spatial_index
+            .as_ref()
+            .is_some_and(|index| index.contains_key(EntityKey::from_bits(key)))
+    }
+
+    fn collect_visible_keys(&self) -> HashSet<u64> {
+        let frustum = Frustum::from_view_projection(self.view_projection());
+        let in_frustum: HashSet<u64> = self
+            .spatial_index
+            .as_ref()
+            .map(|index| {
+                index
+                    .query_frustum(&frustum)
+                    .into_iter()
+                    .map(|entity| EntityKey::from(entity).to_bits())
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.render_data
+            .objects()
+            .zip(self.render_data.object_keys())
+            .enumerate()
+            .filter_map(|(index, ((mesh, object), key))| {
+                let key = (*key)?;
+                if !self.is_drawn(index) {
+                    return None;
+                }
+                if self.is_indexed(key) {
+                    return in_frustum.contains(&key).then_some(key);
+                }
+                let model = Matrix4::from(object.model);
+                let bounds = Aabb::from_points(mesh.bounds.corners().map(|corner| {
+                    (model * Vector4::new(corner[0], corner[1], corner[2], 1.0))
+                        .truncate()
+                        .into()
+                }));
+                frustum
+                    .intersects_aabb(bounds.min.into(), bounds.max.into())
+                    .then_some(key)
+            })
+            .collect()
+    }
+
+    /// Remembers the tracked objects' transforms for the next frame's motion vectors. Retained
+    /// objects count as static from here on.
+    pub fn end_frame(&mut self) {
+        self.previous_models = std::mem::take(&mut self.current_models);
+        self.render_data.settle_object_motion();
+    }
+
+    /// Removes every queued mesh.
+    pub fn clear_objects(&mut self) {
+        self.render_data.reset_object_data();
+    }
+
+    /// Counts of the meshes and queued objects, and the memory their buffers use. Point lights
+    /// are left for the caller to fill in.
+    pub fn scene_stats(&self) -> SceneStats {
+        SceneStats {
+            meshes: self.render_data.meshes().len(),
+            objects: self.render_data.object_count(),
+            mesh_bytes: self
+                .render_data
+                .meshes()
+                .iter()
+                .map(|mesh| mesh.vertex_buffer.size() + mesh.index_buffer.as_bytes().size())
+                .sum(),
+            mesh_pool_bytes: self.mesh_pool.allocated(),
+            object_buffer_bytes: self.object_data_ring.buffer().size(),
+            indirect_buffer_bytes: self.indirect_ring.buffer().size(),
+            ..Default::default()
+        }
+    }
+
+    /// The projection and view matrices of the queued camera, with the projection jittered.
+    pub fn camera_matrices(&self) -> (Matrix4<f32>, Matrix4<f32>) {
+        let (proj, view) = self.render_data.cam_matrices();
+        // Translating clip space by the jitter scaled by w shifts the image by a constant amount
+        let jitter = Matrix4::from_translation(Vector3::new(self.jitter[0], self.jitter[1], 0.0));
+        (jitter * proj, view)
+    }
+
+    /// Offsets this frame's projection by `jitter` in NDC units, for temporal anti-aliasing.
+    pub fn set_jitter(&mut self, jitter: [f32; 2]) {
+        self.jitter = jitter;
+    }
+
+    /// The projection and view matrices of the queued camera, without TAA's jitter.
+    pub fn unjittered_camera_matrices(&self) -> (Matrix4<f32>, Matrix4<f32>) {
+        self.render_data.cam_matrices()
+    }
+
+    /// The unjittered view projection of the queued camera.
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        let (proj, view) = self.render_data.cam_matrices();
+        proj * view
+    }
+
+    /// Draw calls, triangles and occlusion culled objects of the last frame. The draw counts
+    /// include the depth pre-pass and shadow casters.
+    pub fn draw_stats(&self) -> (u32, u64, u32) {
+        (self.draw_calls, self.triangles, self.occluded_objects)
+    }
+
+    pub fn create_mesh<V: MeshVertex>(
         &mut self,
-        verts: Vec<VertexPositionColorNormal>,
-        indices: Vec<u16>,
+        verts: Vec<V>,
+        indices: impl Into<Indices>,
     ) -> anyhow::Result<usize> {
+        profile_scope!("create mesh");
+        self.create_layout_pipelines::<V>()?;
+
         let position = self.render_data.mesh_position();
         let mesh = MeshBuilder::default()
             .with_vertices(verts)
             .with_indices(indices)
-            .build(self.memory_allocator.clone())
+            .build_pooled(&self.mesh_pool)
             .context("building mesh")?;
         self.render_data.add_mesh(mesh);
         Ok(position)
     }
 
-    pub fn enqueue_mesh(&mut self, mesh_id: usize, transform: Transform) {
-        let d = ObjectData {
-            model: transform.model().into(),
+    /// Creates `V`'s pipelines ahead of a mesh of that layout being streamed in.
+    pub fn prepare_layout<V: MeshVertex>(&mut self) -> anyhow::Result<()> {
+        self.create_layout_pipelines::<V>()
+    }
+
+    /// Adds a mesh whose buffers were filled elsewhere, e.g. by the `MeshUploader`, returning
+    /// its mesh id. Its layout must have been prepared.
+    pub fn add_mesh(&mut self, mesh: BasicMesh) -> usize {
+        let position = self.render_data.mesh_position();
+        self.render_data.add_mesh(mesh);
+        position
+    }
+
+    /// Uploads new vertices and indices for `mesh_id`, returning the replaced mesh, which frames
+    /// in flight may still be drawing.
+    pub fn replace_mesh<V: MeshVertex>(
+        &mut self,
+        mesh_id: usize,
+        verts: Vec<V>,
+        indices: impl Into<Indices>,
+    ) -> anyhow::Result<BasicMesh> {
+        if mesh_id >= self.render_data.mesh_position() {
+            bail!("no mesh {}", mesh_id);
+        }
+        self.create_layout_pipelines::<V>()?;
+
+        let mesh = MeshBuilder::default()
+            .with_vertices(verts)
+            .with_indices(indices)
+            .build_pooled(&self.mesh_pool)
+            .context("building mesh")?;
+        Ok(self.render_data.replace_mesh(mesh_id, mesh))
+    }
+
+    /// Creates a material from its factors and texture views. The views are bound with
+    /// `sampler`, materials without a texture should pass a white one.
+    pub fn create_material(
+        &mut self,
+        material: &Material,
+        base_color_texture: Arc<ImageView>,
+        metallic_roughness_texture: Arc<ImageView>,
+        emissive_texture: Arc<ImageView>,
+        lightmap_texture: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> anyhow::Result<MaterialId> {
+        let gpu_material = self.build_material(
+            material,
+            base_color_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+            lightmap_texture,
+            sampler,
+        )?;
+        self.create_feature_pipelines(gpu_material.features)?;
+        let id = MaterialId(self.materials.len());
+        self.materials.push(gpu_material);
+        Ok(id)
+    }
+
+    /// Rebuilds material `id`, e.g. after one of its textures was reloaded. Returns the replaced
+    /// descriptor set, which frames in flight may still be using.
+    #[allow(clippy::too_many_arguments)]
+    pub fn replace_material(
+        &mut self,
+        id: MaterialId,
+        material: &Material,
+        base_color_texture: Arc<ImageView>,
+        metallic_roughness_texture: Arc<ImageView>,
+        emissive_texture: Arc<ImageView>,
+        lightmap_texture: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> anyhow::Result<Arc<DescriptorSet>> {
+        if id.0 >= self.materials.len() {
+            bail!("no material {:?}", id);
+        }
+        let gpu_material = self.build_material(
+            material,
+            base_color_texture,
+            metallic_roughness_texture,
+            emissive_texture,
+            lightmap_texture,
+            sampler,
+        )?;
+        self.create_feature_pipelines(gpu_material.features)?;
+        Ok(std::mem::replace(&mut self.materials[id.0], gpu_material).descriptor_set)
+    }
+
+    fn build_material(
+        &self,
+        material: &Material,
+        base_color_texture: Arc<ImageView>,
+        metallic_roughness_texture: Arc<ImageView>,
+        emissive_texture: Arc<ImageView>,
+        lightmap_texture: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> anyhow::Result<GpuMaterial> {
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.pipeline_layout.set_layouts()[BASE_COLOR_TEXTURE_BINDING.set as usize].clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    BASE_COLOR_TEXTURE_BINDING.binding,
+                    base_color_texture,
+                    sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    METALLIC_ROUGHNESS_TEXTURE_BINDING.binding,
+                    metallic_roughness_texture,
+                    sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    EMISSIVE_TEXTURE_BINDING.binding,
+                    emissive_texture,
+                    sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    LIGHTMAP_TEXTURE_BINDING.binding,
+                    lightmap_texture,
+                    sampler,
+                ),
+            ],
+            [],
+        )
+        .context("creating material descriptor set")?;
+
+        Ok(GpuMaterial {
+            features: MaterialFeatures::of(material),
+            base_color: material.base_color,
+            factors: [
+                material.metallic.clamp(0.0, 1.0),
+                // Fully smooth surfaces turn point lights into single bright pixels
+                material.roughness.clamp(0.04, 1.0),
+                0.0,
+                if material.lightmap.is_some() {
+                    1.0
+                } else {
+                    0.0
+                },
+            ],
+            emissive: [
+                material.emissive[0],
+                material.emissive[1],
+                material.emissive[2],
+                0.0,
+            ],
+            descriptor_set,
+        })
+    }
+
+    /// Queues a mesh for the next frame. Objects with a `key` get their model matrix from the
+    /// last frame they were queued under the same key, so their motion shows in the velocity
+    /// buffer.
+    pub fn enqueue_mesh(
+        &mut self,
+        mesh_id: usize,
+        material: MaterialId,
+        transform: Transform,
+        tint: Tint,
+        key: Option<u64>,
+        layers: RenderLayers,
+    ) {
+        let model = transform.model();
+        let previous_model = match key {
+            Some(key) => {
+                self.current_models.insert(key, model);
+                self.previous_models.get(&key).copied().unwrap_or(model)
+            }
+            None => model,
         };
-        self.render_data.add_object_data(mesh_id, d);
+        let d = self.object_data(material, &transform, previous_model, tint);
+        self.render_data
+            .add_object_data(mesh_id, material.0, key, layers, d);
+    }
+
+    /// Replaces the object queued under `key`, or queues it when there's none. Its motion since
+    /// the object's last update goes into the motion vectors.
+    pub fn update_mesh(
+        &mut self,
+        mesh_id: usize,
+        material: MaterialId,
+        transform: Transform,
+        tint: Tint,
+        key: u64,
+        layers: RenderLayers,
+    ) {
+        let model = transform.model();
+        let previous_model = self
+            .render_data
+            .keyed_object_data(key)
+            .map_or(model, |object| Matrix4::from(object.model));
+        let d = self.object_data(material, &transform, previous_model, tint);
+        self.render_data
+            .set_object_data(mesh_id, material.0, key, layers, d);
+    }
+
+    /// Removes the object queued under `key`.
+    pub fn remove_mesh(&mut self, key: u64) {
+        self.render_data.remove_object_data(key);
+    }
+
+    fn object_data(
+        &self,
+        material: MaterialId,
+        transform: &Transform,
+        previous_model: Matrix4<f32>,
+        tint: Tint,
+    ) -> ObjectData {
+        let model = transform.model();
+        let gpu_material = &self.materials[material.0];
+        // The probe goes into an 8 bit channel of the G-buffer, 0 for the environment
+        let mut factors = gpu_material.factors;
+        factors[2] = select_probe(&self.reflection_probes, transform.position)
+            .map_or(0.0, |index| (index + 1) as f32 / 255.0);
+        ObjectData {
+            model: model.into(),
+            previous_model: previous_model.into(),
+            normal_matrix: transform.normal_matrix().into(),
+            base_color: gpu_material.base_color,
+            material: factors,
+            emissive: gpu_material.emissive,
+            tint: [tint.color[0], tint.color[1], tint.color[2], tint.amount],
+        }
     }
 
     pub fn set_camera_params(&mut self, cam_matrices: (Matrix4<f32>, Matrix4<f32>)) {
         self.render_data.update_cam_matrices(cam_matrices);
     }
 
-    fn create_descriptor_sets(
+    /// Limits the main camera to the objects on `layers`. Everything is drawn by default.
+    pub fn set_camera_layers(&mut self, layers: RenderLayers) {
+        self.camera_layers = layers;
+    }
+
+    /// Replaces the cameras drawn after the main one, in the order they're drawn. Only the first
+    /// `MAX_OVERLAY_CAMERAS` are kept.
+    pub fn set_overlay_cameras(&mut self, cameras: impl IntoIterator<Item = OverlayCamera>) {
+        self.overlay_cameras = cameras.into_iter().collect();
+        if self.overlay_cameras.len() > MAX_OVERLAY_CAMERAS {
+            log::warn!(
+                "ignoring {} overlay cameras past the first {}",
+                self.overlay_cameras.len() - MAX_OVERLAY_CAMERAS,
+                MAX_OVERLAY_CAMERAS
+            );
+            self.overlay_cameras.truncate(MAX_OVERLAY_CAMERAS);
+        }
+    }
+
+    /// Replaces the views through portals, as projection and view matrices in the order
+    /// `draw_portal_view` is given their indices. Must be called before anything is drawn in a
+    /// frame, only the first `MAX_PORTAL_VIEWS` are kept.
+    pub fn set_portal_views(
+        &mut self,
+        views: impl IntoIterator<Item = (Matrix4<f32>, Matrix4<f32>)>,
+    ) {
+        self.portal_views = views.into_iter().take(MAX_PORTAL_VIEWS).collect();
+    }
+
+    /// Replaces the faces of the reflection probe captured this frame, as projection and view
+    /// matrices in the order `draw_capture_view` is given them. Must be called before anything
+    /// is drawn in a frame, only the first `CUBE_FACES` are kept.
+    pub(crate) fn set_capture_views(
+        &mut self,
+        views: impl IntoIterator<Item = (Matrix4<f32>, Matrix4<f32>)>,
+    ) {
+        self.capture_views = views.into_iter().take(CUBE_FACES).collect();
+    }
+
+    /// Replaces the reflection probes objects pick from when they're queued, each reflecting
+    /// the smallest probe around its origin. Objects already queued keep the probes they had.
+    pub fn set_reflection_probes(&mut self, probes: &[ReflectionProbe]) {
+        self.reflection_probes = probes.to_vec();
+    }
+
+    fn on_camera_layers(&self, index: usize) -> bool {
+        self.render_data
+            .object_layers()
+            .get(index)
+            .map_or(true, |layers| layers.intersects(self.camera_layers))
+    }
+
+    fn in_depth_prepass(&self, material_index: usize) -> bool {
+        self.materials[material_index]
+            .features
+            .writes_depth_prepass()
+    }
+
+    /// Whether the main camera draws the queued object at `index`: it's on the camera's layers
+    /// and wasn't occlusion culled.
+    fn is_drawn(&self, index: usize) -> bool {
+        self.on_camera_layers(index) && self.visible.get(index).copied().unwrap_or(true)
+    }
+
+    /// The descriptor sets for this frame's data, pushing the data into the ring buffers the
+    /// first time they're requested in a frame.
+    fn frame_descriptor_sets(
+        &mut self,
+        frame_index: usize,
+    ) -> anyhow::Result<Vec<DescriptorSetWithOffsets>> {
+        if let Some(sets) = &self.prepared_sets {
+            return Ok(sets.clone());
+        }
+
+        let sets = self.write_frame_data(frame_index)?;
+        self.prepared_culled = None;
+        self.prepared_indirect = None;
+        if self.culls_on_gpu() {
+            let view_projection = self.view_projection();
+            let culler = self.gpu_culler.as_mut().context("getting GPU culler")?;
+            self.prepared_culled = culler.prepare(
+                frame_index,
+                &self.render_data,
+                view_projection,
+                self.camera_layers,
+            )?;
+        } else if self.indirect_draws {
+            self.prepared_indirect = self.write_indirect_draws(frame_index)?;
+        }
+        self.draw_calls = 0;
+        self.triangles = 0;
+        if self.visible.is_empty() {
+            self.occluded_objects = 0;
+        }
+        self.prepared_sets = Some(sets.clone());
+
+        Ok(sets)
+    }
+
+    fn record_draws(
+        &mut self,
+        depth_only: bool,
+        subpass: Subpass,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+        descriptor_sets: Vec<DescriptorSetWithOffsets>,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Secondary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::MultipleSubmit,
+                inheritance_info: Some(CommandBufferInheritanceInfo {
+                    render_pass: Some(subpass.into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+
+        builder
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .context("setting viewport")?
+            .bind_descriptor_sets(
+                vulkano::pipeline::PipelineBindPoint::Graphics,
+                self.pipeline_layout.clone(),
+                0,
+                descriptor_sets,
+            )
+            .context("binding descriptor sets")?;
+
+        // Meshes are drawn in queue order, the pipeline only changes when the vertex layout does
+        // and the material set when the material does. The depth pre-pass has no use for
+        // materials
+        let mut bound_layout = None;
+        let mut bound_buffers = None;
+        let mut bound_material = None;
+
+        // How many of each run's draws survived is only known to the GPU, so every run is
+        // drawn and counted in full
+        if let Some(culled) = self.prepared_culled.clone() {
+            for (run, (mesh_index, material_index, range)) in culled.runs.into_iter().enumerate() {
+                if depth_only && !self.in_depth_prepass(material_index) {
+                    continue;
+                }
+                let mesh = self.render_data.mesh(mesh_index);
+                Self::bind_layout_pipeline(
+                    &mut builder,
+                    &self.pipelines,
+                    mesh.layout,
+                    (!depth_only).then(|| self.materials[material_index].features),
+                    &mut bound_layout,
+                )?;
+                if !depth_only {
+                    self.bind_material(&mut builder, material_index, &mut bound_material)?;
+                }
+                self.draw_calls += 1;
+                self.triangles += range.len() as u64 * (mesh.index_buffer.len() / 3);
+                Self::bind_mesh_buffers(&mut builder, mesh, &mut bound_buffers)?;
+                unsafe {
+                    builder.draw_indexed_indirect_count(
+                        culled
+                            .commands
+                            .clone()
+                            .slice(range.start as DeviceSize..range.end as DeviceSize),
+                        culled.counts.clone().index(run as DeviceSize),
+                        range.len() as u32,
+                    )
+                }?;
+            }
+
+            return builder.end().context("building command buffer");
+        }
+
+        if let Some(indirect) = self.prepared_indirect.clone() {
+            for (mesh_index, material_index, range) in indirect.runs {
+                if depth_only && !self.in_depth_prepass(material_index) {
+                    continue;
+                }
+                let mesh = self.render_data.mesh(mesh_index);
+                let visible = range
+                    .clone()
+                    .filter(|index| self.is_drawn(*index as usize))
+                    .count() as u64;
+                if visible == 0 {
+                    continue;
+                }
+                Self::bind_layout_pipeline(
+                    &mut builder,
+                    &self.pipelines,
+                    mesh.layout,
+                    (!depth_only).then(|| self.materials[material_index].features),
+                    &mut bound_layout,
+                )?;
+                if !depth_only {
+                    self.bind_material(&mut builder, material_index, &mut bound_material)?;
+                }
+                self.draw_calls += 1;
+                self.triangles += visible * (mesh.index_buffer.len() / 3);
+                Self::bind_mesh_buffers(&mut builder, mesh, &mut bound_buffers)?;
+                unsafe {
+                    builder.draw_indexed_indirect(
+                        indirect
+                            .commands
+                            .clone()
+                            .slice(range.start as DeviceSize..range.end as DeviceSize),
+                    )
+                }?;
+            }
+
+            return builder.end().context("building command buffer");
+        }
+
+        for data in self.render_data.render_iter() {
+            let (index, mesh, material_index) = data;
+            if !self.is_drawn(index as usize)
+                || (depth_only && !self.in_depth_prepass(material_index))
+            {
+                continue;
+            }
+            Self::bind_layout_pipeline(
+                &mut builder,
+                &self.pipelines,
+                mesh.layout,
+                (!depth_only).then(|| self.materials[material_index].features),
+                &mut bound_layout,
+            )?;
+            if !depth_only {
+                self.bind_material(&mut builder, material_index, &mut bound_material)?;
+            }
+            self.draw_calls += 1;
+            self.triangles += mesh.index_buffer.len() / 3;
+            Self::bind_mesh_buffers(&mut builder, mesh, &mut bound_buffers)?;
+            unsafe {
+                builder.draw_indexed(
+                    mesh.index_count(),
+                    1,
+                    mesh.first_index(),
+                    mesh.vertex_offset(),
+                    index,
+                )
+            }?;
+        }
+
+        builder.end().context("building command buffer")
+    }
+
+    /// Binds the buffers `mesh` is drawn from, unless they're bound already, as they are for
+    /// pooled meshes sharing blocks with the mesh drawn before.
+    fn bind_mesh_buffers(
+        builder: &mut RecordingCommandBuffer,
+        mesh: &BasicMesh,
+        bound_buffers: &mut Option<(Subbuffer<[u8]>, Subbuffer<[u8]>)>,
+    ) -> anyhow::Result<()> {
+        let (vertices, indices) = mesh.bound_buffers();
+        let same = |a: &Subbuffer<[u8]>, b: &Subbuffer<[u8]>| {
+            Arc::ptr_eq(a.buffer(), b.buffer()) && a.offset() == b.offset() && a.size() == b.size()
+        };
+        if let Some((bound_vertices, bound_indices)) = bound_buffers {
+            if same(bound_vertices, vertices) && same(bound_indices, indices.as_bytes()) {
+                return Ok(());
+            }
+        }
+
+        builder
+            .bind_vertex_buffers(0, vertices.clone())
+            .context("binding vertex buffer")?
+            .bind_index_buffer(indices.clone())
+            .context("binding index buffer")?;
+        *bound_buffers = Some((vertices.clone(), indices.as_bytes().clone()));
+        Ok(())
+    }
+
+    /// Binds `layout`'s G-buffer pipeline for a material with `features`, or its depth pre-pass
+    /// pipeline when `features` is `None`, unless it's bound already.
+    fn bind_layout_pipeline(
+        builder: &mut RecordingCommandBuffer,
+        pipelines: &HashMap<VertexLayout, LayoutPipelines>,
+        layout: VertexLayout,
+        features: Option<MaterialFeatures>,
+        bound_layout: &mut Option<(VertexLayout, Option<MaterialFeatures>)>,
+    ) -> anyhow::Result<()> {
+        if *bound_layout == Some((layout, features)) {
+            return Ok(());
+        }
+
+        let pipelines = pipelines
+            .get(&layout)
+            .with_context(|| format!("no pipelines for {:?} vertex layout", layout))?;
+        let pipeline = match features {
+            Some(features) => pipelines.pipelines.get(&features).with_context(|| {
+                format!("no {:?} pipeline for {:?} materials", layout, features)
+            })?,
+            None => &pipelines.depth_pipeline,
+        };
+        builder
+            .bind_pipeline_graphics(pipeline.clone())
+            .context("binding pipeline graphics")?;
+        *bound_layout = Some((layout, features));
+
+        Ok(())
+    }
+
+    fn bind_material(
         &self,
-        render_data: &RenderData,
-    ) -> anyhow::Result<impl DescriptorSetsCollection> {
-        // Update the object data buffer
-        let object_buffer_span = span!(Level::INFO, "update object buffer").entered();
+        builder: &mut RecordingCommandBuffer,
+        material_index: usize,
+        bound_material: &mut Option<usize>,
+    ) -> anyhow::Result<()> {
+        if *bound_material == Some(material_index) {
+            return Ok(());
+        }
 
-        let objects = render_data.object_data();
+        builder
+            .bind_descriptor_sets(
+                vulkano::pipeline::PipelineBindPoint::Graphics,
+                self.pipeline_layout.clone(),
+                BASE_COLOR_TEXTURE_BINDING.set,
+                self.materials[material_index].descriptor_set.clone(),
+            )
+            .context("binding material descriptor set")?;
+        *bound_material = Some(material_index);
 
-        let object_data_buffer = self
-            .storage_buffer_allocator
-            .allocate_slice(objects.len() as _)?;
+        Ok(())
+    }
 
-        object_data_buffer.write()?.copy_from_slice(&objects);
+    /// Creates the G-buffer, depth pre-pass, selection mask, object ID, shadow and portal view
+    /// pipelines for `V`'s layout, unless they exist.
+    fn create_layout_pipelines<V: MeshVertex>(&mut self) -> anyhow::Result<()> {
+        if self.pipelines.contains_key(&V::LAYOUT) {
+            return Ok(());
+        }
 
-        object_buffer_span.exit();
+        let device = self.gfx_queue.device();
+        let vs = load_vertex_shader(device, V::LAYOUT)?;
+        validate_descriptor_bindings(
+            "GeometrySystem",
+            &[&vs],
+            &[FRAME_DATA_BINDING, OBJECT_DATA_BINDING],
+        )?;
 
-        // (re)create the object data descriptor set
-        let span_ds = span!(Level::INFO, "create object descriptor set").entered();
-        let object_data_buffer_set = DescriptorSet::new(
-            self.descriptor_set_allocator.clone(),
-            self.pipeline.layout().set_layouts()[1].clone(),
-            [WriteDescriptorSet::buffer(0, object_data_buffer)],
-            [],
+        let vertex_input_state = V::per_vertex()
+            .definition(&vs.info().input_interface)
+            .context("matching vertex layout to geometry shader")?;
+
+        // The depth pre-pass shares the vertex buffers, so only the position attribute is
+        // pulled out of the full vertex
+        let depth_vertex_input_state = V::per_vertex()
+            .definition(&self.depth_vs.info().input_interface)
+            .context("matching vertex layout to depth pre-pass shader")?;
+
+        let depth_pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: [PipelineShaderStageCreateInfo::new(self.depth_vs.clone())]
+                    .into_iter()
+                    .collect(),
+                vertex_input_state: Some(depth_vertex_input_state.clone()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(self.depth_subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(self.pipeline_layout.clone())
+            },
         )
-        .context("Creating Object Data Descriptor Set")?;
-        span_ds.exit();
+        .context("creating depth pre-pass pipeline")?;
 
-        // Update the uniform buffer
-        let uniform_buffer: Subbuffer<FrameData> =
-            self.uniform_buffer_allocator.allocate_sized()?;
+        let mask_pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: [
+                    PipelineShaderStageCreateInfo::new(self.depth_vs.clone()),
+                    PipelineShaderStageCreateInfo::new(self.mask_fs.clone()),
+                ]
+                .into_iter()
+                .collect(),
+                vertex_input_state: Some(depth_vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    self.mask_subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(self.mask_subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(self.pipeline_layout.clone())
+            },
+        )
+        .context("creating selection mask pipeline")?;
 
-        *uniform_buffer.write()? = FrameData {
-            view: render_data.cam_matrices().1.into(),
-            proj: render_data.cam_matrices().0.into(),
+        let object_id_pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: [
+                    PipelineShaderStageCreateInfo::new(vs.clone()),
+                    PipelineShaderStageCreateInfo::new(self.object_id_fs.clone()),
+                ]
+                .into_iter()
+                .collect(),
+                vertex_input_state: Some(vertex_input_state.clone()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    self.object_id_subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(self.object_id_subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(self.object_id_layout.clone())
+            },
+        )
+        .context("creating object ID pipeline")?;
+
+        // Shadows are drawn without culling, so meshes that aren't closed still cast them
+        let shadow_pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: [PipelineShaderStageCreateInfo::new(self.shadow_vs.clone())]
+                    .into_iter()
+                    .collect(),
+                vertex_input_state: Some(
+                    V::per_vertex()
+                        .definition(&self.shadow_vs.info().input_interface)
+                        .context("matching vertex layout to shadow shader")?,
+                ),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(self.shadow_subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(self.shadow_layout.clone())
+            },
+        )
+        .context("creating shadow pipeline")?;
+
+        let portal_pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: [
+                    PipelineShaderStageCreateInfo::new(vs.clone()),
+                    PipelineShaderStageCreateInfo::new(self.portal_fs.clone()),
+                ]
+                .into_iter()
+                .collect(),
+                vertex_input_state: Some(vertex_input_state.clone()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    self.portal_subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(self.portal_subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(self.portal_layout.clone())
+            },
+        )
+        .context("creating portal view pipeline")?;
+
+        let mut pipelines = HashMap::new();
+        let features_in_use: HashSet<MaterialFeatures> = self
+            .materials
+            .iter()
+            .map(|material| material.features)
+            .collect();
+        for features in features_in_use {
+            let pipeline =
+                self.create_geometry_pipeline(V::LAYOUT, &vs, &vertex_input_state, features)?;
+            pipelines.insert(features, pipeline);
+        }
+
+        self.debug_namer.name(
+            depth_pipeline.as_ref(),
+            &format!("{:?} depth pre-pass pipeline", V::LAYOUT),
+        );
+        self.debug_namer.name(
+            mask_pipeline.as_ref(),
+            &format!("{:?} selection mask pipeline", V::LAYOUT),
+        );
+        self.debug_namer.name(
+            object_id_pipeline.as_ref(),
+            &format!("{:?} object ID pipeline", V::LAYOUT),
+        );
+        self.debug_namer.name(
+            shadow_pipeline.as_ref(),
+            &format!("{:?} shadow pipeline", V::LAYOUT),
+        );
+        self.debug_namer.name(
+            portal_pipeline.as_ref(),
+            &format!("{:?} portal view pipeline", V::LAYOUT),
+        );
+
+        self.pipelines.insert(
+            V::LAYOUT,
+            LayoutPipelines {
+                vs,
+                vertex_input_state,
+                pipelines,
+                depth_pipeline,
+                mask_pipeline,
+                object_id_pipeline,
+                shadow_pipeline,
+                portal_pipeline,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Builds the G-buffer pipelines for materials with `features`, for every vertex layout that
+    /// has pipelines, unless they exist.
+    fn create_feature_pipelines(&mut self, features: MaterialFeatures) -> anyhow::Result<()> {
+        let layouts: Vec<VertexLayout> = self
+            .pipelines
+            .iter()
+            .filter(|(_, pipelines)| !pipelines.pipelines.contains_key(&features))
+            .map(|(layout, _)| *layout)
+            .collect();
+        for layout in layouts {
+            let (vs, vertex_input_state) = {
+                let pipelines = &self.pipelines[&layout];
+                (pipelines.vs.clone(), pipelines.vertex_input_state.clone())
+            };
+            let pipeline =
+                self.create_geometry_pipeline(layout, &vs, &vertex_input_state, features)?;
+            self.pipelines
+                .get_mut(&layout)
+                .context("getting layout pipelines")?
+                .pipelines
+                .insert(features, pipeline);
+        }
+        Ok(())
+    }
+
+    fn create_geometry_pipeline(
+        &mut self,
+        layout: VertexLayout,
+        vs: &EntryPoint,
+        vertex_input_state: &VertexInputState,
+        features: MaterialFeatures,
+    ) -> anyhow::Result<Arc<GraphicsPipeline>> {
+        let fs = self.fs.get(&features.variant_key())?;
+        let pipeline = GraphicsPipeline::new(
+            self.gfx_queue.device().clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: [
+                    PipelineShaderStageCreateInfo::new(vs.clone()),
+                    PipelineShaderStageCreateInfo::new(fs),
+                ]
+                .into_iter()
+                .collect(),
+                vertex_input_state: Some(vertex_input_state.clone()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                // LESS_OR_EQUAL passes the fragments the depth pre-pass wrote, and works the
+                // same as LESS when the pre-pass is disabled
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        compare_op: CompareOp::LessOrEqual,
+                        ..DepthState::simple()
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    self.subpass.num_color_attachments(),
+                    ColorBlendAttachmentState::default(),
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(self.subpass.clone().into()),
+                ..GraphicsPipelineCreateInfo::layout(self.pipeline_layout.clone())
+            },
+        )
+        .context("creating graphics pipeline")?;
+        self.debug_namer.name(
+            pipeline.as_ref(),
+            &format!(
+                "{:?} geometry pipeline for {:?} materials",
+                layout, features
+            ),
+        );
+        Ok(pipeline)
+    }
+
+    /// Pushes this frame's camera and object data into the ring buffers and returns the
+    /// persistent descriptor sets with the offsets to bind them at.
+    fn write_frame_data(
+        &mut self,
+        frame_index: usize,
+    ) -> anyhow::Result<Vec<DescriptorSetWithOffsets>> {
+        profile_scope!("write frame data");
+
+        let objects = self.render_data.object_data();
+        let objects_size = std::mem::size_of_val(objects.as_slice()) as DeviceSize;
+
+        if objects_size > self.object_data_ring.region_size() {
+            self.grow_object_data_ring(objects.len())?;
+        }
+
+        self.frame_data_ring.begin_frame(frame_index);
+        self.object_data_ring.begin_frame(frame_index);
+
+        let (proj, view) = self.camera_matrices();
+        let view_projection = self.view_projection();
+        let frame_data_offset = self.frame_data_ring.push(&[FrameData {
+            view: view.into(),
+            proj: proj.into(),
+            view_projection: view_projection.into(),
+            previous_view_projection: self
+                .previous_view_projection
+                .unwrap_or(view_projection)
+                .into(),
+            srgb_colors: self.srgb_colors as u32,
+        }])?;
+        self.previous_view_projection = Some(view_projection);
+
+        // Overlays are drawn with the same jitter. Their motion vectors are against their own
+        // last frame, so something following the camera around doesn't smear
+        let jitter = Matrix4::from_translation(Vector3::new(self.jitter[0], self.jitter[1], 0.0));
+        let mut overlay_frame_offsets = vec![];
+        let mut overlay_view_projections = vec![];
+        for (index, camera) in self.overlay_cameras.iter().enumerate() {
+            let view_projection = camera.projection * camera.view;
+            overlay_frame_offsets.push(
+                self.frame_data_ring.push(&[FrameData {
+                    view: camera.view.into(),
+                    proj: (jitter * camera.projection).into(),
+                    view_projection: view_projection.into(),
+                    previous_view_projection: self
+                        .previous_overlay_view_projections
+                        .get(index)
+                        .copied()
+                        .unwrap_or(view_projection)
+                        .into(),
+                    srgb_colors: self.srgb_colors as u32,
+                }])?,
+            );
+            overlay_view_projections.push(view_projection);
+        }
+        self.previous_overlay_view_projections = overlay_view_projections;
+
+        // Portal views and probe faces have no motion vectors, and aren't jittered so the portals
+        // show a steady image whatever TAA does with the rest of the frame
+        let mut portal_frame_offsets = vec![];
+        for (proj, view) in self.portal_views.iter().chain(&self.capture_views) {
+            let view_projection = proj * view;
+            portal_frame_offsets.push(self.frame_data_ring.push(&[FrameData {
+                view: (*view).into(),
+                proj: (*proj).into(),
+                view_projection: view_projection.into(),
+                previous_view_projection: view_projection.into(),
+                srgb_colors: self.srgb_colors as u32,
+            }])?);
+        }
+
+        let object_data_offset = self.object_data_ring.push(&objects)?;
+        self.object_data_offset = object_data_offset;
+        self.frame_data_ring.flush()?;
+        self.object_data_ring.flush()?;
+
+        let sets_for = |frame_data_offset: u32| {
+            vec![
+                DescriptorSetWithOffsets::new(self.frame_data_set.clone(), [frame_data_offset]),
+                DescriptorSetWithOffsets::new(self.object_data_set.clone(), [object_data_offset]),
+            ]
         };
+        self.overlay_sets = overlay_frame_offsets.into_iter().map(sets_for).collect();
+        let capture_frame_offsets = portal_frame_offsets.split_off(self.portal_views.len());
+        self.portal_sets = portal_frame_offsets.into_iter().map(sets_for).collect();
+        self.capture_sets = capture_frame_offsets.into_iter().map(sets_for).collect();
 
-        // (re)create the uniform buffer descriptor set
-        let uniform_set = span!(Level::INFO, "create uniform descriptor set").entered();
-        let uniform_buffer_set = DescriptorSet::new(
-            self.descriptor_set_allocator.clone(),
-            self.pipeline.layout().set_layouts()[0].clone(),
-            [WriteDescriptorSet::buffer(0, uniform_buffer)],
+        Ok(sets_for(frame_data_offset))
+    }
+
+    /// Writes a draw command per queued object, with culled objects drawing zero instances. The
+    /// object's index goes through `first_instance`, the same as the per-object draws.
+    fn write_indirect_draws(
+        &mut self,
+        frame_index: usize,
+    ) -> anyhow::Result<Option<IndirectDraws>> {
+        let commands: Vec<DrawIndexedIndirectCommand> = self
+            .render_data
+            .render_iter()
+            .map(|(index, mesh, _)| DrawIndexedIndirectCommand {
+                index_count: mesh.index_buffer.len() as u32,
+                instance_count: self.is_drawn(index as usize) as u32,
+                first_index: mesh.first_index(),
+                vertex_offset: mesh.vertex_offset(),
+                first_instance: index,
+            })
+            .collect();
+
+        if commands.is_empty() {
+            return Ok(None);
+        }
+
+        let commands_size = std::mem::size_of_val(commands.as_slice()) as DeviceSize;
+        if commands_size > self.indirect_ring.region_size() {
+            let capacity = commands.len().next_power_of_two();
+            log::debug!("growing indirect draw ring buffer to {} draws", capacity);
+            self.indirect_ring = RingBuffer::new(
+                self.memory_allocator.clone(),
+                BufferUsage::INDIRECT_BUFFER,
+                (capacity * std::mem::size_of::<DrawIndexedIndirectCommand>()) as DeviceSize,
+                self.frames_in_flight,
+            )
+            .context("growing indirect draw ring buffer")?;
+        }
+
+        self.indirect_ring.begin_frame(frame_index);
+        let commands = self.indirect_ring.push_slice(&commands)?;
+
+        Ok(Some(IndirectDraws {
+            commands,
+            runs: self.render_data.mesh_runs(),
+        }))
+    }
+
+    /// Replaces the object data ring with one large enough for `object_count` objects. Frames
+    /// still in flight keep the old buffer alive through their command buffers.
+    fn grow_object_data_ring(&mut self, object_count: usize) -> anyhow::Result<()> {
+        let capacity = object_count.next_power_of_two();
+        log::debug!("growing object data ring buffer to {} objects", capacity);
+
+        self.object_data_ring = RingBuffer::persistent(
+            self.memory_allocator.clone(),
+            BufferUsage::STORAGE_BUFFER,
+            (capacity * std::mem::size_of::<ObjectData>()) as DeviceSize,
+            self.frames_in_flight,
+        )
+        .context("growing object data ring buffer")?;
+
+        self.object_data_set = Self::create_ring_descriptor_set(
+            &self.descriptor_set_allocator,
+            &self.pipeline_layout,
+            OBJECT_DATA_BINDING,
+            &self.object_data_ring,
+            self.object_data_ring.region_size(),
+        )
+        .context("recreating object data descriptor set")?;
+
+        Ok(())
+    }
+
+    fn create_ring_descriptor_set(
+        descriptor_set_allocator: &Arc<StandardDescriptorSetAllocator>,
+        pipeline_layout: &Arc<PipelineLayout>,
+        binding: DescriptorBinding,
+        ring: &RingBuffer,
+        range: DeviceSize,
+    ) -> anyhow::Result<Arc<DescriptorSet>> {
+        DescriptorSet::new(
+            descriptor_set_allocator.clone(),
+            pipeline_layout.set_layouts()[binding.set as usize].clone(),
+            [WriteDescriptorSet::buffer_with_range(
+                binding.binding,
+                DescriptorBufferInfo {
+                    buffer: ring.buffer().clone(),
+                    range: 0..range,
+                },
+            )],
             [],
         )
-        .context("creating uniform buffer descriptor set")?;
-        uniform_set.exit();
-        Ok(vec![uniform_buffer_set, object_data_buffer_set])
+        .context("creating ring buffer descriptor set")
     }
 }
@@ -1,4 +1,12 @@
-use vulkano::{buffer::BufferContents, pipeline::graphics::vertex_input::Vertex};
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    buffer::BufferContents, device::Device, pipeline::graphics::vertex_input::Vertex,
+    shader::EntryPoint,
+};
+
+use super::vertex::{MeshVertex, VertexLayout};
 
 #[repr(C)]
 #[derive(Clone, Copy, BufferContents, Vertex)]
@@ -11,10 +19,77 @@ pub struct VertexPositionColorNormal {
     normal: [f32; 3],
 }
 
+impl VertexPositionColorNormal {
+    pub fn new(position: [f32; 3], color: [f32; 3], normal: [f32; 3]) -> Self {
+        VertexPositionColorNormal {
+            position,
+            color,
+            normal,
+        }
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        self.color
+    }
+
+    pub fn normal(&self) -> [f32; 3] {
+        self.normal
+    }
+}
+
+impl MeshVertex for VertexPositionColorNormal {
+    const LAYOUT: VertexLayout = VertexLayout::PositionColorNormal;
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
 pub mod vs {
     vulkano_shaders::shader! {
         ty: "vertex",
         path: "assets/shaders/deferred/geometry.vert",
+        define: [("HAS_COLOR", "1"), ("HAS_NORMAL", "1")],
+    }
+}
+
+/// The geometry vertex shader built for each of the other vertex layouts.
+pub mod vs_position {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/deferred/geometry.vert",
+    }
+}
+
+pub mod vs_normal_uv {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/deferred/geometry.vert",
+        define: [("HAS_NORMAL", "1"), ("HAS_UV", "1")],
+    }
+}
+
+pub mod vs_normal_uv_tangent {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/deferred/geometry.vert",
+        define: [("HAS_NORMAL", "1"), ("HAS_UV", "1"), ("HAS_TANGENT", "1")],
+    }
+}
+
+pub mod vs_normal_uv_lightmap {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/deferred/geometry.vert",
+        define: [("HAS_NORMAL", "1"), ("HAS_UV", "1"), ("HAS_LIGHTMAP_UV", "1")],
+    }
+}
+
+pub mod vs_skinned {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/deferred/geometry.vert",
+        define: [("HAS_NORMAL", "1"), ("HAS_UV", "1"), ("HAS_SKIN", "1")],
     }
 }
 
@@ -25,6 +100,67 @@ pub mod fs {
     }
 }
 
+/// Position-only vertex shader for the depth pre-pass.
+pub mod depth_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/deferred/depth.vert",
+    }
+}
+
+/// Position-only vertex shader drawing shadow casters into a shadow map cascade.
+pub mod shadow_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/deferred/shadow.vert",
+    }
+}
+
+/// Fills the selection mask, drawn with `depth_vs`.
+pub mod mask_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/outline/mask.frag",
+    }
+}
+
+/// Writes the object being drawn into the object ID target, drawn with the geometry vertex
+/// shaders.
+pub mod object_id_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/picking/object_id.frag",
+    }
+}
+
+/// Forward shades the scene seen through a portal, drawn with the geometry vertex shaders.
+pub mod portal_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/portal/view.frag",
+    }
+}
+
+/// Loads the geometry vertex shader matching `layout`'s attributes.
+pub fn load_vertex_shader(
+    device: &Arc<Device>,
+    layout: VertexLayout,
+) -> anyhow::Result<EntryPoint> {
+    let module = match layout {
+        VertexLayout::PositionColorNormal => vs::load(device.clone()),
+        VertexLayout::Position => vs_position::load(device.clone()),
+        VertexLayout::PositionNormalUv => vs_normal_uv::load(device.clone()),
+        VertexLayout::PositionNormalUvTangent => vs_normal_uv_tangent::load(device.clone()),
+        VertexLayout::PositionNormalUvLightmap => vs_normal_uv_lightmap::load(device.clone()),
+        VertexLayout::Skinned => vs_skinned::load(device.clone()),
+    }
+    .with_context(|| format!("creating {:?} vertex shader module", layout))?;
+
+    module
+        .entry_point("main")
+        .context("vertex shader entry point not found")
+}
+
 pub const CUBE_VERTICES: [VertexPositionColorNormal; 24] = [
     // Front face
     VertexPositionColorNormal {
@@ -0,0 +1,427 @@
+use std::{ops::Range, sync::Arc};
+
+use anyhow::Context;
+use cgmath::Matrix4;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        DrawIndexedIndirectCommand, RecordingCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, layout::DescriptorType, DescriptorBufferInfo,
+        DescriptorSet, DescriptorSetWithOffsets, WriteDescriptorSet,
+    },
+    device::{Queue, QueueFlags},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    DeviceSize,
+};
+
+use crate::game::Frustum;
+
+use super::{
+    frames_in_flight::InFlightFrame,
+    layers::RenderLayers,
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    render_data::RenderData,
+    ring_buffer::RingBuffer,
+    vulkan_context::VulkanContext,
+};
+
+const FRUSTUM_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::UniformBuffer);
+const CULL_OBJECTS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::StorageBuffer);
+const COMMANDS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 2, DescriptorType::StorageBuffer);
+const COUNTS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 3, DescriptorType::StorageBuffer);
+const OBJECT_DATA_BINDING: DescriptorBinding =
+    DescriptorBinding::new(1, 0, DescriptorType::StorageBufferDynamic);
+
+/// Must match `local_size_x` in cull.comp.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// A frame slot's buffers. The commands and counts are written by the slot's dispatch and read by
+/// its draws, so the slots can't share them.
+struct CullSlot {
+    /// The `RenderData::generation` the cull objects were written for.
+    generation: Option<u64>,
+    object_capacity: usize,
+    run_capacity: usize,
+    object_count: u32,
+    /// The mesh index, material index and object indices of each run, as written.
+    runs: Vec<(usize, usize, Range<u32>)>,
+    frustum: Subbuffer<cull_cs::Frustum>,
+    objects: Subbuffer<[cull_cs::CullObject]>,
+    commands: Subbuffer<[DrawIndexedIndirectCommand]>,
+    counts: Subbuffer<[u32]>,
+    descriptor_set: Arc<DescriptorSet>,
+    _memory: TrackedMemory,
+}
+
+/// The main camera's draws for a frame, as culled by the GPU. Each run's visible objects are
+/// packed at the start of the run's range of `commands`, with the run's entry of `counts` saying
+/// how many there are.
+#[derive(Clone)]
+pub struct CulledDraws {
+    pub commands: Subbuffer<[DrawIndexedIndirectCommand]>,
+    pub counts: Subbuffer<[u32]>,
+    pub runs: Vec<(usize, usize, Range<u32>)>,
+}
+
+/// Culls the queued objects against the main camera's frustum and layers in a compute pass,
+/// writing the indirect draws of the ones left, so the CPU doesn't look at each object every
+/// frame. What culling needs of the objects is only written again when the queued objects
+/// change, and their model matrices are read from the object data the draws use, so a retained
+/// scene costs the CPU nothing per object.
+pub struct GpuCuller {
+    gfx_queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    memory_tracker: MemoryTracker,
+    pipeline: Arc<ComputePipeline>,
+    slots: Vec<Option<CullSlot>>,
+    /// The set reading the object data ring, with the ring buffer it was written for.
+    object_data_set: Option<(Subbuffer<[u8]>, Arc<DescriptorSet>)>,
+}
+
+impl GpuCuller {
+    /// `None` when the graphics queue can't run compute shaders.
+    pub fn new(context: &VulkanContext, frames_in_flight: usize) -> anyhow::Result<Option<Self>> {
+        let gfx_queue = context.graphics_queue().clone();
+        let device = context.device();
+
+        let supports_compute = device.physical_device().queue_family_properties()
+            [gfx_queue.queue_family_index() as usize]
+            .queue_flags
+            .intersects(QueueFlags::COMPUTE);
+        if !supports_compute {
+            log::info!("graphics queue has no compute support, culling on the CPU");
+            return Ok(None);
+        }
+
+        let cs = cull_cs::load(device.clone())
+            .context("loading culling shader")?
+            .entry_point("main")
+            .context("culling shader entry point not found")?;
+        validate_descriptor_bindings(
+            "GpuCuller",
+            &[&cs],
+            &[
+                FRUSTUM_BINDING,
+                CULL_OBJECTS_BINDING,
+                COMMANDS_BINDING,
+                COUNTS_BINDING,
+                DescriptorBinding::new(1, 0, DescriptorType::StorageBuffer),
+            ],
+        )?;
+
+        // The object data is bound with dynamic offsets into its ring buffer, as it is for the
+        // draws
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let mut layout_create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage]);
+        layout_create_info.set_layouts[OBJECT_DATA_BINDING.set as usize]
+            .bindings
+            .get_mut(&OBJECT_DATA_BINDING.binding)
+            .context("getting culling object buffer binding")?
+            .descriptor_type = OBJECT_DATA_BINDING.ty;
+        let layout = PipelineLayout::new(
+            device.clone(),
+            layout_create_info
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating culling pipeline layout create info")?,
+        )
+        .context("creating culling pipeline layout")?;
+
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .context("creating culling pipeline")?;
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "culling pipeline");
+
+        Ok(Some(GpuCuller {
+            gfx_queue,
+            memory_allocator: context.memory_allocator().clone(),
+            descriptor_set_allocator: context.descriptor_set_allocator().clone(),
+            memory_tracker: context.memory_tracker().clone(),
+            pipeline,
+            slots: (0..frames_in_flight).map(|_| None).collect(),
+            object_data_set: None,
+        }))
+    }
+
+    /// Writes the frame's frustum, and the queued objects when they changed since the slot last
+    /// culled them. Returns `None` when nothing is queued.
+    pub fn prepare(
+        &mut self,
+        frame_index: usize,
+        render_data: &RenderData,
+        view_projection: Matrix4<f32>,
+        camera_layers: RenderLayers,
+    ) -> anyhow::Result<Option<CulledDraws>> {
+        let object_count = render_data.object_count();
+        if object_count == 0 {
+            return Ok(None);
+        }
+
+        let stale = match &self.slots[frame_index] {
+            Some(slot) => slot.generation != Some(render_data.generation()),
+            None => true,
+        };
+        if stale {
+            profile_scope!("write cull objects");
+            let runs = render_data.mesh_runs();
+            let fits = self.slots[frame_index].as_ref().is_some_and(|slot| {
+                slot.object_capacity >= object_count && slot.run_capacity >= runs.len()
+            });
+            if !fits {
+                let slot = self.create_slot(object_count, runs.len())?;
+                self.slots[frame_index] = Some(slot);
+            }
+            let slot = self.slots[frame_index]
+                .as_mut()
+                .context("getting culling slot")?;
+
+            let mut objects = slot.objects.write().context("writing cull objects")?;
+            for (run, (_, _, range)) in runs.iter().enumerate() {
+                for index in range.clone() {
+                    let mesh = render_data.object_mesh(index as usize);
+                    objects[index as usize] = cull_cs::CullObject {
+                        bounds_min: [
+                            mesh.bounds.min[0],
+                            mesh.bounds.min[1],
+                            mesh.bounds.min[2],
+                            0.0,
+                        ],
+                        bounds_max: [
+                            mesh.bounds.max[0],
+                            mesh.bounds.max[1],
+                            mesh.bounds.max[2],
+                            0.0,
+                        ],
+                        index_count: mesh.index_count(),
+                        first_index: mesh.first_index(),
+                        vertex_offset: mesh.vertex_offset(),
+                        layers: render_data.object_layers()[index as usize].0,
+                        run: run as u32,
+                        run_start: range.start,
+                        pad0: 0,
+                        pad1: 0,
+                    };
+                }
+            }
+            drop(objects);
+            slot.object_count = object_count as u32;
+            slot.runs = runs;
+            slot.generation = Some(render_data.generation());
+        }
+
+        let slot = self.slots[frame_index]
+            .as_mut()
+            .context("getting culling slot")?;
+        let frustum = Frustum::from_view_projection(view_projection);
+        *slot.frustum.write().context("writing culling frustum")? = cull_cs::Frustum {
+            planes: frustum.planes.map(|plane| {
+                [
+                    plane.normal.x,
+                    plane.normal.y,
+                    plane.normal.z,
+                    plane.distance,
+                ]
+            }),
+            object_count: slot.object_count,
+            camera_layers: camera_layers.0,
+        };
+
+        Ok(Some(CulledDraws {
+            commands: slot.commands.clone(),
+            counts: slot.counts.clone(),
+            runs: slot.runs.clone(),
+        }))
+    }
+
+    /// Records the culling of the objects `prepare` wrote for `frame`'s slot, reading their
+    /// model matrices from the frame's object data at `object_data_offset`. Must be executed
+    /// before the frame's draws.
+    pub fn record(
+        &mut self,
+        frame: &InFlightFrame,
+        object_data: &RingBuffer,
+        object_data_offset: u32,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        let Some(slot) = self.slots[frame.index].as_ref() else {
+            return Ok(None);
+        };
+
+        let current = self
+            .object_data_set
+            .as_ref()
+            .is_some_and(|(buffer, _)| Arc::ptr_eq(buffer.buffer(), object_data.buffer().buffer()));
+        if !current {
+            let descriptor_set = DescriptorSet::new(
+                self.descriptor_set_allocator.clone(),
+                self.pipeline.layout().set_layouts()[OBJECT_DATA_BINDING.set as usize].clone(),
+                [WriteDescriptorSet::buffer_with_range(
+                    OBJECT_DATA_BINDING.binding,
+                    DescriptorBufferInfo {
+                        buffer: object_data.buffer().clone(),
+                        range: 0..object_data.region_size(),
+                    },
+                )],
+                [],
+            )
+            .context("creating culling object data descriptor set")?;
+            self.object_data_set = Some((object_data.buffer().clone(), descriptor_set));
+        }
+        let (_, object_data_set) = self
+            .object_data_set
+            .as_ref()
+            .context("getting culling object data set")?;
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating culling command buffer")?;
+
+        builder
+            .fill_buffer(slot.counts.clone(), 0)
+            .context("clearing draw counts")?
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("binding culling pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                vec![
+                    slot.descriptor_set.clone().into(),
+                    DescriptorSetWithOffsets::new(object_data_set.clone(), [object_data_offset]),
+                ],
+            )
+            .context("binding culling descriptor sets")?;
+        unsafe { builder.dispatch([slot.object_count.div_ceil(WORKGROUP_SIZE), 1, 1]) }
+            .context("dispatching culling")?;
+
+        builder
+            .end()
+            .context("ending culling command buffer")
+            .map(Some)
+    }
+
+    fn create_slot(&self, object_count: usize, run_count: usize) -> anyhow::Result<CullSlot> {
+        let object_capacity = object_count.next_power_of_two();
+        let run_capacity = run_count.next_power_of_two();
+        log::debug!(
+            "creating culling buffers for {} objects in {} runs",
+            object_capacity,
+            run_capacity
+        );
+
+        let frustum = Buffer::new_sized::<cull_cs::Frustum>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        )
+        .context("creating culling frustum buffer")?;
+        let objects = Buffer::new_slice::<cull_cs::CullObject>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            object_capacity as DeviceSize,
+        )
+        .context("creating cull object buffer")?;
+        let commands = Buffer::new_slice::<DrawIndexedIndirectCommand>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::INDIRECT_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            object_capacity as DeviceSize,
+        )
+        .context("creating culled draw buffer")?;
+        let counts = Buffer::new_slice::<u32>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER
+                    | BufferUsage::INDIRECT_BUFFER
+                    | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            run_capacity as DeviceSize,
+        )
+        .context("creating draw count buffer")?;
+
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::buffer(FRUSTUM_BINDING.binding, frustum.clone()),
+                WriteDescriptorSet::buffer(CULL_OBJECTS_BINDING.binding, objects.clone()),
+                WriteDescriptorSet::buffer(COMMANDS_BINDING.binding, commands.clone()),
+                WriteDescriptorSet::buffer(COUNTS_BINDING.binding, counts.clone()),
+            ],
+            [],
+        )
+        .context("creating culling descriptor set")?;
+
+        let bytes = frustum.size() + objects.size() + commands.size() + counts.size();
+        Ok(CullSlot {
+            generation: None,
+            object_capacity,
+            run_capacity,
+            object_count: 0,
+            runs: vec![],
+            frustum,
+            objects,
+            commands,
+            counts,
+            descriptor_set,
+            _memory: self.memory_tracker.track(MemoryCategory::Meshes, bytes),
+        })
+    }
+}
+
+mod cull_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/culling/cull.comp"
+    }
+}
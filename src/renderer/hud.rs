@@ -0,0 +1,678 @@
+use std::{collections::VecDeque, sync::Arc, time::Instant};
+
+use anyhow::Context;
+use vulkano::{
+    buffer::{BufferContents, BufferUsage, Subbuffer},
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::layout::DescriptorType,
+    image::{sampler::Sampler, view::ImageView},
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+    DeviceSize,
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    ring_buffer::RingBuffer,
+    stats::{FrameStats, SceneStats},
+    vulkan_context::VulkanContext,
+};
+
+const ATLAS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+
+/// Marks a vertex as untextured, see hud.frag.
+const NO_UV: [f32; 2] = [-1.0, -1.0];
+
+/// Logical pixels per font pixel. Layout happens in logical pixels, which the UI scale maps to
+/// screen pixels.
+const TEXT_SCALE: f32 = 2.0;
+const GLYPH_WIDTH: f32 = 3.0;
+const GLYPH_HEIGHT: f32 = 5.0;
+const LINE_HEIGHT: f32 = (GLYPH_HEIGHT + 2.0) * TEXT_SCALE;
+const MARGIN: f32 = 8.0;
+/// Frames shown in the frame time graph, one bar each.
+const GRAPH_FRAMES: usize = 120;
+const GRAPH_HEIGHT: f32 = 60.0;
+/// Frame time at the top of the graph.
+const GRAPH_MAX_MS: f32 = 50.0;
+const INITIAL_VERTEX_CAPACITY: usize = 16 * 1024;
+
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const PANEL_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const TITLE_COLOR: [f32; 4] = [0.6, 0.8, 1.0, 1.0];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 1.0];
+
+#[derive(BufferContents, Vertex, Clone, Copy)]
+#[repr(C)]
+struct HudVertex {
+    #[format(R32G32_SFLOAT)]
+    position: [f32; 2],
+    #[format(R32G32B32A32_SFLOAT)]
+    color: [f32; 4],
+    #[format(R32G32_SFLOAT)]
+    uv: [f32; 2],
+}
+
+/// A shape drawn by the HUD under its own overlay, for building a game's UI. Positions and sizes
+/// are in logical pixels from the top left corner, colors are straight alpha and UVs are regions
+/// of the UI atlas as minimum and maximum UVs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UiPrimitive {
+    Rect {
+        position: [f32; 2],
+        size: [f32; 2],
+        color: [f32; 4],
+    },
+    /// A region of the atlas stretched over the rectangle, multiplied by `color`.
+    Image {
+        position: [f32; 2],
+        size: [f32; 2],
+        uv: [f32; 4],
+        color: [f32; 4],
+    },
+    /// A region of the atlas drawn as a panel whose corners keep their size. `border` is the
+    /// left, top, right and bottom widths of the region's border in atlas pixels, drawn one
+    /// logical pixel each. The edges stretch along the panel and the middle fills it.
+    NineSlice {
+        position: [f32; 2],
+        size: [f32; 2],
+        uv: [f32; 4],
+        border: [f32; 4],
+        color: [f32; 4],
+    },
+}
+
+/// What the HUD shows, gathered by the renderer each frame.
+pub struct HudStats<'a> {
+    pub frame: &'a FrameStats,
+    pub scene: &'a SceneStats,
+    /// Supplied by the game, the renderer doesn't know about entities.
+    pub entities: Option<usize>,
+}
+
+/// A titled list of lines drawn by the HUD whether or not the stats are visible, e.g. by an
+/// editor.
+#[derive(Debug, Clone, Default)]
+pub struct HudPanel {
+    pub title: String,
+    pub lines: Vec<String>,
+    /// Drawn in a highlight color with a marker in front.
+    pub highlighted: Option<usize>,
+}
+
+/// A performance overlay drawn over the final image: FPS, a frame time graph, CPU and GPU time
+/// per pass, draw counts and scene sizes. Text uses a built-in 3x5 pixel font, so only upper
+/// case letters, digits and a little punctuation are available. Queued `UiPrimitive`s are drawn
+/// underneath, sampling a single atlas texture.
+pub struct Hud {
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    atlas: Arc<ImageView>,
+    atlas_sampler: Arc<Sampler>,
+    queued: Vec<UiPrimitive>,
+    frames_in_flight: usize,
+    vertex_ring: RingBuffer,
+    memory_tracker: MemoryTracker,
+    _vertex_memory: TrackedMemory,
+    vertices: Option<Subbuffer<[HudVertex]>>,
+    visible: bool,
+    last_update: Option<Instant>,
+    frame_times: VecDeque<f32>,
+    panels: Vec<HudPanel>,
+    scale: f32,
+}
+
+impl Hud {
+    /// `atlas` is sampled with `atlas_sampler` until replaced with `set_atlas`.
+    pub fn new(
+        context: &VulkanContext,
+        subpass: Subpass,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+        frames_in_flight: usize,
+        atlas: Arc<ImageView>,
+        atlas_sampler: Arc<Sampler>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .context("loading HUD vertex shader")?
+                .entry_point("main")
+                .context("HUD vertex shader entry point not found")?;
+            let fs = fs::load(device.clone())
+                .context("loading HUD fragment shader")?
+                .entry_point("main")
+                .context("HUD fragment shader entry point not found")?;
+            validate_descriptor_bindings("Hud", &[&fs], &[ATLAS_BINDING])?;
+
+            let vertex_input_state = HudVertex::per_vertex()
+                .definition(&vs.info().input_interface)
+                .context("vertex input state")?;
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("creating pipeline layout create info")?,
+            )
+            .context("creating pipeline layout")?;
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(vertex_input_state),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        subpass.num_color_attachments(),
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend::alpha()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(subpass.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .context("creating HUD pipeline")?
+        };
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "HUD pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let vertex_ring = RingBuffer::new(
+            memory_allocator.clone(),
+            BufferUsage::VERTEX_BUFFER,
+            (INITIAL_VERTEX_CAPACITY * std::mem::size_of::<HudVertex>()) as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating HUD vertex ring buffer")?;
+        let memory_tracker = context.memory_tracker().clone();
+        let vertex_memory = memory_tracker.track(MemoryCategory::Ui, vertex_ring.buffer().size());
+
+        Ok(Hud {
+            pipeline,
+            memory_allocator,
+            descriptor_set_cache,
+            atlas,
+            atlas_sampler,
+            queued: vec![],
+            frames_in_flight,
+            vertex_ring,
+            memory_tracker,
+            _vertex_memory: vertex_memory,
+            vertices: None,
+            visible: false,
+            last_update: None,
+            frame_times: VecDeque::with_capacity(GRAPH_FRAMES),
+            panels: vec![],
+            scale: 1.0,
+        })
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    /// Replaces the texture UI primitives sample their `uv` regions from.
+    pub fn set_atlas(&mut self, atlas: Arc<ImageView>) {
+        self.atlas = atlas;
+    }
+
+    /// Queues a primitive for the next `update`, drawn in the order queued.
+    pub fn push(&mut self, primitive: UiPrimitive) {
+        self.queued.push(primitive);
+    }
+
+    /// Replaces the panels drawn from the next `update` on.
+    pub fn set_panels(&mut self, panels: Vec<HudPanel>) {
+        self.panels = panels;
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Screen pixels per logical pixel, applied from the next `update` on.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    /// Lays out this frame's overlay for an output of `extent` pixels. Must be called once per
+    /// frame, before `record`, even while hidden so the frame time graph stays current.
+    pub fn update(
+        &mut self,
+        frame_index: usize,
+        extent: [u32; 2],
+        stats: &HudStats,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let frame_ms = self
+            .last_update
+            .map(|last| now.duration_since(last).as_secs_f32() * 1000.0)
+            .unwrap_or(0.0);
+        self.last_update = Some(now);
+        if self.frame_times.len() == GRAPH_FRAMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_ms);
+
+        self.vertices = None;
+        let mut layout = Layout::new(extent, self.scale);
+        let [atlas_width, atlas_height, _] = self.atlas.image().extent();
+        for primitive in std::mem::take(&mut self.queued) {
+            layout.primitive(&primitive, [atlas_width as f32, atlas_height as f32]);
+        }
+        if self.visible {
+            self.layout_stats(&mut layout, stats);
+        }
+        self.layout_panels(&mut layout);
+        if layout.vertices.is_empty() {
+            return Ok(());
+        }
+
+        let vertices = layout.vertices;
+        let size = std::mem::size_of_val(vertices.as_slice()) as DeviceSize;
+        if size > self.vertex_ring.region_size() {
+            let capacity = vertices.len().next_power_of_two();
+            log::debug!("growing HUD vertex ring buffer to {} vertices", capacity);
+            self.vertex_ring = RingBuffer::new(
+                self.memory_allocator.clone(),
+                BufferUsage::VERTEX_BUFFER,
+                (capacity * std::mem::size_of::<HudVertex>()) as DeviceSize,
+                self.frames_in_flight,
+            )
+            .context("growing HUD vertex ring buffer")?;
+            self._vertex_memory = self
+                .memory_tracker
+                .track(MemoryCategory::Ui, self.vertex_ring.buffer().size());
+        }
+        self.vertex_ring.begin_frame(frame_index);
+        self.vertices = Some(self.vertex_ring.push_slice(&vertices)?);
+
+        Ok(())
+    }
+
+    /// Lays out the stats panel and frame time graph in the top left corner.
+    fn layout_stats(&self, layout: &mut Layout, stats: &HudStats) {
+        let average_ms = self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32;
+        let fps = if average_ms > 0.0 {
+            1000.0 / average_ms
+        } else {
+            0.0
+        };
+        let frame = stats.frame;
+        let ms = |value: Option<f32>| match value {
+            Some(value) => format!("{:6.2}", value),
+            None => "     -".to_string(),
+        };
+
+        let lines = [
+            format!("FPS {:4.0}  FRAME {:.2} MS", fps, average_ms),
+            format!(
+                "CPU {} MS  GPU {} MS",
+                ms(Some(frame.cpu_time_ms)),
+                ms(frame.gpu_time_ms)
+            ),
+            "PASS         CPU    GPU".to_string(),
+            format!("SHADOWS  {}", ms(Some(frame.cpu_pass_ms.shadows))),
+            format!("DEPTH    {}", ms(Some(frame.cpu_pass_ms.depth_prepass))),
+            format!("GEOMETRY {}", ms(Some(frame.cpu_pass_ms.geometry))),
+            format!(
+                "LIGHTING {} {}",
+                ms(Some(frame.cpu_pass_ms.lighting)),
+                ms(frame.gpu_scene_ms)
+            ),
+            format!(
+                "POST     {} {}",
+                ms(Some(frame.cpu_pass_ms.post_process)),
+                ms(frame.gpu_post_process_ms)
+            ),
+            format!("DRAWS {}  TRIS {}", frame.draw_calls, frame.triangles),
+            format!(
+                "OBJECTS {}  LIGHTS {}  ENTITIES {}",
+                stats.scene.objects,
+                stats.scene.point_lights,
+                stats
+                    .entities
+                    .map(|entities| entities.to_string())
+                    .unwrap_or_else(|| "-".to_string())
+            ),
+            format!(
+                "VRAM {}",
+                frame
+                    .vram_bytes
+                    .map(|bytes| format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0)))
+                    .unwrap_or_else(|| "-".to_string())
+            ),
+        ];
+
+        let text_width = lines
+            .iter()
+            .map(|line| line.len() as f32 * (GLYPH_WIDTH + 1.0) * TEXT_SCALE)
+            .fold(0.0, f32::max);
+        let graph_width = GRAPH_FRAMES as f32 * 2.0;
+        let panel_width = text_width.max(graph_width) + MARGIN * 2.0;
+        let panel_height = lines.len() as f32 * LINE_HEIGHT + GRAPH_HEIGHT + MARGIN * 3.0;
+        layout.rect([0.0, 0.0], [panel_width, panel_height], PANEL_COLOR);
+
+        // The GPU column only has the render pass as a whole, shown against the lighting row
+        // since it ends the render pass
+        for (index, line) in lines.iter().enumerate() {
+            layout.text(
+                [MARGIN, MARGIN + index as f32 * LINE_HEIGHT],
+                line,
+                TEXT_COLOR,
+            );
+        }
+
+        let graph_bottom = panel_height - MARGIN;
+        for (index, ms) in self.frame_times.iter().enumerate() {
+            let height = (ms / GRAPH_MAX_MS).min(1.0) * GRAPH_HEIGHT;
+            let color = if *ms <= 1000.0 / 60.0 {
+                [0.2, 0.9, 0.2, 1.0]
+            } else if *ms <= 1000.0 / 30.0 {
+                [0.9, 0.8, 0.2, 1.0]
+            } else {
+                [0.9, 0.2, 0.2, 1.0]
+            };
+            layout.rect(
+                [MARGIN + index as f32 * 2.0, graph_bottom - height],
+                [2.0, height],
+                color,
+            );
+        }
+        // 60 FPS
+        let target = graph_bottom - (1000.0 / 60.0) / GRAPH_MAX_MS * GRAPH_HEIGHT;
+        layout.rect([MARGIN, target], [graph_width, 1.0], [1.0, 1.0, 1.0, 0.5]);
+    }
+
+    /// Lays out the panels side by side from the top right corner, the first rightmost.
+    fn layout_panels(&self, layout: &mut Layout) {
+        let mut right = layout.size[0];
+        for panel in &self.panels {
+            let text_width = |line: &str| line.len() as f32 * (GLYPH_WIDTH + 1.0) * TEXT_SCALE;
+            // Room for the highlight marker in front of every line
+            let width = panel
+                .lines
+                .iter()
+                .map(|line| text_width(line) + 2.0 * (GLYPH_WIDTH + 1.0) * TEXT_SCALE)
+                .fold(text_width(&panel.title), f32::max)
+                + MARGIN * 2.0;
+            let height = (panel.lines.len() + 1) as f32 * LINE_HEIGHT + MARGIN * 2.0;
+            let left = right - width;
+            layout.rect([left, 0.0], [width, height], PANEL_COLOR);
+            layout.text([left + MARGIN, MARGIN], &panel.title, TITLE_COLOR);
+            for (index, line) in panel.lines.iter().enumerate() {
+                let top = MARGIN + (index + 1) as f32 * LINE_HEIGHT;
+                let (marker, color) = if panel.highlighted == Some(index) {
+                    ("> ", HIGHLIGHT_COLOR)
+                } else {
+                    ("  ", TEXT_COLOR)
+                };
+                layout.text([left + MARGIN, top], &format!("{}{}", marker, line), color);
+            }
+            right = left - MARGIN;
+        }
+    }
+
+    /// Draws the overlay laid out by `update`. Must be recorded inside the composite render pass,
+    /// whose viewport is already set.
+    pub fn record(&self, builder: &mut RecordingCommandBuffer) -> anyhow::Result<()> {
+        let Some(vertices) = self.vertices.clone() else {
+            return Ok(());
+        };
+
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &self.pipeline.layout().set_layouts()[0],
+            &[CachedWrite::ImageViewSampler(
+                ATLAS_BINDING.binding,
+                self.atlas.clone(),
+                self.atlas_sampler.clone(),
+            )],
+        )?;
+
+        let vertex_count = vertices.len() as u32;
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .context("binding HUD pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding HUD descriptor set")?
+            .bind_vertex_buffers(0, vertices)
+            .context("binding HUD vertices")?;
+        unsafe { builder.draw(vertex_count, 1, 0, 0) }.context("drawing HUD")?;
+
+        Ok(())
+    }
+}
+
+/// Builds triangles from rectangles in logical pixels, top left origin.
+struct Layout {
+    /// The output's size in logical pixels.
+    size: [f32; 2],
+    scale: [f32; 2],
+    vertices: Vec<HudVertex>,
+}
+
+impl Layout {
+    /// A layout over an output of `extent` screen pixels, `ui_scale` screen pixels to a logical
+    /// one.
+    fn new(extent: [u32; 2], ui_scale: f32) -> Self {
+        let ui_scale = ui_scale.max(0.1);
+        let [width, height] = extent.map(|size| size.max(1) as f32);
+        Layout {
+            size: [width / ui_scale, height / ui_scale],
+            scale: [2.0 * ui_scale / width, 2.0 * ui_scale / height],
+            vertices: vec![],
+        }
+    }
+
+    fn rect(&mut self, position: [f32; 2], size: [f32; 2], color: [f32; 4]) {
+        self.quad(position, size, None, color);
+    }
+
+    /// A rectangle showing the `uv` region of the atlas, or a flat color without one.
+    fn quad(&mut self, position: [f32; 2], size: [f32; 2], uv: Option<[f32; 4]>, color: [f32; 4]) {
+        let to_ndc = |x: f32, y: f32, u: usize, v: usize| HudVertex {
+            position: [x * self.scale[0] - 1.0, y * self.scale[1] - 1.0],
+            color,
+            uv: uv.map_or(NO_UV, |uv| [uv[u], uv[v]]),
+        };
+        let [x0, y0] = position;
+        let [x1, y1] = [x0 + size[0], y0 + size[1]];
+        self.vertices.extend([
+            to_ndc(x0, y0, 0, 1),
+            to_ndc(x1, y0, 2, 1),
+            to_ndc(x0, y1, 0, 3),
+            to_ndc(x0, y1, 0, 3),
+            to_ndc(x1, y0, 2, 1),
+            to_ndc(x1, y1, 2, 3),
+        ]);
+    }
+
+    /// Lays out `primitive` over an atlas of `atlas_size` pixels.
+    fn primitive(&mut self, primitive: &UiPrimitive, atlas_size: [f32; 2]) {
+        match *primitive {
+            UiPrimitive::Rect {
+                position,
+                size,
+                color,
+            } => self.rect(position, size, color),
+            UiPrimitive::Image {
+                position,
+                size,
+                uv,
+                color,
+            } => self.quad(position, size, Some(uv), color),
+            UiPrimitive::NineSlice {
+                position,
+                size,
+                uv,
+                border,
+                color,
+            } => {
+                // Borders wider than the panel are squeezed to meet in the middle
+                let fit = |start: f32, end: f32, length: f32| {
+                    let scale = (length / (start + end).max(f32::EPSILON)).min(1.0);
+                    (start * scale, end * scale)
+                };
+                let (left, right) = fit(border[0], border[2], size[0]);
+                let (top, bottom) = fit(border[1], border[3], size[1]);
+                let [x0, y0] = position;
+                let [x1, y1] = [x0 + size[0], y0 + size[1]];
+                let xs = [x0, x0 + left, x1 - right, x1];
+                let ys = [y0, y0 + top, y1 - bottom, y1];
+                let us = [
+                    uv[0],
+                    uv[0] + border[0] / atlas_size[0],
+                    uv[2] - border[2] / atlas_size[0],
+                    uv[2],
+                ];
+                let vs = [
+                    uv[1],
+                    uv[1] + border[1] / atlas_size[1],
+                    uv[3] - border[3] / atlas_size[1],
+                    uv[3],
+                ];
+                for row in 0..3 {
+                    for column in 0..3 {
+                        self.quad(
+                            [xs[column], ys[row]],
+                            [xs[column + 1] - xs[column], ys[row + 1] - ys[row]],
+                            Some([us[column], vs[row], us[column + 1], vs[row + 1]]),
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn text(&mut self, position: [f32; 2], text: &str, color: [f32; 4]) {
+        for (index, character) in text.chars().enumerate() {
+            let x = position[0] + index as f32 * (GLYPH_WIDTH + 1.0) * TEXT_SCALE;
+            for (row, bits) in glyph(character).iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        self.rect(
+                            [
+                                x + column as f32 * TEXT_SCALE,
+                                position[1] + row as f32 * TEXT_SCALE,
+                            ],
+                            [TEXT_SCALE, TEXT_SCALE],
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rows of a 3x5 glyph, top first, with the leftmost pixel in the highest bit. Unknown characters
+/// are blank.
+fn glyph(character: char) -> [u8; 5] {
+    match character.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '[' => [0b110, 0b100, 0b100, 0b100, 0b110],
+        ']' => [0b011, 0b001, 0b001, 0b001, 0b011],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        _ => [0; 5],
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/hud/hud.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/hud/hud.frag"
+    }
+}
@@ -0,0 +1,480 @@
+use std::{ops::Range, sync::Arc};
+
+use anyhow::Context;
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, ClearColorImageInfo, CommandBufferBeginInfo,
+        CommandBufferLevel, CommandBufferUsage, RecordingCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, layout::DescriptorType, DescriptorSet,
+        WriteDescriptorSet,
+    },
+    device::Queue,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode},
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        Image, ImageAspects, ImageCreateFlags, ImageCreateInfo, ImageSubresourceRange, ImageType,
+        ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    shader::EntryPoint,
+    sync::GpuFuture,
+};
+
+use super::{
+    probe::MAX_REFLECTION_PROBES,
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Roughness goes from 0 at the first level to 1 at the last.
+const PREFILTERED_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 128;
+
+const SOURCE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const OUTPUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::StorageImage);
+const LUT_OUTPUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::StorageImage);
+
+/// The maps the ambient light samples for image-based lighting.
+#[derive(Clone)]
+pub struct EnvironmentMaps {
+    /// Cosine weighted irradiance for each normal direction.
+    pub irradiance: Arc<ImageView>,
+    /// Radiance prefiltered for increasing roughness down the mip chain.
+    pub prefiltered: Arc<ImageView>,
+    /// Scale and bias to F0 by view angle and roughness, shared by every environment.
+    pub brdf_lut: Arc<ImageView>,
+    /// Each reflection probe's radiance, prefiltered like `prefiltered`, as a cube array with a
+    /// cube per probe. Shared by every environment.
+    pub probes: Arc<ImageView>,
+    pub sampler: Arc<Sampler>,
+}
+
+impl EnvironmentMaps {
+    /// The mip level sampled at full roughness.
+    pub fn max_reflection_lod(&self) -> f32 {
+        (PREFILTERED_LEVELS - 1) as f32
+    }
+}
+
+/// Convolves equirectangular environment images into `EnvironmentMaps` with compute shaders,
+/// and reflection probe captures into the probe atlas.
+pub struct EnvironmentBaker {
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    debug_namer: DebugNamer,
+    irradiance_pipeline: Arc<ComputePipeline>,
+    prefilter_pipeline: Arc<ComputePipeline>,
+    /// Prefilters cube captures instead of equirectangular images.
+    probe_pipeline: Arc<ComputePipeline>,
+    brdf_lut: Arc<ImageView>,
+    probe_atlas: Arc<Image>,
+    probes: Arc<ImageView>,
+    sampler: Arc<Sampler>,
+}
+
+impl EnvironmentBaker {
+    /// Creates the pipelines and bakes the BRDF lookup table, blocking until it's done. The probe
+    /// atlas starts out black.
+    pub fn new(context: &VulkanContext) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .context("creating environment sampler")?;
+
+        let create_pipeline = |entry_point: EntryPoint| -> anyhow::Result<Arc<ComputePipeline>> {
+            let stage = PipelineShaderStageCreateInfo::new(entry_point);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("creating pipeline layout create info")?,
+            )
+            .context("creating pipeline layout")?;
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .context("creating environment pipeline")
+        };
+
+        let irradiance_cs = irradiance_cs::load(device.clone())
+            .context("loading irradiance shader")?
+            .entry_point("main")
+            .context("irradiance shader entry point not found")?;
+        let prefilter_cs = prefilter_cs::load(device.clone())
+            .context("loading prefilter shader")?
+            .entry_point("main")
+            .context("prefilter shader entry point not found")?;
+        let probe_cs = probe_prefilter_cs::load(device.clone())
+            .context("loading probe prefilter shader")?
+            .entry_point("main")
+            .context("probe prefilter shader entry point not found")?;
+        let brdf_lut_cs = brdf_lut_cs::load(device.clone())
+            .context("loading BRDF LUT shader")?
+            .entry_point("main")
+            .context("BRDF LUT shader entry point not found")?;
+        validate_descriptor_bindings(
+            "EnvironmentBaker",
+            &[&irradiance_cs, &prefilter_cs, &probe_cs],
+            &[SOURCE_BINDING, OUTPUT_BINDING],
+        )?;
+        validate_descriptor_bindings(
+            "EnvironmentBaker BRDF LUT",
+            &[&brdf_lut_cs],
+            &[LUT_OUTPUT_BINDING],
+        )?;
+
+        let irradiance_pipeline = create_pipeline(irradiance_cs)?;
+        let prefilter_pipeline = create_pipeline(prefilter_cs)?;
+        let probe_pipeline = create_pipeline(probe_cs)?;
+        let brdf_lut_pipeline = create_pipeline(brdf_lut_cs)?;
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(irradiance_pipeline.as_ref(), "irradiance pipeline");
+        debug_namer.name(prefilter_pipeline.as_ref(), "prefilter pipeline");
+        debug_namer.name(probe_pipeline.as_ref(), "probe prefilter pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let brdf_lut_image = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16_SFLOAT,
+                extent: [BRDF_LUT_SIZE, BRDF_LUT_SIZE, 1],
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating BRDF LUT image")?;
+        debug_namer.name(brdf_lut_image.as_ref(), "BRDF LUT");
+        let brdf_lut =
+            ImageView::new_default(brdf_lut_image).context("creating BRDF LUT image view")?;
+
+        // Cube arrays are sampled through one view, so every probe shares an image
+        let probe_atlas = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                flags: ImageCreateFlags::CUBE_COMPATIBLE,
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16B16A16_SFLOAT,
+                extent: [PREFILTERED_SIZE, PREFILTERED_SIZE, 1],
+                array_layers: 6 * MAX_REFLECTION_PROBES as u32,
+                mip_levels: PREFILTERED_LEVELS,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating reflection probe atlas")?;
+        debug_namer.name(probe_atlas.as_ref(), "reflection probe atlas");
+        let probes = ImageView::new(
+            probe_atlas.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::CubeArray,
+                ..ImageViewCreateInfo::from_image(&probe_atlas)
+            },
+        )
+        .context("creating reflection probe atlas view")?;
+
+        let baker = EnvironmentBaker {
+            queue: context.graphics_queue().clone(),
+            memory_allocator,
+            command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                device.clone(),
+                Default::default(),
+            )),
+            descriptor_set_allocator: context.descriptor_set_allocator().clone(),
+            debug_namer,
+            irradiance_pipeline,
+            prefilter_pipeline,
+            probe_pipeline,
+            brdf_lut,
+            probe_atlas,
+            probes,
+            sampler,
+        };
+
+        let descriptor_set = DescriptorSet::new(
+            baker.descriptor_set_allocator.clone(),
+            brdf_lut_pipeline.layout().set_layouts()[0].clone(),
+            [WriteDescriptorSet::image_view(
+                LUT_OUTPUT_BINDING.binding,
+                baker.brdf_lut.clone(),
+            )],
+            [],
+        )
+        .context("creating BRDF LUT descriptor set")?;
+
+        let mut builder = baker.begin()?;
+        builder
+            .bind_pipeline_compute(brdf_lut_pipeline.clone())
+            .context("binding BRDF LUT pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                brdf_lut_pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding BRDF LUT descriptor set")?;
+        unsafe { builder.dispatch([BRDF_LUT_SIZE.div_ceil(8), BRDF_LUT_SIZE.div_ceil(8), 1]) }
+            .context("dispatching BRDF LUT")?;
+        builder
+            .clear_color_image(ClearColorImageInfo::image(baker.probe_atlas.clone()))
+            .context("clearing reflection probe atlas")?;
+        baker.submit(builder).context("baking BRDF LUT")?;
+
+        Ok(baker)
+    }
+
+    /// Bakes the irradiance and prefiltered maps of an equirectangular environment, blocking
+    /// until they're done. `source` should be linear and have a full mip chain, it's sampled
+    /// with `sampler`.
+    pub fn bake(
+        &self,
+        source: Arc<ImageView>,
+        sampler: Arc<Sampler>,
+    ) -> anyhow::Result<EnvironmentMaps> {
+        let irradiance = self.create_cube(IRRADIANCE_SIZE, 1, "irradiance map")?;
+        let prefiltered =
+            self.create_cube(PREFILTERED_SIZE, PREFILTERED_LEVELS, "prefiltered map")?;
+
+        let mut builder = self.begin()?;
+
+        self.record_convolution(
+            &mut builder,
+            &self.irradiance_pipeline,
+            &source,
+            &sampler,
+            &irradiance,
+            0,
+            0..6,
+            None,
+        )?;
+
+        for level in 0..PREFILTERED_LEVELS {
+            let roughness = level as f32 / (PREFILTERED_LEVELS - 1) as f32;
+            self.record_convolution(
+                &mut builder,
+                &self.prefilter_pipeline,
+                &source,
+                &sampler,
+                &prefiltered,
+                level,
+                0..6,
+                Some(prefilter_cs::PushConstants { roughness }),
+            )?;
+        }
+
+        self.submit(builder).context("baking environment maps")?;
+
+        Ok(EnvironmentMaps {
+            irradiance: cube_view(irradiance)?,
+            prefiltered: cube_view(prefiltered)?,
+            brdf_lut: self.brdf_lut.clone(),
+            probes: self.probes.clone(),
+            sampler: self.sampler.clone(),
+        })
+    }
+
+    /// Records prefiltering the cube `capture` into the atlas slot of the probe at `index`.
+    /// `capture` should have a full mip chain.
+    pub(crate) fn record_probe(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        capture: &Arc<ImageView>,
+        index: usize,
+    ) -> anyhow::Result<()> {
+        let first_layer = 6 * index as u32;
+        for level in 0..PREFILTERED_LEVELS {
+            let roughness = level as f32 / (PREFILTERED_LEVELS - 1) as f32;
+            self.record_convolution(
+                builder,
+                &self.probe_pipeline,
+                capture,
+                &self.sampler,
+                &self.probe_atlas,
+                level,
+                first_layer..first_layer + 6,
+                Some(prefilter_cs::PushConstants { roughness }),
+            )?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record_convolution(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        pipeline: &Arc<ComputePipeline>,
+        source: &Arc<ImageView>,
+        sampler: &Arc<Sampler>,
+        cube: &Arc<Image>,
+        level: u32,
+        layers: Range<u32>,
+        push_constants: Option<prefilter_cs::PushConstants>,
+    ) -> anyhow::Result<()> {
+        // Storage images are written through a 2D array view of a single level of one cube
+        let output = ImageView::new(
+            cube.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2dArray,
+                subresource_range: ImageSubresourceRange {
+                    aspects: ImageAspects::COLOR,
+                    mip_levels: level..level + 1,
+                    array_layers: layers,
+                },
+                usage: ImageUsage::STORAGE,
+                ..ImageViewCreateInfo::from_image(cube)
+            },
+        )
+        .context("creating cube level view")?;
+
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    SOURCE_BINDING.binding,
+                    source.clone(),
+                    sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view(OUTPUT_BINDING.binding, output),
+            ],
+            [],
+        )
+        .context("creating environment descriptor set")?;
+
+        builder
+            .bind_pipeline_compute(pipeline.clone())
+            .context("binding environment pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding environment descriptor set")?;
+        if let Some(push_constants) = push_constants {
+            builder
+                .push_constants(pipeline.layout().clone(), 0, push_constants)
+                .context("pushing prefilter constants")?;
+        }
+
+        let size = (cube.extent()[0] >> level).max(1);
+        unsafe { builder.dispatch([size.div_ceil(8), size.div_ceil(8), 6]) }
+            .context("dispatching environment convolution")?;
+
+        Ok(())
+    }
+
+    fn create_cube(&self, size: u32, mip_levels: u32, name: &str) -> anyhow::Result<Arc<Image>> {
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                flags: ImageCreateFlags::CUBE_COMPATIBLE,
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16B16A16_SFLOAT,
+                extent: [size, size, 1],
+                array_layers: 6,
+                mip_levels,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .with_context(|| format!("creating {}", name))?;
+        self.debug_namer.name(image.as_ref(), name);
+        Ok(image)
+    }
+
+    fn begin(&self) -> anyhow::Result<RecordingCommandBuffer> {
+        RecordingCommandBuffer::new(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating environment command buffer")
+    }
+
+    fn submit(&self, builder: RecordingCommandBuffer) -> anyhow::Result<()> {
+        builder
+            .end()
+            .context("ending environment command buffer")?
+            .execute(self.queue.clone())
+            .context("executing environment command buffer")?
+            .then_signal_fence_and_flush()
+            .context("flushing environment command buffer")?
+            .wait(None)
+            .context("waiting for environment command buffer")
+    }
+}
+
+fn cube_view(image: Arc<Image>) -> anyhow::Result<Arc<ImageView>> {
+    ImageView::new(
+        image.clone(),
+        ImageViewCreateInfo {
+            view_type: ImageViewType::Cube,
+            ..ImageViewCreateInfo::from_image(&image)
+        },
+    )
+    .context("creating cube image view")
+}
+
+mod irradiance_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/ibl/irradiance.comp"
+    }
+}
+
+mod prefilter_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/ibl/prefilter.comp"
+    }
+}
+
+mod probe_prefilter_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/ibl/prefilter.comp",
+        define: [("SOURCE_CUBE", "1")],
+    }
+}
+
+mod brdf_lut_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/ibl/brdf_lut.comp"
+    }
+}
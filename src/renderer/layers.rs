@@ -0,0 +1,65 @@
+use cgmath::Matrix4;
+use serde::{Deserialize, Serialize};
+
+/// How many overlay cameras are drawn each frame, the rest are ignored.
+pub const MAX_OVERLAY_CAMERAS: usize = 4;
+
+/// The layer view models are drawn on, by their own pass. Left out of the main camera's layers.
+pub const VIEW_MODEL_LAYER: u32 = 31;
+
+/// Which cameras see an object, one bit per layer. A camera draws the objects that share at least
+/// one layer with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    /// Layer 0, where objects and cameras are unless they're put somewhere else.
+    pub const DEFAULT: RenderLayers = RenderLayers(1);
+    pub const ALL: RenderLayers = RenderLayers(u32::MAX);
+    pub const NONE: RenderLayers = RenderLayers(0);
+
+    /// Only `layer`, from 0 to 31.
+    pub fn layer(layer: u32) -> Self {
+        RenderLayers(1 << layer)
+    }
+
+    pub fn with(self, layer: u32) -> Self {
+        RenderLayers(self.0 | 1 << layer)
+    }
+
+    pub fn without(self, layer: u32) -> Self {
+        RenderLayers(self.0 & !(1 << layer))
+    }
+
+    pub fn contains(self, layer: u32) -> bool {
+        self.0 & 1 << layer != 0
+    }
+
+    pub fn intersects(self, other: RenderLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        RenderLayers::DEFAULT
+    }
+}
+
+/// A camera drawn after the main one, over its image, e.g. to draw a first person weapon with
+/// its own field of view. Only draws the objects on its `layers`, which the main camera should
+/// leave out.
+///
+/// Lighting works out positions from the depth buffer with the main camera, so an overlay is lit
+/// as if seen through the main camera. That's right when it shares the main camera's view and
+/// depth planes, and close enough for things held in front of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverlayCamera {
+    pub projection: Matrix4<f32>,
+    pub view: Matrix4<f32>,
+    pub layers: RenderLayers,
+    /// Draws in front of everything the cameras before it drew, only testing depth against its
+    /// own objects, so they never clip into walls. Its depth is squeezed into the very front of
+    /// the depth buffer, which lights it as if it were just in front of the main camera.
+    pub clear_depth: bool,
+}
@@ -1,19 +1,17 @@
 use std::sync::Arc;
 
 use anyhow::Context;
+use cgmath::Matrix4;
 use vulkano::{
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{
-        allocator::StandardCommandBufferAllocator, CommandBuffer, CommandBufferBeginInfo,
-        CommandBufferInheritanceInfo, CommandBufferLevel, CommandBufferUsage,
-        RecordingCommandBuffer,
-    },
-    descriptor_set::{
-        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferLevel,
+        CommandBufferUsage, RecordingCommandBuffer,
     },
+    descriptor_set::layout::DescriptorType,
     device::Queue,
     image::view::ImageView,
-    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
     pipeline::{
         graphics::{
             color_blend::{
@@ -33,26 +31,53 @@ use vulkano::{
     render_pass::Subpass,
 };
 
+use crate::renderer::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    frames_in_flight::InFlightFrame,
+    ibl::EnvironmentMaps,
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::VulkanContext,
+};
+
 use super::LightingVertex;
 
+const DIFFUSE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::InputAttachment);
+const NORMALS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::InputAttachment);
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 2, DescriptorType::InputAttachment);
+const MATERIAL_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 3, DescriptorType::InputAttachment);
+const EMISSIVE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 7, DescriptorType::InputAttachment);
+const IRRADIANCE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 4, DescriptorType::CombinedImageSampler);
+const PREFILTERED_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 5, DescriptorType::CombinedImageSampler);
+const BRDF_LUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 6, DescriptorType::CombinedImageSampler);
+const PROBES_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 8, DescriptorType::CombinedImageSampler);
+
 pub struct Ambient {
     gfx_queue: Arc<Queue>,
     vertex_buffer: Subbuffer<[LightingVertex]>,
     subpass: Subpass,
     pipeline: Arc<GraphicsPipeline>,
-    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
 }
 
 impl Ambient {
     /// Initializes the ambient lighting system.
     pub fn new(
-        gfx_queue: Arc<Queue>,
+        context: &VulkanContext,
         subpass: Subpass,
-        memory_allocator: Arc<StandardMemoryAllocator>,
-        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
     ) -> anyhow::Result<Self> {
+        let gfx_queue = context.graphics_queue().clone();
+        let memory_allocator = context.memory_allocator().clone();
+
         // TODO: vulkano doesn't allow us to draw without a vertex buffer, otherwise we could
         //       hard-code these values in the shader
         let vertices = [
@@ -93,6 +118,22 @@ impl Ambient {
                 .entry_point("main")
                 .context("fragment shader module entry point")?;
 
+            validate_descriptor_bindings(
+                "Ambient lighting",
+                &[&vs, &fs],
+                &[
+                    DIFFUSE_BINDING,
+                    NORMALS_BINDING,
+                    DEPTH_BINDING,
+                    MATERIAL_BINDING,
+                    IRRADIANCE_BINDING,
+                    PREFILTERED_BINDING,
+                    BRDF_LUT_BINDING,
+                    EMISSIVE_BINDING,
+                    PROBES_BINDING,
+                ],
+            )?;
+
             let vertex_input_state = LightingVertex::per_vertex()
                 .definition(&vs.info().input_interface)
                 .context("vertex_input_state")?;
@@ -142,35 +183,58 @@ impl Ambient {
             .context("graphics pipeline")?
         };
 
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "ambient lighting pipeline");
+
         Ok(Ambient {
             gfx_queue,
             vertex_buffer,
             subpass,
             pipeline,
-            command_buffer_allocator,
-            descriptor_set_allocator,
+            descriptor_set_cache,
         })
     }
 
     /// Builds a secondary command buffer that applies ambient lighting.
     ///
-    /// This secondary command buffer will read `color_input`, multiply it with `ambient_color`
-    /// and write the output to the current framebuffer with additive blending (in other words
+    /// This secondary command buffer will read the G-buffer and light it with `environment`, a
+    /// diffuse term from its irradiance and a specular term from its prefiltered reflections,
+    /// scaled by `ambient_color`. Objects in a reflection probe's box reflect the probe instead,
+    /// as it was lit when captured. Then it adds `emissive_input`. It writes the output to the current framebuffer with additive blending (in other words
     /// the value will be added to the existing value in the framebuffer, and not replace the
     /// existing value).
     ///
+    /// - `frame` is the frame slot being recorded, whose allocator the command buffer comes from.
     /// - `viewport_dimensions` contains the dimensions of the current framebuffer.
     /// - `color_input` is an image containing the albedo of each object of the scene. It is the
     ///   result of the deferred pass.
+    /// - `normals_input`, `depth_input` and `material_input` are the rest of the G-buffer, as
+    ///   read by the directional light.
+    /// - `emissive_input` is an image containing the light emitted by each object of the scene.
+    ///   It is the result of the deferred pass.
+    /// - `screen_to_world` is the inverse of the view projection.
+    /// - `environment` holds the image-based lighting maps.
     /// - `ambient_color` is the color to apply.
+    #[allow(clippy::too_many_arguments)]
     pub fn draw(
         &self,
+        frame: &InFlightFrame,
         viewport_dimensions: [u32; 2],
         color_input: Arc<ImageView>,
+        normals_input: Arc<ImageView>,
+        depth_input: Arc<ImageView>,
+        material_input: Arc<ImageView>,
+        emissive_input: Arc<ImageView>,
+        screen_to_world: Matrix4<f32>,
+        environment: &EnvironmentMaps,
         ambient_color: [f32; 3],
     ) -> anyhow::Result<Arc<CommandBuffer>> {
+        profile_scope!("ambient light");
         let push_constants = fs::PushConstants {
+            screen_to_world: screen_to_world.into(),
             color: [ambient_color[0], ambient_color[1], ambient_color[2], 1.0],
+            max_reflection_lod: environment.max_reflection_lod(),
         };
 
         let layout = self
@@ -180,11 +244,35 @@ impl Ambient {
             .get(0)
             .context("pipeline set layouts")?;
 
-        let descriptor_set = DescriptorSet::new(
-            self.descriptor_set_allocator.clone(),
-            layout.clone(),
-            [WriteDescriptorSet::image_view(0, color_input)],
-            [],
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            layout,
+            &[
+                CachedWrite::ImageView(DIFFUSE_BINDING.binding, color_input),
+                CachedWrite::ImageView(NORMALS_BINDING.binding, normals_input),
+                CachedWrite::ImageView(DEPTH_BINDING.binding, depth_input),
+                CachedWrite::ImageView(MATERIAL_BINDING.binding, material_input),
+                CachedWrite::ImageView(EMISSIVE_BINDING.binding, emissive_input),
+                CachedWrite::ImageViewSampler(
+                    IRRADIANCE_BINDING.binding,
+                    environment.irradiance.clone(),
+                    environment.sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    PREFILTERED_BINDING.binding,
+                    environment.prefiltered.clone(),
+                    environment.sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    BRDF_LUT_BINDING.binding,
+                    environment.brdf_lut.clone(),
+                    environment.sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    PROBES_BINDING.binding,
+                    environment.probes.clone(),
+                    environment.sampler.clone(),
+                ),
+            ],
         )?;
 
         let viewport = Viewport {
@@ -194,7 +282,7 @@ impl Ambient {
         };
 
         let mut builder = RecordingCommandBuffer::new(
-            self.command_buffer_allocator.clone(),
+            frame.command_buffer_allocator.clone(),
             self.gfx_queue.queue_family_index(),
             CommandBufferLevel::Secondary,
             CommandBufferBeginInfo {
@@ -1,19 +1,16 @@
 use anyhow::Context;
-use cgmath::Vector3;
+use cgmath::{Matrix4, Vector3};
 use std::sync::Arc;
 use vulkano::{
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{
-        allocator::StandardCommandBufferAllocator, CommandBuffer, CommandBufferBeginInfo,
-        CommandBufferInheritanceInfo, CommandBufferLevel, CommandBufferUsage,
-        RecordingCommandBuffer,
-    },
-    descriptor_set::{
-        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferLevel,
+        CommandBufferUsage, RecordingCommandBuffer,
     },
+    descriptor_set::{layout::DescriptorType, DescriptorSet, WriteDescriptorSet},
     device::Queue,
     image::view::ImageView,
-    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
     pipeline::{
         graphics::{
             color_blend::{
@@ -33,26 +30,69 @@ use vulkano::{
     render_pass::Subpass,
 };
 
+use crate::renderer::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    frames_in_flight::InFlightFrame,
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    shadows::CascadedShadows,
+    vulkan_context::VulkanContext,
+};
+
 use super::LightingVertex;
 
+const DIFFUSE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::InputAttachment);
+const NORMALS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::InputAttachment);
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 2, DescriptorType::InputAttachment);
+const MATERIAL_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 3, DescriptorType::InputAttachment);
+const SHADOW_MAP_BINDING: DescriptorBinding =
+    DescriptorBinding::new(1, 0, DescriptorType::CombinedImageSampler);
+const SHADOW_PARAMS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(1, 1, DescriptorType::UniformBuffer);
+
+/// Light from far enough away to reach everything from the same direction, like the sun.
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight {
+    /// The way the light travels, in world coordinates.
+    pub direction: Vector3<f32>,
+    /// Tints the light, see `color_temperature` for the color of light sources.
+    pub color: [f32; 3],
+    /// Illuminance in lux on surfaces facing the light. Direct sunlight is around 100000, an
+    /// overcast sky 1000.
+    pub illuminance: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        DirectionalLight {
+            direction: Vector3::new(0.2, -0.1, -0.7),
+            color: [1.0, 1.0, 1.0],
+            illuminance: 750.0,
+        }
+    }
+}
+
 pub struct Directional {
     gfx_queue: Arc<Queue>,
     vertex_buffer: Subbuffer<[LightingVertex]>,
     subpass: Subpass,
     pipeline: Arc<GraphicsPipeline>,
-    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
 }
 
 impl Directional {
     /// Initializes the directional lighting system.
     pub fn new(
-        gfx_queue: Arc<Queue>,
+        context: &VulkanContext,
         subpass: Subpass,
-        memory_allocator: Arc<StandardMemoryAllocator>,
-        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
     ) -> anyhow::Result<Self> {
+        let gfx_queue = context.graphics_queue().clone();
+        let memory_allocator = context.memory_allocator().clone();
+
         // TODO: vulkano doesn't allow us to draw without a vertex buffer, otherwise we could
         //       hard-code these values in the shader
         let vertices = [
@@ -94,6 +134,19 @@ impl Directional {
                 .entry_point("main")
                 .context("fragment shader module entry point")?;
 
+            validate_descriptor_bindings(
+                "Directional lighting",
+                &[&vs, &fs],
+                &[
+                    DIFFUSE_BINDING,
+                    NORMALS_BINDING,
+                    DEPTH_BINDING,
+                    MATERIAL_BINDING,
+                    SHADOW_MAP_BINDING,
+                    SHADOW_PARAMS_BINDING,
+                ],
+            )?;
+
             let vertex_input_state = LightingVertex::per_vertex()
                 .definition(&vs.info().input_interface)
                 .context("vertex input state")?;
@@ -143,20 +196,24 @@ impl Directional {
             .context("graphics pipeline")?
         };
 
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "directional lighting pipeline");
+
         Ok(Directional {
             gfx_queue,
             vertex_buffer,
             subpass,
             pipeline,
-            command_buffer_allocator,
-            descriptor_set_allocator,
+            descriptor_set_cache,
         })
     }
 
     /// Builds a secondary command buffer that applies directional lighting.
     ///
-    /// This secondary command buffer will read `color_input` and `normals_input`, and multiply the
-    /// color with `color` and the dot product of the `direction` with the normal.
+    /// This secondary command buffer will read the G-buffer and shade it with a Cook-Torrance
+    /// BRDF lit by `color` coming from `direction`, darkened where `shadows` says the light is
+    /// blocked.
     /// It then writes the output to the current framebuffer with additive blending (in other words
     /// the value will be added to the existing value in the framebuffer, and not replace the
     /// existing value).
@@ -164,37 +221,76 @@ impl Directional {
     /// Since `normals_input` contains normals in world coordinates, `direction` should also be in
     /// world coordinates.
     ///
+    /// - `frame` is the frame slot being recorded, whose allocator the command buffer comes from.
     /// - `viewport_dimensions` contains the dimensions of the current framebuffer.
     /// - `color_input` is an image containing the albedo of each object of the scene. It is the
     ///   result of the deferred pass.
     /// - `normals_input` is an image containing the normals of each object of the scene. It is the
     ///   result of the deferred pass.
+    /// - `depth_input` is the depth buffer, used with `screen_to_world` to find the direction
+    ///   towards the camera.
+    /// - `material_input` is an image containing the metallic and roughness of each object of
+    ///   the scene. It is the result of the deferred pass.
+    /// - `screen_to_world` is the inverse of the view projection.
     /// - `direction` is the direction of the light in world coordinates.
     /// - `color` is the color to apply.
+    /// - `shadows` holds this frame's cascades, updated for the same `direction`.
+    #[allow(clippy::too_many_arguments)]
     pub fn draw(
         &self,
+        frame: &InFlightFrame,
         viewport_dimensions: [u32; 2],
         color_input: Arc<ImageView>,
         normals_input: Arc<ImageView>,
+        depth_input: Arc<ImageView>,
+        material_input: Arc<ImageView>,
+        screen_to_world: Matrix4<f32>,
         direction: Vector3<f32>,
         color: [f32; 3],
+        shadows: &CascadedShadows,
     ) -> anyhow::Result<Arc<CommandBuffer>> {
+        profile_scope!("directional light");
         let push_constants = fs::PushConstants {
+            screen_to_world: screen_to_world.into(),
             color: [color[0], color[1], color[2], 1.0],
             direction: direction.extend(0.0).into(),
         };
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
-        let descriptor_set = DescriptorSet::new(
-            self.descriptor_set_allocator.clone(),
-            layout.clone(),
+        let descriptor_set = self
+            .descriptor_set_cache
+            .get_or_create(
+                layout,
+                &[
+                    CachedWrite::ImageView(DIFFUSE_BINDING.binding, color_input),
+                    CachedWrite::ImageView(NORMALS_BINDING.binding, normals_input),
+                    CachedWrite::ImageView(DEPTH_BINDING.binding, depth_input),
+                    CachedWrite::ImageView(MATERIAL_BINDING.binding, material_input),
+                ],
+            )
+            .unwrap();
+
+        // The params change every frame, so the set isn't worth caching
+        let shadow_set = DescriptorSet::new(
+            self.descriptor_set_cache.allocator().clone(),
+            self.pipeline.layout().set_layouts()[SHADOW_MAP_BINDING.set as usize].clone(),
             [
-                WriteDescriptorSet::image_view(0, color_input),
-                WriteDescriptorSet::image_view(1, normals_input),
+                WriteDescriptorSet::image_view_sampler(
+                    SHADOW_MAP_BINDING.binding,
+                    shadows.map().clone(),
+                    shadows.sampler().clone(),
+                ),
+                WriteDescriptorSet::buffer(
+                    SHADOW_PARAMS_BINDING.binding,
+                    shadows
+                        .params()
+                        .context("shadows weren't updated this frame")?
+                        .clone(),
+                ),
             ],
             [],
         )
-        .unwrap();
+        .context("creating shadow descriptor set")?;
 
         let viewport = Viewport {
             offset: [0.0, 0.0],
@@ -203,7 +299,7 @@ impl Directional {
         };
 
         let mut builder = RecordingCommandBuffer::new(
-            self.command_buffer_allocator.clone(),
+            frame.command_buffer_allocator.clone(),
             self.gfx_queue.queue_family_index(),
             CommandBufferLevel::Secondary,
             CommandBufferBeginInfo {
@@ -223,7 +319,7 @@ impl Directional {
                 PipelineBindPoint::Graphics,
                 self.pipeline.layout().clone(),
                 0,
-                descriptor_set,
+                (descriptor_set, shadow_set),
             )?
             .push_constants(self.pipeline.layout().clone(), 0, push_constants)?
             .bind_vertex_buffers(0, self.vertex_buffer.clone())?;
@@ -1,16 +1,21 @@
 pub use ambient::Ambient;
-pub use directional::Directional;
-pub use point::Point;
+pub use directional::{Directional, DirectionalLight};
+pub use photometry::{color_temperature, exposure_from_ev100};
+pub use point::{Attenuation, Point, PointLight};
+pub use sky::{Sky, SkyGradient};
 
 mod ambient;
 mod directional;
+mod photometry;
 mod point;
+mod sky;
 
 use vulkano::{buffer::BufferContents, pipeline::graphics::vertex_input::Vertex};
 
+/// A corner of the triangle that covers the screen in full screen passes.
 #[derive(BufferContents, Vertex)]
 #[repr(C)]
-struct LightingVertex {
+pub(crate) struct LightingVertex {
     #[format(R32G32_SFLOAT)]
-    position: [f32; 2],
+    pub(crate) position: [f32; 2],
 }
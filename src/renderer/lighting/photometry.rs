@@ -0,0 +1,32 @@
+/// Exposure of a camera that would correctly expose a scene at `ev100`, the exposure value at
+/// ISO 100. Lights' lumens and lux are multiplied by it before shading. Sunny daylight is about
+/// 15, an overcast day 12 and a lit room 7.
+pub fn exposure_from_ev100(ev100: f32) -> f32 {
+    1.0 / (1.2 * 2f32.powf(ev100))
+}
+
+/// The color of a black body glowing at `kelvin`, with its brightest channel at 1, for tinting
+/// lights. A candle is about 1900 K, a household bulb 2700 K, noon daylight 5500 K and a blue sky
+/// 10000 K. Clamped to 1000..=40000 K.
+pub fn color_temperature(kelvin: f32) -> [f32; 3] {
+    // Tanner Helland's fit of the black body curve, in sRGB
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let red = if t <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (t - 60.0).powf(-0.13320476)
+    };
+    let green = if t <= 66.0 {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12216 * (t - 60.0).powf(-0.07551485)
+    };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.04480
+    };
+    [red, green, blue].map(|channel| channel.clamp(0.0, 255.0) / 255.0)
+}
@@ -1,20 +1,21 @@
 use anyhow::Context;
-use cgmath::{Matrix4, Vector3};
+use cgmath::{Matrix4, SquareMatrix, Vector3, Vector4};
 use std::sync::Arc;
 use vulkano::{
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
     command_buffer::{
-        allocator::StandardCommandBufferAllocator, CommandBuffer, CommandBufferBeginInfo,
-        CommandBufferInheritanceInfo, CommandBufferLevel, CommandBufferUsage,
-        RecordingCommandBuffer,
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferLevel,
+        CommandBufferUsage, RecordingCommandBuffer,
     },
     descriptor_set::{
-        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+        allocator::StandardDescriptorSetAllocator, layout::DescriptorType, DescriptorSet,
+        WriteDescriptorSet,
     },
     device::Queue,
     image::view::ImageView,
     memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
     pipeline::{
+        compute::ComputePipelineCreateInfo,
         graphics::{
             color_blend::{
                 AttachmentBlend, BlendFactor, BlendOp, ColorBlendAttachmentState, ColorBlendState,
@@ -27,31 +28,129 @@ use vulkano::{
             GraphicsPipelineCreateInfo,
         },
         layout::PipelineDescriptorSetLayoutCreateInfo,
-        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
-        PipelineShaderStageCreateInfo,
+        ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+        PipelineLayout, PipelineShaderStageCreateInfo,
     },
     render_pass::Subpass,
+    DeviceSize,
+};
+
+use crate::renderer::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    frames_in_flight::InFlightFrame,
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    ring_buffer::RingBuffer,
+    vulkan_context::VulkanContext,
 };
 
 use super::LightingVertex;
 
+const DIFFUSE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::InputAttachment);
+const NORMALS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::InputAttachment);
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 2, DescriptorType::InputAttachment);
+const MATERIAL_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 3, DescriptorType::InputAttachment);
+
+/// The cluster bindings, in set 0 of the culling shader and set 1 of the lighting shader.
+const LIGHTS_BINDING: u32 = 0;
+const PARAMS_BINDING: u32 = 1;
+const COUNTS_BINDING: u32 = 2;
+const INDICES_BINDING: u32 = 3;
+
+/// Tiles across and down the screen, and depth slices.
+const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+const CLUSTER_COUNT: u32 = CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2];
+/// Must match `MAX_LIGHTS_PER_CLUSTER` in clusters.glsl.
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+/// Number of lights the light ring buffer holds per frame before it has to grow.
+const INITIAL_LIGHT_CAPACITY: usize = 256;
+
+/// How a point light fades with distance.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Attenuation {
+    /// Physically based, falling off with the square of the distance and windowed to reach zero
+    /// at the radius.
+    #[default]
+    InverseSquare,
+    /// Falls off linearly from the light's intensity at 1 unit, as if it were that close, to
+    /// zero at the radius. Easier to control for stylized lighting.
+    Linear,
+}
+
+impl Attenuation {
+    /// The model's index in the lighting shaders.
+    fn shader_index(self) -> f32 {
+        match self {
+            Attenuation::InverseSquare => 0.0,
+            Attenuation::Linear => 1.0,
+        }
+    }
+}
+
+/// A point light, queued for a single frame.
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    pub position: Vector3<f32>,
+    /// Tints the light, see `color_temperature` for the color of light sources.
+    pub color: [f32; 3],
+    /// Luminous power in lumens. A 60 W incandescent bulb gives about 800.
+    pub intensity: f32,
+    /// Distance where the light's contribution reaches zero. Smaller radii touch fewer clusters.
+    pub radius: f32,
+    pub attenuation: Attenuation,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        PointLight {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            color: [1.0, 1.0, 1.0],
+            intensity: 800.0,
+            radius: 5.0,
+            attenuation: Attenuation::default(),
+        }
+    }
+}
+
+/// The per frame cluster lists, written by the culling shader and read by the lighting shader.
+struct ClusterSlot {
+    counts: Subbuffer<[u32]>,
+    indices: Subbuffer<[u32]>,
+    /// Set by `cull`, `None` when the frame has no lights.
+    lighting_set: Option<Arc<DescriptorSet>>,
+}
+
+/// Clustered point lighting. Lights are binned into view space clusters by a compute pass
+/// before the frame's render pass, then a single fullscreen pass shades each pixel with only the
+/// lights in its cluster.
 pub struct Point {
     gfx_queue: Arc<Queue>,
     vertex_buffer: Subbuffer<[LightingVertex]>,
     subpass: Subpass,
     pipeline: Arc<GraphicsPipeline>,
-    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    cluster_pipeline: Arc<ComputePipeline>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
     descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    frames_in_flight: usize,
+    light_ring: RingBuffer,
+    params_ring: RingBuffer,
+    slots: Vec<ClusterSlot>,
 }
 
 impl Point {
     pub fn new(
-        gfx_queue: Arc<Queue>,
+        context: &VulkanContext,
         subpass: Subpass,
-        memory_allocator: Arc<StandardMemoryAllocator>,
-        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+        frames_in_flight: usize,
     ) -> anyhow::Result<Self> {
+        let gfx_queue = context.graphics_queue().clone();
+        let memory_allocator = context.memory_allocator().clone();
+
         let vertices = [
             LightingVertex {
                 position: [-1.0, -1.0],
@@ -64,7 +163,7 @@ impl Point {
             },
         ];
         let vertex_buffer = Buffer::from_iter(
-            memory_allocator,
+            memory_allocator.clone(),
             BufferCreateInfo {
                 usage: BufferUsage::VERTEX_BUFFER,
                 ..Default::default()
@@ -78,6 +177,15 @@ impl Point {
         )
         .context("vertex buffer")?;
 
+        let cluster_bindings = |set| {
+            [
+                DescriptorBinding::new(set, LIGHTS_BINDING, DescriptorType::StorageBuffer),
+                DescriptorBinding::new(set, PARAMS_BINDING, DescriptorType::UniformBuffer),
+                DescriptorBinding::new(set, COUNTS_BINDING, DescriptorType::StorageBuffer),
+                DescriptorBinding::new(set, INDICES_BINDING, DescriptorType::StorageBuffer),
+            ]
+        };
+
         let pipeline = {
             let device = gfx_queue.device();
 
@@ -91,6 +199,22 @@ impl Point {
                 .entry_point("main")
                 .context("fragment shader module entry point")?;
 
+            let [lights, params, counts, indices] = cluster_bindings(1);
+            validate_descriptor_bindings(
+                "Point lighting",
+                &[&vs, &fs],
+                &[
+                    DIFFUSE_BINDING,
+                    NORMALS_BINDING,
+                    DEPTH_BINDING,
+                    MATERIAL_BINDING,
+                    lights,
+                    params,
+                    counts,
+                    indices,
+                ],
+            )?;
+
             let vertex_input_state = LightingVertex::per_vertex()
                 .definition(&vs.info().input_interface)
                 .context("vertex input state")?;
@@ -140,45 +264,260 @@ impl Point {
             .context("graphics pipeline")?
         };
 
+        let cluster_pipeline = {
+            let device = gfx_queue.device();
+
+            let cs = cluster_cs::load(device.clone())
+                .context("light culling shader module")?
+                .entry_point("main")
+                .context("light culling shader module entry point")?;
+
+            validate_descriptor_bindings("Point light culling", &[&cs], &cluster_bindings(0))?;
+
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("pipeline dsl create info")?,
+            )
+            .context("pipeline layout")?;
+
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .context("light culling pipeline")?
+        };
+
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "point lighting pipeline");
+        context
+            .debug_namer()
+            .name(cluster_pipeline.as_ref(), "point light culling pipeline");
+
+        let light_ring = RingBuffer::new(
+            memory_allocator.clone(),
+            BufferUsage::STORAGE_BUFFER,
+            (INITIAL_LIGHT_CAPACITY * std::mem::size_of::<cluster_cs::PointLightData>())
+                as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating point light ring buffer")?;
+
+        let params_ring = RingBuffer::new(
+            memory_allocator.clone(),
+            BufferUsage::UNIFORM_BUFFER,
+            std::mem::size_of::<cluster_cs::ClusterParams>() as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating cluster params ring buffer")?;
+
+        let slots = (0..frames_in_flight)
+            .map(|_| {
+                let cluster_buffer = |len: u32| {
+                    Buffer::new_slice::<u32>(
+                        memory_allocator.clone(),
+                        BufferCreateInfo {
+                            usage: BufferUsage::STORAGE_BUFFER,
+                            ..Default::default()
+                        },
+                        AllocationCreateInfo {
+                            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                            ..Default::default()
+                        },
+                        len as DeviceSize,
+                    )
+                    .context("creating cluster buffer")
+                };
+                Ok(ClusterSlot {
+                    counts: cluster_buffer(CLUSTER_COUNT)?,
+                    indices: cluster_buffer(CLUSTER_COUNT * MAX_LIGHTS_PER_CLUSTER)?,
+                    lighting_set: None,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
         Ok(Point {
             gfx_queue,
             vertex_buffer,
             subpass,
             pipeline,
-            command_buffer_allocator,
-            descriptor_set_allocator,
+            cluster_pipeline,
+            descriptor_set_cache,
+            memory_allocator,
+            descriptor_set_allocator: context.descriptor_set_allocator().clone(),
+            frames_in_flight,
+            light_ring,
+            params_ring,
+            slots,
         })
     }
 
+    /// Builds a primary command buffer that bins `lights` into this frame's clusters. Must run
+    /// before the frame's render pass. Returns `None` when there are no lights, in which case
+    /// `draw` has nothing to do either. Intensities are scaled by `exposure`.
+    pub fn cull(
+        &mut self,
+        frame: &InFlightFrame,
+        viewport_dimensions: [u32; 2],
+        lights: &[PointLight],
+        view: Matrix4<f32>,
+        projection: Matrix4<f32>,
+        exposure: f32,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        self.slots[frame.index].lighting_set = None;
+        if lights.is_empty() {
+            return Ok(None);
+        }
+
+        let lights: Vec<cluster_cs::PointLightData> = lights
+            .iter()
+            .map(|light| {
+                // Spread evenly over the sphere, in candela
+                let intensity = light.intensity / (4.0 * std::f32::consts::PI) * exposure;
+                cluster_cs::PointLightData {
+                    position_radius: light.position.extend(light.radius).into(),
+                    color: [
+                        light.color[0] * intensity,
+                        light.color[1] * intensity,
+                        light.color[2] * intensity,
+                        light.attenuation.shader_index(),
+                    ],
+                }
+            })
+            .collect();
+
+        let lights_size = std::mem::size_of_val(lights.as_slice()) as DeviceSize;
+        if lights_size > self.light_ring.region_size() {
+            let capacity = lights.len().next_power_of_two();
+            log::debug!("growing point light ring buffer to {} lights", capacity);
+            self.light_ring = RingBuffer::new(
+                self.memory_allocator.clone(),
+                BufferUsage::STORAGE_BUFFER,
+                (capacity * std::mem::size_of::<cluster_cs::PointLightData>()) as DeviceSize,
+                self.frames_in_flight,
+            )
+            .context("growing point light ring buffer")?;
+        }
+
+        // The distances the slices are spread between, wherever the projection puts its planes
+        let inverse_projection = projection.invert().context("inverting projection matrix")?;
+        let depth_at = |ndc_z: f32| {
+            let point = inverse_projection * Vector4::new(0.0, 0.0, ndc_z, 1.0);
+            -point.z / point.w
+        };
+
+        self.light_ring.begin_frame(frame.index);
+        self.params_ring.begin_frame(frame.index);
+        let light_count = lights.len() as u32;
+        let lights = self.light_ring.push_slice(&lights)?;
+        let params = self.params_ring.push_slice(&[cluster_cs::ClusterParams {
+            view: view.into(),
+            inverse_projection: inverse_projection.into(),
+            grid: [
+                CLUSTER_GRID[0],
+                CLUSTER_GRID[1],
+                CLUSTER_GRID[2],
+                light_count,
+            ],
+            screen: [
+                viewport_dimensions[0] as f32,
+                viewport_dimensions[1] as f32,
+                depth_at(0.0),
+                depth_at(1.0),
+            ],
+        }])?;
+
+        let slot = &self.slots[frame.index];
+        let writes = || {
+            [
+                WriteDescriptorSet::buffer(LIGHTS_BINDING, lights.clone()),
+                WriteDescriptorSet::buffer(PARAMS_BINDING, params.clone()),
+                WriteDescriptorSet::buffer(COUNTS_BINDING, slot.counts.clone()),
+                WriteDescriptorSet::buffer(INDICES_BINDING, slot.indices.clone()),
+            ]
+        };
+        let cluster_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.cluster_pipeline.layout().set_layouts()[0].clone(),
+            writes(),
+            [],
+        )
+        .context("creating light culling descriptor set")?;
+        let lighting_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.pipeline.layout().set_layouts()[1].clone(),
+            writes(),
+            [],
+        )
+        .context("creating point lighting cluster descriptor set")?;
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("command buffer builder")?;
+
+        builder
+            .bind_pipeline_compute(self.cluster_pipeline.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.cluster_pipeline.layout().clone(),
+                0,
+                cluster_set,
+            )?;
+        unsafe {
+            builder.dispatch([CLUSTER_COUNT.div_ceil(64), 1, 1])?;
+        }
+
+        self.slots[frame.index].lighting_set = Some(lighting_set);
+
+        builder.end().context("ending command buffer").map(Some)
+    }
+
+    /// Builds a secondary command buffer that shades the G-buffer with the lights `cull` binned
+    /// for this frame, or `None` when there are none.
     #[allow(clippy::too_many_arguments)]
     pub fn draw(
         &self,
+        frame: &InFlightFrame,
         viewport_dimensions: [u32; 2],
         color_input: Arc<ImageView>,
         normals_input: Arc<ImageView>,
         depth_input: Arc<ImageView>,
+        material_input: Arc<ImageView>,
         screen_to_world: Matrix4<f32>,
-        position: Vector3<f32>,
-        color: [f32; 3],
-    ) -> anyhow::Result<Arc<CommandBuffer>> {
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        profile_scope!("point lights");
+        let Some(cluster_set) = self.slots[frame.index].lighting_set.clone() else {
+            return Ok(None);
+        };
+
         let push_constants = fs::PushConstants {
             screen_to_world: screen_to_world.into(),
-            color: [color[0], color[1], color[2], 1.0],
-            position: position.extend(0.0).into(),
         };
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
-        let descriptor_set = DescriptorSet::new(
-            self.descriptor_set_allocator.clone(),
-            layout.clone(),
-            [
-                WriteDescriptorSet::image_view(0, color_input),
-                WriteDescriptorSet::image_view(1, normals_input),
-                WriteDescriptorSet::image_view(2, depth_input),
-            ],
-            [],
-        )
-        .context("descriptor set")?;
+        let descriptor_set = self
+            .descriptor_set_cache
+            .get_or_create(
+                layout,
+                &[
+                    CachedWrite::ImageView(DIFFUSE_BINDING.binding, color_input),
+                    CachedWrite::ImageView(NORMALS_BINDING.binding, normals_input),
+                    CachedWrite::ImageView(DEPTH_BINDING.binding, depth_input),
+                    CachedWrite::ImageView(MATERIAL_BINDING.binding, material_input),
+                ],
+            )
+            .context("descriptor set")?;
 
         let viewport = Viewport {
             offset: [0.0, 0.0],
@@ -187,7 +526,7 @@ impl Point {
         };
 
         let mut builder = RecordingCommandBuffer::new(
-            self.command_buffer_allocator.clone(),
+            frame.command_buffer_allocator.clone(),
             self.gfx_queue.queue_family_index(),
             CommandBufferLevel::Secondary,
             CommandBufferBeginInfo {
@@ -208,7 +547,7 @@ impl Point {
                 PipelineBindPoint::Graphics,
                 self.pipeline.layout().clone(),
                 0,
-                descriptor_set,
+                (descriptor_set, cluster_set),
             )?
             .push_constants(self.pipeline.layout().clone(), 0, push_constants)?
             .bind_vertex_buffers(0, self.vertex_buffer.clone())?;
@@ -217,7 +556,7 @@ impl Point {
             builder.draw(self.vertex_buffer.len() as u32, 1, 0, 0)?;
         }
 
-        builder.end().context("ending command buffer")
+        builder.end().context("ending command buffer").map(Some)
     }
 }
 
@@ -234,3 +573,10 @@ mod fs {
         path: "assets/shaders/deferred/point.frag"
     }
 }
+
+mod cluster_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/deferred/cluster_lights.comp"
+    }
+}
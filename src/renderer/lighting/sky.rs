@@ -0,0 +1,250 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::Matrix4;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferLevel,
+        CommandBufferUsage, RecordingCommandBuffer,
+    },
+    descriptor_set::layout::DescriptorType,
+    device::Queue,
+    image::view::ImageView,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+};
+
+use crate::renderer::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    frames_in_flight::InFlightFrame,
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::VulkanContext,
+};
+
+use super::LightingVertex;
+
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::InputAttachment);
+
+/// The colors the sky is drawn with behind the scene, blended from the horizon up to the zenith
+/// and down to the ground. Linear, in the same units as the ambient light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyGradient {
+    pub zenith: [f32; 3],
+    pub horizon: [f32; 3],
+    /// Below the horizon, seen where there's no ground in the scene.
+    pub ground: [f32; 3],
+}
+
+pub struct Sky {
+    gfx_queue: Arc<Queue>,
+    vertex_buffer: Subbuffer<[LightingVertex]>,
+    subpass: Subpass,
+    pipeline: Arc<GraphicsPipeline>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+}
+
+impl Sky {
+    pub fn new(
+        context: &VulkanContext,
+        subpass: Subpass,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let gfx_queue = context.graphics_queue().clone();
+        let memory_allocator = context.memory_allocator().clone();
+
+        let vertices = [
+            LightingVertex {
+                position: [-1.0, -1.0],
+            },
+            LightingVertex {
+                position: [-1.0, 3.0],
+            },
+            LightingVertex {
+                position: [3.0, -1.0],
+            },
+        ];
+        let vertex_buffer = Buffer::from_iter(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            vertices,
+        )
+        .context("creating vertex buffer")?;
+
+        let pipeline = {
+            let device = gfx_queue.device();
+            let vs = vs::load(device.clone())
+                .context("vertex shader module")?
+                .entry_point("main")
+                .context("vertex shader module entry point")?;
+
+            let fs = fs::load(device.clone())
+                .context("fragment shader module")?
+                .entry_point("main")
+                .context("fragment shader module entry point")?;
+
+            validate_descriptor_bindings("Sky", &[&vs, &fs], &[DEPTH_BINDING])?;
+
+            let vertex_input_state = LightingVertex::per_vertex()
+                .definition(&vs.info().input_interface)
+                .context("vertex_input_state")?;
+
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("pipeline dsl create info")?,
+            )
+            .context("pipeline layout")?;
+
+            // Nothing else is drawn at the far plane, so the sky replaces what's there
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(vertex_input_state),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        subpass.num_color_attachments(),
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(subpass.clone().into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .context("graphics pipeline")?
+        };
+
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "sky pipeline");
+
+        Ok(Sky {
+            gfx_queue,
+            vertex_buffer,
+            subpass,
+            pipeline,
+            descriptor_set_cache,
+        })
+    }
+
+    /// Builds a secondary command buffer that fills the pixels the deferred pass left at the far
+    /// plane of `depth_input` with `gradient`, by the direction each looks in.
+    ///
+    /// - `frame` is the frame slot being recorded, whose allocator the command buffer comes from.
+    /// - `viewport_dimensions` contains the dimensions of the current framebuffer.
+    /// - `screen_to_world` is the inverse of the view projection.
+    pub fn draw(
+        &self,
+        frame: &InFlightFrame,
+        viewport_dimensions: [u32; 2],
+        depth_input: Arc<ImageView>,
+        screen_to_world: Matrix4<f32>,
+        gradient: &SkyGradient,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        profile_scope!("sky");
+        let rgba = |color: [f32; 3]| [color[0], color[1], color[2], 1.0];
+        let push_constants = fs::PushConstants {
+            screen_to_world: screen_to_world.into(),
+            zenith: rgba(gradient.zenith),
+            horizon: rgba(gradient.horizon),
+            ground: rgba(gradient.ground),
+        };
+
+        let layout = self
+            .pipeline
+            .layout()
+            .set_layouts()
+            .get(0)
+            .context("pipeline set layouts")?;
+
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            layout,
+            &[CachedWrite::ImageView(DEPTH_BINDING.binding, depth_input)],
+        )?;
+
+        let viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+            depth_range: 0.0..=1.0,
+        };
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Secondary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::MultipleSubmit,
+                inheritance_info: Some(CommandBufferInheritanceInfo {
+                    render_pass: Some(self.subpass.clone().into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())?
+            .bind_pipeline_graphics(self.pipeline.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )?
+            .push_constants(self.pipeline.layout().clone(), 0, push_constants)?
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())?;
+        unsafe {
+            builder.draw(self.vertex_buffer.len() as u32, 1, 0, 0)?;
+        }
+
+        builder.end().context("ending command buffer")
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/deferred/ambient.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/deferred/sky.frag"
+    }
+}
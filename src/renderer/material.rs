@@ -0,0 +1,94 @@
+use super::texture::TextureHandle;
+
+/// Refers to a material created by a `Renderer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct MaterialId(pub(crate) usize);
+
+/// Which variant of the G-buffer shader a material is drawn with.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize, serde::Deserialize,
+)]
+pub enum MaterialShader {
+    /// Shaded by the lights, the ambient light and the reflections.
+    #[default]
+    Lit,
+    /// Shows its base color and emissive as they are, e.g. for sprites and UI in the world.
+    /// Still exposed and tonemapped with the rest of the scene.
+    Unlit,
+}
+
+/// How a material's base color alpha, times its base color texture's, is used.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize, serde::Deserialize,
+)]
+pub enum BlendMode {
+    /// Alpha is ignored.
+    #[default]
+    Opaque,
+    /// Pixels with alpha under a half are cut out, as glTF's `MASK` mode. Masked objects are
+    /// left out of the depth pre-pass. Blending is left out, since the deferred passes only
+    /// keep the nearest surface.
+    Mask,
+}
+
+/// Metallic-roughness surface parameters. The factors multiply the matching texture when there is
+/// one, and the base color also multiplies the vertex color.
+#[derive(Debug, Clone)]
+pub struct Material {
+    /// Linear RGBA. Alpha is only read by `BlendMode::Mask` materials.
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Should be loaded with `TextureUsage::Color { srgb: true }`.
+    pub base_color_texture: Option<TextureHandle>,
+    /// Roughness in green and metallic in blue, as in glTF.
+    pub metallic_roughness_texture: Option<TextureHandle>,
+    /// Linear light the surface emits without being lit. Values above 1 bloom.
+    pub emissive: [f32; 3],
+    /// Should be loaded with `TextureUsage::Color { srgb: true }`.
+    pub emissive_texture: Option<TextureHandle>,
+    /// Baked indirect light for static meshes, laid out by their `lightmap_uv`s and used in
+    /// place of the ambient light's diffuse term. Linear, in the ambient light's units, so a
+    /// surface open to the sky reads the sky's color. Every object drawn with the material reads
+    /// the same lightmap, so each lightmapped mesh usually gets its own material. See
+    /// `Renderer::load_lightmap`.
+    pub lightmap: Option<TextureHandle>,
+    pub shader: MaterialShader,
+    pub blend: BlendMode,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 0.5,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            emissive: [0.0; 3],
+            emissive_texture: None,
+            lightmap: None,
+            shader: MaterialShader::Lit,
+            blend: BlendMode::Opaque,
+        }
+    }
+}
+
+/// A per-object override blended over the material's base color after texturing, for effects
+/// like damage flashes or selection highlights that shouldn't need their own material.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tint {
+    /// Linear RGB.
+    pub color: [f32; 3],
+    /// 0 leaves the material untouched, 1 replaces its base color with `color`.
+    pub amount: f32,
+}
+
+impl Default for Tint {
+    fn default() -> Self {
+        Tint {
+            color: [1.0; 3],
+            amount: 0.0,
+        }
+    }
+}
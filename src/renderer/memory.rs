@@ -0,0 +1,113 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use vulkano::{image::Image, DeviceSize};
+
+/// What a tracked allocation is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    Meshes,
+    Textures,
+    /// The G-buffer, HDR target and post process images, replaced on resize.
+    RenderTargets,
+    /// The HUD's vertices and the UI atlas.
+    Ui,
+}
+
+impl MemoryCategory {
+    const COUNT: usize = 4;
+
+    fn index(self) -> usize {
+        match self {
+            MemoryCategory::Meshes => 0,
+            MemoryCategory::Textures => 1,
+            MemoryCategory::RenderTargets => 2,
+            MemoryCategory::Ui => 3,
+        }
+    }
+}
+
+/// Device memory held by tracked resources, in bytes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryStats {
+    pub meshes: u64,
+    pub textures: u64,
+    pub render_targets: u64,
+    pub ui: u64,
+}
+
+impl MemoryStats {
+    pub fn total(&self) -> u64 {
+        self.meshes + self.textures + self.render_targets + self.ui
+    }
+
+    pub fn get(&self, category: MemoryCategory) -> u64 {
+        match category {
+            MemoryCategory::Meshes => self.meshes,
+            MemoryCategory::Textures => self.textures,
+            MemoryCategory::RenderTargets => self.render_targets,
+            MemoryCategory::Ui => self.ui,
+        }
+    }
+}
+
+/// Counts the device memory used by each category of resource. Systems register what they
+/// allocate and keep the returned `TrackedMemory` next to the resource, so the bytes are given
+/// back when the resource is dropped.
+#[derive(Clone, Default)]
+pub struct MemoryTracker {
+    counters: Arc<[AtomicU64; MemoryCategory::COUNT]>,
+}
+
+impl MemoryTracker {
+    pub fn track(&self, category: MemoryCategory, bytes: DeviceSize) -> TrackedMemory {
+        self.counters[category.index()].fetch_add(bytes, Ordering::Relaxed);
+        TrackedMemory {
+            counters: self.counters.clone(),
+            category,
+            bytes,
+        }
+    }
+
+    /// Tracks the memory bound to `image`, including any padding the driver asked for.
+    pub fn track_image(&self, category: MemoryCategory, image: &Image) -> TrackedMemory {
+        let bytes = image
+            .memory_requirements()
+            .iter()
+            .map(|requirements| requirements.layout.size())
+            .sum();
+        self.track(category, bytes)
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let get =
+            |category: MemoryCategory| self.counters[category.index()].load(Ordering::Relaxed);
+        MemoryStats {
+            meshes: get(MemoryCategory::Meshes),
+            textures: get(MemoryCategory::Textures),
+            render_targets: get(MemoryCategory::RenderTargets),
+            ui: get(MemoryCategory::Ui),
+        }
+    }
+}
+
+/// Bytes counted against a category until dropped.
+pub struct TrackedMemory {
+    counters: Arc<[AtomicU64; MemoryCategory::COUNT]>,
+    category: MemoryCategory,
+    bytes: DeviceSize,
+}
+
+impl TrackedMemory {
+    pub fn category(&self) -> MemoryCategory {
+        self.category
+    }
+}
+
+impl Drop for TrackedMemory {
+    fn drop(&mut self) {
+        self.counters[self.category.index()].fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
@@ -2,31 +2,91 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use vulkano::{
-    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, IndexBuffer, Subbuffer},
     memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter},
 };
 
-use super::geometry_shaders::VertexPositionColorNormal;
+use super::{
+    mesh_pool::{MeshPool, PooledRanges},
+    vertex::{MeshVertex, VertexLayout},
+};
+
+pub struct MeshBuilder<V> {
+    vertices: Option<Vec<V>>,
+    indices: Option<Indices>,
+}
+
+impl<V> Default for MeshBuilder<V> {
+    fn default() -> Self {
+        MeshBuilder {
+            vertices: None,
+            indices: None,
+        }
+    }
+}
+
+/// Index data for a mesh. `MeshBuilder` stores it as `u16` whenever the mesh has few enough
+/// vertices, whichever type it's given in.
+#[derive(Debug, Clone)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Narrows to `u16` when every vertex can be addressed with one, otherwise widens to `u32`.
+    pub(super) fn fit_to(self, vertex_count: usize) -> Self {
+        let fits_u16 = vertex_count <= u16::MAX as usize + 1;
+        match self {
+            Indices::U32(indices) if fits_u16 => {
+                Indices::U16(indices.into_iter().map(|i| i as u16).collect())
+            }
+            Indices::U16(indices) if !fits_u16 => {
+                Indices::U32(indices.into_iter().map(u32::from).collect())
+            }
+            indices => indices,
+        }
+    }
+}
 
-#[derive(Default)]
-pub struct MeshBuilder {
-    vertices: Option<Vec<VertexPositionColorNormal>>,
-    indices: Option<Vec<u16>>,
+impl From<Vec<u16>> for Indices {
+    fn from(value: Vec<u16>) -> Self {
+        Indices::U16(value)
+    }
 }
 
-impl MeshBuilder {
-    pub fn with_vertices(mut self, value: Vec<VertexPositionColorNormal>) -> Self {
+impl From<Vec<u32>> for Indices {
+    fn from(value: Vec<u32>) -> Self {
+        Indices::U32(value)
+    }
+}
+
+impl<V: MeshVertex> MeshBuilder<V> {
+    pub fn with_vertices(mut self, value: Vec<V>) -> Self {
         self.vertices = Some(value);
         self
     }
 
-    pub fn with_indices(mut self, value: Vec<u16>) -> Self {
-        self.indices = Some(value);
+    pub fn with_indices(mut self, value: impl Into<Indices>) -> Self {
+        self.indices = Some(value.into());
         self
     }
 
     pub fn build(self, memory_allocator: Arc<dyn MemoryAllocator>) -> anyhow::Result<BasicMesh> {
         let vertices = self.vertices.unwrap_or_default();
+        let vertex_count = vertices.len();
+        let bounds = Aabb::from_points(vertices.iter().map(|v| v.position()));
 
         let vertex_buffer = Buffer::from_iter(
             memory_allocator.clone(),
@@ -41,32 +101,169 @@ impl MeshBuilder {
             },
             vertices,
         )
-        .context("creating vertex buffer")?;
+        .context("creating vertex buffer")?
+        .into_bytes();
 
-        let indices = self.indices.unwrap_or_default();
-        let index_buffer = Buffer::from_iter(
-            memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::INDEX_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            indices,
-        )
-        .context("creating index buffer")?;
+        let index_buffer = match self
+            .indices
+            .unwrap_or(Indices::U16(vec![]))
+            .fit_to(vertex_count)
+        {
+            Indices::U16(indices) => {
+                IndexBuffer::U16(create_index_buffer(&memory_allocator, indices)?)
+            }
+            Indices::U32(indices) => {
+                IndexBuffer::U32(create_index_buffer(&memory_allocator, indices)?)
+            }
+        };
 
         Ok(BasicMesh {
+            layout: V::LAYOUT,
             vertex_buffer,
             index_buffer,
+            bounds,
+            pool: None,
         })
     }
+
+    /// Builds the mesh into ranges of `pool`'s shared buffers instead of buffers of its own.
+    pub fn build_pooled(self, pool: &MeshPool) -> anyhow::Result<BasicMesh> {
+        let vertices = self.vertices.unwrap_or_default();
+        let bounds = Aabb::from_points(vertices.iter().map(|v| v.position()));
+        let indices = self
+            .indices
+            .unwrap_or(Indices::U16(vec![]))
+            .fit_to(vertices.len());
+
+        let mesh = pool.allocate_mesh::<V>(vertices.len(), &indices, bounds)?;
+        mesh.vertex_buffer
+            .clone()
+            .reinterpret::<[V]>()
+            .write()
+            .context("writing pooled vertices")?
+            .copy_from_slice(&vertices);
+        match (&indices, &mesh.index_buffer) {
+            (Indices::U16(indices), IndexBuffer::U16(buffer)) => buffer
+                .write()
+                .context("writing pooled indices")?
+                .copy_from_slice(indices),
+            (Indices::U32(indices), IndexBuffer::U32(buffer)) => buffer
+                .write()
+                .context("writing pooled indices")?
+                .copy_from_slice(indices),
+            _ => unreachable!("pooled index buffer doesn't match its indices"),
+        }
+        Ok(mesh)
+    }
+}
+
+fn create_index_buffer<T>(
+    memory_allocator: &Arc<dyn MemoryAllocator>,
+    indices: Vec<T>,
+) -> anyhow::Result<Subbuffer<[T]>>
+where
+    T: BufferContents,
+{
+    Buffer::from_iter(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::INDEX_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        indices,
+    )
+    .context("creating index buffer")
 }
 
 pub struct BasicMesh {
-    pub vertex_buffer: Subbuffer<[VertexPositionColorNormal]>,
-    pub index_buffer: Subbuffer<[u16]>,
+    pub layout: VertexLayout,
+    /// Vertices of the type matching `layout`.
+    pub vertex_buffer: Subbuffer<[u8]>,
+    pub index_buffer: IndexBuffer,
+    /// Model space bounds of the vertices.
+    pub bounds: Aabb,
+    /// Where the buffers are in a `MeshPool`, when they're ranges of its blocks.
+    pub pool: Option<PooledRanges>,
+}
+
+impl BasicMesh {
+    pub fn index_count(&self) -> u32 {
+        self.index_buffer.len() as u32
+    }
+
+    /// The buffers to bind to draw the mesh, which are whole pool blocks for pooled meshes.
+    pub fn bound_buffers(&self) -> (&Subbuffer<[u8]>, &IndexBuffer) {
+        match &self.pool {
+            Some(pool) => (pool.vertex_block(), pool.index_block()),
+            None => (&self.vertex_buffer, &self.index_buffer),
+        }
+    }
+
+    /// The `first_index` to draw the mesh's indices from its bound buffers with.
+    pub fn first_index(&self) -> u32 {
+        self.pool.as_ref().map_or(0, PooledRanges::first_index)
+    }
+
+    /// The `vertex_offset` to draw the mesh from its bound buffers with.
+    pub fn vertex_offset(&self) -> i32 {
+        self.pool.as_ref().map_or(0, PooledRanges::vertex_offset)
+    }
+}
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    /// The smallest box containing every point, or an empty box at the origin if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Aabb {
+                min: [0.0; 3],
+                max: [0.0; 3],
+            };
+        };
+
+        points.fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |aabb, p| Aabb {
+                min: [
+                    aabb.min[0].min(p[0]),
+                    aabb.min[1].min(p[1]),
+                    aabb.min[2].min(p[2]),
+                ],
+                max: [
+                    aabb.max[0].max(p[0]),
+                    aabb.max[1].max(p[1]),
+                    aabb.max[2].max(p[2]),
+                ],
+            },
+        )
+    }
+
+    pub fn corners(&self) -> [[f32; 3]; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            [min[0], min[1], min[2]],
+            [max[0], min[1], min[2]],
+            [min[0], max[1], min[2]],
+            [max[0], max[1], min[2]],
+            [min[0], min[1], max[2]],
+            [max[0], min[1], max[2]],
+            [min[0], max[1], max[2]],
+            [max[0], max[1], max[2]],
+        ]
+    }
 }
@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{ensure, Context};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, IndexBuffer, Subbuffer},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    DeviceSize,
+};
+
+use super::{
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    mesh::{Aabb, BasicMesh, Indices},
+    vertex::{MeshVertex, VertexLayout},
+    vulkan_context::VulkanContext,
+};
+
+/// What a pool's blocks hold. Vertices of each layout get blocks of their own, so every vertex
+/// in a block has the same stride and a mesh's first vertex can be given as a vertex offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PoolKind {
+    Vertices(VertexLayout),
+    U16Indices,
+    U32Indices,
+}
+
+/// The free ranges of a block, sorted and never touching.
+#[derive(Debug)]
+struct FreeList {
+    free: Vec<Range<DeviceSize>>,
+}
+
+impl FreeList {
+    fn new(size: DeviceSize) -> Self {
+        FreeList {
+            free: vec![0..size],
+        }
+    }
+
+    /// Takes the first free range `size` fits in once its start is aligned to `alignment`.
+    fn allocate(&mut self, size: DeviceSize, alignment: DeviceSize) -> Option<Range<DeviceSize>> {
+        let index = self.free.iter().position(|free| {
+            let start = free.start.next_multiple_of(alignment);
+            start + size <= free.end
+        })?;
+
+        let free = self.free.remove(index);
+        let start = free.start.next_multiple_of(alignment);
+        let end = start + size;
+        if end < free.end {
+            self.free.insert(index, end..free.end);
+        }
+        if free.start < start {
+            self.free.insert(index, free.start..start);
+        }
+        Some(start..end)
+    }
+
+    fn release(&mut self, range: Range<DeviceSize>) {
+        let index = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(index, range);
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            let next = self.free.remove(index + 1);
+            self.free[index].end = next.end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            let range = self.free.remove(index);
+            self.free[index - 1].end = range.end;
+        }
+    }
+
+    fn is_empty(&self, size: DeviceSize) -> bool {
+        self.free.len() == 1 && self.free[0] == (0..size)
+    }
+}
+
+struct Block {
+    buffer: Subbuffer<[u8]>,
+    free: FreeList,
+    _memory: TrackedMemory,
+}
+
+/// Every kind's blocks. Emptied blocks other than a kind's first are freed, leaving `None` in
+/// their place so the other blocks keep their index.
+#[derive(Default)]
+struct Blocks(HashMap<PoolKind, Vec<Option<Block>>>);
+
+/// A range of a pool block, given back to the pool when dropped. Meshes are retired through
+/// the frames in flight before they're dropped, so nothing can be drawing the range by then.
+struct PoolAllocation {
+    blocks: Arc<Mutex<Blocks>>,
+    kind: PoolKind,
+    block: usize,
+    range: Range<DeviceSize>,
+}
+
+impl Drop for PoolAllocation {
+    fn drop(&mut self) {
+        let mut blocks = self.blocks.lock().unwrap();
+        let Some(blocks) = blocks.0.get_mut(&self.kind) else {
+            return;
+        };
+        let Some(block) = blocks[self.block].as_mut() else {
+            return;
+        };
+        block.free.release(self.range.clone());
+        if self.block > 0 && block.free.is_empty(block.buffer.size()) {
+            blocks[self.block] = None;
+        }
+    }
+}
+
+/// Where a pooled mesh is in its blocks. The blocks are bound whole, with the mesh picked out by
+/// the draw's first index and vertex offset, so meshes sharing blocks share a binding.
+pub struct PooledRanges {
+    vertex_block: Subbuffer<[u8]>,
+    index_block: IndexBuffer,
+    first_index: u32,
+    vertex_offset: i32,
+    _vertices: PoolAllocation,
+    _indices: PoolAllocation,
+}
+
+impl PooledRanges {
+    pub fn vertex_block(&self) -> &Subbuffer<[u8]> {
+        &self.vertex_block
+    }
+
+    pub fn index_block(&self) -> &IndexBuffer {
+        &self.index_block
+    }
+
+    pub fn first_index(&self) -> u32 {
+        self.first_index
+    }
+
+    pub fn vertex_offset(&self) -> i32 {
+        self.vertex_offset
+    }
+}
+
+/// Suballocates the vertices and indices of meshes from a few large buffers, instead of two
+/// buffers of their own per mesh. Cheap to clone, clones share the blocks.
+///
+/// Blocks are `block_size` bytes, with a block of its own for anything bigger. They're host
+/// visible where the device allows it, so meshes can be written in place, and can be copied to
+/// for the ones streamed in by the `MeshUploader`.
+#[derive(Clone)]
+pub struct MeshPool {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    memory_tracker: MemoryTracker,
+    block_size: DeviceSize,
+    blocks: Arc<Mutex<Blocks>>,
+}
+
+impl MeshPool {
+    pub fn new(context: &VulkanContext, block_size: DeviceSize) -> Self {
+        MeshPool {
+            memory_allocator: context.memory_allocator().clone(),
+            memory_tracker: context.memory_tracker().clone(),
+            block_size: block_size.max(1024 * 1024).next_multiple_of(4),
+            blocks: Arc::new(Mutex::new(Blocks::default())),
+        }
+    }
+
+    /// Bytes of every block, used or not.
+    pub fn allocated(&self) -> DeviceSize {
+        let blocks = self.blocks.lock().unwrap();
+        blocks
+            .0
+            .values()
+            .flatten()
+            .flatten()
+            .map(|block| block.buffer.size())
+            .sum()
+    }
+
+    /// Allocates room for `vertex_count` vertices of `V` and for `indices`, which must already
+    /// fit the vertex count, returning a mesh whose buffers are left for the caller to fill.
+    pub fn allocate_mesh<V: MeshVertex>(
+        &self,
+        vertex_count: usize,
+        indices: &Indices,
+        bounds: Aabb,
+    ) -> anyhow::Result<BasicMesh> {
+        ensure!(
+            vertex_count > 0 && !indices.is_empty(),
+            "can't pool a mesh without vertices and indices"
+        );
+
+        let stride = std::mem::size_of::<V>() as DeviceSize;
+        let (vertex_block, vertices) = self.allocate(
+            PoolKind::Vertices(V::LAYOUT),
+            vertex_count as DeviceSize * stride,
+            stride,
+        )?;
+        let vertex_buffer = vertex_block.clone().slice(vertices.range.clone());
+        let vertex_offset = (vertices.range.start / stride) as i32;
+
+        let (index_block, index_buffer, first_index, indices) = match indices {
+            Indices::U16(data) => {
+                let (block, allocation) =
+                    self.allocate(PoolKind::U16Indices, data.len() as DeviceSize * 2, 2)?;
+                let slice = block.clone().slice(allocation.range.clone());
+                (
+                    IndexBuffer::U16(block.reinterpret()),
+                    IndexBuffer::U16(slice.reinterpret()),
+                    (allocation.range.start / 2) as u32,
+                    allocation,
+                )
+            }
+            Indices::U32(data) => {
+                let (block, allocation) =
+                    self.allocate(PoolKind::U32Indices, data.len() as DeviceSize * 4, 4)?;
+                let slice = block.clone().slice(allocation.range.clone());
+                (
+                    IndexBuffer::U32(block.reinterpret()),
+                    IndexBuffer::U32(slice.reinterpret()),
+                    (allocation.range.start / 4) as u32,
+                    allocation,
+                )
+            }
+        };
+
+        Ok(BasicMesh {
+            layout: V::LAYOUT,
+            vertex_buffer,
+            index_buffer,
+            bounds,
+            pool: Some(PooledRanges {
+                vertex_block,
+                index_block,
+                first_index,
+                vertex_offset,
+                _vertices: vertices,
+                _indices: indices,
+            }),
+        })
+    }
+
+    /// Finds `size` bytes in the kind's blocks, adding a block when none has room.
+    fn allocate(
+        &self,
+        kind: PoolKind,
+        size: DeviceSize,
+        alignment: DeviceSize,
+    ) -> anyhow::Result<(Subbuffer<[u8]>, PoolAllocation)> {
+        let mut blocks = self.blocks.lock().unwrap();
+        let kind_blocks = blocks.0.entry(kind).or_default();
+
+        let found = kind_blocks
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, block)| {
+                let block = block.as_mut()?;
+                let range = block.free.allocate(size, alignment)?;
+                Some((index, block.buffer.clone(), range))
+            });
+        let (index, buffer, range) = match found {
+            Some(found) => found,
+            None => {
+                let mut block = self.create_block(kind, size)?;
+                let range = block
+                    .free
+                    .allocate(size, alignment)
+                    .context("allocating from new mesh pool block")?;
+                let buffer = block.buffer.clone();
+                let index = match kind_blocks.iter().position(Option::is_none) {
+                    Some(index) => {
+                        kind_blocks[index] = Some(block);
+                        index
+                    }
+                    None => {
+                        kind_blocks.push(Some(block));
+                        kind_blocks.len() - 1
+                    }
+                };
+                (index, buffer, range)
+            }
+        };
+
+        Ok((
+            buffer,
+            PoolAllocation {
+                blocks: self.blocks.clone(),
+                kind,
+                block: index,
+                range,
+            },
+        ))
+    }
+
+    fn create_block(&self, kind: PoolKind, size: DeviceSize) -> anyhow::Result<Block> {
+        let size = size.next_multiple_of(4).max(self.block_size);
+        log::debug!("creating {} byte mesh pool block for {:?}", size, kind);
+        let usage = match kind {
+            PoolKind::Vertices(_) => BufferUsage::VERTEX_BUFFER,
+            PoolKind::U16Indices | PoolKind::U32Indices => BufferUsage::INDEX_BUFFER,
+        };
+        let buffer = Buffer::new_slice::<u8>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: usage | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            size,
+        )
+        .context("creating mesh pool block")?;
+
+        Ok(Block {
+            _memory: self.memory_tracker.track(MemoryCategory::Meshes, size),
+            free: FreeList::new(size),
+            buffer,
+        })
+    }
+}
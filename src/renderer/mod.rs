@@ -1,16 +1,92 @@
+pub use adapter::{AdapterInfo, DeviceSelector};
+pub use analysis::{AnalysisReport, HISTOGRAM_BINS};
+pub use batch::StaticBatch;
+pub use billboard::Billboard;
+pub use config::{ColorWorkflow, RendererConfig};
+pub use foliage::{FoliageId, FoliageInstance, FoliageLayer, Wind};
 pub use frame_system::FrameSystem;
 pub use geometry::GeometrySystem;
-pub use geometry_shaders::{CUBE_INDICES, CUBE_VERTICES};
+pub use geometry_shaders::{VertexPositionColorNormal, CUBE_INDICES, CUBE_VERTICES};
+pub use hud::{HudPanel, UiPrimitive};
+pub use ibl::EnvironmentMaps;
+pub use layers::{OverlayCamera, RenderLayers, MAX_OVERLAY_CAMERAS, VIEW_MODEL_LAYER};
+pub use lighting::{
+    color_temperature, exposure_from_ev100, Attenuation, DirectionalLight, PointLight, SkyGradient,
+};
+pub use material::{BlendMode, Material, MaterialId, MaterialShader, Tint};
+pub use memory::{MemoryCategory, MemoryStats};
+pub use mesh::Indices;
+pub use motion_blur::MotionBlurSettings;
+pub use outline::OutlineStyle;
 pub use pass::LightingPass;
 pub use pass::Pass;
+pub use picking::PickResult;
+pub use portal::{Portal, MAX_PORTAL_VIEWS};
+pub use post_process::UpscaleFilter;
+pub use probe::{ReflectionProbe, MAX_REFLECTION_PROBES};
+pub use readback::{ReadbackData, ReadbackFuture};
 pub use renderer::Renderer;
+pub use shadows::ShadowSettings;
+pub use sprite::SpriteMeshId;
+pub use ssgi::{GlobalIllumination, SsgiSettings};
+pub use ssr::{SsrQuality, SsrSettings};
+pub use stats::{FrameStats, PassTimes, SceneStats};
+pub use texture::TextureHandle;
+pub use upload::{MeshUploadHandle, UploadStatus};
+pub use user_pass::{PassContext, PassPoint, UserPass, UserPassId};
+pub use variants::{ConstantValue, ShaderVariants, VariantKey};
+pub use vertex::{
+    MeshVertex, VertexLayout, VertexPosition, VertexPositionNormalUv,
+    VertexPositionNormalUvLightmap, VertexPositionNormalUvTangent, VertexSkinned,
+};
+pub use vulkan_context::{Portability, VulkanContext};
 
+mod adapter;
+mod analysis;
+mod batch;
+mod billboard;
+mod capture;
+mod config;
+mod debug_draw;
+mod descriptor_cache;
+mod foliage;
 mod frame;
 mod frame_system;
+mod frames_in_flight;
 mod geometry;
 mod geometry_shaders;
+mod gpu_culling;
+mod hud;
+mod ibl;
+mod layers;
 mod lighting;
+mod material;
+mod memory;
 mod mesh;
+mod mesh_pool;
+mod motion_blur;
+mod occlusion;
+mod outline;
 mod pass;
+mod picking;
+mod portal;
+mod post_process;
+mod probe;
+mod readback;
+mod reflection;
 mod render_data;
 mod renderer;
+mod ring_buffer;
+mod shadows;
+mod sprite;
+mod ssgi;
+mod ssr;
+mod stats;
+mod taa;
+mod texture;
+mod upload;
+mod user_pass;
+mod variants;
+mod vertex;
+mod vulkan_context;
+mod window;
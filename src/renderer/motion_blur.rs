@@ -0,0 +1,246 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::layout::DescriptorType,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+const SCENE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const VELOCITY_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::CombinedImageSampler);
+const OUTPUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 2, DescriptorType::StorageImage);
+
+/// Longest blur, as a fraction of the screen.
+const MAX_BLUR_LENGTH: f32 = 0.05;
+
+/// How motion blur is sampled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionBlurSettings {
+    /// Samples taken along each pixel's motion. More gives smoother streaks at a higher cost.
+    pub samples: u32,
+    /// How long the virtual shutter stays open, in degrees of a frame. 360 blurs over the whole
+    /// frame's motion, 180 over half of it, like film.
+    pub shutter_angle: f32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        MotionBlurSettings {
+            samples: 8,
+            shutter_angle: 180.0,
+        }
+    }
+}
+
+/// Blurs the scene along the velocity buffer, covering both camera and object motion. Pixels
+/// nothing was drawn to have no velocity and stay sharp.
+pub struct MotionBlur {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    debug_namer: DebugNamer,
+    pipeline: Arc<ComputePipeline>,
+    linear_sampler: Arc<Sampler>,
+    nearest_sampler: Arc<Sampler>,
+    /// Recreated when the HDR target is.
+    output: Arc<ImageView>,
+    memory_tracker: MemoryTracker,
+    output_memory: Option<TrackedMemory>,
+    enabled: bool,
+    settings: MotionBlurSettings,
+}
+
+impl MotionBlur {
+    pub fn new(
+        context: &VulkanContext,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let cs = cs::load(device.clone())
+            .context("loading motion blur shader")?
+            .entry_point("main")
+            .context("motion blur shader entry point not found")?;
+        validate_descriptor_bindings(
+            "MotionBlur",
+            &[&cs],
+            &[SCENE_BINDING, VELOCITY_BINDING, OUTPUT_BINDING],
+        )?;
+
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating pipeline layout create info")?,
+        )
+        .context("creating pipeline layout")?;
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .context("creating motion blur pipeline")?;
+
+        let sampler = |filter: Filter| {
+            Sampler::new(
+                device.clone(),
+                SamplerCreateInfo {
+                    mag_filter: filter,
+                    min_filter: filter,
+                    address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                    ..Default::default()
+                },
+            )
+            .context("creating motion blur sampler")
+        };
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(pipeline.as_ref(), "motion blur pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let output = Self::create_output(&memory_allocator, [1, 1, 1])?;
+
+        Ok(MotionBlur {
+            memory_allocator,
+            descriptor_set_cache,
+            debug_namer,
+            pipeline,
+            linear_sampler: sampler(Filter::Linear)?,
+            nearest_sampler: sampler(Filter::Nearest)?,
+            output,
+            memory_tracker: context.memory_tracker().clone(),
+            output_memory: None,
+            enabled: false,
+            settings: MotionBlurSettings::default(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn settings(&self) -> MotionBlurSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: MotionBlurSettings) {
+        self.settings = settings;
+    }
+
+    /// Recreates the output for an HDR target of `extent`.
+    pub fn resize(&mut self, extent: [u32; 3]) -> anyhow::Result<()> {
+        self.output = Self::create_output(&self.memory_allocator, extent)?;
+        self.debug_namer
+            .name(self.output.image().as_ref(), "motion blur output");
+        self.output_memory = Some(
+            self.memory_tracker
+                .track_image(MemoryCategory::RenderTargets, self.output.image()),
+        );
+        Ok(())
+    }
+
+    /// Blurs `scene` along `velocity` and returns the blurred image. Must be recorded outside of
+    /// a render pass.
+    pub fn record(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        scene: &Arc<ImageView>,
+        velocity: &Arc<ImageView>,
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &self.pipeline.layout().set_layouts()[0],
+            &[
+                CachedWrite::ImageViewSampler(
+                    SCENE_BINDING.binding,
+                    scene.clone(),
+                    self.linear_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    VELOCITY_BINDING.binding,
+                    velocity.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageView(OUTPUT_BINDING.binding, self.output.clone()),
+            ],
+        )?;
+
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("binding motion blur pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding motion blur descriptor set")?
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    sample_count: self.settings.samples.clamp(1, 64),
+                    shutter: self.settings.shutter_angle.clamp(0.0, 360.0) / 360.0,
+                    max_length: MAX_BLUR_LENGTH,
+                },
+            )
+            .context("pushing motion blur constants")?;
+
+        let extent = self.output.image().extent();
+        unsafe { builder.dispatch([extent[0].div_ceil(8), extent[1].div_ceil(8), 1]) }
+            .context("dispatching motion blur")?;
+
+        Ok(self.output.clone())
+    }
+
+    fn create_output(
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        extent: [u32; 3],
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let image = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16B16A16_SFLOAT,
+                extent,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating motion blur output")?;
+        ImageView::new_default(image).context("creating motion blur output view")
+    }
+}
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/post/motion_blur.comp"
+    }
+}
@@ -0,0 +1,458 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{Matrix4, Vector4};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        CopyImageToBufferInfo, RecordingCommandBuffer,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, layout::DescriptorType, DescriptorSet,
+        WriteDescriptorSet,
+    },
+    device::{Queue, QueueFlags},
+    image::Image,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use super::{
+    frames_in_flight::InFlightFrame,
+    mesh::Aabb,
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::VulkanContext,
+};
+
+/// Size of the pyramid's largest level. Each texel holds the farthest depth of the screen
+/// region it covers.
+const PYRAMID_BASE: [u32; 2] = [256, 128];
+
+const DEPTH_TEXELS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::StorageBuffer);
+const PYRAMID_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::StorageBuffer);
+
+#[derive(Debug, Clone, Copy)]
+struct PyramidLevel {
+    size: [u32; 2],
+    offset: u32,
+}
+
+fn pyramid_levels() -> Vec<PyramidLevel> {
+    let mut levels = vec![];
+    let mut size = PYRAMID_BASE;
+    let mut offset = 0;
+    loop {
+        levels.push(PyramidLevel { size, offset });
+        if size == [1, 1] {
+            return levels;
+        }
+        offset += size[0] * size[1];
+        size = [(size[0] / 2).max(1), (size[1] / 2).max(1)];
+    }
+}
+
+/// Keeps the farthest value of each `src` region covering a `dst` texel.
+fn reduce(src: impl Fn(u32, u32) -> f32, src_size: [u32; 2], dst_size: [u32; 2]) -> Vec<f32> {
+    let mut values = Vec::with_capacity((dst_size[0] * dst_size[1]) as usize);
+    for y in 0..dst_size[1] {
+        for x in 0..dst_size[0] {
+            let start = [x * src_size[0] / dst_size[0], y * src_size[1] / dst_size[1]];
+            let end = [
+                ((x + 1) * src_size[0] / dst_size[0])
+                    .max(start[0] + 1)
+                    .min(src_size[0]),
+                ((y + 1) * src_size[1] / dst_size[1])
+                    .max(start[1] + 1)
+                    .min(src_size[1]),
+            ];
+            let mut farthest = 0.0f32;
+            for sy in start[1]..end[1] {
+                for sx in start[0]..end[0] {
+                    farthest = farthest.max(src(sx, sy));
+                }
+            }
+            values.push(farthest);
+        }
+    }
+    values
+}
+
+/// A hierarchical depth buffer read back from a completed frame, along with the view projection
+/// it was rendered with.
+pub struct DepthPyramid {
+    levels: Vec<PyramidLevel>,
+    values: Vec<f32>,
+    view_projection: Matrix4<f32>,
+}
+
+impl DepthPyramid {
+    /// Conservatively tests whether `bounds`, transformed by `model`, could be visible. Anything
+    /// crossing the near plane or leaving the screen is treated as visible, since the pyramid
+    /// says nothing about it.
+    pub fn is_visible(&self, model: &Matrix4<f32>, bounds: &Aabb) -> bool {
+        let model_view_projection = self.view_projection * model;
+
+        let mut min = [f32::MAX, f32::MAX];
+        let mut max = [f32::MIN, f32::MIN];
+        let mut nearest = f32::MAX;
+
+        for corner in bounds.corners() {
+            let clip = model_view_projection * Vector4::new(corner[0], corner[1], corner[2], 1.0);
+            if clip.w <= 0.0 {
+                return true;
+            }
+            let ndc = clip.truncate() / clip.w;
+            let uv = [ndc.x * 0.5 + 0.5, ndc.y * 0.5 + 0.5];
+            min = [min[0].min(uv[0]), min[1].min(uv[1])];
+            max = [max[0].max(uv[0]), max[1].max(uv[1])];
+            nearest = nearest.min(ndc.z);
+        }
+
+        if nearest < 0.0 || min[0] < 0.0 || min[1] < 0.0 || max[0] > 1.0 || max[1] > 1.0 {
+            return true;
+        }
+
+        // Pick the level where the bounds cover at most a couple of texels per axis
+        let extent = ((max[0] - min[0]) * PYRAMID_BASE[0] as f32)
+            .max((max[1] - min[1]) * PYRAMID_BASE[1] as f32)
+            .max(1.0);
+        let level_index = (extent.log2().ceil() as usize).min(self.levels.len() - 1);
+        let level = self.levels[level_index];
+
+        let to_texel = |uv: f32, size: u32| ((uv * size as f32) as u32).min(size - 1);
+        let start = [
+            to_texel(min[0], level.size[0]),
+            to_texel(min[1], level.size[1]),
+        ];
+        let end = [
+            to_texel(max[0], level.size[0]),
+            to_texel(max[1], level.size[1]),
+        ];
+
+        let mut farthest = 0.0f32;
+        for y in start[1]..=end[1] {
+            for x in start[0]..=end[0] {
+                let index = level.offset + y * level.size[0] + x;
+                farthest = farthest.max(self.values[index as usize]);
+            }
+        }
+
+        nearest <= farthest
+    }
+}
+
+struct SlotTarget {
+    extent: [u32; 2],
+    depth_texels: Subbuffer<[u32]>,
+    /// Only used when the pyramid is built on the GPU.
+    pyramid: Option<(Subbuffer<[f32]>, Arc<DescriptorSet>)>,
+    recorded_view_projection: Option<Matrix4<f32>>,
+}
+
+/// Builds a depth pyramid out of each frame's depth attachment and keeps the most recent one
+/// around for testing object bounds against.
+///
+/// The pyramid is reduced by a compute pass when the graphics queue supports compute, otherwise
+/// the depth attachment is copied to host memory and reduced on the CPU. Either way the result is
+/// read back once the frame's slot comes around again, so it lags the current frame by the number
+/// of frames in flight.
+pub struct OcclusionCuller {
+    gfx_queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    pipeline: Option<Arc<ComputePipeline>>,
+    levels: Vec<PyramidLevel>,
+    slots: Vec<Option<SlotTarget>>,
+    pyramid: Option<DepthPyramid>,
+    enabled: bool,
+}
+
+impl OcclusionCuller {
+    pub fn new(
+        context: &VulkanContext,
+        frames_in_flight: usize,
+        enabled: bool,
+    ) -> anyhow::Result<Self> {
+        let gfx_queue = context.graphics_queue().clone();
+        let device = context.device();
+
+        let supports_compute = device.physical_device().queue_family_properties()
+            [gfx_queue.queue_family_index() as usize]
+            .queue_flags
+            .intersects(QueueFlags::COMPUTE);
+
+        let pipeline = if supports_compute {
+            let cs = hiz_cs::load(device.clone())
+                .context("loading depth pyramid shader")?
+                .entry_point("main")
+                .context("depth pyramid shader entry point not found")?;
+            validate_descriptor_bindings(
+                "OcclusionCuller",
+                &[&cs],
+                &[DEPTH_TEXELS_BINDING, PYRAMID_BINDING],
+            )?;
+
+            let stage = PipelineShaderStageCreateInfo::new(cs);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("creating pipeline layout create info")?,
+            )
+            .context("creating pipeline layout")?;
+
+            let pipeline = ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .context("creating depth pyramid pipeline")?;
+            context
+                .debug_namer()
+                .name(pipeline.as_ref(), "depth pyramid pipeline");
+            Some(pipeline)
+        } else {
+            log::info!("graphics queue has no compute support, building depth pyramid on the CPU");
+            None
+        };
+
+        Ok(OcclusionCuller {
+            gfx_queue,
+            memory_allocator: context.memory_allocator().clone(),
+            descriptor_set_allocator: context.descriptor_set_allocator().clone(),
+            pipeline,
+            levels: pyramid_levels(),
+            slots: (0..frames_in_flight).map(|_| None).collect(),
+            pyramid: None,
+            enabled,
+        })
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.pyramid = None;
+        }
+    }
+
+    /// The most recent pyramid, `None` until one has been read back or while culling is
+    /// disabled.
+    pub fn pyramid(&self) -> Option<&DepthPyramid> {
+        self.pyramid.as_ref().filter(|_| self.enabled)
+    }
+
+    /// Reads back the pyramid recorded with `frame`'s slot. Call after the slot's fence has been
+    /// waited on.
+    pub fn collect(&mut self, frame: &InFlightFrame) -> anyhow::Result<()> {
+        let Some(slot) = self.slots[frame.index].as_mut() else {
+            return Ok(());
+        };
+        let Some(view_projection) = slot.recorded_view_projection.take() else {
+            return Ok(());
+        };
+
+        let values = match &slot.pyramid {
+            Some((pyramid, _)) => pyramid.read().context("reading depth pyramid")?.to_vec(),
+            None => {
+                let texels = slot.depth_texels.read().context("reading depth texels")?;
+                let extent = slot.extent;
+                let depth = |x: u32, y: u32| {
+                    let i = y * extent[0] + x;
+                    let packed = texels[(i / 2) as usize];
+                    let value = if i % 2 == 0 {
+                        packed & 0xFFFF
+                    } else {
+                        packed >> 16
+                    };
+                    value as f32 / u16::MAX as f32
+                };
+
+                let mut values = reduce(depth, extent, self.levels[0].size);
+                for pair in self.levels.windows(2) {
+                    let (src, dst) = (pair[0], pair[1]);
+                    let next = reduce(
+                        |x, y| values[(src.offset + y * src.size[0] + x) as usize],
+                        src.size,
+                        dst.size,
+                    );
+                    values.extend(next);
+                }
+                values
+            }
+        };
+
+        self.pyramid = Some(DepthPyramid {
+            levels: self.levels.clone(),
+            values,
+            view_projection,
+        });
+
+        Ok(())
+    }
+
+    /// Records the pyramid reduction of `depth`, rendered with `view_projection`, into a command
+    /// buffer to be executed after the frame. Returns `None` while culling is disabled.
+    pub fn record(
+        &mut self,
+        frame: &InFlightFrame,
+        depth: Arc<Image>,
+        view_projection: Matrix4<f32>,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        let extent = [depth.extent()[0], depth.extent()[1]];
+        if !matches!(&self.slots[frame.index], Some(slot) if slot.extent == extent) {
+            self.slots[frame.index] = Some(self.create_slot_target(extent)?);
+        }
+        let slot = self.slots[frame.index]
+            .as_mut()
+            .context("getting occlusion slot")?;
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating depth pyramid command buffer")?;
+
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                depth,
+                slot.depth_texels.clone().into_bytes(),
+            ))
+            .context("copying depth attachment")?;
+
+        if let (Some(pipeline), Some((_, descriptor_set))) = (&self.pipeline, &slot.pyramid) {
+            builder
+                .bind_pipeline_compute(pipeline.clone())
+                .context("binding depth pyramid pipeline")?
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Compute,
+                    pipeline.layout().clone(),
+                    0,
+                    descriptor_set.clone(),
+                )
+                .context("binding depth pyramid descriptor set")?;
+
+            for (i, level) in self.levels.iter().enumerate() {
+                let (src_size, src_offset) = match i {
+                    0 => (extent, 0),
+                    _ => (self.levels[i - 1].size, self.levels[i - 1].offset),
+                };
+
+                builder
+                    .push_constants(
+                        pipeline.layout().clone(),
+                        0,
+                        hiz_cs::PushConstants {
+                            src_size,
+                            dst_size: level.size,
+                            src_offset,
+                            dst_offset: level.offset,
+                            from_depth: (i == 0) as u32,
+                        },
+                    )
+                    .context("pushing depth pyramid constants")?;
+
+                unsafe {
+                    builder.dispatch([level.size[0].div_ceil(8), level.size[1].div_ceil(8), 1])
+                }
+                .context("dispatching depth pyramid reduction")?;
+            }
+        }
+
+        slot.recorded_view_projection = Some(view_projection);
+
+        builder
+            .end()
+            .context("ending depth pyramid command buffer")
+            .map(Some)
+    }
+
+    fn create_slot_target(&self, extent: [u32; 2]) -> anyhow::Result<SlotTarget> {
+        // The CPU path reads the texels back directly
+        let depth_memory = if self.pipeline.is_some() {
+            MemoryTypeFilter::PREFER_DEVICE
+        } else {
+            MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS
+        };
+
+        let depth_texels = Buffer::new_slice::<u32>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: depth_memory,
+                ..Default::default()
+            },
+            (extent[0] * extent[1]).div_ceil(2) as u64,
+        )
+        .context("creating depth texel buffer")?;
+
+        let pyramid = match &self.pipeline {
+            Some(pipeline) => {
+                let last = self.levels.last().context("getting last pyramid level")?;
+                let pyramid = Buffer::new_slice::<f32>(
+                    self.memory_allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::STORAGE_BUFFER,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                            | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                        ..Default::default()
+                    },
+                    (last.offset + 1) as u64,
+                )
+                .context("creating depth pyramid buffer")?;
+
+                let descriptor_set = DescriptorSet::new(
+                    self.descriptor_set_allocator.clone(),
+                    pipeline.layout().set_layouts()[0].clone(),
+                    [
+                        WriteDescriptorSet::buffer(
+                            DEPTH_TEXELS_BINDING.binding,
+                            depth_texels.clone(),
+                        ),
+                        WriteDescriptorSet::buffer(PYRAMID_BINDING.binding, pyramid.clone()),
+                    ],
+                    [],
+                )
+                .context("creating depth pyramid descriptor set")?;
+
+                Some((pyramid, descriptor_set))
+            }
+            None => None,
+        };
+
+        Ok(SlotTarget {
+            extent,
+            depth_texels,
+            pyramid,
+            recorded_view_projection: None,
+        })
+    }
+}
+
+mod hiz_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/occlusion/hiz.comp"
+    }
+}
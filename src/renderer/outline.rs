@@ -0,0 +1,360 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        RecordingCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents,
+    },
+    descriptor_set::layout::DescriptorType,
+    device::Queue,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    frames_in_flight::InFlightFrame,
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+const MASK_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+
+/// Widest outline the shader will draw, in mask pixels.
+const MAX_WIDTH: f32 = 8.0;
+
+/// How selected objects are outlined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlineStyle {
+    /// Straight alpha, blended over the final image.
+    pub color: [f32; 4],
+    /// In pixels of the scene's render resolution, up to 8.
+    pub width: f32,
+}
+
+impl Default for OutlineStyle {
+    fn default() -> Self {
+        OutlineStyle {
+            color: [1.0, 0.6, 0.1, 1.0],
+            width: 2.0,
+        }
+    }
+}
+
+/// Outlines the selected objects. Their silhouettes are drawn into a mask before the frame, and
+/// the outline is drawn over the composited image wherever a pixel outside the mask has a masked
+/// pixel within the outline's width.
+pub struct Outline {
+    gfx_queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    debug_namer: DebugNamer,
+    mask_render_pass: Arc<RenderPass>,
+    pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    /// Matches the HDR target, resized when the mask is drawn.
+    mask: Arc<ImageView>,
+    mask_framebuffer: Arc<Framebuffer>,
+    memory_tracker: MemoryTracker,
+    mask_memory: Option<TrackedMemory>,
+    style: OutlineStyle,
+    /// Whether the mask was drawn this frame.
+    active: bool,
+}
+
+impl Outline {
+    pub fn new(
+        context: &VulkanContext,
+        subpass: Subpass,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let mask_render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                mask: {
+                    format: Format::R8_UNORM,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+            },
+            pass: {
+                color: [mask],
+                depth_stencil: {},
+            },
+        )
+        .context("creating selection mask render pass")?;
+
+        let vs = vs::load(device.clone())
+            .context("loading outline vertex shader")?
+            .entry_point("main")
+            .context("outline vertex shader entry point not found")?;
+        let fs = fs::load(device.clone())
+            .context("loading outline fragment shader")?
+            .entry_point("main")
+            .context("outline fragment shader entry point not found")?;
+        validate_descriptor_bindings("Outline", &[&fs], &[MASK_BINDING])?;
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating pipeline layout create info")?,
+        )
+        .context("creating pipeline layout")?;
+
+        let pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    },
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .context("creating outline pipeline")?;
+
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .context("creating outline sampler")?;
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(mask_render_pass.as_ref(), "selection mask render pass");
+        debug_namer.name(pipeline.as_ref(), "outline pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let mask = Self::create_mask(&memory_allocator, [1, 1, 1])?;
+        let mask_framebuffer = Self::create_mask_framebuffer(&mask_render_pass, &mask)?;
+
+        Ok(Outline {
+            gfx_queue: context.graphics_queue().clone(),
+            memory_allocator,
+            descriptor_set_cache,
+            debug_namer,
+            mask_render_pass,
+            pipeline,
+            sampler,
+            mask,
+            mask_framebuffer,
+            memory_tracker: context.memory_tracker().clone(),
+            mask_memory: None,
+            style: OutlineStyle::default(),
+            active: false,
+        })
+    }
+
+    pub fn style(&self) -> OutlineStyle {
+        self.style
+    }
+
+    pub fn set_style(&mut self, style: OutlineStyle) {
+        self.style = style;
+    }
+
+    /// The subpass selected objects' silhouettes are drawn in.
+    pub fn mask_subpass(&self) -> Subpass {
+        Subpass::from(self.mask_render_pass.clone(), 0).expect("mask render pass has a subpass")
+    }
+
+    /// Recreates the mask for an HDR target of `extent`.
+    fn resize(&mut self, extent: [u32; 3]) -> anyhow::Result<()> {
+        self.mask = Self::create_mask(&self.memory_allocator, extent)?;
+        self.mask_framebuffer = Self::create_mask_framebuffer(&self.mask_render_pass, &self.mask)?;
+        self.debug_namer
+            .name(self.mask.image().as_ref(), "selection mask");
+        self.mask_memory = Some(
+            self.memory_tracker
+                .track_image(MemoryCategory::RenderTargets, self.mask.image()),
+        );
+        Ok(())
+    }
+
+    /// Wraps `silhouettes`, drawn in `mask_subpass` at the HDR target's `extent`, in a command
+    /// buffer that clears and fills the mask. Without silhouettes nothing is selected, and no
+    /// outline is drawn this frame.
+    pub fn record_mask(
+        &mut self,
+        frame: &InFlightFrame,
+        extent: [u32; 3],
+        silhouettes: Option<Arc<CommandBuffer>>,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        self.active = silhouettes.is_some();
+        let Some(silhouettes) = silhouettes else {
+            return Ok(None);
+        };
+        // The mask is drawn before the frame resizes the rest of the targets
+        if self.mask.image().extent() != extent {
+            self.resize(extent)?;
+        }
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating selection mask command buffer")?;
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some([0.0f32].into())],
+                    ..RenderPassBeginInfo::framebuffer(self.mask_framebuffer.clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::SecondaryCommandBuffers,
+                    ..Default::default()
+                },
+            )
+            .context("beginning selection mask render pass")?
+            .execute_commands(silhouettes)
+            .context("executing selection silhouettes")?
+            .end_render_pass(Default::default())
+            .context("ending selection mask render pass")?;
+
+        Ok(Some(
+            builder
+                .end()
+                .context("building selection mask command buffer")?,
+        ))
+    }
+
+    /// Draws the outline over the composited image, if anything was selected this frame. Must be
+    /// recorded in the composite subpass.
+    pub fn record(&self, builder: &mut RecordingCommandBuffer) -> anyhow::Result<()> {
+        if !self.active {
+            return Ok(());
+        }
+
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &self.pipeline.layout().set_layouts()[0],
+            &[CachedWrite::ImageViewSampler(
+                MASK_BINDING.binding,
+                self.mask.clone(),
+                self.sampler.clone(),
+            )],
+        )?;
+
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .context("binding outline pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding outline descriptor set")?
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                fs::PushConstants {
+                    color: self.style.color,
+                    width: self.style.width.clamp(0.0, MAX_WIDTH),
+                },
+            )
+            .context("pushing outline constants")?;
+        unsafe { builder.draw(3, 1, 0, 0) }.context("drawing outline")?;
+
+        Ok(())
+    }
+
+    fn create_mask(
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        extent: [u32; 3],
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let image = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R8_UNORM,
+                extent,
+                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating selection mask")?;
+        ImageView::new_default(image).context("creating selection mask view")
+    }
+
+    fn create_mask_framebuffer(
+        render_pass: &Arc<RenderPass>,
+        mask: &Arc<ImageView>,
+    ) -> anyhow::Result<Arc<Framebuffer>> {
+        Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![mask.clone()],
+                ..Default::default()
+            },
+        )
+        .context("creating selection mask framebuffer")
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/outline/outline.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/outline/outline.frag"
+    }
+}
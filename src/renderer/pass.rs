@@ -1,12 +1,19 @@
 use std::sync::Arc;
 
 use anyhow::Context;
-use cgmath::{Matrix4, SquareMatrix, Vector3};
+use cgmath::{Matrix4, SquareMatrix};
 use vulkano::{command_buffer::CommandBuffer, sync::GpuFuture};
 
-use super::frame::Frame;
+use super::{
+    frame::Frame,
+    frames_in_flight::InFlightFrame,
+    lighting::{DirectionalLight, SkyGradient},
+    user_pass::{PassContext, PassPoint},
+};
 
 pub enum Pass<'f, 's: 'f> {
+    /// Only returned when `FrameSystem::depth_prepass` is enabled.
+    DepthPrepass(DrawPass<'f, 's>),
     Deferred(DrawPass<'f, 's>),
     Lighting(LightingPass<'f, 's>),
     Finished(Box<dyn GpuFuture>),
@@ -26,10 +33,35 @@ impl<'f, 's: 'f> DrawPass<'f, 's> {
         Ok(())
     }
 
+    /// Records the user passes added at `PassPoint::Geometry` and executes them in the subpass.
+    pub fn execute_user_passes(&mut self) -> anyhow::Result<()> {
+        let subpass = self.frame.system.deferred_subpass();
+        let system = &mut *self.frame.system;
+        let command_buffers = system.user_passes.record(PassContext {
+            point: PassPoint::Geometry,
+            queue: &system.gfx_queue,
+            command_buffer_allocator: &self.frame.in_flight.command_buffer_allocator,
+            frame_index: self.frame.in_flight.index,
+            subpass: Some(subpass),
+            extent: self.frame.framebuffer.extent(),
+            view_projection: self.frame.world_to_framebuffer,
+            depth: &system.depth_buffer,
+            target: None,
+        })?;
+        for cb in command_buffers {
+            self.execute(cb).context("executing user pass")?;
+        }
+        Ok(())
+    }
+
     pub fn viewport_dimensions(&self) -> [u32; 2] {
         self.frame.framebuffer.extent()
     }
 
+    pub fn in_flight(&self) -> &InFlightFrame {
+        &self.frame.in_flight
+    }
+
     #[allow(dead_code)]
     pub fn world_to_framebuffer_matrix(&self) -> Matrix4<f32> {
         self.frame.world_to_framebuffer
@@ -42,13 +74,26 @@ pub struct LightingPass<'f, 's: 'f> {
 
 impl<'f, 's: 'f> LightingPass<'f, 's> {
     pub fn ambient_light(&mut self, color: [f32; 3]) -> anyhow::Result<()> {
+        let color = self.frame.system.linear_color(color);
+        self.frame.system.ssgi.set_ambient_color(color);
+        self.frame.system.ssr.set_ambient_color(color);
         let command_buffer = self
             .frame
             .system
             .ambient_lighting_system
             .draw(
+                &self.frame.in_flight,
                 self.frame.framebuffer.extent(),
                 self.frame.system.diffuse_buffer.clone(),
+                self.frame.system.normals_buffer.clone(),
+                self.frame.system.depth_buffer.clone(),
+                self.frame.system.material_buffer.clone(),
+                self.frame.system.emissive_buffer.clone(),
+                self.frame
+                    .world_to_framebuffer
+                    .invert()
+                    .context("inverting matrix")?,
+                &self.frame.system.environment,
                 color,
             )
             .context("ambient lighting draw")?;
@@ -61,21 +106,31 @@ impl<'f, 's: 'f> LightingPass<'f, 's> {
         Ok(())
     }
 
-    pub fn directional_light(
-        &mut self,
-        direction: Vector3<f32>,
-        color: [f32; 3],
-    ) -> anyhow::Result<()> {
+    pub fn directional_light(&mut self, light: &DirectionalLight) -> anyhow::Result<()> {
+        let intensity = light.illuminance * self.frame.system.exposure();
+        let color = self
+            .frame
+            .system
+            .linear_color(light.color)
+            .map(|channel| channel * intensity);
         let command_buffer = self
             .frame
             .system
             .directional_lighting_system
             .draw(
+                &self.frame.in_flight,
                 self.frame.framebuffer.extent(),
                 self.frame.system.diffuse_buffer.clone(),
                 self.frame.system.normals_buffer.clone(),
-                direction,
+                self.frame.system.depth_buffer.clone(),
+                self.frame.system.material_buffer.clone(),
+                self.frame
+                    .world_to_framebuffer
+                    .invert()
+                    .context("inverting matrix")?,
+                light.direction,
                 color,
+                &self.frame.system.shadows,
             )
             .context("drawing directional lights")?;
 
@@ -88,26 +143,28 @@ impl<'f, 's: 'f> LightingPass<'f, 's> {
         Ok(())
     }
 
-    pub fn point_light(&mut self, position: Vector3<f32>, color: [f32; 3]) -> anyhow::Result<()> {
-        let command_buffer = {
-            self.frame
-                .system
-                .point_lighting_system
-                .draw(
-                    self.frame.framebuffer.extent(),
-                    self.frame.system.diffuse_buffer.clone(),
-                    self.frame.system.normals_buffer.clone(),
-                    self.frame.system.depth_buffer.clone(),
-                    self.frame
-                        .world_to_framebuffer
-                        .invert()
-                        .context("inverting matrix")?,
-                    position,
-                    color,
-                )
-                .context("drawing point lights")?
+    /// Draws `gradient` where the deferred pass didn't draw anything.
+    pub fn sky(&mut self, gradient: &SkyGradient) -> anyhow::Result<()> {
+        let gradient = SkyGradient {
+            zenith: self.frame.system.linear_color(gradient.zenith),
+            horizon: self.frame.system.linear_color(gradient.horizon),
+            ground: self.frame.system.linear_color(gradient.ground),
         };
-
+        let command_buffer = self
+            .frame
+            .system
+            .sky_system
+            .draw(
+                &self.frame.in_flight,
+                self.frame.framebuffer.extent(),
+                self.frame.system.depth_buffer.clone(),
+                self.frame
+                    .world_to_framebuffer
+                    .invert()
+                    .context("inverting matrix")?,
+                &gradient,
+            )
+            .context("drawing sky")?;
         self.frame
             .command_buffer_builder
             .as_mut()
@@ -116,4 +173,36 @@ impl<'f, 's: 'f> LightingPass<'f, 's> {
             .context("executing commands")?;
         Ok(())
     }
+
+    /// Draws the point lights passed to `FrameSystem::frame`, using the clusters they were
+    /// culled into.
+    pub fn point_lights(&mut self) -> anyhow::Result<()> {
+        let command_buffer = self
+            .frame
+            .system
+            .point_lighting_system
+            .draw(
+                &self.frame.in_flight,
+                self.frame.framebuffer.extent(),
+                self.frame.system.diffuse_buffer.clone(),
+                self.frame.system.normals_buffer.clone(),
+                self.frame.system.depth_buffer.clone(),
+                self.frame.system.material_buffer.clone(),
+                self.frame
+                    .world_to_framebuffer
+                    .invert()
+                    .context("inverting matrix")?,
+            )
+            .context("drawing point lights")?;
+
+        if let Some(command_buffer) = command_buffer {
+            self.frame
+                .command_buffer_builder
+                .as_mut()
+                .context("getting command buffer builder")?
+                .execute_commands(command_buffer)
+                .context("executing commands")?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        RecordingCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents,
+    },
+    device::Queue,
+    format::Format,
+    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+};
+
+use super::{
+    frames_in_flight::InFlightFrame,
+    readback::{ReadbackFuture, Readbacks},
+    vulkan_context::VulkanContext,
+};
+
+/// What was under a pixel asked for with `Renderer::request_pick`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PickResult {
+    /// As asked for, in window pixels.
+    pub position: [u32; 2],
+    /// The key of the tracked object drawn there, `None` when nothing was, or the object wasn't
+    /// queued with a key.
+    pub key: Option<u64>,
+}
+
+struct PendingPick {
+    position: [u32; 2],
+    /// The tracked objects' keys by index, as they were when the pick was drawn.
+    keys: Vec<Option<u64>>,
+    readback: ReadbackFuture,
+}
+
+/// Picks objects exactly per pixel. The objects are drawn again, with the same vertex shaders as
+/// the G-buffer, into a one pixel object ID target with the viewport shifted so the picked pixel
+/// lands on it. Only frames with a pick requested draw it.
+///
+/// The pixel is read back once its frame's slot comes around again, so a pick resolves a few
+/// frames after it was asked for. It complements ray casting against the spatial index, which
+/// only knows the bounds.
+pub struct ObjectPicker {
+    gfx_queue: Arc<Queue>,
+    render_pass: Arc<RenderPass>,
+    object_ids: Arc<ImageView>,
+    framebuffer: Arc<Framebuffer>,
+    /// Where in the window to pick next, with the window's size when it was asked for.
+    requested: Option<([u32; 2], [u32; 2])>,
+    pending: Vec<PendingPick>,
+    result: Option<PickResult>,
+}
+
+impl ObjectPicker {
+    pub fn new(context: &VulkanContext) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                object_ids: {
+                    format: Format::R32_UINT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                depth: {
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: DontCare,
+                },
+            },
+            pass: {
+                color: [object_ids],
+                depth_stencil: {depth},
+            },
+        )
+        .context("creating object ID render pass")?;
+
+        let memory_allocator = context.memory_allocator().clone();
+        let object_ids = create_target(
+            &memory_allocator,
+            Format::R32_UINT,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+        )
+        .context("creating object ID target")?;
+        let depth = create_target(
+            &memory_allocator,
+            Format::D16_UNORM,
+            ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+        )
+        .context("creating object ID depth")?;
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![object_ids.clone(), depth],
+                ..Default::default()
+            },
+        )
+        .context("creating object ID framebuffer")?;
+
+        let debug_namer = context.debug_namer();
+        debug_namer.name(render_pass.as_ref(), "object ID render pass");
+        debug_namer.name(object_ids.image().as_ref(), "object ID target");
+
+        Ok(ObjectPicker {
+            gfx_queue: context.graphics_queue().clone(),
+            render_pass,
+            object_ids,
+            framebuffer,
+            requested: None,
+            pending: vec![],
+            result: None,
+        })
+    }
+
+    /// The subpass the object IDs are drawn in.
+    pub fn subpass(&self) -> Subpass {
+        Subpass::from(self.render_pass.clone(), 0).expect("object ID render pass has a subpass")
+    }
+
+    /// Picks whatever is drawn at `position` of a `window_size` window in the next frame,
+    /// replacing a pick asked for earlier that frame.
+    pub fn request(&mut self, position: [u32; 2], window_size: [u32; 2]) {
+        self.requested = Some((position, window_size));
+    }
+
+    /// The pixel of a `render_extent` scene to pick this frame, taking the request.
+    pub fn take_request(&mut self, render_extent: [u32; 2]) -> Option<([u32; 2], [u32; 2])> {
+        let (position, window_size) = self.requested.take()?;
+        // The scene is rendered at the render scale, and upscaled to the window
+        let scale = |axis: usize| {
+            let scaled = position[axis] as u64 * render_extent[axis] as u64
+                / window_size[axis].max(1) as u64;
+            (scaled as u32).min(render_extent[axis].saturating_sub(1))
+        };
+        Some((position, [scale(0), scale(1)]))
+    }
+
+    /// Wraps `object_ids`, drawn in `subpass` for the pixel picked at `position`, in a command
+    /// buffer that clears and fills the target, and reads the pixel back through `readbacks`
+    /// after the frame. `keys` are the tracked objects' keys by index.
+    pub fn record(
+        &mut self,
+        frame: &InFlightFrame,
+        object_ids: Arc<CommandBuffer>,
+        position: [u32; 2],
+        keys: Vec<Option<u64>>,
+        readbacks: &mut Readbacks,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating object ID command buffer")?;
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some([0u32].into()), Some(1f32.into())],
+                    ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::SecondaryCommandBuffers,
+                    ..Default::default()
+                },
+            )
+            .context("beginning object ID render pass")?
+            .execute_commands(object_ids)
+            .context("executing object ID draws")?
+            .end_render_pass(Default::default())
+            .context("ending object ID render pass")?;
+
+        self.pending.push(PendingPick {
+            position,
+            keys,
+            readback: readbacks.read_image(self.object_ids.image().clone()),
+        });
+
+        builder.end().context("building object ID command buffer")
+    }
+
+    /// Resolves the picks whose pixel has been read back. The most recent result is kept for
+    /// `take_result`.
+    pub fn collect(&mut self) {
+        let mut index = 0;
+        while index < self.pending.len() {
+            let Some(result) = self.pending[index].readback.try_take() else {
+                index += 1;
+                continue;
+            };
+            let pick = self.pending.remove(index);
+            match result {
+                Ok(data) => {
+                    let object = data.cast::<u32>().first().copied().unwrap_or(0);
+                    let key = object
+                        .checked_sub(1)
+                        .and_then(|index| pick.keys.get(index as usize).copied().flatten());
+                    self.result = Some(PickResult {
+                        position: pick.position,
+                        key,
+                    });
+                }
+                Err(e) => log::warn!("picking at {:?} failed: {:#}", pick.position, e),
+            }
+        }
+    }
+
+    pub fn take_result(&mut self) -> Option<PickResult> {
+        self.result.take()
+    }
+}
+
+/// A one pixel attachment of the object ID render pass.
+fn create_target(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    format: Format,
+    usage: ImageUsage,
+) -> anyhow::Result<Arc<ImageView>> {
+    let image = Image::new(
+        memory_allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [1, 1, 1],
+            usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )?;
+    Ok(ImageView::new_default(image)?)
+}
@@ -0,0 +1,665 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{InnerSpace, Matrix, Matrix4, SquareMatrix, Vector3, Vector4};
+use vulkano::{
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferLevel,
+        CommandBufferUsage, RecordingCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo,
+        SubpassContents,
+    },
+    descriptor_set::layout::DescriptorType,
+    device::Queue,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    shader::EntryPoint,
+};
+
+use crate::game::{Frustum, Transform};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    frames_in_flight::InFlightFrame,
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+const VIEW_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+
+/// Views rendered through portals each frame, counting every level of recursion. Portals past
+/// this are drawn black.
+pub const MAX_PORTAL_VIEWS: usize = 4;
+const VIEW_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+const VIEW_DEPTH_FORMAT: Format = Format::D16_UNORM;
+/// How far past the destination's plane a portal view starts, so what lies right on the plane,
+/// like the other half of a pair of portals, is clipped instead of fighting over depth.
+const CLIP_OFFSET: f32 = 0.01;
+
+/// A quad that shows the scene behind its destination, e.g. a doorway to somewhere else in the
+/// level or, with `Portal::mirror`, a mirror.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Portal {
+    /// Where the quad is, centered on its origin in x and y and facing +z. Only its position and
+    /// rotation, the quad's size is separate.
+    pub source: Matrix4<f32>,
+    /// The other side: what's on the -z side of the destination is seen through the front of
+    /// the source, as if the destination were moved onto the source.
+    pub destination: Matrix4<f32>,
+    /// Width and height of the quad.
+    pub size: [f32; 2],
+}
+
+impl Portal {
+    /// Looks from `source` out of `destination`. Their scales are ignored.
+    pub fn between(source: &Transform, destination: &Transform, size: [f32; 2]) -> Self {
+        Portal {
+            source: unscaled(source),
+            destination: unscaled(destination),
+            size,
+        }
+    }
+
+    /// Reflects what's in front of `source`.
+    pub fn mirror(source: &Transform, size: [f32; 2]) -> Self {
+        let source = unscaled(source);
+        Portal {
+            source,
+            destination: source * Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0),
+            size,
+        }
+    }
+
+    /// The quad, scaled to its size.
+    fn model(&self) -> Matrix4<f32> {
+        self.source * Matrix4::from_nonuniform_scale(self.size[0], self.size[1], 1.0)
+    }
+
+    /// Whether a camera at `position` sees the front of the quad.
+    fn faces(&self, position: Vector3<f32>) -> bool {
+        let normal = self.source.z.truncate();
+        normal.dot(position - self.source.w.truncate()) > 0.0
+    }
+
+    fn is_visible(&self, position: Vector3<f32>, frustum: &Frustum) -> bool {
+        if !self.faces(position) {
+            return false;
+        }
+        let model = self.model();
+        let corners = [[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]]
+            .map(|[x, y]| (model * Vector4::new(x, y, 0.0, 1.0)).truncate());
+        let (min, max) = corners
+            .iter()
+            .fold((corners[0], corners[0]), |(min, max), c| {
+                (
+                    Vector3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z)),
+                    Vector3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z)),
+                )
+            });
+        frustum.intersects_aabb(min, max)
+    }
+
+    /// The view that sees the destination from where `view` sees the source.
+    fn virtual_view(&self, view: Matrix4<f32>) -> anyhow::Result<Matrix4<f32>> {
+        let destination = self
+            .destination
+            .invert()
+            .context("inverting portal destination")?;
+        Ok(view * self.source * destination)
+    }
+
+    /// `projection` with its near plane moved onto the destination's plane, so nothing between
+    /// the virtual camera and the destination shows up in the portal. See Lengyel, "Oblique
+    /// View Frustum Depth Projection and Clipping".
+    fn oblique_projection(
+        &self,
+        projection: Matrix4<f32>,
+        view: Matrix4<f32>,
+    ) -> anyhow::Result<Matrix4<f32>> {
+        // Everything in front of the plane, facing down the destination's -z, is kept
+        let normal = -self.destination.z.truncate().normalize();
+        let point = self.destination.w.truncate();
+        let plane = normal.extend(-normal.dot(point) - CLIP_OFFSET);
+        let plane = view.invert().context("inverting portal view")?.transpose() * plane;
+
+        let corner = projection.invert().context("inverting portal projection")?
+            * Vector4::new(plane.x.signum(), plane.y.signum(), 1.0, 1.0);
+        let clip = plane / plane.dot(corner);
+
+        let mut projection = projection;
+        projection.x.z = clip.x;
+        projection.y.z = clip.y;
+        projection.z.z = clip.z;
+        projection.w.z = clip.w;
+        Ok(projection)
+    }
+}
+
+fn camera_position(view: Matrix4<f32>) -> anyhow::Result<Vector3<f32>> {
+    Ok(view.invert().context("inverting view")?.w.truncate())
+}
+
+fn unscaled(transform: &Transform) -> Matrix4<f32> {
+    Matrix4::from_translation(transform.position) * Matrix4::from(transform.rotation)
+}
+
+/// A view through a portal, rendered before the frame.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PortalView {
+    portal: usize,
+    /// The view the portal is seen in, `None` for the main camera.
+    parent: Option<usize>,
+    pub projection: Matrix4<f32>,
+    pub view: Matrix4<f32>,
+}
+
+/// How the scene through a portal is lit: by the ambient light and the directional light,
+/// without its shadows, the point lights or the environment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PortalLighting {
+    pub ambient: [f32; 3],
+    pub sun_direction: [f32; 3],
+    pub sun_color: [f32; 3],
+}
+
+struct ViewTarget {
+    color: Arc<ImageView>,
+    framebuffer: Arc<Framebuffer>,
+    _memory: [TrackedMemory; 2],
+}
+
+/// Renders the scene through each visible portal into an offscreen target before the frame,
+/// then draws the portals' quads sampling them. Portals seen through other portals are rendered
+/// first, up to `max_depth` levels deep; past that they're drawn black.
+///
+/// Views through portals are forward shaded with the geometry's meshes only: no foliage,
+/// billboards, point lights or shadows. The quads go into the G-buffer as emitted light, so the
+/// frame's lighting doesn't light the scene behind them twice.
+pub struct Portals {
+    gfx_queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    memory_tracker: MemoryTracker,
+    debug_namer: DebugNamer,
+    render_pass: Arc<RenderPass>,
+    deferred_subpass: Subpass,
+    /// Draws quads into the views of their parent portals.
+    surface_pipeline: Arc<GraphicsPipeline>,
+    /// Draws quads into the G-buffer.
+    deferred_surface_pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    /// Grown as more views are needed, all at the render extent.
+    targets: Vec<ViewTarget>,
+    portals: Vec<Portal>,
+    max_depth: usize,
+    /// This frame's views, each after the one it's seen in.
+    views: Vec<PortalView>,
+}
+
+impl Portals {
+    pub fn new(
+        context: &VulkanContext,
+        deferred_subpass: Subpass,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                color: {
+                    format: VIEW_FORMAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                depth: {
+                    format: VIEW_DEPTH_FORMAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: DontCare,
+                },
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth},
+            },
+        )
+        .context("creating portal render pass")?;
+        let view_subpass =
+            Subpass::from(render_pass.clone(), 0).context("portal render pass has no subpass")?;
+
+        let vs = surface_vs::load(device.clone())
+            .context("loading portal surface vertex shader")?
+            .entry_point("main")
+            .context("portal surface vertex shader entry point not found")?;
+        let fs = surface_fs::load(device.clone())
+            .context("loading portal surface fragment shader")?
+            .entry_point("main")
+            .context("portal surface fragment shader entry point not found")?;
+        let deferred_fs = surface_deferred_fs::load(device.clone())
+            .context("loading deferred portal surface fragment shader")?
+            .entry_point("main")
+            .context("deferred portal surface fragment shader entry point not found")?;
+        validate_descriptor_bindings("Portals", &[&fs], &[VIEW_BINDING])?;
+        validate_descriptor_bindings("Portals deferred", &[&deferred_fs], &[VIEW_BINDING])?;
+
+        let create_pipeline =
+            |fs: EntryPoint, subpass: Subpass| -> anyhow::Result<Arc<GraphicsPipeline>> {
+                let stages = [
+                    PipelineShaderStageCreateInfo::new(vs.clone()),
+                    PipelineShaderStageCreateInfo::new(fs),
+                ];
+                let layout = PipelineLayout::new(
+                    device.clone(),
+                    PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                        .into_pipeline_layout_create_info(device.clone())
+                        .context("creating pipeline layout create info")?,
+                )
+                .context("creating pipeline layout")?;
+                GraphicsPipeline::new(
+                    device.clone(),
+                    None,
+                    GraphicsPipelineCreateInfo {
+                        stages: stages.into_iter().collect(),
+                        vertex_input_state: Some(VertexInputState::default()),
+                        input_assembly_state: Some(InputAssemblyState::default()),
+                        viewport_state: Some(ViewportState::default()),
+                        rasterization_state: Some(RasterizationState::default()),
+                        // The quads go through the depth pre-pass's depth like the geometry
+                        depth_stencil_state: Some(DepthStencilState {
+                            depth: Some(DepthState {
+                                compare_op: CompareOp::LessOrEqual,
+                                ..DepthState::simple()
+                            }),
+                            ..Default::default()
+                        }),
+                        multisample_state: Some(MultisampleState::default()),
+                        color_blend_state: Some(ColorBlendState::with_attachment_states(
+                            subpass.num_color_attachments(),
+                            ColorBlendAttachmentState::default(),
+                        )),
+                        dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                        subpass: Some(subpass.into()),
+                        ..GraphicsPipelineCreateInfo::layout(layout)
+                    },
+                )
+                .context("creating portal surface pipeline")
+            };
+        let surface_pipeline = create_pipeline(fs, view_subpass)?;
+        let deferred_surface_pipeline = create_pipeline(deferred_fs, deferred_subpass.clone())?;
+
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .context("creating portal sampler")?;
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(render_pass.as_ref(), "portal render pass");
+        debug_namer.name(surface_pipeline.as_ref(), "portal surface pipeline");
+        debug_namer.name(
+            deferred_surface_pipeline.as_ref(),
+            "deferred portal surface pipeline",
+        );
+
+        Ok(Portals {
+            gfx_queue: context.graphics_queue().clone(),
+            memory_allocator: context.memory_allocator().clone(),
+            descriptor_set_cache,
+            memory_tracker: context.memory_tracker().clone(),
+            debug_namer,
+            render_pass,
+            deferred_subpass,
+            surface_pipeline,
+            deferred_surface_pipeline,
+            sampler,
+            targets: vec![],
+            portals: vec![],
+            max_depth: 1,
+            views: vec![],
+        })
+    }
+
+    /// The subpass the scene is drawn in for each view.
+    pub fn view_subpass(&self) -> Subpass {
+        Subpass::from(self.render_pass.clone(), 0).expect("portal render pass has a subpass")
+    }
+
+    pub fn set_portals(&mut self, portals: impl IntoIterator<Item = Portal>) {
+        self.portals = portals.into_iter().collect();
+    }
+
+    /// How many portals deep views are rendered, 1 to only look through the portals the camera
+    /// sees. Defaults to 1.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Works out this frame's views through the portals the camera sees, and through the
+    /// portals those views see, breadth first until `max_depth` or `MAX_PORTAL_VIEWS`. Views
+    /// aren't jittered by TAA, `camera` shouldn't be either.
+    pub(crate) fn plan(
+        &mut self,
+        camera: (Matrix4<f32>, Matrix4<f32>),
+    ) -> anyhow::Result<&[PortalView]> {
+        self.views.clear();
+        // Every view clips the camera's own projection, rather than stacking obliques
+        let (camera_projection, view) = camera;
+
+        let mut parents = vec![(None, camera_projection, view)];
+        for _ in 0..self.max_depth {
+            let mut children = vec![];
+            for (parent, projection, view) in parents {
+                let position = camera_position(view)?;
+                let frustum = Frustum::from_view_projection(projection * view);
+                for (index, portal) in self.portals.iter().enumerate() {
+                    if !portal.is_visible(position, &frustum) {
+                        continue;
+                    }
+                    if self.views.len() == MAX_PORTAL_VIEWS {
+                        log::debug!(
+                            "more than {} portal views, drawing the rest black",
+                            MAX_PORTAL_VIEWS
+                        );
+                        return Ok(&self.views);
+                    }
+                    let virtual_view = portal.virtual_view(view)?;
+                    let virtual_projection =
+                        portal.oblique_projection(camera_projection, virtual_view)?;
+                    children.push((Some(self.views.len()), virtual_projection, virtual_view));
+                    self.views.push(PortalView {
+                        portal: index,
+                        parent,
+                        projection: virtual_projection,
+                        view: virtual_view,
+                    });
+                }
+            }
+            parents = children;
+        }
+
+        Ok(&self.views)
+    }
+
+    /// Renders this frame's views, `scenes` holding each one's geometry drawn in `view_subpass`,
+    /// into targets of `extent`. The deepest views go first, so the portals seen in a view can
+    /// sample theirs. `None` when no portal is in view.
+    pub(crate) fn record(
+        &mut self,
+        frame: &InFlightFrame,
+        extent: [u32; 3],
+        scenes: Vec<Arc<CommandBuffer>>,
+        clear_color: [f32; 3],
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        if scenes.is_empty() {
+            return Ok(None);
+        }
+        if self
+            .targets
+            .first()
+            .is_some_and(|target| target.color.image().extent() != extent)
+        {
+            self.targets.clear();
+        }
+        while self.targets.len() < scenes.len() {
+            let target = self.create_target(extent)?;
+            self.targets.push(target);
+        }
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating portal command buffer")?;
+
+        let [r, g, b] = clear_color;
+        for (index, scene) in scenes.into_iter().enumerate().rev() {
+            let view = self.views[index];
+            let surfaces = self.draw_surfaces(
+                [extent[0], extent[1]],
+                frame,
+                Some(index),
+                (view.projection, view.view),
+                self.view_subpass(),
+                &self.surface_pipeline,
+            )?;
+            builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: vec![Some([r, g, b, 1.0].into()), Some(1.0f32.into())],
+                        ..RenderPassBeginInfo::framebuffer(self.targets[index].framebuffer.clone())
+                    },
+                    SubpassBeginInfo {
+                        contents: SubpassContents::SecondaryCommandBuffers,
+                        ..Default::default()
+                    },
+                )
+                .context("beginning portal render pass")?
+                .execute_commands(scene)
+                .context("executing portal view")?;
+            if let Some(surfaces) = surfaces {
+                builder
+                    .execute_commands(surfaces)
+                    .context("executing portal surfaces")?;
+            }
+            builder
+                .end_render_pass(Default::default())
+                .context("ending portal render pass")?;
+        }
+
+        Ok(Some(
+            builder.end().context("building portal command buffer")?,
+        ))
+    }
+
+    /// Builds a secondary command buffer that draws the portals `camera` sees into the G-buffer,
+    /// or `None` without portals. `camera` is the geometry's, jitter and all, so the quads line
+    /// up with it.
+    pub fn draw(
+        &self,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+        camera: (Matrix4<f32>, Matrix4<f32>),
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        self.draw_surfaces(
+            viewport_dimensions,
+            frame,
+            None,
+            camera,
+            self.deferred_subpass.clone(),
+            &self.deferred_surface_pipeline,
+        )
+    }
+
+    /// Draws the quads of the portals facing `camera`, the camera of the view at `parent` or the
+    /// main camera, each sampling the view rendered through it from there.
+    fn draw_surfaces(
+        &self,
+        viewport_dimensions: [u32; 2],
+        frame: &InFlightFrame,
+        parent: Option<usize>,
+        camera: (Matrix4<f32>, Matrix4<f32>),
+        subpass: Subpass,
+        pipeline: &Arc<GraphicsPipeline>,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        if self.portals.is_empty() {
+            return Ok(None);
+        }
+        let (projection, view) = camera;
+        let view_projection = projection * view;
+        let position = camera_position(view)?;
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Secondary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::MultipleSubmit,
+                inheritance_info: Some(CommandBufferInheritanceInfo {
+                    render_pass: Some(subpass.into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )?;
+        builder
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .context("setting portal viewport")?
+            .bind_pipeline_graphics(pipeline.clone())
+            .context("binding portal surface pipeline")?;
+
+        for (index, portal) in self.portals.iter().enumerate() {
+            if !portal.faces(position) {
+                continue;
+            }
+            let view = self
+                .views
+                .iter()
+                .position(|view| view.portal == index && view.parent == parent);
+            // Portals past the last view still need a target bound, they're drawn black. Before
+            // any view was rendered there's nothing to bind, and nothing but black to miss
+            let Some(target) = self.targets.get(view.unwrap_or(0)) else {
+                continue;
+            };
+            let descriptor_set = self.descriptor_set_cache.get_or_create(
+                &pipeline.layout().set_layouts()[VIEW_BINDING.set as usize],
+                &[CachedWrite::ImageViewSampler(
+                    VIEW_BINDING.binding,
+                    target.color.clone(),
+                    self.sampler.clone(),
+                )],
+            )?;
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    pipeline.layout().clone(),
+                    VIEW_BINDING.set,
+                    descriptor_set,
+                )
+                .context("binding portal view")?
+                .push_constants(
+                    pipeline.layout().clone(),
+                    0,
+                    surface_vs::Surface {
+                        model_view_projection: (view_projection * portal.model()).into(),
+                        viewport: [viewport_dimensions[0] as f32, viewport_dimensions[1] as f32],
+                        has_view: view.is_some() as u32,
+                    },
+                )
+                .context("pushing portal surface constants")?;
+            unsafe { builder.draw(6, 1, 0, 0) }.context("drawing portal surface")?;
+        }
+
+        Ok(Some(builder.end().context("building portal surfaces")?))
+    }
+
+    fn create_target(&self, extent: [u32; 3]) -> anyhow::Result<ViewTarget> {
+        let create_image = |format, usage| {
+            Image::new(
+                self.memory_allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format,
+                    extent,
+                    usage,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .context("creating portal view target")
+        };
+        let color = create_image(
+            VIEW_FORMAT,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+        )?;
+        let depth = create_image(VIEW_DEPTH_FORMAT, ImageUsage::DEPTH_STENCIL_ATTACHMENT)?;
+        self.debug_namer.name(color.as_ref(), "portal view");
+        self.debug_namer.name(depth.as_ref(), "portal view depth");
+        let memory = [
+            self.memory_tracker
+                .track_image(MemoryCategory::RenderTargets, &color),
+            self.memory_tracker
+                .track_image(MemoryCategory::RenderTargets, &depth),
+        ];
+
+        let color = ImageView::new_default(color).context("creating portal view")?;
+        let depth = ImageView::new_default(depth).context("creating portal view depth")?;
+        let framebuffer = Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![color.clone(), depth],
+                ..Default::default()
+            },
+        )
+        .context("creating portal framebuffer")?;
+
+        Ok(ViewTarget {
+            color,
+            framebuffer,
+            _memory: memory,
+        })
+    }
+}
+
+mod surface_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/portal/surface.vert"
+    }
+}
+
+mod surface_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/portal/surface.frag"
+    }
+}
+
+mod surface_deferred_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/portal/surface_deferred.frag"
+    }
+}
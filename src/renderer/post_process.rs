@@ -0,0 +1,536 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        RecordingCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents,
+    },
+    descriptor_set::layout::DescriptorType,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo,
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+        PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    lighting::LightingVertex,
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+/// Number of bloom levels, the first at half resolution.
+const BLOOM_LEVELS: usize = 5;
+/// Brightness above which light blooms. Lit surfaces rarely exceed it, emissive ones can.
+const BLOOM_THRESHOLD: f32 = 1.0;
+const BLOOM_INTENSITY: f32 = 0.5;
+
+const SOURCE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const OUTPUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::StorageImage);
+const HDR_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const BLOOM_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::CombinedImageSampler);
+
+/// How the scene is scaled to the output when it's rendered at a lower resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UpscaleFilter {
+    #[default]
+    Bilinear,
+    /// Bilinear followed by contrast adaptive sharpening, strength in `0.0..=1.0`, to recover
+    /// some of the detail lost to the lower resolution.
+    Sharpen(f32),
+}
+
+/// Blooms the HDR target and composites it into the output image, after the deferred render
+/// pass has finished.
+pub struct PostProcess {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    debug_namer: DebugNamer,
+    render_pass: Arc<RenderPass>,
+    composite_pipeline: Arc<GraphicsPipeline>,
+    downsample_pipeline: Arc<ComputePipeline>,
+    upsample_pipeline: Arc<ComputePipeline>,
+    vertex_buffer: Subbuffer<[LightingVertex]>,
+    sampler: Arc<Sampler>,
+    /// Halving in size, recreated when the HDR target is.
+    bloom_levels: Vec<Arc<ImageView>>,
+    memory_tracker: MemoryTracker,
+    bloom_memory: Vec<TrackedMemory>,
+    bloom: bool,
+    upscale_filter: UpscaleFilter,
+    transparent: bool,
+}
+
+impl PostProcess {
+    pub fn new(
+        context: &VulkanContext,
+        image_format: Format,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+        let memory_allocator = context.memory_allocator().clone();
+
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                final_color: {
+                    format: image_format,
+                    samples: 1,
+                    load_op: DontCare,
+                    store_op: Store,
+                },
+            },
+            pass: {
+                color: [final_color],
+                depth_stencil: {},
+            },
+        )
+        .context("creating composite render pass")?;
+
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .context("creating post process sampler")?;
+
+        let vertex_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            [[-1.0, -1.0], [-1.0, 3.0], [3.0, -1.0]].map(|position| LightingVertex { position }),
+        )
+        .context("creating composite vertex buffer")?;
+
+        let downsample_cs = bloom_downsample_cs::load(device.clone())
+            .context("loading bloom downsample shader")?
+            .entry_point("main")
+            .context("bloom downsample shader entry point not found")?;
+        let upsample_cs = bloom_upsample_cs::load(device.clone())
+            .context("loading bloom upsample shader")?
+            .entry_point("main")
+            .context("bloom upsample shader entry point not found")?;
+        validate_descriptor_bindings(
+            "PostProcess bloom",
+            &[&downsample_cs, &upsample_cs],
+            &[SOURCE_BINDING, OUTPUT_BINDING],
+        )?;
+
+        let compute_pipeline = |stage: PipelineShaderStageCreateInfo| {
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("creating pipeline layout create info")?,
+            )
+            .context("creating pipeline layout")?;
+            ComputePipeline::new(
+                device.clone(),
+                None,
+                ComputePipelineCreateInfo::stage_layout(stage, layout),
+            )
+            .context("creating bloom pipeline")
+        };
+        let downsample_pipeline =
+            compute_pipeline(PipelineShaderStageCreateInfo::new(downsample_cs))?;
+        let upsample_pipeline = compute_pipeline(PipelineShaderStageCreateInfo::new(upsample_cs))?;
+
+        let composite_pipeline = {
+            let vs = composite_vs::load(device.clone())
+                .context("loading composite vertex shader")?
+                .entry_point("main")
+                .context("composite vertex shader entry point not found")?;
+            let fs = composite_fs::load(device.clone())
+                .context("loading composite fragment shader")?
+                .entry_point("main")
+                .context("composite fragment shader entry point not found")?;
+            validate_descriptor_bindings(
+                "PostProcess composite",
+                &[&fs],
+                &[HDR_BINDING, BLOOM_BINDING],
+            )?;
+
+            let vertex_input_state = LightingVertex::per_vertex()
+                .definition(&vs.info().input_interface)
+                .context("vertex input state")?;
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("creating pipeline layout create info")?,
+            )
+            .context("creating pipeline layout")?;
+            let subpass = Subpass::from(render_pass.clone(), 0).context("getting subpass")?;
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(vertex_input_state),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        subpass.num_color_attachments(),
+                        ColorBlendAttachmentState::default(),
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(subpass.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .context("creating composite pipeline")?
+        };
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(render_pass.as_ref(), "composite render pass");
+        debug_namer.name(composite_pipeline.as_ref(), "composite pipeline");
+        debug_namer.name(downsample_pipeline.as_ref(), "bloom downsample pipeline");
+        debug_namer.name(upsample_pipeline.as_ref(), "bloom upsample pipeline");
+
+        let mut post_process = PostProcess {
+            memory_allocator,
+            descriptor_set_cache,
+            debug_namer,
+            render_pass,
+            composite_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            vertex_buffer,
+            sampler,
+            bloom_levels: vec![],
+            memory_tracker: context.memory_tracker().clone(),
+            bloom_memory: vec![],
+            bloom: true,
+            upscale_filter: UpscaleFilter::default(),
+            transparent: false,
+        };
+        post_process.resize([1, 1, 1])?;
+
+        Ok(post_process)
+    }
+
+    pub fn set_bloom(&mut self, enabled: bool) {
+        self.bloom = enabled;
+    }
+
+    pub fn set_upscale_filter(&mut self, filter: UpscaleFilter) {
+        self.upscale_filter = filter;
+    }
+
+    /// Writes the scene's coverage to the output's alpha instead of making it opaque, for a
+    /// window composited with what's behind it. The HDR target's alpha is the coverage, which
+    /// the passes over it leave as the lighting wrote it.
+    pub fn set_transparent(&mut self, transparent: bool) {
+        self.transparent = transparent;
+    }
+
+    /// Recreates the bloom levels for an HDR target of `extent`.
+    pub fn resize(&mut self, extent: [u32; 3]) -> anyhow::Result<()> {
+        self.bloom_levels = (0..BLOOM_LEVELS)
+            .map(|level| {
+                let image = Image::new(
+                    self.memory_allocator.clone(),
+                    ImageCreateInfo {
+                        image_type: ImageType::Dim2d,
+                        format: Format::R16G16B16A16_SFLOAT,
+                        extent: [
+                            (extent[0] >> (level + 1)).max(1),
+                            (extent[1] >> (level + 1)).max(1),
+                            1,
+                        ],
+                        usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo::default(),
+                )
+                .context("creating bloom level")?;
+                self.debug_namer
+                    .name(image.as_ref(), &format!("bloom level {}", level));
+                ImageView::new_default(image).context("creating bloom level view")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        self.bloom_memory = self
+            .bloom_levels
+            .iter()
+            .map(|view| {
+                self.memory_tracker
+                    .track_image(MemoryCategory::RenderTargets, view.image())
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// A framebuffer for compositing into `final_image_view`.
+    pub fn framebuffer(
+        &self,
+        final_image_view: Arc<ImageView>,
+    ) -> anyhow::Result<Arc<Framebuffer>> {
+        Framebuffer::new(
+            self.render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![final_image_view],
+                ..Default::default()
+            },
+        )
+        .context("creating composite framebuffer")
+    }
+
+    /// The composite subpass, for pipelines drawing over the final image.
+    pub fn subpass(&self) -> Subpass {
+        Subpass::from(self.render_pass.clone(), 0).expect("composite render pass has a subpass")
+    }
+
+    /// Records the bloom passes over `hdr`, then composites both into `framebuffer`. `overlay`
+    /// is recorded after the composite, inside its render pass. Must be recorded outside of a
+    /// render pass.
+    pub fn record(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        hdr: &Arc<ImageView>,
+        framebuffer: Arc<Framebuffer>,
+        overlay: impl FnOnce(&mut RecordingCommandBuffer) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        // The bloom levels hold nothing meaningful while bloom is disabled, so the HDR target
+        // stands in for them
+        let bloom = if self.bloom {
+            self.record_bloom(builder, hdr)?;
+            &self.bloom_levels[0]
+        } else {
+            hdr
+        };
+
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &self.composite_pipeline.layout().set_layouts()[0],
+            &[
+                CachedWrite::ImageViewSampler(
+                    HDR_BINDING.binding,
+                    hdr.clone(),
+                    self.sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    BLOOM_BINDING.binding,
+                    bloom.clone(),
+                    self.sampler.clone(),
+                ),
+            ],
+        )?;
+
+        let extent = framebuffer.extent();
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![None],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer)
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .context("beginning composite render pass")?
+            .set_viewport(
+                0,
+                [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [extent[0] as f32, extent[1] as f32],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .context("setting composite viewport")?
+            .bind_pipeline_graphics(self.composite_pipeline.clone())
+            .context("binding composite pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.composite_pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding composite descriptor set")?
+            .push_constants(
+                self.composite_pipeline.layout().clone(),
+                0,
+                composite_fs::PushConstants {
+                    bloom_intensity: if self.bloom { BLOOM_INTENSITY } else { 0.0 },
+                    // Sharpening a full resolution scene would only add artifacts
+                    sharpness: match self.upscale_filter {
+                        UpscaleFilter::Sharpen(sharpness)
+                            if hdr.image().extent()[..2] != extent[..] =>
+                        {
+                            sharpness.clamp(0.0, 1.0)
+                        }
+                        _ => 0.0,
+                    },
+                    transparent: self.transparent as u32,
+                },
+            )
+            .context("pushing composite constants")?
+            .bind_vertex_buffers(0, self.vertex_buffer.clone())
+            .context("binding composite vertex buffer")?;
+        unsafe { builder.draw(self.vertex_buffer.len() as u32, 1, 0, 0) }
+            .context("drawing composite")?;
+        overlay(builder)?;
+        builder
+            .end_render_pass(Default::default())
+            .context("ending composite render pass")?;
+
+        Ok(())
+    }
+
+    /// Thresholds `hdr` into the first level and downsamples it through the rest, then
+    /// upsamples back, adding each level into the one above it.
+    fn record_bloom(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        hdr: &Arc<ImageView>,
+    ) -> anyhow::Result<()> {
+        builder
+            .bind_pipeline_compute(self.downsample_pipeline.clone())
+            .context("binding bloom downsample pipeline")?;
+        for (level, output) in self.bloom_levels.iter().enumerate() {
+            let source = if level == 0 {
+                hdr
+            } else {
+                &self.bloom_levels[level - 1]
+            };
+            self.dispatch_bloom(
+                builder,
+                &self.downsample_pipeline,
+                source,
+                output,
+                Some(bloom_downsample_cs::PushConstants {
+                    threshold: BLOOM_THRESHOLD,
+                    prefilter: (level == 0) as u32,
+                }),
+            )?;
+        }
+
+        builder
+            .bind_pipeline_compute(self.upsample_pipeline.clone())
+            .context("binding bloom upsample pipeline")?;
+        for level in (0..self.bloom_levels.len() - 1).rev() {
+            self.dispatch_bloom(
+                builder,
+                &self.upsample_pipeline,
+                &self.bloom_levels[level + 1],
+                &self.bloom_levels[level],
+                None,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn dispatch_bloom(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        pipeline: &Arc<ComputePipeline>,
+        source: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+        push_constants: Option<bloom_downsample_cs::PushConstants>,
+    ) -> anyhow::Result<()> {
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &pipeline.layout().set_layouts()[0],
+            &[
+                CachedWrite::ImageViewSampler(
+                    SOURCE_BINDING.binding,
+                    source.clone(),
+                    self.sampler.clone(),
+                ),
+                CachedWrite::ImageView(OUTPUT_BINDING.binding, output.clone()),
+            ],
+        )?;
+
+        builder
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding bloom descriptor set")?;
+        if let Some(push_constants) = push_constants {
+            builder
+                .push_constants(pipeline.layout().clone(), 0, push_constants)
+                .context("pushing bloom constants")?;
+        }
+
+        let extent = output.image().extent();
+        unsafe { builder.dispatch([extent[0].div_ceil(8), extent[1].div_ceil(8), 1]) }
+            .context("dispatching bloom")?;
+
+        Ok(())
+    }
+}
+
+mod bloom_downsample_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/post/bloom_downsample.comp"
+    }
+}
+
+mod bloom_upsample_cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/post/bloom_upsample.comp"
+    }
+}
+
+mod composite_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/post/composite.vert"
+    }
+}
+
+mod composite_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/post/composite.frag"
+    }
+}
@@ -0,0 +1,356 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use anyhow::Context;
+use cgmath::{perspective, Deg, EuclideanSpace, Matrix4, Point3, Vector3};
+use vulkano::{
+    command_buffer::{
+        BlitImageInfo, CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel,
+        CommandBufferUsage, ImageBlit, RecordingCommandBuffer, RenderPassBeginInfo,
+        SubpassBeginInfo, SubpassContents,
+    },
+    device::Queue,
+    format::Format,
+    image::{
+        sampler::Filter,
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        Image, ImageAspects, ImageCreateFlags, ImageCreateInfo, ImageSubresourceLayers,
+        ImageSubresourceRange, ImageType, ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    render_pass::{Framebuffer, FramebufferCreateInfo, Subpass},
+};
+
+use super::{
+    frames_in_flight::InFlightFrame,
+    ibl::EnvironmentBaker,
+    memory::{MemoryCategory, TrackedMemory},
+    vulkan_context::VulkanContext,
+};
+
+/// How many reflection probes fit in the atlas, the rest are ignored.
+pub const MAX_REFLECTION_PROBES: usize = 16;
+pub(crate) const CUBE_FACES: usize = 6;
+const CAPTURE_SIZE: u32 = 128;
+const CAPTURE_LEVELS: u32 = CAPTURE_SIZE.ilog2() + 1;
+const CAPTURE_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+const CAPTURE_DEPTH_FORMAT: Format = Format::D16_UNORM;
+const CAPTURE_NEAR: f32 = 0.05;
+/// How far past its box a probe's captures reach, so walls right on the box aren't clipped.
+const CAPTURE_MARGIN: f32 = 0.1;
+
+/// Each cube face's direction and up, in the layer order Vulkan uses for cube images. The ups
+/// point down the faces' images, which the projection doesn't flip.
+const FACE_DIRECTIONS: [([f32; 3], [f32; 3]); CUBE_FACES] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+/// A box whose surroundings are captured into a cubemap from its center, which the objects in
+/// it reflect in place of the environment, e.g. to keep the sky out of a room's reflections.
+/// Each face is captured out to the side of the box it looks at, so fitting the box to a room
+/// keeps the rooms next door out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReflectionProbe {
+    pub position: Vector3<f32>,
+    /// Half the box's size along each axis.
+    pub half_extents: Vector3<f32>,
+}
+
+impl ReflectionProbe {
+    pub fn new(position: Vector3<f32>, half_extents: Vector3<f32>) -> Self {
+        ReflectionProbe {
+            position,
+            half_extents,
+        }
+    }
+
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        let offset = point - self.position;
+        offset.x.abs() <= self.half_extents.x
+            && offset.y.abs() <= self.half_extents.y
+            && offset.z.abs() <= self.half_extents.z
+    }
+
+    fn volume(&self) -> f32 {
+        self.half_extents.x * self.half_extents.y * self.half_extents.z
+    }
+
+    /// The projection and view each face is captured with, the far plane on the side of the box
+    /// the face looks at.
+    fn faces(&self) -> [(Matrix4<f32>, Matrix4<f32>); CUBE_FACES] {
+        let eye = Point3::from_vec(self.position);
+        FACE_DIRECTIONS.map(|(direction, up)| {
+            let direction = Vector3::from(direction);
+            let extent = Vector3::new(
+                self.half_extents.x * direction.x,
+                self.half_extents.y * direction.y,
+                self.half_extents.z * direction.z,
+            );
+            let far = (extent.x + extent.y + extent.z).abs() + CAPTURE_MARGIN;
+            (
+                perspective(Deg(90.0), 1.0, CAPTURE_NEAR, far.max(CAPTURE_NEAR * 2.0)),
+                Matrix4::look_to_rh(eye, direction, Vector3::from(up)),
+            )
+        })
+    }
+}
+
+/// The probe an object at `position` reflects: the smallest whose box holds it, if any.
+pub(crate) fn select_probe(probes: &[ReflectionProbe], position: Vector3<f32>) -> Option<usize> {
+    probes
+        .iter()
+        .enumerate()
+        .filter(|(_, probe)| probe.contains(position))
+        .min_by(|(_, a), (_, b)| a.volume().total_cmp(&b.volume()))
+        .map(|(index, _)| index)
+}
+
+/// A probe to capture this frame, with the camera for each face.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ProbeCapture {
+    pub probe: usize,
+    pub faces: [(Matrix4<f32>, Matrix4<f32>); CUBE_FACES],
+}
+
+/// Captures reflection probes into the environment baker's probe atlas, one probe a frame.
+/// Probes are captured when they're added or moved, or when asked to.
+///
+/// Captures are rendered like the views through portals: forward shaded with the geometry's
+/// meshes, the ambient light and the directional light, without its shadows. Anything past a
+/// probe's box is left the ambient light's color.
+pub struct ReflectionProbes {
+    gfx_queue: Arc<Queue>,
+    /// A cube with a full mip chain, to prefilter from.
+    capture: Arc<ImageView>,
+    framebuffers: Vec<Arc<Framebuffer>>,
+    _memory: [TrackedMemory; 2],
+    probes: Vec<ReflectionProbe>,
+    /// Probes waiting to be captured, by index.
+    pending: VecDeque<usize>,
+}
+
+impl ReflectionProbes {
+    /// Creates the capture targets for `view_subpass`, the subpass portal views are drawn in.
+    pub fn new(context: &VulkanContext, view_subpass: Subpass) -> anyhow::Result<Self> {
+        let memory_allocator = context.memory_allocator();
+        let debug_namer = context.debug_namer();
+        let memory_tracker = context.memory_tracker();
+
+        let capture = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                flags: ImageCreateFlags::CUBE_COMPATIBLE,
+                image_type: ImageType::Dim2d,
+                format: CAPTURE_FORMAT,
+                extent: [CAPTURE_SIZE, CAPTURE_SIZE, 1],
+                array_layers: CUBE_FACES as u32,
+                mip_levels: CAPTURE_LEVELS,
+                usage: ImageUsage::COLOR_ATTACHMENT
+                    | ImageUsage::SAMPLED
+                    | ImageUsage::TRANSFER_SRC
+                    | ImageUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating reflection probe capture")?;
+        let depth = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: CAPTURE_DEPTH_FORMAT,
+                extent: [CAPTURE_SIZE, CAPTURE_SIZE, 1],
+                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating reflection probe capture depth")?;
+        debug_namer.name(capture.as_ref(), "reflection probe capture");
+        debug_namer.name(depth.as_ref(), "reflection probe capture depth");
+        let memory = [
+            memory_tracker.track_image(MemoryCategory::RenderTargets, &capture),
+            memory_tracker.track_image(MemoryCategory::RenderTargets, &depth),
+        ];
+
+        let depth = ImageView::new_default(depth).context("creating capture depth view")?;
+        let framebuffers = (0..CUBE_FACES as u32)
+            .map(|face| {
+                let color = ImageView::new(
+                    capture.clone(),
+                    ImageViewCreateInfo {
+                        view_type: ImageViewType::Dim2d,
+                        subresource_range: ImageSubresourceRange {
+                            aspects: ImageAspects::COLOR,
+                            mip_levels: 0..1,
+                            array_layers: face..face + 1,
+                        },
+                        ..ImageViewCreateInfo::from_image(&capture)
+                    },
+                )
+                .context("creating capture face view")?;
+                Framebuffer::new(
+                    view_subpass.render_pass().clone(),
+                    FramebufferCreateInfo {
+                        attachments: vec![color, depth.clone()],
+                        ..Default::default()
+                    },
+                )
+                .context("creating capture framebuffer")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let capture = ImageView::new(
+            capture.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Cube,
+                ..ImageViewCreateInfo::from_image(&capture)
+            },
+        )
+        .context("creating capture cube view")?;
+
+        Ok(ReflectionProbes {
+            gfx_queue: context.graphics_queue().clone(),
+            capture,
+            framebuffers,
+            _memory: memory,
+            probes: vec![],
+            pending: VecDeque::new(),
+        })
+    }
+
+    pub fn probes(&self) -> &[ReflectionProbe] {
+        &self.probes
+    }
+
+    /// Replaces the probes, queueing the new and changed ones to be captured. Only the first
+    /// `MAX_REFLECTION_PROBES` are kept.
+    pub fn set_probes(&mut self, probes: impl IntoIterator<Item = ReflectionProbe>) {
+        let mut probes = probes.into_iter().collect::<Vec<_>>();
+        if probes.len() > MAX_REFLECTION_PROBES {
+            log::warn!(
+                "ignoring {} reflection probes past the first {}",
+                probes.len() - MAX_REFLECTION_PROBES,
+                MAX_REFLECTION_PROBES
+            );
+            probes.truncate(MAX_REFLECTION_PROBES);
+        }
+        for (index, probe) in probes.iter().enumerate() {
+            if self.probes.get(index) != Some(probe) {
+                self.recapture(index);
+            }
+        }
+        self.probes = probes;
+    }
+
+    /// Queues the probe at `index` to be captured again, e.g. after what's around it changed.
+    pub fn recapture(&mut self, index: usize) {
+        if !self.pending.contains(&index) {
+            self.pending.push_back(index);
+        }
+    }
+
+    pub fn recapture_all(&mut self) {
+        for index in 0..self.probes.len() {
+            self.recapture(index);
+        }
+    }
+
+    /// The next probe to capture, if any are waiting.
+    pub(crate) fn plan(&mut self) -> Option<ProbeCapture> {
+        while let Some(index) = self.pending.pop_front() {
+            if let Some(probe) = self.probes.get(index) {
+                return Some(ProbeCapture {
+                    probe: index,
+                    faces: probe.faces(),
+                });
+            }
+        }
+        None
+    }
+
+    /// The extent each face's scene is drawn at.
+    pub(crate) fn face_extent(&self) -> [u32; 2] {
+        [CAPTURE_SIZE, CAPTURE_SIZE]
+    }
+
+    /// Renders `capture`'s faces, `scenes` holding each one's geometry drawn in the view subpass,
+    /// and prefilters them into the probe's slot of `baker`'s atlas.
+    pub(crate) fn record(
+        &self,
+        frame: &InFlightFrame,
+        capture: &ProbeCapture,
+        scenes: Vec<Arc<CommandBuffer>>,
+        clear_color: [f32; 3],
+        baker: &EnvironmentBaker,
+    ) -> anyhow::Result<Arc<CommandBuffer>> {
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating reflection probe command buffer")?;
+
+        let [r, g, b] = clear_color;
+        for (framebuffer, scene) in self.framebuffers.iter().zip(scenes) {
+            builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: vec![Some([r, g, b, 1.0].into()), Some(1.0f32.into())],
+                        ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+                    },
+                    SubpassBeginInfo {
+                        contents: SubpassContents::SecondaryCommandBuffers,
+                        ..Default::default()
+                    },
+                )
+                .context("beginning reflection probe render pass")?
+                .execute_commands(scene)
+                .context("executing reflection probe face")?
+                .end_render_pass(Default::default())
+                .context("ending reflection probe render pass")?;
+        }
+
+        // The prefilter samples lower levels for rougher reflections, each blitted down from the
+        // one before it
+        let image = self.capture.image();
+        for level in 1..CAPTURE_LEVELS {
+            let src = (CAPTURE_SIZE >> (level - 1)).max(1);
+            let dst = (CAPTURE_SIZE >> level).max(1);
+            builder
+                .blit_image(BlitImageInfo {
+                    regions: [ImageBlit {
+                        src_subresource: ImageSubresourceLayers {
+                            mip_level: level - 1,
+                            ..image.subresource_layers()
+                        },
+                        src_offsets: [[0; 3], [src, src, 1]],
+                        dst_subresource: ImageSubresourceLayers {
+                            mip_level: level,
+                            ..image.subresource_layers()
+                        },
+                        dst_offsets: [[0; 3], [dst, dst, 1]],
+                        ..Default::default()
+                    }]
+                    .into(),
+                    filter: Filter::Linear,
+                    ..BlitImageInfo::images(image.clone(), image.clone())
+                })
+                .context("generating reflection probe mip")?;
+        }
+
+        baker.record_probe(&mut builder, &self.capture, capture.probe)?;
+
+        builder
+            .end()
+            .context("building reflection probe command buffer")
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll, Waker},
+};
+
+use anyhow::{anyhow, ensure, Context};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        CopyBufferInfo, CopyImageToBufferInfo, RecordingCommandBuffer,
+    },
+    device::Queue,
+    format::Format,
+    image::{Image, ImageAspects, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    DeviceSize,
+};
+
+use super::{frames_in_flight::InFlightFrame, vulkan_context::VulkanContext};
+
+/// Bytes copied back from the GPU.
+#[derive(Debug, Clone)]
+pub struct ReadbackData {
+    pub bytes: Vec<u8>,
+    /// The image's extent, or the buffer's size in bytes by 1 by 1.
+    pub extent: [u32; 3],
+    /// `None` for buffers.
+    pub format: Option<Format>,
+}
+
+impl ReadbackData {
+    /// The bytes as elements of `T`, e.g. the `u32`s of a storage buffer.
+    pub fn cast<T: bytemuck::Pod>(&self) -> Vec<T> {
+        bytemuck::pod_collect_to_vec(&self.bytes)
+    }
+
+    /// An 8 bit RGBA or BGRA image as RGBA, e.g. to save a screenshot. `None` for anything else.
+    pub fn to_rgba8(&self) -> Option<image::RgbaImage> {
+        let mut bytes = self.bytes.clone();
+        match self.format? {
+            Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => {}
+            Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => {
+                bytes.chunks_exact_mut(4).for_each(|texel| texel.swap(0, 2));
+            }
+            _ => return None,
+        }
+        image::RgbaImage::from_raw(self.extent[0], self.extent[1], bytes)
+    }
+}
+
+#[derive(Default)]
+struct ReadbackState {
+    result: Option<anyhow::Result<ReadbackData>>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// Resolves once the GPU has finished the frame a readback was copied in and the data is on the
+/// host, a frame slot or so after it was asked for.
+///
+/// Await it from an executor, or check on it each frame with `try_take`. Either way the result is
+/// only handed out once.
+pub struct ReadbackFuture {
+    state: Arc<Mutex<ReadbackState>>,
+}
+
+impl ReadbackFuture {
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().unwrap().result.is_some()
+    }
+
+    /// The result, if it has arrived and hasn't been taken yet.
+    pub fn try_take(&mut self) -> Option<anyhow::Result<ReadbackData>> {
+        self.state.lock().unwrap().result.take()
+    }
+}
+
+impl Future for ReadbackFuture {
+    type Output = anyhow::Result<ReadbackData>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The renderer's end of a `ReadbackFuture`. Fails the future if dropped before completing it,
+/// e.g. when the renderer goes away with readbacks still in flight.
+struct Completer(Arc<Mutex<ReadbackState>>);
+
+impl Completer {
+    fn complete(&self, result: anyhow::Result<ReadbackData>) {
+        let mut state = self.0.lock().unwrap();
+        if state.done {
+            return;
+        }
+        state.done = true;
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Completer {
+    fn drop(&mut self) {
+        self.complete(Err(anyhow!("readback dropped before it completed")));
+    }
+}
+
+/// What a readback copies.
+enum ReadbackSource {
+    Buffer(Subbuffer<[u8]>),
+    /// Mip 0 of layer 0.
+    Image(Arc<Image>),
+    /// The frame's final color, once it's been rendered.
+    Frame,
+}
+
+struct ReadbackRequest {
+    source: ReadbackSource,
+    completer: Completer,
+}
+
+struct InFlightReadback {
+    staging: Subbuffer<[u8]>,
+    size: DeviceSize,
+    extent: [u32; 3],
+    format: Option<Format>,
+    completer: Completer,
+}
+
+#[derive(Default)]
+struct ReadbackSlot {
+    /// Staging buffers not in use, kept for the slot's next readbacks.
+    free: Vec<Subbuffer<[u8]>>,
+    in_flight: Vec<InFlightReadback>,
+}
+
+/// Copies device local buffers and images to host visible staging buffers after a frame, for
+/// screenshots, picking and debugging.
+///
+/// Each frame slot has staging buffers of its own, so a readback is copied into memory no other
+/// frame is using and read once the slot's fence has signaled, without waiting on the GPU.
+pub struct Readbacks {
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    requests: Vec<ReadbackRequest>,
+    slots: Vec<ReadbackSlot>,
+}
+
+impl Readbacks {
+    pub fn new(context: &VulkanContext, frames: usize) -> Self {
+        Readbacks {
+            queue: context.graphics_queue().clone(),
+            memory_allocator: context.memory_allocator().clone(),
+            requests: vec![],
+            slots: (0..frames).map(|_| ReadbackSlot::default()).collect(),
+        }
+    }
+
+    /// Copies `buffer` after the next frame. Needs `TRANSFER_SRC` usage.
+    pub fn read_buffer(&mut self, buffer: Subbuffer<[u8]>) -> ReadbackFuture {
+        self.request(ReadbackSource::Buffer(buffer))
+    }
+
+    /// Copies mip 0 of `image`'s first layer after the next frame. Needs `TRANSFER_SRC` usage
+    /// and an uncompressed format with a single aspect.
+    pub fn read_image(&mut self, image: Arc<Image>) -> ReadbackFuture {
+        self.request(ReadbackSource::Image(image))
+    }
+
+    /// Copies the next frame's final color, as it's presented.
+    pub fn read_frame(&mut self) -> ReadbackFuture {
+        self.request(ReadbackSource::Frame)
+    }
+
+    fn request(&mut self, source: ReadbackSource) -> ReadbackFuture {
+        let state = Arc::new(Mutex::new(ReadbackState::default()));
+        self.requests.push(ReadbackRequest {
+            source,
+            completer: Completer(state.clone()),
+        });
+        ReadbackFuture { state }
+    }
+
+    /// Completes the readbacks copied with `frame`'s slot. Call after the slot's fence has been
+    /// waited on.
+    pub fn collect(&mut self, frame: &InFlightFrame) {
+        let slot = &mut self.slots[frame.index];
+        for readback in slot.in_flight.drain(..) {
+            let result = readback
+                .staging
+                .clone()
+                .slice(0..readback.size)
+                .read()
+                .context("reading readback staging buffer")
+                .map(|bytes| ReadbackData {
+                    bytes: bytes.to_vec(),
+                    extent: readback.extent,
+                    format: readback.format,
+                });
+            readback.completer.complete(result);
+            slot.free.push(readback.staging);
+        }
+    }
+
+    /// Records the copies of the readbacks asked for since the last frame, to be executed after
+    /// the frame that rendered `frame_color`. A readback that can't be copied fails on its own
+    /// instead of failing the frame. Returns `None` when nothing was asked for.
+    pub fn record(
+        &mut self,
+        frame: &InFlightFrame,
+        frame_color: &Arc<Image>,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        if self.requests.is_empty() {
+            return Ok(None);
+        }
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating readback command buffer")?;
+
+        for request in std::mem::take(&mut self.requests) {
+            let source = match request.source {
+                ReadbackSource::Frame => ReadbackSource::Image(frame_color.clone()),
+                source => source,
+            };
+            match self.record_copy(frame.index, &mut builder, &source) {
+                Ok((staging, size, extent, format)) => {
+                    self.slots[frame.index].in_flight.push(InFlightReadback {
+                        staging,
+                        size,
+                        extent,
+                        format,
+                        completer: request.completer,
+                    });
+                }
+                Err(e) => request.completer.complete(Err(e)),
+            }
+        }
+
+        builder
+            .end()
+            .context("ending readback command buffer")
+            .map(Some)
+    }
+
+    fn record_copy(
+        &mut self,
+        frame_index: usize,
+        builder: &mut RecordingCommandBuffer,
+        source: &ReadbackSource,
+    ) -> anyhow::Result<(Subbuffer<[u8]>, DeviceSize, [u32; 3], Option<Format>)> {
+        match source {
+            ReadbackSource::Buffer(buffer) => {
+                ensure!(
+                    buffer
+                        .buffer()
+                        .usage()
+                        .intersects(BufferUsage::TRANSFER_SRC),
+                    "can't read back a buffer without TRANSFER_SRC usage"
+                );
+                let size = buffer.size();
+                let staging = self.staging(frame_index, size)?;
+                builder
+                    .copy_buffer(CopyBufferInfo::buffers(
+                        buffer.clone(),
+                        staging.clone().slice(0..size),
+                    ))
+                    .context("copying buffer to readback staging")?;
+                Ok((staging, size, [size as u32, 1, 1], None))
+            }
+            ReadbackSource::Image(image) => {
+                let format = image.format();
+                ensure!(
+                    image.usage().intersects(ImageUsage::TRANSFER_SRC),
+                    "can't read back an image without TRANSFER_SRC usage"
+                );
+                ensure!(
+                    format.block_extent() == [1, 1, 1]
+                        && !format
+                            .aspects()
+                            .contains(ImageAspects::DEPTH | ImageAspects::STENCIL),
+                    "can't read back {:?} images",
+                    format
+                );
+                let [width, height, depth] = image.extent();
+                let size = format.block_size()
+                    * width as DeviceSize
+                    * height as DeviceSize
+                    * depth as DeviceSize;
+                let staging = self.staging(frame_index, size)?;
+                builder
+                    .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                        image.clone(),
+                        staging.clone().slice(0..size),
+                    ))
+                    .context("copying image to readback staging")?;
+                Ok((staging, size, image.extent(), Some(format)))
+            }
+            ReadbackSource::Frame => unreachable!("frame readbacks are resolved to its image"),
+        }
+    }
+
+    /// The slot's smallest free staging buffer that fits `size` bytes, or a new one.
+    fn staging(&mut self, frame_index: usize, size: DeviceSize) -> anyhow::Result<Subbuffer<[u8]>> {
+        let free = &mut self.slots[frame_index].free;
+        let fitting = free
+            .iter()
+            .enumerate()
+            .filter(|(_, staging)| staging.size() >= size)
+            .min_by_key(|(_, staging)| staging.size())
+            .map(|(index, _)| index);
+        if let Some(index) = fitting {
+            return Ok(free.swap_remove(index));
+        }
+
+        let size = size.max(1).next_power_of_two();
+        log::debug!("creating {} byte readback staging buffer", size);
+        Buffer::new_slice::<u8>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            size,
+        )
+        .context("creating readback staging buffer")
+    }
+}
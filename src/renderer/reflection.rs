@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use vulkano::{descriptor_set::layout::DescriptorType, shader::EntryPoint};
+
+/// A descriptor a render system writes when it builds its descriptor sets.
+#[derive(Debug, Clone, Copy)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub ty: DescriptorType,
+}
+
+impl DescriptorBinding {
+    pub const fn new(set: u32, binding: u32, ty: DescriptorType) -> Self {
+        DescriptorBinding { set, binding, ty }
+    }
+}
+
+/// Cross-checks the bindings a system writes against the SPIR-V reflection data of its shader
+/// stages.
+///
+/// Run this when a system is created so a shader edit that moves or retypes a binding fails at
+/// startup with a report of every mismatch, rather than as a validation error mid-frame.
+pub fn validate_descriptor_bindings(
+    system_name: &str,
+    entry_points: &[&EntryPoint],
+    expected: &[DescriptorBinding],
+) -> anyhow::Result<()> {
+    let mut reflected: BTreeMap<(u32, u32), Vec<DescriptorType>> = BTreeMap::new();
+
+    for entry_point in entry_points {
+        for (&key, requirements) in entry_point.info().descriptor_binding_requirements.iter() {
+            reflected
+                .entry(key)
+                .or_default()
+                .extend(requirements.descriptor_types.iter().copied());
+        }
+    }
+
+    let mut problems = vec![];
+
+    for binding in expected {
+        match reflected.remove(&(binding.set, binding.binding)) {
+            None => problems.push(format!(
+                "set {} binding {}: written as {:?} but not declared by any shader stage",
+                binding.set, binding.binding, binding.ty
+            )),
+            Some(types) if !types.contains(&binding.ty) => problems.push(format!(
+                "set {} binding {}: written as {:?} but the shader declares {:?}",
+                binding.set, binding.binding, binding.ty, types
+            )),
+            Some(_) => {}
+        }
+    }
+
+    for ((set, binding), types) in reflected {
+        problems.push(format!(
+            "set {} binding {}: the shader declares {:?} but nothing is written to it",
+            set, binding, types
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "descriptor bindings of {} don't match its shaders:\n  {}",
+            system_name,
+            problems.join("\n  ")
+        ))
+    }
+}
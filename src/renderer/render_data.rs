@@ -1,13 +1,25 @@
-use std::fmt;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::Range,
+};
 
 use cgmath::{Matrix4, SquareMatrix};
 
-use super::{geometry_shaders::vs::ObjectData, mesh::BasicMesh};
+use super::{geometry_shaders::vs::ObjectData, layers::RenderLayers, mesh::BasicMesh};
 
 pub struct RenderData {
     meshes: Vec<BasicMesh>,
-    object_data: Vec<(usize, ObjectData)>,
+    /// The mesh and material index of each queued object, with its object data.
+    object_data: Vec<(usize, usize, ObjectData)>,
+    /// The key each queued object was tracked under, if any.
+    object_keys: Vec<Option<u64>>,
+    object_layers: Vec<RenderLayers>,
+    /// Index of the object tracked under each key, for updating a retained scene in place.
+    key_indices: HashMap<u64, usize>,
     cam_matrices: (Matrix4<f32>, Matrix4<f32>),
+    /// Bumped whenever the queued objects or their meshes change, see `generation`.
+    generation: u64,
 }
 
 impl RenderData {
@@ -19,12 +31,88 @@ impl RenderData {
         self.meshes.push(mesh);
     }
 
+    /// Puts `mesh` in place of the mesh at `index`, returning the old one.
+    pub fn replace_mesh(&mut self, index: usize, mesh: BasicMesh) -> BasicMesh {
+        self.generation += 1;
+        std::mem::replace(&mut self.meshes[index], mesh)
+    }
+
+    /// Changes whenever objects are queued or cleared, or a mesh is replaced, so what's derived
+    /// from the queued objects can be kept while it stays the same, as it does from frame to
+    /// frame for a retained scene.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
     pub fn reset_object_data(&mut self) {
+        self.generation += 1;
         self.object_data = vec![];
+        self.object_keys = vec![];
+        self.object_layers = vec![];
+        self.key_indices.clear();
+    }
+
+    pub fn add_object_data(
+        &mut self,
+        mesh_id: usize,
+        material_id: usize,
+        key: Option<u64>,
+        layers: RenderLayers,
+        object_data: ObjectData,
+    ) {
+        self.generation += 1;
+        if let Some(key) = key {
+            self.key_indices.insert(key, self.object_data.len());
+        }
+        self.object_data.push((mesh_id, material_id, object_data));
+        self.object_keys.push(key);
+        self.object_layers.push(layers);
+    }
+
+    /// Replaces the object tracked under `key`, or adds it when there's none.
+    pub fn set_object_data(
+        &mut self,
+        mesh_id: usize,
+        material_id: usize,
+        key: u64,
+        layers: RenderLayers,
+        object_data: ObjectData,
+    ) {
+        let Some(&index) = self.key_indices.get(&key) else {
+            self.add_object_data(mesh_id, material_id, Some(key), layers, object_data);
+            return;
+        };
+        self.generation += 1;
+        self.object_data[index] = (mesh_id, material_id, object_data);
+        self.object_layers[index] = layers;
+    }
+
+    /// Removes the object tracked under `key`, moving the last object into its place.
+    pub fn remove_object_data(&mut self, key: u64) {
+        let Some(index) = self.key_indices.remove(&key) else {
+            return;
+        };
+        self.generation += 1;
+        self.object_data.swap_remove(index);
+        self.object_keys.swap_remove(index);
+        self.object_layers.swap_remove(index);
+        if let Some(Some(moved)) = self.object_keys.get(index) {
+            self.key_indices.insert(*moved, index);
+        }
     }
 
-    pub fn add_object_data(&mut self, mesh_id: usize, object_data: ObjectData) {
-        self.object_data.push((mesh_id, object_data));
+    /// The object data of the object tracked under `key`.
+    pub fn keyed_object_data(&self, key: u64) -> Option<&ObjectData> {
+        self.key_indices
+            .get(&key)
+            .map(|&index| &self.object_data[index].2)
+    }
+
+    /// Sets every queued object's previous model matrix to its current one.
+    pub fn settle_object_motion(&mut self) {
+        for (_, _, object) in &mut self.object_data {
+            object.previous_model = object.model;
+        }
     }
 
     pub fn update_cam_matrices(&mut self, matrices: (Matrix4<f32>, Matrix4<f32>)) {
@@ -36,16 +124,83 @@ impl RenderData {
     }
 
     pub fn object_data(&self) -> Vec<ObjectData> {
-        self.object_data.iter().map(|a| a.1).collect()
+        self.object_data.iter().map(|a| a.2).collect()
+    }
+
+    pub fn mesh(&self, index: usize) -> &BasicMesh {
+        &self.meshes[index]
+    }
+
+    pub fn meshes(&self) -> &[BasicMesh] {
+        &self.meshes
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.object_data.len()
+    }
+
+    /// Consecutive queued objects that share a mesh and material, as the mesh's index, the
+    /// material's index and the range of the objects' indices.
+    pub fn mesh_runs(&self) -> Vec<(usize, usize, Range<u32>)> {
+        let mut runs: Vec<(usize, usize, Range<u32>)> = vec![];
+        for (index, (mesh_index, material_index, _)) in self.object_data.iter().enumerate() {
+            let index = index as u32;
+            match runs.last_mut() {
+                Some((mesh, material, range))
+                    if mesh == mesh_index && material == material_index =>
+                {
+                    range.end = index + 1
+                }
+                _ => runs.push((*mesh_index, *material_index, index..index + 1)),
+            }
+        }
+        runs
+    }
+
+    /// The mesh of the queued object at `index`.
+    pub fn object_mesh(&self, index: usize) -> &BasicMesh {
+        &self.meshes[self.object_data[index].0]
+    }
+
+    /// The queued objects' meshes along with their object data, in draw order.
+    pub fn objects(&self) -> impl Iterator<Item = (&BasicMesh, &ObjectData)> {
+        self.object_data
+            .iter()
+            .map(|(mesh_index, _, object)| (&self.meshes[*mesh_index], object))
+    }
+
+    /// The key each queued object was tracked under, in draw order.
+    pub fn object_keys(&self) -> &[Option<u64>] {
+        &self.object_keys
+    }
+
+    /// The layers of each queued object, in draw order.
+    pub fn object_layers(&self) -> &[RenderLayers] {
+        &self.object_layers
+    }
+
+    /// The index and mesh of each queued object whose key is in `keys`.
+    pub fn keyed_objects<'a>(
+        &'a self,
+        keys: &'a HashSet<u64>,
+    ) -> impl Iterator<Item = (u32, &'a BasicMesh)> {
+        self.object_data
+            .iter()
+            .zip(&self.object_keys)
+            .enumerate()
+            .filter(|(_, (_, key))| key.is_some_and(|key| keys.contains(&key)))
+            .map(|(index, ((mesh_index, _, _), _))| (index as u32, &self.meshes[*mesh_index]))
     }
 
-    /// Produces a vector containing a tuple of the mesh's index in the ObjectData array and the
-    /// mesh itself.
-    pub fn render_iter<'a>(&'a self) -> impl Iterator<Item = (u32, &'a BasicMesh)> {
+    /// Produces a vector containing a tuple of the mesh's index in the ObjectData array, the
+    /// mesh itself and its material's index.
+    pub fn render_iter<'a>(&'a self) -> impl Iterator<Item = (u32, &'a BasicMesh, usize)> {
         self.object_data
             .iter()
             .enumerate()
-            .map(|(index, (mesh_index, _))| (index as u32, &self.meshes[*mesh_index]))
+            .map(|(index, (mesh_index, material_index, _))| {
+                (index as u32, &self.meshes[*mesh_index], *material_index)
+            })
     }
 }
 
@@ -65,7 +220,11 @@ impl Default for RenderData {
         RenderData {
             meshes: vec![],
             object_data: vec![],
+            object_keys: vec![],
+            object_layers: vec![],
+            key_indices: HashMap::new(),
             cam_matrices: (Matrix4::identity(), Matrix4::identity()),
+            generation: 0,
         }
     }
 }
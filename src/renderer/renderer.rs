@@ -1,206 +1,895 @@
-use std::sync::Arc;
+use std::{collections::HashSet, path::Path, sync::Arc, time::Instant};
 
 use anyhow::{anyhow, Context};
-use cgmath::{Matrix4, SquareMatrix, Vector3};
+use cgmath::{InnerSpace, Matrix4, Vector3};
 use vulkano::{
-    command_buffer::allocator::{
-        StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo,
-    },
-    device::DeviceExtensions,
-    instance::{
-        debug::{
-            DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessengerCallback,
-            DebugUtilsMessengerCreateInfo,
-        },
-        InstanceCreateInfo, InstanceExtensions,
-    },
+    buffer::Subbuffer,
+    image::{sampler::Sampler, view::ImageView, Image},
     sync::{self, GpuFuture},
 };
-use vulkano_util::{
-    context::{VulkanoConfig, VulkanoContext},
-    window::{VulkanoWindows, WindowDescriptor},
-};
 use winit::{
     dpi::PhysicalSize,
     event_loop::EventLoop,
-    window::{CursorGrabMode, WindowId},
+    window::{CursorGrabMode, WindowId, WindowLevel},
+};
+
+use crate::{
+    assets::TextureUsage,
+    game::{CursorMode, SpatialIndex, Transform},
+    FrameSystem, GeometrySystem, LightingPass, Pass,
 };
 
-use crate::{game::Transform, FrameSystem, GeometrySystem, LightingPass, Pass};
+use super::{
+    analysis::{AnalysisReport, ImageAnalysis},
+    batch::StaticBatch,
+    billboard::Billboard,
+    capture::FrameCapture,
+    config::RendererConfig,
+    descriptor_cache::DescriptorSetCache,
+    foliage::{Foliage, FoliageId, FoliageLayer, Wind},
+    frames_in_flight::FramesInFlight,
+    hud::{HudPanel, HudStats, UiPrimitive},
+    ibl::EnvironmentBaker,
+    layers::{OverlayCamera, RenderLayers},
+    lighting::{DirectionalLight, PointLight, SkyGradient},
+    material::{Material, MaterialId, Tint},
+    memory::{MemoryCategory, MemoryStats},
+    mesh::Indices,
+    mesh_pool::MeshPool,
+    motion_blur::MotionBlurSettings,
+    occlusion::OcclusionCuller,
+    outline::OutlineStyle,
+    picking::{ObjectPicker, PickResult},
+    portal::{Portal, PortalLighting, Portals},
+    post_process::UpscaleFilter,
+    probe::{ReflectionProbe, ReflectionProbes, CUBE_FACES},
+    readback::{ReadbackFuture, Readbacks},
+    shadows::{ShadowSettings, SHADOW_MAP_SIZE},
+    sprite::SpriteMeshId,
+    ssgi::{GlobalIllumination, SsgiSettings},
+    ssr::SsrSettings,
+    stats::{FrameStats, PassTimes, SceneStats},
+    texture::{TextureHandle, TextureLoader},
+    upload::{MeshUploadHandle, MeshUploader, UploadStatus},
+    user_pass::{PassContext, PassPoint, UserPass, UserPassId},
+    vertex::{MeshVertex, VertexPositionNormalUv},
+    vulkan_context::VulkanContext,
+    window::{RenderWindow, SwapchainSettings},
+};
 
 pub struct Renderer {
-    context: VulkanoContext,
-    windows: VulkanoWindows,
+    context: Arc<VulkanContext>,
+    window: RenderWindow,
+    frames_in_flight: FramesInFlight,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
     frame_system: FrameSystem,
     geometry_system: GeometrySystem,
+    /// Drawn after the geometry, in the same subpass.
+    foliage: Foliage,
+    /// Rendered before the frame, their quads drawn after the foliage.
+    portals: Portals,
+    /// Captured before the frame, into the environment baker's atlas.
+    reflection_probes: ReflectionProbes,
+    analysis: ImageAnalysis,
+    occlusion_culler: OcclusionCuller,
+    /// Copied after everything else of the frame.
+    readbacks: Readbacks,
+    /// Draws its object IDs before the frame, on frames with a pick asked for.
+    picker: ObjectPicker,
+    /// Streams meshes in before each frame.
+    uploader: MeshUploader,
+    textures: TextureLoader,
+    environment_baker: EnvironmentBaker,
+    default_material: MaterialId,
+    /// What each material was created from, to rebuild them when their textures are reloaded.
+    materials: Vec<(MaterialId, Material)>,
+    point_lights: Vec<PointLight>,
+    /// Casts the shadows, unlike the point lights. Persists across frames.
+    directional_light: Option<DirectionalLight>,
+    /// Scales the environment lighting everything. Persists across frames.
+    ambient_light: [f32; 3],
+    /// Drawn behind the scene, which is left black without one. Persists across frames.
+    sky: Option<SkyGradient>,
+    /// Set by `begin_scene`, after which queued meshes and lights survive `render`.
+    retain_scene: bool,
+    scene_open: bool,
+    /// Reported by the caller, shown by the HUD.
+    cpu_time_ms: f32,
+    pass_times: PassTimes,
+    hud_entities: Option<usize>,
+    analysis_requested: bool,
+    analysis_report: Option<AnalysisReport>,
+    capture: FrameCapture,
+    cursor_mode: CursorMode,
+    text_input: bool,
+    /// See `RendererConfig::low_latency`.
+    low_latency: bool,
 }
 
-#[cfg(feature = "tracing")]
-use tracing_tracy::client::frame_mark;
-
-use super::geometry_shaders::VertexPositionColorNormal;
-
 impl Renderer {
-    pub fn new(event_loop: &EventLoop<()>) -> anyhow::Result<Self> {
-        let context = VulkanoContext::new(VulkanoConfig {
-            device_extensions: DeviceExtensions {
-                khr_swapchain: true,
-                khr_shader_draw_parameters: true,
-                ..Default::default()
-            },
-            instance_create_info: InstanceCreateInfo {
-                enabled_extensions: InstanceExtensions {
-                    ext_debug_utils: true,
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            debug_create_info: Some(DebugUtilsMessengerCreateInfo {
-                message_severity: DebugUtilsMessageSeverity::ERROR
-                    | DebugUtilsMessageSeverity::WARNING
-                    | DebugUtilsMessageSeverity::INFO
-                    | DebugUtilsMessageSeverity::VERBOSE,
-                message_type: DebugUtilsMessageType::GENERAL
-                    | DebugUtilsMessageType::VALIDATION
-                    | DebugUtilsMessageType::PERFORMANCE,
-                ..DebugUtilsMessengerCreateInfo::user_callback(unsafe {
-                    DebugUtilsMessengerCallback::new(
-                        |message_severity, message_type, callback_data| {
-                            let severity = if message_severity
-                                .intersects(DebugUtilsMessageSeverity::ERROR)
-                            {
-                                "error"
-                            } else if message_severity
-                                .intersects(DebugUtilsMessageSeverity::WARNING)
-                            {
-                                "warning"
-                            } else if message_severity.intersects(DebugUtilsMessageSeverity::INFO) {
-                                "information"
-                            } else if message_severity
-                                .intersects(DebugUtilsMessageSeverity::VERBOSE)
-                            {
-                                "verbose"
-                            } else {
-                                panic!("no-impl");
-                            };
-
-                            let ty = if message_type.intersects(DebugUtilsMessageType::GENERAL) {
-                                "general"
-                            } else if message_type.intersects(DebugUtilsMessageType::VALIDATION) {
-                                "validation"
-                            } else if message_type.intersects(DebugUtilsMessageType::PERFORMANCE) {
-                                "performance"
-                            } else {
-                                panic!("no-impl");
-                            };
-
-                            log::debug!(
-                                "{} {} {}: {}",
-                                callback_data.message_id_name.unwrap_or("unknown"),
-                                ty,
-                                severity,
-                                callback_data.message
-                            );
-                        },
-                    )
-                })
-            }),
-            ..Default::default()
-        });
-
-        let mut windows = VulkanoWindows::default();
+    pub fn new(event_loop: &EventLoop<()>, config: RendererConfig) -> anyhow::Result<Self> {
+        let context = Arc::new(VulkanContext::new(&config).context("creating Vulkan context")?);
+        Self::with_context(event_loop, context, config)
+    }
 
-        windows.create_window(event_loop, &context, &WindowDescriptor::default(), |ci| {
-            ci.image_format = vulkano::format::Format::B8G8R8A8_UNORM;
-            ci.min_image_count = ci.min_image_count.max(2);
-        });
+    /// Creates a renderer on an existing context, so several renderers can share one device.
+    /// Each gets its own window, whose swapchain follows this renderer's config.
+    pub fn with_context(
+        event_loop: &EventLoop<()>,
+        context: Arc<VulkanContext>,
+        config: RendererConfig,
+    ) -> anyhow::Result<Self> {
+        let window = RenderWindow::new(event_loop, &context, &config).context("creating window")?;
+        let SwapchainSettings {
+            format: image_format,
+            color_space,
+            ..
+        } = *window.settings();
 
-        let queue = windows
-            .get_primary_renderer()
-            .context("geting primary renderer")?
-            .graphics_queue();
+        let frames_in_flight = FramesInFlight::new(&context, config.frames_in_flight)
+            .context("creating frames in flight")?;
 
-        let image_format = windows
-            .get_primary_renderer()
-            .context("geting primary renderer")?
-            .swapchain_format();
+        let descriptor_set_cache = Arc::new(DescriptorSetCache::new(
+            context.descriptor_set_allocator().clone(),
+        ));
 
-        let memory_allocator = context.memory_allocator();
+        let textures = TextureLoader::new(&context).context("creating texture loader")?;
+        let white = textures
+            .view(textures.white())
+            .context("getting white texture")?
+            .clone();
 
-        let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
-            context.device().clone(),
-            StandardCommandBufferAllocatorCreateInfo {
-                secondary_buffer_count: 32,
-                ..Default::default()
-            },
-        ));
+        // Until an environment is set, a uniform white one makes the ambient light behave like
+        // a flat color
+        let environment_baker =
+            EnvironmentBaker::new(&context).context("creating environment baker")?;
+        let environment = environment_baker
+            .bake(white.clone(), textures.sampler().clone())
+            .context("baking default environment")?;
 
-        let frame_system = FrameSystem::new(
-            queue.clone(),
+        let mut frame_system = FrameSystem::new(
+            &context,
             image_format,
-            memory_allocator.clone(),
-            command_buffer_allocator.clone(),
+            color_space,
+            descriptor_set_cache.clone(),
+            environment,
+            (white.clone(), textures.sampler().clone()),
+            frames_in_flight.count(),
+            config.motion_vectors,
         )
         .context("creating FrameSystem")?;
+        frame_system.set_render_scale(config.render_scale);
+        frame_system
+            .post_process
+            .set_transparent(window.transparent());
+        frame_system.ssgi.set_mode(config.global_illumination);
 
-        let geometry_system = GeometrySystem::new(
-            queue.clone(),
+        let portals = Portals::new(
+            &context,
             frame_system.deferred_subpass(),
-            memory_allocator.clone(),
-            command_buffer_allocator.clone(),
+            descriptor_set_cache.clone(),
+        )
+        .context("creating portals")?;
+        let reflection_probes = ReflectionProbes::new(&context, portals.view_subpass())
+            .context("creating reflection probes")?;
+
+        let picker = ObjectPicker::new(&context).context("creating object picker")?;
+
+        let mesh_pool = MeshPool::new(&context, config.mesh_pool_block_size);
+        let mut geometry_system = GeometrySystem::new(
+            &context,
+            frame_system.deferred_subpass(),
+            frame_system.depth_prepass_subpass(),
+            frame_system.outline.mask_subpass(),
+            picker.subpass(),
+            frame_system.shadows.subpass(),
+            portals.view_subpass(),
+            &descriptor_set_cache,
+            frames_in_flight.count(),
+            mesh_pool.clone(),
         )
         .context("creating Geometry System")?;
+        geometry_system.set_indirect_draws(config.indirect_draws);
+        if config.gpu_culling {
+            geometry_system.set_gpu_culling(true);
+        }
+        geometry_system.set_srgb_colors(frame_system.srgb_colors());
+
+        let foliage = Foliage::new(
+            &context,
+            frame_system.deferred_subpass(),
+            descriptor_set_cache.clone(),
+            frames_in_flight.count(),
+            textures.sampler().clone(),
+        )
+        .context("creating foliage")?;
+
+        let analysis = ImageAnalysis::new(&context).context("creating image analysis")?;
 
-        Ok(Renderer {
+        let occlusion_culler =
+            OcclusionCuller::new(&context, frames_in_flight.count(), config.occlusion_culling)
+                .context("creating occlusion culler")?;
+
+        let readbacks = Readbacks::new(&context, frames_in_flight.count());
+
+        let uploader = MeshUploader::new(
+            &context,
+            frames_in_flight.count(),
+            config.upload_budget,
+            mesh_pool,
+        )
+        .context("creating mesh uploader")?;
+
+        let default_material = geometry_system
+            .create_material(
+                &Material::default(),
+                white.clone(),
+                white.clone(),
+                white.clone(),
+                white,
+                textures.sampler().clone(),
+            )
+            .context("creating default material")?;
+
+        let mut renderer = Renderer {
             context,
-            windows,
+            window,
+            frames_in_flight,
+            descriptor_set_cache,
             frame_system,
             geometry_system,
-        })
+            foliage,
+            portals,
+            reflection_probes,
+            analysis,
+            occlusion_culler,
+            readbacks,
+            picker,
+            uploader,
+            textures,
+            environment_baker,
+            default_material,
+            materials: vec![],
+            point_lights: Vec::new(),
+            directional_light: Some(DirectionalLight::default()),
+            ambient_light: [0.1, 0.1, 0.1],
+            sky: None,
+            retain_scene: false,
+            scene_open: false,
+            cpu_time_ms: 0.0,
+            pass_times: PassTimes::default(),
+            hud_entities: None,
+            analysis_requested: false,
+            analysis_report: None,
+            capture: FrameCapture::load(),
+            cursor_mode: CursorMode::Free,
+            text_input: false,
+            low_latency: config.low_latency,
+        };
+        renderer.set_always_on_top(config.always_on_top);
+        renderer.set_click_through(config.click_through);
+
+        Ok(renderer)
+    }
+
+    pub fn context(&self) -> &Arc<VulkanContext> {
+        &self.context
+    }
+
+    pub fn set_depth_prepass(&mut self, enabled: bool) {
+        self.frame_system.set_depth_prepass(enabled);
+    }
+
+    pub fn set_indirect_draws(&mut self, enabled: bool) {
+        self.geometry_system.set_indirect_draws(enabled);
+    }
+
+    /// See `RendererConfig::low_latency`.
+    pub fn set_low_latency(&mut self, enabled: bool) {
+        self.low_latency = enabled;
+    }
+
+    pub fn low_latency(&self) -> bool {
+        self.low_latency
+    }
+
+    /// Culls the indirect draws on the GPU, see `RendererConfig::gpu_culling`.
+    pub fn set_gpu_culling(&mut self, enabled: bool) {
+        self.geometry_system.set_gpu_culling(enabled);
+    }
+
+    /// Toggles bloom, which spreads light brighter than white, like strong emissive materials,
+    /// into its surroundings.
+    pub fn set_bloom(&mut self, enabled: bool) {
+        self.frame_system.post_process.set_bloom(enabled);
+    }
+
+    /// Renders the 3D scene at `scale` times the window resolution, clamped to `0.25..=1.0`, and
+    /// upscales it before the HUD is drawn. Lower scales trade sharpness for GPU time.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.frame_system.set_render_scale(scale);
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.frame_system.render_scale()
+    }
+
+    /// How the scene is upscaled when the render scale is below 1.
+    pub fn set_upscale_filter(&mut self, filter: UpscaleFilter) {
+        self.frame_system.post_process.set_upscale_filter(filter);
+    }
+
+    /// Toggles temporal anti-aliasing, which jitters the camera by a subpixel each frame and
+    /// blends the results over time. Needs `RendererConfig::motion_vectors`.
+    pub fn set_taa(&mut self, enabled: bool) {
+        if enabled && self.frame_system.velocity_buffer.is_none() {
+            log::warn!("TAA needs motion vectors, which the renderer was created without");
+            return;
+        }
+        self.frame_system.taa.set_enabled(enabled);
+    }
+
+    pub fn taa_enabled(&self) -> bool {
+        self.frame_system.taa.enabled()
+    }
+
+    /// Keeps TAA from blending in frames from before a camera cut.
+    pub fn reset_taa_history(&mut self) {
+        self.frame_system.taa.reset();
+    }
+
+    /// Toggles motion blur along the motion vectors. Needs `RendererConfig::motion_vectors`.
+    pub fn set_motion_blur(&mut self, enabled: bool) {
+        if enabled && self.frame_system.velocity_buffer.is_none() {
+            log::warn!("motion blur needs motion vectors, which the renderer was created without");
+            return;
+        }
+        self.frame_system.motion_blur.set_enabled(enabled);
+    }
+
+    pub fn motion_blur_enabled(&self) -> bool {
+        self.frame_system.motion_blur.enabled()
+    }
+
+    pub fn set_motion_blur_settings(&mut self, settings: MotionBlurSettings) {
+        self.frame_system.motion_blur.set_settings(settings);
+    }
+
+    /// Toggles screen-space reflections, which trace glossy reflections of what's on screen
+    /// and fall back to the environment where they can't.
+    pub fn set_ssr(&mut self, enabled: bool) {
+        self.frame_system.ssr.set_enabled(enabled);
+    }
+
+    pub fn ssr_enabled(&self) -> bool {
+        self.frame_system.ssr.enabled()
+    }
+
+    pub fn ssr_settings(&self) -> SsrSettings {
+        self.frame_system.ssr.settings()
+    }
+
+    pub fn set_ssr_settings(&mut self, settings: SsrSettings) {
+        self.frame_system.ssr.set_settings(settings);
+    }
+
+    /// Picks how indirect diffuse light is approximated on top of the ambient light and
+    /// lightmaps.
+    pub fn set_global_illumination(&mut self, mode: GlobalIllumination) {
+        self.frame_system.ssgi.set_mode(mode);
+    }
+
+    pub fn global_illumination(&self) -> GlobalIllumination {
+        self.frame_system.ssgi.mode()
+    }
+
+    pub fn ssgi_settings(&self) -> SsgiSettings {
+        self.frame_system.ssgi.settings()
+    }
+
+    pub fn set_ssgi_settings(&mut self, settings: SsgiSettings) {
+        self.frame_system.ssgi.set_settings(settings);
+    }
+
+    /// Toggles the directional light's shadows.
+    pub fn set_shadows(&mut self, enabled: bool) {
+        self.frame_system.shadows.set_enabled(enabled);
+    }
+
+    pub fn shadows_enabled(&self) -> bool {
+        self.frame_system.shadows.enabled()
+    }
+
+    pub fn shadow_settings(&self) -> ShadowSettings {
+        self.frame_system.shadows.settings()
+    }
+
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.frame_system.shadows.set_settings(settings);
+    }
+
+    /// Tints the scene by the shadow cascade each pixel falls in, to see where their boundaries
+    /// are.
+    pub fn set_shadow_cascade_debug(&mut self, enabled: bool) {
+        self.frame_system.shadows.set_debug_cascades(enabled);
+    }
+
+    /// Draws a line over the next frame, on top of everything in the scene.
+    pub fn draw_debug_line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: [f32; 4]) {
+        self.frame_system.debug_draw.line(from, to, color);
+    }
+
+    /// Queues a billboard for the next frame.
+    pub fn enqueue_billboard(&mut self, billboard: &Billboard) {
+        self.frame_system.billboards.push(billboard);
+    }
+
+    /// Creates a mesh drawn with `enqueue_sprite_mesh`, unlit over the composited image.
+    pub fn create_sprite_mesh(
+        &mut self,
+        vertices: Vec<VertexPositionNormalUv>,
+        indices: Vec<u32>,
+    ) -> anyhow::Result<SpriteMeshId> {
+        self.frame_system.sprites.create_mesh(vertices, indices)
+    }
+
+    /// Replaces the vertices and indices of a sprite mesh. Must be called between frames.
+    pub fn reload_sprite_mesh(
+        &mut self,
+        mesh: SpriteMeshId,
+        vertices: Vec<VertexPositionNormalUv>,
+        indices: Vec<u32>,
+    ) -> anyhow::Result<()> {
+        let old = self
+            .frame_system
+            .sprites
+            .replace_mesh(mesh, vertices, indices)?;
+        self.frames_in_flight.retire(old);
+        Ok(())
+    }
+
+    /// Queues a sprite mesh for the next frame, textured with `texture` and placed by
+    /// `transform`. Sprites are alpha blended in the order they're queued.
+    pub fn enqueue_sprite_mesh(
+        &mut self,
+        mesh: SpriteMeshId,
+        texture: TextureHandle,
+        transform: Transform,
+    ) -> anyhow::Result<()> {
+        let view = self
+            .textures
+            .view(texture)
+            .context("getting sprite texture")?
+            .clone();
+        self.frame_system
+            .sprites
+            .push(mesh, view, transform.model());
+        Ok(())
+    }
+
+    /// Makes `texture` the atlas billboards sample their `uv` regions from.
+    pub fn set_billboard_atlas(&mut self, texture: TextureHandle) -> anyhow::Result<()> {
+        let view = self
+            .textures
+            .view(texture)
+            .context("getting billboard atlas")?
+            .clone();
+        self.frame_system.billboards.set_atlas(view);
+        Ok(())
+    }
+
+    /// Uploads `layer` as the foliage layer `id`, replacing the layer `id` had. Layers are drawn
+    /// every frame until they're removed.
+    pub fn set_foliage(&mut self, id: FoliageId, mut layer: FoliageLayer) -> anyhow::Result<()> {
+        let texture = self
+            .textures
+            .view(layer.texture.unwrap_or(self.textures.white()))
+            .context("getting foliage texture")?
+            .clone();
+        for instance in &mut layer.instances {
+            instance.color = self.frame_system.linear_color(instance.color);
+        }
+        self.foliage.set_layer(id, layer, texture)
+    }
+
+    pub fn remove_foliage(&mut self, id: FoliageId) {
+        self.foliage.remove_layer(id);
+    }
+
+    /// Replaces the wind swaying every foliage layer.
+    pub fn set_wind(&mut self, wind: Wind) {
+        self.foliage.set_wind(wind);
+    }
+
+    /// Outlines the tracked objects queued under `keys`, replacing the previous selection.
+    pub fn set_selected_objects(&mut self, keys: impl IntoIterator<Item = u64>) {
+        self.geometry_system.set_selected(keys);
+    }
+
+    /// Keys of the tracked objects that were inside the camera's frustum and not occlusion culled
+    /// in the last rendered frame.
+    pub fn visible_objects(&self) -> &HashSet<u64> {
+        self.geometry_system.visible_keys()
+    }
+
+    pub fn outline_style(&self) -> OutlineStyle {
+        self.frame_system.outline.style()
+    }
+
+    pub fn set_outline_style(&mut self, style: OutlineStyle) {
+        self.frame_system.outline.set_style(style);
+    }
+
+    pub fn set_occlusion_culling(&mut self, enabled: bool) {
+        self.occlusion_culler.set_enabled(enabled);
     }
 
     pub fn enqueue_mesh(&mut self, mesh_id: usize, transform: Transform) {
-        self.geometry_system.enqueue_mesh(mesh_id, transform);
+        self.enqueue_mesh_tinted(mesh_id, None, transform, Tint::default());
+    }
+
+    pub fn enqueue_mesh_with_material(
+        &mut self,
+        mesh_id: usize,
+        material: MaterialId,
+        transform: Transform,
+    ) {
+        self.enqueue_mesh_tinted(mesh_id, Some(material), transform, Tint::default());
+    }
+
+    /// Queues a mesh with its base color blended toward `tint`. Uses the default material when
+    /// `material` is `None`.
+    pub fn enqueue_mesh_tinted(
+        &mut self,
+        mesh_id: usize,
+        material: Option<MaterialId>,
+        transform: Transform,
+        tint: Tint,
+    ) {
+        self.geometry_system.enqueue_mesh(
+            mesh_id,
+            material.unwrap_or(self.default_material),
+            transform,
+            tint,
+            None,
+            RenderLayers::DEFAULT,
+        );
+    }
+
+    /// Like `enqueue_mesh_tinted`, for an object that may move. The transform is remembered
+    /// under `object_key` so the object's own motion since the previous frame ends up in the
+    /// motion vectors. Objects queued without a key are treated as static, on the default
+    /// layer.
+    pub fn enqueue_tracked_mesh(
+        &mut self,
+        object_key: u64,
+        mesh_id: usize,
+        material: Option<MaterialId>,
+        transform: Transform,
+        tint: Tint,
+        layers: RenderLayers,
+    ) {
+        self.geometry_system.enqueue_mesh(
+            mesh_id,
+            material.unwrap_or(self.default_material),
+            transform,
+            tint,
+            Some(object_key),
+            layers,
+        );
+    }
+
+    /// Keeps the object tracked under `object_key` in a retained scene up to date, replacing it
+    /// in place or queuing it when there's none, so the game only has to send what changed
+    /// since the scene was started with `begin_scene`. Without a scene this queues the object
+    /// like `enqueue_tracked_mesh`.
+    pub fn update_tracked_mesh(
+        &mut self,
+        object_key: u64,
+        mesh_id: usize,
+        material: Option<MaterialId>,
+        transform: Transform,
+        tint: Tint,
+        layers: RenderLayers,
+    ) {
+        let material = material.unwrap_or(self.default_material);
+        if self.retain_scene {
+            self.geometry_system
+                .update_mesh(mesh_id, material, transform, tint, object_key, layers);
+        } else {
+            self.geometry_system.enqueue_mesh(
+                mesh_id,
+                material,
+                transform,
+                tint,
+                Some(object_key),
+                layers,
+            );
+        }
+    }
+
+    /// Removes the object tracked under `object_key` from a retained scene.
+    pub fn remove_tracked_mesh(&mut self, object_key: u64) {
+        self.geometry_system.remove_mesh(object_key);
+    }
+
+    /// Replaces the queued point lights, e.g. to move a retained scene's lights without
+    /// starting a new scene.
+    pub fn set_point_lights(&mut self, lights: Vec<PointLight>) {
+        self.point_lights = lights;
+    }
+
+    /// Replaces the index of entity bounds that frustum culling and picking query instead of
+    /// testing every object. Objects tracked under the id of an entity it doesn't hold are still
+    /// tested one by one.
+    pub fn set_spatial_index(&mut self, index: Arc<SpatialIndex>) {
+        self.geometry_system.set_spatial_index(index);
+    }
+
+    /// Clears the queued meshes and lights so the game can specify the scene from scratch.
+    ///
+    /// Without scenes, everything queued is drawn by the next `render` and then cleared. Once
+    /// `begin_scene` has been called the queue is retained instead: every `render` draws the
+    /// last scene until the next `begin_scene`.
+    pub fn begin_scene(&mut self) {
+        if self.scene_open {
+            log::warn!("begin_scene called before end_scene, discarding the open scene");
+        }
+        self.geometry_system.clear_objects();
+        self.point_lights.clear();
+        self.retain_scene = true;
+        self.scene_open = true;
+    }
+
+    /// Finishes the scene started by `begin_scene`.
+    pub fn end_scene(&mut self) {
+        if !self.scene_open {
+            log::warn!("end_scene called without begin_scene");
+        }
+        self.scene_open = false;
+    }
+
+    /// The meshes, queued objects and lights the renderer currently holds.
+    pub fn scene_stats(&self) -> SceneStats {
+        SceneStats {
+            point_lights: self.point_lights.len(),
+            foliage_instances: self.foliage.instance_count(),
+            ..self.geometry_system.scene_stats()
+        }
+    }
+
+    /// Replaces the directional light, which lights every frame until it's replaced. `None`
+    /// leaves the scene to the ambient and point lights.
+    pub fn set_directional_light(&mut self, light: Option<DirectionalLight>) {
+        self.directional_light = light;
+    }
+
+    /// Replaces the color the environment is scaled by to light the scene. Defaults to a dim
+    /// grey.
+    pub fn set_ambient_light(&mut self, color: [f32; 3]) {
+        self.ambient_light = color;
+    }
+
+    /// Replaces the sky drawn behind the scene, which persists across frames. `None` leaves it
+    /// black.
+    pub fn set_sky(&mut self, sky: Option<SkyGradient>) {
+        self.sky = sky;
+    }
+
+    /// Exposes the scene for `ev100`, the exposure value at ISO 100, which scales every light's
+    /// lumens and lux into the brightness they're shaded with. Around 15 suits sunlight and 7 a
+    /// lit room. Defaults to 10.
+    pub fn set_exposure(&mut self, ev100: f32) {
+        self.frame_system.set_exposure_ev100(ev100);
+    }
+
+    /// Queues a point light for the next frame.
+    pub fn enqueue_point_light(&mut self, light: PointLight) {
+        self.point_lights.push(light);
     }
 
     pub fn set_camera_params(&mut self, matrices: (Matrix4<f32>, Matrix4<f32>)) {
         self.geometry_system.set_camera_params(matrices);
     }
 
+    /// Limits the camera set with `set_camera_params` to the meshes on `layers`. Billboards,
+    /// foliage and the debug drawing aren't on layers, the main camera always draws them.
+    pub fn set_camera_layers(&mut self, layers: RenderLayers) {
+        self.geometry_system.set_camera_layers(layers);
+    }
+
+    /// Replaces the cameras drawn over the main one each frame, in order. See `OverlayCamera`.
+    pub fn set_overlay_cameras(&mut self, cameras: impl IntoIterator<Item = OverlayCamera>) {
+        self.geometry_system.set_overlay_cameras(cameras);
+    }
+
+    /// Replaces the portals and mirrors, which persist across frames. See `Portal`.
+    pub fn set_portals(&mut self, portals: impl IntoIterator<Item = Portal>) {
+        self.portals.set_portals(portals);
+    }
+
+    /// How many portals deep views through portals are rendered, e.g. 2 to see a mirror in a
+    /// mirror. Each view renders the scene again, and `MAX_PORTAL_VIEWS` caps them across every
+    /// level. Defaults to 1.
+    pub fn set_portal_depth(&mut self, depth: usize) {
+        self.portals.set_max_depth(depth);
+    }
+
+    /// Replaces the reflection probes, which persist across frames. New and moved probes are
+    /// captured over the next frames, one a frame, and only meshes queued after this pick from
+    /// them. See `ReflectionProbe`.
+    pub fn set_reflection_probes(&mut self, probes: impl IntoIterator<Item = ReflectionProbe>) {
+        self.reflection_probes.set_probes(probes);
+        self.geometry_system
+            .set_reflection_probes(self.reflection_probes.probes());
+    }
+
+    /// Captures every reflection probe again over the next frames, e.g. after the scene around
+    /// them changed.
+    pub fn recapture_reflection_probes(&mut self) {
+        self.reflection_probes.recapture_all();
+    }
+
+    /// Counters for the last rendered frame. `cpu_time_ms` is the last value passed to
+    /// `set_cpu_time`.
+    pub fn frame_stats(&self) -> FrameStats {
+        let (draw_calls, triangles, occluded_objects) = self.geometry_system.draw_stats();
+        let gpu_times = self.frames_in_flight.last_gpu_times();
+        FrameStats {
+            cpu_time_ms: self.cpu_time_ms,
+            gpu_time_ms: gpu_times.map(|times| times.frame_ms),
+            gpu_scene_ms: gpu_times.map(|times| times.scene_ms),
+            gpu_post_process_ms: gpu_times.map(|times| times.post_process_ms),
+            cpu_pass_ms: self.pass_times,
+            draw_calls: draw_calls + self.foliage.draw_calls(),
+            triangles,
+            occluded_objects,
+            vram_bytes: Some(self.memory_stats().total()),
+            ..Default::default()
+        }
+    }
+
+    /// The CPU time of the whole frame, which only the caller can measure.
+    pub fn set_cpu_time(&mut self, cpu_time_ms: f32) {
+        self.cpu_time_ms = cpu_time_ms;
+    }
+
+    /// Shows or hides the performance overlay.
+    pub fn set_hud_visible(&mut self, visible: bool) {
+        self.frame_system.hud.set_visible(visible);
+    }
+
+    pub fn hud_visible(&self) -> bool {
+        self.frame_system.hud.visible()
+    }
+
+    /// Replaces the panels drawn over the frame, shown even while the performance overlay is
+    /// hidden.
+    pub fn set_hud_panels(&mut self, panels: Vec<HudPanel>) {
+        self.frame_system.hud.set_panels(panels);
+    }
+
+    /// Queues a UI primitive for the next frame, drawn under the HUD.
+    pub fn draw_ui(&mut self, primitive: UiPrimitive) {
+        self.frame_system.hud.push(primitive);
+    }
+
+    /// Makes `texture` the atlas UI primitives sample their `uv` regions from.
+    pub fn set_ui_atlas(&mut self, texture: TextureHandle) -> anyhow::Result<()> {
+        let view = self
+            .textures
+            .view(texture)
+            .context("getting UI atlas")?
+            .clone();
+        self.textures.set_category(texture, MemoryCategory::Ui);
+        self.frame_system.hud.set_atlas(view);
+        Ok(())
+    }
+
+    /// Scales everything the UI passes draw by `scale` screen pixels per logical pixel, usually
+    /// the window's scale factor.
+    pub fn set_ui_scale(&mut self, scale: f32) {
+        self.frame_system.hud.set_scale(scale);
+    }
+
+    pub fn ui_scale(&self) -> f32 {
+        self.frame_system.hud.scale()
+    }
+
+    /// The entity count shown by the HUD, left blank when `None`.
+    pub fn set_hud_entities(&mut self, entities: Option<usize>) {
+        self.hud_entities = entities;
+    }
+
+    /// Device memory held by meshes, textures and render targets. Meshes are never freed, so
+    /// steady growth there points at meshes being created every frame.
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.context.memory_tracker().stats()
+    }
+
     pub fn resize(&mut self) -> anyhow::Result<()> {
-        self.windows
-            .get_primary_renderer_mut()
-            .ok_or_else(|| anyhow!("No primary renderer available"))
-            .map(|renderer| renderer.resize())
+        self.window.resize();
+        Ok(())
     }
 
     pub fn window_size(&self) -> Option<PhysicalSize<u32>> {
-        self.windows.get_primary_window().map(|w| w.inner_size())
+        Some(self.window.window().inner_size())
     }
 
     pub fn window_id(&self) -> Option<WindowId> {
-        self.windows.primary_window_id()
+        Some(self.window.window().id())
     }
 
-    pub fn set_cursor_captured(&self, captured: bool) {
-        if let Some(window) = self.windows.get_primary_window() {
-            if captured {
-                let _ = window
+    /// Keeps the window above the others, e.g. for an overlay over another application.
+    pub fn set_always_on_top(&mut self, enabled: bool) {
+        self.window.window().set_window_level(if enabled {
+            WindowLevel::AlwaysOnTop
+        } else {
+            WindowLevel::Normal
+        });
+    }
+
+    /// Lets the mouse through to whatever is behind the window, so it no longer gets clicks
+    /// itself. Keys still reach it while it's focused.
+    pub fn set_click_through(&mut self, enabled: bool) {
+        if let Err(e) = self.window.window().set_cursor_hittest(!enabled) {
+            log::warn!("Could not change click through: {}", e);
+        }
+    }
+
+    /// Physical pixels per logical pixel of the primary window's monitor.
+    pub fn scale_factor(&self) -> Option<f64> {
+        Some(self.window.window().scale_factor())
+    }
+
+    /// Applies the cursor mode to the primary window. Platforms that can't confine the cursor
+    /// fall back to locking it in place.
+    pub fn set_cursor_mode(&mut self, mode: CursorMode) {
+        if self.cursor_mode == mode {
+            return;
+        }
+
+        let window = self.window.window();
+        match mode {
+            CursorMode::Captured => {
+                if let Err(e) = window
                     .set_cursor_grab(CursorGrabMode::Confined)
-                    .or_else(|_e| window.set_cursor_grab(CursorGrabMode::Locked));
+                    .or_else(|_e| window.set_cursor_grab(CursorGrabMode::Locked))
+                {
+                    log::warn!("Could not capture cursor: {}", e);
+                }
                 window.set_cursor_visible(false);
-            } else {
+            }
+            CursorMode::Free => {
                 let _ = window.set_cursor_grab(CursorGrabMode::None);
                 window.set_cursor_visible(true);
             }
         }
+        self.cursor_mode = mode;
+    }
+
+    /// Lets the primary window's input method compose text, delivered as `Ime` events, while
+    /// `active`.
+    pub fn set_text_input(&mut self, active: bool) {
+        if self.text_input == active {
+            return;
+        }
+
+        self.window.window().set_ime_allowed(active);
+        self.text_input = active;
     }
 
     pub fn render(&mut self) -> anyhow::Result<()> {
-        let renderer = self
-            .windows
-            .get_primary_renderer_mut()
-            .context("getting primary renderer")?;
+        profile_scope!("renderer");
+        self.capture.begin_frame();
+        let in_flight = self.frames_in_flight.begin_frame()?;
+        self.descriptor_set_cache.next_frame();
+        let frame_stats = self.frame_stats();
+        let scene_stats = self.scene_stats();
+        profile_plot!("draw calls", frame_stats.draw_calls);
+        profile_plot!("queued objects", scene_stats.objects);
+
+        let renderer = &mut self.window;
 
         let acquire_future = match renderer.acquire() {
             Ok(future) => future,
@@ -211,53 +900,696 @@ impl Renderer {
             Err(e) => return Err(anyhow!("Unexpected error acquiring swapchain image: {}", e)),
         };
 
+        for (handle, mesh) in self.uploader.begin_frame(in_flight.index) {
+            let mesh_id = self.geometry_system.add_mesh(mesh);
+            self.uploader.set_ready(handle, mesh_id);
+        }
+        // Copied ahead of everything else, the meshes are only drawn once this frame is done
+        let acquire_future = match self.uploader.record(&in_flight)? {
+            Some(cb) => acquire_future
+                .then_execute(self.context.graphics_queue().clone(), cb)
+                .context("executing mesh uploads")?
+                .boxed(),
+            None => acquire_future,
+        };
+
+        if let Some(report) = self.analysis.collect(&in_flight)? {
+            log::info!(
+                "frame analysis: luminance {:.4}..{:.4}, {} non-finite pixels, depth {:.4}..{:.4}",
+                report.min_luminance,
+                report.max_luminance,
+                report.non_finite_pixels,
+                report.min_depth,
+                report.max_depth
+            );
+            self.analysis_report = Some(report);
+        }
+
+        self.readbacks.collect(&in_flight);
+        self.picker.collect();
+        self.occlusion_culler.collect(&in_flight)?;
+        if let Some(pyramid) = self.occlusion_culler.pyramid() {
+            if !self.geometry_system.culls_on_gpu() {
+                self.geometry_system.cull_occluded(pyramid);
+            }
+        }
+        let view_projection = self.geometry_system.view_projection();
+        if self.scene_open {
+            log::warn!("rendering a scene that hasn't been ended");
+        }
+        let point_lights = std::mem::take(&mut self.point_lights);
+
+        let render_extent = self
+            .frame_system
+            .render_extent(renderer.swapchain_image_view().image().extent());
+        let jitter = self
+            .frame_system
+            .taa
+            .begin_frame(view_projection, render_extent);
+        self.geometry_system.set_jitter(jitter);
+        let portal_views = self
+            .portals
+            .plan(self.geometry_system.unjittered_camera_matrices())?
+            .iter()
+            .map(|view| (view.projection, view.view))
+            .collect::<Vec<_>>();
+        let portal_view_count = portal_views.len();
+        self.geometry_system.set_portal_views(portal_views);
+        let probe_capture = self.reflection_probes.plan();
+        self.geometry_system
+            .set_capture_views(probe_capture.iter().flat_map(|capture| capture.faces));
+
+        let acquire_future = match self.geometry_system.record_culling(&in_flight)? {
+            Some(cb) => acquire_future
+                .then_execute(self.context.graphics_queue().clone(), cb)
+                .context("executing GPU culling")?
+                .boxed(),
+            None => acquire_future,
+        };
+
+        let silhouettes = self
+            .geometry_system
+            .draw_selection_mask([render_extent[0], render_extent[1]], &in_flight)
+            .context("drawing selection mask")?;
+        let mask = self
+            .frame_system
+            .outline
+            .record_mask(&in_flight, render_extent, silhouettes)?;
+        let acquire_future = match mask {
+            Some(cb) => acquire_future
+                .then_execute(self.context.graphics_queue().clone(), cb)
+                .context("executing selection mask")?
+                .boxed(),
+            None => acquire_future,
+        };
+
+        let acquire_future = match self
+            .picker
+            .take_request([render_extent[0], render_extent[1]])
+        {
+            Some((position, pixel)) => {
+                let (object_ids, keys) = self
+                    .geometry_system
+                    .draw_object_ids(pixel, [render_extent[0], render_extent[1]], &in_flight)
+                    .context("drawing object IDs")?;
+                let cb = self.picker.record(
+                    &in_flight,
+                    object_ids,
+                    position,
+                    keys,
+                    &mut self.readbacks,
+                )?;
+                acquire_future
+                    .then_execute(self.context.graphics_queue().clone(), cb)
+                    .context("executing object ID pass")?
+                    .boxed()
+            }
+            None => acquire_future,
+        };
+
+        let (projection, view) = self.geometry_system.camera_matrices();
+        let user_passes = self.frame_system.user_passes.record(PassContext {
+            point: PassPoint::BeforeScene,
+            queue: &self.frame_system.gfx_queue,
+            command_buffer_allocator: &in_flight.command_buffer_allocator,
+            frame_index: in_flight.index,
+            subpass: None,
+            extent: [render_extent[0], render_extent[1]],
+            view_projection: projection * view,
+            depth: &self.frame_system.depth_buffer,
+            target: None,
+        })?;
+        let mut acquire_future = acquire_future;
+        for cb in user_passes {
+            acquire_future = acquire_future
+                .then_execute(self.context.graphics_queue().clone(), cb)
+                .context("executing user pass")?
+                .boxed();
+        }
+
+        let mut pass_start = Instant::now();
+        let cascades = self
+            .frame_system
+            .shadows
+            .update(
+                &in_flight,
+                self.geometry_system.unjittered_camera_matrices(),
+                self.directional_light
+                    .map(|light| light.direction)
+                    .unwrap_or(Vector3::new(0.0, 0.0, 0.0)),
+            )?
+            .to_vec();
+        let casters = cascades
+            .into_iter()
+            .map(|cascade| {
+                self.geometry_system
+                    .draw_shadow_casters(cascade, [SHADOW_MAP_SIZE; 2], &in_flight)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("drawing shadow casters")?;
+        let acquire_future = match self.frame_system.shadows.record(&in_flight, casters)? {
+            Some(cb) => acquire_future
+                .then_execute(self.context.graphics_queue().clone(), cb)
+                .context("executing shadows")?
+                .boxed(),
+            None => acquire_future,
+        };
+        let shadow_time = lap(&mut pass_start);
+
+        let ambient = self.frame_system.linear_color(self.ambient_light);
+        let portal_lighting = match self.directional_light {
+            Some(light) => {
+                let intensity = light.illuminance * self.frame_system.exposure();
+                PortalLighting {
+                    ambient,
+                    sun_direction: light.direction.normalize().into(),
+                    sun_color: self
+                        .frame_system
+                        .linear_color(light.color)
+                        .map(|channel| channel * intensity),
+                }
+            }
+            None => PortalLighting {
+                ambient,
+                sun_direction: [0.0, -1.0, 0.0],
+                sun_color: [0.0; 3],
+            },
+        };
+        let portal_scenes = (0..portal_view_count)
+            .map(|index| {
+                self.geometry_system.draw_portal_view(
+                    index,
+                    [render_extent[0], render_extent[1]],
+                    &in_flight,
+                    portal_lighting,
+                )
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("drawing portal views")?;
+        // Open sky through a portal is the ambient light, without the environment behind it
+        let acquire_future =
+            match self
+                .portals
+                .record(&in_flight, render_extent, portal_scenes, ambient)?
+            {
+                Some(cb) => acquire_future
+                    .then_execute(self.context.graphics_queue().clone(), cb)
+                    .context("executing portal views")?
+                    .boxed(),
+                None => acquire_future,
+            };
+        let acquire_future = match probe_capture {
+            Some(capture) => {
+                let scenes = (0..CUBE_FACES)
+                    .map(|face| {
+                        self.geometry_system.draw_capture_view(
+                            face,
+                            self.reflection_probes.face_extent(),
+                            &in_flight,
+                            portal_lighting,
+                        )
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()
+                    .context("drawing reflection probe faces")?;
+                let cb = self.reflection_probes.record(
+                    &in_flight,
+                    &capture,
+                    scenes,
+                    ambient,
+                    &self.environment_baker,
+                )?;
+                acquire_future
+                    .then_execute(self.context.graphics_queue().clone(), cb)
+                    .context("executing reflection probe capture")?
+                    .boxed()
+            }
+            None => acquire_future,
+        };
+
+        self.frame_system
+            .debug_draw
+            .update(in_flight.index, view_projection)?;
+        self.frame_system.billboards.update(
+            in_flight.index,
+            self.geometry_system.unjittered_camera_matrices(),
+        )?;
+        self.frame_system
+            .sprites
+            .update(self.geometry_system.unjittered_camera_matrices());
+        self.frame_system.hud.update(
+            in_flight.index,
+            renderer.swapchain_image_size(),
+            &HudStats {
+                frame: &frame_stats,
+                scene: &scene_stats,
+                entities: self.hud_entities,
+            },
+        )?;
+
         let mut frame = self.frame_system.frame(
             acquire_future,
             renderer.swapchain_image_view().clone(),
-            Matrix4::identity(),
+            self.geometry_system.camera_matrices(),
+            &point_lights,
+            in_flight.clone(),
         )?;
+        if self.retain_scene {
+            self.point_lights = point_lights;
+        }
 
         let mut after_future: Option<Box<dyn GpuFuture>> = None;
+        let mut pass_times = PassTimes {
+            shadows: shadow_time,
+            ..Default::default()
+        };
+        pass_start = Instant::now();
 
         while let Some(pass) = frame.next_pass()? {
             match pass {
+                Pass::DepthPrepass(mut draw_pass) => {
+                    let cb = self
+                        .geometry_system
+                        .draw_depth(draw_pass.viewport_dimensions(), draw_pass.in_flight())
+                        .context("drawing depth pre-pass")?;
+                    draw_pass.execute(cb)?;
+                    pass_times.depth_prepass += lap(&mut pass_start);
+                }
                 Pass::Deferred(mut draw_pass) => {
+                    let overlays = self
+                        .geometry_system
+                        .draw_overlays(draw_pass.viewport_dimensions(), draw_pass.in_flight())
+                        .context("drawing overlay cameras")?;
                     let cb = self
                         .geometry_system
-                        .draw(draw_pass.viewport_dimensions())
+                        .draw(draw_pass.viewport_dimensions(), draw_pass.in_flight())
                         .context("drawing geometry")?;
                     draw_pass.execute(cb)?;
+                    let foliage = self
+                        .foliage
+                        .draw(
+                            draw_pass.viewport_dimensions(),
+                            draw_pass.in_flight(),
+                            self.geometry_system.camera_matrices(),
+                            self.geometry_system.unjittered_camera_matrices(),
+                        )
+                        .context("drawing foliage")?;
+                    if let Some(cb) = foliage {
+                        draw_pass.execute(cb)?;
+                    }
+                    let portals = self
+                        .portals
+                        .draw(
+                            draw_pass.viewport_dimensions(),
+                            draw_pass.in_flight(),
+                            self.geometry_system.camera_matrices(),
+                        )
+                        .context("drawing portals")?;
+                    if let Some(cb) = portals {
+                        draw_pass.execute(cb)?;
+                    }
+                    draw_pass.execute_user_passes()?;
+                    // Last, so overlays that don't clear depth still sort against the foliage
+                    if let Some(cb) = overlays {
+                        draw_pass.execute(cb)?;
+                    }
+                    pass_times.geometry += lap(&mut pass_start);
                 }
                 Pass::Lighting(lighting) => {
-                    Self::render_lighting(lighting)?;
+                    Self::render_lighting(
+                        lighting,
+                        self.ambient_light,
+                        self.directional_light.as_ref(),
+                        self.sky.as_ref(),
+                    )?;
+                    pass_times.lighting += lap(&mut pass_start);
                 }
                 Pass::Finished(af) => {
                     after_future = Some(af);
+                    pass_times.post_process += lap(&mut pass_start);
                 }
             }
         }
-        renderer.present(
-            after_future.context("getting renderpass finish future")?,
-            true,
-        );
+        let mut after_future = after_future.context("getting renderpass finish future")?;
+        self.pass_times = pass_times;
+        self.geometry_system.end_frame();
+        if !self.retain_scene {
+            self.geometry_system.clear_objects();
+        }
+
+        if let Some(cb) = self.occlusion_culler.record(
+            &in_flight,
+            self.frame_system.depth_buffer.image().clone(),
+            view_projection,
+        )? {
+            after_future = Box::new(
+                after_future
+                    .then_execute(self.context.graphics_queue().clone(), cb)
+                    .context("executing depth pyramid build")?,
+            );
+        }
+
+        let (projection, view) = self.geometry_system.camera_matrices();
+        let swapchain_image_view = renderer.swapchain_image_view();
+        // Drawn over the upscaled image, so at the swapchain's size rather than the scene's
+        let swapchain_extent = swapchain_image_view.image().extent();
+        let user_passes = self.frame_system.user_passes.record(PassContext {
+            point: PassPoint::AfterFrame,
+            queue: &self.frame_system.gfx_queue,
+            command_buffer_allocator: &in_flight.command_buffer_allocator,
+            frame_index: in_flight.index,
+            subpass: None,
+            extent: [swapchain_extent[0], swapchain_extent[1]],
+            view_projection: projection * view,
+            depth: &self.frame_system.depth_buffer,
+            target: Some(&swapchain_image_view),
+        })?;
+        for cb in user_passes {
+            after_future = Box::new(
+                after_future
+                    .then_execute(self.context.graphics_queue().clone(), cb)
+                    .context("executing user pass")?,
+            );
+        }
+
+        if self.analysis_requested && !self.analysis.is_pending() {
+            let cb = self.analysis.record(
+                &in_flight,
+                self.frame_system.hdr_buffer.image().clone(),
+                self.frame_system.depth_buffer.image().clone(),
+            )?;
+            after_future = Box::new(
+                after_future
+                    .then_execute(self.context.graphics_queue().clone(), cb)
+                    .context("executing frame analysis")?,
+            );
+            self.analysis_requested = false;
+        }
+
+        if let Some(cb) = self
+            .readbacks
+            .record(&in_flight, renderer.swapchain_image_view().image())?
+        {
+            after_future = Box::new(
+                after_future
+                    .then_execute(self.context.graphics_queue().clone(), cb)
+                    .context("executing readbacks")?,
+            );
+        }
+
+        let after_future = self.frames_in_flight.end_frame(after_future)?;
+
+        // The frame's fence is waited on when its slot comes around again, so there's no need to
+        // block here unless the next frame should start as late as possible. That only waits for
+        // the GPU to finish the frame, not for the image to reach the display
+        renderer.present(after_future, self.low_latency);
+        self.capture.end_frame();
 
         Ok(())
     }
 
-    pub fn create_mesh(
+    /// Captures the next frame with RenderDoc. Needs the `renderdoc` feature and RenderDoc
+    /// attached to the process, the request is logged and dropped otherwise.
+    pub fn capture_frame(&mut self) {
+        self.capture.request();
+    }
+
+    /// Whether `capture_frame` can do anything.
+    pub fn frame_capture_available(&self) -> bool {
+        self.capture.available()
+    }
+
+    /// Runs the frame analysis tools on the next rendered frame. The report becomes available
+    /// from `take_analysis_report` once the GPU has finished with that frame.
+    pub fn request_analysis(&mut self) {
+        self.analysis_requested = true;
+    }
+
+    pub fn take_analysis_report(&mut self) -> Option<AnalysisReport> {
+        self.analysis_report.take()
+    }
+
+    /// Picks the object drawn at `position`, in window pixels, in the next frame. The result
+    /// becomes available from `take_pick` once the GPU has finished with that frame. Only the
+    /// last pick asked for before a frame is drawn.
+    pub fn request_pick(&mut self, position: [f32; 2]) {
+        let Some(size) = self.window_size() else {
+            return;
+        };
+        let position = [position[0].max(0.0) as u32, position[1].max(0.0) as u32];
+        self.picker.request(position, [size.width, size.height]);
+    }
+
+    pub fn take_pick(&mut self) -> Option<PickResult> {
+        self.picker.take_result()
+    }
+
+    /// Copies `buffer` back to the host after the next frame, e.g. to look at what a compute pass
+    /// wrote. The buffer needs `TRANSFER_SRC` usage.
+    pub fn read_buffer<T: ?Sized>(&mut self, buffer: &Subbuffer<T>) -> ReadbackFuture {
+        self.readbacks.read_buffer(buffer.as_bytes().clone())
+    }
+
+    /// Copies mip 0 of `image` back to the host after the next frame. The image needs
+    /// `TRANSFER_SRC` usage and an uncompressed color or depth format.
+    pub fn read_image(&mut self, image: Arc<Image>) -> ReadbackFuture {
+        self.readbacks.read_image(image)
+    }
+
+    /// Copies the next frame back to the host as it's presented, for screenshots. See
+    /// `ReadbackData::to_rgba8`.
+    pub fn read_frame(&mut self) -> ReadbackFuture {
+        self.readbacks.read_frame()
+    }
+
+    /// Records `pass` at `point` of every frame from the next one on, for rendering the engine
+    /// doesn't know about without forking it. Passes at the same point run in the order they
+    /// were added.
+    pub fn add_user_pass(&mut self, point: PassPoint, pass: impl UserPass + 'static) -> UserPassId {
+        self.frame_system.add_user_pass(point, pass)
+    }
+
+    /// Stops recording a pass, handing it back.
+    pub fn remove_user_pass(&mut self, id: UserPassId) -> Option<Box<dyn UserPass>> {
+        self.frame_system.remove_user_pass(id)
+    }
+
+    pub fn create_mesh<V: MeshVertex>(
         &mut self,
-        verts: Vec<VertexPositionColorNormal>,
-        indices: Vec<u16>,
+        verts: Vec<V>,
+        indices: impl Into<Indices>,
     ) -> anyhow::Result<usize> {
         self.geometry_system.create_mesh(verts, indices)
     }
 
-    fn render_lighting(mut lighting: LightingPass<'_, '_>) -> anyhow::Result<()> {
-        lighting.ambient_light([0.1, 0.1, 0.1])?;
-        lighting.directional_light(Vector3::new(0.2, -0.1, -0.7), [0.6, 0.0, 0.0])?;
-        lighting.point_light(Vector3::new(0.5, -0.5, -0.1), [1.0, 0.0, 0.0])?;
-        lighting.point_light(Vector3::new(-0.9, 0.2, -0.15), [0.0, 1.0, 0.0])?;
-        lighting.point_light(Vector3::new(0.0, 0.5, -0.05), [0.0, 0.0, 1.0])?;
+    /// Streams a mesh to the GPU over the next frames, copying at most
+    /// `RendererConfig::upload_budget` bytes a frame, for meshes too big to upload at once
+    /// without a hitch. The mesh gets its id once it's on the GPU, see `mesh_upload_status`.
+    pub fn upload_mesh<V: MeshVertex>(
+        &mut self,
+        verts: Vec<V>,
+        indices: impl Into<Indices>,
+    ) -> anyhow::Result<MeshUploadHandle> {
+        self.geometry_system.prepare_layout::<V>()?;
+        self.uploader.stage(verts, indices)
+    }
+
+    pub fn mesh_upload_status(&self, handle: MeshUploadHandle) -> Option<UploadStatus> {
+        self.uploader.status(handle)
+    }
+
+    /// Uploads a batch of merged static meshes as a single mesh, returning its mesh id.
+    pub fn create_static_batch(&mut self, batch: StaticBatch) -> anyhow::Result<usize> {
+        let (verts, indices) = batch.into_parts();
+        self.geometry_system
+            .create_mesh(verts, indices)
+            .context("creating static batch mesh")
+    }
+
+    /// Loads a PNG, JPEG, KTX2 or DDS texture, blocking until it's on the GPU.
+    pub fn load_texture(
+        &mut self,
+        path: &Path,
+        usage: TextureUsage,
+    ) -> anyhow::Result<TextureHandle> {
+        self.textures.load(path, usage)
+    }
+
+    pub fn texture_view(&self, handle: TextureHandle) -> Option<&Arc<ImageView>> {
+        self.textures.view(handle)
+    }
+
+    pub fn texture_sampler(&self) -> &Arc<Sampler> {
+        self.textures.sampler()
+    }
+
+    /// Loads a lightmap for `Material::lightmap`. Lightmaps hold linear light, so KTX2 and DDS
+    /// files are best stored as 16 or 32 bit floats to keep their range, 8 bit files are read as
+    /// linear 0 to 1.
+    pub fn load_lightmap(&mut self, path: &Path) -> anyhow::Result<TextureHandle> {
+        self.textures
+            .load(path, TextureUsage::Color { srgb: false })
+            .with_context(|| format!("loading lightmap {:?}", path))
+    }
+
+    /// Lights the scene with an equirectangular environment texture, which should be loaded as
+    /// an sRGB color texture. Blocks while the image-based lighting maps are baked.
+    pub fn set_environment(&mut self, texture: TextureHandle) -> anyhow::Result<()> {
+        let view = self
+            .textures
+            .view(texture)
+            .context("getting environment texture")?
+            .clone();
+        self.frame_system.environment = self
+            .environment_baker
+            .bake(view, self.textures.sampler().clone())
+            .context("baking environment maps")?;
         Ok(())
     }
+
+    /// Creates a material for `enqueue_mesh_with_material`. Missing textures are replaced with
+    /// white, leaving only the factors.
+    pub fn create_material(&mut self, material: &Material) -> anyhow::Result<MaterialId> {
+        let view = |texture: Option<TextureHandle>| {
+            self.textures
+                .view(texture.unwrap_or(self.textures.white()))
+                .cloned()
+                .context("getting material texture")
+        };
+        let base_color = view(material.base_color_texture)?;
+        let metallic_roughness = view(material.metallic_roughness_texture)?;
+        let emissive = view(material.emissive_texture)?;
+        let lightmap = view(material.lightmap)?;
+
+        let id = self.geometry_system.create_material(
+            material,
+            base_color,
+            metallic_roughness,
+            emissive,
+            lightmap,
+            self.textures.sampler().clone(),
+        )?;
+        self.materials.push((id, material.clone()));
+        Ok(id)
+    }
+
+    pub fn material(&self, id: MaterialId) -> Option<&Material> {
+        self.materials
+            .iter()
+            .find(|(material, _)| *material == id)
+            .map(|(_, material)| material)
+    }
+
+    /// Every material created so far, in creation order.
+    pub fn materials(&self) -> impl Iterator<Item = (MaterialId, &Material)> {
+        self.materials.iter().map(|(id, material)| (*id, material))
+    }
+
+    /// Rebuilds the material `id` as `material`, e.g. after its file changed or it was edited.
+    /// Objects drawn with it pick up the change on the next frame.
+    pub fn update_material(&mut self, id: MaterialId, material: &Material) -> anyhow::Result<()> {
+        let index = self
+            .materials
+            .iter()
+            .position(|(material, _)| *material == id)
+            .context("material doesn't exist")?;
+        let view = |texture: Option<TextureHandle>| {
+            self.textures
+                .view(texture.unwrap_or(self.textures.white()))
+                .cloned()
+                .context("getting material texture")
+        };
+        let old = self.geometry_system.replace_material(
+            id,
+            material,
+            view(material.base_color_texture)?,
+            view(material.metallic_roughness_texture)?,
+            view(material.emissive_texture)?,
+            view(material.lightmap)?,
+            self.textures.sampler().clone(),
+        )?;
+        self.frames_in_flight.retire(old);
+        self.materials[index].1 = material.clone();
+        Ok(())
+    }
+
+    /// Loads `path` again in place of `texture`, rebuilding the materials that use it. The old
+    /// texture is kept until the frames in flight are done with it.
+    pub fn reload_texture(
+        &mut self,
+        texture: TextureHandle,
+        path: &Path,
+        usage: TextureUsage,
+    ) -> anyhow::Result<()> {
+        let old = self.textures.reload(texture, path, usage)?;
+        self.frames_in_flight.retire(old);
+
+        for (id, material) in &self.materials {
+            let uses_texture = [
+                material.base_color_texture,
+                material.metallic_roughness_texture,
+                material.emissive_texture,
+                material.lightmap,
+            ]
+            .contains(&Some(texture));
+            if !uses_texture {
+                continue;
+            }
+            let view = |texture: Option<TextureHandle>| {
+                self.textures
+                    .view(texture.unwrap_or(self.textures.white()))
+                    .cloned()
+                    .context("getting material texture")
+            };
+            let old = self.geometry_system.replace_material(
+                *id,
+                material,
+                view(material.base_color_texture)?,
+                view(material.metallic_roughness_texture)?,
+                view(material.emissive_texture)?,
+                view(material.lightmap)?,
+                self.textures.sampler().clone(),
+            )?;
+            self.frames_in_flight.retire(old);
+        }
+        Ok(())
+    }
+
+    /// Replaces the vertices and indices of `mesh_id`. The old buffers are kept until the frames
+    /// in flight are done with them.
+    pub fn reload_mesh<V: MeshVertex>(
+        &mut self,
+        mesh_id: usize,
+        verts: Vec<V>,
+        indices: impl Into<Indices>,
+    ) -> anyhow::Result<()> {
+        let old = self.geometry_system.replace_mesh(mesh_id, verts, indices)?;
+        self.frames_in_flight.retire(old);
+        Ok(())
+    }
+
+    fn render_lighting(
+        mut lighting: LightingPass<'_, '_>,
+        ambient_light: [f32; 3],
+        directional_light: Option<&DirectionalLight>,
+        sky: Option<&SkyGradient>,
+    ) -> anyhow::Result<()> {
+        profile_scope!("lighting");
+        lighting.ambient_light(ambient_light)?;
+        if let Some(sky) = sky {
+            lighting.sky(sky)?;
+        }
+        if let Some(light) = directional_light {
+            lighting.directional_light(light)?;
+        }
+        lighting.point_lights()?;
+        Ok(())
+    }
+}
+
+/// Milliseconds since `start`, which is reset to now.
+fn lap(start: &mut Instant) -> f32 {
+    let now = Instant::now();
+    let elapsed = now.duration_since(*start).as_secs_f32() * 1000.0;
+    *start = now;
+    elapsed
 }
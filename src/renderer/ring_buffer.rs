@@ -0,0 +1,230 @@
+use std::{ops::Range, sync::Arc};
+
+use anyhow::{bail, Context};
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferMemory, BufferUsage, Subbuffer},
+    device::DeviceOwned,
+    memory::{
+        allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+        MappedMemoryRange,
+    },
+    DeviceSize,
+};
+
+/// A persistent ring buffer's mapping of the whole buffer.
+struct Mapping {
+    ptr: *mut u8,
+    /// The memory's non-coherent atom size, `None` when it's host coherent and never needs
+    /// flushing.
+    atom_size: Option<DeviceSize>,
+    /// What's been pushed since the last flush.
+    dirty: Option<Range<DeviceSize>>,
+}
+
+// The pointer is into memory the ring buffer's own buffer keeps alive and mapped
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+/// A host-writable buffer split into one region per frame in flight.
+///
+/// Per-frame data is pushed into the region of the frame being recorded and addressed through
+/// dynamic offsets, so descriptor sets pointing at the buffer can be created once and reused
+/// every frame.
+pub struct RingBuffer {
+    buffer: Subbuffer<[u8]>,
+    region_size: DeviceSize,
+    alignment: DeviceSize,
+    region_start: DeviceSize,
+    head: DeviceSize,
+    /// Set for persistent ring buffers.
+    mapping: Option<Mapping>,
+}
+
+impl RingBuffer {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        usage: BufferUsage,
+        region_size: DeviceSize,
+        regions: usize,
+    ) -> anyhow::Result<Self> {
+        let properties = memory_allocator.device().physical_device().properties();
+        let alignment = if usage.intersects(BufferUsage::UNIFORM_BUFFER) {
+            properties.min_uniform_buffer_offset_alignment
+        } else {
+            properties.min_storage_buffer_offset_alignment
+        }
+        .as_devicesize();
+
+        let region_size = region_size.max(1).next_multiple_of(alignment);
+
+        let buffer = Buffer::new_slice::<u8>(
+            memory_allocator,
+            BufferCreateInfo {
+                usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            region_size * regions as DeviceSize,
+        )
+        .context("creating ring buffer")?;
+
+        Ok(RingBuffer {
+            buffer,
+            region_size,
+            alignment,
+            region_start: 0,
+            head: 0,
+            mapping: None,
+        })
+    }
+
+    /// Like `new`, but pushes are copied straight through a mapping of the whole buffer kept for
+    /// its lifetime, instead of mapping and flushing the range of every push. Meant for data
+    /// pushed many times a frame. Pushes only reach the GPU once `flush` is called.
+    pub fn persistent(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        usage: BufferUsage,
+        region_size: DeviceSize,
+        regions: usize,
+    ) -> anyhow::Result<Self> {
+        let mut ring = Self::new(memory_allocator, usage, region_size, regions)?;
+        let ptr = ring
+            .buffer
+            .mapped_slice()
+            .context("mapping persistent ring buffer")?;
+        let BufferMemory::Normal(memory) = ring.buffer.buffer().memory() else {
+            bail!("persistent ring buffer isn't bound to memory");
+        };
+        ring.mapping = Some(Mapping {
+            ptr: ptr.as_ptr() as *mut u8,
+            atom_size: memory.atom_size().map(|atom| atom.as_devicesize()),
+            dirty: None,
+        });
+        Ok(ring)
+    }
+
+    pub fn buffer(&self) -> &Subbuffer<[u8]> {
+        &self.buffer
+    }
+
+    /// Size in bytes of a single frame's region. Descriptors bound to the buffer should use this
+    /// as their range.
+    pub fn region_size(&self) -> DeviceSize {
+        self.region_size
+    }
+
+    /// Starts writing into the region owned by `frame_index`, overwriting what was pushed the last
+    /// time that frame slot was recorded.
+    pub fn begin_frame(&mut self, frame_index: usize) {
+        self.region_start = frame_index as DeviceSize * self.region_size;
+        self.head = 0;
+    }
+
+    /// Copies `data` into the current region and returns its offset from the start of the buffer,
+    /// suitable for use as a dynamic offset.
+    pub fn push<T>(&mut self, data: &[T]) -> anyhow::Result<u32>
+    where
+        T: BufferContents + Copy,
+    {
+        let offset = self.region_start + self.head;
+        let size = std::mem::size_of_val(data) as DeviceSize;
+
+        if size == 0 {
+            return Ok(offset as u32);
+        }
+
+        if self.head + size > self.region_size {
+            bail!(
+                "ring buffer region overflow: {} bytes requested, {} available",
+                size,
+                self.region_size - self.head
+            );
+        }
+
+        match &mut self.mapping {
+            Some(mapping) => {
+                // The region belongs to a frame slot the GPU is done with, and the range was
+                // checked to be inside it above
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        data.as_ptr() as *const u8,
+                        mapping.ptr.add(offset as usize),
+                        size as usize,
+                    );
+                }
+                mapping.dirty = Some(match mapping.dirty.take() {
+                    Some(dirty) => dirty.start.min(offset)..dirty.end.max(offset + size),
+                    None => offset..offset + size,
+                });
+            }
+            None => self
+                .buffer
+                .clone()
+                .slice(offset..offset + size)
+                .reinterpret::<[T]>()
+                .write()
+                .context("writing ring buffer region")?
+                .copy_from_slice(data),
+        }
+
+        self.head = (self.head + size).next_multiple_of(self.alignment);
+
+        Ok(offset as u32)
+    }
+
+    /// Makes a persistent ring buffer's pushes since the last flush visible to the GPU, with a
+    /// single flush of the range they span. Must be called before submitting work that reads
+    /// them. Does nothing for other ring buffers, which flush every push, or when the memory is
+    /// host coherent.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        let Some(mapping) = &mut self.mapping else {
+            return Ok(());
+        };
+        let (Some(dirty), Some(atom_size)) = (mapping.dirty.take(), mapping.atom_size) else {
+            return Ok(());
+        };
+        let BufferMemory::Normal(memory) = self.buffer.buffer().memory() else {
+            return Ok(());
+        };
+
+        // Flushed ranges must be whole atoms, or run to the end of the memory
+        let start = dirty.start - dirty.start % atom_size;
+        let end = dirty
+            .end
+            .next_multiple_of(atom_size)
+            .min(self.buffer.size());
+        unsafe {
+            memory.flush_range(MappedMemoryRange {
+                offset: start,
+                size: end - start,
+                ..Default::default()
+            })
+        }
+        .context("flushing ring buffer")?;
+        Ok(())
+    }
+
+    /// Like `push`, but returns the pushed range as a subbuffer, for data that's consumed directly
+    /// or bound without a dynamic offset. `data` must not be empty.
+    pub fn push_slice<T>(&mut self, data: &[T]) -> anyhow::Result<Subbuffer<[T]>>
+    where
+        T: BufferContents + Copy,
+    {
+        if data.is_empty() {
+            bail!("can't push an empty slice into a ring buffer");
+        }
+
+        let offset = self.push(data)? as DeviceSize;
+        let size = std::mem::size_of_val(data) as DeviceSize;
+
+        Ok(self
+            .buffer
+            .clone()
+            .slice(offset..offset + size)
+            .reinterpret::<[T]>())
+    }
+}
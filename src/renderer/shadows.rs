@@ -0,0 +1,455 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{ortho, EuclideanSpace, InnerSpace, Matrix4, Point3, Vector2, Vector3, Vector4, Zero};
+use vulkano::{
+    buffer::{BufferContents, BufferUsage, Subbuffer},
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        RecordingCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents,
+    },
+    device::Queue,
+    format::Format,
+    image::{
+        sampler::{BorderColor, Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        Image, ImageAspects, ImageCreateInfo, ImageSubresourceRange, ImageType, ImageUsage,
+    },
+    memory::allocator::AllocationCreateInfo,
+    pipeline::graphics::depth_stencil::CompareOp,
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    DeviceSize,
+};
+
+use crate::game::frustum_corners;
+
+use super::{
+    frames_in_flight::InFlightFrame,
+    memory::{MemoryCategory, TrackedMemory},
+    ring_buffer::RingBuffer,
+    vulkan_context::VulkanContext,
+};
+
+/// Layers of the shadow map, the most cascades `ShadowSettings::cascades` can ask for.
+pub const MAX_CASCADES: usize = 4;
+pub(crate) const SHADOW_MAP_SIZE: u32 = 2048;
+const SHADOW_MAP_FORMAT: Format = Format::D32_SFLOAT;
+/// How far behind each cascade, towards the light, casters are still drawn into it.
+const CASTER_DISTANCE: f32 = 50.0;
+
+/// How the directional light's shadow cascades are laid out and sampled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Cascades the view is split into, from 1 to 4.
+    pub cascades: usize,
+    /// Distance from the camera past which nothing is shadowed, capped by the camera's far plane.
+    pub distance: f32,
+    /// Blends between evenly spaced splits at 0 and logarithmic ones at 1, which give the
+    /// cascades close to the camera more of the shadow map.
+    pub split_lambda: f32,
+    /// Fraction of each cascade, at its far end, blended into the next to hide the seam.
+    pub blend: f32,
+    /// Subtracted from the depth of each lookup, scaled up on surfaces facing away from the
+    /// light, to keep surfaces from shadowing themselves.
+    pub depth_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            cascades: MAX_CASCADES,
+            distance: 100.0,
+            split_lambda: 0.75,
+            blend: 0.1,
+            depth_bias: 0.0005,
+        }
+    }
+}
+
+/// What the directional lighting shader reads to find a pixel's cascade and sample it.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct ShadowParams {
+    /// The camera's view, for the view depth the splits are compared against.
+    view: [[f32; 4]; 4],
+    /// World to shadow map, with x and y in -1..1 and depth in 0..1.
+    cascades: [[[f32; 4]; 4]; MAX_CASCADES],
+    /// View depth at which each cascade ends.
+    splits: [f32; 4],
+    /// Cascade count (zero without shadows), blend fraction, depth bias, and non-zero to tint
+    /// the lit scene by cascade.
+    settings: [f32; 4],
+}
+
+/// Shadows of the directional light, drawn from the light into one shadow map layer per cascade
+/// before the frame. Each cascade covers a slice of the camera's view, nearer slices getting more
+/// shadow map texels per meter.
+///
+/// Cascades are fit around a bounding sphere of their slice and moved in whole shadow map texels,
+/// so their shadows don't shimmer as the camera turns or moves.
+pub struct CascadedShadows {
+    gfx_queue: Arc<Queue>,
+    render_pass: Arc<RenderPass>,
+    /// Every layer, sampled by the directional light.
+    map: Arc<ImageView>,
+    /// One per layer, drawn into by the cascades.
+    framebuffers: Vec<Arc<Framebuffer>>,
+    sampler: Arc<Sampler>,
+    params_ring: RingBuffer,
+    params: Option<Subbuffer<ShadowParams>>,
+    _memory: TrackedMemory,
+    settings: ShadowSettings,
+    enabled: bool,
+    debug_cascades: bool,
+    /// This frame's cascades, empty while disabled.
+    cascades: Vec<Matrix4<f32>>,
+    /// The map's layers start out undefined, so it's cleared once even while disabled.
+    cleared: bool,
+}
+
+impl CascadedShadows {
+    pub fn new(context: &VulkanContext, frames_in_flight: usize) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let render_pass = vulkano::single_pass_renderpass!(
+            device.clone(),
+            attachments: {
+                depth: {
+                    format: SHADOW_MAP_FORMAT,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+            },
+            pass: {
+                color: [],
+                depth_stencil: {depth},
+            },
+        )
+        .context("creating shadow render pass")?;
+
+        let image = Image::new(
+            context.memory_allocator().clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: SHADOW_MAP_FORMAT,
+                extent: [SHADOW_MAP_SIZE, SHADOW_MAP_SIZE, 1],
+                array_layers: MAX_CASCADES as u32,
+                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating shadow map")?;
+
+        let map = ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2dArray,
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .context("creating shadow map view")?;
+
+        let framebuffers = (0..MAX_CASCADES as u32)
+            .map(|layer| {
+                let view = ImageView::new(
+                    image.clone(),
+                    ImageViewCreateInfo {
+                        view_type: ImageViewType::Dim2d,
+                        subresource_range: ImageSubresourceRange {
+                            aspects: ImageAspects::DEPTH,
+                            mip_levels: 0..1,
+                            array_layers: layer..layer + 1,
+                        },
+                        ..ImageViewCreateInfo::from_image(&image)
+                    },
+                )
+                .context("creating shadow cascade view")?;
+                Framebuffer::new(
+                    render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: vec![view],
+                        ..Default::default()
+                    },
+                )
+                .context("creating shadow cascade framebuffer")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Compares against the map while filtering, for smoothed shadow edges. Lookups outside
+        // the map are lit
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToBorder; 3],
+                border_color: BorderColor::FloatOpaqueWhite,
+                compare: Some(CompareOp::LessOrEqual),
+                ..Default::default()
+            },
+        )
+        .context("creating shadow sampler")?;
+
+        let params_ring = RingBuffer::new(
+            context.memory_allocator().clone(),
+            BufferUsage::UNIFORM_BUFFER,
+            std::mem::size_of::<ShadowParams>() as DeviceSize,
+            frames_in_flight,
+        )
+        .context("creating shadow params ring buffer")?;
+
+        let debug_namer = context.debug_namer();
+        debug_namer.name(render_pass.as_ref(), "shadow render pass");
+        debug_namer.name(image.as_ref(), "shadow map");
+
+        Ok(CascadedShadows {
+            gfx_queue: context.graphics_queue().clone(),
+            render_pass,
+            map,
+            framebuffers,
+            sampler,
+            params_ring,
+            params: None,
+            _memory: context
+                .memory_tracker()
+                .track_image(MemoryCategory::RenderTargets, &image),
+            settings: ShadowSettings::default(),
+            enabled: true,
+            debug_cascades: false,
+            cascades: vec![],
+            cleared: false,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn settings(&self) -> ShadowSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: ShadowSettings) {
+        self.settings = settings;
+    }
+
+    /// Tints the lit scene by the cascade each pixel was shadowed from.
+    pub fn set_debug_cascades(&mut self, enabled: bool) {
+        self.debug_cascades = enabled;
+    }
+
+    /// The subpass shadow casters are drawn in.
+    pub fn subpass(&self) -> Subpass {
+        Subpass::from(self.render_pass.clone(), 0).expect("shadow render pass has a subpass")
+    }
+
+    /// Fits this frame's cascades to the camera, given as unjittered projection and view
+    /// matrices, and a light shining along `direction`. Returns the world to shadow map matrix
+    /// of each cascade to draw, none while disabled.
+    pub fn update(
+        &mut self,
+        frame: &InFlightFrame,
+        camera: (Matrix4<f32>, Matrix4<f32>),
+        direction: Vector3<f32>,
+    ) -> anyhow::Result<&[Matrix4<f32>]> {
+        let (projection, view) = camera;
+        self.cascades.clear();
+        let mut splits = [0.0; 4];
+        if self.enabled && direction.magnitude2() > 0.0 {
+            let direction = direction.normalize();
+            for (index, (near, far)) in self.split_distances(projection).into_iter().enumerate() {
+                self.cascades
+                    .push(fit_cascade(projection, view, near, far, direction));
+                splits[index] = far;
+            }
+        }
+
+        let mut cascades = [[[0.0; 4]; 4]; MAX_CASCADES];
+        for (matrix, cascade) in cascades.iter_mut().zip(&self.cascades) {
+            *matrix = (*cascade).into();
+        }
+        self.params_ring.begin_frame(frame.index);
+        self.params = Some(
+            self.params_ring
+                .push_slice(&[ShadowParams {
+                    view: view.into(),
+                    cascades,
+                    splits,
+                    settings: [
+                        self.cascades.len() as f32,
+                        self.settings.blend.clamp(0.0, 1.0),
+                        self.settings.depth_bias,
+                        self.debug_cascades as f32,
+                    ],
+                }])?
+                .index(0),
+        );
+
+        Ok(&self.cascades)
+    }
+
+    /// Wraps the casters drawn for each cascade `update` returned, in `subpass`, in a command
+    /// buffer that clears and fills the shadow map. `None` when there's nothing to do.
+    pub fn record(
+        &mut self,
+        frame: &InFlightFrame,
+        casters: Vec<Arc<CommandBuffer>>,
+    ) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        if casters.is_empty() && self.cleared {
+            return Ok(None);
+        }
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating shadow command buffer")?;
+
+        // Without casters every layer is cleared, so none are left undefined
+        let layers = if casters.is_empty() {
+            self.framebuffers.len()
+        } else {
+            casters.len()
+        };
+        let mut casters = casters.into_iter();
+        for framebuffer in &self.framebuffers[..layers] {
+            builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: vec![Some(1.0f32.into())],
+                        ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+                    },
+                    SubpassBeginInfo {
+                        contents: SubpassContents::SecondaryCommandBuffers,
+                        ..Default::default()
+                    },
+                )
+                .context("beginning shadow render pass")?;
+            if let Some(casters) = casters.next() {
+                builder
+                    .execute_commands(casters)
+                    .context("executing shadow casters")?;
+            }
+            builder
+                .end_render_pass(Default::default())
+                .context("ending shadow render pass")?;
+        }
+        self.cleared = true;
+
+        Ok(Some(
+            builder.end().context("building shadow command buffer")?,
+        ))
+    }
+
+    pub(crate) fn map(&self) -> &Arc<ImageView> {
+        &self.map
+    }
+
+    pub(crate) fn sampler(&self) -> &Arc<Sampler> {
+        &self.sampler
+    }
+
+    /// The params pushed by this frame's `update`.
+    pub(crate) fn params(&self) -> Option<&Subbuffer<ShadowParams>> {
+        self.params.as_ref()
+    }
+
+    /// The view distances each cascade covers, blending between logarithmic and even splits.
+    fn split_distances(&self, projection: Matrix4<f32>) -> Vec<(f32, f32)> {
+        let (near, far) = clip_planes(projection);
+        let far = far.min(self.settings.distance).max(near);
+        let count = self.settings.cascades.clamp(1, MAX_CASCADES);
+        let lambda = self.settings.split_lambda.clamp(0.0, 1.0);
+
+        let mut start = near;
+        (1..=count)
+            .map(|index| {
+                let fraction = index as f32 / count as f32;
+                let logarithmic = near * (far / near).powf(fraction);
+                let uniform = near + (far - near) * fraction;
+                let end = lambda * logarithmic + (1.0 - lambda) * uniform;
+                let split = (start, end);
+                start = end;
+                split
+            })
+            .collect()
+    }
+}
+
+/// The near and far planes of a cgmath perspective projection.
+fn clip_planes(projection: Matrix4<f32>) -> (f32, f32) {
+    let (a, b) = (projection[2][2], projection[3][2]);
+    (b / (a - 1.0), b / (a + 1.0))
+}
+
+/// A world to shadow map matrix covering the camera's view from `near` to `far`, looking along
+/// `direction`.
+fn fit_cascade(
+    projection: Matrix4<f32>,
+    view: Matrix4<f32>,
+    near: f32,
+    far: f32,
+    direction: Vector3<f32>,
+) -> Matrix4<f32> {
+    // Each corner of the slice lies on the edge of the view frustum between a near plane corner
+    // and the matching far plane corner, along which view depth changes linearly
+    let (camera_near, camera_far) = clip_planes(projection);
+    let corners = frustum_corners(projection * view);
+    let at = |depth: f32, corner: usize| {
+        let t = (depth - camera_near) / (camera_far - camera_near);
+        corners[corner] + (corners[corner + 4] - corners[corner]) * t
+    };
+    let slice: Vec<Vector3<f32>> = (0..4)
+        .flat_map(|corner| [at(near, corner), at(far, corner)])
+        .collect();
+
+    // A sphere keeps the cascade the same size however the camera turns. Rounding the radius
+    // keeps float error from resizing it
+    let center = slice
+        .iter()
+        .fold(Vector3::zero(), |sum, corner| sum + *corner)
+        / 8.0;
+    let radius = slice
+        .iter()
+        .map(|corner| (*corner - center).magnitude())
+        .fold(0.0, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let eye = center - direction * (radius + CASTER_DISTANCE);
+    let light_view = Matrix4::look_at_rh(Point3::from_vec(eye), Point3::from_vec(center), up);
+    let light_projection = ortho(
+        -radius,
+        radius,
+        -radius,
+        radius,
+        0.0,
+        2.0 * radius + CASTER_DISTANCE,
+    );
+    // cgmath's clip space depth runs -1..1, Vulkan's 0..1
+    let vulkan_depth = Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.5))
+        * Matrix4::from_nonuniform_scale(1.0, 1.0, 0.5);
+    let view_projection = vulkan_depth * light_projection * light_view;
+
+    // Moving the cascade in whole texels keeps the world from sliding across them
+    let half_size = SHADOW_MAP_SIZE as f32 / 2.0;
+    let origin = view_projection * Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let origin = Vector2::new(origin.x, origin.y) * half_size;
+    let snap = Vector2::new(origin.x.round(), origin.y.round()) - origin;
+    Matrix4::from_translation((snap / half_size).extend(0.0)) * view_projection
+}
@@ -0,0 +1,321 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{Matrix4, SquareMatrix};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::layout::DescriptorType,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::ViewportState,
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vertex::VertexPositionNormalUv,
+    vulkan_context::VulkanContext,
+};
+
+const TEXTURE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::CombinedImageSampler);
+
+/// Refers to a mesh created with `Renderer::create_sprite_mesh`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpriteMeshId(usize);
+
+/// A mesh's buffers, kept alive by the frames in flight once replaced.
+pub(crate) struct SpriteMesh {
+    vertices: Subbuffer<[VertexPositionNormalUv]>,
+    indices: Subbuffer<[u32]>,
+}
+
+struct SpriteDraw {
+    mesh: SpriteMeshId,
+    texture: Arc<ImageView>,
+    model: Matrix4<f32>,
+}
+
+/// Textured meshes drawn unlit over the composited image, e.g. tilemaps, so they keep their
+/// texture's colors instead of being lit, tonemapped and bloomed. Like billboards they're hidden
+/// behind the scene by testing against its depth buffer in the fragment shader, and alpha
+/// blended in the order they were queued. Draws are queued every frame and dropped once drawn.
+pub struct Sprites {
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    meshes: Vec<SpriteMesh>,
+    queued: Vec<SpriteDraw>,
+    draws: Vec<SpriteDraw>,
+    texture_sampler: Arc<Sampler>,
+    depth_sampler: Arc<Sampler>,
+    view_projection: Matrix4<f32>,
+}
+
+impl Sprites {
+    pub fn new(
+        context: &VulkanContext,
+        subpass: Subpass,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let pipeline = {
+            let vs = vs::load(device.clone())
+                .context("loading sprite vertex shader")?
+                .entry_point("main")
+                .context("sprite vertex shader entry point not found")?;
+            let fs = fs::load(device.clone())
+                .context("loading sprite fragment shader")?
+                .entry_point("main")
+                .context("sprite fragment shader entry point not found")?;
+            validate_descriptor_bindings("Sprites", &[&fs], &[TEXTURE_BINDING, DEPTH_BINDING])?;
+
+            let vertex_input_state = VertexPositionNormalUv::per_vertex()
+                .definition(&vs.info().input_interface)
+                .context("vertex input state")?;
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .context("creating pipeline layout create info")?,
+            )
+            .context("creating pipeline layout")?;
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(vertex_input_state),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        subpass.num_color_attachments(),
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend::alpha()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(subpass.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .context("creating sprite pipeline")?
+        };
+        context
+            .debug_namer()
+            .name(pipeline.as_ref(), "sprite pipeline");
+
+        // Tilesets are usually pixel art, and filtering would bleed neighboring tiles in
+        let texture_sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .context("creating sprite texture sampler")?;
+        let depth_sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .context("creating sprite depth sampler")?;
+
+        Ok(Sprites {
+            pipeline,
+            memory_allocator: context.memory_allocator().clone(),
+            descriptor_set_cache,
+            meshes: vec![],
+            queued: vec![],
+            draws: vec![],
+            texture_sampler,
+            depth_sampler,
+            view_projection: Matrix4::identity(),
+        })
+    }
+
+    pub fn create_mesh(
+        &mut self,
+        vertices: Vec<VertexPositionNormalUv>,
+        indices: Vec<u32>,
+    ) -> anyhow::Result<SpriteMeshId> {
+        let mesh = self.upload(vertices, indices)?;
+        self.meshes.push(mesh);
+        Ok(SpriteMeshId(self.meshes.len() - 1))
+    }
+
+    /// Swaps the buffers of `id`, returning the old ones for the caller to keep alive until
+    /// the frames that use them have finished.
+    pub(crate) fn replace_mesh(
+        &mut self,
+        id: SpriteMeshId,
+        vertices: Vec<VertexPositionNormalUv>,
+        indices: Vec<u32>,
+    ) -> anyhow::Result<SpriteMesh> {
+        let mesh = self.upload(vertices, indices)?;
+        let current = self
+            .meshes
+            .get_mut(id.0)
+            .context("sprite mesh doesn't exist")?;
+        Ok(std::mem::replace(current, mesh))
+    }
+
+    fn upload(
+        &self,
+        vertices: Vec<VertexPositionNormalUv>,
+        indices: Vec<u32>,
+    ) -> anyhow::Result<SpriteMesh> {
+        let allocation = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+        let vertices = Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            allocation.clone(),
+            vertices,
+        )
+        .context("creating sprite vertex buffer")?;
+        let indices = Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::INDEX_BUFFER,
+                ..Default::default()
+            },
+            allocation,
+            indices,
+        )
+        .context("creating sprite index buffer")?;
+        Ok(SpriteMesh { vertices, indices })
+    }
+
+    /// Queues `mesh` for the next frame, textured with `texture` and placed by `model`.
+    pub fn push(&mut self, mesh: SpriteMeshId, texture: Arc<ImageView>, model: Matrix4<f32>) {
+        self.queued.push(SpriteDraw {
+            mesh,
+            texture,
+            model,
+        });
+    }
+
+    /// Takes the queued draws, seen through the unjittered `camera` projection and view
+    /// matrices. Must be called once per frame, before `record`.
+    pub fn update(&mut self, camera: (Matrix4<f32>, Matrix4<f32>)) {
+        let (projection, view) = camera;
+        self.view_projection = projection * view;
+        self.draws = std::mem::take(&mut self.queued);
+    }
+
+    /// Draws the meshes taken by `update`, hidden where they're behind `depth_buffer`. Must be
+    /// recorded inside the composite render pass, whose viewport is already set.
+    pub fn record(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        depth_buffer: &Arc<ImageView>,
+    ) -> anyhow::Result<()> {
+        if self.draws.is_empty() {
+            return Ok(());
+        }
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .context("binding sprite pipeline")?;
+
+        for draw in &self.draws {
+            let Some(mesh) = self.meshes.get(draw.mesh.0) else {
+                continue;
+            };
+            let descriptor_set = self.descriptor_set_cache.get_or_create(
+                &self.pipeline.layout().set_layouts()[0],
+                &[
+                    CachedWrite::ImageViewSampler(
+                        TEXTURE_BINDING.binding,
+                        draw.texture.clone(),
+                        self.texture_sampler.clone(),
+                    ),
+                    CachedWrite::ImageViewSampler(
+                        DEPTH_BINDING.binding,
+                        depth_buffer.clone(),
+                        self.depth_sampler.clone(),
+                    ),
+                ],
+            )?;
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.pipeline.layout().clone(),
+                    0,
+                    descriptor_set,
+                )
+                .context("binding sprite descriptor set")?
+                .push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    vs::PushConstants {
+                        model_view_projection: (self.view_projection * draw.model).into(),
+                    },
+                )
+                .context("pushing sprite constants")?
+                .bind_vertex_buffers(0, mesh.vertices.clone())
+                .context("binding sprite vertices")?
+                .bind_index_buffer(mesh.indices.clone())
+                .context("binding sprite indices")?;
+            unsafe { builder.draw_indexed(mesh.indices.len() as u32, 1, 0, 0, 0) }
+                .context("drawing sprite mesh")?;
+        }
+
+        Ok(())
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "assets/shaders/sprite/sprite.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "assets/shaders/sprite/sprite.frag"
+    }
+}
@@ -0,0 +1,352 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{Matrix4, SquareMatrix};
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::layout::DescriptorType,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    ibl::EnvironmentMaps,
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+const SCENE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::CombinedImageSampler);
+const NORMALS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 2, DescriptorType::CombinedImageSampler);
+const MATERIAL_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 3, DescriptorType::CombinedImageSampler);
+const DIFFUSE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 4, DescriptorType::CombinedImageSampler);
+const IRRADIANCE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 5, DescriptorType::CombinedImageSampler);
+const OUTPUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 6, DescriptorType::StorageImage);
+
+/// How indirect diffuse light beyond the ambient light and lightmaps is approximated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlobalIllumination {
+    /// Only the ambient light and lightmaps.
+    #[default]
+    Off,
+    /// Light bounced off what's on screen, traced through the depth buffer. Noisy without TAA.
+    ScreenSpace,
+}
+
+/// How screen-space GI is traced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsgiSettings {
+    /// Rays per pixel per frame.
+    pub rays: u32,
+    /// Steps each ray takes through the depth buffer.
+    pub steps: u32,
+    /// Longest ray, in world units. Light bounces further than this are left to the ambient
+    /// light.
+    pub max_distance: f32,
+    /// How far behind the depth buffer a ray still counts as hitting it, in world units.
+    pub thickness: f32,
+    /// Scales the bounced light.
+    pub intensity: f32,
+}
+
+impl Default for SsgiSettings {
+    fn default() -> Self {
+        SsgiSettings {
+            rays: 4,
+            steps: 12,
+            max_distance: 3.0,
+            thickness: 0.3,
+            intensity: 1.0,
+        }
+    }
+}
+
+/// Adds a bounce of diffuse light to the lit scene, by tracing rays around each pixel's normal
+/// through the depth buffer and gathering the light of the surfaces they hit. Where a ray hits,
+/// the environment the ambient light lit that direction with is taken back out, so enclosed
+/// spaces also darken.
+pub struct Ssgi {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    debug_namer: DebugNamer,
+    pipeline: Arc<ComputePipeline>,
+    linear_sampler: Arc<Sampler>,
+    nearest_sampler: Arc<Sampler>,
+    /// Recreated when the HDR target is.
+    output: Arc<ImageView>,
+    memory_tracker: MemoryTracker,
+    output_memory: Option<TrackedMemory>,
+    mode: GlobalIllumination,
+    settings: SsgiSettings,
+    projection: Matrix4<f32>,
+    view: Matrix4<f32>,
+    /// `None` until the ambient light is drawn.
+    ambient_color: Option<[f32; 3]>,
+    frame: u32,
+}
+
+impl Ssgi {
+    pub fn new(
+        context: &VulkanContext,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let cs = cs::load(device.clone())
+            .context("loading SSGI shader")?
+            .entry_point("main")
+            .context("SSGI shader entry point not found")?;
+        validate_descriptor_bindings(
+            "Ssgi",
+            &[&cs],
+            &[
+                SCENE_BINDING,
+                DEPTH_BINDING,
+                NORMALS_BINDING,
+                MATERIAL_BINDING,
+                DIFFUSE_BINDING,
+                IRRADIANCE_BINDING,
+                OUTPUT_BINDING,
+            ],
+        )?;
+
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating pipeline layout create info")?,
+        )
+        .context("creating pipeline layout")?;
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .context("creating SSGI pipeline")?;
+
+        let sampler = |filter: Filter| {
+            Sampler::new(
+                device.clone(),
+                SamplerCreateInfo {
+                    mag_filter: filter,
+                    min_filter: filter,
+                    address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                    ..Default::default()
+                },
+            )
+            .context("creating SSGI sampler")
+        };
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(pipeline.as_ref(), "SSGI pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let output = Self::create_output(&memory_allocator, [1, 1, 1])?;
+
+        Ok(Ssgi {
+            memory_allocator,
+            descriptor_set_cache,
+            debug_namer,
+            pipeline,
+            linear_sampler: sampler(Filter::Linear)?,
+            nearest_sampler: sampler(Filter::Nearest)?,
+            output,
+            memory_tracker: context.memory_tracker().clone(),
+            output_memory: None,
+            mode: GlobalIllumination::default(),
+            settings: SsgiSettings::default(),
+            projection: Matrix4::identity(),
+            view: Matrix4::identity(),
+            ambient_color: None,
+            frame: 0,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mode == GlobalIllumination::ScreenSpace
+    }
+
+    pub fn mode(&self) -> GlobalIllumination {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: GlobalIllumination) {
+        self.mode = mode;
+    }
+
+    pub fn settings(&self) -> SsgiSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SsgiSettings) {
+        self.settings = settings;
+    }
+
+    /// Starts a frame seen through `projection`, jittered as it was drawn, and `view`.
+    pub fn begin_frame(&mut self, projection: Matrix4<f32>, view: Matrix4<f32>) {
+        self.projection = projection;
+        self.view = view;
+        self.ambient_color = None;
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// The linear color the ambient light scaled the environment by this frame.
+    pub fn set_ambient_color(&mut self, color: [f32; 3]) {
+        self.ambient_color = Some(color);
+    }
+
+    /// Recreates the output for an HDR target of `extent`.
+    pub fn resize(&mut self, extent: [u32; 3]) -> anyhow::Result<()> {
+        self.output = Self::create_output(&self.memory_allocator, extent)?;
+        self.debug_namer
+            .name(self.output.image().as_ref(), "SSGI output");
+        self.output_memory = Some(
+            self.memory_tracker
+                .track_image(MemoryCategory::RenderTargets, self.output.image()),
+        );
+        Ok(())
+    }
+
+    /// Adds bounced light to `scene` from the G-buffer and returns the result. Must be recorded
+    /// outside of a render pass.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        scene: &Arc<ImageView>,
+        depth: &Arc<ImageView>,
+        normals: &Arc<ImageView>,
+        material: &Arc<ImageView>,
+        diffuse: &Arc<ImageView>,
+        environment: &EnvironmentMaps,
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &self.pipeline.layout().set_layouts()[0],
+            &[
+                CachedWrite::ImageViewSampler(
+                    SCENE_BINDING.binding,
+                    scene.clone(),
+                    self.linear_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    DEPTH_BINDING.binding,
+                    depth.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    NORMALS_BINDING.binding,
+                    normals.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    MATERIAL_BINDING.binding,
+                    material.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    DIFFUSE_BINDING.binding,
+                    diffuse.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    IRRADIANCE_BINDING.binding,
+                    environment.irradiance.clone(),
+                    environment.sampler.clone(),
+                ),
+                CachedWrite::ImageView(OUTPUT_BINDING.binding, self.output.clone()),
+            ],
+        )?;
+
+        let projection = self.projection;
+        // Without the ambient light there's no environment for hits to take out
+        let [r, g, b, a] = match self.ambient_color {
+            Some([r, g, b]) => [r, g, b, 1.0],
+            None => [0.0; 4],
+        };
+
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("binding SSGI pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding SSGI descriptor set")?
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    view: self.view.into(),
+                    projection: [
+                        projection[0][0],
+                        projection[1][1],
+                        projection[2][2],
+                        projection[3][2],
+                    ],
+                    ambient_color: [r, g, b, a],
+                    jitter: [projection[2][0], projection[2][1]],
+                    frame: self.frame,
+                    rays: self.settings.rays.max(1),
+                    steps: self.settings.steps.max(1),
+                    max_distance: self.settings.max_distance.max(0.01),
+                    thickness: self.settings.thickness.max(0.0),
+                    intensity: self.settings.intensity.max(0.0),
+                },
+            )
+            .context("pushing SSGI constants")?;
+
+        let extent = self.output.image().extent();
+        unsafe { builder.dispatch([extent[0].div_ceil(8), extent[1].div_ceil(8), 1]) }
+            .context("dispatching SSGI")?;
+
+        Ok(self.output.clone())
+    }
+
+    fn create_output(
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        extent: [u32; 3],
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let image = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16B16A16_SFLOAT,
+                extent,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating SSGI output")?;
+        ImageView::new_default(image).context("creating SSGI output view")
+    }
+}
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/post/ssgi.comp"
+    }
+}
@@ -0,0 +1,369 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{Matrix4, SquareMatrix};
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::layout::DescriptorType,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    ibl::EnvironmentMaps,
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+const SCENE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::CombinedImageSampler);
+const NORMALS_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 2, DescriptorType::CombinedImageSampler);
+const MATERIAL_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 3, DescriptorType::CombinedImageSampler);
+const DIFFUSE_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 4, DescriptorType::CombinedImageSampler);
+const PREFILTERED_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 5, DescriptorType::CombinedImageSampler);
+const BRDF_LUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 6, DescriptorType::CombinedImageSampler);
+const OUTPUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 7, DescriptorType::StorageImage);
+const PROBES_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 8, DescriptorType::CombinedImageSampler);
+
+/// How many steps a reflection ray takes through the depth buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SsrQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl SsrQuality {
+    /// Steps along the ray, then binary search steps refining a hit.
+    fn steps(self) -> (u32, u32) {
+        match self {
+            SsrQuality::Low => (16, 3),
+            SsrQuality::Medium => (32, 5),
+            SsrQuality::High => (64, 8),
+        }
+    }
+}
+
+/// How screen-space reflections are traced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsrSettings {
+    pub quality: SsrQuality,
+    /// Longest ray, in world units.
+    pub max_distance: f32,
+    /// How far behind the depth buffer a ray still counts as hitting it, in world units. Too
+    /// little lets rays slip through thin objects, too much smears reflections behind them.
+    pub thickness: f32,
+    /// Surfaces at least this rough only get the environment's reflection.
+    pub max_roughness: f32,
+}
+
+impl Default for SsrSettings {
+    fn default() -> Self {
+        SsrSettings {
+            quality: SsrQuality::default(),
+            max_distance: 20.0,
+            thickness: 0.3,
+            max_roughness: 0.6,
+        }
+    }
+}
+
+/// Adds glossy reflections of what's on screen to the lit scene, by marching the reflected view
+/// ray through the depth buffer. Where a ray misses, the ambient light's environment reflection
+/// is left as it was.
+pub struct Ssr {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    debug_namer: DebugNamer,
+    pipeline: Arc<ComputePipeline>,
+    linear_sampler: Arc<Sampler>,
+    nearest_sampler: Arc<Sampler>,
+    /// Recreated when the HDR target is.
+    output: Arc<ImageView>,
+    memory_tracker: MemoryTracker,
+    output_memory: Option<TrackedMemory>,
+    enabled: bool,
+    settings: SsrSettings,
+    projection: Matrix4<f32>,
+    view: Matrix4<f32>,
+    /// `None` until the ambient light is drawn.
+    ambient_color: Option<[f32; 3]>,
+}
+
+impl Ssr {
+    pub fn new(
+        context: &VulkanContext,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let cs = cs::load(device.clone())
+            .context("loading SSR shader")?
+            .entry_point("main")
+            .context("SSR shader entry point not found")?;
+        validate_descriptor_bindings(
+            "Ssr",
+            &[&cs],
+            &[
+                SCENE_BINDING,
+                DEPTH_BINDING,
+                NORMALS_BINDING,
+                MATERIAL_BINDING,
+                DIFFUSE_BINDING,
+                PREFILTERED_BINDING,
+                BRDF_LUT_BINDING,
+                OUTPUT_BINDING,
+                PROBES_BINDING,
+            ],
+        )?;
+
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating pipeline layout create info")?,
+        )
+        .context("creating pipeline layout")?;
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .context("creating SSR pipeline")?;
+
+        let sampler = |filter: Filter| {
+            Sampler::new(
+                device.clone(),
+                SamplerCreateInfo {
+                    mag_filter: filter,
+                    min_filter: filter,
+                    address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                    ..Default::default()
+                },
+            )
+            .context("creating SSR sampler")
+        };
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(pipeline.as_ref(), "SSR pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let output = Self::create_output(&memory_allocator, [1, 1, 1])?;
+
+        Ok(Ssr {
+            memory_allocator,
+            descriptor_set_cache,
+            debug_namer,
+            pipeline,
+            linear_sampler: sampler(Filter::Linear)?,
+            nearest_sampler: sampler(Filter::Nearest)?,
+            output,
+            memory_tracker: context.memory_tracker().clone(),
+            output_memory: None,
+            enabled: false,
+            settings: SsrSettings::default(),
+            projection: Matrix4::identity(),
+            view: Matrix4::identity(),
+            ambient_color: None,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn settings(&self) -> SsrSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SsrSettings) {
+        self.settings = settings;
+    }
+
+    /// Starts a frame seen through `projection`, jittered as it was drawn, and `view`. Until the
+    /// ambient light is drawn, the frame has no environment reflection to replace.
+    pub fn begin_frame(&mut self, projection: Matrix4<f32>, view: Matrix4<f32>) {
+        self.projection = projection;
+        self.view = view;
+        self.ambient_color = None;
+    }
+
+    /// The linear color the ambient light scaled the environment by this frame, so the
+    /// reflections it added can be swapped for traced ones.
+    pub fn set_ambient_color(&mut self, color: [f32; 3]) {
+        self.ambient_color = Some(color);
+    }
+
+    /// Recreates the output for an HDR target of `extent`.
+    pub fn resize(&mut self, extent: [u32; 3]) -> anyhow::Result<()> {
+        self.output = Self::create_output(&self.memory_allocator, extent)?;
+        self.debug_namer
+            .name(self.output.image().as_ref(), "SSR output");
+        self.output_memory = Some(
+            self.memory_tracker
+                .track_image(MemoryCategory::RenderTargets, self.output.image()),
+        );
+        Ok(())
+    }
+
+    /// Adds reflections to `scene` from the G-buffer and returns the result. Must be recorded
+    /// outside of a render pass.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        builder: &mut RecordingCommandBuffer,
+        scene: &Arc<ImageView>,
+        depth: &Arc<ImageView>,
+        normals: &Arc<ImageView>,
+        material: &Arc<ImageView>,
+        diffuse: &Arc<ImageView>,
+        environment: &EnvironmentMaps,
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &self.pipeline.layout().set_layouts()[0],
+            &[
+                CachedWrite::ImageViewSampler(
+                    SCENE_BINDING.binding,
+                    scene.clone(),
+                    self.linear_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    DEPTH_BINDING.binding,
+                    depth.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    NORMALS_BINDING.binding,
+                    normals.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    MATERIAL_BINDING.binding,
+                    material.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    DIFFUSE_BINDING.binding,
+                    diffuse.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    PREFILTERED_BINDING.binding,
+                    environment.prefiltered.clone(),
+                    environment.sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    BRDF_LUT_BINDING.binding,
+                    environment.brdf_lut.clone(),
+                    environment.sampler.clone(),
+                ),
+                CachedWrite::ImageView(OUTPUT_BINDING.binding, self.output.clone()),
+                CachedWrite::ImageViewSampler(
+                    PROBES_BINDING.binding,
+                    environment.probes.clone(),
+                    environment.sampler.clone(),
+                ),
+            ],
+        )?;
+
+        let (max_steps, refinement_steps) = self.settings.quality.steps();
+        let projection = self.projection;
+        // Alpha says whether the probes' reflections were added, they aren't scaled by the color
+        let [r, g, b, a] = match self.ambient_color {
+            Some([r, g, b]) => [r, g, b, 1.0],
+            None => [0.0; 4],
+        };
+
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("binding SSR pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding SSR descriptor set")?
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    view: self.view.into(),
+                    projection: [
+                        projection[0][0],
+                        projection[1][1],
+                        projection[2][2],
+                        projection[3][2],
+                    ],
+                    ambient_color: [r, g, b, a],
+                    jitter: [projection[2][0], projection[2][1]],
+                    max_reflection_lod: environment.max_reflection_lod(),
+                    thickness: self.settings.thickness.max(0.0),
+                    max_steps,
+                    refinement_steps,
+                    max_distance: self.settings.max_distance.max(0.01),
+                    max_roughness: self.settings.max_roughness.clamp(0.01, 1.0),
+                },
+            )
+            .context("pushing SSR constants")?;
+
+        let extent = self.output.image().extent();
+        unsafe { builder.dispatch([extent[0].div_ceil(8), extent[1].div_ceil(8), 1]) }
+            .context("dispatching SSR")?;
+
+        Ok(self.output.clone())
+    }
+
+    fn create_output(
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        extent: [u32; 3],
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let image = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16B16A16_SFLOAT,
+                extent,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating SSR output")?;
+        ImageView::new_default(image).context("creating SSR output view")
+    }
+}
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/post/ssr.comp"
+    }
+}
@@ -0,0 +1,56 @@
+/// Counters for the most recently rendered frame.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameStats {
+    /// Time spent on the CPU for the whole frame, filled in by the game loop.
+    pub cpu_time_ms: f32,
+    /// GPU time of the primary command buffer. Lags the CPU by the number of frames in flight
+    /// and is `None` until the first frame completes or when the queue can't write timestamps.
+    pub gpu_time_ms: Option<f32>,
+    /// GPU time of the deferred render pass: depth pre-pass, geometry and lighting.
+    pub gpu_scene_ms: Option<f32>,
+    /// GPU time of the post process and HUD, after the render pass.
+    pub gpu_post_process_ms: Option<f32>,
+    /// CPU time spent recording each pass.
+    pub cpu_pass_ms: PassTimes,
+    /// Scene geometry draws, not counting the fullscreen lighting passes.
+    pub draw_calls: u32,
+    pub triangles: u64,
+    /// Objects skipped by occlusion culling.
+    pub occluded_objects: u32,
+    /// Device memory held by tracked resources, broken down by `Renderer::memory_stats`. `None`
+    /// when it isn't being tracked.
+    pub vram_bytes: Option<u64>,
+}
+
+/// Milliseconds spent on each pass of a frame.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PassTimes {
+    /// Recording the shadow casters, before the frame.
+    pub shadows: f32,
+    pub depth_prepass: f32,
+    pub geometry: f32,
+    pub lighting: f32,
+    /// Includes submitting the frame.
+    pub post_process: f32,
+}
+
+/// Size of the scene the renderer currently holds, for debugging.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SceneStats {
+    /// Meshes uploaded with `create_mesh`, which live as long as the renderer.
+    pub meshes: usize,
+    /// Objects queued for the next frame.
+    pub objects: usize,
+    /// Point lights queued for the next frame.
+    pub point_lights: usize,
+    /// Instances of every foliage layer, drawn or not.
+    pub foliage_instances: usize,
+    /// Vertex and index buffer memory of every mesh.
+    pub mesh_bytes: u64,
+    /// Memory of the shared buffers meshes are suballocated from, including what's free.
+    pub mesh_pool_bytes: u64,
+    /// Size of the object data ring buffer, across all frames in flight.
+    pub object_buffer_bytes: u64,
+    /// Size of the indirect draw ring buffer, across all frames in flight.
+    pub indirect_buffer_bytes: u64,
+}
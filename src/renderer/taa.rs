@@ -0,0 +1,321 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::{Matrix4, SquareMatrix};
+use vulkano::{
+    command_buffer::RecordingCommandBuffer,
+    descriptor_set::layout::DescriptorType,
+    format::Format,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, layout::PipelineDescriptorSetLayoutCreateInfo,
+        ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use super::{
+    descriptor_cache::{CachedWrite, DescriptorSetCache},
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    reflection::{validate_descriptor_bindings, DescriptorBinding},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+const CURRENT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 0, DescriptorType::CombinedImageSampler);
+const HISTORY_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 1, DescriptorType::CombinedImageSampler);
+const VELOCITY_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 2, DescriptorType::CombinedImageSampler);
+const DEPTH_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 3, DescriptorType::CombinedImageSampler);
+const OUTPUT_BINDING: DescriptorBinding =
+    DescriptorBinding::new(0, 4, DescriptorType::StorageImage);
+
+/// Length of the jitter sequence, after which it repeats.
+const JITTER_SAMPLES: u32 = 8;
+/// Weight of the current frame against the history. Lower is smoother but ghosts more.
+const CURRENT_WEIGHT: f32 = 0.1;
+
+/// Temporal anti-aliasing. Each frame is rendered with a different subpixel jitter, and the
+/// resolve blends it into the history of previous frames, reprojected through the velocity
+/// buffer and clamped to the current frame's neighborhood to reject stale colors.
+pub struct Taa {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_cache: Arc<DescriptorSetCache>,
+    debug_namer: DebugNamer,
+    pipeline: Arc<ComputePipeline>,
+    linear_sampler: Arc<Sampler>,
+    /// Depth formats can't always be filtered.
+    nearest_sampler: Arc<Sampler>,
+    /// Resolved frames, written alternately. `latest` is the one written last.
+    history: [Arc<ImageView>; 2],
+    latest: usize,
+    history_valid: bool,
+    memory_tracker: MemoryTracker,
+    history_memory: Vec<TrackedMemory>,
+    enabled: bool,
+    frame: u32,
+    previous_view_projection: Option<Matrix4<f32>>,
+    reprojection: Matrix4<f32>,
+}
+
+impl Taa {
+    pub fn new(
+        context: &VulkanContext,
+        descriptor_set_cache: Arc<DescriptorSetCache>,
+    ) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let cs = cs::load(device.clone())
+            .context("loading TAA shader")?
+            .entry_point("main")
+            .context("TAA shader entry point not found")?;
+        validate_descriptor_bindings(
+            "Taa",
+            &[&cs],
+            &[
+                CURRENT_BINDING,
+                HISTORY_BINDING,
+                VELOCITY_BINDING,
+                DEPTH_BINDING,
+                OUTPUT_BINDING,
+            ],
+        )?;
+
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                .into_pipeline_layout_create_info(device.clone())
+                .context("creating pipeline layout create info")?,
+        )
+        .context("creating pipeline layout")?;
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .context("creating TAA pipeline")?;
+
+        let sampler = |filter: Filter| {
+            Sampler::new(
+                device.clone(),
+                SamplerCreateInfo {
+                    mag_filter: filter,
+                    min_filter: filter,
+                    address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                    ..Default::default()
+                },
+            )
+            .context("creating TAA sampler")
+        };
+        let linear_sampler = sampler(Filter::Linear)?;
+        let nearest_sampler = sampler(Filter::Nearest)?;
+
+        let debug_namer = context.debug_namer().clone();
+        debug_namer.name(pipeline.as_ref(), "TAA resolve pipeline");
+
+        let memory_allocator = context.memory_allocator().clone();
+        let history = [
+            Self::create_history(&memory_allocator, [1, 1, 1])?,
+            Self::create_history(&memory_allocator, [1, 1, 1])?,
+        ];
+
+        Ok(Taa {
+            memory_allocator,
+            descriptor_set_cache,
+            debug_namer,
+            pipeline,
+            linear_sampler,
+            nearest_sampler,
+            history,
+            latest: 0,
+            history_valid: false,
+            memory_tracker: context.memory_tracker().clone(),
+            history_memory: vec![],
+            enabled: false,
+            frame: 0,
+            previous_view_projection: None,
+            reprojection: Matrix4::identity(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled != self.enabled {
+            self.enabled = enabled;
+            self.reset();
+        }
+    }
+
+    /// Drops the history, e.g. after a camera cut, so nothing from before is blended in.
+    pub fn reset(&mut self) {
+        self.history_valid = false;
+        self.previous_view_projection = None;
+    }
+
+    /// Recreates the history for an HDR target of `extent`, dropping what it held.
+    pub fn resize(&mut self, extent: [u32; 3]) -> anyhow::Result<()> {
+        self.history = [
+            Self::create_history(&self.memory_allocator, extent)?,
+            Self::create_history(&self.memory_allocator, extent)?,
+        ];
+        for (index, view) in self.history.iter().enumerate() {
+            self.debug_namer
+                .name(view.image().as_ref(), &format!("TAA history {}", index));
+        }
+        self.history_memory = self
+            .history
+            .iter()
+            .map(|view| {
+                self.memory_tracker
+                    .track_image(MemoryCategory::RenderTargets, view.image())
+            })
+            .collect();
+        self.history_valid = false;
+        Ok(())
+    }
+
+    /// Advances the jitter sequence for a frame seen through the unjittered `view_projection`,
+    /// rendered at `extent`. Returns the offset to jitter the projection by in NDC units, zero
+    /// while TAA is disabled.
+    pub fn begin_frame(&mut self, view_projection: Matrix4<f32>, extent: [u32; 3]) -> [f32; 2] {
+        if !self.enabled {
+            return [0.0, 0.0];
+        }
+
+        let previous = match self.previous_view_projection.replace(view_projection) {
+            Some(previous) => previous,
+            None => {
+                self.history_valid = false;
+                view_projection
+            }
+        };
+        self.reprojection = previous * view_projection.invert().unwrap_or_else(Matrix4::identity);
+
+        self.frame = (self.frame + 1) % JITTER_SAMPLES;
+        let index = self.frame + 1;
+        // Pixel offsets in -0.5..0.5, converted to NDC where the viewport spans 2 units
+        [
+            (halton(index, 2) - 0.5) * 2.0 / extent[0] as f32,
+            (halton(index, 3) - 0.5) * 2.0 / extent[1] as f32,
+        ]
+    }
+
+    /// Resolves `hdr` against the history and returns the resolved image, which becomes the next
+    /// frame's history. Must be recorded outside of a render pass.
+    pub fn record(
+        &mut self,
+        builder: &mut RecordingCommandBuffer,
+        hdr: &Arc<ImageView>,
+        velocity: &Arc<ImageView>,
+        depth: &Arc<ImageView>,
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let history = self.history[self.latest].clone();
+        let output = self.history[1 - self.latest].clone();
+
+        let descriptor_set = self.descriptor_set_cache.get_or_create(
+            &self.pipeline.layout().set_layouts()[0],
+            &[
+                CachedWrite::ImageViewSampler(
+                    CURRENT_BINDING.binding,
+                    hdr.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    HISTORY_BINDING.binding,
+                    history,
+                    self.linear_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    VELOCITY_BINDING.binding,
+                    velocity.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageViewSampler(
+                    DEPTH_BINDING.binding,
+                    depth.clone(),
+                    self.nearest_sampler.clone(),
+                ),
+                CachedWrite::ImageView(OUTPUT_BINDING.binding, output.clone()),
+            ],
+        )?;
+
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .context("binding TAA pipeline")?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .context("binding TAA descriptor set")?
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                cs::PushConstants {
+                    reprojection: self.reprojection.into(),
+                    current_weight: CURRENT_WEIGHT,
+                    history_valid: self.history_valid as u32,
+                },
+            )
+            .context("pushing TAA constants")?;
+
+        let extent = output.image().extent();
+        unsafe { builder.dispatch([extent[0].div_ceil(8), extent[1].div_ceil(8), 1]) }
+            .context("dispatching TAA resolve")?;
+
+        self.latest = 1 - self.latest;
+        self.history_valid = true;
+
+        Ok(output)
+    }
+
+    fn create_history(
+        memory_allocator: &Arc<StandardMemoryAllocator>,
+        extent: [u32; 3],
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let image = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R16G16B16A16_SFLOAT,
+                extent,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating TAA history")?;
+        ImageView::new_default(image).context("creating TAA history view")
+    }
+}
+
+/// The `index`th element of the Halton sequence in `base`, in `0..1`.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "assets/shaders/post/taa.comp"
+    }
+}
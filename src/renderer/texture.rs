@@ -0,0 +1,490 @@
+use std::{fs, path::Path, sync::Arc};
+
+use anyhow::{anyhow, bail, Context};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, BlitImageInfo, BufferImageCopy,
+        CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage, CopyBufferToImageInfo,
+        ImageBlit, RecordingCommandBuffer,
+    },
+    device::Queue,
+    format::{Format, FormatFeatures},
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageSubresourceLayers, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    sync::GpuFuture,
+};
+
+use ddsfile::{D3DFormat, DxgiFormat};
+
+use crate::assets::TextureUsage;
+
+use super::{
+    memory::{MemoryCategory, MemoryTracker, TrackedMemory},
+    vulkan_context::{DebugNamer, VulkanContext},
+};
+
+/// Refers to a texture loaded by a `Renderer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+/// Pixel data ready to upload, with every mip level present or just the first.
+struct TextureData {
+    format: Format,
+    extent: [u32; 2],
+    levels: Vec<Vec<u8>>,
+}
+
+/// Loads textures onto the GPU and keeps them alive for as long as the renderer.
+///
+/// PNG and JPEG files are decoded to RGBA8 and get a full mip chain generated with blits. KTX2
+/// and DDS files are uploaded as stored, generating mips only when an uncompressed file has a
+/// single level, and may also hold 16 or 32 bit float RGBA for HDR data like lightmaps. Their
+/// BC1, BC3, BC5 and BC7 payloads are decompressed to RGBA8 on devices that
+/// can't sample them. The format follows the texture's usage, so color textures are sampled as
+/// sRGB and normal and mask textures as linear data.
+pub struct TextureLoader {
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    debug_namer: DebugNamer,
+    sampler: Arc<Sampler>,
+    textures: Vec<Arc<ImageView>>,
+    memory_tracker: MemoryTracker,
+    texture_memory: Vec<TrackedMemory>,
+}
+
+impl TextureLoader {
+    pub fn new(context: &VulkanContext) -> anyhow::Result<Self> {
+        let device = context.device();
+
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [SamplerAddressMode::Repeat; 3],
+                ..Default::default()
+            },
+        )
+        .context("creating texture sampler")?;
+
+        let mut loader = TextureLoader {
+            queue: context.graphics_queue().clone(),
+            memory_allocator: context.memory_allocator().clone(),
+            command_buffer_allocator: Arc::new(StandardCommandBufferAllocator::new(
+                device.clone(),
+                Default::default(),
+            )),
+            debug_namer: context.debug_namer().clone(),
+            sampler,
+            textures: vec![],
+            memory_tracker: context.memory_tracker().clone(),
+            texture_memory: vec![],
+        };
+
+        let white = loader
+            .upload(TextureData {
+                format: Format::R8G8B8A8_UNORM,
+                extent: [1, 1],
+                levels: vec![vec![255; 4]],
+            })
+            .context("uploading white texture")?;
+        loader.add(white);
+
+        Ok(loader)
+    }
+
+    /// A single opaque white texel, bound in place of missing material textures.
+    pub fn white(&self) -> TextureHandle {
+        TextureHandle(0)
+    }
+
+    /// Loads and uploads the texture at `path`, blocking until the upload has finished.
+    pub fn load(&mut self, path: &Path, usage: TextureUsage) -> anyhow::Result<TextureHandle> {
+        let view = self.load_view(path, usage)?;
+        Ok(self.add(view))
+    }
+
+    /// Loads the texture at `path` again in place of `handle`'s, returning the replaced view.
+    pub fn reload(
+        &mut self,
+        handle: TextureHandle,
+        path: &Path,
+        usage: TextureUsage,
+    ) -> anyhow::Result<Arc<ImageView>> {
+        if handle.0 >= self.textures.len() {
+            bail!("no texture {:?}", handle);
+        }
+        let view = self.load_view(path, usage)?;
+        let category = self.texture_memory[handle.0].category();
+        self.texture_memory[handle.0] = self.memory_tracker.track_image(category, view.image());
+        Ok(std::mem::replace(&mut self.textures[handle.0], view))
+    }
+
+    fn load_view(&self, path: &Path, usage: TextureUsage) -> anyhow::Result<Arc<ImageView>> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        let data = match extension.as_deref() {
+            Some("ktx2") => {
+                let bytes = fs::read(path).with_context(|| format!("reading {:?}", path))?;
+                decode_ktx2(&bytes).with_context(|| format!("decoding {:?}", path))?
+            }
+            Some("dds") => {
+                let bytes = fs::read(path).with_context(|| format!("reading {:?}", path))?;
+                decode_dds(&bytes, usage).with_context(|| format!("decoding {:?}", path))?
+            }
+            _ => decode_image(path, usage)?,
+        };
+
+        let data = if data.format.compression().is_some() && !self.can_sample(data.format) {
+            log::debug!(
+                "{:?} can't be sampled, decompressing {:?}",
+                data.format,
+                path
+            );
+            decompress(data).with_context(|| format!("decompressing {:?}", path))?
+        } else {
+            data
+        };
+
+        let view = self
+            .upload(data)
+            .with_context(|| format!("uploading {:?}", path))?;
+        self.debug_namer
+            .name(view.image().as_ref(), &path.to_string_lossy());
+
+        Ok(view)
+    }
+
+    fn add(&mut self, view: Arc<ImageView>) -> TextureHandle {
+        self.texture_memory.push(
+            self.memory_tracker
+                .track_image(MemoryCategory::Textures, view.image()),
+        );
+        self.textures.push(view);
+        TextureHandle(self.textures.len() - 1)
+    }
+
+    /// Counts `handle`'s memory under `category` instead of `Textures`, e.g. for the UI atlas.
+    pub fn set_category(&mut self, handle: TextureHandle, category: MemoryCategory) {
+        if let (Some(view), Some(memory)) = (
+            self.textures.get(handle.0),
+            self.texture_memory.get_mut(handle.0),
+        ) {
+            *memory = self.memory_tracker.track_image(category, view.image());
+        }
+    }
+
+    pub fn view(&self, handle: TextureHandle) -> Option<&Arc<ImageView>> {
+        self.textures.get(handle.0)
+    }
+
+    /// A trilinear, repeating sampler suitable for most material textures.
+    pub fn sampler(&self) -> &Arc<Sampler> {
+        &self.sampler
+    }
+
+    fn can_sample(&self, format: Format) -> bool {
+        let device = self.queue.device();
+        device.enabled_features().texture_compression_bc
+            && device
+                .physical_device()
+                .format_properties(format)
+                .map(|properties| {
+                    properties
+                        .optimal_tiling_features
+                        .intersects(FormatFeatures::SAMPLED_IMAGE)
+                })
+                .unwrap_or(false)
+    }
+
+    fn upload(&self, data: TextureData) -> anyhow::Result<Arc<ImageView>> {
+        let [width, height] = data.extent;
+        // Block compressed formats can't be blitted
+        let generate_mips = data.levels.len() == 1 && data.format.compression().is_none();
+        let mip_levels = if generate_mips && width.max(height) > 1 {
+            32 - width.max(height).leading_zeros()
+        } else {
+            data.levels.len() as u32
+        };
+
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: data.format,
+                extent: [width, height, 1],
+                mip_levels,
+                usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .context("creating texture image")?;
+
+        let staging = Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            data.levels.concat(),
+        )
+        .context("creating texture staging buffer")?;
+
+        let mut offset = 0;
+        let regions = data
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, bytes)| {
+                let region = BufferImageCopy {
+                    buffer_offset: offset,
+                    image_subresource: ImageSubresourceLayers {
+                        mip_level: level as u32,
+                        ..image.subresource_layers()
+                    },
+                    image_extent: mip_extent(data.extent, level as u32),
+                    ..Default::default()
+                };
+                offset += bytes.len() as u64;
+                region
+            })
+            .collect();
+
+        let mut builder = RecordingCommandBuffer::new(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating texture upload command buffer")?;
+
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo {
+                regions,
+                ..CopyBufferToImageInfo::buffer_image(staging, image.clone())
+            })
+            .context("copying texture data")?;
+
+        if generate_mips {
+            // Each level is blitted down from the one before it
+            for level in 1..mip_levels {
+                let src = mip_extent(data.extent, level - 1);
+                let dst = mip_extent(data.extent, level);
+                builder
+                    .blit_image(BlitImageInfo {
+                        regions: [ImageBlit {
+                            src_subresource: ImageSubresourceLayers {
+                                mip_level: level - 1,
+                                ..image.subresource_layers()
+                            },
+                            src_offsets: [[0; 3], src],
+                            dst_subresource: ImageSubresourceLayers {
+                                mip_level: level,
+                                ..image.subresource_layers()
+                            },
+                            dst_offsets: [[0; 3], dst],
+                            ..Default::default()
+                        }]
+                        .into(),
+                        filter: Filter::Linear,
+                        ..BlitImageInfo::images(image.clone(), image.clone())
+                    })
+                    .context("generating texture mip")?;
+            }
+        }
+
+        builder
+            .end()
+            .context("ending texture upload command buffer")?
+            .execute(self.queue.clone())
+            .context("executing texture upload")?
+            .then_signal_fence_and_flush()
+            .context("flushing texture upload")?
+            .wait(None)
+            .context("waiting for texture upload")?;
+
+        ImageView::new_default(image).context("creating texture image view")
+    }
+}
+
+fn mip_extent(extent: [u32; 2], level: u32) -> [u32; 3] {
+    [(extent[0] >> level).max(1), (extent[1] >> level).max(1), 1]
+}
+
+fn rgba8_format(usage: TextureUsage) -> Format {
+    match usage {
+        TextureUsage::Color { srgb: true } => Format::R8G8B8A8_SRGB,
+        _ => Format::R8G8B8A8_UNORM,
+    }
+}
+
+fn decode_image(path: &Path, usage: TextureUsage) -> anyhow::Result<TextureData> {
+    let image = image::open(path)
+        .with_context(|| format!("decoding {:?}", path))?
+        .to_rgba8();
+
+    Ok(TextureData {
+        format: rgba8_format(usage),
+        extent: [image.width(), image.height()],
+        levels: vec![image.into_raw()],
+    })
+}
+
+/// Reads RGBA8, float RGBA and BCn KTX2 files. The stored format's color space is used regardless of the
+/// texture's usage, since the texels were authored for it.
+fn decode_ktx2(bytes: &[u8]) -> anyhow::Result<TextureData> {
+    let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow!("reading KTX2 header: {:?}", e))?;
+    let header = reader.header();
+
+    if header.supercompression_scheme.is_some() {
+        bail!("supercompressed KTX2 files are not supported");
+    }
+
+    let format = match header.format {
+        Some(ktx2::Format::R8G8B8A8_SRGB) => Format::R8G8B8A8_SRGB,
+        Some(ktx2::Format::R8G8B8A8_UNORM) => Format::R8G8B8A8_UNORM,
+        Some(ktx2::Format::R16G16B16A16_SFLOAT) => Format::R16G16B16A16_SFLOAT,
+        Some(ktx2::Format::R32G32B32A32_SFLOAT) => Format::R32G32B32A32_SFLOAT,
+        Some(ktx2::Format::BC1_RGB_UNORM_BLOCK) => Format::BC1_RGB_UNORM_BLOCK,
+        Some(ktx2::Format::BC1_RGB_SRGB_BLOCK) => Format::BC1_RGB_SRGB_BLOCK,
+        Some(ktx2::Format::BC1_RGBA_UNORM_BLOCK) => Format::BC1_RGBA_UNORM_BLOCK,
+        Some(ktx2::Format::BC1_RGBA_SRGB_BLOCK) => Format::BC1_RGBA_SRGB_BLOCK,
+        Some(ktx2::Format::BC3_UNORM_BLOCK) => Format::BC3_UNORM_BLOCK,
+        Some(ktx2::Format::BC3_SRGB_BLOCK) => Format::BC3_SRGB_BLOCK,
+        Some(ktx2::Format::BC5_UNORM_BLOCK) => Format::BC5_UNORM_BLOCK,
+        Some(ktx2::Format::BC7_UNORM_BLOCK) => Format::BC7_UNORM_BLOCK,
+        Some(ktx2::Format::BC7_SRGB_BLOCK) => Format::BC7_SRGB_BLOCK,
+        Some(format) => bail!("unsupported KTX2 format {:?}", format),
+        None => bail!("KTX2 files without a Vulkan format are not supported"),
+    };
+
+    Ok(TextureData {
+        format,
+        extent: [header.pixel_width, header.pixel_height.max(1)],
+        levels: reader.levels().map(|level| level.data.to_vec()).collect(),
+    })
+}
+
+/// Reads RGBA8, float RGBA and BCn DDS files. Legacy DXT files don't record a color space, so theirs comes
+/// from the usage.
+fn decode_dds(bytes: &[u8], usage: TextureUsage) -> anyhow::Result<TextureData> {
+    let dds = ddsfile::Dds::read(bytes).context("reading DDS header")?;
+    let srgb = matches!(usage, TextureUsage::Color { srgb: true });
+
+    let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+        (Some(format), _) => match format {
+            DxgiFormat::R8G8B8A8_UNorm => Format::R8G8B8A8_UNORM,
+            DxgiFormat::R8G8B8A8_UNorm_sRGB => Format::R8G8B8A8_SRGB,
+            DxgiFormat::R16G16B16A16_Float => Format::R16G16B16A16_SFLOAT,
+            DxgiFormat::R32G32B32A32_Float => Format::R32G32B32A32_SFLOAT,
+            DxgiFormat::BC1_UNorm => Format::BC1_RGBA_UNORM_BLOCK,
+            DxgiFormat::BC1_UNorm_sRGB => Format::BC1_RGBA_SRGB_BLOCK,
+            DxgiFormat::BC3_UNorm => Format::BC3_UNORM_BLOCK,
+            DxgiFormat::BC3_UNorm_sRGB => Format::BC3_SRGB_BLOCK,
+            DxgiFormat::BC5_UNorm => Format::BC5_UNORM_BLOCK,
+            DxgiFormat::BC7_UNorm => Format::BC7_UNORM_BLOCK,
+            DxgiFormat::BC7_UNorm_sRGB => Format::BC7_SRGB_BLOCK,
+            format => bail!("unsupported DDS format {:?}", format),
+        },
+        (None, Some(D3DFormat::DXT1)) if srgb => Format::BC1_RGBA_SRGB_BLOCK,
+        (None, Some(D3DFormat::DXT1)) => Format::BC1_RGBA_UNORM_BLOCK,
+        (None, Some(D3DFormat::DXT5)) if srgb => Format::BC3_SRGB_BLOCK,
+        (None, Some(D3DFormat::DXT5)) => Format::BC3_UNORM_BLOCK,
+        (None, Some(D3DFormat::A16B16G16R16F)) => Format::R16G16B16A16_SFLOAT,
+        (None, Some(D3DFormat::A32B32G32R32F)) => Format::R32G32B32A32_SFLOAT,
+        (None, format) => bail!("unsupported DDS format {:?}", format),
+    };
+
+    let extent = [dds.get_width(), dds.get_height()];
+    let data = dds.get_data(0).context("reading DDS texels")?;
+
+    let mut levels = vec![];
+    let mut offset = 0;
+    for level in 0..dds.get_num_mipmap_levels().max(1) {
+        let size = level_size(format, mip_extent(extent, level));
+        let bytes = data
+            .get(offset..offset + size)
+            .context("DDS file is missing mip levels")?;
+        levels.push(bytes.to_vec());
+        offset += size;
+    }
+
+    Ok(TextureData {
+        format,
+        extent,
+        levels,
+    })
+}
+
+/// Bytes taken by a level of `extent` texels, rounded up to whole blocks.
+fn level_size(format: Format, extent: [u32; 3]) -> usize {
+    let block = format.block_extent();
+    let blocks_x = extent[0].div_ceil(block[0]) as usize;
+    let blocks_y = extent[1].div_ceil(block[1]) as usize;
+    blocks_x * blocks_y * format.block_size() as usize
+}
+
+/// Decodes BCn levels to RGBA8 in the matching color space.
+fn decompress(data: TextureData) -> anyhow::Result<TextureData> {
+    type Decoder = fn(&[u8], usize, usize, &mut [u32]) -> Result<(), &'static str>;
+
+    let (decoder, format): (Decoder, Format) = match data.format {
+        Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGBA_UNORM_BLOCK => {
+            (texture2ddecoder::decode_bc1, Format::R8G8B8A8_UNORM)
+        }
+        Format::BC1_RGB_SRGB_BLOCK | Format::BC1_RGBA_SRGB_BLOCK => {
+            (texture2ddecoder::decode_bc1, Format::R8G8B8A8_SRGB)
+        }
+        Format::BC3_UNORM_BLOCK => (texture2ddecoder::decode_bc3, Format::R8G8B8A8_UNORM),
+        Format::BC3_SRGB_BLOCK => (texture2ddecoder::decode_bc3, Format::R8G8B8A8_SRGB),
+        Format::BC5_UNORM_BLOCK => (texture2ddecoder::decode_bc5, Format::R8G8B8A8_UNORM),
+        Format::BC7_UNORM_BLOCK => (texture2ddecoder::decode_bc7, Format::R8G8B8A8_UNORM),
+        Format::BC7_SRGB_BLOCK => (texture2ddecoder::decode_bc7, Format::R8G8B8A8_SRGB),
+        format => bail!("no decoder for {:?}", format),
+    };
+
+    let levels = data
+        .levels
+        .iter()
+        .enumerate()
+        .map(|(level, bytes)| {
+            let [width, height, _] = mip_extent(data.extent, level as u32);
+            let mut pixels = vec![0u32; (width * height) as usize];
+            decoder(bytes, width as usize, height as usize, &mut pixels)
+                .map_err(|e| anyhow!("decoding level {}: {}", level, e))?;
+
+            // The decoder packs pixels as BGRA
+            Ok(pixels
+                .into_iter()
+                .flat_map(|pixel| {
+                    let [b, g, r, a] = pixel.to_le_bytes();
+                    [r, g, b, a]
+                })
+                .collect())
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(TextureData {
+        format,
+        extent: data.extent,
+        levels,
+    })
+}
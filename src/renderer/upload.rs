@@ -0,0 +1,311 @@
+use std::{collections::VecDeque, ops::Range, sync::Arc};
+
+use anyhow::{bail, Context};
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        CommandBuffer, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsage,
+        CopyBufferInfo, RecordingCommandBuffer,
+    },
+    device::Queue,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    DeviceSize,
+};
+
+use super::{
+    frames_in_flight::InFlightFrame,
+    memory::{MemoryCategory, TrackedMemory},
+    mesh::{Aabb, BasicMesh, Indices},
+    mesh_pool::MeshPool,
+    vertex::MeshVertex,
+    vulkan_context::VulkanContext,
+};
+
+/// Smallest per-frame budget, so every vertex fits in a frame's staging buffer.
+const MIN_BUDGET: DeviceSize = 64 * 1024;
+
+/// Refers to a mesh being streamed in by `Renderer::upload_mesh`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeshUploadHandle(usize);
+
+/// How far a streamed mesh has come.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadStatus {
+    /// `uploaded` of `total` bytes have been copied. The mesh can't be drawn yet.
+    Streaming { uploaded: u64, total: u64 },
+    /// On the GPU and drawable as this mesh id.
+    Ready(usize),
+}
+
+impl UploadStatus {
+    /// From 0 when nothing has been copied to 1 once the mesh is ready.
+    pub fn progress(&self) -> f32 {
+        match *self {
+            UploadStatus::Streaming { total: 0, .. } => 0.0,
+            UploadStatus::Streaming { uploaded, total } => uploaded as f32 / total as f32,
+            UploadStatus::Ready(_) => 1.0,
+        }
+    }
+}
+
+/// Typed data a staged buffer is copied from, a chunk of elements at a time.
+trait Source {
+    fn len(&self) -> usize;
+
+    fn element_size(&self) -> DeviceSize;
+
+    /// Writes `elements` to `staging`, which is exactly their size.
+    fn write(&self, elements: Range<usize>, staging: Subbuffer<[u8]>) -> anyhow::Result<()>;
+}
+
+impl<T: BufferContents + Copy> Source for Vec<T> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn element_size(&self) -> DeviceSize {
+        std::mem::size_of::<T>() as DeviceSize
+    }
+
+    fn write(&self, elements: Range<usize>, staging: Subbuffer<[u8]>) -> anyhow::Result<()> {
+        staging
+            .reinterpret::<[T]>()
+            .write()
+            .context("writing staging buffer")?
+            .copy_from_slice(&self[elements]);
+        Ok(())
+    }
+}
+
+/// A device local buffer and the data still to be copied into it.
+struct StagedBuffer {
+    source: Box<dyn Source>,
+    destination: Subbuffer<[u8]>,
+    /// Elements copied so far.
+    copied: usize,
+}
+
+impl StagedBuffer {
+    fn remaining(&self) -> usize {
+        self.source.len() - self.copied
+    }
+
+    fn copied_bytes(&self) -> u64 {
+        self.copied as u64 * self.source.element_size()
+    }
+}
+
+struct PendingMesh {
+    handle: MeshUploadHandle,
+    mesh: BasicMesh,
+    vertices: StagedBuffer,
+    indices: StagedBuffer,
+    /// The frame slot that recorded the last chunk. The mesh is ready once that slot comes
+    /// around again, since its fence has then signaled.
+    completed_in: Option<usize>,
+}
+
+/// Streams meshes into the mesh pool over several frames, copying at most a fixed budget
+/// of bytes a frame so big meshes don't stall a frame, or wait on a single host visible
+/// allocation the size of the mesh.
+///
+/// Each frame in flight has its own staging buffer the size of the budget, refilled once the
+/// frame's fence has signaled. Meshes are streamed in the order they were queued.
+pub struct MeshUploader {
+    queue: Arc<Queue>,
+    pool: MeshPool,
+    staging: Vec<Subbuffer<[u8]>>,
+    _staging_memory: TrackedMemory,
+    budget: DeviceSize,
+    pending: VecDeque<PendingMesh>,
+    /// Every upload's status, by handle.
+    statuses: Vec<UploadStatus>,
+}
+
+impl MeshUploader {
+    /// Copies up to `budget` bytes a frame, raised to `MIN_BUDGET`.
+    pub fn new(
+        context: &VulkanContext,
+        frames: usize,
+        budget: DeviceSize,
+        pool: MeshPool,
+    ) -> anyhow::Result<Self> {
+        let memory_allocator = context.memory_allocator().clone();
+        let budget = budget.max(MIN_BUDGET);
+        let staging = (0..frames)
+            .map(|_| {
+                Buffer::new_slice::<u8>(
+                    memory_allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::TRANSFER_SRC,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                            | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                    budget,
+                )
+                .context("creating mesh staging buffer")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(MeshUploader {
+            queue: context.graphics_queue().clone(),
+            pool,
+            staging,
+            _staging_memory: context
+                .memory_tracker()
+                .track(MemoryCategory::Meshes, budget * frames as DeviceSize),
+            budget,
+            pending: VecDeque::new(),
+            statuses: vec![],
+        })
+    }
+
+    pub fn budget(&self) -> DeviceSize {
+        self.budget
+    }
+
+    /// Allocates the mesh's ranges of the pool and queues its data to be copied over the next frames.
+    pub fn stage<V: MeshVertex>(
+        &mut self,
+        vertices: Vec<V>,
+        indices: impl Into<Indices>,
+    ) -> anyhow::Result<MeshUploadHandle> {
+        let indices = indices.into();
+        if vertices.is_empty() || indices.is_empty() {
+            bail!("can't stream a mesh without vertices and indices");
+        }
+
+        let indices = indices.fit_to(vertices.len());
+        let bounds = Aabb::from_points(vertices.iter().map(|v| v.position()));
+        let mesh = self
+            .pool
+            .allocate_mesh::<V>(vertices.len(), &indices, bounds)?;
+
+        let vertices = StagedBuffer {
+            source: Box::new(vertices),
+            destination: mesh.vertex_buffer.clone(),
+            copied: 0,
+        };
+        let indices = StagedBuffer {
+            source: match indices {
+                Indices::U16(indices) => Box::new(indices),
+                Indices::U32(indices) => Box::new(indices),
+            },
+            destination: mesh.index_buffer.as_bytes().clone(),
+            copied: 0,
+        };
+
+        let handle = MeshUploadHandle(self.statuses.len());
+        self.statuses.push(UploadStatus::Streaming {
+            uploaded: 0,
+            total: vertices.destination.size() + indices.destination.size(),
+        });
+        self.pending.push_back(PendingMesh {
+            handle,
+            mesh,
+            vertices,
+            indices,
+            completed_in: None,
+        });
+        Ok(handle)
+    }
+
+    pub fn status(&self, handle: MeshUploadHandle) -> Option<UploadStatus> {
+        self.statuses.get(handle.0).copied()
+    }
+
+    /// Whether any mesh is still streaming.
+    pub fn is_busy(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Starts the frame slot `frame_index`, once its fence has signaled. Returns the meshes the
+    /// slot finished copying, to be added to the scene and marked with `set_ready`.
+    pub fn begin_frame(&mut self, frame_index: usize) -> Vec<(MeshUploadHandle, BasicMesh)> {
+        let (ready, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|pending| pending.completed_in == Some(frame_index));
+        self.pending = pending;
+        ready
+            .into_iter()
+            .map(|pending: PendingMesh| (pending.handle, pending.mesh))
+            .collect()
+    }
+
+    pub fn set_ready(&mut self, handle: MeshUploadHandle, mesh_id: usize) {
+        self.statuses[handle.0] = UploadStatus::Ready(mesh_id);
+    }
+
+    /// Records the copies of this frame's share of the budget, to be executed before anything
+    /// that frame draws. Returns `None` when nothing is left to copy.
+    pub fn record(&mut self, frame: &InFlightFrame) -> anyhow::Result<Option<Arc<CommandBuffer>>> {
+        let staging = &self.staging[frame.index];
+        let mut head: DeviceSize = 0;
+        let mut copies = vec![];
+
+        for pending in self.pending.iter_mut() {
+            if pending.completed_in.is_some() {
+                continue;
+            }
+            for staged in [&mut pending.vertices, &mut pending.indices] {
+                let element_size = staged.source.element_size();
+                // Keeps every chunk aligned for the element types it's written as
+                let start = head.next_multiple_of(4);
+                let room = self.budget.saturating_sub(start) / element_size;
+                let count = (room as usize).min(staged.remaining());
+                if count == 0 {
+                    continue;
+                }
+
+                let size = count as DeviceSize * element_size;
+                let source = staging.clone().slice(start..start + size);
+                staged
+                    .source
+                    .write(staged.copied..staged.copied + count, source.clone())?;
+                let offset = staged.copied_bytes();
+                let destination = staged.destination.clone().slice(offset..offset + size);
+                copies.push(CopyBufferInfo::buffers(source, destination));
+                staged.copied += count;
+                head = start + size;
+            }
+
+            if pending.vertices.remaining() == 0 && pending.indices.remaining() == 0 {
+                pending.completed_in = Some(frame.index);
+            }
+            self.statuses[pending.handle.0] = UploadStatus::Streaming {
+                uploaded: pending.vertices.copied_bytes() + pending.indices.copied_bytes(),
+                total: pending.vertices.destination.size() + pending.indices.destination.size(),
+            };
+            if head >= self.budget {
+                break;
+            }
+        }
+
+        if copies.is_empty() {
+            return Ok(None);
+        }
+        profile_plot!("streamed mesh bytes", head);
+
+        let mut builder = RecordingCommandBuffer::new(
+            frame.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .context("creating mesh upload command buffer")?;
+        for copy in copies {
+            builder.copy_buffer(copy).context("copying mesh chunk")?;
+        }
+        builder
+            .end()
+            .context("ending mesh upload command buffer")
+            .map(Some)
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cgmath::Matrix4;
+use vulkano::{
+    command_buffer::{allocator::StandardCommandBufferAllocator, CommandBuffer},
+    device::Queue,
+    image::view::ImageView,
+    render_pass::Subpass,
+};
+
+/// Where in the frame a user pass is recorded, and so which command buffer it returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PassPoint {
+    /// A primary command buffer executed before the scene is rendered, after the mesh uploads,
+    /// culling and selection mask, e.g. a compute pass the frame reads from.
+    BeforeScene,
+    /// A secondary command buffer for the G-buffer subpass, `PassContext::subpass`, executed
+    /// after the geometry, foliage and portals but before the overlay cameras. Its pipelines
+    /// write the same attachments as the G-buffer's.
+    Geometry,
+    /// A secondary command buffer executed outside of any render pass once the scene has been
+    /// lit, before the screen space effects and post process. `PassContext::target` is the HDR
+    /// target, e.g. for fog or a custom light.
+    AfterScene,
+    /// A primary command buffer executed after the frame has been composited, before it's read
+    /// back or presented. `PassContext::target` is the swapchain image, e.g. for an overlay
+    /// drawn in a render pass of the pass's own that loads it.
+    AfterFrame,
+}
+
+/// What a user pass gets to record with.
+#[derive(Clone)]
+pub struct PassContext<'a> {
+    pub point: PassPoint,
+    pub queue: &'a Arc<Queue>,
+    /// The frame slot's allocator, whose command buffers are reused once the slot's fence has
+    /// signaled.
+    pub command_buffer_allocator: &'a Arc<StandardCommandBufferAllocator>,
+    /// The frame slot being recorded, for resources kept per frame in flight.
+    pub frame_index: usize,
+    /// The G-buffer subpass for `PassPoint::Geometry`, `None` at the other points.
+    pub subpass: Option<Subpass>,
+    /// The scene's render extent, see `Renderer::set_render_scale`, except at
+    /// `PassPoint::AfterFrame` where it's the swapchain's.
+    pub extent: [u32; 2],
+    /// The main camera's, jittered like the scene while TAA is enabled.
+    pub view_projection: Matrix4<f32>,
+    /// The scene's depth. Before the scene it still holds the last frame's.
+    pub depth: &'a Arc<ImageView>,
+    /// What `PassPoint::AfterScene` and `PassPoint::AfterFrame` draw over, `None` at the others.
+    pub target: Option<&'a Arc<ImageView>>,
+}
+
+/// A pass of a downstream crate's, recorded every frame at the point it was added at. See
+/// `Renderer::add_user_pass`.
+pub trait UserPass {
+    /// Shown in errors.
+    fn name(&self) -> &str;
+
+    /// Records the pass's commands for this frame, as the kind of command buffer its point
+    /// expects. Returning `None` skips the pass this frame.
+    fn record(&mut self, context: PassContext) -> anyhow::Result<Option<Arc<CommandBuffer>>>;
+}
+
+/// Refers to a pass added with `Renderer::add_user_pass`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UserPassId(u64);
+
+/// The user passes, recorded in the order they were added at each point.
+#[derive(Default)]
+pub struct UserPasses {
+    passes: Vec<(UserPassId, PassPoint, Box<dyn UserPass>)>,
+    next_id: u64,
+}
+
+impl UserPasses {
+    pub fn add(&mut self, point: PassPoint, pass: Box<dyn UserPass>) -> UserPassId {
+        let id = UserPassId(self.next_id);
+        self.next_id += 1;
+        self.passes.push((id, point, pass));
+        id
+    }
+
+    pub fn remove(&mut self, id: UserPassId) -> Option<Box<dyn UserPass>> {
+        let index = self
+            .passes
+            .iter()
+            .position(|(pass_id, ..)| *pass_id == id)?;
+        Some(self.passes.remove(index).2)
+    }
+
+    /// Records the passes at `context`'s point.
+    pub fn record(&mut self, context: PassContext) -> anyhow::Result<Vec<Arc<CommandBuffer>>> {
+        let mut command_buffers = vec![];
+        for (_, point, pass) in &mut self.passes {
+            if *point != context.point {
+                continue;
+            }
+            if let Some(cb) = pass
+                .record(context.clone())
+                .with_context(|| format!("recording user pass {}", pass.name()))?
+            {
+                command_buffers.push(cb);
+            }
+        }
+        Ok(command_buffers)
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use vulkano::shader::{EntryPoint, ShaderModule, SpecializationConstant};
+
+/// A specialization constant's value. Floats are left out so variants can be hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConstantValue {
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+}
+
+impl From<ConstantValue> for SpecializationConstant {
+    fn from(value: ConstantValue) -> Self {
+        match value {
+            ConstantValue::Bool(value) => SpecializationConstant::Bool(value),
+            ConstantValue::I32(value) => SpecializationConstant::I32(value),
+            ConstantValue::U32(value) => SpecializationConstant::U32(value),
+        }
+    }
+}
+
+/// The specialization constants a shader variant is built with, by `constant_id`. Constants left
+/// out keep the default the shader declares, so the empty key is the shader as written.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct VariantKey(BTreeMap<u32, ConstantValue>);
+
+impl VariantKey {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, constant_id: u32, value: ConstantValue) -> Self {
+        self.0.insert(constant_id, value);
+        self
+    }
+
+    pub fn with_bool(self, constant_id: u32, value: bool) -> Self {
+        self.with(constant_id, ConstantValue::Bool(value))
+    }
+
+    pub fn with_u32(self, constant_id: u32, value: u32) -> Self {
+        self.with(constant_id, ConstantValue::U32(value))
+    }
+}
+
+/// One shader module specialized into variants, e.g. with features switched on and off or a
+/// sample count baked in, instead of a GLSL file or `define` per combination. Each variant is
+/// specialized the first time it's asked for and cached by its key.
+pub struct ShaderVariants {
+    /// For errors and logs.
+    name: &'static str,
+    module: Arc<ShaderModule>,
+    variants: HashMap<VariantKey, EntryPoint>,
+}
+
+impl ShaderVariants {
+    pub fn new(name: &'static str, module: Arc<ShaderModule>) -> Self {
+        ShaderVariants {
+            name,
+            module,
+            variants: HashMap::new(),
+        }
+    }
+
+    /// The `main` entry point of the variant built with `key`'s constants.
+    pub fn get(&mut self, key: &VariantKey) -> anyhow::Result<EntryPoint> {
+        if let Some(entry_point) = self.variants.get(key) {
+            return Ok(entry_point.clone());
+        }
+
+        log::debug!("specializing {} shader for {:?}", self.name, key);
+        let entry_point = self
+            .module
+            .specialize(
+                key.0
+                    .iter()
+                    .map(|(id, value)| (*id, SpecializationConstant::from(*value)))
+                    .collect(),
+            )
+            .with_context(|| format!("specializing {} shader for {:?}", self.name, key))?
+            .entry_point("main")
+            .with_context(|| format!("{} shader entry point not found", self.name))?;
+        self.variants.insert(key.clone(), entry_point.clone());
+        Ok(entry_point)
+    }
+
+    /// Variants built so far.
+    pub fn len(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variants.is_empty()
+    }
+}
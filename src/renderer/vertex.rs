@@ -0,0 +1,129 @@
+use vulkano::{buffer::BufferContents, pipeline::graphics::vertex_input::Vertex};
+
+/// The attribute sets meshes can be built from. The geometry system creates a pipeline for each
+/// layout the first time a mesh using it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VertexLayout {
+    PositionColorNormal,
+    /// Depth-only content such as occluders. Lit by ambient light alone.
+    Position,
+    PositionNormalUv,
+    PositionNormalUvTangent,
+    /// Static meshes with a second UV channel laying out their material's lightmap.
+    PositionNormalUvLightmap,
+    /// Joint palettes aren't bound yet, so skinned meshes are drawn in their bind pose.
+    Skinned,
+}
+
+/// A vertex type meshes can be built from.
+///
+/// Attributes are matched to the geometry shader by field name, so implementors must name their
+/// fields after the shader inputs: `position`, `color`, `normal`, `uv`, `tangent`, `lightmap_uv`,
+/// `joints` and `weights`.
+pub trait MeshVertex: Vertex + Copy {
+    const LAYOUT: VertexLayout;
+
+    fn position(&self) -> [f32; 3];
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, BufferContents, Vertex)]
+pub struct VertexPosition {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+}
+
+impl MeshVertex for VertexPosition {
+    const LAYOUT: VertexLayout = VertexLayout::Position;
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, BufferContents, Vertex)]
+pub struct VertexPositionNormalUv {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+    #[format(R32G32B32_SFLOAT)]
+    pub normal: [f32; 3],
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+}
+
+impl MeshVertex for VertexPositionNormalUv {
+    const LAYOUT: VertexLayout = VertexLayout::PositionNormalUv;
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, BufferContents, Vertex)]
+pub struct VertexPositionNormalUvTangent {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+    #[format(R32G32B32_SFLOAT)]
+    pub normal: [f32; 3],
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+    /// The bitangent's sign is stored in `w`.
+    #[format(R32G32B32A32_SFLOAT)]
+    pub tangent: [f32; 4],
+}
+
+impl MeshVertex for VertexPositionNormalUvTangent {
+    const LAYOUT: VertexLayout = VertexLayout::PositionNormalUvTangent;
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, BufferContents, Vertex)]
+pub struct VertexPositionNormalUvLightmap {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+    #[format(R32G32B32_SFLOAT)]
+    pub normal: [f32; 3],
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+    /// Where the vertex is in the lightmap, unique across the mesh so no texel is shared by two
+    /// surfaces.
+    #[format(R32G32_SFLOAT)]
+    pub lightmap_uv: [f32; 2],
+}
+
+impl MeshVertex for VertexPositionNormalUvLightmap {
+    const LAYOUT: VertexLayout = VertexLayout::PositionNormalUvLightmap;
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, BufferContents, Vertex)]
+pub struct VertexSkinned {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+    #[format(R32G32B32_SFLOAT)]
+    pub normal: [f32; 3],
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+    #[format(R8G8B8A8_UINT)]
+    pub joints: [u8; 4],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub weights: [f32; 4],
+}
+
+impl MeshVertex for VertexSkinned {
+    const LAYOUT: VertexLayout = VertexLayout::Skinned;
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
@@ -0,0 +1,342 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    descriptor_set::allocator::StandardDescriptorSetAllocator,
+    device::{physical::PhysicalDevice, Device, DeviceExtensions, DeviceOwned, Features, Queue},
+    instance::{
+        debug::{
+            DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessengerCallback,
+            DebugUtilsMessengerCreateInfo,
+        },
+        Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions,
+    },
+    memory::allocator::StandardMemoryAllocator,
+    VulkanLibrary, VulkanObject,
+};
+use vulkano_util::context::{VulkanoConfig, VulkanoContext};
+
+use super::{adapter::AdapterInfo, config::RendererConfig, memory::MemoryTracker};
+
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// The instance, device, queues, allocators and debug messenger shared by every renderer and
+/// render system.
+///
+/// This is the only place a Vulkan device is created, so extensions and debug settings are
+/// configured here rather than by each renderer.
+pub struct VulkanContext {
+    context: VulkanoContext,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    debug_namer: DebugNamer,
+    memory_tracker: MemoryTracker,
+    portability: Portability,
+}
+
+/// How the platform's Vulkan departs from a full implementation, e.g. on macOS, where it runs
+/// on MoltenVK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Portability {
+    /// The instance enumerates portability implementations, through
+    /// `VK_KHR_portability_enumeration`.
+    pub enumeration: bool,
+    /// The device implements only the portability subset, `VK_KHR_portability_subset`, which
+    /// vulkano enables whenever a device supports it.
+    pub subset: bool,
+    /// Portability subset features the renderer relies on that the device lacks. Empty on full
+    /// implementations.
+    pub missing_features: Features,
+}
+
+/// Attaches names to Vulkan objects through `VK_EXT_debug_utils`, so they can be told apart in
+/// validation messages and RenderDoc captures. Does nothing unless `RendererConfig::debug_names`
+/// is set.
+#[derive(Clone)]
+pub struct DebugNamer {
+    device: Option<Arc<Device>>,
+}
+
+impl DebugNamer {
+    pub fn name<T>(&self, object: &T, name: &str)
+    where
+        T: VulkanObject + DeviceOwned,
+    {
+        if let Some(device) = &self.device {
+            if let Err(e) = device.set_debug_utils_object_name(object, Some(name)) {
+                log::debug!("failed to name {}: {}", name, e);
+            }
+        }
+    }
+}
+
+impl VulkanContext {
+    pub fn new(config: &RendererConfig) -> anyhow::Result<Self> {
+        let library = VulkanLibrary::new().context("loading Vulkan library")?;
+
+        let debug_utils_supported = library.supported_extensions().ext_debug_utils;
+        let validation_supported = library
+            .layer_properties()
+            .context("enumerating instance layers")?
+            .any(|layer| layer.name() == VALIDATION_LAYER);
+
+        if config.validation && !validation_supported {
+            log::warn!(
+                "{} is not installed, continuing without validation",
+                VALIDATION_LAYER
+            );
+        }
+        if (config.validation || config.debug_names) && !debug_utils_supported {
+            log::warn!("VK_EXT_debug_utils is not supported, debug output is disabled");
+        }
+
+        let base_instance_info = base_instance_create_info(&library);
+        let enumeration = base_instance_info
+            .flags
+            .intersects(InstanceCreateFlags::ENUMERATE_PORTABILITY);
+
+        let validation = config.validation && validation_supported;
+        let messenger = config.validation && debug_utils_supported;
+        let debug_names = config.debug_names && debug_utils_supported;
+
+        let default_config = VulkanoConfig::default();
+        let default_priority = default_config.device_priority_fn.clone();
+
+        let adapters = AdapterInfo::enumerate()?;
+
+        // The device is picked by vulkano_util after the features are requested, so optional
+        // features are only enabled when every adapter it could pick supports them
+        let texture_compression_bc = !adapters.is_empty()
+            && adapters
+                .iter()
+                .all(|adapter| adapter.supported_features.texture_compression_bc);
+
+        let draw_indirect_count = config.indirect_draws
+            && config.gpu_culling
+            && !adapters.is_empty()
+            && adapters
+                .iter()
+                .all(|adapter| adapter.supported_features.draw_indirect_count);
+
+        // The subset's features don't exist on full implementations, so they can only be
+        // enabled when every adapter is a portability one, as on macOS
+        let portability_subset =
+            !adapters.is_empty() && adapters.iter().all(|adapter| adapter.portability_subset);
+        let subset_features = if portability_subset {
+            adapters
+                .iter()
+                .fold(portability_subset_features(), |features, adapter| {
+                    features.intersection(&adapter.supported_features)
+                })
+        } else {
+            Features::empty()
+        };
+
+        let preferred = match &config.preferred_device {
+            Some(selector) => {
+                let adapter = adapters
+                    .into_iter()
+                    .find(|adapter| selector.matches(adapter));
+                if adapter.is_none() {
+                    log::warn!("no adapter matches {:?}, using the default", selector);
+                }
+                adapter
+            }
+            None => None,
+        };
+
+        let context = VulkanoContext::new(VulkanoConfig {
+            device_extensions: DeviceExtensions {
+                khr_swapchain: true,
+                khr_shader_draw_parameters: true,
+                ..Default::default()
+            },
+            device_features: Features {
+                multi_draw_indirect: config.indirect_draws,
+                draw_indirect_first_instance: config.indirect_draws,
+                draw_indirect_count,
+                texture_compression_bc,
+                // The reflection probe atlas
+                image_cube_array: true,
+                ..subset_features
+            },
+            instance_create_info: InstanceCreateInfo {
+                enabled_layers: if validation {
+                    vec![VALIDATION_LAYER.to_owned()]
+                } else {
+                    vec![]
+                },
+                enabled_extensions: InstanceExtensions {
+                    ext_debug_utils: messenger || debug_names,
+                    ..base_instance_info.enabled_extensions
+                },
+                ..base_instance_info
+            },
+            debug_create_info: messenger.then(|| DebugUtilsMessengerCreateInfo {
+                message_severity: config.debug_message_severity,
+                message_type: config.debug_message_type,
+                ..DebugUtilsMessengerCreateInfo::user_callback(unsafe {
+                    DebugUtilsMessengerCallback::new(
+                        |message_severity, message_type, callback_data| {
+                            let level = if message_severity
+                                .intersects(DebugUtilsMessageSeverity::ERROR)
+                            {
+                                log::Level::Error
+                            } else if message_severity
+                                .intersects(DebugUtilsMessageSeverity::WARNING)
+                            {
+                                log::Level::Warn
+                            } else if message_severity.intersects(DebugUtilsMessageSeverity::INFO) {
+                                log::Level::Info
+                            } else {
+                                log::Level::Trace
+                            };
+
+                            let ty = if message_type.intersects(DebugUtilsMessageType::VALIDATION) {
+                                "validation"
+                            } else if message_type.intersects(DebugUtilsMessageType::PERFORMANCE) {
+                                "performance"
+                            } else {
+                                "general"
+                            };
+
+                            log::log!(
+                                level,
+                                "{} {}: {}",
+                                callback_data.message_id_name.unwrap_or("unknown"),
+                                ty,
+                                callback_data.message
+                            );
+                        },
+                    )
+                })
+            }),
+            // Lower is preferred. The default ranking still breaks ties when the preferred
+            // adapter is filtered out for lacking a required feature
+            device_priority_fn: Arc::new(
+                move |physical_device: &PhysicalDevice| match &preferred {
+                    Some(adapter) if adapter.is(physical_device) => 0,
+                    _ => default_priority(physical_device) + 1,
+                },
+            ),
+            ..default_config
+        });
+
+        log::info!(
+            "using {}",
+            context.device().physical_device().properties().device_name
+        );
+
+        let device = context.device();
+        let subset = device.enabled_extensions().khr_portability_subset;
+        let portability = Portability {
+            enumeration,
+            subset,
+            missing_features: if subset {
+                portability_subset_features().difference(device.enabled_features())
+            } else {
+                Features::empty()
+            },
+        };
+        if portability.subset {
+            log::info!("the device implements the Vulkan portability subset");
+        }
+        if !portability.missing_features.is_empty() {
+            log::warn!(
+                "the device lacks portability subset features the renderer relies on: {:?}",
+                portability.missing_features
+            );
+        }
+
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            context.device().clone(),
+            Default::default(),
+        ));
+
+        let debug_namer = DebugNamer {
+            device: debug_names.then(|| context.device().clone()),
+        };
+
+        Ok(VulkanContext {
+            context,
+            descriptor_set_allocator,
+            debug_namer,
+            memory_tracker: MemoryTracker::default(),
+            portability,
+        })
+    }
+
+    /// Whether the device is a portability implementation, and what the renderer is missing
+    /// on it.
+    pub fn portability(&self) -> &Portability {
+        &self.portability
+    }
+
+    /// Names Vulkan objects for validation messages and capture tools.
+    pub fn debug_namer(&self) -> &DebugNamer {
+        &self.debug_namer
+    }
+
+    pub fn instance(&self) -> &Arc<Instance> {
+        self.context.instance()
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        self.context.device()
+    }
+
+    pub fn graphics_queue(&self) -> &Arc<Queue> {
+        self.context.graphics_queue()
+    }
+
+    /// A dedicated compute queue if the device has one, otherwise the graphics queue.
+    pub fn compute_queue(&self) -> &Arc<Queue> {
+        self.context.compute_queue()
+    }
+
+    pub fn memory_allocator(&self) -> &Arc<StandardMemoryAllocator> {
+        self.context.memory_allocator()
+    }
+
+    /// Device memory used per category, for resources that register themselves.
+    pub fn memory_tracker(&self) -> &MemoryTracker {
+        &self.memory_tracker
+    }
+
+    pub fn descriptor_set_allocator(&self) -> &Arc<StandardDescriptorSetAllocator> {
+        &self.descriptor_set_allocator
+    }
+
+    /// The underlying `vulkano_util` context.
+    pub fn vulkano(&self) -> &VulkanoContext {
+        &self.context
+    }
+}
+
+/// The settings every instance starts from, so portability implementations like MoltenVK are
+/// enumerated wherever devices are listed. Callers add their own layers and extensions.
+pub(crate) fn base_instance_create_info(library: &VulkanLibrary) -> InstanceCreateInfo {
+    let portability = library.supported_extensions().khr_portability_enumeration;
+    InstanceCreateInfo {
+        flags: if portability {
+            InstanceCreateFlags::ENUMERATE_PORTABILITY
+        } else {
+            InstanceCreateFlags::empty()
+        },
+        enabled_extensions: InstanceExtensions {
+            khr_portability_enumeration: portability,
+            // Lists the HDR color spaces among a surface's formats
+            ext_swapchain_colorspace: library.supported_extensions().ext_swapchain_colorspace,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// The portability subset features the render systems use, enabled where the subset applies.
+fn portability_subset_features() -> Features {
+    Features {
+        // The shadow map samplers compare depth
+        mutable_comparison_samplers: true,
+        ..Features::empty()
+    }
+}
@@ -0,0 +1,334 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use vulkano::{
+    device::Queue,
+    format::Format,
+    image::{view::ImageView, ImageUsage},
+    swapchain::{
+        self, ColorSpace, CompositeAlpha, CompositeAlphas, PresentMode, Surface, Swapchain,
+        SwapchainCreateInfo, SwapchainPresentInfo,
+    },
+    sync::{self, GpuFuture},
+    Validated, VulkanError,
+};
+use winit::{
+    dpi::LogicalSize,
+    event_loop::EventLoop,
+    window::{Window, WindowBuilder},
+};
+
+use super::{
+    config::{ColorWorkflow, RendererConfig},
+    vulkan_context::VulkanContext,
+};
+
+/// How a window's swapchain is created, picked once from what its surface supports and reused
+/// every time the swapchain is recreated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SwapchainSettings {
+    pub format: Format,
+    pub color_space: ColorSpace,
+    /// `RendererConfig::swapchain_images`, within the surface's limits.
+    pub min_image_count: u32,
+    pub composite_alpha: CompositeAlpha,
+    pub present_mode: PresentMode,
+}
+
+/// A window and the swapchain presenting to it. Each renderer has its own, so renderers sharing
+/// a context can have different swapchain settings.
+pub(crate) struct RenderWindow {
+    window: Arc<Window>,
+    queue: Arc<Queue>,
+    settings: SwapchainSettings,
+    /// Set when the window was asked to be transparent and the surface can blend it.
+    transparent: bool,
+    swapchain: Arc<Swapchain>,
+    image_views: Vec<Arc<ImageView>>,
+    image_index: u32,
+    recreate: bool,
+    previous_frame_end: Option<Box<dyn GpuFuture>>,
+}
+
+impl RenderWindow {
+    pub fn new(
+        event_loop: &EventLoop<()>,
+        context: &VulkanContext,
+        config: &RendererConfig,
+    ) -> anyhow::Result<Self> {
+        let window = Arc::new(
+            WindowBuilder::new()
+                .with_inner_size(LogicalSize::new(
+                    config.window_size[0],
+                    config.window_size[1],
+                ))
+                .with_title(config.window_title.clone())
+                .with_transparent(config.transparent)
+                .with_visible(config.visible)
+                .build(event_loop)
+                .context("creating window")?,
+        );
+        let surface = Surface::from_window(context.instance().clone(), window.clone())
+            .context("creating window surface")?;
+
+        let physical_device = context.device().physical_device();
+        let capabilities = physical_device
+            .surface_capabilities(&surface, Default::default())
+            .context("getting surface capabilities")?;
+        let formats = physical_device
+            .surface_formats(&surface, Default::default())
+            .context("getting surface formats")?;
+        let (format, color_space) = choose_surface_format(config.color_workflow, &formats)
+            .context("the surface supports no formats")?;
+
+        let mut min_image_count = config
+            .swapchain_images
+            .max(capabilities.min_image_count)
+            .max(2);
+        if let Some(max) = capabilities.max_image_count {
+            if min_image_count > max {
+                log::warn!(
+                    "the surface takes at most {} swapchain images, not {}",
+                    max,
+                    min_image_count
+                );
+                min_image_count = max;
+            }
+        }
+
+        let supported_alpha = capabilities.supported_composite_alpha;
+        let opaque = supported_alpha
+            .into_iter()
+            .next()
+            .context("the surface supports no composite alpha")?;
+        let mut transparent = false;
+        let mut composite_alpha = opaque;
+        if config.transparent {
+            match transparent_composite_alpha(supported_alpha) {
+                Some(alpha) => {
+                    transparent = true;
+                    composite_alpha = alpha;
+                }
+                None => log::warn!(
+                    "the surface can't be composited with alpha, so the window stays opaque"
+                ),
+            }
+        }
+
+        let settings = SwapchainSettings {
+            format,
+            color_space,
+            min_image_count,
+            composite_alpha,
+            present_mode: if config.vsync {
+                PresentMode::Fifo
+            } else {
+                PresentMode::Immediate
+            },
+        };
+
+        let (swapchain, images) = Swapchain::new(
+            context.device().clone(),
+            surface,
+            SwapchainCreateInfo {
+                min_image_count: settings.min_image_count,
+                image_format: settings.format,
+                image_color_space: settings.color_space,
+                image_extent: window.inner_size().into(),
+                // Read by the frame analysis tools
+                image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+                composite_alpha: settings.composite_alpha,
+                present_mode: settings.present_mode,
+                ..Default::default()
+            },
+        )
+        .context("creating swapchain")?;
+        let image_views = images
+            .into_iter()
+            .map(ImageView::new_default)
+            .collect::<Result<_, _>>()
+            .context("creating swapchain image views")?;
+
+        Ok(RenderWindow {
+            window,
+            queue: context.graphics_queue().clone(),
+            settings,
+            transparent,
+            swapchain,
+            image_views,
+            image_index: 0,
+            recreate: false,
+            previous_frame_end: Some(sync::now(context.device().clone()).boxed()),
+        })
+    }
+
+    pub fn window(&self) -> &Arc<Window> {
+        &self.window
+    }
+
+    pub fn settings(&self) -> &SwapchainSettings {
+        &self.settings
+    }
+
+    /// Whether the window is composited with what's behind it.
+    pub fn transparent(&self) -> bool {
+        self.transparent
+    }
+
+    pub fn swapchain_image_view(&self) -> &Arc<ImageView> {
+        &self.image_views[self.image_index as usize]
+    }
+
+    pub fn swapchain_image_size(&self) -> [u32; 2] {
+        let extent = self.swapchain.image_extent();
+        [extent[0], extent[1]]
+    }
+
+    /// Recreates the swapchain at the window's new size before the next `acquire`.
+    pub fn resize(&mut self) {
+        self.recreate = true;
+    }
+
+    /// Acquires the next swapchain image, recreating the swapchain first when it's out of date.
+    /// Returns a future for the previous frame's presentation joined with the acquire.
+    pub fn acquire(&mut self) -> Result<Box<dyn GpuFuture>, VulkanError> {
+        if let Some(previous) = self.previous_frame_end.as_mut() {
+            previous.cleanup_finished();
+        }
+        if self.recreate {
+            self.recreate_swapchain()?;
+        }
+
+        let (image_index, suboptimal, acquire_future) =
+            match swapchain::acquire_next_image(self.swapchain.clone(), None)
+                .map_err(Validated::unwrap)
+            {
+                Ok(acquired) => acquired,
+                Err(VulkanError::OutOfDate) => {
+                    self.recreate = true;
+                    return Err(VulkanError::OutOfDate);
+                }
+                Err(e) => return Err(e),
+            };
+        if suboptimal {
+            self.recreate = true;
+        }
+        self.image_index = image_index;
+
+        let previous = self
+            .previous_frame_end
+            .take()
+            .unwrap_or_else(|| sync::now(self.queue.device().clone()).boxed());
+        Ok(previous.join(acquire_future).boxed())
+    }
+
+    /// Presents the acquired image once `after_future` is done. With `wait`, blocks until the
+    /// GPU has finished the frame, which doesn't wait for the image to reach the display.
+    pub fn present(&mut self, after_future: Box<dyn GpuFuture>, wait: bool) {
+        let future = after_future
+            .then_swapchain_present(
+                self.queue.clone(),
+                SwapchainPresentInfo::swapchain_image_index(
+                    self.swapchain.clone(),
+                    self.image_index,
+                ),
+            )
+            .then_signal_fence_and_flush()
+            .map_err(Validated::unwrap);
+        self.previous_frame_end = match future {
+            Ok(mut future) => {
+                if wait {
+                    if let Err(e) = future.wait(None) {
+                        log::error!("waiting for the frame: {}", e);
+                    }
+                    future.cleanup_finished();
+                }
+                Some(future.boxed())
+            }
+            Err(VulkanError::OutOfDate) => {
+                self.recreate = true;
+                Some(sync::now(self.queue.device().clone()).boxed())
+            }
+            Err(e) => {
+                log::error!("presenting: {}", e);
+                Some(sync::now(self.queue.device().clone()).boxed())
+            }
+        };
+    }
+
+    /// Recreates the swapchain at the window's size, with the same settings it was created with.
+    fn recreate_swapchain(&mut self) -> Result<(), VulkanError> {
+        let (swapchain, images) = self
+            .swapchain
+            .recreate(SwapchainCreateInfo {
+                image_extent: self.window.inner_size().into(),
+                min_image_count: self.settings.min_image_count,
+                image_format: self.settings.format,
+                image_color_space: self.settings.color_space,
+                composite_alpha: self.settings.composite_alpha,
+                present_mode: self.settings.present_mode,
+                ..self.swapchain.create_info()
+            })
+            .map_err(Validated::unwrap)?;
+        self.image_views = images
+            .into_iter()
+            .map(ImageView::new_default)
+            .collect::<Result<_, _>>()
+            .map_err(Validated::unwrap)?;
+        self.swapchain = swapchain;
+        self.recreate = false;
+        Ok(())
+    }
+}
+
+/// The swapchain format for `workflow` out of the surface's `supported` ones. The sRGB workflow
+/// prefers sRGB formats, which the hardware encodes the output to, then an extended linear float
+/// format that an HDR display's compositor encodes. The linear workflow prefers UNORM formats.
+/// Falls back to the surface's first format, in which case colors may come out too bright or
+/// too dark.
+fn choose_surface_format(
+    workflow: ColorWorkflow,
+    supported: &[(Format, ColorSpace)],
+) -> Option<(Format, ColorSpace)> {
+    let preferred: &[(Format, ColorSpace)] = match workflow {
+        ColorWorkflow::Srgb => &[
+            (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear),
+            (Format::R8G8B8A8_SRGB, ColorSpace::SrgbNonLinear),
+            (Format::A8B8G8R8_SRGB_PACK32, ColorSpace::SrgbNonLinear),
+            (Format::R16G16B16A16_SFLOAT, ColorSpace::ExtendedSrgbLinear),
+        ],
+        ColorWorkflow::Linear => &[
+            (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear),
+            (Format::R8G8B8A8_UNORM, ColorSpace::SrgbNonLinear),
+            (Format::A8B8G8R8_UNORM_PACK32, ColorSpace::SrgbNonLinear),
+            (Format::A2B10G10R10_UNORM_PACK32, ColorSpace::SrgbNonLinear),
+        ],
+    };
+    let format = preferred
+        .iter()
+        .find(|format| supported.contains(format))
+        .or_else(|| {
+            let fallback = supported.first();
+            log::warn!(
+                "the surface supports no format suited to the {:?} color workflow, using {:?}",
+                workflow,
+                fallback
+            );
+            fallback
+        })
+        .copied();
+    log::debug!("swapchain format {:?}", format);
+    format
+}
+
+/// How a transparent window's swapchain is blended with what's behind it. The composite writes
+/// premultiplied colors, so that's preferred.
+fn transparent_composite_alpha(supported: CompositeAlphas) -> Option<CompositeAlpha> {
+    [
+        CompositeAlpha::PreMultiplied,
+        CompositeAlpha::PostMultiplied,
+        CompositeAlpha::Inherit,
+    ]
+    .into_iter()
+    .find(|&alpha| supported.contains_enum(alpha))
+}